@@ -55,6 +55,7 @@ export interface Snippet {
   repo_name: string;
   repo_ref: string;
   lang: string;
+  license?: string;
   snippets: SnippetItem[];
 }
 
@@ -226,9 +227,19 @@ type ProcStep = {
   content: { query: string; paths: string[] };
 };
 
+export type RankedChunk = {
+  path: string;
+  alias: number;
+  snippet: string;
+  start: number;
+  end: number;
+  retrieval_rank: number;
+  presentation_rank: number | null;
+};
+
 type CodeStep = {
   type: 'code';
-  content: { query: string };
+  content: { query: string; ranking?: RankedChunk[] };
 };
 
 type PathStep = {
@@ -240,6 +251,7 @@ export type SearchStepType = ProcStep | CodeStep | PathStep;
 
 export type ConversationType = {
   id: string;
+  parent?: string;
   search_steps: SearchStepType[];
   query: { target: { Plain: string } };
   conclusion: string;
@@ -247,6 +259,71 @@ export type ConversationType = {
   paths: string[];
   response_timestamp: string;
   focused_chunk: { file_path: string } | null;
+  suggested_edits?: {
+    path: string;
+    start_line: number;
+    end_line: number;
+    diff: string;
+  }[];
+  corrections?: {
+    original: string;
+    corrected: string;
+  }[];
+  citations?: {
+    repo: string;
+    peer?: string;
+    path: string;
+    start_line: number;
+    end_line: number;
+    score: number | null;
+  }[];
+  answer_kind?: 'code' | 'intro' | 'info' | 'cannot_help';
+  usage?: {
+    prompt_tokens: number;
+    completion_tokens: number;
+    calls: number;
+    cost: number;
+  };
+  outcome?:
+    | {
+        kind: 'no_answer';
+        message: string;
+        suggestions: string[];
+      }
+    | {
+        kind: 'navigate';
+        message: string;
+        path: string;
+        start_line: number;
+        end_line: number;
+      }
+    | {
+        kind: 'policy_violation';
+        message: string;
+        rule: string;
+      }
+    | {
+        kind: 'clarification_needed';
+        message: string;
+        candidates: {
+          label: string;
+          paths: string[];
+          code_chunks: Omit<RankedChunk, 'retrieval_rank' | 'presentation_rank'>[];
+        }[];
+      }
+    | {
+        kind: 'partial';
+        message: string;
+        timeout: boolean;
+        completed: ('search' | 'selection' | 'answer')[];
+      };
+};
+
+export type ConversationBranchType = {
+  leaf: string;
+  title: string;
+  exchanges: number;
+  active: boolean;
 };
 
 export interface SuggestionsResponse {
@@ -276,3 +353,14 @@ export interface NLSearchResponse {
   snippets: NLSnippet[];
   user_id: string;
 }
+
+export type CredentialNotification = {
+  id: number;
+  created_at: string;
+  backend: 'github';
+} & (
+  | { status: 'reauthentication_required' }
+  | { status: 'expires_soon'; expiry: string }
+  | { status: 'refresh_failing'; failures: number }
+  | { status: 'installation_suspended' }
+);
@@ -72,6 +72,9 @@ export type RepoType = {
   most_common_lang: string;
   branches: { name: string; last_commit_unix_secs: number }[];
   branch_filter: { select: string[] } | null;
+  revisions?: string[];
+  pinned?: boolean;
+  excluded_paths?: string[];
 };
 
 export type RepoUi = RepoType & {
@@ -0,0 +1,31 @@
+//! The bloop engine, without the web server.
+//!
+//! This crate is the supported surface for embedding bloop in other Rust tools. It exposes
+//! indexing, search and the answer pipeline through [`Workspace`]:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use bleep_core::{Configuration, Workspace};
+//!
+//! let workspace = Workspace::open(Configuration::read("bloop.json")?).await?;
+//! let repo_ref = workspace.index("/home/me/src/project").await?;
+//!
+//! let results = workspace.search("symbol:Workspace").await?;
+//! let answer = workspace.ask(&repo_ref, "how are repositories indexed?").await?;
+//! println!("{}", answer.text);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything else in `bleep` is an implementation detail of the bloop application, and may
+//! change between releases.
+//!
+//! This crate is a facade: it re-exports the API above from `bleep`, and depends on all of it,
+//! the web server included. The engine has not been split out of `bleep`, so embedders build the
+//! server even though they never run it.
+
+pub use bleep::{
+    query::execute::{QueryResponse, QueryResult},
+    workspace::{Answer, Workspace},
+    Configuration, RepoRef,
+};
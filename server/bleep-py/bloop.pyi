@@ -0,0 +1,26 @@
+from typing import List, Optional
+
+class Snippet:
+    text: str
+    start_line: int
+    end_line: int
+
+class SearchResult:
+    kind: str
+    repo_ref: Optional[str]
+    repo_name: Optional[str]
+    relative_path: Optional[str]
+    lang: Optional[str]
+    snippets: List[Snippet]
+
+class Answer:
+    thread_id: str
+    text: str
+    paths: List[str]
+
+class Workspace:
+    @staticmethod
+    async def open(config_path: str) -> Workspace: ...
+    async def index(self, path: str) -> str: ...
+    async def search(self, query: str) -> List[SearchResult]: ...
+    async def ask(self, repo_ref: str, question: str) -> Answer: ...
@@ -0,0 +1,249 @@
+//! Python bindings for the bloop engine.
+//!
+//! Every method that does I/O returns an awaitable, driven by a tokio runtime owned by the
+//! extension. Results are converted into plain Python objects, so they can be used without
+//! holding on to the workspace.
+
+use std::str::FromStr;
+
+use bleep_core::{Configuration, QueryResult, RepoRef};
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+use serde_json::Value;
+
+fn to_py_err(err: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(format!("{err:#}"))
+}
+
+/// A set of indexed repositories.
+#[pyclass(module = "bloop")]
+#[derive(Clone)]
+struct Workspace(bleep_core::Workspace);
+
+#[pymethods]
+impl Workspace {
+    /// Open a workspace, with the configuration read from a JSON file.
+    #[staticmethod]
+    fn open(py: Python<'_>, config_path: String) -> PyResult<&PyAny> {
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let config = Configuration::read(&config_path).map_err(to_py_err)?;
+            let workspace = bleep_core::Workspace::open(config)
+                .await
+                .map_err(to_py_err)?;
+
+            Ok(Workspace(workspace))
+        })
+    }
+
+    /// Index a local repository, and return its repo ref once indexing is complete.
+    fn index<'py>(&self, py: Python<'py>, path: String) -> PyResult<&'py PyAny> {
+        let workspace = self.0.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let repo_ref = workspace.index(path).await.map_err(to_py_err)?;
+            Ok(repo_ref.to_string())
+        })
+    }
+
+    /// Run a query written in the bloop query language.
+    fn search<'py>(&self, py: Python<'py>, query: String) -> PyResult<&'py PyAny> {
+        let workspace = self.0.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let response = workspace.search(&query).await.map_err(to_py_err)?;
+
+            response
+                .data
+                .iter()
+                .map(SearchResult::new)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(to_py_err)
+        })
+    }
+
+    /// Answer a natural language question about an indexed repository.
+    fn ask<'py>(
+        &self,
+        py: Python<'py>,
+        repo_ref: String,
+        question: String,
+    ) -> PyResult<&'py PyAny> {
+        let workspace = self.0.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let repo_ref = RepoRef::from_str(&repo_ref).map_err(to_py_err)?;
+            let answer = workspace
+                .ask(&repo_ref, &question)
+                .await
+                .map_err(to_py_err)?;
+
+            Ok(Answer {
+                thread_id: answer.thread_id.to_string(),
+                text: answer.text,
+                paths: answer.paths,
+            })
+        })
+    }
+}
+
+/// A single search result: a file, a directory, a repository, or snippets from a file.
+#[pyclass(module = "bloop", get_all)]
+#[derive(Clone, Debug, PartialEq)]
+struct SearchResult {
+    kind: String,
+    repo_ref: Option<String>,
+    repo_name: Option<String>,
+    relative_path: Option<String>,
+    lang: Option<String>,
+    snippets: Vec<Snippet>,
+}
+
+impl SearchResult {
+    fn new(result: &QueryResult) -> serde_json::Result<Self> {
+        Ok(Self::from_json(&serde_json::to_value(result)?))
+    }
+
+    /// Read a result from its serialized form, which is the same as in the HTTP API.
+    fn from_json(value: &Value) -> Self {
+        let data = &value["data"];
+
+        // Some fields are serialized as highlighted strings, with the text nested in an object.
+        let string = |key: &str| match &data[key] {
+            Value::String(s) => Some(s.clone()),
+            Value::Object(o) => o.get("text").and_then(Value::as_str).map(str::to_owned),
+            _ => None,
+        };
+
+        let snippets = data["snippets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|s| Snippet {
+                text: s["data"].as_str().unwrap_or_default().to_owned(),
+                start_line: s["line_range"]["start"].as_u64().unwrap_or_default() as usize,
+                end_line: s["line_range"]["end"].as_u64().unwrap_or_default() as usize,
+            })
+            .collect();
+
+        Self {
+            kind: value["kind"].as_str().unwrap_or_default().to_owned(),
+            repo_ref: string("repo_ref"),
+            repo_name: string("repo_name").or_else(|| string("name")),
+            relative_path: string("relative_path"),
+            lang: string("lang"),
+            snippets,
+        }
+    }
+}
+
+#[pymethods]
+impl SearchResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "SearchResult(kind={:?}, repo_ref={:?}, relative_path={:?}, snippets={})",
+            self.kind,
+            self.repo_ref,
+            self.relative_path,
+            self.snippets.len()
+        )
+    }
+}
+
+/// A snippet of a file matching a search. Line numbers are zero-based, and the end is exclusive.
+#[pyclass(module = "bloop", get_all)]
+#[derive(Clone, Debug, PartialEq)]
+struct Snippet {
+    text: String,
+    start_line: usize,
+    end_line: usize,
+}
+
+#[pymethods]
+impl Snippet {
+    fn __repr__(&self) -> String {
+        format!(
+            "Snippet(start_line={}, end_line={})",
+            self.start_line, self.end_line
+        )
+    }
+}
+
+/// The answer to a question, in markdown.
+#[pyclass(module = "bloop", get_all)]
+#[derive(Clone, Debug)]
+struct Answer {
+    thread_id: String,
+    text: String,
+    paths: Vec<String>,
+}
+
+#[pymethods]
+impl Answer {
+    fn __repr__(&self) -> String {
+        format!(
+            "Answer(thread_id={:?}, paths={:?})",
+            self.thread_id, self.paths
+        )
+    }
+}
+
+#[pymodule]
+fn bloop(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Workspace>()?;
+    m.add_class::<SearchResult>()?;
+    m.add_class::<Snippet>()?;
+    m.add_class::<Answer>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_result_from_snippets() {
+        let value = serde_json::json!({
+            "kind": "snippets",
+            "data": {
+                "relative_path": "src/lib.rs",
+                "repo_name": "bloop",
+                "repo_ref": "github.com/bloopai/bloop",
+                "lang": "Rust",
+                "snippets": [{
+                    "data": "pub struct Workspace",
+                    "highlights": [],
+                    "symbols": [],
+                    "line_range": { "start": 3, "end": 4 },
+                }],
+            },
+        });
+
+        assert_eq!(
+            SearchResult::from_json(&value),
+            SearchResult {
+                kind: "snippets".to_owned(),
+                repo_ref: Some("github.com/bloopai/bloop".to_owned()),
+                repo_name: Some("bloop".to_owned()),
+                relative_path: Some("src/lib.rs".to_owned()),
+                lang: Some("Rust".to_owned()),
+                snippets: vec![Snippet {
+                    text: "pub struct Workspace".to_owned(),
+                    start_line: 3,
+                    end_line: 4,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn search_result_from_highlighted_repository() {
+        let value = serde_json::json!({
+            "kind": "repository_result",
+            "data": {
+                "name": { "text": "bloop", "highlights": [] },
+                "repo_ref": "github.com/bloopai/bloop",
+            },
+        });
+
+        let result = SearchResult::from_json(&value);
+        assert_eq!(result.repo_name.as_deref(), Some("bloop"));
+        assert_eq!(result.relative_path, None);
+        assert!(result.snippets.is_empty());
+    }
+}
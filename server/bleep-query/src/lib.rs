@@ -0,0 +1,13 @@
+//! The bloop query language.
+//!
+//! This crate holds the parts of query handling that need no index: parsing, language aliases,
+//! and a lightweight filter and ranking pass over results that are already known. It does no I/O,
+//! so it also compiles to `wasm32-unknown-unknown`; with the `wasm` feature, the frontend uses it
+//! to validate queries and preview the effect of filters without a round trip to the server.
+
+pub mod languages;
+pub mod parser;
+pub mod preview;
+
+#[cfg(feature = "wasm")]
+mod wasm;
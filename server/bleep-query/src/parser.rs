@@ -3,6 +3,21 @@ use regex::Regex;
 use smallvec::{smallvec, SmallVec};
 use std::{borrow::Cow, collections::HashSet, mem};
 
+/// Prefix of the branch names under which historical revisions are indexed.
+///
+/// Git does not allow `:` in reference names, so these never clash with actual branches.
+pub const REVISION_PREFIX: &str = "rev:";
+
+/// The branch name under which the revision `name`, such as a release tag, is indexed.
+pub fn revision_branch(name: &str) -> String {
+    format!("{REVISION_PREFIX}{name}")
+}
+
+/// Whether `branch` refers to a historical revision, rather than an actual branch.
+pub fn is_revision(branch: &str) -> bool {
+    branch.starts_with(REVISION_PREFIX)
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct Query<'a> {
     pub open: Option<bool>,
@@ -11,6 +26,8 @@ pub struct Query<'a> {
 
     pub org: Option<Literal<'a>>,
     pub repo: Option<Literal<'a>>,
+    /// A topic of the repository, as set on GitHub.
+    pub topic: Option<Literal<'a>>,
     pub path: Option<Literal<'a>>,
     pub lang: Option<Cow<'a, str>>,
     pub branch: Option<Literal<'a>>,
@@ -21,6 +38,8 @@ pub struct Query<'a> {
 pub enum Target<'a> {
     Symbol(Literal<'a>),
     Content(Literal<'a>),
+    /// A code pattern, matching code of the same shape regardless of naming.
+    Structural(Literal<'a>),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -110,6 +129,7 @@ impl<'a> Query<'a> {
 
             org: rhs.org.or(self.org),
             repo: rhs.repo.or(self.repo),
+            topic: rhs.topic.or(self.topic),
             path: rhs.path.or(self.path),
             lang: rhs.lang.or(self.lang),
             branch: rhs.branch.or(self.branch),
@@ -175,6 +195,7 @@ impl<'a> Query<'a> {
         if let Some(true) = value {
             self.org.as_mut().map(Literal::make_regex);
             self.repo.as_mut().map(Literal::make_regex);
+            self.topic.as_mut().map(Literal::make_regex);
             self.path.as_mut().map(Literal::make_regex);
             self.target.as_mut().map(Target::make_regex);
         }
@@ -187,6 +208,7 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit,
             Self::Content(lit) => lit,
+            Self::Structural(lit) => lit,
         }
     }
 
@@ -194,15 +216,23 @@ impl<'a> Target<'a> {
     pub fn symbol(&self) -> Option<&Literal<'_>> {
         match self {
             Self::Symbol(lit) => Some(lit),
-            Self::Content(_) => None,
+            _ => None,
         }
     }
 
     /// Get the content literal, if present
     pub fn content(&self) -> Option<&Literal<'_>> {
         match self {
-            Self::Symbol(_) => None,
             Self::Content(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    /// Get the structural pattern, if present
+    pub fn structural(&self) -> Option<&Literal<'_>> {
+        match self {
+            Self::Structural(lit) => Some(lit),
+            _ => None,
         }
     }
 
@@ -210,12 +240,14 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit.make_regex(),
             Self::Content(lit) => lit.make_regex(),
+            // Patterns are code, which is never read as a regex.
+            Self::Structural(_) => {}
         }
     }
 }
 
 #[derive(pest_derive::Parser)]
-#[grammar = "query/grammar.pest"] // relative to src
+#[grammar = "grammar.pest"] // relative to src
 struct PestParser;
 
 #[derive(Debug, PartialEq, thiserror::Error)]
@@ -288,6 +320,21 @@ impl<'a> Literal<'a> {
         }
     }
 
+    /// Whether this literal, used as a branch, refers to a historical revision.
+    pub fn is_revision(&self) -> bool {
+        match self {
+            Self::Plain(s) | Self::Regex(s) => is_revision(s),
+        }
+    }
+
+    /// Scope this literal to the branch namespace of historical revisions.
+    fn into_revision(self) -> Self {
+        match self {
+            Self::Plain(s) => Self::Plain(revision_branch(&s).into()),
+            Self::Regex(r) => Self::Regex(format!("{REVISION_PREFIX}(?:{r})").into()),
+        }
+    }
+
     pub fn unwrap(self) -> Cow<'a, str> {
         match self {
             Literal::Plain(v) => v,
@@ -368,7 +415,9 @@ enum Expr<'a> {
 
     Org(Literal<'a>),
     Repo(Literal<'a>),
+    Topic(Literal<'a>),
     Symbol(Literal<'a>),
+    Structural(Literal<'a>),
     Path(Literal<'a>),
     Lang(Cow<'a, str>),
     Content(Literal<'a>),
@@ -396,9 +445,12 @@ impl<'a> Expr<'a> {
             Rule::content => Content(Literal::from(pair.into_inner().next().unwrap())),
             Rule::path => Path(Literal::from(pair.into_inner().next().unwrap())),
             Rule::repo => Repo(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::topic => Topic(Literal::from(pair.into_inner().next().unwrap())),
             Rule::symbol => Symbol(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::structural => Structural(Literal::from(pair.into_inner().next().unwrap())),
             Rule::org => Org(Literal::from(pair.into_inner().next().unwrap())),
             Rule::branch => Branch(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::rev => Branch(Literal::from(pair.into_inner().next().unwrap()).into_revision()),
             Rule::lang => Lang(pair.into_inner().as_str().into()),
 
             Rule::open => {
@@ -533,8 +585,12 @@ pub fn parse_nl(query: &str) -> Result<ParsedQuery<'_>, ParseError> {
                 let item = Literal::from(pair.into_inner().next().unwrap());
                 let _ = branch.insert(item);
             }
+            Rule::rev => {
+                let item = Literal::from(pair.into_inner().next().unwrap()).into_revision();
+                let _ = branch.insert(item);
+            }
             Rule::lang => {
-                let item = super::languages::parse_alias(pair.into_inner().as_str().into());
+                let item = crate::languages::parse_alias(pair.into_inner().as_str().into());
                 let _ = langs.insert(item);
             }
             Rule::raw_text => {
@@ -579,6 +635,10 @@ fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
             repo: Some(repo),
             ..Default::default()
         }],
+        Expr::Topic(topic) => smallvec![Query {
+            topic: Some(topic),
+            ..Default::default()
+        }],
         Expr::Branch(branch) => smallvec![Query {
             branch: Some(branch),
             ..Default::default()
@@ -596,8 +656,12 @@ fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
             target: Some(Target::Symbol(sym)),
             ..Default::default()
         }],
+        Expr::Structural(pattern) => smallvec![Query {
+            target: Some(Target::Structural(pattern)),
+            ..Default::default()
+        }],
         Expr::Lang(lang) => smallvec![Query {
-            lang: Some(super::languages::parse_alias(lang)),
+            lang: Some(crate::languages::parse_alias(lang)),
             ..Default::default()
         }],
         Expr::Content(lit) => smallvec![Query {
@@ -1238,6 +1302,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn revisions() {
+        assert_eq!(
+            parse("rev:v1.4 symbol:parse").unwrap(),
+            vec![Query {
+                branch: Some(Literal::Plain("rev:v1.4".into())),
+                target: Some(Target::Symbol(Literal::Plain("parse".into()))),
+                ..Query::default()
+            }],
+        );
+
+        assert_eq!(
+            parse("rev:/v1\\..*/ foo").unwrap(),
+            vec![Query {
+                branch: Some(Literal::Regex("rev:(?:v1\\..*)".into())),
+                target: Some(Target::Content(Literal::Plain("foo".into()))),
+                ..Query::default()
+            }],
+        );
+
+        assert_eq!(
+            parse_nl("how did parse look in rev:v1.4").unwrap(),
+            ParsedQuery::Semantic(SemanticQuery {
+                target: Some(Literal::Plain("how did parse look in".into())),
+                branch: [Literal::Plain("rev:v1.4".into())].into(),
+                ..Default::default()
+            })
+        );
+
+        assert!(Literal::Plain("rev:v1.4".into()).is_revision());
+        assert!(!Literal::Plain("origin/main".into()).is_revision());
+        assert!(!is_revision("main"));
+    }
+
+    #[test]
+    fn topics() {
+        assert_eq!(
+            parse("topic:search symbol:parse").unwrap(),
+            vec![Query {
+                topic: Some(Literal::Plain("search".into())),
+                target: Some(Target::Symbol(Literal::Plain("parse".into()))),
+                ..Query::default()
+            }],
+        );
+
+        assert_eq!(
+            parse("(topic:search or topic:/^ml/) foo global_regex:true").unwrap(),
+            vec![
+                Query {
+                    global_regex: Some(true),
+                    topic: Some(Literal::Regex("search".into())),
+                    target: Some(Target::Content(Literal::Regex("foo".into()))),
+                    ..Query::default()
+                },
+                Query {
+                    global_regex: Some(true),
+                    topic: Some(Literal::Regex("^ml".into())),
+                    target: Some(Target::Content(Literal::Regex("foo".into()))),
+                    ..Query::default()
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn structural() {
+        assert_eq!(
+            parse(r#"lang:go structural:"if err != nil { return err }""#).unwrap(),
+            vec![Query {
+                lang: Some("go".into()),
+                target: Some(Target::Structural(Literal::Plain(
+                    "if err != nil { return err }".into()
+                ))),
+                ..Query::default()
+            }],
+        );
+
+        // Patterns are left as they are by `global_regex`.
+        assert_eq!(
+            parse("structural:'x.unwrap()' global_regex:true").unwrap(),
+            vec![Query {
+                global_regex: Some(true),
+                target: Some(Target::Structural(Literal::Plain("x.unwrap()".into()))),
+                ..Query::default()
+            }],
+        );
+    }
+
     // NL queries should permit arbitrary text in the `target` field, such as `(` and `|`
     #[test]
     fn nl_parse_arbitrary_text() {
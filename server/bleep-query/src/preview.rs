@@ -0,0 +1,195 @@
+//! Preview the effect of query filters on results that are already known.
+//!
+//! The server remains the source of truth. This only narrows down and orders a list of results the
+//! client already holds, so that editing the filters of a query gives immediate feedback.
+
+use std::collections::HashSet;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    languages,
+    parser::{Literal, Query},
+};
+
+/// A search result, reduced to the fields that filters and ranking look at.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Item {
+    pub repo_name: String,
+    pub relative_path: String,
+    #[serde(default)]
+    pub lang: Option<String>,
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub score: f32,
+}
+
+/// The filters of a single query, compiled once for all items.
+struct Filter {
+    repo: Option<Regex>,
+    path: Option<Regex>,
+    lang: Option<String>,
+    branch: Option<Regex>,
+}
+
+impl Filter {
+    fn new(query: &Query<'_>) -> Result<Self, regex::Error> {
+        let compile = |literal: &Option<Literal<'_>>| {
+            literal
+                .as_ref()
+                .map(|l| {
+                    RegexBuilder::new(&l.regex_str())
+                        .case_insensitive(!query.is_case_sensitive())
+                        .build()
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            repo: compile(&query.repo)?,
+            path: compile(&query.path)?,
+            lang: query.lang.as_ref().map(|l| l.to_string()),
+            branch: compile(&query.branch)?,
+        })
+    }
+
+    fn matches(&self, item: &Item) -> bool {
+        let is_match =
+            |re: &Option<Regex>, text: &str| re.as_ref().is_none_or(|re| re.is_match(text));
+
+        let lang_matches = match (&self.lang, &item.lang) {
+            (None, _) => true,
+            (Some(lang), Some(item_lang)) => languages::parse_alias(item_lang.into()) == *lang,
+            (Some(_), None) => false,
+        };
+
+        // Results without a branch come from the default branch, whose name we don't know here,
+        // so they are kept rather than hidden.
+        let branch_matches = item
+            .branch
+            .as_ref()
+            .is_none_or(|branch| is_match(&self.branch, branch));
+
+        is_match(&self.repo, &item.repo_name)
+            && is_match(&self.path, &item.relative_path)
+            && lang_matches
+            && branch_matches
+    }
+}
+
+/// Keep the items that match any of `queries`, ranked and without duplicates.
+///
+/// `queries` is the output of [`crate::parser::parse`]; as on the server, each query is an
+/// alternative.
+pub fn preview(queries: &[Query<'_>], items: Vec<Item>) -> Result<Vec<Item>, regex::Error> {
+    let filters = queries
+        .iter()
+        .map(Filter::new)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let items = items
+        .into_iter()
+        .filter(|item| filters.is_empty() || filters.iter().any(|f| f.matches(item)))
+        .collect();
+
+    Ok(rank(items))
+}
+
+/// Order items by descending score, then by repository and path.
+///
+/// The same file can be returned more than once, for example when it matches several
+/// alternatives of a query. Only its best scoring copy is kept.
+pub fn rank(mut items: Vec<Item>) -> Vec<Item> {
+    items.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.repo_name.cmp(&b.repo_name))
+            .then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+
+    let mut seen = HashSet::new();
+    items.retain(|item| {
+        seen.insert((
+            item.repo_name.clone(),
+            item.relative_path.clone(),
+            item.branch.clone(),
+        ))
+    });
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn item(repo_name: &str, relative_path: &str, lang: &str, score: f32) -> Item {
+        Item {
+            repo_name: repo_name.to_owned(),
+            relative_path: relative_path.to_owned(),
+            lang: Some(lang.to_owned()),
+            branch: None,
+            score,
+        }
+    }
+
+    fn paths(items: &[Item]) -> Vec<&str> {
+        items.iter().map(|i| i.relative_path.as_str()).collect()
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            item("bloop", "server/bleep/src/lib.rs", "Rust", 0.4),
+            item("bloop", "client/src/App.tsx", "TSX", 0.9),
+            item("zoekt", "api.go", "Go", 0.7),
+            item("bloop", "server/bleep/src/lib.rs", "Rust", 0.8),
+        ]
+    }
+
+    #[test]
+    fn filters_by_lang_alias() {
+        let queries = parse("lang:rs foo").unwrap();
+        let items = preview(&queries, items()).unwrap();
+
+        assert_eq!(paths(&items), ["server/bleep/src/lib.rs"]);
+        assert_eq!(items[0].score, 0.8);
+    }
+
+    #[test]
+    fn filters_by_path_and_repo() {
+        let queries = parse("repo:BLOOP path:src foo").unwrap();
+        assert_eq!(
+            paths(&preview(&queries, items()).unwrap()),
+            ["client/src/App.tsx", "server/bleep/src/lib.rs"]
+        );
+
+        let queries = parse("case:sensitive repo:BLOOP foo").unwrap();
+        assert!(preview(&queries, items()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn alternatives_are_combined() {
+        let queries = parse("(repo:zoekt or lang:tsx) foo").unwrap();
+        assert_eq!(
+            paths(&preview(&queries, items()).unwrap()),
+            ["client/src/App.tsx", "api.go"]
+        );
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let queries = parse("path:/src(/ foo").unwrap();
+        assert!(preview(&queries, items()).is_err());
+    }
+
+    #[test]
+    fn rank_dedups() {
+        assert_eq!(
+            paths(&rank(items())),
+            ["client/src/App.tsx", "server/bleep/src/lib.rs", "api.go"]
+        );
+    }
+}
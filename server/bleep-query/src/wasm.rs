@@ -0,0 +1,32 @@
+//! Bindings for the frontend.
+//!
+//! Values cross the boundary as JSON strings, so the frontend can reuse the result types it
+//! already has for the HTTP API.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{parser, preview};
+
+/// Check a search query, returning the parse error if it is invalid.
+#[wasm_bindgen]
+pub fn validate(query: &str) -> Option<String> {
+    parser::parse(query).err().map(|err| err.to_string())
+}
+
+/// Check a natural language query, as sent to the answer API.
+#[wasm_bindgen(js_name = validateNl)]
+pub fn validate_nl(query: &str) -> Option<String> {
+    parser::parse_nl(query).err().map(|err| err.to_string())
+}
+
+/// Apply the filters of a search query to a JSON array of results, and rank them.
+///
+/// See [`preview::Item`] for the fields read from each result.
+#[wasm_bindgen]
+pub fn preview(query: &str, items: &str) -> Result<String, JsError> {
+    let queries = parser::parse(query)?;
+    let items = serde_json::from_str(items)?;
+    let items = preview::preview(&queries, items)?;
+
+    Ok(serde_json::to_string(&items)?)
+}
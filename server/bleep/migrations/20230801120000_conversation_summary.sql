@@ -0,0 +1,2 @@
+-- JSON serialized rolling summary of older exchanges
+ALTER TABLE conversations ADD COLUMN summary TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE policy_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME NOT NULL default (datetime('now')),
+    user_id TEXT,
+    thread_id TEXT NOT NULL,
+    query_id TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    action TEXT NOT NULL,
+    excerpt TEXT NOT NULL
+);
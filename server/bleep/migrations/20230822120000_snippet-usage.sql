@@ -0,0 +1,12 @@
+CREATE TABLE snippet_usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,
+    repo_ref TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    query_id TEXT NOT NULL,
+    -- Either `selected` or `upvoted`
+    signal TEXT NOT NULL
+);
+
+CREATE INDEX snippet_usage_repo_ref ON snippet_usage (repo_ref, created_at);
@@ -0,0 +1,8 @@
+CREATE TABLE last_seen (
+    user_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    commit_id TEXT NOT NULL,
+    -- Seconds since the unix epoch
+    seen_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, repo_ref)
+);
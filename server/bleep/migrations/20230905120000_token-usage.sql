@@ -0,0 +1,15 @@
+CREATE TABLE token_usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at INTEGER NOT NULL,
+    -- Empty for requests without a user, such as bot requests
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    query_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL
+);
+
+CREATE INDEX token_usage_user_id ON token_usage (user_id, created_at);
+CREATE INDEX token_usage_created_at ON token_usage (created_at);
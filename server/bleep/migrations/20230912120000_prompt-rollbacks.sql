@@ -0,0 +1,6 @@
+CREATE TABLE prompt_rollbacks (
+    version TEXT PRIMARY KEY NOT NULL,
+    reason TEXT NOT NULL,
+    -- Seconds since the unix epoch
+    rolled_back_at INTEGER NOT NULL
+);
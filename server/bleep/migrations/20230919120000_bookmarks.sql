@@ -0,0 +1,16 @@
+CREATE TABLE bookmarks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Seconds since the unix epoch
+    created_at INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    -- 1-indexed and inclusive, or both null to bookmark the whole file
+    start_line INTEGER,
+    end_line INTEGER,
+    note TEXT,
+    -- A JSON array of strings
+    tags TEXT NOT NULL
+);
+
+CREATE INDEX bookmarks_user_id ON bookmarks (user_id, repo_ref);
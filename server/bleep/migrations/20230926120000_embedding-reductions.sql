@@ -0,0 +1,5 @@
+CREATE TABLE embedding_reductions (
+    collection_name TEXT PRIMARY KEY NOT NULL,
+    -- JSON of the reduction, or `null` for full-size embeddings
+    reduction TEXT NOT NULL
+);
@@ -0,0 +1,18 @@
+CREATE TABLE answer_feedback (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Seconds since the unix epoch
+    created_at INTEGER NOT NULL,
+    -- Null when authorization isn't required
+    user_id TEXT,
+    thread_id TEXT NOT NULL,
+    query_id TEXT NOT NULL,
+    repo_ref TEXT,
+    -- The version of the agent's prompt template the query was answered with
+    prompt_version TEXT NOT NULL,
+    -- 1 for a thumbs up, 0 for a thumbs down
+    positive INTEGER NOT NULL,
+    comment TEXT
+);
+
+CREATE INDEX answer_feedback_query_id ON answer_feedback (query_id);
+CREATE INDEX answer_feedback_prompt_version ON answer_feedback (prompt_version, created_at);
@@ -0,0 +1,13 @@
+CREATE TABLE user_profiles (
+    user_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    -- `repo` for questions about the repository, `lang` and `dir` for the languages and
+    -- directories of the files their answers were based on
+    kind TEXT NOT NULL,
+    -- A lowercase language name, a directory, or empty for `repo`
+    name TEXT NOT NULL,
+    hits INTEGER NOT NULL,
+    -- Seconds since the unix epoch
+    last_hit_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, repo_ref, kind, name)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE repo_renames (
+    from_ref TEXT PRIMARY KEY NOT NULL,
+    -- The latest name of the repository, even if it was renamed several times
+    to_ref TEXT NOT NULL,
+    -- Seconds since the unix epoch
+    renamed_at INTEGER NOT NULL
+);
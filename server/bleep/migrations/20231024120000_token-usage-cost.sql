@@ -0,0 +1,3 @@
+-- Usage recorded before calls were counted has 0 calls, and no cost
+ALTER TABLE token_usage ADD COLUMN calls INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE token_usage ADD COLUMN cost REAL NOT NULL DEFAULT 0;
@@ -0,0 +1,22 @@
+CREATE TABLE precise_symbols (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    repo_ref TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    -- One of `scip`, `lsif` or `ctags`. All the symbols of a file come from the same source
+    source TEXT NOT NULL,
+    -- Occurrences with the same symbol and source refer to the same entity
+    symbol TEXT NOT NULL,
+    -- The text of the occurrence when it was imported, to detect changes to the file since
+    name TEXT NOT NULL,
+    is_definition BOOLEAN NOT NULL,
+    start_byte INTEGER NOT NULL,
+    end_byte INTEGER NOT NULL,
+    -- 0-indexed
+    start_line INTEGER NOT NULL,
+    end_line INTEGER NOT NULL,
+    start_column INTEGER NOT NULL,
+    end_column INTEGER NOT NULL
+);
+
+CREATE INDEX precise_symbols_file ON precise_symbols (repo_ref, relative_path);
+CREATE INDEX precise_symbols_symbol ON precise_symbols (repo_ref, symbol);
@@ -0,0 +1,2 @@
+-- Hash of the embedding model, or NULL for collections embedded before it was recorded
+ALTER TABLE embedding_reductions ADD COLUMN model_hash TEXT;
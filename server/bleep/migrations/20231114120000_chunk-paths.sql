@@ -0,0 +1,4 @@
+-- Chunks are matched against those of the previous version of their file, so
+-- that only chunks which changed are embedded again.
+ALTER TABLE chunk_cache ADD COLUMN relative_path TEXT;
+CREATE INDEX chunk_cache_path ON chunk_cache (repo_ref, relative_path);
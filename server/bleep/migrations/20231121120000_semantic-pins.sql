@@ -0,0 +1,18 @@
+-- Copies of the semantic index of a repository, which evaluation runs search to compare
+-- retrieval on the same code while syncs keep changing the live index.
+CREATE TABLE semantic_pins (
+    name TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    -- The collection that holds the copied points
+    collection_name TEXT NOT NULL,
+    -- The model that embedded the points, as `name@version`
+    embedding_model TEXT NOT NULL,
+    -- The index generation that was copied: its schema version, and when the repository was
+    -- last indexed, in seconds since the unix epoch
+    schema_version TEXT NOT NULL,
+    indexed_at INTEGER NOT NULL,
+    points INTEGER NOT NULL,
+    -- Seconds since the unix epoch
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (name, repo_ref)
+);
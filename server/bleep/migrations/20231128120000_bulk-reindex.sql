@@ -0,0 +1,13 @@
+-- The repositories of the last bulk re-index, and how far it got, so that it resumes where it
+-- left off after a restart.
+CREATE TABLE bulk_reindex (
+    repo_ref TEXT PRIMARY KEY NOT NULL,
+    -- The order the repositories are re-indexed in
+    position INTEGER NOT NULL,
+    -- `pending`, `running`, `done`, `failed` or `skipped`
+    status TEXT NOT NULL,
+    -- Why the repository failed or was skipped
+    message TEXT,
+    -- Seconds since the unix epoch
+    updated_at INTEGER NOT NULL
+);
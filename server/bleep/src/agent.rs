@@ -1,25 +1,55 @@
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context, Result};
 use futures::TryStreamExt;
+use minijinja::context;
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 
 use crate::{
     analytics::{EventData, QueryEvent},
+    db::{AuditEntry, Bookmarks, SnippetUsage, TokenUsage, UserProfiles},
+    federation,
     indexes::reader::{ContentDocument, FileDocument},
-    llm_gateway::{self, api::FunctionCall},
-    query::parser,
+    llm_gateway::{self, api::FunctionCall, usage::Spend},
+    policy::{Scope, Verdict},
+    query::{correction, parser},
     repo::RepoRef,
-    semantic,
+    secrets, semantic,
+    state::SCHEMA_VERSION,
     webserver::middleware::User,
     Application,
 };
 
-use self::exchange::{Exchange, SearchStep, Update};
+use self::{
+    budget::Budget,
+    diagnostics::{IndexGeneration, Retrieval},
+    exchange::{CodeChunk, Exchange, Outcome, SearchStep, StepTrace, Update},
+    priors::Priors,
+    profile::Profile,
+};
 
+pub mod answer_cache;
+pub mod attachment;
+pub mod budget;
+pub mod classification;
+pub mod diagnostics;
+mod diff;
 pub mod exchange;
+pub mod map_reduce;
+pub mod persona;
+mod priors;
+pub mod profile;
+mod prompt_budget;
 mod prompts;
+pub mod rollout;
+pub mod snippet_filters;
+pub mod stages;
+pub mod summary;
+pub mod templates;
 mod transcoder;
 
 /// A collection of modules that each add methods to `Agent`.
@@ -29,9 +59,16 @@ mod transcoder;
 /// tests.
 mod tools {
     pub mod answer;
+    pub mod clarify;
     pub mod code;
+    pub mod grep;
+    pub mod navigate;
+    pub mod open;
     pub mod path;
     pub mod proc;
+    pub mod route;
+    pub mod stack_trace;
+    pub mod symbols;
 }
 
 const ANSWER_MODEL: &str = "gpt-4-0613";
@@ -41,6 +78,23 @@ pub enum Error {
     Processing(anyhow::Error),
 }
 
+/// A stage of an answer that made no progress within `answer_stage_timeout_secs`.
+#[derive(Debug, thiserror::Error)]
+#[error("{stage} timed out after {duration:?}")]
+pub struct StageTimeout {
+    pub stage: &'static str,
+    pub duration: Duration,
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<StageTimeout>() {
+            Ok(timeout) => Self::Timeout(timeout.duration),
+            Err(err) => Self::Processing(err),
+        }
+    }
+}
+
 pub struct Agent {
     pub app: Application,
     pub repo_ref: RepoRef,
@@ -48,10 +102,29 @@ pub struct Agent {
     pub exchange_tx: Sender<Exchange>,
 
     pub llm_gateway: llm_gateway::Client,
+    pub stages: stages::Stages,
+
+    /// A rolling summary of older exchanges, used in place of them in prompt histories.
+    pub summary: Option<summary::Summary>,
     pub user: User,
     pub thread_id: uuid::Uuid,
     pub query_id: uuid::Uuid,
 
+    /// The limits of a deep answer, which can also open files, list symbols and grep.
+    ///
+    /// This is `None` for regular answers, which only have the search tools and no limits.
+    pub budget: Option<Budget>,
+
+    /// Whether to record how code was retrieved in each exchange, see [`diagnostics`].
+    pub diagnostics: bool,
+
+    /// The number of alternative answers to generate after the answer, at higher temperatures.
+    pub alternatives: usize,
+
+    /// The directory that the code of answers is restricted to, relative to the repository root
+    /// and without slashes at either end.
+    pub path_scope: Option<String>,
+
     /// Indicate whether the request was answered.
     ///
     /// This is used in the `Drop` handler, in order to track cancelled answer queries.
@@ -108,6 +181,95 @@ impl Agent {
         self.app.track_query(&self.user, &event);
     }
 
+    /// Store the tokens spent on this query so far in the last exchange and the database, and
+    /// report them in analytics.
+    pub async fn record_usage(&mut self) {
+        let Some(meter) = &self.llm_gateway.meter else {
+            return;
+        };
+
+        let usage = meter.totals();
+        if usage.is_empty() {
+            return;
+        }
+
+        let mut spend = Spend {
+            cost: self.app.prices.total_cost(&usage),
+            ..Default::default()
+        };
+        for model_usage in usage.values() {
+            spend.usage += *model_usage;
+        }
+        self.last_exchange_mut().usage = Some(spend);
+
+        self.track_query(
+            EventData::output_stage("token usage")
+                .with_payload("models", &usage)
+                .with_payload("total", spend.usage.total())
+                .with_payload("calls", spend.usage.calls)
+                .with_payload("cost", spend.cost),
+        );
+
+        if let Err(err) = TokenUsage::new(&self.app.sql)
+            .insert(
+                self.user.login(),
+                self.thread_id,
+                self.query_id,
+                &self.repo_ref.to_string(),
+                &usage,
+                &self.app.prices,
+            )
+            .await
+        {
+            warn!(?err, "failed to record token usage");
+        }
+    }
+
+    /// Count the answer to the current query towards the context profile of the user.
+    pub async fn record_profile(&self) {
+        let Some(user_id) = self.user.login() else {
+            return;
+        };
+
+        let (langs, dirs) = profile::observed(self.last_exchange());
+        let langs = langs.iter().map(String::as_str).collect::<Vec<_>>();
+        let dirs = dirs.iter().map(String::as_str).collect::<Vec<_>>();
+
+        if let Err(err) = UserProfiles::new(&self.app.sql)
+            .record(user_id, &self.repo_ref.to_string(), &langs, &dirs)
+            .await
+        {
+            warn!(?err, "failed to record user profile");
+        }
+    }
+
+    /// The context profile of the user, which is empty if it could not be loaded.
+    async fn profile(&self) -> Profile {
+        let Some(user_id) = self.user.login() else {
+            return Profile::default();
+        };
+
+        match UserProfiles::new(&self.app.sql).list(user_id).await {
+            Ok(entries) => Profile::new(entries),
+            Err(err) => {
+                warn!(?err, "failed to load user profile");
+                Profile::default()
+            }
+        }
+    }
+
+    /// Run a stage of the answer, failing with a `StageTimeout` if it does not finish in time.
+    async fn stage<T>(
+        &self,
+        stage: &'static str,
+        future: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let duration = Duration::from_secs(self.app.config.answer_stage_timeout_secs);
+        tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| StageTimeout { stage, duration })?
+    }
+
     fn last_exchange(&self) -> &Exchange {
         self.exchanges.last().expect("exchange list was empty")
     }
@@ -123,6 +285,48 @@ impl Agent {
             .map(String::as_str)
     }
 
+    /// A line for each repository of the paths in context, with its description and topics, when
+    /// the paths come from more than one repository.
+    ///
+    /// This tells similarly named repositories apart in cross-repository conversations.
+    async fn repositories(&self) -> Vec<String> {
+        let mut repos = Vec::<(String, Option<RepoRef>)>::new();
+        for path in self.paths() {
+            let (name, repo_ref) = match federation::parse_remote_path(path) {
+                Some((peer, repo_ref, _)) => (format!("{peer}/{repo_ref}"), repo_ref.parse().ok()),
+                None => (self.repo_ref.to_string(), Some(self.repo_ref.clone())),
+            };
+
+            if !repos.iter().any(|(n, _)| n == &name) {
+                repos.push((name, repo_ref));
+            }
+        }
+
+        if repos.len() < 2 {
+            return vec![];
+        }
+
+        let mut lines = Vec::with_capacity(repos.len());
+        for (name, repo_ref) in repos {
+            let about = match repo_ref {
+                Some(repo_ref) => self
+                    .app
+                    .repo_pool
+                    .read_async(&repo_ref, |_, repo| repo.about())
+                    .await
+                    .flatten(),
+                None => None,
+            };
+
+            lines.push(match about {
+                Some(about) => format!("{name}: {about}"),
+                None => name,
+            });
+        }
+
+        lines
+    }
+
     fn get_path_alias(&mut self, path: &str) -> usize {
         // This has to be stored a variable due to a Rust NLL bug:
         // https://github.com/rust-lang/rust/issues/51826
@@ -139,11 +343,56 @@ impl Agent {
     #[instrument(skip(self))]
     pub async fn step(&mut self, action: Action) -> Result<Option<Action>> {
         info!(?action, %self.thread_id, "executing next action");
+        let started = Instant::now();
 
-        match &action {
+        let response = match &action {
             Action::Query(s) => {
                 self.track_query(EventData::input_stage("query").with_payload("q", s));
-                s.clone()
+
+                let Some(query) = self.enforce_policy(Scope::Question, s, true).await? else {
+                    return Ok(None);
+                };
+
+                if query != *s {
+                    self.update(Update::Rewrite(query.clone())).await?;
+                }
+
+                let query = self.correct_query(query).await?;
+
+                if let Some(command) = tools::navigate::parse_command(&query) {
+                    match self.navigate(&command).await {
+                        Ok(true) => return Ok(None),
+                        Ok(false) => debug!("no navigation target found, answering instead"),
+                        Err(err) => warn!(?err, "navigation failed, answering instead"),
+                    }
+                }
+
+                self.search_attachment().await?;
+
+                // A stack trace points at the code to explain, so we answer straight away.
+                let trace = self
+                    .last_exchange()
+                    .prompt()
+                    .as_deref()
+                    .and_then(tools::stack_trace::parse);
+                if let Some(trace) = trace {
+                    let paths = self.fetch_stack_trace(&trace).await?;
+                    if !paths.is_empty() {
+                        return Ok(Some(Action::Answer { paths }));
+                    }
+                }
+
+                if self.route(&query).await? {
+                    return Ok(None);
+                }
+
+                // Follow-up questions are read in the context of the conversation, so only the
+                // opening question is checked for ambiguity.
+                if self.exchanges.len() == 1 && self.clarify(&query).await? {
+                    return Ok(None);
+                }
+
+                query
             }
 
             Action::Answer { paths } => {
@@ -154,23 +403,100 @@ impl Agent {
             Action::Path { query } => self.path_search(query).await?,
             Action::Code { query } => self.code_search(query).await?,
             Action::Proc { query, paths } => self.process_files(query, paths).await?,
+            Action::Open { path, start, end } => self.open_file(*path, *start, *end).await?,
+            Action::Symbols { path } => self.list_symbols(*path).await?,
+            Action::Grep { pattern } => self.grep(pattern).await?,
         };
 
+        // Tool responses are redacted whenever the history is sent, so this only checks whether
+        // the code they found blocks the question.
+        if !matches!(action, Action::Query(_)) && self.redact_secrets(&response).await?.is_none() {
+            return Ok(None);
+        }
+
+        let deep = self.budget.is_some();
         let functions = serde_json::from_value::<Vec<llm_gateway::api::Function>>(
-            prompts::functions(self.paths().next().is_some()), // Only add proc if there are paths in context
+            prompts::functions(self.paths().next().is_some(), deep), // Only add proc if there are paths in context
         )
         .unwrap();
 
-        let mut history = vec![llm_gateway::api::Message::system(&prompts::system(
-            self.paths(),
-        ))];
+        let repos = self.repositories().await;
+        let rollout = Arc::clone(&self.app.prompt_rollout);
+        let template = rollout.template(self.query_id);
+        let paths = self.paths().collect::<Vec<_>>();
+        let system = self.app.prompt_templates.render_or(
+            "action_selection",
+            context! { repos, paths, rules => template.rules, deep },
+            || prompts::system(&repos, paths.iter().copied(), &template.rules, deep),
+        );
+        let mut history = vec![llm_gateway::api::Message::system(&system)];
         history.extend(self.history()?);
 
-        let trimmed_history = trim_history(history.clone())?;
+        let mut trimmed_history =
+            trim_history(history.clone(), self.app.context_windows.size(ANSWER_MODEL))?;
+        let prompt_tokens = tiktoken_rs::num_tokens_from_messages(
+            ANSWER_MODEL,
+            &trimmed_history.iter().map(|m| m.into()).collect::<Vec<_>>(),
+        )?;
+
+        if let Some(budget) = self.budget.as_mut() {
+            if !budget.spend(prompt_tokens) {
+                let (steps, tokens) = (budget.steps(), budget.tokens());
+                warn!(steps, tokens, "deep answer budget exhausted, answering");
+
+                let next = Action::Answer {
+                    paths: self.context_aliases(),
+                };
+
+                self.track_query(
+                    EventData::output_stage("budget_exhausted")
+                        .with_payload("steps", steps)
+                        .with_payload("tokens", tokens),
+                );
+                self.trace(&action, &next, prompt_tokens, started, true)
+                    .await?;
 
-        let raw_response = self
-            .llm_gateway
-            .chat(&trim_history(history.clone())?, Some(&functions))
+                return Ok(Some(next));
+            }
+        }
+
+        let raw_response = match self.function_call(&trimmed_history, &functions).await {
+            // We assumed the wrong context size, so we trim the history again and retry once.
+            Err(err) if self.app.context_windows.learn(ANSWER_MODEL, &err) => {
+                trimmed_history =
+                    trim_history(history.clone(), self.app.context_windows.size(ANSWER_MODEL))?;
+                self.function_call(&trimmed_history, &functions).await?
+            }
+            result => result?,
+        };
+
+        self.track_query(
+            EventData::output_stage("llm_reply")
+                .with_payload("full_history", &history)
+                .with_payload("trimmed_history", &trimmed_history)
+                .with_payload("last_message", history.last())
+                .with_payload("functions", &functions)
+                .with_payload("raw_response", &raw_response)
+                .with_payload("prompt_version", &template.version),
+        );
+
+        let next = Action::deserialize_gpt(&raw_response);
+        rollout.record_step(self.query_id, next.is_ok()).await;
+        let next = next.context("failed to deserialize LLM output")?;
+
+        self.trace(&action, &next, prompt_tokens, started, false)
+            .await?;
+
+        Ok(Some(next))
+    }
+
+    async fn function_call(
+        &self,
+        messages: &[llm_gateway::api::Message],
+        functions: &[llm_gateway::api::Function],
+    ) -> Result<FunctionCall> {
+        self.llm_gateway
+            .chat(messages, Some(functions))
             .await?
             .try_fold(
                 llm_gateway::api::FunctionCall::default(),
@@ -183,21 +509,168 @@ impl Agent {
                 },
             )
             .await
-            .context("failed to fold LLM function call output")?;
+            .context("failed to fold LLM function call output")
+    }
+
+    /// Record a step in the trace of the last exchange.
+    async fn trace(
+        &mut self,
+        action: &Action,
+        next: &Action,
+        prompt_tokens: usize,
+        started: Instant,
+        exhausted: bool,
+    ) -> Result<()> {
+        self.update(Update::Trace(StepTrace {
+            action: serde_json::to_value(action)?,
+            next: serde_json::to_value(next)?,
+            prompt_tokens,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            exhausted,
+        }))
+        .await
+    }
+
+    /// The aliases of the paths that code in the context of the last exchange was taken from.
+    fn context_aliases(&self) -> Vec<usize> {
+        let mut aliases = self
+            .last_exchange()
+            .code_chunks
+            .iter()
+            .map(|c| c.alias)
+            .collect::<Vec<_>>();
+        aliases.sort_unstable();
+        aliases.dedup();
+        aliases
+    }
+
+    /// Correct typos in the identifiers of a query, so that retrieval searches for code that
+    /// actually exists in the repository.
+    ///
+    /// This returns the corrected query, which every later step of the exchange works with.
+    async fn correct_query(&mut self, query: String) -> Result<String> {
+        let branch = self
+            .last_exchange()
+            .query
+            .first_branch()
+            .map(|b| b.into_owned());
+        let (corrected, corrections) = correction::correct(
+            &self.app.indexes.file,
+            &self.repo_ref,
+            branch.as_deref(),
+            &query,
+        )
+        .await;
+
+        if corrections.is_empty() {
+            return Ok(query);
+        }
 
         self.track_query(
-            EventData::output_stage("llm_reply")
-                .with_payload("full_history", &history)
-                .with_payload("trimmed_history", &trimmed_history)
-                .with_payload("last_message", history.last())
-                .with_payload("functions", &functions)
-                .with_payload("raw_response", &raw_response),
+            EventData::output_stage("correct_query")
+                .with_payload("corrected", &corrected)
+                .with_payload("corrections", &corrections),
         );
 
-        let action =
-            Action::deserialize_gpt(&raw_response).context("failed to deserialize LLM output")?;
+        self.update(Update::Correct(corrected.clone(), corrections))
+            .await?;
 
-        Ok(Some(action))
+        Ok(corrected)
+    }
+
+    /// Check `text` against the configured policy.
+    ///
+    /// This returns the text to use, which may have been rewritten, or `None` if the text was
+    /// blocked, in which case the exchange is concluded with a policy violation. Blocks are always
+    /// recorded in the audit log, but rewrites only when `audit_rewrites` is set, so that answers
+    /// checked while streaming are recorded once.
+    async fn enforce_policy(
+        &mut self,
+        scope: Scope,
+        text: &str,
+        audit_rewrites: bool,
+    ) -> Result<Option<String>> {
+        match self.app.policy.check(scope, text) {
+            Verdict::Allow => Ok(Some(text.to_owned())),
+            Verdict::Rewrite {
+                text: rewritten,
+                rules,
+            } => {
+                if audit_rewrites {
+                    for rule in &rules {
+                        self.audit_policy(scope, rule, "rewrite", text).await;
+                    }
+                }
+
+                Ok(Some(rewritten))
+            }
+            Verdict::Block { rule, message } => {
+                warn!(?scope, %rule, "blocked by policy");
+                self.audit_policy(scope, &rule, "block", text).await;
+                self.update(Update::Outcome(Outcome::PolicyViolation { message, rule }))
+                    .await?;
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Redact the secrets in code that is about to be sent to the LLM.
+    ///
+    /// This returns the redacted code, or `None` if `block_secrets` is set and the code contains
+    /// secrets that look real, in which case the exchange is concluded with a policy violation.
+    async fn redact_secrets(&mut self, code: &str) -> Result<Option<String>> {
+        let redaction = secrets::redact(code);
+        if redaction.kinds.is_empty() {
+            return Ok(Some(code.to_owned()));
+        }
+
+        let blocked = self.app.config.block_secrets && redaction.high_entropy;
+        self.track_query(
+            EventData::output_stage("secrets")
+                .with_payload("kinds", &redaction.kinds)
+                .with_payload("action", if blocked { "block" } else { "redact" }),
+        );
+
+        if !blocked {
+            debug!(kinds = ?redaction.kinds, "redacted secrets");
+            return Ok(Some(redaction.text.into_owned()));
+        }
+
+        warn!(kinds = ?redaction.kinds, "blocked by secrets in code");
+        self.update(Update::Outcome(Outcome::PolicyViolation {
+            message: "The code needed to answer this contains credentials, which are never \
+                      sent to the language model."
+                .to_owned(),
+            rule: "secrets".to_owned(),
+        }))
+        .await?;
+
+        Ok(None)
+    }
+
+    /// Record a policy decision in the audit log and in analytics.
+    async fn audit_policy(&self, scope: Scope, rule: &str, action: &str, text: &str) {
+        let scope = scope.as_str();
+
+        self.track_query(
+            EventData::output_stage("policy")
+                .with_payload("scope", scope)
+                .with_payload("rule", rule)
+                .with_payload("action", action),
+        );
+
+        let entry = AuditEntry {
+            user_id: self.user.login(),
+            thread_id: self.thread_id,
+            query_id: self.query_id,
+            scope,
+            rule,
+            action,
+            excerpt: text,
+        };
+
+        self.app.audit_policy(&entry).await;
     }
 
     /// The full history of messages, including intermediate function calls
@@ -205,15 +678,23 @@ impl Agent {
         const ANSWER_MAX_HISTORY_SIZE: usize = 3;
         const FUNCTION_CALL_INSTRUCTION: &str = "Call a function. Do not answer";
 
+        let summarized = self.summary.as_ref().map_or(0, |s| s.exchanges);
+        let summary = self
+            .summary
+            .iter()
+            .map(summary::Summary::message)
+            .collect::<Vec<_>>();
+
         let history = self
             .exchanges
             .iter()
+            .skip(summarized)
             .rev()
             .take(ANSWER_MAX_HISTORY_SIZE)
             .rev()
-            .try_fold(Vec::new(), |mut acc, e| -> Result<_> {
+            .try_fold(summary, |mut acc, e| -> Result<_> {
                 let query = e
-                    .query()
+                    .prompt()
                     .map(|q| llm_gateway::api::Message::user(&q))
                     .ok_or_else(|| anyhow!("query does not have target"))?;
 
@@ -242,6 +723,28 @@ impl Agent {
                                     .join(", ")
                             ),
                         ),
+                        SearchStep::Open {
+                            path, start, end, ..
+                        } => (
+                            "open".to_owned(),
+                            serde_json::json!({
+                                "path": self.paths().position(|p| p == path).unwrap(),
+                                "start": start,
+                                "end": end,
+                            })
+                            .to_string(),
+                        ),
+                        SearchStep::Symbols { path, .. } => (
+                            "symbols".to_owned(),
+                            serde_json::json!({
+                                "path": self.paths().position(|p| p == path).unwrap(),
+                            })
+                            .to_string(),
+                        ),
+                        SearchStep::Grep { pattern, .. } => (
+                            "grep".to_owned(),
+                            serde_json::json!({ "pattern": pattern }).to_string(),
+                        ),
                     };
 
                     vec![
@@ -249,7 +752,10 @@ impl Agent {
                             name: Some(name.clone()),
                             arguments,
                         }),
-                        llm_gateway::api::Message::function_return(&name, &s.get_response()),
+                        llm_gateway::api::Message::function_return(
+                            &name,
+                            &secrets::redact(&s.get_response()).text,
+                        ),
                         llm_gateway::api::Message::user(FUNCTION_CALL_INSTRUCTION),
                     ]
                 });
@@ -278,26 +784,174 @@ impl Agent {
     }
 
     async fn semantic_search(
-        &self,
+        &mut self,
         query: parser::Literal<'_>,
         limit: u64,
         offset: u64,
         threshold: f32,
         retrieve_more: bool,
     ) -> Result<Vec<semantic::Payload>> {
-        let query = parser::SemanticQuery {
+        let mut query = parser::SemanticQuery {
             target: Some(query),
             repos: [parser::Literal::Plain(self.repo_ref.display_name().into())].into(),
             ..self.last_exchange().query.clone()
         };
 
+        // Paths that merely contain the scope are dropped below.
+        if let Some(scope) = &self.path_scope {
+            query
+                .paths
+                .insert(parser::Literal::Plain(scope.clone().into()));
+        }
+
+        let priors = self.usage_priors().await;
+        let candidates = if priors.is_empty() {
+            limit
+        } else {
+            limit * priors::CANDIDATE_FACTOR
+        };
+
         debug!(?query, %self.thread_id, "executing semantic query");
-        self.app
-            .semantic
-            .as_ref()
-            .unwrap()
-            .search(&query, limit, offset, threshold, retrieve_more)
+        let mut diagnostics = Retrieval::new(query.target().unwrap_or_default());
+        let retrieval = self.stages.retriever.retrieve(
+            &query,
+            candidates,
+            offset,
+            threshold,
+            retrieve_more,
+            &mut diagnostics,
+        );
+        let retrieved = self.stage("retrieval", retrieval).await?;
+        let retrieved_count = retrieved.len();
+
+        let mut results = retrieved
+            .into_iter()
+            .filter(|payload| !self.is_license_excluded(payload.license.as_deref()))
+            .collect::<Vec<_>>();
+        diagnostics.license_excluded = retrieved_count - results.len();
+
+        results.retain(|payload| self.in_scope(&payload.relative_path));
+
+        let unrestricted_count = results.len();
+        results.retain(|payload| !self.is_retrieval_only(&payload.repo_ref));
+        diagnostics.retrieval_only = unrestricted_count - results.len();
+
+        if !self.app.snippet_filters.is_empty() {
+            let filters = Arc::clone(&self.app.snippet_filters);
+            let (filtered, dropped) = tokio::task::spawn_blocking(move || {
+                let dropped = filters.apply(&mut results);
+                (results, dropped)
+            })
+            .await?;
+
+            results = filtered;
+            diagnostics.filtered = dropped;
+        }
+
+        if !priors.is_empty() {
+            priors.rerank(&mut results);
+            results.truncate(limit as usize);
+            diagnostics.usage_boosted = true;
+        }
+
+        if self.diagnostics {
+            diagnostics.returned(&results);
+            let index = self.index_generation().await;
+            self.update(Update::Diagnose(diagnostics, index)).await?;
+        }
+
+        Ok(results)
+    }
+
+    /// The state of this repository's index, for diagnostics.
+    async fn index_generation(&self) -> IndexGeneration {
+        let indexed_at = self
+            .app
+            .repo_pool
+            .read_async(&self.repo_ref, |_, repo| repo.last_index_unix_secs)
+            .await
+            .unwrap_or_default();
+
+        IndexGeneration {
+            repo_ref: self.repo_ref.to_string(),
+            schema_version: SCHEMA_VERSION.to_owned(),
+            previous_generation: self.app.indexes.file.serves_previous(),
+            indexed_at,
+            embedding_model: self
+                .app
+                .semantic
+                .as_ref()
+                .map(|s| s.model_for(&self.repo_ref.indexed_name()).id()),
+        }
+    }
+
+    /// Priors derived from how often files in this repository were used in earlier answers, from
+    /// the files the user bookmarked, and from the user's context profile.
+    ///
+    /// Usage is left out when `disable_usage_boost` is set, or when it could not be loaded.
+    async fn usage_priors(&self) -> Priors {
+        let repo_ref = self.repo_ref.to_string();
+        let mut priors = self.answer_priors(&repo_ref).await;
+
+        if let Some(user_id) = self.user.login() {
+            match Bookmarks::new(&self.app.sql)
+                .paths(user_id, &repo_ref)
+                .await
+            {
+                Ok(paths) => paths.iter().for_each(|path| priors.bookmark(path)),
+                Err(err) => warn!(?err, "failed to load bookmarks"),
+            }
+        }
+
+        priors.profile(&self.profile().await, &repo_ref);
+        priors
+    }
+
+    async fn answer_priors(&self, repo_ref: &str) -> Priors {
+        if self.app.config.disable_usage_boost {
+            return Priors::default();
+        }
+
+        let half_life = self.app.config.usage_half_life_days;
+        let now = chrono::Utc::now().timestamp();
+
+        match SnippetUsage::new(&self.app.sql)
+            .since(repo_ref, Priors::horizon(now, half_life))
             .await
+        {
+            Ok(events) => Priors::new(&events, now, half_life),
+            Err(err) => {
+                warn!(?err, "failed to load usage priors");
+                Priors::default()
+            }
+        }
+    }
+
+    /// Record that chunks from these files were added to the context of the current answer.
+    async fn record_selection(&self, chunks: &[&CodeChunk]) {
+        let mut paths = chunks
+            .iter()
+            .map(|c| c.path.as_str())
+            .filter(|path| federation::parse_remote_path(path).is_none())
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths.dedup();
+
+        if paths.is_empty() {
+            return;
+        }
+
+        if let Err(err) = SnippetUsage::new(&self.app.sql)
+            .insert_selected(
+                &self.repo_ref.to_string(),
+                self.thread_id,
+                self.query_id,
+                &paths,
+            )
+            .await
+        {
+            warn!(?err, "failed to record snippet usage");
+        }
     }
 
     #[allow(dead_code)]
@@ -330,6 +984,29 @@ impl Agent {
     }
 
     async fn get_file_content(&self, path: &str) -> Result<Option<ContentDocument>> {
+        if self.is_retrieval_only(&self.repo_ref.to_string()) {
+            return Ok(None);
+        }
+
+        if let Some((peer_name, repo_ref, relative_path)) = federation::parse_remote_path(path) {
+            if let Some(peer) = self.app.federated_peers.read(peer_name, |_, p| p.clone()) {
+                debug!(
+                    peer_name,
+                    repo_ref,
+                    relative_path,
+                    %self.thread_id,
+                    "executing remote file search"
+                );
+                return federation::fetch_file(&peer, repo_ref, relative_path)
+                    .await
+                    .with_context(|| format!("failed to read remote path: {}", path));
+            }
+        }
+
+        if !self.in_scope(path) {
+            return Ok(None);
+        }
+
         let branch = self.last_exchange().query.first_branch();
 
         debug!(%self.repo_ref, path, ?branch, %self.thread_id, "executing file search");
@@ -353,18 +1030,58 @@ impl Agent {
             .file
             .fuzzy_path_match(&self.repo_ref, query, branch.as_deref(), 50)
             .await
+            .filter(|doc| !self.is_license_excluded(doc.license.as_deref()))
+            .filter(|doc| self.in_scope(&doc.relative_path))
+    }
+
+    /// Whether a path is within the `path_scope` of the answer, if it has one.
+    fn in_scope(&self, path: &str) -> bool {
+        self.path_scope
+            .as_deref()
+            .map_or(true, |scope| is_within(path, scope))
     }
+
+    /// Whether code under `license` is left out of answers, as configured with
+    /// `excluded_licenses`.
+    fn is_license_excluded(&self, license: Option<&str>) -> bool {
+        self.app.is_license_excluded(license)
+    }
+
+    /// Whether the code of a repository is kept from the LLM, because it is set to
+    /// `retrieval_only`.
+    fn is_retrieval_only(&self, repo_ref: &str) -> bool {
+        repo_ref
+            .parse::<RepoRef>()
+            .ok()
+            .and_then(|repo_ref| {
+                self.app
+                    .repo_pool
+                    .read(&repo_ref, |_, repo| repo.retrieval_only)
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Whether a relative path is `dir`, or is in it.
+fn is_within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
 }
 
+/// Hide function returns and assistant messages in `history`, oldest first, until it fits into a
+/// context of `context_size` tokens with room for a reply.
 fn trim_history(
     mut history: Vec<llm_gateway::api::Message>,
+    context_size: usize,
 ) -> Result<Vec<llm_gateway::api::Message>> {
     const HEADROOM: usize = 2048;
     const HIDDEN: &str = "[HIDDEN]";
 
     let mut tiktoken_msgs = history.iter().map(|m| m.into()).collect::<Vec<_>>();
 
-    while tiktoken_rs::get_chat_completion_max_tokens(ANSWER_MODEL, &tiktoken_msgs)? < HEADROOM {
+    while tiktoken_rs::num_tokens_from_messages(ANSWER_MODEL, &tiktoken_msgs)? + HEADROOM
+        > context_size
+    {
         let _ = history
             .iter_mut()
             .zip(tiktoken_msgs.iter_mut())
@@ -418,6 +1135,17 @@ pub enum Action {
         query: String,
         paths: Vec<usize>,
     },
+    Open {
+        path: usize,
+        start: Option<usize>,
+        end: Option<usize>,
+    },
+    Symbols {
+        path: usize,
+    },
+    Grep {
+        pattern: String,
+    },
 }
 
 impl Action {
@@ -455,6 +1183,23 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn scopes_paths_to_directories() {
+        assert!(is_within(
+            "services/billing/src/main.rs",
+            "services/billing"
+        ));
+        assert!(is_within("services/billing", "services/billing"));
+        assert!(!is_within(
+            "services/billing-v2/src/main.rs",
+            "services/billing"
+        ));
+        assert!(!is_within(
+            "lib/services/billing/main.rs",
+            "services/billing"
+        ));
+    }
+
     #[test]
     fn test_trimming_history() {
         let long_string = "long string ".repeat(2000);
@@ -471,7 +1216,7 @@ mod tests {
         ];
 
         assert_eq!(
-            trim_history(history).unwrap(),
+            trim_history(history.clone(), 8192).unwrap(),
             vec![
                 llm_gateway::api::Message::system("foo"),
                 llm_gateway::api::Message::user("bar"),
@@ -484,5 +1229,24 @@ mod tests {
                 llm_gateway::api::Message::user("corge"),
             ]
         );
+
+        // Nothing is hidden when the history fits into a larger context.
+        assert_eq!(trim_history(history.clone(), 32768).unwrap(), history);
+    }
+
+    #[test]
+    fn stage_timeouts_are_timeouts() {
+        let duration = Duration::from_secs(3);
+        let err = anyhow::Error::from(StageTimeout {
+            stage: "retrieval",
+            duration,
+        })
+        .context("search failed");
+
+        assert!(matches!(Error::from(err), Error::Timeout(d) if d == duration));
+        assert!(matches!(
+            Error::from(anyhow!("upstream error")),
+            Error::Processing(_)
+        ));
     }
 }
@@ -0,0 +1,179 @@
+//! Answers to questions that start a conversation, reused while the repository is unchanged.
+//!
+//! Answers are keyed by the normalized question and by the time the repository was last indexed,
+//! which changes whenever its HEAD does, so that a new commit invalidates every answer about the
+//! repository. They are also keyed by the user, as their bookmarks and context profile bias the
+//! code that answers are retrieved from. Entries expire after `answer_cache_ttl_secs`, and requests can skip the cache with
+//! `no_cache`. Follow-up questions depend on the rest of the conversation, and are never cached.
+
+use std::time::{Duration, Instant};
+
+use super::exchange::Exchange;
+use crate::repo::RepoRef;
+
+/// The most answers kept at once. Answers are not cached while the cache is full of entries that
+/// have yet to expire.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    query: String,
+    user_id: Option<String>,
+    repo_ref: RepoRef,
+    indexed_at: u64,
+    deep: bool,
+    federated: bool,
+    hybrid: bool,
+}
+
+impl Key {
+    pub fn new(
+        query: &str,
+        user_id: Option<&str>,
+        repo_ref: RepoRef,
+        indexed_at: u64,
+        deep: bool,
+        federated: bool,
+        hybrid: bool,
+    ) -> Self {
+        Self {
+            query: normalize(query),
+            user_id: user_id.map(str::to_owned),
+            repo_ref,
+            indexed_at,
+            deep,
+            federated,
+            hybrid,
+        }
+    }
+}
+
+pub struct AnswerCache {
+    ttl: Duration,
+    entries: scc::HashMap<Key, (Instant, Exchange)>,
+}
+
+impl AnswerCache {
+    /// A cache whose answers expire after `ttl`. A zero `ttl` disables the cache.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: scc::HashMap::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// The answered exchange cached under `key`, if it hasn't expired.
+    pub fn get(&self, key: &Key) -> Option<Exchange> {
+        let (created, exchange) = self.entries.read(key, |_, entry| entry.clone())?;
+        if created.elapsed() < self.ttl {
+            return Some(exchange);
+        }
+
+        self.entries.remove(key);
+        None
+    }
+
+    /// Cache an exchange, if it ended with an answer.
+    pub fn insert(&self, key: Key, exchange: &Exchange) {
+        if !self.is_enabled() || exchange.answer().is_none() {
+            return;
+        }
+
+        self.entries
+            .retain(|_, (created, _)| created.elapsed() < self.ttl);
+        if self.entries.len() >= MAX_ENTRIES {
+            return;
+        }
+
+        self.entries.upsert(key, (Instant::now(), exchange.clone()));
+    }
+}
+
+/// Fold the differences between questions that don't change their meaning: case, whitespace, and
+/// trailing punctuation.
+pub(super) fn normalize(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() && c != ')' && c != '`')
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::exchange::Update;
+
+    fn answered() -> Exchange {
+        let mut exchange = Exchange::default();
+        exchange.apply_update(Update::Article("It parses queries.".to_owned()));
+        exchange.apply_update(Update::Conclude("Anything else?".to_owned()));
+        exchange
+    }
+
+    fn key(query: &str, indexed_at: u64) -> Key {
+        Key::new(
+            query,
+            Some("alice"),
+            RepoRef::from("github.com/BloopAI/bloop"),
+            indexed_at,
+            false,
+            false,
+            false,
+        )
+    }
+
+    #[test]
+    fn normalizes_queries() {
+        assert_eq!(
+            normalize("  What does   the Parser do?? "),
+            "what does the parser do"
+        );
+        assert_eq!(normalize("what calls `parse()`"), "what calls `parse()`");
+        assert_eq!(key("Where is main?", 1), key("where is main", 1));
+        assert_ne!(key("where is main", 1), key("where is main", 2));
+    }
+
+    #[test]
+    fn caches_answers() {
+        let cache = AnswerCache::new(Duration::from_secs(60));
+
+        cache.insert(key("where is main", 1), &Exchange::default());
+        assert!(cache.get(&key("where is main", 1)).is_none());
+
+        cache.insert(key("where is main", 1), &answered());
+        let cached = cache.get(&key("Where is main?", 1)).unwrap();
+        assert_eq!(cached.answer(), answered().answer());
+        assert!(cache.get(&key("where is main", 2)).is_none());
+
+        // Retrieval is biased by the bookmarks and profile of the user who asked.
+        let other_user = Key::new(
+            "where is main",
+            Some("bob"),
+            RepoRef::from("github.com/BloopAI/bloop"),
+            1,
+            false,
+            false,
+            false,
+        );
+        assert!(cache.get(&other_user).is_none());
+    }
+
+    #[test]
+    fn expires_answers() {
+        let cache = AnswerCache::new(Duration::from_millis(1));
+        cache.insert(key("where is main", 1), &answered());
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get(&key("where is main", 1)).is_none());
+
+        let disabled = AnswerCache::new(Duration::ZERO);
+        disabled.insert(key("where is main", 1), &answered());
+        assert!(disabled.get(&key("where is main", 1)).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+//! Images attached to queries, such as screenshots of code or stack traces.
+//!
+//! The text in an image is read by a vision-capable model when `vision_model` is set, and
+//! otherwise by the command in `ocr_command`. It is added to the query in prompts, and the paths
+//! and identifiers in it are searched for before the agent picks its first action.
+
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use futures::TryStreamExt;
+use lazy_regex::regex;
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use super::{prompts, Agent};
+use crate::{analytics::EventData, llm_gateway, Configuration};
+
+/// The largest image that can be attached, in bytes.
+pub const MAX_IMAGE_BYTES: usize = 1024 * 1024;
+
+/// The most text kept from an image, in characters.
+const MAX_TEXT_CHARS: usize = 4000;
+
+const MAX_PATHS: usize = 2;
+const MAX_IDENTIFIERS: usize = 10;
+
+/// How long `ocr_command` can take to read an image.
+const OCR_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Image {
+    mime: String,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Parse an image from a URL like `data:image/png;base64,...`.
+    pub fn from_data_url(url: &str) -> Result<Self> {
+        let (header, data) = url
+            .strip_prefix("data:")
+            .and_then(|url| url.split_once(','))
+            .context("image is not a data URL")?;
+
+        let mime = header
+            .strip_suffix(";base64")
+            .context("image is not base64 encoded")?;
+        if !mime.starts_with("image/") {
+            bail!("attachment is not an image: {mime}");
+        }
+
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .context("invalid base64 in image")?;
+        if data.len() > MAX_IMAGE_BYTES {
+            bail!("image is larger than {MAX_IMAGE_BYTES} bytes");
+        }
+
+        Ok(Self {
+            mime: mime.to_owned(),
+            data,
+        })
+    }
+
+    fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.mime,
+            base64::engine::general_purpose::STANDARD.encode(&self.data)
+        )
+    }
+}
+
+pub fn is_enabled(config: &Configuration) -> bool {
+    config.vision_model.is_some() || config.ocr_command.is_some()
+}
+
+/// Read the text in an image.
+pub async fn extract_text(
+    config: &Configuration,
+    llm_gateway: &llm_gateway::Client,
+    image: &Image,
+) -> Result<String> {
+    let text = match (&config.vision_model, &config.ocr_command) {
+        (Some(model), _) => read_with_model(llm_gateway, model, image).await?,
+        (None, Some(command)) => run_ocr(command, &image.data).await?,
+        (None, None) => bail!("reading images needs `vision_model` or `ocr_command`"),
+    };
+
+    Ok(text.trim().chars().take(MAX_TEXT_CHARS).collect())
+}
+
+async fn read_with_model(
+    llm_gateway: &llm_gateway::Client,
+    model: &str,
+    image: &Image,
+) -> Result<String> {
+    let messages = [llm_gateway::api::Message::user_with_image(
+        prompts::image_transcription_prompt(),
+        &image.data_url(),
+    )];
+
+    llm_gateway
+        .clone()
+        .model(model)
+        .chat(&messages, None)
+        .await?
+        .try_collect::<String>()
+        .await
+}
+
+async fn run_ocr(command: &str, image: &[u8]) -> Result<String> {
+    let mut args = command.split_whitespace();
+    let program = args.next().context("`ocr_command` is empty")?;
+
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to run `{program}`"))?;
+
+    // The command can fill its stdout before it has read the whole image, so the image is written
+    // while waiting for the output. Dropping stdin once it's written closes it, so that the command
+    // can finish.
+    let mut stdin = child.stdin.take().context("OCR command has no stdin")?;
+    let image = image.to_vec();
+    let writer = tokio::spawn(async move { stdin.write_all(&image).await });
+
+    // The child is killed when the timeout drops it.
+    let output = tokio::time::timeout(OCR_TIMEOUT, child.wait_with_output())
+        .await
+        .with_context(|| format!("`{program}` timed out after {OCR_TIMEOUT:?}"))??;
+    if !output.status.success() {
+        bail!(
+            "`{program}` failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    writer
+        .await?
+        .with_context(|| format!("failed to write the image to `{program}`"))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Paths and identifiers in text from an image, in order of appearance.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Keys {
+    paths: Vec<String>,
+    identifiers: Vec<String>,
+}
+
+impl Keys {
+    fn new(text: &str) -> Self {
+        let mut keys = Self::default();
+
+        for m in regex!(r"(?:[\w.-]+/)+[\w-]+\.\w+").find_iter(text) {
+            let path = m.as_str().trim_start_matches("./").to_owned();
+            if keys.paths.len() < MAX_PATHS && !keys.paths.contains(&path) {
+                keys.paths.push(path);
+            }
+        }
+
+        // Identifiers are told apart from prose by an underscore or an inner capital letter, as
+        // in `snake_case`, `camelCase` or `PascalCase`.
+        for m in regex!(r"\b[A-Za-z_][A-Za-z0-9_]{3,}\b").find_iter(text) {
+            let word = m.as_str();
+            let is_identifier = word.trim_matches('_').contains('_')
+                || (word.chars().skip(1).any(|c| c.is_ascii_uppercase())
+                    && word.chars().any(|c| c.is_ascii_lowercase()));
+
+            if is_identifier
+                && keys.identifiers.len() < MAX_IDENTIFIERS
+                && !keys.identifiers.iter().any(|i| i == word)
+            {
+                keys.identifiers.push(word.to_owned());
+            }
+        }
+
+        keys
+    }
+}
+
+impl Agent {
+    /// Search for the paths and identifiers in the image attached to the last query, if any.
+    #[instrument(skip(self))]
+    pub async fn search_attachment(&mut self) -> Result<()> {
+        let Some(text) = self.last_exchange().attachment.clone() else {
+            return Ok(());
+        };
+
+        let keys = Keys::new(&text);
+
+        for path in &keys.paths {
+            self.path_search(path).await?;
+        }
+
+        if !keys.identifiers.is_empty() {
+            self.code_search(&keys.identifiers.join(" ")).await?;
+        }
+
+        self.track_query(
+            EventData::input_stage("attachment")
+                .with_payload("text", &text)
+                .with_payload("paths", &keys.paths)
+                .with_payload("identifiers", &keys.identifiers),
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_data_urls() {
+        let image = Image::from_data_url("data:image/png;base64,aGVsbG8=").unwrap();
+        assert_eq!(image.mime, "image/png");
+        assert_eq!(image.data, b"hello");
+        assert_eq!(image.data_url(), "data:image/png;base64,aGVsbG8=");
+
+        assert!(Image::from_data_url("https://example.com/a.png").is_err());
+        assert!(Image::from_data_url("data:text/plain;base64,aGVsbG8=").is_err());
+        assert!(Image::from_data_url("data:image/png,hello").is_err());
+        assert!(Image::from_data_url("data:image/png;base64,!!!").is_err());
+    }
+
+    #[test]
+    fn finds_keys_in_stack_traces() {
+        let text = "\
+thread 'main' panicked at 'called `Option::unwrap()` on a `None` value'
+   at bleep::indexes::file_cache::FileCache::process_chunk (./src/cache.rs:412)
+   at bleep::background::sync_queue (src/background.rs:88)
+   at handleRequest (client/src/api.ts:10)
+Please help, this happens when syncing";
+
+        assert_eq!(
+            Keys::new(text),
+            Keys {
+                paths: vec!["src/cache.rs".to_owned(), "src/background.rs".to_owned()],
+                identifiers: vec![
+                    "file_cache".to_owned(),
+                    "FileCache".to_owned(),
+                    "process_chunk".to_owned(),
+                    "sync_queue".to_owned(),
+                    "handleRequest".to_owned(),
+                ],
+            }
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reads_output_while_writing_the_image() {
+        // Larger than a pipe's buffer, so that `cat` blocks on its stdout until it's read.
+        let image = vec![b'a'; MAX_IMAGE_BYTES];
+        let text = run_ocr("cat", &image).await.unwrap();
+        assert_eq!(text.len(), image.len());
+    }
+}
@@ -0,0 +1,70 @@
+/// The steps and prompt tokens available to the tool-use loop of a deep answer.
+///
+/// Every call to the LLM that picks the next tool spends one step, along with the tokens of the
+/// prompt it was sent. Once either limit would be exceeded, the agent stops calling tools and
+/// answers with what it has gathered so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    max_steps: usize,
+    max_tokens: usize,
+    steps: usize,
+    tokens: usize,
+}
+
+impl Budget {
+    pub fn new(max_steps: usize, max_tokens: usize) -> Self {
+        Self {
+            max_steps,
+            max_tokens,
+            steps: 0,
+            tokens: 0,
+        }
+    }
+
+    /// Spend a step with a prompt of `tokens` tokens.
+    ///
+    /// Returns `false`, without spending anything, if this would exceed either limit.
+    pub fn spend(&mut self, tokens: usize) -> bool {
+        if self.steps >= self.max_steps || self.tokens + tokens > self.max_tokens {
+            return false;
+        }
+
+        self.steps += 1;
+        self.tokens += tokens;
+        true
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_steps() {
+        let mut budget = Budget::new(2, 1000);
+
+        assert!(budget.spend(10));
+        assert!(budget.spend(10));
+        assert!(!budget.spend(10));
+        assert_eq!((budget.steps(), budget.tokens()), (2, 20));
+    }
+
+    #[test]
+    fn limits_tokens() {
+        let mut budget = Budget::new(10, 100);
+
+        assert!(budget.spend(60));
+        assert!(!budget.spend(60));
+        assert!(budget.spend(40));
+        assert!(!budget.spend(1));
+        assert_eq!((budget.steps(), budget.tokens()), (2, 100));
+    }
+}
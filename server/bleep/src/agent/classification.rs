@@ -0,0 +1,98 @@
+//! Shortcuts around the LLM call that classifies queries before they are answered.
+//!
+//! Queries that obviously refer to code are answered from the code straight away, and the
+//! classifications of questions that start a conversation are cached, keyed by the normalized
+//! question. Follow-up questions are classified in the context of the conversation, and are never
+//! cached.
+
+use lazy_regex::regex;
+
+use super::{answer_cache::normalize, exchange::AnswerKind};
+
+/// The most classifications kept at once. The cache is emptied when it fills up.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Whether `query` obviously refers to code, so that there is no need to classify it.
+///
+/// This looks for code blocks and inline code, paths, and identifiers written in the conventions
+/// of code: `snake_case`, `camelCase`, `Type::member` and `call()`. `PascalCase` is left out, as
+/// it is also how many products are named.
+pub fn looks_like_code(query: &str) -> bool {
+    if query.contains('`') || query.contains("::") {
+        return true;
+    }
+
+    let identifiers = [
+        regex!(r"\b[A-Za-z][A-Za-z0-9]*(_[A-Za-z0-9]+)+\b"),
+        regex!(r"\b[a-z][a-z0-9]*[A-Z][A-Za-z0-9]*\b"),
+        regex!(r"\b[A-Za-z_]\w*\(\)"),
+    ];
+    if identifiers.iter().any(|re| re.is_match(query)) {
+        return true;
+    }
+
+    regex!(r"(?:^|\s)/?([\w.-]+(?:/[\w.-]+)+)")
+        .captures_iter(query)
+        .any(|c| is_path(&c[1]))
+}
+
+/// Whether a word with slashes in it is a path. "and/or" or "TCP/IP" are not, so paths need at
+/// least three segments, or a file name with an extension.
+fn is_path(word: &str) -> bool {
+    let word = word.trim_end_matches(['.', ',']);
+    let name = word.rsplit('/').next().unwrap_or_default();
+
+    word.split('/').count() >= 3 || regex!(r"^[\w-]+\.[A-Za-z][A-Za-z0-9]{0,4}$").is_match(name)
+}
+
+/// Classifications of the questions that start conversations.
+#[derive(Default)]
+pub struct ClassificationCache {
+    entries: scc::HashMap<String, AnswerKind>,
+}
+
+impl ClassificationCache {
+    pub fn get(&self, query: &str) -> Option<AnswerKind> {
+        self.entries.read(&normalize(query), |_, kind| *kind)
+    }
+
+    pub fn insert(&self, query: &str, kind: AnswerKind) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.clear();
+        }
+
+        self.entries.upsert(normalize(query), kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_code() {
+        assert!(looks_like_code("what does ```fn main() {}``` do"));
+        assert!(looks_like_code("where is `Agent` defined?"));
+        assert!(looks_like_code("how is RepoRef::from used"));
+        assert!(looks_like_code("what sets retrieval_only?"));
+        assert!(looks_like_code("who calls getUserProfile"));
+        assert!(looks_like_code("what does parse() return"));
+        assert!(looks_like_code("explain server/bleep/src/agent"));
+        assert!(looks_like_code("what is in webserver/answer.rs?"));
+
+        assert!(!looks_like_code("hi there!"));
+        assert!(!looks_like_code("how do I use GitHub and/or GitLab?"));
+        assert!(!looks_like_code("what is TCP/IP"));
+        assert!(!looks_like_code("how does authentication work?"));
+        assert!(!looks_like_code("what's the weather like"));
+    }
+
+    #[test]
+    fn caches_normalized_queries() {
+        let cache = ClassificationCache::default();
+        cache.insert("Hi there!", AnswerKind::Intro);
+
+        assert_eq!(cache.get("hi   there"), Some(AnswerKind::Intro));
+        assert_eq!(cache.get("hi"), None);
+    }
+}
@@ -0,0 +1,216 @@
+//! Machine-readable diagnostics of how the code of an answer was retrieved.
+//!
+//! These are attached to exchanges of answers that ask for them, so that clients can show why an
+//! answer used the code it did, and so that bug reports carry the numbers to reproduce it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::semantic::{Payload, SearchStats};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    /// Each code search of the exchange, in order.
+    pub searches: Vec<Retrieval>,
+    /// The index that was searched, for each repository.
+    pub repositories: Vec<IndexGeneration>,
+}
+
+impl Diagnostics {
+    pub fn record(&mut self, retrieval: Retrieval, index: IndexGeneration) {
+        self.searches.push(retrieval);
+
+        match self
+            .repositories
+            .iter_mut()
+            .find(|i| i.repo_ref == index.repo_ref)
+        {
+            Some(existing) => *existing = index,
+            None => self.repositories.push(index),
+        }
+    }
+}
+
+/// A single code search.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Retrieval {
+    pub query: String,
+    /// The chunks returned by vector search, before deduplication.
+    pub candidates: usize,
+    /// The candidates dropped as duplicates, or past the limit of the search.
+    pub deduplicated: usize,
+    /// Whether a cross-encoder reranked the candidates.
+    pub reranked: bool,
+    /// The requests to qdrant of each vector search, and where their time went.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub qdrant: Vec<QdrantSearch>,
+    /// The chunks found by lexical search, if hybrid retrieval ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical: Option<usize>,
+    /// The chunks returned by federated peers, if they were searched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub federated: Option<usize>,
+    /// The chunks dropped because their license is excluded.
+    pub license_excluded: usize,
+    /// The chunks dropped by snippet filters.
+    #[serde(default)]
+    pub filtered: usize,
+    /// The chunks dropped because their repository is retrieval-only.
+    #[serde(default)]
+    pub retrieval_only: usize,
+    /// Whether usage, bookmarks or the user's profile reordered the results.
+    pub usage_boosted: bool,
+    /// The chunks that the search returned to the agent.
+    pub returned: usize,
+    /// The distribution of the scores of the returned chunks that have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scores: Option<Scores>,
+}
+
+impl Retrieval {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Count the results of a vector search.
+    pub fn searched(&mut self, stats: SearchStats) {
+        self.candidates += stats.candidates;
+        self.deduplicated += stats.deduplicated;
+        self.reranked |= stats.reranked;
+
+        let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+        self.qdrant.push(QdrantSearch {
+            filter: stats.filter,
+            limit: stats.limit,
+            hnsw_ef: stats.hnsw_ef,
+            embed_ms: ms(stats.timings.embed),
+            scoring_ms: ms(stats.timings.scoring),
+            network_ms: ms(stats.timings.network),
+            postprocessing_ms: ms(stats.timings.postprocessing),
+        });
+    }
+
+    /// Describe the results that the agent got.
+    pub fn returned(&mut self, results: &[Payload]) {
+        self.returned = results.len();
+        self.scores = Scores::of(results.iter().filter_map(|p| p.score).collect());
+    }
+}
+
+/// A vector search as it was sent to qdrant, to tune its search parameters by.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QdrantSearch {
+    /// The payload filter, such as `(repo_ref = "github.com/BloopAI/bloop") AND (lang = "rust")`.
+    pub filter: String,
+    /// The points requested from each collection.
+    pub limit: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hnsw_ef: Option<u64>,
+    /// Time spent embedding the query.
+    pub embed_ms: f64,
+    /// Time that qdrant reports having spent on the search.
+    pub scoring_ms: f64,
+    /// The rest of the round trip to qdrant.
+    pub network_ms: f64,
+    /// Time spent filtering, reranking and deduplicating the candidates.
+    pub postprocessing_ms: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Scores {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub median: f32,
+}
+
+impl Scores {
+    fn of(mut scores: Vec<f32>) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+
+        scores.sort_by(f32::total_cmp);
+        let n = scores.len();
+        let median = if n % 2 == 0 {
+            (scores[n / 2 - 1] + scores[n / 2]) / 2.0
+        } else {
+            scores[n / 2]
+        };
+
+        Some(Self {
+            min: scores[0],
+            max: scores[n - 1],
+            mean: scores.iter().sum::<f32>() / n as f32,
+            median,
+        })
+    }
+}
+
+/// The state of a repository's index when it was searched.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IndexGeneration {
+    pub repo_ref: String,
+    /// The schema version of the index generation that answers queries.
+    pub schema_version: String,
+    /// Whether queries are still answered by the previous generation, during a migration.
+    pub previous_generation: bool,
+    /// When the repository was last indexed, in seconds since the epoch.
+    pub indexed_at: u64,
+    /// The model that embeds the repository's code, as `name@version`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding_model: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scored(score: f32) -> Payload {
+        Payload {
+            score: Some(score),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn describes_scores() {
+        let mut retrieval = Retrieval::new("parser");
+        retrieval.returned(&[scored(0.75), scored(0.25), Payload::default(), scored(0.5)]);
+
+        assert_eq!(retrieval.returned, 4);
+        assert_eq!(
+            retrieval.scores,
+            Some(Scores {
+                min: 0.25,
+                max: 0.75,
+                mean: 0.5,
+                median: 0.5,
+            })
+        );
+
+        retrieval.returned(&[Payload::default()]);
+        assert_eq!(retrieval.scores, None);
+    }
+
+    #[test]
+    fn keeps_latest_index_per_repository() {
+        let index = |repo_ref: &str, indexed_at| IndexGeneration {
+            repo_ref: repo_ref.to_owned(),
+            indexed_at,
+            ..Default::default()
+        };
+
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.record(Retrieval::new("a"), index("github.com/BloopAI/bloop", 1));
+        diagnostics.record(Retrieval::new("b"), index("github.com/BloopAI/bloop", 2));
+
+        assert_eq!(diagnostics.searches.len(), 2);
+        assert_eq!(
+            diagnostics.repositories,
+            [index("github.com/BloopAI/bloop", 2)]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Strict application of unified diffs.
+//!
+//! This is used to validate edits suggested by the LLM: an edit is only offered to the user if its
+//! diff applies cleanly against the current contents of the file, without any fuzzing.
+
+use anyhow::{bail, Context, Result};
+use lazy_regex::regex;
+
+/// Apply a unified diff to `original`, returning the patched text.
+///
+/// File headers (`---` and `+++`) are optional. Every context and removed line must match the
+/// original text exactly, ignoring trailing whitespace, otherwise this returns an error.
+pub fn apply(original: &str, diff: &str) -> Result<String> {
+    let lines = original.lines().collect::<Vec<_>>();
+    let hunks = parse(diff)?;
+
+    if hunks.is_empty() {
+        bail!("diff contained no hunks");
+    }
+
+    let mut out = Vec::new();
+    let mut cursor = 0;
+
+    for hunk in hunks {
+        // A hunk that removes no lines is anchored *after* its start line.
+        let mut pos = if hunk.removes_lines() {
+            hunk.old_start.saturating_sub(1)
+        } else {
+            hunk.old_start
+        };
+
+        if pos < cursor || pos > lines.len() {
+            bail!(
+                "hunk at line {} is out of order or out of bounds",
+                hunk.old_start
+            );
+        }
+
+        out.extend_from_slice(&lines[cursor..pos]);
+
+        for line in hunk.lines {
+            match line {
+                Line::Context(expected) | Line::Remove(expected) => {
+                    let actual = lines
+                        .get(pos)
+                        .with_context(|| format!("hunk runs past the end of the file at {pos}"))?;
+
+                    if actual.trim_end() != expected.trim_end() {
+                        bail!("line {} does not match the diff", pos + 1);
+                    }
+
+                    if let Line::Context(_) = line {
+                        out.push(actual);
+                    }
+
+                    pos += 1;
+                }
+                Line::Add(added) => out.push(added),
+            }
+        }
+
+        cursor = pos;
+    }
+
+    out.extend_from_slice(&lines[cursor..]);
+
+    let mut patched = out.join("\n");
+    if original.ends_with('\n') {
+        patched.push('\n');
+    }
+
+    Ok(patched)
+}
+
+struct Hunk<'a> {
+    old_start: usize,
+    lines: Vec<Line<'a>>,
+}
+
+impl Hunk<'_> {
+    fn removes_lines(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|l| matches!(l, Line::Context(_) | Line::Remove(_)))
+    }
+}
+
+enum Line<'a> {
+    Context(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+fn parse(diff: &str) -> Result<Vec<Hunk<'_>>> {
+    let mut hunks = Vec::<Hunk>::new();
+
+    for line in diff.lines() {
+        if let Some(captures) = regex!(r"^@@ -(\d+)(?:,\d+)? \+\d+(?:,\d+)? @@").captures(line) {
+            hunks.push(Hunk {
+                old_start: captures[1].parse()?,
+                lines: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let Some(hunk) = hunks.last_mut() else {
+            // Anything before the first hunk header is treated as a file header.
+            continue;
+        };
+
+        let line = match line.chars().next() {
+            Some(' ') => Line::Context(&line[1..]),
+            Some('-') => Line::Remove(&line[1..]),
+            Some('+') => Line::Add(&line[1..]),
+            Some('\\') => continue,
+            // The LLM often drops the leading space on empty context lines.
+            None => Line::Context(""),
+            Some(_) => bail!("malformed diff line: {line}"),
+        };
+
+        hunk.lines.push(line);
+    }
+
+    Ok(hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let x = 1;\n\n    println!(\"{x}\");\n}\n";
+
+    #[test]
+    fn test_apply() {
+        let diff = "--- a/src/main.rs\n\
+                    +++ b/src/main.rs\n\
+                    @@ -2,3 +2,3 @@\n \
+                    \x20   let x = 1;\n\
+                    \n\
+                    -    println!(\"{x}\");\n\
+                    +    dbg!(x);\n";
+
+        assert_eq!(
+            apply(ORIGINAL, diff).unwrap(),
+            "fn main() {\n    let x = 1;\n\n    dbg!(x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_insertion() {
+        let diff = "@@ -1,0 +2,1 @@\n+    // entry point\n";
+
+        assert_eq!(
+            apply(ORIGINAL, diff).unwrap(),
+            "fn main() {\n    // entry point\n    let x = 1;\n\n    println!(\"{x}\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_mismatch() {
+        let diff = "@@ -2,1 +2,1 @@\n-    let x = 2;\n+    let x = 3;\n";
+        assert!(apply(ORIGINAL, diff).is_err());
+
+        let diff = "@@ -9,1 +9,1 @@\n-}\n+};\n";
+        assert!(apply(ORIGINAL, diff).is_err());
+
+        assert!(apply(ORIGINAL, "just some text").is_err());
+    }
+}
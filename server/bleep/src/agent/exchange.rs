@@ -1,4 +1,12 @@
-use crate::query::parser::SemanticQuery;
+use crate::{
+    agent::diagnostics::{Diagnostics, IndexGeneration, Retrieval},
+    federation,
+    llm_gateway::usage::Spend,
+    query::{
+        correction::Correction,
+        parser::{Literal, SemanticQuery},
+    },
+};
 use std::{fmt, mem};
 
 use chrono::prelude::{DateTime, Utc};
@@ -10,12 +18,25 @@ use chrono::prelude::{DateTime, Utc};
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct Exchange {
     pub id: uuid::Uuid,
+
+    /// The exchange that this one follows in its branch of the conversation, or the nil UUID if
+    /// it opens a branch.
+    ///
+    /// Conversations stored before branching have no parents, and each of their exchanges
+    /// follows the one before it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<uuid::Uuid>,
+
     pub query: SemanticQuery<'static>,
     pub answer: Option<String>,
     pub search_steps: Vec<SearchStep>,
     pub paths: Vec<String>,
     pub code_chunks: Vec<CodeChunk>,
 
+    /// Text read from an image attached to the query, such as a screenshot of a stack trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<String>,
+
     /// A specifically chosen "focused" code chunk.
     ///
     /// This is different from the `code_chunks` list, as focused code chunks also contain the full
@@ -25,6 +46,44 @@ pub struct Exchange {
     /// as when displaying an article.
     pub focused_chunk: Option<FocusedChunk>,
 
+    /// Edits suggested in the answer, each validated to apply cleanly against the current file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggested_edits: Vec<SuggestedEdit>,
+
+    /// The snippets that the answer was based on, for clients to link to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+
+    /// Other answers to the query, generated from the same code, for the user to compare with the
+    /// answer. Each cites the snippets of the files it mentions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+
+    /// Typos in identifiers that were corrected in the query, before searching.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corrections: Vec<Correction>,
+
+    /// What the query was classified as, so that clients can render replies that are not about
+    /// code differently.
+    #[serde(default)]
+    pub answer_kind: AnswerKind,
+
+    /// A structured outcome, set when this exchange ended without a regular answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+
+    /// The LLM tokens spent on this exchange, and their estimated cost.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Spend>,
+
+    /// A log of every step the agent took, for debugging.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<StepTrace>,
+
+    /// How code was retrieved for this exchange, if the answer asked for diagnostics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Diagnostics>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     query_timestamp: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -53,6 +112,9 @@ impl Exchange {
                 (Some(l @ SearchStep::Path { .. }), r @ SearchStep::Path { .. }) => *l = r,
                 (Some(l @ SearchStep::Code { .. }), r @ SearchStep::Code { .. }) => *l = r,
                 (Some(l @ SearchStep::Proc { .. }), r @ SearchStep::Proc { .. }) => *l = r,
+                (Some(l @ SearchStep::Open { .. }), r @ SearchStep::Open { .. }) => *l = r,
+                (Some(l @ SearchStep::Symbols { .. }), r @ SearchStep::Symbols { .. }) => *l = r,
+                (Some(l @ SearchStep::Grep { .. }), r @ SearchStep::Grep { .. }) => *l = r,
                 _ => panic!("Tried to replace a step that was not found"),
             },
             Update::Article(full_text) => {
@@ -65,14 +127,84 @@ impl Exchange {
             Update::Focus(chunk) => {
                 self.focused_chunk = Some(chunk);
             }
+            Update::SuggestEdits(edits) => {
+                self.suggested_edits = edits;
+            }
+            Update::Cite(citations) => {
+                self.citations = citations;
+            }
+            Update::Alternative(alternative) => {
+                self.alternatives.push(alternative);
+            }
+            Update::Rewrite(query) => {
+                self.query.target = Some(Literal::Plain(query.into()));
+            }
+            Update::Correct(query, corrections) => {
+                self.query.target = Some(Literal::Plain(query.into()));
+                self.corrections = corrections;
+            }
+            Update::Outcome(outcome) => {
+                match &outcome {
+                    Outcome::NoAnswer { message, .. }
+                    | Outcome::Navigate { message, .. }
+                    | Outcome::PolicyViolation { message, .. }
+                    | Outcome::ClarificationNeeded { message, .. } => {
+                        self.answer = Some(message.clone());
+                        self.conclusion = Some(message.clone());
+                    }
+                    // The partial answer is kept, as it may still be useful.
+                    Outcome::Partial { message, .. } => {
+                        self.answer.get_or_insert_with(|| message.clone());
+                        self.conclusion = Some(message.clone());
+                    }
+                }
+
+                self.response_timestamp = Some(Utc::now());
+                self.outcome = Some(outcome);
+            }
+            Update::Trace(step) => {
+                self.trace.push(step);
+            }
+            Update::Diagnose(retrieval, index) => {
+                self.diagnostics
+                    .get_or_insert_with(Diagnostics::default)
+                    .record(retrieval, index);
+            }
         }
     }
 
+    /// The stages of the answer pipeline that produced results for this exchange so far.
+    pub fn completed_stages(&self) -> Vec<Stage> {
+        [
+            (Stage::Search, !self.search_steps.is_empty()),
+            (Stage::Selection, !self.code_chunks.is_empty()),
+            (Stage::Answer, self.answer.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(stage, completed)| completed.then_some(stage))
+        .collect()
+    }
+
     /// Get the query associated with this exchange, if it has been made.
     pub fn query(&self) -> Option<String> {
         self.query.target().map(|q| q.to_string())
     }
 
+    /// When the response to the query was concluded, if it was.
+    pub fn answered_at(&self) -> Option<DateTime<Utc>> {
+        self.response_timestamp
+    }
+
+    /// Get the query as it is sent to the LLM, including the text of its attachment.
+    pub fn prompt(&self) -> Option<String> {
+        let query = self.query()?;
+
+        Some(match &self.attachment {
+            Some(text) => format!("{query}\n\nText from an attached image:\n{text}"),
+            None => query,
+        })
+    }
+
     /// Get the answer and conclusion associated with this exchange, if a conclusion has been made.
     ///
     /// This returns a tuple of `(full_text, conclusion)`.
@@ -112,12 +244,28 @@ pub enum SearchStep {
     Code {
         query: String,
         response: String,
+        #[serde(default)]
+        ranking: RankedChunks,
     },
     Proc {
         query: String,
         paths: Vec<String>,
         response: String,
     },
+    Open {
+        path: String,
+        start: Option<usize>,
+        end: Option<usize>,
+        response: String,
+    },
+    Symbols {
+        path: String,
+        response: String,
+    },
+    Grep {
+        pattern: String,
+        response: String,
+    },
 }
 
 impl SearchStep {
@@ -130,15 +278,32 @@ impl SearchStep {
                 query: query.clone(),
                 response: "[hidden, compressed]".into(),
             },
-            Self::Code { query, .. } => Self::Code {
+            Self::Code { query, ranking, .. } => Self::Code {
                 query: query.clone(),
                 response: "[hidden, compressed]".into(),
+                ranking: ranking.compressed(),
             },
             Self::Proc { query, paths, .. } => Self::Proc {
                 query: query.clone(),
                 paths: paths.clone(),
                 response: "[hidden, compressed]".into(),
             },
+            Self::Open {
+                path, start, end, ..
+            } => Self::Open {
+                path: path.clone(),
+                start: *start,
+                end: *end,
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Symbols { path, .. } => Self::Symbols {
+                path: path.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Grep { pattern, .. } => Self::Grep {
+                pattern: pattern.clone(),
+                response: "[hidden, compressed]".into(),
+            },
         }
     }
 
@@ -147,6 +312,9 @@ impl SearchStep {
             Self::Path { response, .. } => response.clone(),
             Self::Code { response, .. } => response.clone(),
             Self::Proc { response, .. } => response.clone(),
+            Self::Open { response, .. } => response.clone(),
+            Self::Symbols { response, .. } => response.clone(),
+            Self::Grep { response, .. } => response.clone(),
         }
     }
 }
@@ -162,6 +330,9 @@ pub struct CodeChunk {
     pub start_line: usize,
     #[serde(rename = "end")]
     pub end_line: usize,
+    /// The semantic search score of this chunk, if it was retrieved by one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
 }
 
 impl CodeChunk {
@@ -177,6 +348,126 @@ impl fmt::Display for CodeChunk {
     }
 }
 
+/// An answer other than the one in `Exchange::answer`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Alternative {
+    pub article: String,
+    pub citations: Vec<Citation>,
+}
+
+/// A snippet that an answer was based on.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Citation {
+    /// The repository of the snippet.
+    pub repo: String,
+    /// The federated peer that the snippet was retrieved from, if it is not local.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    /// The path of the snippet, relative to the repository root.
+    pub path: String,
+    /// One-based and inclusive.
+    pub start_line: usize,
+    /// One-based and inclusive.
+    pub end_line: usize,
+    /// The semantic search score of the snippet, if it was retrieved by one.
+    pub score: Option<f32>,
+}
+
+impl Citation {
+    /// Cite a code chunk of `repo`, whose path may point into a federated peer instead.
+    ///
+    /// The line range of the chunk is zero-based, and exclusive of its end.
+    pub fn new(repo: &str, chunk: &CodeChunk) -> Self {
+        let (peer, repo, path) = match federation::parse_remote_path(&chunk.path) {
+            Some((peer, repo, path)) => (Some(peer.to_owned()), repo, path),
+            None => (None, repo, chunk.path.as_str()),
+        };
+
+        Self {
+            repo: repo.to_owned(),
+            peer,
+            path: path.to_owned(),
+            start_line: chunk.start_line + 1,
+            end_line: chunk.end_line.max(chunk.start_line + 1),
+            score: chunk.score,
+        }
+    }
+}
+
+/// Retrieved code chunks, in retrieval order, annotated with how they were ranked.
+///
+/// Retrieval order (by similarity score) and presentation order (the order chunks appear in the
+/// context) differ, and not every retrieved chunk is selected. Both are tracked here separately,
+/// so that they can be inspected without reconstructing one from the other.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct RankedChunks(Vec<RankedChunk>);
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RankedChunk {
+    #[serde(flatten)]
+    pub chunk: CodeChunk,
+    /// The position of this chunk in the retrieval results.
+    pub retrieval_rank: usize,
+    /// The position of this chunk in the presented context, or `None` if it was not selected.
+    pub presentation_rank: Option<usize>,
+}
+
+impl RankedChunks {
+    /// Rank retrieved chunks, given the indices of the selected chunks in presentation order.
+    ///
+    /// Out of bounds indices in `selection` are ignored.
+    pub fn new(retrieved: Vec<CodeChunk>, selection: &[usize]) -> Self {
+        let mut ranked = retrieved
+            .into_iter()
+            .enumerate()
+            .map(|(retrieval_rank, chunk)| RankedChunk {
+                chunk,
+                retrieval_rank,
+                presentation_rank: None,
+            })
+            .collect::<Vec<_>>();
+
+        for (presentation_rank, &i) in selection.iter().enumerate() {
+            if let Some(r) = ranked.get_mut(i) {
+                r.presentation_rank = Some(presentation_rank);
+            }
+        }
+
+        Self(ranked)
+    }
+
+    /// All chunks, in retrieval order.
+    pub fn retrieved(&self) -> &[RankedChunk] {
+        &self.0
+    }
+
+    /// The selected chunks, in presentation order.
+    pub fn presented(&self) -> Vec<&CodeChunk> {
+        let mut presented = self
+            .0
+            .iter()
+            .filter_map(|r| Some((r.presentation_rank?, &r.chunk)))
+            .collect::<Vec<_>>();
+
+        presented.sort_by_key(|(rank, _)| *rank);
+        presented.into_iter().map(|(_, chunk)| chunk).collect()
+    }
+
+    /// A clone of these rankings without the snippets, keeping only paths, lines, scores and
+    /// ranks.
+    ///
+    /// Used in `SearchStep::compressed`.
+    fn compressed(&self) -> Self {
+        let mut compressed = self.clone();
+        for r in &mut compressed.0 {
+            r.chunk.snippet.clear();
+        }
+
+        compressed
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct FocusedChunk {
     pub file_path: String,
@@ -184,6 +475,128 @@ pub struct FocusedChunk {
     pub end_line: usize,
 }
 
+/// An edit to existing code, suggested as part of an answer.
+///
+/// Line numbers are zero-based and inclusive, and refer to the snippet the edit is anchored to.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct SuggestedEdit {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// A unified diff against the current contents of the file at `path`.
+    pub diff: String,
+}
+
+/// The outcome of an exchange that was not answered with a regular article.
+///
+/// This is serialized with a `kind` tag, so that clients can render it without treating it as an
+/// error.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Outcome {
+    /// No relevant code was found for the query.
+    NoAnswer {
+        message: String,
+        suggestions: Vec<String>,
+    },
+    /// The query asked to open a piece of code, which was resolved to this location.
+    ///
+    /// Line numbers are zero-based and inclusive.
+    Navigate {
+        message: String,
+        path: String,
+        start_line: usize,
+        end_line: usize,
+    },
+    /// The question or its answer was blocked by a policy rule.
+    PolicyViolation { message: String, rule: String },
+    /// The most relevant code is spread across unrelated parts of the codebase, so the user is
+    /// asked which of them the question is about.
+    ///
+    /// A follow-up request picks one of the candidates by index, to answer from its code.
+    ClarificationNeeded {
+        message: String,
+        candidates: Vec<Interpretation>,
+    },
+    /// The answer was cut short, and the exchange holds what the completed stages produced. The
+    /// answer text, if any, may be incomplete.
+    Partial {
+        message: String,
+        /// Whether the answer ran out of time.
+        timeout: bool,
+        /// The stages that produced results, in pipeline order.
+        completed: Vec<Stage>,
+    },
+}
+
+/// The kind of reply given to a query.
+///
+/// Only `Code` replies are based on a search of the codebase. The other kinds are answered from
+/// the conversation alone.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerKind {
+    /// A question about the code in the repositories.
+    #[default]
+    Code,
+    /// A greeting, or a question about what the assistant can do.
+    Intro,
+    /// A general question about programming that doesn't need the code to be answered.
+    Info,
+    /// A request that is unrelated to code, which the assistant can't help with.
+    CannotHelp,
+}
+
+impl AnswerKind {
+    /// The kinds that a query is classified into, in the order they are offered to the LLM.
+    pub const ALL: [Self; 4] = [Self::Code, Self::Intro, Self::Info, Self::CannotHelp];
+}
+
+/// A stage of the answer pipeline, as reported in partial answers.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Code, paths or other context was searched for.
+    Search,
+    /// Code chunks were selected for the answer.
+    Selection,
+    /// The answer started streaming.
+    Answer,
+}
+
+/// One way to read an ambiguous question: a part of the codebase, and the code found in it.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Interpretation {
+    pub label: String,
+    pub paths: Vec<String>,
+    /// The code found for this interpretation, aliased by position in `paths`.
+    pub code_chunks: Vec<CodeChunk>,
+}
+
+impl Interpretation {
+    /// Start an exchange that answers `query` from the code of this interpretation.
+    ///
+    /// Aliases continue from the `offset` paths that are already in the conversation.
+    pub fn exchange(
+        &self,
+        id: uuid::Uuid,
+        query: SemanticQuery<'static>,
+        offset: usize,
+    ) -> Exchange {
+        let mut exchange = Exchange::new(id, query);
+        exchange.paths = self.paths.clone();
+        exchange.code_chunks = self
+            .code_chunks
+            .iter()
+            .map(|chunk| CodeChunk {
+                alias: chunk.alias + offset,
+                ..chunk.clone()
+            })
+            .collect();
+        exchange
+    }
+}
+
 #[derive(Debug)]
 pub enum Update {
     StartStep(SearchStep),
@@ -191,4 +604,236 @@ pub enum Update {
     Article(String),
     Conclude(String),
     Focus(FocusedChunk),
+    SuggestEdits(Vec<SuggestedEdit>),
+    Cite(Vec<Citation>),
+    /// Add an alternative answer.
+    Alternative(Alternative),
+    /// Replace the query target, as required by a policy rule.
+    Rewrite(String),
+    /// Replace the query target with a corrected version.
+    Correct(String, Vec<Correction>),
+    Outcome(Outcome),
+    Trace(StepTrace),
+    /// Record the diagnostics of a code search, and the index it searched.
+    Diagnose(Retrieval, IndexGeneration),
+}
+
+/// A single step of the agent loop: an action that was executed, and the action chosen next.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct StepTrace {
+    pub action: serde_json::Value,
+    pub next: serde_json::Value,
+    /// The number of tokens in the prompt that chose the next action.
+    pub prompt_tokens: usize,
+    /// The time taken to execute the action and choose the next one.
+    pub elapsed_ms: u64,
+    /// Whether the step budget of a deep answer ran out, forcing an answer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exhausted: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_answer_outcome_concludes_exchange() {
+        let mut exchange = Exchange::default();
+
+        exchange.apply_update(Update::Outcome(Outcome::NoAnswer {
+            message: "Nothing found".to_owned(),
+            suggestions: vec!["Try another repository".to_owned()],
+        }));
+
+        assert_eq!(exchange.answer(), Some(("Nothing found", "Nothing found")));
+        assert_eq!(
+            serde_json::to_value(&exchange).unwrap()["outcome"],
+            serde_json::json!({
+                "kind": "no_answer",
+                "message": "Nothing found",
+                "suggestions": ["Try another repository"],
+            })
+        );
+    }
+
+    #[test]
+    fn partial_outcome_keeps_answer() {
+        let mut exchange = Exchange::default();
+        exchange.code_chunks.push(chunk("src/main.rs", 3));
+        exchange.apply_update(Update::Article("The entry point is".to_owned()));
+
+        let completed = exchange.completed_stages();
+        assert_eq!(completed, [Stage::Selection, Stage::Answer]);
+
+        exchange.apply_update(Update::Outcome(Outcome::Partial {
+            message: "The answer was cut short".to_owned(),
+            timeout: true,
+            completed,
+        }));
+
+        assert_eq!(
+            exchange.answer(),
+            Some(("The entry point is", "The answer was cut short"))
+        );
+        assert_eq!(
+            serde_json::to_value(&exchange).unwrap()["outcome"],
+            serde_json::json!({
+                "kind": "partial",
+                "message": "The answer was cut short",
+                "timeout": true,
+                "completed": ["selection", "answer"],
+            })
+        );
+    }
+
+    #[test]
+    fn interpretation_starts_exchange() {
+        let interpretation = Interpretation {
+            label: "`src/cli`".to_owned(),
+            paths: vec!["src/cli/parser.rs".to_owned()],
+            code_chunks: vec![chunk("src/cli/parser.rs", 3)],
+        };
+
+        let mut exchange = Exchange::default();
+        exchange.apply_update(Update::Outcome(Outcome::ClarificationNeeded {
+            message: "Did you mean `src/cli` or `src/webserver`?".to_owned(),
+            candidates: vec![interpretation.clone()],
+        }));
+        assert_eq!(
+            serde_json::to_value(&exchange).unwrap()["outcome"]["kind"],
+            "clarification_needed"
+        );
+
+        let query = SemanticQuery {
+            target: Some(Literal::Plain("where are queries parsed?".into())),
+            ..Default::default()
+        };
+        let resumed = interpretation.exchange(uuid::Uuid::nil(), query, 2);
+        assert_eq!(
+            resumed.query().as_deref(),
+            Some("where are queries parsed?")
+        );
+        assert_eq!(resumed.paths, ["src/cli/parser.rs"]);
+        assert_eq!(resumed.code_chunks[0].alias, 2);
+        assert_eq!(resumed.code_chunks[0].start_line, 3);
+    }
+
+    #[test]
+    fn cites_chunks() {
+        let local = CodeChunk {
+            score: Some(0.8),
+            ..chunk("src/main.rs", 3)
+        };
+        assert_eq!(
+            Citation::new("github.com/BloopAI/bloop", &local),
+            Citation {
+                repo: "github.com/BloopAI/bloop".to_owned(),
+                peer: None,
+                path: "src/main.rs".to_owned(),
+                start_line: 4,
+                end_line: 4,
+                score: Some(0.8),
+            }
+        );
+
+        let remote = chunk("acme::github.com/acme/api::src/lib.rs", 0);
+        let citation = Citation::new("github.com/BloopAI/bloop", &remote);
+        assert_eq!(citation.peer.as_deref(), Some("acme"));
+        assert_eq!(citation.repo, "github.com/acme/api");
+        assert_eq!(citation.path, "src/lib.rs");
+        assert_eq!(
+            serde_json::to_value(&citation).unwrap()["score"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn correction_replaces_query_target() {
+        let mut exchange = Exchange::new(
+            uuid::Uuid::nil(),
+            SemanticQuery {
+                target: Some(Literal::Plain("where is RepoReff parsed?".into())),
+                ..Default::default()
+            },
+        );
+
+        exchange.apply_update(Update::Correct(
+            "where is RepoRef parsed?".to_owned(),
+            vec![Correction {
+                original: "RepoReff".to_owned(),
+                corrected: "RepoRef".to_owned(),
+            }],
+        ));
+
+        assert_eq!(
+            exchange.query().as_deref(),
+            Some("where is RepoRef parsed?")
+        );
+        assert_eq!(
+            serde_json::to_value(&exchange).unwrap()["corrections"],
+            serde_json::json!([{ "original": "RepoReff", "corrected": "RepoRef" }])
+        );
+    }
+
+    fn chunk(path: &str, start_line: usize) -> CodeChunk {
+        CodeChunk {
+            path: path.to_owned(),
+            alias: 0,
+            snippet: "fn f() {}".to_owned(),
+            start_line,
+            end_line: start_line + 1,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn ranked_chunks_track_retrieval_and_presentation_separately() {
+        let ranked = RankedChunks::new(
+            vec![chunk("a.rs", 10), chunk("b.rs", 1), chunk("c.rs", 5)],
+            &[2, 0, 7],
+        );
+
+        assert_eq!(
+            ranked
+                .retrieved()
+                .iter()
+                .map(|r| (r.retrieval_rank, r.presentation_rank))
+                .collect::<Vec<_>>(),
+            vec![(0, Some(1)), (1, None), (2, Some(0))]
+        );
+        assert_eq!(
+            ranked.presented(),
+            vec![&chunk("c.rs", 5), &chunk("a.rs", 10)]
+        );
+    }
+
+    #[test]
+    fn compressed_search_steps_drop_snippets() {
+        let step = SearchStep::Code {
+            query: "foo".to_owned(),
+            response: "a.rs\nfn f() {}".to_owned(),
+            ranking: RankedChunks::new(vec![chunk("a.rs", 10), chunk("b.rs", 1)], &[1]),
+        };
+
+        let SearchStep::Code { ranking, .. } = step.compressed() else {
+            panic!("compressing changed the kind of step");
+        };
+
+        assert!(ranking
+            .retrieved()
+            .iter()
+            .all(|r| r.chunk.snippet.is_empty()));
+        assert_eq!(
+            ranking
+                .retrieved()
+                .iter()
+                .map(|r| (
+                    r.chunk.path.as_str(),
+                    r.chunk.start_line,
+                    r.presentation_rank
+                ))
+                .collect::<Vec<_>>(),
+            vec![("a.rs", 10, None), ("b.rs", 1, Some(0))]
+        );
+    }
 }
@@ -0,0 +1,211 @@
+//! Answers to broad questions, written in two steps.
+//!
+//! The top snippets are explained concurrently, each on its own (map), then the answer combines
+//! these explanations with the code they are about (reduce).
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use futures::{future, TryStreamExt};
+use minijinja::context;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    agent::{exchange::CodeChunk, prompts, Agent},
+    analytics::EventData,
+    llm_gateway, secrets,
+};
+
+const MAP_MODEL: &str = "gpt-3.5-turbo-0613";
+
+/// The fewest snippets that are worth explaining separately.
+const MIN_SNIPPETS: usize = 3;
+
+/// The most snippets that are explained separately.
+const MAX_SNIPPETS: usize = 5;
+
+/// The longest explanation of a single snippet.
+const MAX_EXPLANATION_TOKENS: usize = 300;
+
+/// Snippets that leave fewer tokens than this for their explanation are not explained.
+const MIN_EXPLANATION_TOKENS: usize = 64;
+
+/// The tokens of the answer prompt that are set aside for the explanations.
+pub(super) const RESERVED_TOKENS: usize = MAX_SNIPPETS * MAX_EXPLANATION_TOKENS;
+
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnswerMode {
+    /// Explain all of the code in one response.
+    #[default]
+    Single,
+    /// Explain the top snippets of broad questions concurrently, then combine the explanations.
+    MapReduce,
+}
+
+/// The snippets to explain separately, best first, or none if the question is not broad.
+///
+/// A question is broad when the code found for it spans several files.
+pub(super) fn map_chunks(chunks: &[CodeChunk]) -> Vec<&CodeChunk> {
+    let files = chunks.iter().map(|c| &c.path).collect::<HashSet<_>>();
+    if chunks.len() < MIN_SNIPPETS || files.len() < 2 {
+        return vec![];
+    }
+
+    // Chunks without a score were not found by semantic search, and keep their order after the
+    // scored ones.
+    let mut chunks = chunks.iter().collect::<Vec<_>>();
+    chunks.sort_by(|a, b| {
+        let score = |c: &CodeChunk| c.score.unwrap_or(f32::NEG_INFINITY);
+        score(b).total_cmp(&score(a))
+    });
+    chunks.truncate(MAX_SNIPPETS);
+    chunks
+}
+
+fn header(chunk: &CodeChunk) -> String {
+    format!(
+        "### {} (lines {}-{}) ###",
+        chunk.path,
+        chunk.start_line + 1,
+        chunk.end_line + 1
+    )
+}
+
+impl Agent {
+    /// Explain each of `chunks` concurrently, spending at most `map_reduce_max_tokens` on all of
+    /// them together.
+    ///
+    /// Snippets whose explanation doesn't fit, or fails, are left out. Returns the explanations
+    /// as a section of the answer context, which is empty if there are none.
+    #[instrument(skip(self, chunks))]
+    pub(super) async fn explain_snippets(
+        &self,
+        query: &str,
+        chunks: &[&CodeChunk],
+    ) -> Result<String> {
+        let allowance = self.app.config.map_reduce_max_tokens / chunks.len().max(1);
+        let bpe = tiktoken_rs::get_bpe_from_model(MAP_MODEL)?;
+
+        let explanations = chunks.iter().map(|chunk| {
+            let code = chunk
+                .snippet
+                .lines()
+                .enumerate()
+                .map(|(i, line)| format!("{} {line}\n", i + chunk.start_line + 1))
+                .collect::<String>();
+            let code = secrets::redact(&code).text.into_owned();
+
+            let path = &chunk.path;
+            let prompt = self.app.prompt_templates.render_or(
+                "explain_snippet",
+                context! { question => query, path, code },
+                || prompts::snippet_explanation_prompt(query, path, &code),
+            );
+            let max_tokens = allowance
+                .saturating_sub(bpe.encode_ordinary(&prompt).len())
+                .min(MAX_EXPLANATION_TOKENS);
+
+            async move {
+                if max_tokens < MIN_EXPLANATION_TOKENS {
+                    debug!(%path, "snippet is too long to explain on its own");
+                    return None;
+                }
+
+                let explanation = async {
+                    self.llm_gateway
+                        .clone()
+                        .model(MAP_MODEL)
+                        .max_tokens(max_tokens as u32)
+                        .chat(&[llm_gateway::api::Message::system(&prompt)], None)
+                        .await?
+                        .try_collect::<String>()
+                        .await
+                };
+
+                match explanation.await {
+                    Ok(explanation) => {
+                        Some(format!("{}\n{}\n\n", header(chunk), explanation.trim()))
+                    }
+                    Err(err) => {
+                        warn!(?err, %path, "failed to explain snippet");
+                        None
+                    }
+                }
+            }
+        });
+
+        let explanations = self
+            .stage("map", async { Ok(future::join_all(explanations).await) })
+            .await?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        self.track_query(
+            EventData::output_stage("map_reduce")
+                .with_payload("snippets", chunks.len())
+                .with_payload("explanations", &explanations),
+        );
+
+        Ok(explanations.concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(path: &str, score: Option<f32>) -> CodeChunk {
+        CodeChunk {
+            path: path.to_owned(),
+            alias: 0,
+            snippet: String::new(),
+            start_line: 0,
+            end_line: 0,
+            score,
+        }
+    }
+
+    #[test]
+    fn picks_top_chunks_of_broad_questions() {
+        let chunks = [
+            chunk("a.rs", Some(0.5)),
+            chunk("b.rs", None),
+            chunk("a.rs", Some(0.9)),
+            chunk("c.rs", Some(0.7)),
+            chunk("d.rs", Some(0.6)),
+            chunk("e.rs", Some(0.8)),
+        ];
+
+        let scores = map_chunks(&chunks)
+            .into_iter()
+            .map(|c| c.score)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scores,
+            [Some(0.9), Some(0.8), Some(0.7), Some(0.6), Some(0.5)]
+        );
+    }
+
+    #[test]
+    fn skips_narrow_questions() {
+        assert!(map_chunks(&[chunk("a.rs", Some(0.9)), chunk("b.rs", Some(0.8))]).is_empty());
+        assert!(map_chunks(&[
+            chunk("a.rs", Some(0.9)),
+            chunk("a.rs", Some(0.8)),
+            chunk("a.rs", Some(0.7)),
+        ])
+        .is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! Who the assistant introduces itself as.
+//!
+//! Greetings and questions about the assistant get the same reply whatever the codebase, so the
+//! common phrasings of them are recognized locally, and replied to from a template without
+//! calling the LLM. Other phrasings are still classified, and replied to, by the LLM.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Persona {
+    /// The name that the assistant introduces itself with.
+    pub name: String,
+    /// A sentence about the assistant, said after its name.
+    pub description: Option<String>,
+    /// Questions that the introduction suggests asking.
+    pub examples: Vec<String>,
+}
+
+impl Default for Persona {
+    fn default() -> Self {
+        Self {
+            name: "bloop".to_owned(),
+            description: None,
+            examples: vec![
+                "Where do we handle authentication?".to_owned(),
+                "How are errors reported to the user?".to_owned(),
+                "What happens when a file changes during indexing?".to_owned(),
+            ],
+        }
+    }
+}
+
+/// Greetings, after which the name of the assistant may follow.
+const GREETINGS: &[&str] = &[
+    "hi",
+    "hello",
+    "hey",
+    "hiya",
+    "howdy",
+    "greetings",
+    "good morning",
+    "good afternoon",
+    "good evening",
+    "hi there",
+    "hello there",
+    "hey there",
+];
+
+/// Questions about the assistant, where `{name}` stands for its name.
+const QUESTIONS: &[&str] = &[
+    "help",
+    "who are you",
+    "what are you",
+    "what is this",
+    "what is {name}",
+    "what can you do",
+    "what do you do",
+    "how can you help",
+    "how can you help me",
+    "what can i ask",
+    "what can i ask you",
+    "what should i ask",
+    "how does {name} work",
+    "how do you work",
+    "tell me about yourself",
+    "introduce yourself",
+];
+
+impl Persona {
+    /// Whether a query is a greeting or a question about the assistant, in a common phrasing.
+    ///
+    /// The whole query has to match, so that a greeting followed by a question about the code is
+    /// answered from the code.
+    pub fn is_intro(&self, query: &str) -> bool {
+        let name = self.name.to_lowercase();
+        let query = normalize(query);
+
+        // "hey bloop", "bloop, who are you?"
+        let unnamed = query
+            .strip_suffix(&name)
+            .or_else(|| query.strip_prefix(&name))
+            .map(str::trim);
+
+        [Some(query.as_str()), unnamed]
+            .into_iter()
+            .flatten()
+            .any(|query| {
+                GREETINGS.contains(&query)
+                    || QUESTIONS
+                        .iter()
+                        .any(|question| question.replace("{name}", &name) == query)
+            })
+    }
+
+    /// The built-in introduction, to the repositories of a conversation, one per line.
+    pub fn intro(&self, repos: &str) -> String {
+        let mut intro = format!("Hi, I'm {}.", self.name);
+        if let Some(description) = &self.description {
+            intro += &format!(" {description}");
+        }
+
+        match repos.lines().collect::<Vec<_>>()[..] {
+            [repo] => {
+                intro += &format!(
+                    " I answer questions about the code in `{repo}`, by searching it and \
+                     explaining what I find."
+                );
+            }
+            ref repos => {
+                intro += " I answer questions about the code in these repositories, by searching \
+                           it and explaining what I find:\n";
+                for repo in repos {
+                    intro += &format!("\n- `{repo}`");
+                }
+            }
+        }
+
+        if !self.examples.is_empty() {
+            intro += "\n\nYou could ask me:\n";
+            for example in &self.examples {
+                intro += &format!("\n- {example}");
+            }
+        }
+
+        intro
+    }
+}
+
+/// Lowercase words, without punctuation.
+fn normalize(query: &str) -> String {
+    query
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_intros() {
+        let persona = Persona::default();
+
+        assert!(persona.is_intro("Hi!"));
+        assert!(persona.is_intro("hey bloop"));
+        assert!(persona.is_intro("Bloop, who are you?"));
+        assert!(persona.is_intro("What can you do?"));
+        assert!(persona.is_intro("what is bloop"));
+
+        assert!(!persona.is_intro("Hi, where is the config parsed?"));
+        assert!(!persona.is_intro("what is this function for"));
+        assert!(!persona.is_intro("help me find the router"));
+    }
+
+    #[test]
+    fn introduces_repositories() {
+        let persona = Persona {
+            name: "Ada".to_owned(),
+            description: Some("I live in your editor.".to_owned()),
+            examples: vec!["Where is the router?".to_owned()],
+        };
+
+        assert_eq!(
+            persona.intro("github.com/org/app"),
+            "Hi, I'm Ada. I live in your editor. I answer questions about the code in \
+             `github.com/org/app`, by searching it and explaining what I find.\n\n\
+             You could ask me:\n\n\
+             - Where is the router?"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Usage-based priors for code retrieval.
+//!
+//! Files that were used in earlier answers, and especially in answers that were upvoted, are
+//! likely to be useful again. Their semantic search scores get a small boost, so that frequently
+//! useful areas of a codebase surface faster. Usage decays exponentially with age, and files get
+//! part of their boost from the usage of their siblings in the same directory.
+//!
+//! Files that a user bookmarked count as heavily used, without decay, in that user's priors. The
+//! directories in a user's context profile count like selections, and results in the languages
+//! the user asks about most get a further, smaller boost.
+
+use std::collections::HashMap;
+
+use crate::{
+    agent::profile::Profile,
+    db::{Signal, UsageEvent},
+    semantic,
+};
+
+/// The largest relative boost a result can get, e.g. `0.1` for at most 10%.
+const MAX_BOOST: f32 = 0.1;
+
+/// The share of the boost that comes from the usage of the file itself, rather than its directory.
+const FILE_SHARE: f32 = 0.7;
+
+/// The decayed weight of usage at which a prior reaches half of its maximum.
+const SATURATION: f32 = 5.0;
+
+/// The weight of a bookmarked file, as if it was upvoted a few times today.
+const BOOKMARK_WEIGHT: f32 = 12.0;
+
+/// The largest relative boost from the language of a result.
+const MAX_LANG_BOOST: f32 = 0.02;
+
+/// Usage older than this many half-lives has a negligible weight, and is not loaded.
+const HORIZON_HALF_LIVES: f32 = 8.0;
+
+/// How many more candidates to retrieve than requested, so that boosted results can move up from
+/// below the cutoff.
+pub const CANDIDATE_FACTOR: u64 = 2;
+
+const SECS_PER_DAY: f32 = 24.0 * 60.0 * 60.0;
+
+/// Decayed usage weights of files and directories in a repository.
+#[derive(Debug, Default)]
+pub struct Priors {
+    files: HashMap<String, f32>,
+    dirs: HashMap<String, f32>,
+    /// The share of a user's questions that were answered from files in each language.
+    langs: HashMap<String, f32>,
+}
+
+impl Signal {
+    fn weight(self) -> f32 {
+        match self {
+            Self::Selected => 1.0,
+            Self::Upvoted => 4.0,
+        }
+    }
+}
+
+impl Priors {
+    /// Aggregate usage events into priors, as of `now` seconds since the unix epoch.
+    pub fn new(events: &[UsageEvent], now: i64, half_life_days: f32) -> Self {
+        let mut priors = Self::default();
+
+        for event in events {
+            let age_days = (now - event.created_at).max(0) as f32 / SECS_PER_DAY;
+            let weight = event.signal.weight() * 0.5f32.powf(age_days / half_life_days);
+
+            *priors.files.entry(event.relative_path.clone()).or_default() += weight;
+
+            if let Some(dir) = parent(&event.relative_path) {
+                *priors.dirs.entry(dir.to_owned()).or_default() += weight;
+            }
+        }
+
+        priors
+    }
+
+    /// Count a bookmarked file towards the priors.
+    pub fn bookmark(&mut self, relative_path: &str) {
+        *self.files.entry(relative_path.to_owned()).or_default() += BOOKMARK_WEIGHT;
+
+        if let Some(dir) = parent(relative_path) {
+            *self.dirs.entry(dir.to_owned()).or_default() += BOOKMARK_WEIGHT;
+        }
+    }
+
+    /// Count the directories of `repo_ref` and the languages in a user's profile towards the
+    /// priors.
+    pub fn profile(&mut self, profile: &Profile, repo_ref: &str) {
+        for (dir, hits) in profile.dirs_in(repo_ref) {
+            *self.dirs.entry(dir.to_owned()).or_default() += hits as f32;
+        }
+
+        let total = profile.langs.iter().map(|l| l.hits).sum::<i64>();
+        for lang in &profile.langs {
+            self.langs
+                .insert(lang.lang.clone(), lang.hits as f32 / total as f32);
+        }
+    }
+
+    /// The oldest usage, in seconds since the unix epoch, that still contributes to priors.
+    pub fn horizon(now: i64, half_life_days: f32) -> i64 {
+        now - (HORIZON_HALF_LIVES * half_life_days * SECS_PER_DAY) as i64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty() && self.langs.is_empty()
+    }
+
+    /// The factor by which the score of a result in `relative_path` is multiplied.
+    pub fn boost(&self, relative_path: &str) -> f32 {
+        let file = self.files.get(relative_path).copied().unwrap_or_default();
+        let dir = parent(relative_path)
+            .and_then(|dir| self.dirs.get(dir))
+            .copied()
+            .unwrap_or_default();
+
+        1.0 + MAX_BOOST * (FILE_SHARE * saturate(file) + (1.0 - FILE_SHARE) * saturate(dir))
+    }
+
+    /// The factor by which the score of a result in `lang` is multiplied.
+    pub fn lang_boost(&self, lang: &str) -> f32 {
+        1.0 + MAX_LANG_BOOST * self.langs.get(lang).copied().unwrap_or_default()
+    }
+
+    /// Boost the scores of `payloads`, and sort them by descending score.
+    pub fn rerank(&self, payloads: &mut [semantic::Payload]) {
+        if self.is_empty() {
+            return;
+        }
+
+        for payload in payloads.iter_mut() {
+            let boost = self.boost(&payload.relative_path) * self.lang_boost(&payload.lang);
+            payload.score = payload.score.map(|s| s * boost);
+        }
+
+        payloads.sort_by(|a, b| {
+            b.score
+                .unwrap_or_default()
+                .total_cmp(&a.score.unwrap_or_default())
+        });
+    }
+}
+
+fn saturate(weight: f32) -> f32 {
+    weight / (weight + SATURATION)
+}
+
+/// The directory of a file. Files at the root of a repository are not grouped, as that would
+/// boost the whole repository.
+fn parent(relative_path: &str) -> Option<&str> {
+    relative_path.rsplit_once('/').map(|(dir, _)| dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn event(relative_path: &str, signal: Signal, created_at: i64) -> UsageEvent {
+        UsageEvent {
+            relative_path: relative_path.to_owned(),
+            signal,
+            created_at,
+        }
+    }
+
+    fn payload(relative_path: &str, score: f32) -> semantic::Payload {
+        semantic::Payload {
+            relative_path: relative_path.to_owned(),
+            score: Some(score),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn boost_is_small_and_bounded() {
+        let events = (0..1000)
+            .map(|_| event("src/lib.rs", Signal::Upvoted, 0))
+            .collect::<Vec<_>>();
+        let priors = Priors::new(&events, 0, 14.0);
+
+        let boost = priors.boost("src/lib.rs");
+        assert!(boost > 1.09 && boost <= 1.0 + MAX_BOOST, "{boost}");
+
+        // Siblings only get the directory share.
+        let sibling = priors.boost("src/main.rs");
+        assert!(sibling > 1.0 && sibling < 1.0 + MAX_BOOST * (1.0 - FILE_SHARE) + 1e-6);
+
+        assert_eq!(priors.boost("README.md"), 1.0);
+        assert_eq!(priors.boost("tests/lib.rs"), 1.0);
+    }
+
+    #[test]
+    fn usage_decays() {
+        let now = 100 * DAY;
+        let recent = Priors::new(&[event("src/lib.rs", Signal::Selected, now)], now, 14.0);
+        let old = Priors::new(
+            &[event("src/lib.rs", Signal::Selected, now - 14 * DAY)],
+            now,
+            14.0,
+        );
+
+        assert!((recent.files["src/lib.rs"] - 1.0).abs() < 1e-6);
+        assert!((old.files["src/lib.rs"] - 0.5).abs() < 1e-6);
+        assert!(recent.boost("src/lib.rs") > old.boost("src/lib.rs"));
+
+        assert_eq!(Priors::horizon(now, 14.0), now - 8 * 14 * DAY);
+    }
+
+    #[test]
+    fn upvotes_weigh_more_than_selections() {
+        let priors = Priors::new(
+            &[
+                event("a/x.rs", Signal::Selected, 0),
+                event("b/y.rs", Signal::Upvoted, 0),
+            ],
+            0,
+            14.0,
+        );
+
+        assert!(priors.boost("b/y.rs") > priors.boost("a/x.rs"));
+    }
+
+    #[test]
+    fn bookmarks_boost_files() {
+        let mut priors = Priors::default();
+        assert!(priors.is_empty());
+
+        priors.bookmark("src/saved.rs");
+        assert!(!priors.is_empty());
+        assert!(priors.boost("src/saved.rs") > 1.0 + MAX_BOOST * FILE_SHARE * 0.5);
+        assert!(priors.boost("src/saved.rs") > priors.boost("src/other.rs"));
+        assert!(priors.boost("src/other.rs") > 1.0);
+    }
+
+    #[test]
+    fn profiles_boost_dirs_and_langs() {
+        use crate::db::{ProfileEntry, ProfileKind};
+
+        let entry = |repo_ref: &str, kind, name: &str, hits| ProfileEntry {
+            repo_ref: repo_ref.to_owned(),
+            kind,
+            name: name.to_owned(),
+            hits,
+            last_hit_at: 0,
+        };
+        let profile = Profile::new(vec![
+            entry("r", ProfileKind::Dir, "src/cli", 8),
+            entry("r", ProfileKind::Lang, "rust", 3),
+            entry("r", ProfileKind::Lang, "markdown", 1),
+            entry("other", ProfileKind::Dir, "src/db", 8),
+        ]);
+
+        let mut priors = Priors::default();
+        priors.profile(&profile, "r");
+        assert!(!priors.is_empty());
+
+        assert!(priors.boost("src/cli/args.rs") > 1.0);
+        assert_eq!(priors.boost("src/db/mod.rs"), 1.0);
+
+        assert!((priors.lang_boost("rust") - (1.0 + MAX_LANG_BOOST * 0.75)).abs() < 1e-6);
+        assert!(priors.lang_boost("rust") > priors.lang_boost("markdown"));
+        assert_eq!(priors.lang_boost("python"), 1.0);
+    }
+
+    #[test]
+    fn rerank_promotes_used_files() {
+        let priors = Priors::new(
+            &(0..10)
+                .map(|_| event("src/used.rs", Signal::Upvoted, 0))
+                .collect::<Vec<_>>(),
+            0,
+            14.0,
+        );
+
+        let mut payloads = vec![
+            payload("src/other.rs", 0.80),
+            payload("src/used.rs", 0.78),
+            payload("docs/far.md", 0.50),
+        ];
+        priors.rerank(&mut payloads);
+
+        assert_eq!(
+            payloads
+                .iter()
+                .map(|p| p.relative_path.as_str())
+                .collect::<Vec<_>>(),
+            ["src/used.rs", "src/other.rs", "docs/far.md"]
+        );
+        assert_eq!(payloads[2].score, Some(0.50));
+    }
+}
@@ -0,0 +1,249 @@
+//! Context profiles of users.
+//!
+//! Every answer counts towards the profile of the user who asked for it: the repository, and the
+//! languages and directories of the files that the answer was based on. Profiles bias retrieval
+//! towards the areas a user usually asks about, settle ambiguous questions without asking, and
+//! provide a default repository for new conversations.
+
+use std::{collections::HashMap, io::Cursor, path::Path};
+
+use crate::{
+    agent::exchange::Exchange,
+    db::{ProfileEntry, ProfileKind},
+    federation,
+};
+
+/// The most entries of each kind that are shown, and used for retrieval.
+const MAX_ENTRIES: usize = 20;
+
+/// The fewest hits of an area before it is preferred over other interpretations of a question.
+const MIN_PREFERRED_HITS: i64 = 3;
+
+/// How many times more hits the preferred area needs than the next one.
+const PREFERENCE_RATIO: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RepoHits {
+    pub repo_ref: String,
+    pub hits: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct LangHits {
+    pub lang: String,
+    pub hits: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DirHits {
+    pub repo_ref: String,
+    pub dir: String,
+    pub hits: i64,
+}
+
+/// The most queried repositories, languages and directories of a user, most hit first.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Profile {
+    pub repos: Vec<RepoHits>,
+    pub langs: Vec<LangHits>,
+    pub dirs: Vec<DirHits>,
+}
+
+impl Profile {
+    /// Aggregate profile entries, which must be ordered by descending hits.
+    pub fn new(entries: Vec<ProfileEntry>) -> Self {
+        let mut profile = Self::default();
+        let mut langs = HashMap::<String, i64>::new();
+
+        for entry in entries {
+            match entry.kind {
+                ProfileKind::Repo => profile.repos.push(RepoHits {
+                    repo_ref: entry.repo_ref,
+                    hits: entry.hits,
+                }),
+                ProfileKind::Lang => *langs.entry(entry.name).or_default() += entry.hits,
+                ProfileKind::Dir => profile.dirs.push(DirHits {
+                    repo_ref: entry.repo_ref,
+                    dir: entry.name,
+                    hits: entry.hits,
+                }),
+            }
+        }
+
+        profile.langs = langs
+            .into_iter()
+            .map(|(lang, hits)| LangHits { lang, hits })
+            .collect();
+        profile
+            .langs
+            .sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.lang.cmp(&b.lang)));
+
+        profile.repos.truncate(MAX_ENTRIES);
+        profile.langs.truncate(MAX_ENTRIES);
+        profile.dirs.truncate(MAX_ENTRIES);
+        profile
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repos.is_empty()
+    }
+
+    /// The repository that the user asks about the most.
+    pub fn default_repo(&self) -> Option<&str> {
+        self.repos.first().map(|r| r.repo_ref.as_str())
+    }
+
+    /// The directories of a repository that the user asked about, with their hits.
+    pub fn dirs_in<'a>(&'a self, repo_ref: &'a str) -> impl Iterator<Item = (&'a str, i64)> + 'a {
+        self.dirs
+            .iter()
+            .filter(move |d| d.repo_ref == repo_ref)
+            .map(|d| (d.dir.as_str(), d.hits))
+    }
+
+    /// Which of the candidate directories the user usually asks about, if one of them clearly
+    /// dominates the others.
+    ///
+    /// Candidates get the hits of profile directories within them, and of those that contain them.
+    pub fn preferred(&self, repo_ref: &str, candidates: &[&str]) -> Option<usize> {
+        let mut hits = candidates
+            .iter()
+            .map(|candidate| {
+                let candidate = Path::new(candidate);
+                self.dirs_in(repo_ref)
+                    .filter(|(dir, _)| {
+                        let dir = Path::new(dir);
+                        dir.starts_with(candidate) || candidate.starts_with(dir)
+                    })
+                    .map(|(_, hits)| hits)
+                    .sum::<i64>()
+            })
+            .enumerate()
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.1.cmp(&a.1));
+
+        match hits[..] {
+            [(i, best), (_, next), ..]
+                if best >= MIN_PREFERRED_HITS && best >= PREFERENCE_RATIO * next =>
+            {
+                Some(i)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The languages and directories of the local files that an answer was based on.
+pub fn observed(exchange: &Exchange) -> (Vec<String>, Vec<String>) {
+    let mut langs = vec![];
+    let mut dirs = vec![];
+
+    let paths = exchange
+        .paths
+        .iter()
+        .filter(|path| federation::parse_remote_path(path).is_none());
+
+    for path in paths {
+        if let Some(lang) = language(path) {
+            langs.push(lang);
+        }
+
+        if let Some((dir, _)) = path.rsplit_once('/') {
+            dirs.push(dir.to_owned());
+        }
+    }
+
+    langs.sort_unstable();
+    langs.dedup();
+    dirs.sort_unstable();
+    dirs.dedup();
+    (langs, dirs)
+}
+
+/// The language of a file by its name, in the lowercase form that is indexed.
+fn language(path: &str) -> Option<String> {
+    hyperpolyglot::detect_buffer(Path::new(path), |_| Ok(Cursor::new(&[][..])))
+        .ok()
+        .flatten()
+        .map(|d| d.language().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(repo_ref: &str, kind: ProfileKind, name: &str, hits: i64) -> ProfileEntry {
+        ProfileEntry {
+            repo_ref: repo_ref.to_owned(),
+            kind,
+            name: name.to_owned(),
+            hits,
+            last_hit_at: 0,
+        }
+    }
+
+    #[test]
+    fn aggregates_entries() {
+        let profile = Profile::new(vec![
+            entry("github.com/a/b", ProfileKind::Repo, "", 9),
+            entry("github.com/a/b", ProfileKind::Lang, "rust", 8),
+            entry("github.com/a/b", ProfileKind::Dir, "src/cli", 5),
+            entry("github.com/c/d", ProfileKind::Repo, "", 4),
+            entry("github.com/c/d", ProfileKind::Lang, "rust", 4),
+            entry("github.com/c/d", ProfileKind::Lang, "typescript", 3),
+        ]);
+
+        assert_eq!(profile.default_repo(), Some("github.com/a/b"));
+        assert_eq!(
+            profile.langs,
+            [
+                LangHits {
+                    lang: "rust".into(),
+                    hits: 12
+                },
+                LangHits {
+                    lang: "typescript".into(),
+                    hits: 3
+                },
+            ]
+        );
+        assert_eq!(
+            profile.dirs_in("github.com/a/b").collect::<Vec<_>>(),
+            [("src/cli", 5)]
+        );
+        assert_eq!(profile.dirs_in("github.com/c/d").count(), 0);
+
+        assert!(Profile::new(vec![]).is_empty());
+        assert_eq!(Profile::new(vec![]).default_repo(), None);
+    }
+
+    #[test]
+    fn prefers_dominant_area() {
+        let profile = Profile::new(vec![
+            entry("r", ProfileKind::Dir, "src/cli/args", 6),
+            entry("r", ProfileKind::Dir, "src/webserver", 2),
+            entry("other", ProfileKind::Dir, "src/webserver", 50),
+        ]);
+
+        assert_eq!(
+            profile.preferred("r", &["src/webserver", "src/cli"]),
+            Some(1)
+        );
+        assert_eq!(profile.preferred("r", &["src/db", "src/webserver"]), None);
+        assert_eq!(profile.preferred("other", &["src/cli", "src/db"]), None);
+    }
+
+    #[test]
+    fn observes_languages_and_dirs() {
+        let mut exchange = Exchange::default();
+        exchange.paths = vec![
+            "cmd/cli/args.go".into(),
+            "cmd/cli/parser.go".into(),
+            "README.md".into(),
+        ];
+
+        let (langs, dirs) = observed(&exchange);
+        assert_eq!(langs, ["go", "markdown"]);
+        assert_eq!(dirs, ["cmd/cli"]);
+    }
+}
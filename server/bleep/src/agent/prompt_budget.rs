@@ -0,0 +1,200 @@
+use anyhow::{bail, Result};
+use tiktoken_rs::CoreBPE;
+
+use crate::llm_gateway::api::Message;
+
+/// The tokens left for the parts of a prompt in the context window of a model.
+///
+/// Parts are spent in order of priority, so that the least important ones are trimmed when the
+/// window is full: the question and instructions have to fit in full, then as many code snippets
+/// as fit are added, and the conversation history gets what is left. Context window sizes are
+/// configured per model with `llm_context_sizes`.
+pub struct PromptBudget {
+    model: String,
+    bpe: CoreBPE,
+    remaining: usize,
+}
+
+impl PromptBudget {
+    /// A budget for a prompt to `model`, keeping `headroom` of its `context_size` tokens for the
+    /// completion.
+    pub fn new(model: &str, context_size: usize, headroom: usize) -> Result<Self> {
+        Ok(Self {
+            model: model.to_owned(),
+            bpe: tiktoken_rs::get_bpe_from_model(model)?,
+            remaining: context_size.saturating_sub(headroom),
+        })
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Spend the tokens of a message that has to be sent in full.
+    pub fn require(&mut self, message: &Message) -> Result<()> {
+        let tokens = self.message_tokens(message)?;
+        if tokens > self.remaining {
+            bail!(
+                "prompt needs {tokens} more tokens, but only {} fit into the context window of {}",
+                self.remaining,
+                self.model
+            );
+        }
+
+        self.remaining -= tokens;
+        Ok(())
+    }
+
+    /// The leading lines of `text` that fit, without spending anything.
+    pub fn truncate<'a>(&self, text: &'a str) -> &'a str {
+        let mut tokens = 0;
+        let mut end = 0;
+
+        for line in text.split_inclusive('\n') {
+            tokens += self.bpe.encode_ordinary(line).len();
+            if tokens > self.remaining {
+                break;
+            }
+
+            end += line.len();
+        }
+
+        &text[..end]
+    }
+
+    /// Set aside up to `tokens` for a part of the prompt that is only known later, returning how
+    /// many were set aside.
+    pub fn reserve(&mut self, tokens: usize) -> usize {
+        let reserved = tokens.min(self.remaining);
+        self.remaining -= reserved;
+        reserved
+    }
+
+    /// Make tokens that were set aside with `reserve` available again.
+    pub fn release(&mut self, tokens: usize) {
+        self.remaining += tokens;
+    }
+
+    /// Spend the tokens of `text`.
+    pub fn spend(&mut self, text: &str) {
+        let tokens = self.bpe.encode_ordinary(text).len();
+        self.remaining = self.remaining.saturating_sub(tokens);
+    }
+
+    /// Spend the tokens of the leading lines of `text` that fit, and return them.
+    pub fn take<'a>(&mut self, text: &'a str) -> &'a str {
+        let taken = self.truncate(text);
+        self.spend(taken);
+        taken
+    }
+
+    /// Keep the most recent messages of `history` that fit, dropping older ones.
+    pub fn history(&mut self, mut history: Vec<Message>) -> Result<Vec<Message>> {
+        let mut kept = 0;
+        for message in history.iter().rev() {
+            let tokens = self.message_tokens(message)?;
+            if tokens > self.remaining {
+                break;
+            }
+
+            self.remaining -= tokens;
+            kept += 1;
+        }
+
+        Ok(history.split_off(history.len() - kept))
+    }
+
+    fn message_tokens(&self, message: &Message) -> Result<usize> {
+        tiktoken_rs::num_tokens_from_messages(&self.model, &[message.into()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "gpt-4-0613";
+
+    #[test]
+    fn trims_history() {
+        let long_string = "long string ".repeat(2000);
+        let history = vec![
+            Message::user("bar"),
+            Message::assistant("baz"),
+            Message::user(&long_string),
+            Message::assistant("quux"),
+            Message::user("fred"),
+            Message::assistant("thud"),
+            Message::user(&long_string),
+            Message::user("corge"),
+        ];
+
+        // the answer needs 8100 tokens of 8192, the history can admit just one message
+        let mut budget = PromptBudget::new(MODEL, 8192, 8100).unwrap();
+        assert_eq!(
+            budget.history(history.clone()).unwrap(),
+            vec![Message::user("corge")]
+        );
+
+        // the answer needs just 4000 tokens of 8192, the history can accomodate one long_string,
+        // but no more long_strings
+        let mut budget = PromptBudget::new(MODEL, 8192, 4000).unwrap();
+        assert_eq!(
+            budget.history(history.clone()).unwrap(),
+            vec![
+                Message::assistant("quux"),
+                Message::user("fred"),
+                Message::assistant("thud"),
+                Message::user(&long_string),
+                Message::user("corge"),
+            ]
+        );
+
+        // with a larger context, the whole history fits
+        let mut budget = PromptBudget::new(MODEL, 32768, 8100).unwrap();
+        assert_eq!(budget.history(history.clone()).unwrap(), history);
+    }
+
+    #[test]
+    fn questions_must_fit() {
+        let mut budget = PromptBudget::new(MODEL, 100, 50).unwrap();
+
+        budget.require(&Message::user("short question")).unwrap();
+        assert!(budget.remaining() < 50);
+
+        let remaining = budget.remaining();
+        assert!(budget
+            .require(&Message::user(&"word ".repeat(100)))
+            .is_err());
+        assert_eq!(budget.remaining(), remaining);
+    }
+
+    #[test]
+    fn truncates_by_line() {
+        let bpe = tiktoken_rs::get_bpe_from_model(MODEL).unwrap();
+        let two_lines = bpe.encode_ordinary("first line\nsecond line\n").len();
+
+        let mut budget = PromptBudget::new(MODEL, two_lines + 1, 0).unwrap();
+        let text = "first line\nsecond line\nthird line\n";
+
+        assert_eq!(budget.truncate(text), "first line\nsecond line\n");
+        assert_eq!(budget.remaining(), two_lines + 1);
+
+        assert_eq!(budget.take(text), "first line\nsecond line\n");
+        assert_eq!(budget.remaining(), 1);
+        assert_eq!(budget.take(text), "");
+    }
+
+    #[test]
+    fn reserves_tokens() {
+        let mut budget = PromptBudget::new(MODEL, 100, 0).unwrap();
+
+        assert_eq!(budget.reserve(30), 30);
+        assert_eq!(budget.remaining(), 70);
+        assert_eq!(budget.reserve(100), 70);
+        assert_eq!(budget.remaining(), 0);
+
+        budget.release(100);
+        assert_eq!(budget.remaining(), 100);
+    }
+}
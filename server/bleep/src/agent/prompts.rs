@@ -1,4 +1,6 @@
-pub fn functions(add_proc: bool) -> serde_json::Value {
+use super::exchange::AnswerKind;
+
+pub fn functions(add_proc: bool, deep: bool) -> serde_json::Value {
     let mut funcs = serde_json::json!(
         [
             {
@@ -76,24 +78,75 @@ pub fn functions(add_proc: bool) -> serde_json::Value {
             )
         );
     }
-    funcs
-}
-
-pub fn system<'a>(paths: impl IntoIterator<Item = &'a str>) -> String {
-    let mut s = "".to_string();
 
-    let mut paths = paths.into_iter().peekable();
+    if deep {
+        funcs.as_array_mut().unwrap().push(serde_json::json!(
+            {
+                "name": "grep",
+                "description": "Search the contents of files in a codebase for a regular expression, and list the matching lines. Use when you know an exact identifier or string.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "description": "The regular expression to search for, e.g. 'fn parse_\\w+' or 'MAX_TOKENS'."
+                        }
+                    },
+                    "required": ["pattern"]
+                }
+            }
+        ));
+    }
 
-    if paths.peek().is_some() {
-        s.push_str("## PATHS ##\nindex, path\n");
-        for (i, path) in paths.enumerate() {
-            s.push_str(&format!("{}, {}\n", i, path));
-        }
-        s.push('\n');
+    if deep && add_proc {
+        let funcs = funcs.as_array_mut().unwrap();
+        funcs.push(serde_json::json!(
+            {
+                "name": "open",
+                "description": "Read a range of lines of a file. Use to read code around a search result, or a definition listed by functions.symbols.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "integer",
+                            "description": "The index of the path to read."
+                        },
+                        "start": {
+                            "type": "integer",
+                            "description": "The first line to read, starting from 1. Defaults to the start of the file."
+                        },
+                        "end": {
+                            "type": "integer",
+                            "description": "The last line to read. Defaults to the end of the file. At most 200 lines are read."
+                        }
+                    },
+                    "required": ["path"]
+                }
+            }
+        ));
+        funcs.push(serde_json::json!(
+            {
+                "name": "symbols",
+                "description": "List the symbols defined in a file, such as functions and types, with the lines they are defined on.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "integer",
+                            "description": "The index of the path to list symbols of."
+                        }
+                    },
+                    "required": ["path"]
+                }
+            }
+        ));
     }
 
-    s.push_str(
-        r#"Your job is to choose the best action. Call functions to find information that will help answer the user's query. Call functions.none when you have enough information to answer. Follow these rules at all times:
+    funcs
+}
+
+/// The rules of the agent's system prompt, unless a canary template replaces them.
+pub const SYSTEM_RULES: &str = r#"Your job is to choose the best action. Call functions to find information that will help answer the user's query. Call functions.none when you have enough information to answer. Follow these rules at all times:
 
 - ALWAYS call a function, DO NOT answer the question directly, even if the query is not in English
 - DO NOT call a function that you've used before with the same arguments
@@ -110,7 +163,49 @@ pub fn system<'a>(paths: impl IntoIterator<Item = &'a str>) -> String {
 - Call functions.proc with paths that might contain relevant information. Either because of the path name, or to expand on code that's already been returned by functions.code 
 - DO NOT call functions.proc with more than 5 paths
 - DO NOT call functions.proc on the same file more than once
-- ALWAYS call a function. DO NOT answer the question directly"#);
+- ALWAYS call a function. DO NOT answer the question directly"#;
+
+pub fn system<'a>(
+    repos: &[String],
+    paths: impl IntoIterator<Item = &'a str>,
+    rules: &str,
+    deep: bool,
+) -> String {
+    let mut s = "".to_string();
+
+    if !repos.is_empty() {
+        s.push_str("## REPOSITORIES ##\n");
+        for repo in repos {
+            s.push_str(&format!("{repo}\n"));
+        }
+        s.push('\n');
+    }
+
+    let mut paths = paths.into_iter().peekable();
+
+    if paths.peek().is_some() {
+        s.push_str("## PATHS ##\nindex, path\n");
+        for (i, path) in paths.enumerate() {
+            s.push_str(&format!("{}, {}\n", i, path));
+        }
+        s.push('\n');
+    }
+
+    s.push_str(rules);
+
+    if deep {
+        s.push_str(
+            r#"
+
+This question needs careful research. You can also call these functions:
+
+- Call functions.grep with a regular expression to find exact identifiers or strings, e.g. every caller of a function
+- Call functions.symbols to see the outline of a file under the PATHS heading, then functions.open to read the definitions you need
+- Call functions.open with a line range, rather than reading a whole file
+- Follow references across files until you understand how the code fits together, then call functions.none"#,
+        );
+    }
+
     s
 }
 
@@ -221,10 +316,28 @@ println!("hello world!");
 <StartLine>4</StartLine>
 <EndLine>5</EndLine>
 </QuotedCode>
+###
+  - To suggest a change to existing code, use the following structure (example given):
+###
+The loop can be simplified:
+<SuggestedEdit>
+<Diff>
+@@ -12,3 +12,1 @@
+-for i in 0..items.len() {{
+-    total += items[i];
+-}}
++total += items.iter().sum::<i32>();
+</Diff>
+<Path>src/main.rs</Path>
+<StartLine>12</StartLine>
+<EndLine>14</EndLine>
+</SuggestedEdit>
 ###
   - `<GeneratedCode>` and `<QuotedCode>` elements MUST contain a `<Language>` value, and `<QuotedCode>` MUST additionally contain `<Path>`, `<StartLine>`, and `<EndLine>`.
+  - `<SuggestedEdit>` elements MUST contain a `<Diff>`, which is a unified diff against the file in `<Path>`, and the `<StartLine>` and `<EndLine>` of the code it changes. Line numbers in the diff must match the original file.
   - Note: the line range is inclusive
 - When writing example code blocks, use `<GeneratedCode>`, and when quoting existing code, use `<QuotedCode>`.
+- Only use `<SuggestedEdit>` when the user asks how to change or fix existing code, and only for code you have been shown above.
 - You MUST use XML code blocks instead of markdown."#
     );
 
@@ -235,6 +348,57 @@ println!("hello world!");
     }
 }
 
+pub fn explain_crash_prompt(aliases: &[usize], context: &str, trace: &str) -> String {
+    let article_prompt = answer_article_prompt(aliases, context);
+
+    format!(
+        r#"{article_prompt}
+
+The user's query contains a stack trace, and the code above includes the lines around its frames that could be found in the codebase:
+
+{trace}
+Your job is to explain this crash:
+- Start from the innermost frame in the codebase, and explain what the code there does and why it fails
+- Follow the frames outwards only as far as needed to explain how the failing state came about
+- Frames of the standard library or of dependencies are not in the codebase, DO NOT guess their code
+- Name the most likely cause, and if the code above is enough to fix it, suggest a fix"#
+    )
+}
+
+pub fn snippet_explanation_prompt(question: &str, path: &str, code: &str) -> String {
+    format!(
+        r#"Below are some lines from the file /{path}. Each line is numbered.
+
+#####
+
+{code}
+
+#####
+
+Your job is to explain how these lines relate to the question: {question}
+- Explain only what these lines do, DO NOT guess what the rest of the codebase does
+- Refer to the lines by their numbers
+- If the lines are not relevant to the question, say so in one sentence
+- Write at most 5 sentences"#
+    )
+}
+
+pub fn map_reduce_prompt(aliases: &[usize], context: &str, explanations: &str) -> String {
+    let article_prompt = answer_article_prompt(aliases, context);
+
+    format!(
+        r#"{article_prompt}
+
+The most relevant code chunks above were each explained on their own:
+
+{explanations}
+Your job is to combine these explanations into one coherent answer:
+- Explain how the code chunks work together, rather than explaining each one in turn
+- Leave out the code chunks whose explanation says they are not relevant
+- If the explanations disagree with the code, trust the code"#
+    )
+}
+
 pub fn hypothetical_document_prompt(query: &str) -> String {
     format!(
         r#"Write a code snippet that could hypothetically be returned by a code search engine as the answer to the query: {query}
@@ -270,6 +434,132 @@ pub fn try_parse_hypothetical_documents(document: &str) -> Vec<String> {
         .collect()
 }
 
+pub fn query_paraphrases_prompt(query: &str, count: usize) -> String {
+    format!(
+        r#"Rewrite the following code search query in {count} different ways, to find the code that answers it: {query}
+
+- Use the words that the code is likely to use, such as the names of functions, types and concepts
+- Keep the meaning of the query
+- Write one rewrite per line, without numbering or any other text"#
+    )
+}
+
+/// The non-empty lines of a response to the paraphrase prompt, without list markers.
+pub fn try_parse_paraphrases(response: &str, count: usize) -> Vec<String> {
+    response
+        .lines()
+        .map(|line| {
+            lazy_regex::regex!(r"^\s*(\d+[.)]|[-*])?\s*")
+                .replace(line, "")
+                .trim()
+                .trim_matches('"')
+                .to_owned()
+        })
+        .filter(|line| !line.is_empty())
+        .take(count)
+        .collect()
+}
+
+pub fn conversation_summary_prompt(previous: Option<&str>, transcript: &str) -> String {
+    let previous = previous
+        .map(|s| format!("Summary of the conversation so far:\n\n{s}\n\n#####\n\n"))
+        .unwrap_or_default();
+
+    format!(
+        r#"{previous}Below is a transcript of a conversation between a user and an assistant about a codebase.
+
+#####
+
+{transcript}
+
+#####
+
+Your job is to write a concise summary of the conversation, combined with the summary so far if there is one.
+- Keep the questions the user asked, and the facts the assistant established about the codebase
+- Keep file paths, symbol names and other identifiers verbatim
+- DO NOT include code blocks
+- Write at most 10 sentences"#
+    )
+}
+
+pub fn image_transcription_prompt() -> &'static str {
+    r#"Transcribe the code and text in this image, such as source code, stack traces, error messages or terminal output.
+- Reproduce identifiers, file paths and line numbers exactly
+- Keep the line breaks and indentation of code
+- DO NOT describe the image, explain the code or add anything that is not in the image
+- If there is no text in the image, respond with nothing"#
+}
+
+pub fn disambiguate_symbol_prompt(description: &str, candidates: &str) -> String {
+    format!(
+        r#"Below is a numbered list of code symbols.
+
+#####
+
+{candidates}
+
+#####
+
+Your job is to pick the symbol that best matches this description: {description}
+- Respond with only the number of the symbol
+- DO NOT explain your answer"#
+    )
+}
+
+pub fn route_query_prompt(repos: &str) -> String {
+    format!(
+        r#"You are an assistant that answers questions about these repositories:
+
+{repos}
+
+Your job is to classify the last message of the user into one of these kinds:
+
+0: A question or request about the code in the repositories, or a follow-up to an earlier answer
+1: A greeting, or a question about who you are and what you can do
+2: A general programming question that can be answered without reading the code in the repositories
+3: A request that has nothing to do with code or programming
+
+- Respond with only the number of the kind
+- If you are not sure, respond with 0
+- DO NOT explain your answer"#
+    )
+}
+
+pub fn route_reply_prompt(kind: AnswerKind, repos: &str) -> String {
+    let instructions = match kind {
+        AnswerKind::Intro => {
+            r#"The user greeted you, or asked what you can do. Introduce yourself briefly:
+- You answer questions about the code in the repositories above, by searching it and explaining what you find
+- Give two or three examples of questions you could answer"#
+        }
+        AnswerKind::Info => {
+            r#"The user asked a general programming question. Answer it concisely:
+- DO NOT make claims about the code in the repositories above, as you have not read it
+- Suggest a question about the repositories that could follow, if there is an obvious one"#
+        }
+        _ => {
+            r#"The user asked for something that has nothing to do with code. Politely explain that you can't help with it:
+- DO NOT answer the request
+- Remind the user that you answer questions about the code in the repositories above"#
+        }
+    };
+
+    format!(
+        r#"You are an assistant that answers questions about these repositories:
+
+{repos}
+
+{instructions}
+- Reply in a few sentences of markdown
+- DO NOT write code blocks"#
+    )
+}
+
+pub fn no_answer() -> &'static str {
+    "I couldn't find any code in this repository that answers your question. Try rephrasing it, \
+or asking about a specific file or symbol."
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -306,4 +596,21 @@ pub fn final_explanation_prompt(context: &str, query: &str, query_history: &str)
 
         assert_eq!(try_parse_hypothetical_documents(document), expected);
     }
+
+    #[test]
+    fn test_parse_paraphrases() {
+        let response = "1. Where is the semantic search query parsed?\n\n\
+                        - \"parse_nl SemanticQuery\"\n\
+                        * 2D query parser\n\
+                        extra";
+
+        assert_eq!(
+            try_parse_paraphrases(response, 3),
+            [
+                "Where is the semantic search query parsed?",
+                "parse_nl SemanticQuery",
+                "2D query parser",
+            ]
+        );
+    }
 }
@@ -0,0 +1,307 @@
+//! Canary rollouts of the agent's system prompt.
+//!
+//! A prompt template set with `prompt_template` replaces the built-in rules of the system prompt
+//! for `prompt_canary_percent` of queries, while the rest keep the built-in rules. Each side counts
+//! the function calls that fail to parse and the votes on its answers. Once the canary has enough
+//! samples, a failure or downvote rate that exceeds the built-in one by more than the configured
+//! margin rolls it back, and every query gets the built-in rules again.
+//!
+//! Rollbacks are stored by template version, which is the hash of the template, so a template
+//! that was rolled back stays rolled back across restarts until it is changed.
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+use super::prompts;
+use crate::{
+    db::{PromptRollbacks, SqlDb},
+    Configuration,
+};
+
+pub const BUILTIN_VERSION: &str = "builtin";
+
+pub struct Template {
+    pub version: String,
+    pub rules: String,
+}
+
+impl Template {
+    fn builtin() -> Self {
+        Self {
+            version: BUILTIN_VERSION.to_owned(),
+            rules: prompts::SYSTEM_RULES.to_owned(),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let rules = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read prompt template {}", path.display()))?;
+        let version = blake3::hash(rules.as_bytes()).to_hex()[..12].to_owned();
+
+        Ok(Self { version, rules })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// The percentage of queries that get the canary.
+    pub percent: u8,
+    /// The number of steps, and separately of votes, each side needs before they are compared.
+    pub min_samples: u64,
+    /// The largest acceptable increase in the rate of function calls that fail to parse.
+    pub max_failure_increase: f64,
+    /// The largest acceptable increase in the rate of downvoted answers.
+    pub max_downvote_increase: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Stats {
+    steps: u64,
+    parse_failures: u64,
+    upvotes: u64,
+    downvotes: u64,
+}
+
+impl Stats {
+    fn failure_rate(&self) -> f64 {
+        ratio(self.parse_failures, self.steps)
+    }
+
+    fn votes(&self) -> u64 {
+        self.upvotes + self.downvotes
+    }
+
+    fn downvote_rate(&self) -> f64 {
+        ratio(self.downvotes, self.votes())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Arms {
+    builtin: Stats,
+    canary: Stats,
+}
+
+pub struct PromptRollout {
+    builtin: Template,
+    canary: Option<Template>,
+    thresholds: Thresholds,
+    arms: Mutex<Arms>,
+    rolled_back: AtomicBool,
+    sql: SqlDb,
+}
+
+impl PromptRollout {
+    pub async fn load(config: &Configuration, sql: SqlDb) -> Result<Self> {
+        let canary = config
+            .prompt_template
+            .as_deref()
+            .map(Template::load)
+            .transpose()?;
+
+        let rolled_back = match &canary {
+            Some(canary) => PromptRollbacks::new(&sql).contains(&canary.version).await?,
+            None => false,
+        };
+
+        if let Some(canary) = &canary {
+            if rolled_back {
+                warn!(version = %canary.version, "prompt template was rolled back before");
+            } else {
+                info!(
+                    version = %canary.version,
+                    percent = config.prompt_canary_percent,
+                    "rolling out prompt template"
+                );
+            }
+        }
+
+        Ok(Self {
+            builtin: Template::builtin(),
+            canary,
+            thresholds: Thresholds {
+                percent: config.prompt_canary_percent.min(100),
+                min_samples: config.prompt_canary_min_samples,
+                max_failure_increase: config.prompt_canary_max_failure_increase,
+                max_downvote_increase: config.prompt_canary_max_downvote_increase,
+            },
+            arms: Default::default(),
+            rolled_back: AtomicBool::new(rolled_back),
+            sql,
+        })
+    }
+
+    /// The template to prompt with for a query.
+    pub fn template(&self, query_id: uuid::Uuid) -> &Template {
+        match &self.canary {
+            Some(canary) if self.is_canary(query_id) && !self.rolled_back() => canary,
+            _ => &self.builtin,
+        }
+    }
+
+    pub fn rolled_back(&self) -> bool {
+        self.rolled_back.load(Ordering::Relaxed)
+    }
+
+    /// Record whether the function call of an agent step could be parsed.
+    pub async fn record_step(&self, query_id: uuid::Uuid, parsed: bool) {
+        self.record(query_id, |stats| {
+            stats.steps += 1;
+            stats.parse_failures += u64::from(!parsed);
+        })
+        .await;
+    }
+
+    /// Record a vote on the answer to a query.
+    pub async fn record_vote(&self, query_id: uuid::Uuid, positive: bool) {
+        self.record(query_id, |stats| {
+            if positive {
+                stats.upvotes += 1;
+            } else {
+                stats.downvotes += 1;
+            }
+        })
+        .await;
+    }
+
+    async fn record(&self, query_id: uuid::Uuid, update: impl FnOnce(&mut Stats)) {
+        let Some(canary) = &self.canary else {
+            return;
+        };
+
+        if self.rolled_back() {
+            return;
+        }
+
+        let reason = {
+            let mut arms = self.arms.lock().unwrap();
+            if self.is_canary(query_id) {
+                update(&mut arms.canary);
+            } else {
+                update(&mut arms.builtin);
+            }
+
+            regression(&arms.builtin, &arms.canary, &self.thresholds)
+        };
+
+        let Some(reason) = reason else {
+            return;
+        };
+
+        // Only the first regression rolls back.
+        if self.rolled_back.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        warn!(version = %canary.version, %reason, "rolling back prompt template");
+        if let Err(err) = PromptRollbacks::new(&self.sql)
+            .insert(&canary.version, &reason)
+            .await
+        {
+            warn!(?err, "failed to store prompt template rollback");
+        }
+    }
+
+    fn is_canary(&self, query_id: uuid::Uuid) -> bool {
+        in_canary(query_id, self.thresholds.percent)
+    }
+}
+
+/// Queries are split by id, so that votes can be attributed to the template that was used.
+fn in_canary(query_id: uuid::Uuid, percent: u8) -> bool {
+    query_id.as_u128() % 100 < u128::from(percent)
+}
+
+/// Why the canary regressed compared to the built-in template, if it did.
+fn regression(builtin: &Stats, canary: &Stats, thresholds: &Thresholds) -> Option<String> {
+    let min = thresholds.min_samples;
+
+    if builtin.steps >= min && canary.steps >= min {
+        let (before, after) = (builtin.failure_rate(), canary.failure_rate());
+        if after - before > thresholds.max_failure_increase {
+            return Some(format!(
+                "parse failure rate went from {before:.3} to {after:.3}"
+            ));
+        }
+    }
+
+    if builtin.votes() >= min && canary.votes() >= min {
+        let (before, after) = (builtin.downvote_rate(), canary.downvote_rate());
+        if after - before > thresholds.max_downvote_increase {
+            return Some(format!("downvote rate went from {before:.3} to {after:.3}"));
+        }
+    }
+
+    None
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        percent: 10,
+        min_samples: 10,
+        max_failure_increase: 0.05,
+        max_downvote_increase: 0.2,
+    };
+
+    fn stats(steps: u64, parse_failures: u64, upvotes: u64, downvotes: u64) -> Stats {
+        Stats {
+            steps,
+            parse_failures,
+            upvotes,
+            downvotes,
+        }
+    }
+
+    #[test]
+    fn detects_regressions() {
+        let builtin = stats(100, 2, 20, 5);
+
+        assert_eq!(
+            regression(&builtin, &stats(20, 1, 10, 2), &THRESHOLDS),
+            None
+        );
+        assert_eq!(
+            regression(&builtin, &stats(20, 3, 10, 2), &THRESHOLDS).unwrap(),
+            "parse failure rate went from 0.020 to 0.150"
+        );
+        assert_eq!(
+            regression(&builtin, &stats(20, 0, 4, 6), &THRESHOLDS).unwrap(),
+            "downvote rate went from 0.200 to 0.600"
+        );
+
+        // Too few samples to tell.
+        assert_eq!(regression(&builtin, &stats(5, 5, 1, 5), &THRESHOLDS), None);
+    }
+
+    #[test]
+    fn splits_queries() {
+        let canary = (0..1000)
+            .filter(|_| in_canary(uuid::Uuid::new_v4(), 10))
+            .count();
+        assert!((50..150).contains(&canary), "{canary}");
+
+        let id = uuid::Uuid::new_v4();
+        assert_eq!(in_canary(id, 10), in_canary(id, 10));
+        assert!(!in_canary(id, 0));
+        assert!(in_canary(id, 100));
+    }
+}
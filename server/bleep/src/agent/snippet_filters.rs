@@ -0,0 +1,298 @@
+//! Filters of retrieved code, written as small WASM modules.
+//!
+//! Each `<name>.wasm` module in `snippet_filters_dir` runs over the snippets that retrieval
+//! returned, before the agent selects which of them make it into the context. Modules run in the
+//! order of their file names, so that deployments can apply rules of their own, like never citing
+//! generated code, without changing the retrieval code.
+//!
+//! A module exports its `memory`, and two functions:
+//!
+//! - `alloc(len: i32) -> i32`, which returns a pointer to `len` bytes of free memory
+//! - `filter(ptr: i32, len: i32) -> f32`, which reads the metadata of a snippet as JSON from the
+//!   memory returned by `alloc`, and returns the weight of the snippet
+//!
+//! The metadata has the `repo_name`, `repo_ref`, `relative_path`, `lang`, `branches`, `license`,
+//! `start_line`, `end_line` and `score` of the snippet. A weight of 0 or less drops the snippet,
+//! and any other weight multiplies its score.
+//!
+//! Modules can't import anything from the host, each call of `filter` runs with a limited amount
+//! of fuel, and an instance can't grow its memory past a limit. A module that fails leaves the
+//! snippets as they are.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::{semantic::Payload, Configuration};
+
+const EXTENSION: &str = "wasm";
+
+/// The fuel of one call of `filter`, which is roughly the number of instructions it executes.
+const FUEL_PER_SNIPPET: u64 = 10_000_000;
+
+/// The most memory that an instance of a filter can use, in bytes.
+const MAX_MEMORY: usize = 16 << 20;
+
+#[derive(Default)]
+pub struct SnippetFilters {
+    engine: Engine,
+    filters: Vec<Filter>,
+}
+
+struct Filter {
+    name: String,
+    module: Module,
+}
+
+struct State {
+    limits: StoreLimits,
+}
+
+#[derive(Serialize)]
+struct Metadata<'a> {
+    repo_name: &'a str,
+    repo_ref: &'a str,
+    relative_path: &'a str,
+    lang: &'a str,
+    branches: &'a [String],
+    license: Option<&'a str>,
+    start_line: u64,
+    end_line: u64,
+    score: Option<f32>,
+}
+
+impl<'a> From<&'a Payload> for Metadata<'a> {
+    fn from(payload: &'a Payload) -> Self {
+        Self {
+            repo_name: &payload.repo_name,
+            repo_ref: &payload.repo_ref,
+            relative_path: &payload.relative_path,
+            lang: &payload.lang,
+            branches: &payload.branches,
+            license: payload.license.as_deref(),
+            start_line: payload.start_line,
+            end_line: payload.end_line,
+            score: payload.score,
+        }
+    }
+}
+
+impl SnippetFilters {
+    pub fn load(config: &Configuration) -> Result<Self> {
+        let Some(dir) = config.snippet_filters_dir.as_deref() else {
+            return Ok(Self::default());
+        };
+
+        let filters = Self::new(read_dir(dir)?)?;
+        info!(
+            count = filters.filters.len(),
+            ?dir,
+            "loaded snippet filters"
+        );
+        Ok(filters)
+    }
+
+    /// Compile the modules, given by name.
+    fn new(modules: Vec<(String, Vec<u8>)>) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let filters = modules
+            .into_iter()
+            .map(|(name, wasm)| {
+                let module = Module::new(&engine, &wasm[..])
+                    .with_context(|| format!("invalid snippet filter `{name}`"))?;
+                Ok(Filter { name, module })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { engine, filters })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Run every filter over `snippets`, and return the number of snippets they dropped.
+    ///
+    /// Snippets are sorted by their score again if a filter changed it. This runs the modules on
+    /// the current thread, so async callers should move it to a blocking task.
+    pub fn apply(&self, snippets: &mut Vec<Payload>) -> usize {
+        let before = snippets.len();
+        let mut reweighted = false;
+
+        for filter in &self.filters {
+            let weights = match filter.weights(&self.engine, snippets) {
+                Ok(weights) => weights,
+                Err(err) => {
+                    warn!(?err, filter = filter.name, "snippet filter failed");
+                    continue;
+                }
+            };
+
+            let mut weights = weights.into_iter();
+            snippets.retain_mut(|snippet| {
+                let weight = weights.next().unwrap_or(1.0);
+                if weight != 1.0 {
+                    reweighted = true;
+                    snippet.score = snippet.score.map(|score| score * weight);
+                }
+
+                // NaN drops the snippet too
+                weight > 0.0
+            });
+        }
+
+        if reweighted {
+            snippets.sort_by(|a, b| {
+                let score = |p: &Payload| p.score.unwrap_or_default();
+                score(b).total_cmp(&score(a))
+            });
+        }
+
+        before - snippets.len()
+    }
+}
+
+impl Filter {
+    /// The weight that this filter gives each snippet, from a fresh instance of its module.
+    fn weights(&self, engine: &Engine, snippets: &[Payload]) -> Result<Vec<f32>> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store = Store::new(engine, State { limits });
+        store.limiter(|state| &mut state.limits);
+        set_fuel(&mut store, FUEL_PER_SNIPPET)?;
+
+        let instance = Linker::<State>::new(engine)
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .context("the module doesn't export its `memory`")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let filter = instance.get_typed_func::<(i32, i32), f32>(&store, "filter")?;
+
+        snippets
+            .iter()
+            .map(|snippet| {
+                let metadata = serde_json::to_vec(&Metadata::from(snippet))?;
+                let len = i32::try_from(metadata.len())?;
+
+                set_fuel(&mut store, FUEL_PER_SNIPPET)?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as usize, &metadata)?;
+                Ok(filter.call(&mut store, (ptr, len))?)
+            })
+            .collect()
+    }
+}
+
+/// Leave exactly `fuel` in the store, whatever the previous call left over.
+fn set_fuel(store: &mut Store<State>, fuel: u64) -> Result<()> {
+    let remaining = store.consume_fuel(0)?;
+    if remaining < fuel {
+        store.add_fuel(fuel - remaining)?;
+    } else {
+        store.consume_fuel(remaining - fuel)?;
+    }
+
+    Ok(())
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut modules = vec![];
+    for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != EXTENSION) {
+            continue;
+        }
+
+        let Some(name) = path.file_stem().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        let wasm = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+        modules.push((name.to_owned(), wasm));
+    }
+
+    modules.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(wat: &str) -> SnippetFilters {
+        SnippetFilters::new(vec![("test".to_owned(), wat::parse_str(wat).unwrap())]).unwrap()
+    }
+
+    fn snippet(path: &str, score: f32) -> Payload {
+        Payload {
+            relative_path: path.to_owned(),
+            score: Some(score),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn drops_and_reweights_snippets() {
+        // Drops snippets with long metadata, and halves the score of the others.
+        let filters = filters(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "filter") (param i32 i32) (result f32)
+                    (if (result f32) (i32.gt_u (local.get 1) (i32.const 200))
+                        (then (f32.const 0))
+                        (else (f32.const 0.5)))))"#,
+        );
+
+        let mut snippets = vec![
+            snippet("src/lib.rs", 0.8),
+            snippet(&format!("generated/{}.rs", "a".repeat(200)), 0.9),
+        ];
+
+        assert_eq!(filters.apply(&mut snippets), 1);
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].relative_path, "src/lib.rs");
+        assert_eq!(snippets[0].score, Some(0.4));
+    }
+
+    #[test]
+    fn keeps_snippets_when_out_of_fuel() {
+        let filters = filters(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "filter") (param i32 i32) (result f32)
+                    (loop (br 0))
+                    (f32.const 0)))"#,
+        );
+
+        let mut snippets = vec![snippet("src/lib.rs", 0.8)];
+        assert_eq!(filters.apply(&mut snippets), 0);
+        assert_eq!(snippets[0].score, Some(0.8));
+    }
+
+    #[test]
+    fn limits_the_memory_of_filters() {
+        // Drops the snippet only if growing the memory by 32MiB succeeds.
+        let filters = filters(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "filter") (param i32 i32) (result f32)
+                    (if (result f32) (i32.eq (memory.grow (i32.const 512)) (i32.const -1))
+                        (then (f32.const 1))
+                        (else (f32.const 0)))))"#,
+        );
+
+        let mut snippets = vec![snippet("src/lib.rs", 0.8)];
+        assert_eq!(filters.apply(&mut snippets), 0);
+        assert_eq!(snippets[0].score, Some(0.8));
+    }
+}
@@ -0,0 +1,490 @@
+//! Pluggable stages of the answer pipeline.
+//!
+//! The agent retrieves code, selects which of the retrieved chunks make it into the context, and
+//! finally explains the result to the user. Each of these steps is expressed as a trait here,
+//! with the default implementations reproducing the standard behaviour, so that alternative
+//! strategies can be swapped in and tested in isolation.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use lazy_regex::regex;
+
+use crate::{
+    agent::{diagnostics::Retrieval, exchange::CodeChunk, ANSWER_MODEL},
+    federation::{self, FederatedHit},
+    indexes::Indexes,
+    llm_gateway,
+    query::{
+        execute::{ApiQuery, QueryResult},
+        parser::{self, Literal, SemanticQuery},
+    },
+    repo::RepoRef,
+    semantic::{self, SearchFilters, Semantic},
+    state::RepositoryPool,
+    Application,
+};
+
+/// Find candidate code for a query, recording how in `diagnostics`.
+#[async_trait]
+pub trait Retriever: Send + Sync {
+    async fn retrieve(
+        &self,
+        query: &SemanticQuery<'_>,
+        limit: u64,
+        offset: u64,
+        threshold: f32,
+        retrieve_more: bool,
+        diagnostics: &mut Retrieval,
+    ) -> Result<Vec<semantic::Payload>>;
+}
+
+/// Decide which chunks are added to the context, and in which order.
+pub trait Selector: Send + Sync {
+    /// Returns the indices of the selected chunks, in presentation order.
+    fn select(&self, chunks: &[CodeChunk]) -> Vec<usize>;
+}
+
+/// Turn the final prompt into a stream of answer fragments.
+#[async_trait]
+pub trait Explainer: Send + Sync {
+    async fn explain<'a>(
+        &'a self,
+        messages: &'a [llm_gateway::api::Message],
+    ) -> Result<BoxStream<'a, Result<String>>>;
+}
+
+/// The set of stages used by an `Agent`.
+#[derive(Clone)]
+pub struct Stages {
+    pub retriever: Arc<dyn Retriever>,
+    pub selector: Arc<dyn Selector>,
+    pub explainer: Arc<dyn Explainer>,
+}
+
+impl Stages {
+    /// The default pipeline: semantic retrieval, order-preserving selection, and an explanation
+    /// generated by the answer model.
+    pub fn new(app: &Application, llm_gateway: &llm_gateway::Client) -> Self {
+        Self {
+            retriever: Arc::new(SemanticRetriever(app.semantic.clone())),
+            selector: Arc::new(DefaultSelector),
+            explainer: Arc::new(LlmExplainer(llm_gateway.clone().model(ANSWER_MODEL))),
+        }
+    }
+}
+
+pub struct SemanticRetriever(Option<Semantic>);
+
+impl SemanticRetriever {
+    pub fn new(semantic: Option<Semantic>) -> Self {
+        Self(semantic)
+    }
+}
+
+#[async_trait]
+impl Retriever for SemanticRetriever {
+    async fn retrieve(
+        &self,
+        query: &SemanticQuery<'_>,
+        limit: u64,
+        offset: u64,
+        threshold: f32,
+        retrieve_more: bool,
+        diagnostics: &mut Retrieval,
+    ) -> Result<Vec<semantic::Payload>> {
+        let filters = SearchFilters::from_query(query);
+        let (results, stats) = self
+            .0
+            .as_ref()
+            .context("semantic search is not configured")?
+            .search_with_stats(query, &filters, limit, offset, threshold, retrieve_more)
+            .await?;
+
+        diagnostics.searched(stats);
+        Ok(results)
+    }
+}
+
+/// Semantic retrieval that also fans out to federated peers, merging all results by score.
+///
+/// Results from peers keep their origin in their path, see [`federation::remote_path`].
+pub struct FederatedRetriever {
+    local: SemanticRetriever,
+    peers: Vec<(String, federation::Peer)>,
+}
+
+impl FederatedRetriever {
+    pub fn new(app: &Application) -> Self {
+        Self {
+            local: SemanticRetriever(app.semantic.clone()),
+            peers: federation::snapshot(&app.federated_peers),
+        }
+    }
+}
+
+#[async_trait]
+impl Retriever for FederatedRetriever {
+    async fn retrieve(
+        &self,
+        query: &SemanticQuery<'_>,
+        limit: u64,
+        offset: u64,
+        threshold: f32,
+        retrieve_more: bool,
+        diagnostics: &mut Retrieval,
+    ) -> Result<Vec<semantic::Payload>> {
+        let local = self
+            .local
+            .retrieve(query, limit, offset, threshold, retrieve_more, diagnostics)
+            .await?;
+
+        // Peers only return their top results, so there is nothing to page through.
+        let (Some(target), 0) = (query.target(), offset) else {
+            return Ok(local);
+        };
+
+        let remote = federation::search(&self.peers, &target, limit)
+            .await
+            .into_iter()
+            .filter(|hit| hit.score >= threshold)
+            .collect::<Vec<_>>();
+        diagnostics.federated = Some(remote.len());
+
+        let keep = local.len().max(limit as usize);
+        let hits = local
+            .into_iter()
+            .map(|payload| FederatedHit {
+                origin: None,
+                score: payload.score.unwrap_or_default(),
+                payload,
+            })
+            .chain(remote)
+            .collect();
+
+        Ok(federation::merge(hits, keep)
+            .into_iter()
+            .map(|hit| match hit.origin {
+                Some(peer) => semantic::Payload {
+                    relative_path: federation::remote_path(
+                        &peer,
+                        &hit.payload.repo_ref,
+                        &hit.payload.relative_path,
+                    ),
+                    repo_name: format!("{peer}/{}", hit.payload.repo_name),
+                    score: Some(hit.score),
+                    ..hit.payload
+                },
+                None => hit.payload,
+            })
+            .collect())
+    }
+}
+
+/// Retrieval that also searches the lexical index for identifiers in the query, and merges both
+/// result lists by reciprocal rank fusion.
+///
+/// Embeddings capture what code does rather than what it is called, so exact identifiers are
+/// often better matched lexically. Lexical hits leave out the same code as semantic search, and
+/// the paths that their repository excludes.
+pub struct HybridRetriever {
+    inner: Arc<dyn Retriever>,
+    indexes: Arc<Indexes>,
+    semantic: Option<Semantic>,
+    repo_pool: RepositoryPool,
+}
+
+impl HybridRetriever {
+    pub fn new(app: &Application, inner: Arc<dyn Retriever>) -> Self {
+        Self {
+            inner,
+            indexes: Arc::clone(&app.indexes),
+            semantic: app.semantic.clone(),
+            repo_pool: app.repo_pool.clone(),
+        }
+    }
+
+    /// Whether semantic search would leave out this lexical hit.
+    fn is_excluded(&self, payload: &semantic::Payload) -> bool {
+        let by_repo = || {
+            payload
+                .repo_ref
+                .parse::<RepoRef>()
+                .ok()
+                .and_then(|repo_ref| {
+                    self.repo_pool.read(&repo_ref, |_, repo| {
+                        repo.is_excluded(Path::new(&payload.relative_path))
+                    })
+                })
+                .unwrap_or(false)
+        };
+
+        self.semantic
+            .as_ref()
+            .is_some_and(|semantic| semantic.excludes_snippet(payload))
+            || by_repo()
+    }
+
+    /// Snippets of files that contain any of `identifiers`, in the order of their files' rank.
+    async fn lexical(
+        &self,
+        query: &SemanticQuery<'_>,
+        identifiers: &[String],
+        limit: u64,
+    ) -> Result<Vec<semantic::Payload>> {
+        fn or_any<T>(values: Vec<T>) -> Vec<Option<T>> {
+            if values.is_empty() {
+                vec![None]
+            } else {
+                values.into_iter().map(Some).collect()
+            }
+        }
+
+        let branch = query
+            .first_branch()
+            .map(|b| Literal::Plain(b.into_owned().into()));
+        let repos = or_any(query.repos.iter().cloned().collect());
+        let paths = or_any(query.paths.iter().cloned().collect());
+        let langs = or_any(query.langs.iter().cloned().collect());
+
+        let mut queries = vec![];
+        for identifier in identifiers {
+            for repo in &repos {
+                for path in &paths {
+                    for lang in &langs {
+                        queries.push(parser::Query {
+                            repo: repo.clone(),
+                            path: path.clone(),
+                            lang: lang.clone(),
+                            branch: branch.clone(),
+                            target: Some(parser::Target::Content(Literal::Plain(
+                                identifier.clone().into(),
+                            ))),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut params = ApiQuery::new(identifiers.join(" "));
+        params.page_size = limit as usize;
+        params.calculate_totals = false;
+
+        let results = Arc::new(params)
+            .query_with(Arc::clone(&self.indexes), queries)
+            .await?;
+
+        Ok(results
+            .data
+            .into_iter()
+            .filter_map(|r| match r {
+                QueryResult::Snippets(file) => Some(file),
+                _ => None,
+            })
+            .flat_map(|file| {
+                file.snippets
+                    .into_iter()
+                    .map(move |snippet| semantic::Payload {
+                        lang: file.lang.clone().unwrap_or_default(),
+                        repo_name: file.repo_name.clone(),
+                        repo_ref: file.repo_ref.clone(),
+                        relative_path: file.relative_path.clone(),
+                        text: snippet.data,
+                        start_line: snippet.line_range.start as u64,
+                        end_line: snippet.line_range.end as u64,
+                        license: file.license.clone(),
+                        ..Default::default()
+                    })
+            })
+            .filter(|payload| !self.is_excluded(payload))
+            .take(limit as usize)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Retriever for HybridRetriever {
+    async fn retrieve(
+        &self,
+        query: &SemanticQuery<'_>,
+        limit: u64,
+        offset: u64,
+        threshold: f32,
+        retrieve_more: bool,
+        diagnostics: &mut Retrieval,
+    ) -> Result<Vec<semantic::Payload>> {
+        let semantic = self
+            .inner
+            .retrieve(query, limit, offset, threshold, retrieve_more, diagnostics)
+            .await?;
+
+        // Lexical results are ranked by file, so there is nothing to page through.
+        let identifiers = query.target().map_or_else(Vec::new, |t| identifiers(&t));
+        if offset > 0 || identifiers.is_empty() {
+            return Ok(semantic);
+        }
+
+        let lexical = self.lexical(query, &identifiers, limit).await?;
+        diagnostics.lexical = Some(lexical.len());
+        let keep = semantic.len().max(limit as usize);
+        Ok(fuse(vec![semantic, lexical], keep))
+    }
+}
+
+/// Words of a question that look like identifiers in code, rather than prose: those quoted in
+/// backticks, and those with underscores, path separators or inner capitals.
+fn identifiers(text: &str) -> Vec<String> {
+    const MAX_IDENTIFIERS: usize = 5;
+
+    let quoted = regex!(r"`([^`\s]+)`")
+        .captures_iter(text)
+        .map(|c| c[1].to_owned());
+    let named = regex!(r"[A-Za-z_][A-Za-z0-9_]*(?:::[A-Za-z_][A-Za-z0-9_]*)*")
+        .find_iter(text)
+        .map(|m| m.as_str())
+        .filter(|word| {
+            word.contains('_')
+                || word.contains("::")
+                || word.chars().skip(1).any(|c| c.is_ascii_uppercase())
+        })
+        .map(str::to_owned);
+
+    let mut identifiers = Vec::new();
+    for identifier in quoted.chain(named) {
+        if !identifiers.contains(&identifier) && identifiers.len() < MAX_IDENTIFIERS {
+            identifiers.push(identifier);
+        }
+    }
+
+    identifiers
+}
+
+/// Merge ranked result lists by reciprocal rank fusion, keeping the best `keep` results.
+///
+/// Results of the same file with overlapping lines are considered the same, keeping the version of
+/// the first list they are in, which is the one with a similarity score in hybrid retrieval.
+pub(crate) fn fuse(lists: Vec<Vec<semantic::Payload>>, keep: usize) -> Vec<semantic::Payload> {
+    /// Dampens the weight of top ranks, the usual value from the original paper.
+    const RRF_K: f32 = 60.0;
+
+    let mut fused = Vec::<(f32, semantic::Payload)>::new();
+    for list in lists {
+        for (rank, payload) in list.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            let existing = fused.iter_mut().find(|(_, p)| {
+                p.repo_ref == payload.repo_ref
+                    && p.relative_path == payload.relative_path
+                    && p.start_line <= payload.end_line
+                    && payload.start_line <= p.end_line
+            });
+
+            match existing {
+                Some((fused_score, _)) => *fused_score += score,
+                None => fused.push((score, payload)),
+            }
+        }
+    }
+
+    // A stable sort keeps ties in the order of the first list.
+    fused.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    fused.truncate(keep);
+    fused.into_iter().map(|(_, payload)| payload).collect()
+}
+
+/// Keeps every non-empty chunk, grouped by path alias and sorted by line.
+pub struct DefaultSelector;
+
+impl Selector for DefaultSelector {
+    fn select(&self, chunks: &[CodeChunk]) -> Vec<usize> {
+        let mut selected = (0..chunks.len())
+            .filter(|&i| !chunks[i].is_empty())
+            .collect::<Vec<_>>();
+
+        selected.sort_by(|&a, &b| {
+            let (a, b) = (&chunks[a], &chunks[b]);
+            a.alias.cmp(&b.alias).then(a.start_line.cmp(&b.start_line))
+        });
+
+        selected
+    }
+}
+
+pub struct LlmExplainer(llm_gateway::Client);
+
+#[async_trait]
+impl Explainer for LlmExplainer {
+    async fn explain<'a>(
+        &'a self,
+        messages: &'a [llm_gateway::api::Message],
+    ) -> Result<BoxStream<'a, Result<String>>> {
+        Ok(self.0.chat(messages, None).await?.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(alias: usize, start_line: usize, snippet: &str) -> CodeChunk {
+        CodeChunk {
+            path: format!("src/{alias}.rs"),
+            alias,
+            snippet: snippet.to_owned(),
+            start_line,
+            end_line: start_line + 1,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn default_selector_drops_empty_and_orders() {
+        let selected = DefaultSelector.select(&[
+            chunk(1, 10, "fn b() {}"),
+            chunk(0, 20, "fn a() {}"),
+            chunk(0, 5, "   "),
+            chunk(0, 1, "fn c() {}"),
+        ]);
+
+        assert_eq!(selected, vec![3, 1, 0]);
+    }
+
+    #[test]
+    fn finds_identifiers() {
+        assert_eq!(
+            identifiers("Where is `parse` called on RepoRef in query::execute or ApiQuery?"),
+            ["parse", "RepoRef", "query::execute", "ApiQuery"]
+        );
+        assert_eq!(
+            identifiers("How do I add a new_field to Config and config_path?"),
+            ["new_field", "config_path"]
+        );
+        assert!(identifiers("What does this Repository do?").is_empty());
+    }
+
+    #[test]
+    fn fuses_ranks() {
+        let payload = |path: &str, start_line: u64| semantic::Payload {
+            relative_path: path.to_owned(),
+            start_line,
+            end_line: start_line + 10,
+            ..Default::default()
+        };
+
+        let fused = fuse(
+            vec![
+                vec![payload("a.rs", 0), payload("b.rs", 0), payload("c.rs", 0)],
+                vec![payload("c.rs", 5), payload("d.rs", 0)],
+            ],
+            3,
+        );
+
+        let paths = fused
+            .iter()
+            .map(|p| (p.relative_path.as_str(), p.start_line))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [("c.rs", 0), ("a.rs", 0), ("b.rs", 0)]);
+    }
+}
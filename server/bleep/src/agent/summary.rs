@@ -0,0 +1,167 @@
+//! Rolling summaries of long conversations.
+//!
+//! Once a conversation grows long enough, its older exchanges are compressed into a single summary
+//! that is stored alongside the conversation. Prompt histories then use this summary in place of
+//! the raw exchanges it covers, which keeps token use bounded regardless of the conversation
+//! length.
+
+use std::ops::Range;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use minijinja::context;
+
+use crate::{
+    agent::{exchange::Exchange, prompts, templates::PromptTemplates},
+    llm_gateway,
+};
+
+/// Summarize once this many exchanges are not covered by the current summary.
+const SUMMARIZE_AFTER: usize = 6;
+
+/// The number of most recent exchanges that are always kept verbatim.
+const KEEP_RECENT: usize = 2;
+
+const SUMMARY_MODEL: &str = "gpt-3.5-turbo-16k-0613";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub text: String,
+
+    /// The number of leading exchanges in the conversation this summary covers.
+    pub exchanges: usize,
+
+    /// The id of the last exchange this summary covers, which ties it to the branch it was
+    /// written for.
+    #[serde(default)]
+    pub last_exchange: Option<uuid::Uuid>,
+}
+
+impl Summary {
+    /// The message that stands in for the summarized exchanges in a prompt history.
+    pub fn message(&self) -> llm_gateway::api::Message {
+        llm_gateway::api::Message::system(&format!(
+            "Summary of the earlier conversation:\n{}",
+            self.text
+        ))
+    }
+
+    /// Whether this summary covers the leading exchanges of `branch`, rather than those of a
+    /// branch that left it earlier.
+    pub fn covers(&self, branch: &[Exchange]) -> bool {
+        self.exchanges
+            .checked_sub(1)
+            .and_then(|last| branch.get(last))
+            .is_some_and(|e| Some(e.id) == self.last_exchange)
+    }
+}
+
+/// The range of exchanges that should be folded into a new summary, if any.
+fn pending(previous: Option<&Summary>, exchanges: usize) -> Option<Range<usize>> {
+    let start = previous.map_or(0, |s| s.exchanges);
+
+    if exchanges.saturating_sub(start) < SUMMARIZE_AFTER {
+        return None;
+    }
+
+    Some(start..exchanges - KEEP_RECENT)
+}
+
+/// Fold older exchanges into a new summary, extending `previous`.
+///
+/// Returns `None` when there are not yet enough unsummarized exchanges.
+pub async fn summarize(
+    llm_gateway: &llm_gateway::Client,
+    templates: &PromptTemplates,
+    previous: Option<&Summary>,
+    exchanges: &[Exchange],
+) -> Result<Option<Summary>> {
+    let Some(range) = pending(previous, exchanges.len()) else {
+        return Ok(None);
+    };
+
+    let transcript = exchanges[range.clone()]
+        .iter()
+        .flat_map(|e| {
+            let query = e.query().map(|q| format!("User: {q}"));
+            let answer = e.answer().map(|(answer, _)| format!("Assistant: {answer}"));
+            query.into_iter().chain(answer)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let previous = previous.map(|s| s.text.as_str());
+    let prompt = templates.render_or(
+        "conversation_summary",
+        context! { previous, transcript },
+        || prompts::conversation_summary_prompt(previous, &transcript),
+    );
+
+    let text = llm_gateway
+        .clone()
+        .model(SUMMARY_MODEL)
+        .chat(&[llm_gateway::api::Message::system(&prompt)], None)
+        .await?
+        .try_collect::<String>()
+        .await?;
+
+    Ok(Some(Summary {
+        text,
+        exchanges: range.end,
+        last_exchange: Some(exchanges[range.end - 1].id),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending() {
+        assert_eq!(pending(None, 0), None);
+        assert_eq!(pending(None, SUMMARIZE_AFTER - 1), None);
+        assert_eq!(
+            pending(None, SUMMARIZE_AFTER),
+            Some(0..SUMMARIZE_AFTER - KEEP_RECENT)
+        );
+
+        let previous = Summary {
+            text: String::new(),
+            exchanges: 4,
+            last_exchange: None,
+        };
+
+        assert_eq!(pending(Some(&previous), 4 + SUMMARIZE_AFTER - 1), None);
+        assert_eq!(
+            pending(Some(&previous), 4 + SUMMARIZE_AFTER),
+            Some(4..4 + SUMMARIZE_AFTER - KEEP_RECENT)
+        );
+    }
+
+    #[test]
+    fn covers_its_own_branch() {
+        let exchange = |id: u128| {
+            let mut exchange = Exchange::default();
+            exchange.id = uuid::Uuid::from_u128(id);
+            exchange
+        };
+
+        let summary = Summary {
+            text: String::new(),
+            exchanges: 2,
+            last_exchange: Some(uuid::Uuid::from_u128(2)),
+        };
+
+        assert!(summary.covers(&[exchange(1), exchange(2)]));
+        assert!(summary.covers(&[exchange(1), exchange(2), exchange(3)]));
+        // A regenerated second answer starts another branch.
+        assert!(!summary.covers(&[exchange(1), exchange(4), exchange(5)]));
+        assert!(!summary.covers(&[exchange(1)]));
+
+        let unversioned = Summary {
+            last_exchange: None,
+            ..summary
+        };
+        assert!(!unversioned.covers(&[exchange(1), exchange(2)]));
+    }
+}
@@ -0,0 +1,239 @@
+//! Prompt templates that replace the built-in prompts.
+//!
+//! Each `<name>.jinja` file in `prompt_templates_dir` replaces the prompt of that name, and is
+//! rendered with [minijinja](https://docs.rs/minijinja). The directory is watched, so templates
+//! can be changed without a restart. A template that is missing, fails to parse, or fails to
+//! render falls back to the built-in prompt.
+//!
+//! The templates, and the variables they are rendered with, are:
+//!
+//! - `action_selection`: the system prompt of each agent step, with `repos`, `paths`, `deep`, and
+//!   the `rules` of the prompt rollout
+//! - `explain`: the prompt of the answer, with `context` and `aliases`
+//! - `explain_crash`: the prompt of the answer to a stack trace, with `context`, `aliases` and
+//!   `trace`
+//! - `file_explanation`: the prompt that picks relevant lines of a file, with `question`, `path`
+//!   and `code`
+//! - `hypothetical_document`: the prompt that writes code to search with, with `query`
+//! - `query_paraphrases`: the prompt that rewrites a code search, with `query` and the `count` of
+//!   rewrites
+//! - `disambiguate_symbol`: the prompt that picks one of several symbols, with `description` and
+//!   `candidates`
+//! - `conversation_summary`: the prompt of rolling conversation summaries, with `previous` and
+//!   `transcript`
+//! - `intro`: the reply to greetings and questions about the assistant, which is sent as is,
+//!   without calling the LLM, with the `name`, `description` and `examples` of the persona, and
+//!   `repos`
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use tracing::{error, info, warn};
+
+use crate::Configuration;
+
+const NAMES: &[&str] = &[
+    "action_selection",
+    "explain",
+    "explain_crash",
+    "explain_map_reduce",
+    "explain_snippet",
+    "file_explanation",
+    "hypothetical_document",
+    "query_paraphrases",
+    "disambiguate_symbol",
+    "conversation_summary",
+    "route_query",
+    "route_reply",
+    "intro",
+];
+
+const EXTENSION: &str = "jinja";
+
+type Sources = Arc<RwLock<HashMap<String, String>>>;
+
+#[derive(Default)]
+pub struct PromptTemplates {
+    sources: Sources,
+    /// Kept alive to reload templates when the directory changes.
+    _watcher: Option<Debouncer<RecommendedWatcher>>,
+}
+
+impl PromptTemplates {
+    pub fn load(config: &Configuration) -> Result<Self> {
+        let Some(dir) = config.prompt_templates_dir.clone() else {
+            return Ok(Self::default());
+        };
+
+        let sources = Sources::default();
+        *sources.write().unwrap() = read_dir(&dir)?;
+
+        let watcher = {
+            let (sources, dir) = (sources.clone(), dir.clone());
+            let mut debouncer = new_debouncer(
+                Duration::from_secs(1),
+                None,
+                move |event: DebounceEventResult| match event {
+                    Ok(_) => match read_dir(&dir) {
+                        Ok(templates) => *sources.write().unwrap() = templates,
+                        Err(err) => warn!(?err, "failed to reload prompt templates"),
+                    },
+                    Err(err) => error!(?err, "prompt template monitoring"),
+                },
+            )
+            .context("failed to watch prompt templates")?;
+
+            debouncer
+                .watcher()
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .context("failed to watch prompt templates")?;
+            debouncer
+        };
+
+        Ok(Self {
+            sources,
+            _watcher: Some(watcher),
+        })
+    }
+
+    /// Render the template `name`, or fall back to the built-in `default` prompt.
+    pub fn render_or(
+        &self,
+        name: &str,
+        context: minijinja::Value,
+        default: impl FnOnce() -> String,
+    ) -> String {
+        self.render(name, context).unwrap_or_else(default)
+    }
+
+    fn render(&self, name: &str, context: minijinja::Value) -> Option<String> {
+        let sources = self.sources.read().unwrap();
+        let source = sources.get(name)?;
+
+        match minijinja::Environment::new().render_str(source, context) {
+            Ok(prompt) => Some(prompt),
+            Err(err) => {
+                warn!(?err, name, "failed to render prompt template");
+                None
+            }
+        }
+    }
+}
+
+/// Read the templates in `dir` that have known names and parse.
+fn read_dir(dir: &Path) -> Result<HashMap<String, String>> {
+    let mut templates = HashMap::new();
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read prompt templates in {}", dir.display()))?;
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if let Some((name, source)) = read_template(&path) {
+            templates.insert(name, source);
+        }
+    }
+
+    info!(
+        names = ?templates.keys().collect::<Vec<_>>(),
+        "loaded prompt templates"
+    );
+    Ok(templates)
+}
+
+fn read_template(path: &Path) -> Option<(String, String)> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+
+    let name = path.file_stem()?.to_str()?;
+    if !NAMES.contains(&name) {
+        warn!(path = %path.display(), "ignoring prompt template with an unknown name");
+        return None;
+    }
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            warn!(?err, path = %path.display(), "failed to read prompt template");
+            return None;
+        }
+    };
+
+    if let Err(err) = minijinja::Environment::new().template_from_str(&source) {
+        warn!(?err, path = %path.display(), "ignoring prompt template that does not parse");
+        return None;
+    }
+
+    Some((name.to_owned(), source))
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::context;
+
+    use super::*;
+
+    fn templates(sources: &[(&str, &str)]) -> PromptTemplates {
+        let templates = PromptTemplates::default();
+        *templates.sources.write().unwrap() = sources
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect();
+        templates
+    }
+
+    #[test]
+    fn renders_or_falls_back() {
+        let templates = templates(&[
+            (
+                "explain",
+                "{{ context }}\nAnswer with aliases {{ aliases|join(', ') }}",
+            ),
+            ("hypothetical_document", "{{ missing.field }}"),
+        ]);
+
+        let builtin = || "built-in".to_owned();
+
+        assert_eq!(
+            templates.render_or(
+                "explain",
+                context! { context => "fn main() {}", aliases => [1, 2] },
+                builtin
+            ),
+            "fn main() {}\nAnswer with aliases 1, 2"
+        );
+        assert_eq!(
+            templates.render_or("file_explanation", context! {}, builtin),
+            "built-in"
+        );
+        assert_eq!(
+            templates.render_or("hypothetical_document", context! {}, builtin),
+            "built-in"
+        );
+    }
+
+    #[test]
+    fn reads_known_templates() {
+        let dir = tempdir::TempDir::new("prompt-templates").unwrap();
+        let write = |name: &str, source: &str| std::fs::write(dir.path().join(name), source);
+
+        write("explain.jinja", "{{ context }}").unwrap();
+        write("unknown.jinja", "{{ context }}").unwrap();
+        write("file_explanation.jinja", "{% if %}").unwrap();
+        write("action_selection.txt", "{{ rules }}").unwrap();
+
+        let templates = read_dir(dir.path()).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates["explain"], "{{ context }}");
+    }
+}
@@ -1,26 +1,87 @@
-use std::{collections::HashMap, mem, ops::Range, pin::pin};
+use std::{collections::HashMap, mem, ops::Range, sync::Arc, time::Duration};
 
-use anyhow::{anyhow, Context, Result};
-use futures::StreamExt;
+use anyhow::{Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use lazy_regex::regex;
+use minijinja::context;
 use rand::{rngs::OsRng, seq::SliceRandom};
-use tracing::{debug, info, instrument, trace};
+use tracing::{debug, info, instrument, trace, warn};
 
 use crate::{
     agent::{
-        exchange::{CodeChunk, FocusedChunk, Update},
-        prompts, transcoder, Agent, ANSWER_MODEL,
+        diff,
+        exchange::{
+            Alternative, Citation, CodeChunk, FocusedChunk, Outcome, SuggestedEdit, Update,
+        },
+        map_reduce::{self, AnswerMode},
+        prompt_budget::PromptBudget,
+        prompts,
+        summary::Summary,
+        tools::stack_trace,
+        transcoder, Agent, StageTimeout, ANSWER_MODEL,
     },
     analytics::EventData,
     llm_gateway,
+    policy::{Scope, Verdict},
+    repo::license,
+    snippet,
 };
 
+/// The temperatures that alternative answers are generated at, in order.
+const ALTERNATIVE_TEMPERATURES: [f32; 2] = [0.7, 1.0];
+
 impl Agent {
     #[instrument(skip(self))]
     pub async fn answer(&mut self, aliases: &[usize]) -> Result<()> {
+        match self.try_answer(aliases).await {
+            // We assumed the wrong context size. The provider rejects the prompt before anything
+            // is streamed, so we can retry once with a prompt that fits.
+            Err(err)
+                if self.last_exchange().answer.is_none()
+                    && self.app.context_windows.learn(ANSWER_MODEL, &err) =>
+            {
+                self.try_answer(aliases).await
+            }
+            result => result,
+        }
+    }
+
+    /// The instructions of the answer, which explain a crash when there is a stack `trace`, and
+    /// combine the `explanations` of snippets when there are some.
+    fn answer_prompt(
+        &self,
+        aliases: &[usize],
+        context: &str,
+        trace: Option<&str>,
+        explanations: Option<&str>,
+    ) -> String {
+        let templates = &self.app.prompt_templates;
+        match (trace, explanations) {
+            (Some(trace), _) => templates.render_or(
+                "explain_crash",
+                context! { aliases, context, trace },
+                || prompts::explain_crash_prompt(aliases, context, trace),
+            ),
+            (None, Some(explanations)) => templates.render_or(
+                "explain_map_reduce",
+                context! { aliases, context, explanations },
+                || prompts::map_reduce_prompt(aliases, context, explanations),
+            ),
+            (None, None) => templates.render_or("explain", context! { aliases, context }, || {
+                prompts::answer_article_prompt(aliases, context)
+            }),
+        }
+    }
+
+    async fn try_answer(&mut self, aliases: &[usize]) -> Result<()> {
         const ANSWER_HEADROOM: usize = 1024; // the number of tokens reserved for the answer
 
         debug!("creating article response");
 
+        if aliases.is_empty() && self.exchanges.iter().all(|e| e.code_chunks.is_empty()) {
+            return self.no_answer().await;
+        }
+
         if aliases.len() == 1 {
             let path = self
                 .paths()
@@ -40,34 +101,115 @@ impl Agent {
             .await?;
         }
 
-        let context = self.answer_context(aliases, ANSWER_MODEL).await?;
-        let system_prompt = prompts::answer_article_prompt(aliases, &context);
-        let system_message = llm_gateway::api::Message::system(&system_prompt);
-        let history = {
-            let h = self.utter_history().collect::<Vec<_>>();
-            let system_headroom =
-                tiktoken_rs::num_tokens_from_messages(ANSWER_MODEL, &[(&system_message).into()])?;
-            trim_utter_history(h, ANSWER_HEADROOM + system_headroom)?
+        let mut budget = PromptBudget::new(
+            ANSWER_MODEL,
+            self.app.context_windows.size(ANSWER_MODEL),
+            ANSWER_HEADROOM,
+        )?;
+
+        // The last message is the query being answered.
+        let mut history = self.utter_history().collect::<Vec<_>>();
+        let query = history.pop();
+        if let Some(query) = &query {
+            budget.require(query)?;
+        }
+
+        let trace = self
+            .last_exchange()
+            .prompt()
+            .as_deref()
+            .and_then(stack_trace::parse)
+            .map(|trace| trace.to_string());
+
+        // Crashes are explained from the frames of the stack trace, so they are never answered in
+        // two steps.
+        let map_reduce = self.app.config.answer_mode == AnswerMode::MapReduce && trace.is_none();
+
+        // The instructions are sent in full, except for the stack trace, whose outermost frames
+        // are left out if they don't fit.
+        let instructions = self.answer_prompt(
+            aliases,
+            "",
+            trace.as_ref().map(|_| ""),
+            map_reduce.then_some(""),
+        );
+        budget.require(&llm_gateway::api::Message::system(&instructions))?;
+        let trace = trace.map(|trace| budget.take(&trace).to_owned());
+
+        let reserved = if map_reduce {
+            budget.reserve(map_reduce::RESERVED_TOKENS)
+        } else {
+            0
+        };
+        let (context, context_chunks) = self
+            .answer_context(aliases, ANSWER_MODEL, &mut budget)
+            .await?;
+        budget.release(reserved);
+
+        let Some(context) = self.redact_secrets(&context).await? else {
+            return Ok(());
+        };
+
+        let chunks = map_reduce::map_chunks(&context_chunks);
+        let explanations = if map_reduce && !chunks.is_empty() {
+            let query = self.last_exchange().query().unwrap_or_default();
+            let explanations = self.explain_snippets(&query, &chunks).await?;
+            Some(budget.take(&explanations).to_owned()).filter(|e| !e.is_empty())
+        } else {
+            None
         };
+
+        let system_prompt =
+            self.answer_prompt(aliases, &context, trace.as_deref(), explanations.as_deref());
+        let system_message = llm_gateway::api::Message::system(&system_prompt);
+        let history = budget
+            .history(history)?
+            .into_iter()
+            .chain(query)
+            .collect::<Vec<_>>();
         let messages = Some(system_message)
             .into_iter()
             .chain(history.iter().cloned())
             .collect::<Vec<_>>();
 
-        let mut stream = pin!(
-            self.llm_gateway
-                .clone()
-                .model(ANSWER_MODEL)
-                .chat(&messages, None)
-                .await?
-        );
+        // Cloned so that the stream does not borrow `self`, which we update below.
+        let explainer = Arc::clone(&self.stages.explainer);
+        let mut stream = self
+            .stage("explanation", explainer.explain(&messages))
+            .await?;
+
+        // A stream that stops sending tokens is generated again once, from the same prompt.
+        let stall = Duration::from_secs(self.app.config.answer_stall_timeout_secs);
+        let mut retried = false;
 
         let mut response = String::new();
-        while let Some(fragment) = stream.next().await {
-            let fragment = fragment?;
+        loop {
+            let fragment = match tokio::time::timeout(stall, stream.next()).await {
+                Ok(Some(fragment)) => fragment?,
+                Ok(None) => break,
+                Err(_) if !retried => {
+                    warn!(?stall, "answer stream stalled, generating it again");
+                    retried = true;
+                    response.clear();
+                    stream = self
+                        .stage("explanation", explainer.explain(&messages))
+                        .await?;
+                    continue;
+                }
+                Err(_) => {
+                    return Err(StageTimeout {
+                        stage: "explanation",
+                        duration: stall,
+                    }
+                    .into())
+                }
+            };
             response += &fragment;
 
             let (article, summary) = transcoder::decode(&response);
+            let Some(article) = self.enforce_policy(Scope::Answer, &article, false).await? else {
+                return Ok(());
+            };
             self.update(Update::Article(article)).await?;
 
             if let Some(summary) = summary {
@@ -78,6 +220,12 @@ impl Agent {
         // We re-decode one final time to catch cases where `summary` is `None`, and to log the
         // output as a trace.
         let (article, summary) = transcoder::decode(&response);
+        let Some(article) = self.enforce_policy(Scope::Answer, &article, true).await? else {
+            return Ok(());
+        };
+        let article = self.annotate_licenses(article).await;
+        self.update(Update::Article(article.clone())).await?;
+
         let summary = summary.unwrap_or_else(|| {
             [
                 "I hope that was useful, can I help with anything else?",
@@ -92,6 +240,28 @@ impl Agent {
 
         trace!(%article, "generated answer");
 
+        let suggested_edits = self
+            .validate_edits(transcoder::suggested_edits(&response))
+            .await;
+        if !suggested_edits.is_empty() {
+            self.update(Update::SuggestEdits(suggested_edits)).await?;
+        }
+
+        let repo = self.repo_ref.to_string();
+        let citations = context_chunks
+            .iter()
+            .map(|chunk| Citation::new(&repo, chunk))
+            .collect::<Vec<_>>();
+        self.update(Update::Cite(citations.clone())).await?;
+
+        for &temperature in ALTERNATIVE_TEMPERATURES.iter().take(self.alternatives) {
+            match self.alternative(&messages, temperature, &citations).await {
+                Ok(Some(alternative)) => self.update(Update::Alternative(alternative)).await?,
+                Ok(None) => {}
+                Err(err) => warn!(?err, temperature, "failed to generate alternative answer"),
+            }
+        }
+
         self.update(Update::Conclude(summary)).await?;
 
         self.track_query(
@@ -105,20 +275,134 @@ impl Agent {
         Ok(())
     }
 
-    #[instrument(skip(self))]
-    async fn answer_context(&mut self, aliases: &[usize], gpt_model: &str) -> Result<String> {
-        let paths = self.paths().collect::<Vec<_>>();
+    /// Answer again from the same `messages` at a higher `temperature`, citing those of the
+    /// answer's `citations` whose files the alternative mentions.
+    ///
+    /// Alternatives that a policy blocks are left out, rather than blocking the exchange.
+    async fn alternative(
+        &self,
+        messages: &[llm_gateway::api::Message],
+        temperature: f32,
+        citations: &[Citation],
+    ) -> Result<Option<Alternative>> {
+        let response = self
+            .llm_gateway
+            .clone()
+            .model(ANSWER_MODEL)
+            .temperature(temperature)
+            .chat(messages, None)
+            .await?
+            .try_collect::<String>()
+            .await?;
 
-        let mut s = "".to_owned();
+        let (article, _) = transcoder::decode(&response);
+        let article = match self.app.policy.check(Scope::Answer, &article) {
+            Verdict::Allow => article,
+            Verdict::Rewrite { text, .. } => text,
+            Verdict::Block { rule, .. } => {
+                self.audit_policy(Scope::Answer, &rule, "block", &article).await;
+                return Ok(None);
+            }
+        };
+        let article = self.annotate_licenses(article).await;
 
-        let mut aliases = aliases
+        let citations = citations
             .iter()
-            .copied()
-            .filter(|alias| *alias < paths.len())
-            .collect::<Vec<_>>();
+            .filter(|c| article.contains(&c.path))
+            .cloned()
+            .collect();
 
-        aliases.sort();
-        aliases.dedup();
+        Ok(Some(Alternative { article, citations }))
+    }
+
+    /// Keep only the suggested edits that apply cleanly against the current file contents.
+    async fn validate_edits(&self, edits: Vec<SuggestedEdit>) -> Vec<SuggestedEdit> {
+        let mut valid = Vec::new();
+
+        for edit in edits {
+            let applies = match self.get_file_content(&edit.path).await {
+                Ok(Some(doc)) => diff::apply(&doc.content, &edit.diff)
+                    .map_err(|err| debug!(?err, path = %edit.path, "suggested edit does not apply"))
+                    .is_ok(),
+                _ => false,
+            };
+
+            if applies {
+                valid.push(edit);
+            }
+        }
+
+        valid
+    }
+
+    /// Append a note to the article for every quoted file under a copyleft license, naming the
+    /// license.
+    async fn annotate_licenses(&self, mut article: String) -> String {
+        let mut notes = Vec::new();
+
+        for path in quoted_paths(&article) {
+            let license = match self.get_file_content(path).await {
+                Ok(Some(doc)) => doc.license,
+                _ => None,
+            };
+
+            if let Some(license) = license.filter(|l| license::is_copyleft(l)) {
+                notes.push(format!(
+                    "> Code quoted from `{path}` is licensed under `{license}`."
+                ));
+            }
+        }
+
+        if !notes.is_empty() {
+            article += "\n\n";
+            article += &notes.join("\n>\n");
+        }
+
+        article
+    }
+
+    /// Conclude the exchange with a structured "no answer" outcome, as nothing relevant was found
+    /// to base an answer on.
+    async fn no_answer(&mut self) -> Result<()> {
+        let suggestions = vec![
+            "Search across more repositories, or ask a broader question".to_owned(),
+            format!(
+                "Check that {} is indexed and up to date",
+                self.repo_ref.display_name()
+            ),
+        ];
+
+        self.update(Update::Outcome(Outcome::NoAnswer {
+            message: prompts::no_answer().to_owned(),
+            suggestions,
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::output_stage("no_answer")
+                .with_payload("query", self.last_exchange().query()),
+        );
+
+        Ok(())
+    }
+
+    /// The paths and code chunks to answer with, as many as fit into `budget`, along with the
+    /// chunks that made it into the context.
+    ///
+    /// The most recent chunks are picked first, and the first chunk that doesn't fit is cut short
+    /// if some of its lines do.
+    #[instrument(skip(self, budget))]
+    async fn answer_context(
+        &mut self,
+        aliases: &[usize],
+        gpt_model: &str,
+        budget: &mut PromptBudget,
+    ) -> Result<(String, Vec<CodeChunk>)> {
+        let paths = self.paths().collect::<Vec<_>>();
+
+        let mut s = "".to_owned();
+
+        let aliases = context_aliases(aliases, &paths, |path| self.in_scope(path));
 
         debug!(?paths, ?aliases, "created filtered path alias list");
 
@@ -131,15 +415,9 @@ impl Agent {
             }
         }
 
+        let mut s = budget.take(&s).to_owned();
         let code_chunks = self.canonicalize_code_chunks(&aliases, gpt_model).await;
 
-        // Sometimes, there are just too many code chunks in the context, and deduplication still
-        // doesn't trim enough chunks. So, we enforce a hard limit here that stops adding tokens
-        // early if we reach a heuristic limit.
-        const PROMPT_HEADROOM: usize = 2500;
-        let bpe = tiktoken_rs::get_bpe_from_model(gpt_model)?;
-        let mut remaining_prompt_tokens = tiktoken_rs::get_completion_max_tokens(gpt_model, &s)?;
-
         // Select as many recent chunks as possible
         let mut recent_chunks = Vec::new();
         for chunk in code_chunks.iter().rev() {
@@ -151,18 +429,27 @@ impl Agent {
                 .collect::<String>();
 
             let formatted_snippet = format!("### {} ###\n{snippet}\n\n", chunk.path);
+            let fitted = budget.truncate(&formatted_snippet);
+
+            if fitted.len() < formatted_snippet.len() {
+                // Keep the header and at least one line.
+                let fitted_lines = fitted.lines().count().saturating_sub(1);
+                if fitted_lines > 0 {
+                    budget.spend(fitted);
+                    let chunk = CodeChunk {
+                        end_line: chunk.end_line.min(chunk.start_line + fitted_lines),
+                        ..chunk.clone()
+                    };
+                    recent_chunks.push((chunk, format!("{fitted}\n")));
+                }
 
-            let snippet_tokens = bpe.encode_ordinary(&formatted_snippet).len();
-
-            if snippet_tokens >= remaining_prompt_tokens - PROMPT_HEADROOM {
-                info!("breaking at {} tokens", remaining_prompt_tokens);
+                info!("breaking at {} tokens", budget.remaining());
                 break;
             }
 
+            budget.spend(&formatted_snippet);
             recent_chunks.push((chunk.clone(), formatted_snippet));
-
-            remaining_prompt_tokens -= snippet_tokens;
-            debug!("{}", remaining_prompt_tokens);
+            debug!("{}", budget.remaining());
         }
 
         // group recent chunks by path alias
@@ -183,28 +470,35 @@ impl Agent {
         let mut aliases = recent_chunks_by_alias.keys().copied().collect::<Vec<_>>();
         aliases.sort();
 
+        let mut context_chunks = Vec::new();
         for alias in aliases {
-            let chunks = recent_chunks_by_alias.get_mut(&alias).unwrap();
+            let mut chunks = recent_chunks_by_alias.remove(&alias).unwrap();
             chunks.sort_by(|a, b| a.0.start_line.cmp(&b.0.start_line));
-            for (_, formatted_snippet) in chunks {
-                s += formatted_snippet;
+            for (chunk, formatted_snippet) in chunks {
+                s += &formatted_snippet;
+                context_chunks.push(chunk);
             }
         }
 
-        Ok(s)
+        Ok((s, context_chunks))
     }
 
     /// History of `user`, `assistant` messages. These are the messages that are shown to the user.
-    fn utter_history(&self) -> impl Iterator<Item = llm_gateway::api::Message> + '_ {
+    pub(super) fn utter_history(&self) -> impl Iterator<Item = llm_gateway::api::Message> + '_ {
         const ANSWER_MAX_HISTORY_SIZE: usize = 5;
 
-        self.exchanges
+        let summarized = self.summary.as_ref().map_or(0, |s| s.exchanges);
+        let summary = self.summary.iter().map(Summary::message);
+
+        let history = self
+            .exchanges
             .iter()
+            .skip(summarized)
             .rev()
             .take(ANSWER_MAX_HISTORY_SIZE)
             .rev()
             .flat_map(|e| {
-                let query = e.query().map(|q| llm_gateway::api::Message::PlainText {
+                let query = e.prompt().map(|q| llm_gateway::api::Message::PlainText {
                     role: "user".to_owned(),
                     content: q,
                 });
@@ -224,7 +518,9 @@ impl Agent {
                     .into_iter()
                     .chain(conclusion.into_iter())
                     .collect::<Vec<_>>()
-            })
+            });
+
+        summary.chain(history)
     }
 
     fn code_chunks(&self) -> impl Iterator<Item = CodeChunk> + '_ {
@@ -247,7 +543,7 @@ impl Agent {
         const CONTEXT_CODE_RATIO: f32 = 0.5;
 
         let bpe = tiktoken_rs::get_bpe_from_model(gpt_model).unwrap();
-        let context_size = tiktoken_rs::model::get_context_size(gpt_model);
+        let context_size = self.app.context_windows.size(gpt_model);
         let max_tokens = (context_size as f32 * CONTEXT_CODE_RATIO) as usize;
 
         // Note: The end line number here is *not* inclusive.
@@ -262,25 +558,44 @@ impl Agent {
         debug!(?spans_by_path, "expanding spans");
 
         let self_ = &*self;
-        // Map of path -> line list
-        let lines_by_file = futures::stream::iter(&mut spans_by_path)
-            .then(|(path, spans)| async move {
-                spans.sort_by_key(|c| c.start);
-
-                let lines = self_
-                    .get_file_content(path)
-                    .await
-                    .unwrap()
-                    .unwrap_or_else(|| panic!("path did not exist in the index: {path}"))
-                    .content
-                    .lines()
-                    .map(str::to_owned)
-                    .collect::<Vec<_>>();
-
-                (path.clone(), lines)
-            })
-            .collect::<HashMap<_, _>>()
-            .await;
+        // Maps of path -> line list, and path -> scope line ranges
+        let (lines_by_file, scopes_by_file): (HashMap<_, _>, HashMap<_, _>) =
+            futures::stream::iter(&mut spans_by_path)
+                .filter_map(|(path, spans)| async move {
+                    spans.sort_by_key(|c| c.start);
+
+                    // Files can be removed from the index, or kept from the LLM, after their code
+                    // was found.
+                    let doc = match self_.get_file_content(path).await {
+                        Ok(Some(doc)) => doc,
+                        Ok(None) => {
+                            warn!(path, "leaving out code that can no longer be read");
+                            return None;
+                        }
+                        Err(err) => {
+                            warn!(?err, path, "failed to read code, leaving it out");
+                            return None;
+                        }
+                    };
+
+                    let lines = doc.content.lines().map(str::to_owned).collect::<Vec<_>>();
+                    let scopes = doc
+                        .symbol_locations
+                        .scope_graph()
+                        .map(|graph| {
+                            graph
+                                .scope_ranges()
+                                .map(|r| r.start.line..r.end.line + 1)
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+
+                    Some(((path.clone(), lines), (path.clone(), scopes)))
+                })
+                .unzip()
+                .await;
+
+        spans_by_path.retain(|path, _| lines_by_file.contains_key(path));
 
         // Total number of lines to try and expand by, per loop iteration.
         const TOTAL_LINE_INC: usize = 100;
@@ -292,13 +607,15 @@ impl Agent {
         while !spans_by_path.is_empty() && changed {
             changed = false;
 
-            let tokens = spans_by_path
+            let count_tokens = |path: &str, span: Range<usize>| {
+                let snippet = lines_by_file.get(path).unwrap()[span].join("\n");
+                bpe.encode_ordinary(&snippet).len()
+            };
+
+            let mut tokens = spans_by_path
                 .iter()
                 .flat_map(|(path, spans)| spans.iter().map(move |s| (path, s)))
-                .map(|(path, span)| {
-                    let snippet = lines_by_file.get(path).unwrap()[span.clone()].join("\n");
-                    bpe.encode_ordinary(&snippet).len()
-                })
+                .map(|(path, span)| count_tokens(path, span.clone()))
                 .sum::<usize>();
 
             // First, we grow the spans if possible.
@@ -321,11 +638,31 @@ impl Agent {
 
                     let old_span = span.clone();
 
-                    span.start = span.start.saturating_sub(range_step);
-
-                    // Expand the end line forwards, capping at the total number of lines.
-                    span.end += range_step;
-                    span.end = span.end.min(file_lines);
+                    // Spans first grow to the function or class they are in, so that they don't
+                    // cut it in half, as long as it fits in the budget. Otherwise, they grow by
+                    // lines within it, or by whole items outside of any, and only by lines in
+                    // languages without a scope graph.
+                    let scope = snippet::enclosing_scope(old_span.clone(), &scopes_by_file[path])
+                        .filter(|scope| scope.end <= file_lines)
+                        .map(|scope| {
+                            let added = count_tokens(path, scope.clone())
+                                .saturating_sub(count_tokens(path, old_span.clone()));
+                            (scope, added)
+                        })
+                        .filter(|(_, added)| tokens + added <= max_tokens);
+
+                    *span = match scope {
+                        Some((scope, added)) => {
+                            tokens += added;
+                            scope
+                        }
+                        None => snippet::grow_within_scopes(
+                            old_span.clone(),
+                            range_step,
+                            &scopes_by_file[path],
+                            file_lines,
+                        ),
+                    };
 
                     if *span != old_span {
                         trace!(?path, "growing span");
@@ -358,11 +695,23 @@ impl Agent {
 
         debug!(?spans_by_path, "expanded spans");
 
+        // Grown spans take the best score of the chunks they contain.
+        let scores = self
+            .code_chunks()
+            .filter(|c| aliases.contains(&c.alias))
+            .filter_map(|c| Some((c.path, c.start_line, c.score?)))
+            .collect::<Vec<_>>();
+
         spans_by_path
             .into_iter()
             .flat_map(|(path, spans)| spans.into_iter().map(move |s| (path.clone(), s)))
             .map(|(path, span)| {
                 let snippet = lines_by_file.get(&path).unwrap()[span.clone()].join("\n");
+                let score = scores
+                    .iter()
+                    .filter(|(p, start, _)| *p == path && span.contains(start))
+                    .map(|(_, _, score)| *score)
+                    .reduce(f32::max);
 
                 CodeChunk {
                     alias: self.get_path_alias(&path),
@@ -370,31 +719,27 @@ impl Agent {
                     snippet,
                     start_line: span.start,
                     end_line: span.end,
+                    score,
                 }
             })
             .collect()
     }
 }
 
-// headroom refers to the amount of space reserved for the rest of the prompt
-fn trim_utter_history(
-    mut history: Vec<llm_gateway::api::Message>,
-    headroom: usize,
-) -> Result<Vec<llm_gateway::api::Message>> {
-    let mut tiktoken_msgs: Vec<tiktoken_rs::ChatCompletionRequestMessage> =
-        history.iter().map(|m| m.into()).collect::<Vec<_>>();
-
-    // remove the earliest messages, one by one, until we can accommodate into prompt
-    while tiktoken_rs::get_chat_completion_max_tokens(ANSWER_MODEL, &tiktoken_msgs)? < headroom {
-        if !tiktoken_msgs.is_empty() {
-            tiktoken_msgs.remove(0);
-            history.remove(0);
-        } else {
-            return Err(anyhow!("could not find message to trim"));
+/// The distinct paths of the code quoted in a decoded article, in order of appearance.
+fn quoted_paths(article: &str) -> Vec<&str> {
+    let mut paths = Vec::new();
+
+    for captures in
+        regex!(r"^```type:Quoted,lang:[^,]*,path:(.+),lines:\d+-\d+$"m).captures_iter(article)
+    {
+        let path = captures.get(1).unwrap().as_str();
+        if !paths.contains(&path) {
+            paths.push(path);
         }
     }
 
-    Ok(history)
+    paths
 }
 
 /// Merge line ranges if they overlap.
@@ -414,41 +759,57 @@ fn merge_overlapping(a: &mut Range<usize>, b: Range<usize>) -> Option<Range<usiz
     }
 }
 
+/// The aliases of `paths` to answer from, sorted and without duplicates.
+///
+/// A scoped follow-up can have paths from earlier in the conversation that are out of its scope,
+/// whose code can't be read, so those are left out.
+fn context_aliases(
+    aliases: &[usize],
+    paths: &[&str],
+    in_scope: impl Fn(&str) -> bool,
+) -> Vec<usize> {
+    let mut aliases = aliases
+        .iter()
+        .copied()
+        .filter(|&alias| paths.get(alias).is_some_and(|path| in_scope(path)))
+        .collect::<Vec<_>>();
+
+    aliases.sort();
+    aliases.dedup();
+    aliases
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_trimming_utter_history() {
-        let long_string = "long string ".repeat(2000);
-        let history = vec![
-            llm_gateway::api::Message::user("bar"),
-            llm_gateway::api::Message::assistant("baz"),
-            llm_gateway::api::Message::user(&long_string),
-            llm_gateway::api::Message::assistant("quux"),
-            llm_gateway::api::Message::user("fred"),
-            llm_gateway::api::Message::assistant("thud"),
-            llm_gateway::api::Message::user(&long_string),
-            llm_gateway::api::Message::user("corge"),
+    fn scoped_follow_ups_leave_out_paths_outside_the_scope() {
+        // The first question was unscoped, and the follow-up is scoped to `server/bleep`.
+        let paths = [
+            "client/src/App.tsx",
+            "server/bleep/src/lib.rs",
+            "server/README.md",
         ];
+        let in_scope = |path: &str| crate::agent::is_within(path, "server/bleep");
 
-        // the answer needs 8100 tokens of 8192, the utter history can admit just one message
-        assert_eq!(
-            trim_utter_history(history.clone(), 8100).unwrap(),
-            vec![llm_gateway::api::Message::user("corge"),]
-        );
+        assert_eq!(context_aliases(&[2, 1, 0, 1, 7], &paths, in_scope), [1]);
+        assert_eq!(context_aliases(&[2, 1, 0], &paths, |_| true), [0, 1, 2]);
+    }
 
-        // the answer needs just 4000 tokens of 8192, the utter history can accomodate
-        // one long_string, but no more long_strings
-        assert_eq!(
-            trim_utter_history(history, 4000).unwrap(),
-            vec![
-                llm_gateway::api::Message::assistant("quux"),
-                llm_gateway::api::Message::user("fred"),
-                llm_gateway::api::Message::assistant("thud"),
-                llm_gateway::api::Message::user(&long_string),
-                llm_gateway::api::Message::user("corge"),
-            ]
-        );
+    #[test]
+    fn test_quoted_paths() {
+        let article = "Parsing happens here:\n\n\
+            ```type:Quoted,lang:Rust,path:src/query/parser.rs,lines:10-20\n\
+            fn parse() {}\n\
+            ```\n\n\
+            ```type:Generated,lang:Rust,path:,lines:0-0\n\
+            fn main() {}\n\
+            ```\n\n\
+            ```type:Quoted,lang:Rust,path:src/query/parser.rs,lines:30-40\n\
+            fn flatten() {}\n\
+            ```";
+
+        assert_eq!(quoted_paths(article), ["src/query/parser.rs"]);
     }
 }
@@ -0,0 +1,273 @@
+use std::path::Path;
+
+use anyhow::Result;
+use tracing::{debug, instrument};
+
+use crate::{
+    agent::{
+        exchange::{CodeChunk, Interpretation, Outcome, Update},
+        Agent,
+    },
+    analytics::EventData,
+    query::parser,
+};
+
+/// Results scoring within this margin of the best result are considered equally relevant.
+const SCORE_MARGIN: f32 = 0.02;
+
+/// The most interpretations offered to the user.
+const MAX_INTERPRETATIONS: usize = 3;
+
+/// A directory that equally relevant results were found in.
+#[derive(Debug, PartialEq)]
+struct Area {
+    dir: String,
+    /// Indices of the results in this area, in retrieval order.
+    results: Vec<usize>,
+}
+
+/// Group the results that score close to the best one by the directory they are in.
+///
+/// Nested directories are considered to be one area, under their common ancestor. This returns
+/// fewer than two areas if the question is not ambiguous.
+fn areas(results: &[(&str, f32)]) -> Vec<Area> {
+    let Some(top) = results.iter().map(|(_, score)| *score).reduce(f32::max) else {
+        return vec![];
+    };
+
+    let mut areas = Vec::<Area>::new();
+    for (i, (path, score)) in results.iter().enumerate() {
+        if *score < top - SCORE_MARGIN {
+            continue;
+        }
+
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let is_related = |area: &Area| {
+            let other = Path::new(&area.dir);
+            dir.starts_with(other) || other.starts_with(dir)
+        };
+
+        let Some(pos) = areas.iter().position(is_related) else {
+            areas.push(Area {
+                dir: dir.to_string_lossy().into_owned(),
+                results: vec![i],
+            });
+            continue;
+        };
+
+        // A result can relate areas that were unrelated so far, when it is in their common
+        // ancestor directory, so merge all of them into the first one.
+        let mut merged = Area {
+            dir: dir.to_string_lossy().into_owned(),
+            results: vec![i],
+        };
+        let mut j = pos;
+        while j < areas.len() {
+            if !is_related(&areas[j]) {
+                j += 1;
+                continue;
+            }
+
+            let area = areas.remove(j);
+            if area.dir.len() < merged.dir.len() {
+                merged.dir = area.dir;
+            }
+            merged.results.extend(area.results);
+        }
+
+        merged.results.sort_unstable();
+        areas.insert(pos, merged);
+    }
+
+    areas.truncate(MAX_INTERPRETATIONS);
+    areas
+}
+
+/// A short description of an area, like "`src/cli` (parser.rs, args.rs)".
+fn label(dir: &str, paths: &[String]) -> String {
+    let files = paths
+        .iter()
+        .filter_map(|p| Path::new(p).file_name())
+        .map(|f| f.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if dir.is_empty() {
+        format!("the repository root ({files})")
+    } else {
+        format!("`{dir}` ({files})")
+    }
+}
+
+impl Agent {
+    /// Ask the user which part of the codebase the question is about, if the most relevant code
+    /// is spread across unrelated areas with similar scores.
+    ///
+    /// If the user's context profile shows that they usually ask about one of the areas, the
+    /// question is scoped to that area instead.
+    ///
+    /// Returns `false` if the question is not ambiguous, or was scoped, in which case it should be
+    /// answered as usual.
+    #[instrument(skip(self))]
+    pub async fn clarify(&mut self, query: &str) -> Result<bool> {
+        const CLARIFY_SEARCH_LIMIT: u64 = 10;
+
+        let results = self
+            .semantic_search(query.into(), CLARIFY_SEARCH_LIMIT, 0, 0.0, true)
+            .await?;
+
+        let Some(scored) = results
+            .iter()
+            .map(|r| Some((r.relative_path.as_str(), r.score?)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(false);
+        };
+
+        let areas = areas(&scored);
+        debug!(?areas, "found areas of relevant code");
+
+        if areas.len() < 2 {
+            return Ok(false);
+        }
+
+        let dirs = areas.iter().map(|a| a.dir.as_str()).collect::<Vec<_>>();
+        let repo_ref = self.repo_ref.to_string();
+        if let Some(i) = self.profile().await.preferred(&repo_ref, &dirs) {
+            debug!(
+                dir = dirs[i],
+                "scoping ambiguous question to the user's usual area"
+            );
+            self.track_query(
+                EventData::output_stage("clarification_defaulted").with_payload("dir", dirs[i]),
+            );
+
+            if !dirs[i].is_empty() {
+                let scope = parser::Literal::Plain(dirs[i].to_owned().into());
+                self.last_exchange_mut().query.paths.insert(scope);
+            }
+
+            return Ok(false);
+        }
+
+        let candidates = areas
+            .iter()
+            .map(|area| {
+                let mut paths = Vec::<String>::new();
+                let code_chunks = area
+                    .results
+                    .iter()
+                    .map(|&i| {
+                        let result = &results[i];
+                        let alias = match paths.iter().position(|p| *p == result.relative_path) {
+                            Some(alias) => alias,
+                            None => {
+                                paths.push(result.relative_path.clone());
+                                paths.len() - 1
+                            }
+                        };
+
+                        CodeChunk {
+                            path: result.relative_path.clone(),
+                            alias,
+                            snippet: result.text.clone(),
+                            start_line: result.start_line as usize,
+                            end_line: result.end_line as usize,
+                            score: result.score,
+                        }
+                    })
+                    .collect();
+
+                Interpretation {
+                    label: label(&area.dir, &paths),
+                    paths,
+                    code_chunks,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let labels = candidates
+            .iter()
+            .map(|c| c.label.as_str())
+            .collect::<Vec<_>>();
+        let (last, rest) = labels.split_last().unwrap();
+        let message = format!(
+            "This question matches code in unrelated parts of the repository. Did you mean {} or {last}?",
+            rest.join(", "),
+        );
+
+        self.track_query(
+            EventData::output_stage("clarification_needed").with_payload("candidates", &labels),
+        );
+
+        self.update(Update::Outcome(Outcome::ClarificationNeeded {
+            message,
+            candidates,
+        }))
+        .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_unrelated_areas() {
+        let results = [
+            ("src/cli/parser.rs", 0.81),
+            ("src/webserver/query.rs", 0.80),
+            ("src/cli/args/mod.rs", 0.80),
+            ("src/db.rs", 0.5),
+        ];
+
+        assert_eq!(
+            areas(&results),
+            [
+                Area {
+                    dir: "src/cli".to_owned(),
+                    results: vec![0, 2],
+                },
+                Area {
+                    dir: "src/webserver".to_owned(),
+                    results: vec![1],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_nested_areas() {
+        let results = [
+            ("src/agent/tools/code.rs", 0.8),
+            ("src/agent.rs", 0.8),
+            ("src/webserver/answer.rs", 0.6),
+        ];
+
+        assert_eq!(
+            areas(&results),
+            [Area {
+                dir: "src".to_owned(),
+                results: vec![0, 1],
+            }]
+        );
+        assert!(areas(&[]).is_empty());
+    }
+
+    #[test]
+    fn labels_areas() {
+        assert_eq!(
+            label(
+                "src/cli",
+                &["src/cli/parser.rs".into(), "src/cli/args.rs".into()]
+            ),
+            "`src/cli` (parser.rs, args.rs)"
+        );
+        assert_eq!(
+            label("", &["build.rs".into()]),
+            "the repository root (build.rs)"
+        );
+    }
+}
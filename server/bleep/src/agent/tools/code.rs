@@ -1,11 +1,12 @@
 use anyhow::Result;
 use futures::TryStreamExt;
-use tracing::{info, instrument};
+use minijinja::context;
+use tracing::{info, instrument, warn};
 
 use crate::{
     agent::{
-        exchange::{CodeChunk, SearchStep, Update},
-        prompts, Agent,
+        exchange::{CodeChunk, RankedChunks, SearchStep, Update},
+        prompts, stages, Agent,
     },
     analytics::EventData,
     llm_gateway,
@@ -18,13 +19,39 @@ impl Agent {
         self.update(Update::StartStep(SearchStep::Code {
             query: query.clone(),
             response: String::new(),
+            ranking: RankedChunks::default(),
         }))
         .await?;
 
+        /// The most paraphrases of a query that are searched, as each is a search of its own.
+        const MAX_PARAPHRASES: usize = 3;
+
         let mut results = self
             .semantic_search(query.into(), CODE_SEARCH_LIMIT, 0, 0.0, true)
             .await?;
 
+        let count = self.app.config.query_paraphrases.min(MAX_PARAPHRASES);
+        let paraphrases = if count > 0 {
+            self.paraphrase(query, count).await.unwrap_or_else(|err| {
+                warn!(?err, "failed to paraphrase query");
+                vec![]
+            })
+        } else {
+            vec![]
+        };
+
+        if !paraphrases.is_empty() {
+            let mut lists = vec![results];
+            for paraphrase in &paraphrases {
+                let paraphrase_results = self
+                    .semantic_search(paraphrase.into(), CODE_SEARCH_LIMIT, 0, 0.0, true)
+                    .await?;
+                lists.push(paraphrase_results);
+            }
+
+            results = stages::fuse(lists, CODE_SEARCH_LIMIT as usize);
+        }
+
         let hyde_docs = self.hyde(query).await?;
         if !hyde_docs.is_empty() {
             let hyde_doc = hyde_docs.first().unwrap().into();
@@ -34,7 +61,7 @@ impl Agent {
             results.extend(hyde_results);
         }
 
-        let mut chunks = results
+        let chunks = results
             .into_iter()
             .map(|chunk| {
                 let relative_path = chunk.relative_path;
@@ -45,23 +72,26 @@ impl Agent {
                     snippet: chunk.text,
                     start_line: chunk.start_line as usize,
                     end_line: chunk.end_line as usize,
+                    score: chunk.score,
                 }
             })
             .collect::<Vec<_>>();
 
-        chunks.sort_by(|a, b| a.alias.cmp(&b.alias).then(a.start_line.cmp(&b.start_line)));
+        let selection = self.stages.selector.select(&chunks);
+        let ranking = RankedChunks::new(chunks, &selection);
+        let chunks = ranking.presented();
+        self.record_selection(&chunks).await;
 
-        for chunk in chunks.iter().filter(|c| !c.is_empty()) {
+        for chunk in chunks.iter() {
             self.exchanges
                 .last_mut()
                 .unwrap()
                 .code_chunks
-                .push(chunk.clone())
+                .push((*chunk).clone())
         }
 
         let response = chunks
             .iter()
-            .filter(|c| !c.is_empty())
             .map(|c| c.to_string())
             .collect::<Vec<_>>()
             .join("\n\n");
@@ -69,6 +99,7 @@ impl Agent {
         self.update(Update::ReplaceStep(SearchStep::Code {
             query: query.clone(),
             response: response.clone(),
+            ranking: ranking.clone(),
         }))
         .await?;
 
@@ -76,20 +107,51 @@ impl Agent {
             EventData::input_stage("semantic code search")
                 .with_payload("query", query)
                 .with_payload("hyde_queries", &hyde_docs)
-                .with_payload("chunks", &chunks)
+                .with_payload("paraphrases", &paraphrases)
+                .with_payload("chunks", &ranking)
                 .with_payload("raw_prompt", &response),
         );
 
         Ok(response)
     }
 
+    /// Rewrite a query in `count` ways, which are likely to be worded more like the code that
+    /// answers it.
+    async fn paraphrase(&self, query: &str, count: usize) -> Result<Vec<String>> {
+        let prompt = vec![llm_gateway::api::Message::system(
+            &self.app.prompt_templates.render_or(
+                "query_paraphrases",
+                context! { query, count },
+                || prompts::query_paraphrases_prompt(query, count),
+            ),
+        )];
+
+        let response = self
+            .llm_gateway
+            .clone()
+            .model("gpt-3.5-turbo-0613")
+            .chat(&prompt, None)
+            .await?
+            .try_collect::<String>()
+            .await?;
+
+        let paraphrases = prompts::try_parse_paraphrases(&response, count);
+        info!(?paraphrases, "paraphrased query");
+
+        Ok(paraphrases)
+    }
+
     /// Hypothetical Document Embedding (HyDE): https://arxiv.org/abs/2212.10496
     ///
     /// This method generates synthetic documents based on the query. These are then
     /// parsed and code is extracted. This has been shown to improve semantic search recall.
     async fn hyde(&self, query: &str) -> Result<Vec<String>> {
         let prompt = vec![llm_gateway::api::Message::system(
-            &prompts::hypothetical_document_prompt(query),
+            &self.app.prompt_templates.render_or(
+                "hypothetical_document",
+                context! { query },
+                || prompts::hypothetical_document_prompt(query),
+            ),
         )];
 
         tracing::trace!(?query, "generating hyde docs");
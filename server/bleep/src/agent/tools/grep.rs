@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    query::{
+        execute::{ApiQuery, QueryResult},
+        parser,
+    },
+    snippet::Snippet,
+};
+
+impl Agent {
+    /// Search the contents of the repository for a regex, and list the matching lines.
+    ///
+    /// Files with matches are added to the paths in context, so that they can be opened or
+    /// answered with.
+    #[instrument(skip(self))]
+    pub async fn grep(&mut self, pattern: &str) -> Result<String> {
+        const MAX_GREP_FILES: usize = 20;
+        const MAX_GREP_LINES: usize = 50;
+
+        self.update(Update::StartStep(SearchStep::Grep {
+            pattern: pattern.to_owned(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let query = parser::Query {
+            repo: Some(parser::Literal::Plain(self.repo_ref.display_name().into())),
+            branch: self
+                .last_exchange()
+                .query
+                .first_branch()
+                .map(|b| parser::Literal::Plain(b.into_owned().into())),
+            target: Some(parser::Target::Content(parser::Literal::Regex(
+                pattern.to_owned().into(),
+            ))),
+            ..Default::default()
+        };
+
+        let mut params = ApiQuery::new(pattern);
+        params.page_size = MAX_GREP_FILES;
+        params.calculate_totals = false;
+
+        let results = Arc::new(params)
+            .query_with(self.app.indexes.clone(), vec![query])
+            .await?;
+
+        let repo_ref = self.repo_ref.to_string();
+        let mut sections = vec![];
+        let mut remaining = MAX_GREP_LINES;
+
+        for file in results.data.into_iter().filter_map(|r| match r {
+            QueryResult::Snippets(file) if file.repo_ref == repo_ref => Some(file),
+            _ => None,
+        }) {
+            if remaining == 0
+                || self.is_license_excluded(file.license.as_deref())
+                || self.is_retrieval_only(&file.repo_ref)
+                || !self.in_scope(&file.relative_path)
+            {
+                continue;
+            }
+
+            let lines = file
+                .snippets
+                .iter()
+                .flat_map(matched_lines)
+                .take(remaining)
+                .map(|(n, line)| format!("{n} {line}"))
+                .collect::<Vec<_>>();
+
+            if lines.is_empty() {
+                continue;
+            }
+
+            remaining -= lines.len();
+            let alias = self.get_path_alias(&file.relative_path);
+            sections.push(format!(
+                "{alias}: {}\n{}",
+                file.relative_path,
+                lines.join("\n")
+            ));
+        }
+
+        let response = sections.join("\n\n");
+
+        self.update(Update::ReplaceStep(SearchStep::Grep {
+            pattern: pattern.to_owned(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("grep")
+                .with_payload("pattern", pattern)
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
+
+/// The lines of a snippet that contain a highlight, with their one-based line numbers.
+fn matched_lines(snippet: &Snippet) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+
+    snippet
+        .data
+        .split_inclusive('\n')
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let range = offset..offset + line.len();
+            offset = range.end;
+
+            snippet
+                .highlights
+                .iter()
+                .any(|h| h.start < range.end && range.start < h.end.max(h.start + 1))
+                .then(|| (snippet.line_range.start + i + 1, line.trim_end()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_lines() {
+        let snippet = Snippet {
+            data: "fn main() {\n    let key = 1;\n    key\n}\n".into(),
+            highlights: vec![20..23, 33..36],
+            symbols: vec![],
+            line_range: 9..13,
+        };
+
+        assert_eq!(
+            matched_lines(&snippet),
+            [(11, "    let key = 1;"), (12, "    key")]
+        );
+    }
+}
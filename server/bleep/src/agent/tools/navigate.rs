@@ -0,0 +1,236 @@
+use anyhow::Result;
+use lazy_regex::regex;
+use minijinja::context;
+use tracing::{debug, instrument};
+
+use crate::{
+    agent::{
+        exchange::{FocusedChunk, Outcome, Update},
+        prompts, Agent,
+    },
+    analytics::EventData,
+    llm_gateway,
+};
+
+/// An imperative request to open a piece of code, like "open the function that parses RepoRef".
+#[derive(Debug, PartialEq)]
+pub struct Command {
+    /// The symbol kinds the user asked for. An empty list matches any symbol.
+    pub kinds: &'static [&'static str],
+    /// The description of the code to open.
+    pub description: String,
+}
+
+#[derive(Debug)]
+struct Candidate {
+    name: String,
+    kind: String,
+    path: String,
+    start_line: usize,
+    end_line: usize,
+}
+
+/// Parse a navigation command out of a user query, if it is one.
+pub fn parse_command(query: &str) -> Option<Command> {
+    let captures = regex!(
+        r"(?i)^\s*(?:please\s+)?(?:open|go\s+to|goto|jump\s+to|navigate\s+to|take\s+me\s+to)\s+(?:the\s+)?(function|method|fn|class|struct|type|trait|interface|enum|definition|symbol)s?\b\s*(.*?)[\s.?!]*$"
+    )
+    .captures(query)?;
+
+    let kinds: &[&str] = match captures[1].to_ascii_lowercase().as_str() {
+        "function" | "method" | "fn" => &["function", "method"],
+        "class" | "struct" | "type" | "trait" | "interface" | "enum" => &[
+            "class",
+            "struct",
+            "enum",
+            "union",
+            "typedef",
+            "interface",
+            "trait",
+        ],
+        _ => &[],
+    };
+
+    let description = captures[2].trim();
+    if description.is_empty() {
+        return None;
+    }
+
+    Some(Command {
+        kinds,
+        description: description.to_owned(),
+    })
+}
+
+impl Agent {
+    /// Resolve a navigation command to a single symbol, and conclude the exchange with it.
+    ///
+    /// Returns `false` if no candidate symbol was found, in which case the query should be
+    /// answered as usual.
+    #[instrument(skip(self))]
+    pub async fn navigate(&mut self, command: &Command) -> Result<bool> {
+        const NAVIGATE_SEARCH_LIMIT: u64 = 10;
+        const MAX_CANDIDATES: usize = 20;
+
+        let chunks = self
+            .semantic_search(
+                command.description.as_str().into(),
+                NAVIGATE_SEARCH_LIMIT,
+                0,
+                0.0,
+                true,
+            )
+            .await?;
+
+        let mut candidates = Vec::<Candidate>::new();
+        for chunk in chunks {
+            let Some(doc) = self.get_file_content(&chunk.relative_path).await? else {
+                continue;
+            };
+
+            let (start, end) = (chunk.start_line as usize, chunk.end_line as usize);
+
+            let symbols = doc
+                .symbol_locations
+                .list()
+                .into_iter()
+                .filter(|s| command.kinds.is_empty() || command.kinds.contains(&s.kind.as_str()))
+                .filter(|s| (start..=end).contains(&s.range.start.line))
+                .filter_map(|s| {
+                    Some(Candidate {
+                        name: doc
+                            .content
+                            .get(s.range.start.byte..s.range.end.byte)?
+                            .to_owned(),
+                        kind: s.kind,
+                        path: chunk.relative_path.clone(),
+                        start_line: s.range.start.line,
+                        end_line: end,
+                    })
+                });
+
+            for candidate in symbols {
+                let exists = candidates
+                    .iter()
+                    .any(|c| c.path == candidate.path && c.start_line == candidate.start_line);
+
+                if !exists && candidates.len() < MAX_CANDIDATES {
+                    candidates.push(candidate);
+                }
+            }
+        }
+
+        debug!(?candidates, "found navigation candidates");
+
+        let candidate = match candidates.len() {
+            0 => return Ok(false),
+            1 => candidates.remove(0),
+            _ => {
+                let i = self.disambiguate(&command.description, &candidates).await?;
+                candidates.swap_remove(i)
+            }
+        };
+
+        self.update(Update::Focus(FocusedChunk {
+            file_path: candidate.path.clone(),
+            start_line: candidate.start_line,
+            end_line: candidate.end_line,
+        }))
+        .await?;
+
+        self.update(Update::Outcome(Outcome::Navigate {
+            message: format!(
+                "Opening [`{}`]({}#L{}-L{})",
+                candidate.name, candidate.path, candidate.start_line, candidate.end_line
+            ),
+            path: candidate.path.clone(),
+            start_line: candidate.start_line,
+            end_line: candidate.end_line,
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::output_stage("navigate")
+                .with_payload("description", &command.description)
+                .with_payload("path", &candidate.path)
+                .with_payload("symbol", &candidate.name),
+        );
+
+        Ok(true)
+    }
+
+    /// Ask the LLM to pick the candidate that best matches the description.
+    ///
+    /// Falls back to the first candidate, which is the most relevant semantic match, if the
+    /// response cannot be understood.
+    async fn disambiguate(&self, description: &str, candidates: &[Candidate]) -> Result<usize> {
+        let list = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{i}: {} `{}` in {}", c.kind, c.name, c.path))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let prompt = self.app.prompt_templates.render_or(
+            "disambiguate_symbol",
+            context! { description, candidates => list },
+            || prompts::disambiguate_symbol_prompt(description, &list),
+        );
+
+        let choice = self
+            .llm_gateway
+            .clone()
+            .model("gpt-3.5-turbo-0613")
+            .select(
+                &[llm_gateway::api::Message::system(&prompt)],
+                candidates.len(),
+            )
+            .await?;
+
+        Ok(choice.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("open the function that parses RepoRef"),
+            Some(Command {
+                kinds: &["function", "method"],
+                description: "that parses RepoRef".to_owned(),
+            })
+        );
+
+        assert_eq!(
+            parse_command("Go to the struct for semantic payloads?"),
+            Some(Command {
+                kinds: &[
+                    "class",
+                    "struct",
+                    "enum",
+                    "union",
+                    "typedef",
+                    "interface",
+                    "trait"
+                ],
+                description: "for semantic payloads".to_owned(),
+            })
+        );
+
+        assert_eq!(
+            parse_command("jump to definition of Agent::step"),
+            Some(Command {
+                kinds: &[],
+                description: "of Agent::step".to_owned(),
+            })
+        );
+
+        assert_eq!(parse_command("open the function"), None);
+        assert_eq!(parse_command("how do I open a file?"), None);
+        assert_eq!(parse_command("what does the function foo do?"), None);
+    }
+}
@@ -0,0 +1,113 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, Context, Result};
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{CodeChunk, SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+};
+
+impl Agent {
+    /// Read lines from a file in context, and add them to the context of the answer.
+    ///
+    /// `start` and `end` are one-based and inclusive, and default to the whole file. At most
+    /// `MAX_OPEN_LINES` lines are read.
+    #[instrument(skip(self))]
+    pub async fn open_file(
+        &mut self,
+        alias: usize,
+        start: Option<usize>,
+        end: Option<usize>,
+    ) -> Result<String> {
+        const MAX_OPEN_LINES: usize = 200;
+
+        let path = self
+            .paths()
+            .nth(alias)
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("invalid path alias {alias}"))?;
+
+        self.update(Update::StartStep(SearchStep::Open {
+            path: path.clone(),
+            start,
+            end,
+            response: String::new(),
+        }))
+        .await?;
+
+        let content = self
+            .get_file_content(&path)
+            .await?
+            .with_context(|| format!("path does not exist in the index: {path}"))?
+            .content;
+
+        let lines = content.lines().collect::<Vec<_>>();
+        let range = line_range(lines.len(), start, end, MAX_OPEN_LINES);
+
+        let response = if range.is_empty() {
+            String::new()
+        } else {
+            self.last_exchange_mut().code_chunks.push(CodeChunk {
+                path: path.clone(),
+                alias,
+                snippet: lines[range.clone()].join("\n"),
+                start_line: range.start,
+                end_line: range.end,
+                score: None,
+            });
+
+            let numbered = range
+                .clone()
+                .map(|i| format!("{} {}", i + 1, lines[i]))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!("{alias}: {path}\n{numbered}")
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Open {
+            path: path.clone(),
+            start,
+            end,
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("open file")
+                .with_payload("path", &path)
+                .with_payload("lines", [range.start, range.end])
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
+
+/// The zero-based, exclusive range of lines to read out of `len` lines, given a one-based and
+/// inclusive `start` and `end`.
+fn line_range(len: usize, start: Option<usize>, end: Option<usize>, max: usize) -> Range<usize> {
+    let start = start.unwrap_or(1).max(1) - 1;
+    let end = end.unwrap_or(len).min(len).min(start + max);
+
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(line_range(10, None, None, 200), 0..10);
+        assert_eq!(line_range(10, Some(3), Some(5), 200), 2..5);
+        assert_eq!(line_range(10, Some(0), Some(20), 200), 0..10);
+        assert_eq!(line_range(500, Some(101), None, 200), 100..300);
+        assert_eq!(line_range(10, Some(8), Some(4), 200), 7..7);
+        assert_eq!(line_range(10, Some(20), None, 200), 19..19);
+    }
+}
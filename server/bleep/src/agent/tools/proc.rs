@@ -1,11 +1,12 @@
 use anyhow::{anyhow, Context, Result};
 use futures::{stream, StreamExt, TryStreamExt};
+use minijinja::context;
 use tiktoken_rs::CoreBPE;
 use tracing::{debug, instrument};
 
 use crate::{
     agent::{
-        exchange::{CodeChunk, SearchStep, Update},
+        exchange::{CodeChunk, RankedChunks, SearchStep, Update},
         prompts, Agent,
     },
     analytics::EventData,
@@ -78,7 +79,11 @@ impl Agent {
                 // We store the lines separately, so that we can reference them later to trim
                 // this snippet by line number.
                 let contents = lines.join("\n");
-                let prompt = prompts::file_explanation(query, &path, &contents);
+                let prompt = self_.app.prompt_templates.render_or(
+                    "file_explanation",
+                    context! { question => query, path, code => contents },
+                    || prompts::file_explanation(query, &path, &contents),
+                );
 
                 debug!(?path, "calling chat API on file");
 
@@ -174,7 +179,7 @@ impl Agent {
             .collect::<Vec<_>>()
             .await;
 
-        let mut chunks = processed
+        let chunks = processed
             .into_iter()
             .flat_map(|(relevant_chunks, path)| {
                 let alias = self.get_path_alias(&path);
@@ -185,23 +190,26 @@ impl Agent {
                     snippet: c.code,
                     start_line: c.range.start,
                     end_line: c.range.end,
+                    score: None,
                 })
             })
             .collect::<Vec<_>>();
 
-        chunks.sort_by(|a, b| a.alias.cmp(&b.alias).then(a.start_line.cmp(&b.start_line)));
+        let selection = self.stages.selector.select(&chunks);
+        let ranking = RankedChunks::new(chunks, &selection);
+        let chunks = ranking.presented();
+        self.record_selection(&chunks).await;
 
-        for chunk in chunks.iter().filter(|c| !c.is_empty()) {
+        for chunk in chunks.iter() {
             self.exchanges
                 .last_mut()
                 .unwrap()
                 .code_chunks
-                .push(chunk.clone())
+                .push((*chunk).clone())
         }
 
         let response = chunks
             .iter()
-            .filter(|c| !c.is_empty())
             .map(|c| c.to_string())
             .collect::<Vec<_>>()
             .join("\n\n");
@@ -0,0 +1,245 @@
+use anyhow::Result;
+use futures::StreamExt;
+use minijinja::context;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    agent::{
+        classification,
+        exchange::{AnswerKind, Exchange, Update},
+        prompts, Agent,
+    },
+    analytics::EventData,
+    llm_gateway,
+    policy::Scope,
+};
+
+const ROUTE_MODEL: &str = "gpt-3.5-turbo-0613";
+
+/// The kind picked by the LLM, falling back to a code answer if the response cannot be
+/// understood.
+fn parse_kind(choice: Option<usize>) -> AnswerKind {
+    choice
+        .and_then(|i| AnswerKind::ALL.get(i))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Whether the last exchange is a follow-up in a conversation about the code, which makes it
+/// about the code too.
+fn follows_code(exchanges: &[Exchange]) -> bool {
+    exchanges
+        .iter()
+        .rev()
+        .nth(1)
+        .is_some_and(|previous| previous.answer_kind == AnswerKind::Code)
+}
+
+impl Agent {
+    /// Classify the query, and reply straight away if it is not about the code.
+    ///
+    /// Greetings, general programming questions and unrelated requests are answered from the
+    /// conversation alone, without searching the codebase.
+    ///
+    /// Returns `false` if the query is about the code, in which case it should be answered as
+    /// usual. Queries with an attachment are always answered from the code.
+    #[instrument(skip(self))]
+    pub async fn route(&mut self, query: &str) -> Result<bool> {
+        if self.last_exchange().attachment.is_some() {
+            return Ok(false);
+        }
+
+        let repos = self.route_repos().await;
+
+        if !self.app.config.disable_canned_replies && self.app.config.persona.is_intro(query) {
+            debug!("introducing from the template");
+            self.last_exchange_mut().answer_kind = AnswerKind::Intro;
+            self.introduce(&repos).await?;
+            self.track_query(
+                EventData::output_stage("routed")
+                    .with_payload("query", query)
+                    .with_payload("kind", AnswerKind::Intro)
+                    .with_payload("canned", true),
+            );
+
+            return Ok(true);
+        }
+
+        let kind = match self.classify(query, &repos).await {
+            Ok(kind) => kind,
+            Err(err) => {
+                warn!(?err, "failed to classify query, answering from the code");
+                AnswerKind::Code
+            }
+        };
+
+        debug!(?kind, "classified query");
+        self.last_exchange_mut().answer_kind = kind;
+
+        if kind == AnswerKind::Code {
+            return Ok(false);
+        }
+
+        self.reply(kind, &repos).await?;
+        self.track_query(
+            EventData::output_stage("routed")
+                .with_payload("query", query)
+                .with_payload("kind", kind),
+        );
+
+        Ok(true)
+    }
+
+    async fn route_repos(&self) -> String {
+        let repos = self.repositories().await;
+
+        if repos.is_empty() {
+            self.repo_ref.to_string()
+        } else {
+            repos.join("\n")
+        }
+    }
+
+    /// Classify the query, skipping the LLM when it obviously refers to code or the same question
+    /// has started a conversation before.
+    async fn classify(&self, query: &str, repos: &str) -> Result<AnswerKind> {
+        if follows_code(&self.exchanges) {
+            debug!("follow-up in a conversation about the code, not classifying it");
+            return Ok(AnswerKind::Code);
+        }
+
+        if classification::looks_like_code(query) {
+            debug!("query looks like code, not classifying it");
+            return Ok(AnswerKind::Code);
+        }
+
+        // Follow-ups are classified in the context of the conversation, so only opening questions
+        // are cached.
+        let cache = Some(&*self.app.classifications).filter(|_| self.exchanges.len() == 1);
+        if let Some(kind) = cache.and_then(|cache| cache.get(query)) {
+            debug!(?kind, "reusing the classification of the same question");
+            return Ok(kind);
+        }
+
+        let prompt = self
+            .app
+            .prompt_templates
+            .render_or("route_query", context! { repos }, || {
+                prompts::route_query_prompt(repos)
+            });
+
+        let messages = Some(llm_gateway::api::Message::system(&prompt))
+            .into_iter()
+            .chain(self.utter_history())
+            .collect::<Vec<_>>();
+
+        let choice = self
+            .llm_gateway
+            .clone()
+            .model(ROUTE_MODEL)
+            .select(&messages, AnswerKind::ALL.len())
+            .await?;
+
+        let kind = parse_kind(choice);
+        if let Some(cache) = cache {
+            cache.insert(query, kind);
+        }
+
+        Ok(kind)
+    }
+
+    /// Reply to a greeting or a question about the assistant from its template, without calling
+    /// the LLM.
+    async fn introduce(&mut self, repos: &str) -> Result<()> {
+        let persona = &self.app.config.persona;
+        let intro = self.app.prompt_templates.render_or(
+            "intro",
+            context! {
+                name => persona.name,
+                description => persona.description,
+                repos => repos.lines().collect::<Vec<_>>(),
+                examples => persona.examples,
+            },
+            || persona.intro(repos),
+        );
+
+        let Some(article) = self.enforce_policy(Scope::Answer, &intro, true).await? else {
+            return Ok(());
+        };
+        self.update(Update::Article(article.clone())).await?;
+        self.update(Update::Conclude(article)).await
+    }
+
+    /// Stream a reply that is not based on the code, as the answer of the last exchange.
+    async fn reply(&mut self, kind: AnswerKind, repos: &str) -> Result<()> {
+        let prompt =
+            self.app
+                .prompt_templates
+                .render_or("route_reply", context! { kind, repos }, || {
+                    prompts::route_reply_prompt(kind, repos)
+                });
+
+        let messages = Some(llm_gateway::api::Message::system(&prompt))
+            .into_iter()
+            .chain(self.utter_history())
+            .collect::<Vec<_>>();
+
+        let mut stream = self
+            .llm_gateway
+            .clone()
+            .model(ROUTE_MODEL)
+            .chat(&messages, None)
+            .await?;
+
+        let mut response = String::new();
+        while let Some(fragment) = stream.next().await {
+            response += &fragment?;
+
+            let Some(article) = self.enforce_policy(Scope::Answer, &response, false).await? else {
+                return Ok(());
+            };
+            self.update(Update::Article(article)).await?;
+        }
+
+        let Some(article) = self.enforce_policy(Scope::Answer, &response, true).await? else {
+            return Ok(());
+        };
+        self.update(Update::Article(article.clone())).await?;
+        self.update(Update::Conclude(article)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_choices() {
+        assert_eq!(parse_kind(Some(0)), AnswerKind::Code);
+        assert_eq!(parse_kind(Some(1)), AnswerKind::Intro);
+        assert_eq!(parse_kind(Some(2)), AnswerKind::Info);
+        assert_eq!(parse_kind(Some(3)), AnswerKind::CannotHelp);
+
+        assert_eq!(parse_kind(Some(4)), AnswerKind::Code);
+        assert_eq!(parse_kind(None), AnswerKind::Code);
+    }
+
+    #[test]
+    fn follow_ups_about_code_are_not_classified() {
+        let exchange = |answer_kind| {
+            let mut exchange = Exchange::default();
+            exchange.answer_kind = answer_kind;
+            exchange
+        };
+
+        assert!(!follows_code(&[exchange(AnswerKind::Code)]));
+        assert!(follows_code(&[
+            exchange(AnswerKind::Code),
+            exchange(AnswerKind::Code)
+        ]));
+        assert!(!follows_code(&[
+            exchange(AnswerKind::Intro),
+            exchange(AnswerKind::Code)
+        ]));
+    }
+}
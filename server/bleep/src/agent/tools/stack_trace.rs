@@ -0,0 +1,386 @@
+use std::fmt;
+
+use anyhow::Result;
+use lazy_regex::regex;
+use tracing::{debug, instrument};
+
+use crate::{
+    agent::{exchange::CodeChunk, Agent},
+    analytics::EventData,
+};
+
+/// The most frames that are resolved and fetched for one stack trace.
+const MAX_FRAMES: usize = 5;
+
+/// The number of lines fetched on either side of the line of a frame.
+const FRAME_CONTEXT_LINES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    Java,
+    Go,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The path as printed in the trace, which is often absolute or prefixed with a build
+    /// directory, and has to be resolved against the indexed paths.
+    pub path: String,
+    /// 1-indexed line number.
+    pub line: usize,
+    pub function: Option<String>,
+}
+
+/// A stack trace pasted into a query, with its innermost frame first.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StackTrace {
+    pub language: Language,
+    pub frames: Vec<Frame>,
+}
+
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:?} stack trace, innermost frame first:", self.language)?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let function = frame.function.as_deref().unwrap_or("<unknown>");
+            writeln!(f, "{}. {function} at {}:{}", i + 1, frame.path, frame.line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a stack trace out of a user query, if it contains one.
+///
+/// Traces are recognized by the frame format of each supported language, and the language with
+/// the most frames wins.
+pub fn parse(query: &str) -> Option<StackTrace> {
+    [
+        (Language::Rust, rust_frames(query)),
+        (Language::Python, python_frames(query)),
+        (Language::JavaScript, javascript_frames(query)),
+        (Language::Java, java_frames(query)),
+        (Language::Go, go_frames(query)),
+    ]
+    .into_iter()
+    .filter(|(_, frames)| !frames.is_empty())
+    .max_by_key(|(_, frames)| frames.len())
+    .map(|(language, frames)| StackTrace { language, frames })
+}
+
+fn frame(path: &str, line: &str, function: Option<&str>) -> Option<Frame> {
+    Some(Frame {
+        path: path.replace('\\', "/"),
+        line: line.parse().ok().filter(|&l| l > 0)?,
+        function: function.map(str::to_owned),
+    })
+}
+
+fn rust_frames(text: &str) -> Vec<Frame> {
+    let panic = regex!(r"panicked at (?:'[^\n]*', )?([^\s:']+\.rs):(\d+)")
+        .captures_iter(text)
+        .filter_map(|c| frame(&c[1], &c[2], None));
+
+    let backtrace = regex!(r"(?m)^\s*\d+:\s+(\S+)\s*\n\s+at\s+(\S+?\.rs):(\d+)(?::\d+)?\s*$")
+        .captures_iter(text)
+        .filter_map(|c| frame(&c[2], &c[3], Some(&c[1])));
+
+    panic.chain(backtrace).collect()
+}
+
+fn python_frames(text: &str) -> Vec<Frame> {
+    let mut frames = regex!(r#"(?m)^\s*File "([^"]+)", line (\d+), in (\S+)"#)
+        .captures_iter(text)
+        .filter_map(|c| frame(&c[1], &c[2], Some(&c[3])))
+        .collect::<Vec<_>>();
+
+    // Python prints the most recent call last.
+    frames.reverse();
+    frames
+}
+
+fn javascript_frames(text: &str) -> Vec<Frame> {
+    regex!(r"(?m)^\s*at\s+(?:(\S+)\s+\()?([^\s()]+?\.(?:js|jsx|ts|tsx|mjs|cjs)):(\d+):\d+\)?\s*$")
+        .captures_iter(text)
+        .filter_map(|c| frame(&c[2], &c[3], c.get(1).map(|m| m.as_str())))
+        .collect()
+}
+
+fn java_frames(text: &str) -> Vec<Frame> {
+    regex!(r"(?m)^\s*at\s+([\w$.<>]+)\(([\w$]+\.(?:java|kt|scala|groovy)):(\d+)\)")
+        .captures_iter(text)
+        .filter_map(|c| {
+            // Java only prints the file name, so the directory is taken from the package.
+            let segments = c[1].split('.').collect::<Vec<_>>();
+            let package = &segments[..segments.len().saturating_sub(2)];
+            let function = segments[package.len()..].join(".");
+
+            let path = package
+                .iter()
+                .copied()
+                .chain([&c[2]])
+                .collect::<Vec<_>>()
+                .join("/");
+
+            frame(&path, &c[3], Some(&function))
+        })
+        .collect()
+}
+
+fn go_frames(text: &str) -> Vec<Frame> {
+    regex!(r"(?m)^(\S+)\(.*\)\s*\n\s+(\S+\.go):(\d+)")
+        .captures_iter(text)
+        .filter_map(|c| frame(&c[2], &c[3], Some(&c[1])))
+        .collect()
+}
+
+/// Pick the indexed path that shares the longest suffix of path components with a frame path.
+///
+/// At least the file name must match.
+fn resolve<'a>(frame_path: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let shared_suffix = |candidate: &str| {
+        frame_path
+            .rsplit('/')
+            .zip(candidate.rsplit('/'))
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+
+    candidates
+        .iter()
+        .map(|c| (shared_suffix(c), c))
+        .filter(|(shared, _)| *shared > 0)
+        // `max_by_key` returns the last maximum, so reverse to prefer the first.
+        .rev()
+        .max_by_key(|(shared, _)| *shared)
+        .map(|(_, c)| c.as_str())
+}
+
+impl Agent {
+    /// Resolve the frames of a stack trace against the indexed paths, and add the code around
+    /// each frame to the context of the answer.
+    ///
+    /// Returns the aliases of the paths that were resolved, innermost frame first.
+    #[instrument(skip(self))]
+    pub async fn fetch_stack_trace(&mut self, trace: &StackTrace) -> Result<Vec<usize>> {
+        let mut aliases = Vec::new();
+        let mut resolved = Vec::new();
+
+        for frame in &trace.frames {
+            if resolved.len() == MAX_FRAMES {
+                break;
+            }
+
+            let file_name = frame.path.rsplit('/').next().unwrap_or(&frame.path);
+            let candidates = self
+                .fuzzy_path_search(file_name)
+                .await
+                .map(|doc| doc.relative_path)
+                .collect::<Vec<_>>();
+
+            let Some(path) = resolve(&frame.path, &candidates).map(str::to_owned) else {
+                debug!(path = %frame.path, "stack frame is not in the index");
+                continue;
+            };
+
+            let Some(doc) = self.get_file_content(&path).await? else {
+                continue;
+            };
+
+            let lines = doc.content.lines().collect::<Vec<_>>();
+            if frame.line > lines.len() {
+                continue;
+            }
+
+            let start_line = (frame.line - 1).saturating_sub(FRAME_CONTEXT_LINES);
+            let end_line = (frame.line + FRAME_CONTEXT_LINES).min(lines.len());
+
+            let alias = self.get_path_alias(&path);
+            self.last_exchange_mut().code_chunks.push(CodeChunk {
+                path: path.clone(),
+                alias,
+                snippet: lines[start_line..end_line].join("\n"),
+                start_line,
+                end_line,
+                score: None,
+            });
+
+            if !aliases.contains(&alias) {
+                aliases.push(alias);
+            }
+            resolved.push((path, frame.line));
+        }
+
+        self.track_query(
+            EventData::input_stage("stack trace")
+                .with_payload("language", format!("{:?}", trace.language))
+                .with_payload("frames", trace.frames.len())
+                .with_payload("resolved", &resolved),
+        );
+
+        Ok(aliases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(trace: &StackTrace) -> Vec<(&str, usize, Option<&str>)> {
+        trace
+            .frames
+            .iter()
+            .map(|f| (f.path.as_str(), f.line, f.function.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_rust_panics() {
+        let trace = parse(
+            "why does this happen?
+thread 'main' panicked at 'called `Option::unwrap()` on a `None` value', src/main.rs:12:5
+stack backtrace:
+   0: rust_begin_unwind
+             at /rustc/90c5418/library/std/src/panicking.rs:578:5
+   1: app::config::load
+             at ./src/config.rs:40:9",
+        )
+        .unwrap();
+
+        assert_eq!(trace.language, Language::Rust);
+        assert_eq!(
+            frames(&trace),
+            [
+                ("src/main.rs", 12, None),
+                (
+                    "/rustc/90c5418/library/std/src/panicking.rs",
+                    578,
+                    Some("rust_begin_unwind")
+                ),
+                ("./src/config.rs", 40, Some("app::config::load")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_python_tracebacks() {
+        let trace = parse(
+            r#"Traceback (most recent call last):
+  File "/home/me/app/server/main.py", line 8, in <module>
+    run()
+  File "/home/me/app/server/db.py", line 21, in connect
+    raise ValueError("no url")
+ValueError: no url"#,
+        )
+        .unwrap();
+
+        assert_eq!(trace.language, Language::Python);
+        assert_eq!(
+            frames(&trace),
+            [
+                ("/home/me/app/server/db.py", 21, Some("connect")),
+                ("/home/me/app/server/main.py", 8, Some("<module>")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_javascript_stacks() {
+        let trace = parse(
+            "TypeError: Cannot read properties of undefined (reading 'map')
+    at renderList (webpack:///./src/components/List.tsx:14:22)
+    at http://localhost:3000/static/js/main.js:1:100",
+        )
+        .unwrap();
+
+        assert_eq!(trace.language, Language::JavaScript);
+        assert_eq!(
+            frames(&trace),
+            [
+                (
+                    "webpack:///./src/components/List.tsx",
+                    14,
+                    Some("renderList")
+                ),
+                ("http://localhost:3000/static/js/main.js", 1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_java_stacks() {
+        let trace = parse(
+            "Exception in thread \"main\" java.lang.NullPointerException
+\tat com.example.app.Server.start(Server.java:42)
+\tat com.example.app.Main.main(Main.java:7)",
+        )
+        .unwrap();
+
+        assert_eq!(trace.language, Language::Java);
+        assert_eq!(
+            frames(&trace),
+            [
+                ("com/example/app/Server.java", 42, Some("Server.start")),
+                ("com/example/app/Main.java", 7, Some("Main.main")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_go_panics() {
+        let trace = parse(
+            "panic: runtime error: invalid memory address or nil pointer dereference
+
+goroutine 1 [running]:
+main.(*Server).handle(0x0, {0x1, 0x2})
+\t/home/me/app/server.go:27 +0x1d
+main.main()
+\t/home/me/app/main.go:9 +0x25",
+        )
+        .unwrap();
+
+        assert_eq!(trace.language, Language::Go);
+        assert_eq!(
+            frames(&trace),
+            [
+                ("/home/me/app/server.go", 27, Some("main.(*Server).handle")),
+                ("/home/me/app/main.go", 9, Some("main.main")),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_plain_questions() {
+        assert_eq!(parse("what is at src/main.rs:12?"), None);
+        assert_eq!(parse("how does the File \"class\" work"), None);
+    }
+
+    #[test]
+    fn resolves_paths_by_suffix() {
+        let candidates = [
+            "client/src/main.rs".to_owned(),
+            "server/src/main.rs".to_owned(),
+            "server/src/config.rs".to_owned(),
+        ];
+
+        assert_eq!(
+            resolve("/home/me/repo/server/src/main.rs", &candidates),
+            Some("server/src/main.rs")
+        );
+        assert_eq!(
+            resolve("./src/config.rs", &candidates),
+            Some("server/src/config.rs")
+        );
+        assert_eq!(
+            resolve("src/main.rs", &candidates),
+            Some("client/src/main.rs")
+        );
+        assert_eq!(
+            resolve("/rustc/library/std/src/panicking.rs", &candidates),
+            None
+        );
+    }
+}
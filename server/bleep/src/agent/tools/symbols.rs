@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Context, Result};
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    symbol::Symbol,
+};
+
+impl Agent {
+    /// List the symbols defined in a file in context, with the lines they are defined on.
+    #[instrument(skip(self))]
+    pub async fn list_symbols(&mut self, alias: usize) -> Result<String> {
+        const MAX_SYMBOLS: usize = 200;
+
+        let path = self
+            .paths()
+            .nth(alias)
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("invalid path alias {alias}"))?;
+
+        self.update(Update::StartStep(SearchStep::Symbols {
+            path: path.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let doc = self
+            .get_file_content(&path)
+            .await?
+            .with_context(|| format!("path does not exist in the index: {path}"))?;
+
+        let symbols = doc.symbol_locations.list();
+        let response = format!(
+            "{alias}: {path}\n{}",
+            format_symbols(&doc.content, &symbols, MAX_SYMBOLS)
+        );
+
+        self.update(Update::ReplaceStep(SearchStep::Symbols {
+            path: path.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("list symbols")
+                .with_payload("path", &path)
+                .with_payload("count", symbols.len())
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
+
+/// Format symbols as `line kind name`, in order of definition, with one-based line numbers.
+fn format_symbols(content: &str, symbols: &[Symbol], max: usize) -> String {
+    let mut symbols = symbols.iter().collect::<Vec<_>>();
+    symbols.sort_by_key(|s| s.range.start.byte);
+
+    let mut lines = symbols
+        .iter()
+        .take(max)
+        .filter_map(|s| {
+            let name = content.get(s.range.start.byte..s.range.end.byte)?;
+            Some(format!("{} {} {name}", s.range.start.line + 1, s.kind))
+        })
+        .collect::<Vec<_>>();
+
+    if symbols.len() > max {
+        lines.push(format!("[{} more]", symbols.len() - max));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_range::{Point, TextRange};
+
+    fn symbol(kind: &str, start: Point, end: Point) -> Symbol {
+        Symbol {
+            kind: kind.to_owned(),
+            range: TextRange::new(start, end),
+        }
+    }
+
+    #[test]
+    fn formats_symbols() {
+        let content = "fn main() {}\n\nstruct Foo;\n";
+        let symbols = [
+            symbol("struct", Point::new(21, 2, 7), Point::new(24, 2, 10)),
+            symbol("function", Point::new(3, 0, 3), Point::new(7, 0, 7)),
+        ];
+
+        assert_eq!(
+            format_symbols(content, &symbols, 10),
+            "1 function main\n3 struct Foo"
+        );
+        assert_eq!(
+            format_symbols(content, &symbols, 1),
+            "1 function main\n[1 more]"
+        );
+    }
+}
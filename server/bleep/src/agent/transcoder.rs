@@ -13,6 +13,8 @@ use regex::Regex;
 use serde::Deserialize;
 use tiktoken_rs::CoreBPE;
 
+use super::exchange::SuggestedEdit;
+
 /// Decode an article.
 ///
 /// If successful, this returns a tuple of `(body, conclusion)`.
@@ -107,6 +109,34 @@ pub fn decode(llm_message: &str) -> (String, Option<String>) {
     (comrak_to_string(root), None)
 }
 
+/// Extract the edits suggested in an article.
+///
+/// Incomplete or malformed suggestions are skipped. Line numbers are converted to be zero-based.
+pub fn suggested_edits(llm_message: &str) -> Vec<SuggestedEdit> {
+    let mut edits = Vec::new();
+
+    xml_for_each(&sanitize(llm_message), |xml| {
+        if let Ok(CodeChunk::SuggestedEdit {
+            diff,
+            path,
+            start_line: Some(start_line),
+            end_line: Some(end_line),
+        }) = quick_xml::de::from_str(xml)
+        {
+            edits.push(SuggestedEdit {
+                path,
+                start_line: start_line.saturating_sub(1) as usize,
+                end_line: end_line.saturating_sub(1) as usize,
+                diff,
+            });
+        }
+
+        None
+    });
+
+    edits
+}
+
 /// Offset line ranges in embedded links by a specific value.
 ///
 /// This can be used to offset lines by one, either positively or negatively.
@@ -264,9 +294,10 @@ fn fixup_xml_code(xml: &str) -> Cow<str> {
         return Cow::Borrowed(xml);
     }
 
-    if let Some(match_) = regex!("<(Generated|Quoted)Code>\\s*<Code>(.*)"sm)
-        .captures(xml)
-        .and_then(|cap| cap.get(2))
+    if let Some(match_) =
+        regex!("<(GeneratedCode|QuotedCode|SuggestedEdit)>\\s*<(Code|Diff)>(.*)"sm)
+            .captures(xml)
+            .and_then(|cap| cap.get(3))
     {
         let mut buf = String::new();
 
@@ -276,7 +307,7 @@ fn fixup_xml_code(xml: &str) -> Cow<str> {
         {
             let s = &xml[match_.range()];
 
-            let code_len = regex!("</Code>")
+            let code_len = regex!("</(Code|Diff)>")
                 .find(s)
                 .map(|m| m.start())
                 .unwrap_or(s.len());
@@ -317,12 +348,14 @@ fn fixup_xml_code(xml: &str) -> Cow<str> {
 
             for tag in [
                 "Code",
+                "Diff",
                 "Language",
                 "Path",
                 "StartLine",
                 "EndLine",
                 "QuotedCode",
                 "GeneratedCode",
+                "SuggestedEdit",
             ] {
                 let opening_tag = format!("<{tag}>");
                 let closing_tag = format!("</{tag}>");
@@ -367,6 +400,16 @@ enum CodeChunk {
         #[serde(default, rename = "Language")]
         language: String,
     },
+    SuggestedEdit {
+        #[serde(default, rename = "Diff")]
+        diff: String,
+        #[serde(default, rename = "Path")]
+        path: String,
+        #[serde(default, rename = "StartLine", deserialize_with = "deserialize_lineno")]
+        start_line: Option<u32>,
+        #[serde(default, rename = "EndLine", deserialize_with = "deserialize_lineno")]
+        end_line: Option<u32>,
+    },
 }
 
 fn deserialize_lineno<'a, D: serde::Deserializer<'a>>(de: D) -> Result<Option<u32>, D::Error> {
@@ -394,14 +437,27 @@ impl CodeChunk {
             } => (
                 "Quoted",
                 code,
-                language,
+                language.as_str(),
                 path.as_str(),
                 start_line.map(|n| n.saturating_sub(1)),
                 end_line.map(|n| n.saturating_sub(1)),
             ),
             CodeChunk::GeneratedCode { code, language } => {
-                ("Generated", code, language, "", None, None)
+                ("Generated", code, language.as_str(), "", None, None)
             }
+            CodeChunk::SuggestedEdit {
+                diff,
+                path,
+                start_line,
+                end_line,
+            } => (
+                "Suggested",
+                diff,
+                "diff",
+                path.as_str(),
+                start_line.map(|n| n.saturating_sub(1)),
+                end_line.map(|n| n.saturating_sub(1)),
+            ),
         };
 
         format!(
@@ -443,7 +499,7 @@ impl CodeChunk {
 ///
 /// For further context, we must accept ambiguous unescaped (invalid) input, as the LLM may
 /// generate such documents.
-fn xml_for_each(article: &str, f: impl Fn(&str) -> Option<String>) -> String {
+fn xml_for_each(article: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
     let mut out = String::new();
     let mut rest = article;
 
@@ -513,6 +569,29 @@ fn try_trim_code_xml(xml: &str) -> Result<String> {
                 </GeneratedCode>"
             )
         }
+
+        CodeChunk::SuggestedEdit {
+            diff: _,
+            path,
+            start_line,
+            end_line,
+        } => {
+            let start_line = start_line
+                .map(|n| format!("<StartLine>{n}</StartLine>\n"))
+                .unwrap_or_default();
+            let end_line = end_line
+                .map(|n| format!("<EndLine>{n}</EndLine>\n"))
+                .unwrap_or_default();
+
+            format!(
+                "<SuggestedEdit>\n\
+                <Diff>[REDACTED]</Diff>\n\
+                <Path>{path}</Path>\n\
+                {start_line}\
+                {end_line}\
+                </SuggestedEdit>"
+            )
+        }
     })
 }
 
@@ -979,6 +1058,48 @@ Hello again, world.
         );
     }
 
+    #[test]
+    fn test_suggested_edits() {
+        let input = r#"The loop can be simplified:
+
+<SuggestedEdit>
+<Diff>
+@@ -12,3 +12,1 @@
+-for i in 0..items.len() {
+-    total += items[i];
+-}
++total += items.iter().sum::<i32>();
+</Diff>
+<Path>src/main.rs</Path>
+<StartLine>12</StartLine>
+<EndLine>14</EndLine>
+</SuggestedEdit>
+
+And an incomplete one:
+
+<SuggestedEdit>
+<Diff>
+@@ -1,1 +1,1 @@"#;
+
+        assert_eq!(
+            suggested_edits(input),
+            vec![SuggestedEdit {
+                path: "src/main.rs".to_owned(),
+                start_line: 11,
+                end_line: 13,
+                diff: "@@ -12,3 +12,1 @@
+-for i in 0..items.len() {
+-    total += items[i];
+-}
++total += items.iter().sum::<i32>();"
+                    .to_owned(),
+            }]
+        );
+
+        let (body, _) = decode(input);
+        assert!(body.contains("``` type:Suggested,lang:diff,path:src/main.rs,lines:11-13"));
+    }
+
     #[test]
     fn test_encode() {
         let input = "Foo
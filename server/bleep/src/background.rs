@@ -3,7 +3,9 @@ use tokio::sync::Semaphore;
 use tracing::{debug, info};
 
 use crate::{
-    repo::{BranchFilter, RepoRef, SyncStatus},
+    cache::FileCache,
+    db::RepoRenames,
+    repo::{BranchFilter, RepoRef, RepoRemote, SyncStatus},
     Application, Configuration,
 };
 
@@ -12,6 +14,8 @@ use std::{future::Future, pin::Pin, sync::Arc, thread};
 mod sync;
 pub(crate) use sync::SyncHandle;
 
+pub(crate) mod reindex;
+
 mod control;
 pub(crate) use control::SyncPipes;
 
@@ -274,6 +278,93 @@ impl BoundSyncQueue {
         Some(())
     }
 
+    /// Move a repository that was renamed on GitHub to its new name, and index it under that
+    /// name.
+    ///
+    /// Repositories that are being synced are left alone, and renamed when they are idle.
+    pub(crate) async fn rename(self, from: RepoRef, to: RepoRef) -> anyhow::Result<()> {
+        let Self(app, queue) = &self;
+
+        if app.repo_pool.contains(&to) || queue.active.contains(&from) {
+            return Ok(());
+        }
+
+        // Holding the writers keeps the indexes of the repository from changing meanwhile.
+        let writers = app.indexes.writers().await?;
+        let Some((_, mut repo)) = app.repo_pool.remove_if(&from, |repo| {
+            !matches!(
+                repo.sync_status,
+                SyncStatus::Queued | SyncStatus::Syncing | SyncStatus::Indexing
+            )
+        }) else {
+            return Ok(());
+        };
+
+        info!(%from, %to, "renaming repository");
+
+        for handle in writers.iter() {
+            handle.delete(&repo);
+        }
+        writers.commit().await?;
+
+        // The clone stays where it is, and pulls from the new remote.
+        repo.remote = RepoRemote::from(&to);
+        repo.sync_status = SyncStatus::Queued;
+        repo.last_index_unix_secs = 0;
+        _ = app.repo_pool.insert(to.clone(), repo);
+        app.config.source.save_pool(app.repo_pool.clone())?;
+
+        if let Some(ref semantic) = app.semantic {
+            semantic.rename_repo(&from, &to).await?;
+        }
+
+        RepoRenames::new(&app.sql)
+            .record(&from.to_string(), &to.to_string())
+            .await?;
+
+        self.enqueue_sync(vec![to]).await;
+        Ok(())
+    }
+
+    /// Drop the lexical and semantic indexes of a repository, and index it again from scratch.
+    ///
+    /// Returns the status of the repository once it is indexed.
+    pub(crate) async fn rebuild(self, reporef: RepoRef) -> anyhow::Result<SyncStatus> {
+        let Self(app, _) = &self;
+
+        {
+            // Holding the writers keeps the repository from being indexed while it is reset.
+            let writers = app.indexes.writers().await?;
+            let Some(repo) = app
+                .repo_pool
+                .update_async(&reporef, |_, repo| {
+                    repo.last_index_unix_secs = 0;
+                    repo.clone()
+                })
+                .await
+            else {
+                anyhow::bail!("unknown repository");
+            };
+
+            for handle in writers.iter() {
+                handle.delete(&repo);
+            }
+            writers.commit().await?;
+
+            // Without cached files and chunks, every file is indexed and embedded again.
+            FileCache::for_repo(&app.sql, app.semantic.as_ref(), &reporef)
+                .delete()
+                .await?;
+
+            if let Some(ref semantic) = app.semantic {
+                semantic.delete_repo(&reporef).await?;
+            }
+        }
+
+        info!(%reporef, "rebuilding repository indexes");
+        self.block_until_synced(reporef).await
+    }
+
     pub(crate) async fn cancel(&self, reporef: RepoRef) {
         self.1
             .active
@@ -0,0 +1,128 @@
+//! Re-indexing every repository from scratch, after an upgrade of the embedding model or of the
+//! index schema.
+//!
+//! Repositories are re-indexed in batches of `reindex_batch_size`. The lexical and semantic
+//! indexes of a batch are dropped and built again before the next batch starts, so that only a
+//! few repositories can't be searched at a time, and syncs of the others carry on meanwhile.
+//!
+//! The status of each repository is checkpointed in the database, so a re-index that was
+//! interrupted by a restart resumes with the repositories that weren't done.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::{error, info, warn};
+
+use crate::{
+    db::BulkReindex,
+    repo::{RepoRef, SyncStatus},
+    Application,
+};
+
+/// Whether a re-index is running, as there is at most one at a time.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// Start re-indexing every repository in the background, unless a re-index is running already.
+///
+/// Returns whether it was started.
+pub(crate) async fn start(app: &Application) -> anyhow::Result<bool> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Ok(false);
+    }
+
+    let mut repo_refs = vec![];
+    app.repo_pool
+        .scan_async(|k, repo| {
+            if repo.sync_status.indexable() {
+                repo_refs.push(k.to_string());
+            }
+        })
+        .await;
+    repo_refs.sort();
+
+    if let Err(err) = BulkReindex::new(&app.sql).start(&repo_refs).await {
+        RUNNING.store(false, Ordering::SeqCst);
+        return Err(err);
+    }
+
+    info!(repos = repo_refs.len(), "re-indexing every repository");
+    tokio::spawn(run(app.clone()));
+    Ok(true)
+}
+
+/// Carry on with a re-index that was interrupted, if there is one.
+pub(crate) async fn resume(app: Application) {
+    match BulkReindex::new(&app.sql).unfinished().await {
+        Ok(unfinished) if unfinished.is_empty() => {}
+        Ok(unfinished) => {
+            if !RUNNING.swap(true, Ordering::SeqCst) {
+                info!(repos = unfinished.len(), "resuming re-index");
+                run(app).await;
+            }
+        }
+        Err(err) => error!(?err, "failed to load re-index progress"),
+    }
+}
+
+async fn run(app: Application) {
+    let progress = BulkReindex::new(&app.sql);
+    let batch_size = app.config.reindex_batch_size.get();
+
+    loop {
+        let batch = match progress.unfinished().await {
+            Ok(unfinished) if unfinished.is_empty() => {
+                info!("finished re-indexing every repository");
+                break;
+            }
+            Ok(unfinished) => unfinished.into_iter().take(batch_size).collect::<Vec<_>>(),
+            Err(err) => {
+                error!(?err, "failed to load re-index progress");
+                break;
+            }
+        };
+
+        let reindexed =
+            futures::future::join_all(batch.iter().map(|repo_ref| reindex(&app, repo_ref))).await;
+
+        if let Some(err) = reindexed.into_iter().find_map(Result::err) {
+            error!(?err, "failed to checkpoint re-index progress");
+            break;
+        }
+    }
+
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+/// Re-index one repository, and record how it went.
+async fn reindex(app: &Application, repo_ref: &str) -> anyhow::Result<()> {
+    let progress = BulkReindex::new(&app.sql);
+
+    let reporef = match repo_ref.parse::<RepoRef>() {
+        Ok(reporef) if app.repo_pool.contains(&reporef) => reporef,
+        _ => {
+            return progress
+                .set_status(repo_ref, "skipped", Some("the repository was removed"))
+                .await;
+        }
+    };
+
+    progress.set_status(repo_ref, "running", None).await?;
+
+    let (status, message) = match app.write_index().rebuild(reporef).await {
+        Ok(SyncStatus::Done) => ("done", None),
+        Ok(SyncStatus::Error { message }) => ("failed", Some(message)),
+        Ok(other) => ("failed", Some(format!("finished as {other:?}"))),
+        Err(err) => ("failed", Some(err.to_string())),
+    };
+
+    if let Some(ref message) = message {
+        warn!(repo_ref, message, "failed to re-index repository");
+    }
+
+    progress
+        .set_status(repo_ref, status, message.as_deref())
+        .await
+}
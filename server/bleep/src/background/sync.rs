@@ -4,7 +4,9 @@ use tracing::{debug, error, info, warn};
 
 use crate::{
     cache::FileCache,
+    db::PreciseSymbols,
     indexes,
+    intelligence::precise::{self, Dump, Source},
     remotes::RemoteError,
     repo::{Backend, RepoError, RepoMetadata, RepoRef, Repository, SyncStatus},
     Application,
@@ -50,6 +52,9 @@ pub(super) enum SyncError {
     #[error("sql: {0:?}")]
     Sql(anyhow::Error),
 
+    #[error("semantic index cleanup failed: {0:?}")]
+    Semantic(anyhow::Error),
+
     #[error("syncing in progress")]
     SyncInProgress,
 
@@ -107,21 +112,12 @@ impl SyncHandle {
                     Repository::local_from(&reporef)
                 } else {
                     let name = reporef.to_string();
-                    let remote = reporef.as_ref().into();
                     let disk_path = app
                         .config
                         .source
                         .repo_path_for_name(&name.replace('/', "_"));
 
-                    Repository {
-                        disk_path,
-                        remote,
-                        sync_status: SyncStatus::Queued,
-                        last_index_unix_secs: 0,
-                        last_commit_unix_secs: 0,
-                        most_common_lang: None,
-                        branch_filter: None,
-                    }
+                    Repository::remote_from(&reporef, disk_path)
                 }
             });
 
@@ -191,6 +187,10 @@ impl SyncHandle {
                 self.set_status(|_| SyncStatus::Done)
             }
             Err(SyncError::Cancelled) => self.set_status(|_| SyncStatus::Cancelled),
+            Err(err) if removed || self.pipes.is_removed() => {
+                error!(?err, ?self.reporef, "failed to delete repository");
+                self.set_status(|_| SyncStatus::Removed)
+            }
             Err(err) => {
                 error!(?err, ?self.reporef, "failed to index repository");
                 self.set_status(|_| SyncStatus::Error {
@@ -244,6 +244,11 @@ impl SyncHandle {
         match indexed {
             Ok(_) => {
                 writers.commit().await.map_err(SyncError::Tantivy)?;
+                indexes
+                    .migrated(&self.reporef)
+                    .await
+                    .map_err(SyncError::Tantivy)?;
+                self.import_ctags(&repo).await;
                 indexed.map_err(SyncError::Indexing)
             }
             Err(_) if self.pipes.is_removed() => self.delete_repo(&repo, writers).await,
@@ -259,16 +264,58 @@ impl SyncHandle {
         }
     }
 
+    /// Import the first ctags file at the root of the repository, if there is one.
+    ///
+    /// Remote repositories are bare clones, so the file is read from the index when it is not
+    /// on disk.
+    async fn import_ctags(&self, repo: &Repository) {
+        for name in precise::CTAGS_FILES {
+            let data = match tokio::fs::read(repo.disk_path.join(name)).await {
+                Ok(data) => data,
+                Err(_) => match self
+                    .app
+                    .indexes
+                    .file
+                    .by_path(&self.reporef, name, None)
+                    .await
+                {
+                    Ok(Some(doc)) => doc.content.into_bytes(),
+                    _ => continue,
+                },
+            };
+
+            let imported = match Dump::parse(Source::Ctags, &data) {
+                Ok(dump) => {
+                    precise::import(&self.app.indexes, &self.app.sql, &self.reporef, &dump).await
+                }
+                Err(err) => Err(err),
+            };
+
+            match imported {
+                Ok(stats) => debug!(?self.reporef, ?stats, "imported ctags"),
+                Err(err) => warn!(?err, ?self.reporef, "failed to import ctags"),
+            }
+
+            return;
+        }
+    }
+
     async fn delete_repo(
         &self,
         repo: &Repository,
         writers: indexes::GlobalWriteHandle<'_>,
     ) -> Result<Either<SyncStatus, Arc<RepoMetadata>>> {
-        self.app.repo_pool.remove(&self.reporef);
-
+        // The repository stays in the pool until its indexes are gone, so that a failed deletion
+        // is retried, rather than leaving its snippets behind.
         let deleted = self.delete_repo_indexes(repo, &writers).await;
         if deleted.is_ok() {
             writers.commit().await.map_err(SyncError::Tantivy)?;
+            self.app.repo_pool.remove(&self.reporef);
+            self.app
+                .indexes
+                .migrated(&self.reporef)
+                .await
+                .map_err(SyncError::Tantivy)?;
             self.app
                 .config
                 .source
@@ -286,8 +333,8 @@ impl SyncHandle {
             Some(creds) => creds,
             None => {
                 let Some(path) = repo.local_path() else {
-		    return Err(SyncError::NoKeysForBackend(backend));
-		};
+                    return Err(SyncError::NoKeysForBackend(backend));
+                };
 
                 if !self.app.allow_path(&path) {
                     return Err(SyncError::PathNotAllowed(path));
@@ -383,8 +430,9 @@ impl SyncHandle {
 
         if let Some(semantic) = semantic {
             semantic
-                .delete_points_for_hash(&self.reporef.to_string(), std::iter::empty())
-                .await;
+                .delete_repo(&self.reporef)
+                .await
+                .map_err(SyncError::Semantic)?;
         }
 
         FileCache::for_repo(sql, semantic.as_ref(), &self.reporef)
@@ -392,6 +440,11 @@ impl SyncHandle {
             .await
             .map_err(SyncError::Sql)?;
 
+        PreciseSymbols::new(sql)
+            .delete_repo(&self.reporef.to_string())
+            .await
+            .map_err(SyncError::Sql)?;
+
         if !self.reporef.is_local() {
             tokio::fs::remove_dir_all(&repo.disk_path)
                 .await
@@ -1,11 +1,12 @@
 use std::{
+    collections::{HashMap, HashSet},
     sync::{Arc, RwLock},
     time::Instant,
 };
 
 use qdrant_client::qdrant::{PointId, PointStruct};
 use sqlx::Sqlite;
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 use uuid::Uuid;
 
 use crate::{
@@ -168,39 +169,24 @@ impl<'a> FileCache<'a> {
     /// If `flush == true`, drain the log, send the entire batch to
     /// the embedder, and commit the results, disregarding the internal
     /// batch sizing.
+    ///
+    /// Each batch is written to qdrant as soon as it's embedded, and
+    /// embedding waits for a permit from [`Semantic::batch_embed`],
+    /// so indexing slows down to the pace of the embedder instead of
+    /// piling up chunks in memory.
     pub async fn batched_process_embed_queue(&self, flush: bool) -> anyhow::Result<()> {
         let Some(semantic) = self.semantic
 	else {
 	    return Ok(());
 	};
 
-        let new_points = self.embed_queued_points(semantic, flush).await?;
-
-        if !new_points.is_empty() {
-            if let Err(err) = semantic
-                .qdrant_client()
-                .upsert_points(&semantic.collection_name(), new_points, None)
-                .await
-            {
-                error!(?err, "failed to write new points into qdrant");
-            }
-        }
-        Ok(())
-    }
-
-    async fn embed_queued_points(
-        &self,
-        semantic: &Semantic,
-        flush: bool,
-    ) -> Result<Vec<PointStruct>, anyhow::Error> {
         let batch_size = semantic.config.embedding_batch_size.get();
         let log = &self.embed_queue;
-        let mut output = vec![];
 
         loop {
             // if we're not currently flushing the log, only process full batches
             if log.is_empty() || (log.len() < batch_size && !flush) {
-                return Ok(output);
+                return Ok(());
             }
 
             let mut batch = vec![];
@@ -214,42 +200,90 @@ impl<'a> FileCache<'a> {
                 }
             }
 
-            let (elapsed, res) = {
-                let time = Instant::now();
-                let res = semantic
-                    .embedder()
-                    .batch_embed(batch.iter().map(|c| c.data.as_ref()).collect::<Vec<_>>())
-                    .await;
-
-                (time.elapsed(), res)
-            };
-
-            match res {
-                Ok(res) => {
-                    trace!(?elapsed, size = batch.len(), "batch embedding successful");
-                    output.extend(
-                        res.into_iter()
-                            .zip(batch)
-                            .map(|(embedding, src)| PointStruct {
-                                id: Some(PointId::from(src.id)),
-                                vectors: Some(embedding.into()),
-                                payload: src.payload,
-                            }),
-                    )
-                }
-                Err(err) => {
-                    error!(
-                        ?err,
-                        ?elapsed,
-                        size = batch.len(),
-                        "remote batch embeddings failed"
-                    )
+            let new_points = self.embed_batch(semantic, batch).await;
+            if !new_points.is_empty() {
+                if let Err(err) = semantic.upsert_points(self.reporef, new_points).await {
+                    error!(?err, "failed to write new points into qdrant");
                 }
             }
         }
     }
 
-    pub async fn chunks_for_file(&'a self, key: &'a str) -> ChunkCache<'a> {
+    async fn embed_batch(&self, semantic: &Semantic, batch: Vec<EmbedChunk>) -> Vec<PointStruct> {
+        let mut output = vec![];
+
+        // chunks that survived an edit of their file keep their embeddings
+        let hashes = batch
+            .iter()
+            .map(|c| c.data_hash.as_str())
+            .collect::<Vec<_>>();
+        let reusable = semantic
+            .embeddings_for_data(self.reporef, &hashes)
+            .await
+            .unwrap_or_else(|err| {
+                warn!(?err, "failed to look up reusable embeddings");
+                HashMap::new()
+            });
+
+        let (reused, batch): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|c| reusable.contains_key(&c.data_hash));
+
+        trace!(reused = reused.len(), "reusing embeddings");
+        output.extend(reused.into_iter().map(|src| PointStruct {
+            id: Some(PointId::from(src.id)),
+            vectors: Some(reusable[&src.data_hash].clone().into()),
+            payload: src.payload,
+        }));
+
+        if batch.is_empty() {
+            return output;
+        }
+
+        let (elapsed, res) = {
+            let time = Instant::now();
+            let res = semantic
+                .batch_embed(
+                    &self.reporef.indexed_name(),
+                    batch.iter().map(|c| c.data.as_ref()).collect::<Vec<_>>(),
+                )
+                .await;
+
+            (time.elapsed(), res)
+        };
+
+        match res {
+            Ok(res) => {
+                trace!(?elapsed, size = batch.len(), "batch embedding successful");
+                output.extend(
+                    res.into_iter()
+                        .zip(batch)
+                        .map(|(embedding, src)| PointStruct {
+                            id: Some(PointId::from(src.id)),
+                            vectors: Some(embedding.into()),
+                            payload: src.payload,
+                        }),
+                )
+            }
+            Err(err) => {
+                error!(
+                    ?err,
+                    ?elapsed,
+                    size = batch.len(),
+                    "remote batch embeddings failed"
+                )
+            }
+        }
+
+        output
+    }
+
+    pub async fn chunks_for_file(
+        &'a self,
+        key: &'a str,
+        relative_path: &'a str,
+        branches: &[String],
+    ) -> ChunkCache<'a> {
         ChunkCache::for_file(
             self.db,
             self.semantic
@@ -257,6 +291,8 @@ impl<'a> FileCache<'a> {
             self.reporef,
             &self.embed_queue,
             key,
+            relative_path,
+            branches,
         )
         .await
     }
@@ -266,14 +302,24 @@ impl<'a> FileCache<'a> {
 /// ensure consistency.
 ///
 /// Operates on a single file's level.
+///
+/// Chunk ids only depend on the content of the chunk, so chunks that
+/// survive a change to their file keep their point in qdrant, and
+/// only have their payload updated. Chunks of the previous version
+/// of the file that are gone are deleted.
 pub struct ChunkCache<'a> {
     sql: &'a SqlDb,
     semantic: &'a Semantic,
     reporef: &'a RepoRef,
     file_cache_key: &'a str,
+    relative_path: &'a str,
+    branches_hash: String,
+    model_id: String,
     cache: scc::HashMap<String, FreshValue<String>>,
+    previous: scc::HashMap<String, FreshValue<()>>,
     update: scc::HashMap<(Vec<String>, String), Vec<String>>,
     new_sql: RwLock<Vec<(String, String)>>,
+    kept: RwLock<Vec<(String, HashMap<String, qdrant_client::qdrant::Value>)>>,
     embed_queue: &'a EmbedQueue,
 }
 
@@ -284,7 +330,12 @@ impl<'a> ChunkCache<'a> {
         reporef: &'a RepoRef,
         embed_log: &'a EmbedQueue,
         file_cache_key: &'a str,
+        relative_path: &'a str,
+        branches: &[String],
     ) -> ChunkCache<'a> {
+        let branches_hash = blake3::hash(branches.join("\n").as_ref()).to_string();
+        let repo_str = reporef.to_string();
+
         let rows = sqlx::query! {
             "SELECT chunk_hash, branches FROM chunk_cache \
              WHERE file_hash = ?",
@@ -298,20 +349,68 @@ impl<'a> ChunkCache<'a> {
             _ = cache.insert(row.chunk_hash, FreshValue::stale(row.branches));
         }
 
+        // Chunks of other versions of the file on other branches are
+        // left alone, they're still searchable there.
+        let rows = sqlx::query! {
+            "SELECT chunk_hash FROM chunk_cache \
+             WHERE repo_ref = ? AND relative_path = ? AND branches = ? AND file_hash != ?",
+            repo_str,
+            relative_path,
+            branches_hash,
+            file_cache_key,
+        }
+        .fetch_all(sql.as_ref())
+        .await;
+
+        let previous = scc::HashMap::<String, FreshValue<_>>::default();
+        for row in rows.into_iter().flatten() {
+            _ = previous.insert(row.chunk_hash, FreshValue::stale(()));
+        }
+
         Self {
             sql,
             semantic,
             reporef,
             file_cache_key,
+            relative_path,
+            branches_hash,
+            model_id: semantic.model_for(&reporef.indexed_name()).id(),
             cache,
+            previous,
             embed_queue: embed_log,
             update: Default::default(),
             new_sql: Default::default(),
+            kept: Default::default(),
         }
     }
 
-    pub fn update_or_embed(&self, data: &'a str, payload: Payload) -> anyhow::Result<()> {
-        let id = self.cache_key(data, &payload);
+    /// The paths of the files of a repository that chunks were
+    /// embedded for.
+    pub async fn embedded_paths(sql: &SqlDb, reporef: &RepoRef) -> anyhow::Result<HashSet<String>> {
+        let repo_str = reporef.to_string();
+        let rows = sqlx::query! {
+            "SELECT DISTINCT relative_path FROM chunk_cache \
+             WHERE repo_ref = ? AND relative_path IS NOT NULL",
+            repo_str,
+        }
+        .fetch_all(sql.as_ref())
+        .await?;
+
+        Ok(rows.into_iter().filter_map(|r| r.relative_path).collect())
+    }
+
+    /// Record a chunk of the file, to be embedded unless the previous
+    /// version of the file had it.
+    ///
+    /// `occurrence` tells apart chunks with the same data in a file,
+    /// counting from 0 in the order they appear.
+    pub fn update_or_embed(
+        &self,
+        data: &'a str,
+        occurrence: usize,
+        payload: Payload,
+    ) -> anyhow::Result<()> {
+        let id = self.cache_key(data, occurrence);
         let branches_hash = blake3::hash(payload.branches.join("\n").as_ref()).to_string();
 
         match self.cache.entry(id) {
@@ -335,11 +434,25 @@ impl<'a> ChunkCache<'a> {
                     .unwrap()
                     .push((vacant.key().to_owned(), branches_hash.clone()));
 
-                self.embed_queue.push(EmbedChunk {
-                    id: vacant.key().clone(),
-                    data: data.into(),
-                    payload: payload.into_qdrant(),
-                });
+                let data_hash = blake3::hash(data.as_bytes()).to_string();
+                let mut payload = payload.into_qdrant();
+                payload.insert("data_hash".into(), data_hash.clone().into());
+
+                match self.previous.entry(key.clone()) {
+                    scc::hash_map::Entry::Occupied(mut previous) => {
+                        trace!(?key, "unchanged since the last version; not embedding");
+                        previous.get_mut().fresh = true;
+                        self.kept.write().unwrap().push((key.to_owned(), payload));
+                    }
+                    scc::hash_map::Entry::Vacant(_) => {
+                        self.embed_queue.push(EmbedChunk {
+                            id: key.clone(),
+                            data: data.into(),
+                            data_hash,
+                            payload,
+                        });
+                    }
+                }
 
                 vacant.insert_entry(branches_hash.into());
             }
@@ -360,16 +473,20 @@ impl<'a> ChunkCache<'a> {
     /// Since qdrant changes are pipelined on their end, data written
     /// here is not necessarily available for querying when the
     /// commit's completed.
-    pub async fn commit(self) -> anyhow::Result<(usize, usize, usize)> {
+    ///
+    /// Returns the number of new, kept, updated and deleted chunks.
+    pub async fn commit(self) -> anyhow::Result<(usize, usize, usize, usize)> {
         let mut tx = self.sql.begin().await?;
 
         let update_size = self.commit_branch_updates(&mut tx).await?;
+        let kept_size = self.commit_kept().await?;
         let delete_size = self.commit_deletes(&mut tx).await?;
+        let removed_size = self.commit_removals(&mut tx).await?;
         let new_size = self.commit_inserts(&mut tx).await?;
 
         tx.commit().await?;
 
-        Ok((new_size, update_size, delete_size))
+        Ok((new_size, kept_size, update_size, delete_size + removed_size))
     }
 
     /// Insert new additions to sqlite
@@ -389,9 +506,9 @@ impl<'a> ChunkCache<'a> {
         let repo_str = self.reporef.to_string();
         for (p, branches) in new_sql {
             sqlx::query! {
-                "INSERT INTO chunk_cache (chunk_hash, file_hash, branches, repo_ref) \
-                 VALUES (?, ?, ?, ?)",
-                 p, self.file_cache_key, branches, repo_str
+                "INSERT INTO chunk_cache (chunk_hash, file_hash, branches, repo_ref, relative_path) \
+                 VALUES (?, ?, ?, ?, ?)",
+                 p, self.file_cache_key, branches, repo_str, self.relative_path
             }
             .execute(&mut *tx)
             .await?;
@@ -428,15 +545,84 @@ impl<'a> ChunkCache<'a> {
 
         if !to_delete.is_empty() {
             self.semantic
-                .qdrant_client()
+                .store()
+                .delete_points(
+                    &self.semantic.collection_for(self.reporef),
+                    &to_delete
+                        .into_iter()
+                        .map(PointId::from)
+                        .collect::<Vec<_>>()
+                        .into(),
+                )
+                .await?;
+        }
+        Ok(delete_size)
+    }
+
+    /// Point chunks carried over from the previous version of the
+    /// file at their new location, without embedding them again.
+    async fn commit_kept(&self) -> Result<usize, anyhow::Error> {
+        let kept = std::mem::take(&mut *self.kept.write().unwrap());
+        let kept_size = kept.len();
+        let collection_name = &self.semantic.collection_for(self.reporef);
+
+        let qdrant_updates = kept.into_iter().map(|(id, payload)| async move {
+            self.semantic
+                .store()
+                .set_payload(
+                    collection_name,
+                    &vec![PointId::from(id)].into(),
+                    qdrant_client::client::Payload::new_from_hashmap(payload),
+                )
+                .await
+        });
+
+        futures::future::join_all(qdrant_updates)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(kept_size)
+    }
+
+    /// Delete chunks of the previous version of the file that are no
+    /// longer in it, and forget that version.
+    async fn commit_removals(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<usize, anyhow::Error> {
+        let mut to_delete = vec![];
+        self.previous
+            .scan_async(|id, p| {
+                if !p.fresh {
+                    to_delete.push(id.to_owned());
+                }
+            })
+            .await;
+
+        let repo_str = self.reporef.to_string();
+        sqlx::query! {
+            "DELETE FROM chunk_cache \
+             WHERE repo_ref = ? AND relative_path = ? AND branches = ? AND file_hash != ?",
+            repo_str,
+            self.relative_path,
+            self.branches_hash,
+            self.file_cache_key
+        }
+        .execute(&mut *tx)
+        .await?;
+
+        let delete_size = to_delete.len();
+        if !to_delete.is_empty() {
+            self.semantic
+                .store()
                 .delete_points(
-                    self.semantic.collection_name(),
+                    &self.semantic.collection_for(self.reporef),
                     &to_delete
                         .into_iter()
                         .map(PointId::from)
                         .collect::<Vec<_>>()
                         .into(),
-                    None,
                 )
                 .await?;
         }
@@ -451,6 +637,7 @@ impl<'a> ChunkCache<'a> {
     ) -> Result<usize, anyhow::Error> {
         let mut update_size = 0;
         let mut qdrant_updates = vec![];
+        let collection_name = &self.semantic.collection_for(self.reporef);
 
         let mut next = self.update.first_occupied_entry();
         while let Some(entry) = next {
@@ -482,8 +669,8 @@ impl<'a> ChunkCache<'a> {
 
             qdrant_updates.push(async move {
                 self.semantic
-                    .qdrant_client()
-                    .set_payload(self.semantic.collection_name(), &id, payload, None)
+                    .store()
+                    .set_payload(collection_name, &id, payload)
                     .await
             });
             next = entry.next();
@@ -507,15 +694,19 @@ impl<'a> ChunkCache<'a> {
         self.file_cache_key.to_string()
     }
 
-    /// Generate a content hash from the embedding data, and pin it to
-    /// the containing file's content id.
-    fn cache_key(&self, data: &str, payload: &Payload) -> String {
+    /// Generate a content hash from the embedding data, independent
+    /// of where in the file the chunk is, or what else the file holds.
+    ///
+    /// The branches are part of the key, so versions of a file on
+    /// different branches don't share points.
+    fn cache_key(&self, data: &str, occurrence: usize) -> String {
         let id = {
             let mut bytes = [0; 16];
             let mut hasher = blake3::Hasher::new();
-            hasher.update(&payload.start_line.to_le_bytes());
-            hasher.update(&payload.end_line.to_le_bytes());
-            hasher.update(self.file_cache_key.as_bytes());
+            hasher.update(self.reporef.to_string().as_bytes());
+            hasher.update(self.model_id.as_bytes());
+            hasher.update(self.branches_hash.as_bytes());
+            hasher.update(&occurrence.to_le_bytes());
             hasher.update(data.as_ref());
             bytes.copy_from_slice(&hasher.finalize().as_bytes()[16..32]);
             Uuid::from_bytes(bytes).to_string()
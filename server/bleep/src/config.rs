@@ -1,11 +1,22 @@
-use crate::state::StateSource;
+use crate::{
+    agent::{map_reduce::AnswerMode, persona::Persona},
+    llm_gateway::api::{Backend, Provider},
+    semantic::{
+        chunk::{ChunkParams, ChunkStrategy, OverlapStrategy},
+        embedder::Accelerator,
+        reduction::Method,
+        CollectionLayout,
+    },
+    state::StateSource,
+};
 use anyhow::{Context, Result};
 use clap::Parser;
 
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize, Serializer};
 use std::{
-    num::NonZeroUsize,
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroUsize},
     path::{Path, PathBuf},
 };
 
@@ -76,6 +87,32 @@ pub struct Configuration {
     /// Bind the webserver to `<host>`
     pub port: u16,
 
+    #[clap(long, default_value_t = default_answer_concurrency_limit())]
+    #[serde(default = "default_answer_concurrency_limit")]
+    /// Maximum number of answer requests that are handled at once. Further requests wait
+    pub answer_concurrency_limit: NonZeroUsize,
+
+    #[clap(long, default_value_t = default_file_concurrency_limit())]
+    #[serde(default = "default_file_concurrency_limit")]
+    /// Maximum number of requests that read files from the index, like `/file`, that are handled
+    /// at once. Further requests wait, so that indexing is not starved
+    pub file_concurrency_limit: NonZeroUsize,
+
+    #[clap(long, default_value_t = default_bulk_concurrency_limit())]
+    #[serde(default = "default_bulk_concurrency_limit")]
+    /// Maximum number of bulk requests, like answer bundles, that are handled at once. These
+    /// also count towards `file_concurrency_limit`
+    pub bulk_concurrency_limit: NonZeroUsize,
+
+    #[clap(long)]
+    /// Maximum number of answers each user can ask for per minute, on average. Unlimited if unset
+    pub answer_rate_limit: Option<NonZeroU32>,
+
+    #[clap(long)]
+    /// Maximum number of answers each user can ask for in a burst, before `answer_rate_limit`
+    /// applies. Defaults to `answer_rate_limit`
+    pub answer_burst_limit: Option<NonZeroU32>,
+
     //
     // External dependencies
     //
@@ -84,6 +121,55 @@ pub struct Configuration {
     /// URL for the answer-api
     pub answer_api_url: String,
 
+    #[clap(long, value_enum, default_value_t = Provider::default())]
+    #[serde(default)]
+    /// The LLM provider that the answer-api forwards requests to
+    pub llm_provider: Provider,
+
+    #[clap(long, value_enum, default_value_t = Backend::default())]
+    #[serde(default)]
+    /// Where LLM requests are sent. `gateway` uses the answer-api, while `openai`, `azure` and
+    /// `local` call an OpenAI-compatible API directly at `llm_api_url`, for self-hosted installs.
+    /// `openai-compatible` does too, discovering the models and capabilities of any gateway
+    pub llm_backend: Backend,
+
+    #[clap(long)]
+    /// Base URL of a direct LLM backend. Defaults to the OpenAI API for `openai`, and to
+    /// `http://127.0.0.1:8080` for `local`. Azure OpenAI and `openai-compatible` gateways need
+    /// their URL
+    pub llm_api_url: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// API key of a direct LLM backend
+    pub llm_api_key: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Stop sequences sent with every LLM request, in addition to those the provider needs
+    pub llm_stop_sequences: Vec<String>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Context window sizes of LLMs as `model=tokens`, e.g. `gpt-4-0613=32768`, overriding the
+    /// built-in sizes
+    pub llm_context_sizes: Vec<String>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Prices of LLMs in USD per 1000 prompt and completion tokens as `model=prompt/completion`,
+    /// e.g. `gpt-4-0613=0.03/0.06`, overriding the built-in prices
+    pub llm_prices: Vec<String>,
+
+    #[clap(long)]
+    /// A vision-capable LLM that reads code and text from images attached to queries
+    pub vision_model: Option<String>,
+
+    #[clap(long)]
+    /// Command that reads an image from stdin and prints its text, e.g. `tesseract stdin stdout`.
+    /// Used for images attached to queries when `vision_model` is not set
+    pub ocr_command: Option<String>,
+
     #[clap(long)]
     /// Key for analytics backend
     pub analytics_key: Option<String>,
@@ -112,19 +198,104 @@ pub struct Configuration {
     // Semantic values
     //
     #[clap(long)]
-    /// URL for the qdrant server
+    /// URL for the qdrant server. Without it, embeddings are stored in the index directory
     pub qdrant_url: Option<String>,
 
+    #[clap(long)]
+    /// The size of the candidate list of qdrant's HNSW search. Larger values are more accurate
+    /// and slower. Without it, qdrant uses the `ef_construct` of the collection
+    pub qdrant_hnsw_ef: Option<u64>,
+
     #[clap(long, default_value_os_t = default_model_dir())]
     #[serde(default = "default_model_dir")]
-    /// Path to the embedding model directory
+    /// Path to the embedding model directory, with `model.onnx` and `tokenizer.json`. When the
+    /// model is replaced, for instance with one fine-tuned on an exported corpus, the semantic
+    /// index is re-embedded with it
     pub model_dir: PathBuf,
 
+    #[clap(skip)]
+    #[serde(default)]
+    /// More embedding models by name, each a directory with `model.onnx` and `tokenizer.json`.
+    /// The model in `model_dir` is named `default`
+    pub embedding_models: HashMap<String, PathBuf>,
+
+    #[clap(skip)]
+    #[serde(default)]
+    /// The embedding model of repositories by their indexed name, e.g.
+    /// `{"github.com/org/docs": "prose"}`, in the `per-repo` collection layout. Other repositories
+    /// use the `default` model. When the model of a repository changes, its files are re-embedded
+    /// as they are indexed
+    pub repo_embedding_models: HashMap<String, String>,
+
+    #[clap(long, value_enum, default_value_t = Accelerator::default())]
+    #[serde(default)]
+    /// The hardware that local embedding models run on. `auto` picks the first of `cuda`,
+    /// `coreml` and `directml` that the ONNX runtime supports on this machine. Embeddings are
+    /// computed on the CPU when the accelerator isn't available
+    pub embedding_accelerator: Accelerator,
+
     #[clap(long, default_value_t = default_max_chunk_tokens())]
     #[serde(default = "default_max_chunk_tokens")]
     /// Maximum number of tokens in a chunk (should be the model's input size)
     pub max_chunk_tokens: usize,
 
+    #[clap(long)]
+    /// Path to a cross-encoder model directory, with `model.onnx` and `tokenizer.json`, to rerank
+    /// semantic search results by relevance to the query. Results are ordered by embedding
+    /// similarity without it
+    pub reranker_model_dir: Option<PathBuf>,
+
+    #[clap(skip)]
+    #[serde(default)]
+    /// Chunk size and overlap per language, keyed by lowercase language name, e.g.
+    /// `{"rust": {"max_tokens": 192, "overlap": "50%"}}`. Languages without an entry use built-in
+    /// defaults. Sizes are capped at `max_chunk_tokens`, and files are re-chunked as they change
+    pub chunk_params: HashMap<String, ChunkParams>,
+
+    #[clap(long, value_enum, default_value_t = ChunkStrategy::default())]
+    #[serde(default)]
+    /// How files are split into chunks, unless `chunk_params` picks a strategy for the language.
+    /// `content-defined` chunks keep their boundaries when the file is edited elsewhere, so
+    /// incremental syncs re-embed far fewer chunks. `lines` splits files into windows of
+    /// `chunk_lines` lines, and `scopes` splits them between functions and classes where
+    /// tree-sitter parses the language
+    pub chunk_strategy: ChunkStrategy,
+
+    #[clap(long, default_value_t = OverlapStrategy::default(), value_parser = parse_overlap)]
+    #[serde(default)]
+    /// Overlap of `tokens` chunks, as a number of lines or a percentage of the chunk, for
+    /// languages without built-in defaults or an entry in `chunk_params`
+    pub chunk_overlap: OverlapStrategy,
+
+    #[clap(long, default_value_t = default_chunk_lines())]
+    #[serde(default = "default_chunk_lines")]
+    /// Number of lines in each chunk of the `lines` strategy
+    pub chunk_lines: usize,
+
+    #[clap(long, default_value_t = default_chunk_stride())]
+    #[serde(default = "default_chunk_stride")]
+    /// Number of lines between the starts of chunks of the `lines` strategy. Chunks overlap when
+    /// it is smaller than `chunk_lines`
+    pub chunk_stride: usize,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Gitignore-style globs of files that are left out of semantic search, in addition to
+    /// vendored directories, lockfiles, and minified and generated code
+    pub semantic_exclusions: Vec<String>,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Only leave the files of `semantic_exclusions` out of semantic search, and keep vendored
+    /// directories, lockfiles and generated code in it
+    pub disable_default_semantic_exclusions: bool,
+
+    #[clap(long, default_value_t = default_semantic_max_avg_line_length())]
+    #[serde(default = "default_semantic_max_avg_line_length")]
+    /// Files with longer lines on average are considered minified, and left out of semantic
+    /// search. 0 keeps them in
+    pub semantic_max_avg_line_length: usize,
+
     #[clap(long, default_value_t = default_collection_name())]
     #[serde(default = "default_collection_name")]
     /// Qdrant collection name. Defaults to `documents`
@@ -135,6 +306,49 @@ pub struct Configuration {
     /// Batch size for batched embeddings
     pub embedding_batch_size: NonZeroUsize,
 
+    #[clap(long, default_value_t = default_reindex_batch_size())]
+    #[serde(default = "default_reindex_batch_size")]
+    /// Number of repositories that a bulk re-index rebuilds at once
+    pub reindex_batch_size: NonZeroUsize,
+
+    #[clap(long, default_value_t = default_embedding_concurrency())]
+    #[serde(default = "default_embedding_concurrency")]
+    /// The maximum number of embedding batches computed at once, across all repositories being
+    /// indexed. Indexing waits for a batch to finish before it queues more chunks
+    pub embedding_concurrency: NonZeroUsize,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Reduce embeddings to this many dimensions before they are stored, trading a little recall
+    /// for memory. Changing the reduction of a collection re-indexes it
+    pub embedding_dims: Option<usize>,
+
+    #[clap(long, value_enum, default_value_t = Method::default())]
+    #[serde(default)]
+    /// How embeddings are reduced to `embedding_dims`. `truncate` keeps the leading dimensions,
+    /// which suits Matryoshka models. `pca` projects onto principal components fit on a
+    /// full-size index, so the first index of a collection is built full-size and reduced on the
+    /// next start
+    pub embedding_reduction: Method,
+
+    #[clap(long, value_enum, default_value_t = CollectionLayout::default())]
+    #[serde(default)]
+    /// How embeddings are split into qdrant collections. `per-repo` keeps each repository in its
+    /// own collection, which keeps searches scoped to a few repositories fast on large installs.
+    /// Changing the layout re-indexes all repositories
+    pub collection_layout: CollectionLayout,
+
+    #[clap(long, default_value_t = default_max_snippets_per_file())]
+    #[serde(default = "default_max_snippets_per_file")]
+    /// Most semantic search results from one file. Overlapping and adjacent results of a file are
+    /// merged into one before they are counted
+    pub max_snippets_per_file: usize,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Most semantic search results in total, which lowers the limits that searches ask for
+    pub max_snippets: Option<usize>,
+
     //
     // Cognito setup
     //
@@ -175,11 +389,236 @@ pub struct Configuration {
     /// Path to a GitHub private key file, for signing access token requests
     pub github_app_private_key: Option<PathBuf>,
 
+    #[clap(long, default_value_t = default_gitlab_url())]
+    #[serde(default = "default_gitlab_url")]
+    /// Base URL of the GitLab instance that repositories are indexed from
+    pub gitlab_url: String,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// GitLab access token with the `read_api` and `read_repository` scopes, which connects
+    /// GitLab for the whole instance
+    pub gitlab_token: Option<SecretString>,
+
+    #[clap(long)]
+    /// Application ID of an OAuth application on the GitLab instance, for users to connect
+    /// GitLab through OAuth
+    pub gitlab_client_id: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Secret of the GitLab OAuth application
+    pub gitlab_client_secret: Option<SecretString>,
+
+    #[clap(long, default_value_t = default_credential_expiry_warning_days())]
+    #[serde(default = "default_credential_expiry_warning_days")]
+    /// Number of days before the remote credentials expire from which users are warned, if the
+    /// credentials cannot be renewed
+    pub credential_expiry_warning_days: u64,
+
     #[clap(long)]
     #[serde(serialize_with = "serialize_secret_opt_str", default)]
     /// Bot secret token
     pub bot_secret: Option<SecretString>,
 
+    #[clap(long)]
+    /// Path to a JSON file with guardrail rules for questions and answers
+    pub policy_file: Option<PathBuf>,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Refuse to answer when the code sent to the LLM contains secrets that look real, instead of
+    /// only redacting them
+    pub block_secrets: bool,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// SPDX identifiers of licenses whose code is never used in answers, e.g. `GPL-3.0`
+    pub excluded_licenses: Vec<String>,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Disable boosting search results from files that were useful in earlier answers
+    pub disable_usage_boost: bool,
+
+    #[clap(skip)]
+    #[serde(default)]
+    /// The name, description and example questions that the assistant introduces itself with,
+    /// e.g. `{"name": "Ada", "examples": ["Where is the router?"]}`
+    pub persona: Persona,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Reply to greetings and questions about the assistant with the LLM, instead of from the
+    /// `intro` template
+    pub disable_canned_replies: bool,
+
+    #[clap(long, default_value_t = 0)]
+    #[serde(default)]
+    /// The number of paraphrases of each code search that the LLM writes, at most 3. Their
+    /// results are fused with those of the search, which helps when questions aren't worded like
+    /// the code. Disabled when 0
+    pub query_paraphrases: usize,
+
+    #[clap(long, default_value_t = default_usage_half_life_days())]
+    #[serde(default = "default_usage_half_life_days")]
+    /// Number of days after which the usage of a file counts half as much towards its boost
+    pub usage_half_life_days: f32,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Words that are dropped from lexical search queries, e.g. `the`
+    pub search_stopwords: Vec<String>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Groups of words that lexical search treats as synonyms, e.g. `k8s,kubernetes`
+    pub search_synonyms: Vec<String>,
+
+    #[clap(long, default_value_t = default_search_path_boost())]
+    #[serde(default = "default_search_path_boost")]
+    /// Weight of path matches in the ranking of lexical search results
+    pub search_path_boost: f32,
+
+    #[clap(long, default_value_t = default_search_content_boost())]
+    #[serde(default = "default_search_content_boost")]
+    /// Weight of content matches in the ranking of lexical search results
+    pub search_content_boost: f32,
+
+    #[clap(long, default_value_t = default_search_symbol_boost())]
+    #[serde(default = "default_search_symbol_boost")]
+    /// Weight of symbol matches in the ranking of lexical search results
+    pub search_symbol_boost: f32,
+
+    #[clap(long, default_value_t = default_deep_max_steps())]
+    #[serde(default = "default_deep_max_steps")]
+    /// Maximum number of tool calls the agent makes for a single deep answer
+    pub deep_max_steps: usize,
+
+    #[clap(long, default_value_t = default_deep_max_tokens())]
+    #[serde(default = "default_deep_max_tokens")]
+    /// Maximum number of prompt tokens the agent sends while calling tools for a single deep answer
+    pub deep_max_tokens: usize,
+
+    #[clap(long, default_value_t = default_answer_timeout_secs())]
+    #[serde(default = "default_answer_timeout_secs")]
+    /// Maximum number of seconds a single answer can take, from the question to the last update
+    pub answer_timeout_secs: u64,
+
+    #[clap(long, default_value_t = default_answer_stage_timeout_secs())]
+    #[serde(default = "default_answer_stage_timeout_secs")]
+    /// Maximum number of seconds a stage of an answer, like a search or a model response, can go
+    /// without making progress
+    pub answer_stage_timeout_secs: u64,
+
+    #[clap(long, default_value_t = default_answer_stall_timeout_secs())]
+    #[serde(default = "default_answer_stall_timeout_secs")]
+    /// Maximum number of seconds the answer model can go without sending a token, before the
+    /// answer is generated again from the same prompt. A second stall fails the stage. This should
+    /// be shorter than `answer_stage_timeout_secs`
+    pub answer_stall_timeout_secs: u64,
+
+    #[clap(long, default_value_t = default_answer_heartbeat_secs())]
+    #[serde(default = "default_answer_heartbeat_secs")]
+    /// Number of seconds between heartbeat events of streamed answers, which keep proxies from
+    /// closing connections that go quiet while an answer is prepared
+    pub answer_heartbeat_secs: u64,
+
+    #[clap(long, value_enum, default_value_t = AnswerMode::default())]
+    #[serde(default)]
+    /// How answers are written from the code that was found. `single` explains all of it in one
+    /// response. `map-reduce` explains the top snippets of broad questions concurrently, then
+    /// combines the explanations into one answer
+    pub answer_mode: AnswerMode,
+
+    #[clap(long, default_value_t = default_map_reduce_max_tokens())]
+    #[serde(default = "default_map_reduce_max_tokens")]
+    /// Maximum number of tokens, prompts and responses together, spent explaining the snippets of
+    /// a single answer in `map-reduce` mode
+    pub map_reduce_max_tokens: usize,
+
+    #[clap(long)]
+    /// Maximum number of LLM tokens each user can spend on answers in a day
+    pub user_daily_token_quota: Option<usize>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Users who can see the token usage of every user, in addition to `admins`
+    pub usage_admins: Vec<String>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Users who can change settings that apply to every user, like federation peers, pins and
+    /// retrieval-only repositories. The user of a local install is always an admin
+    pub admins: Vec<String>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Delete conversations that weren't continued for this many days. Kept forever if not set
+    pub conversation_retention_days: Option<u64>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Delete the query log, snippet usage and token usage older than this many days. Kept forever
+    /// if not set
+    pub query_event_retention_days: Option<u64>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Delete policy audit entries older than this many days. Kept forever if not set
+    pub audit_log_retention_days: Option<u64>,
+
+    #[clap(long, default_value_t = default_log_retention_days())]
+    #[serde(default = "default_log_retention_days")]
+    /// Number of days of log files that are kept on disk
+    pub log_retention_days: u64,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Only report the data that is past its retention window, instead of deleting it
+    pub retention_dry_run: bool,
+
+    #[clap(long, default_value_t = default_answer_cache_ttl_secs())]
+    #[serde(default = "default_answer_cache_ttl_secs")]
+    /// Number of seconds an answer is reused for the same question about an unchanged repository.
+    /// Set to 0 to disable the cache
+    pub answer_cache_ttl_secs: u64,
+
+    #[clap(long)]
+    /// Path to a file that replaces the rules of the agent's system prompt for a share of queries,
+    /// until it regresses
+    pub prompt_template: Option<PathBuf>,
+
+    #[clap(long, default_value_t = default_prompt_canary_percent())]
+    #[serde(default = "default_prompt_canary_percent")]
+    /// Percentage of queries that are answered with `prompt_template`
+    pub prompt_canary_percent: u8,
+
+    #[clap(long, default_value_t = default_prompt_canary_min_samples())]
+    #[serde(default = "default_prompt_canary_min_samples")]
+    /// Number of agent steps, and of votes, needed before `prompt_template` can be rolled back
+    pub prompt_canary_min_samples: u64,
+
+    #[clap(long, default_value_t = default_prompt_canary_max_failure_increase())]
+    #[serde(default = "default_prompt_canary_max_failure_increase")]
+    /// Increase in the rate of unparseable function calls that rolls back `prompt_template`
+    pub prompt_canary_max_failure_increase: f64,
+
+    #[clap(long, default_value_t = default_prompt_canary_max_downvote_increase())]
+    #[serde(default = "default_prompt_canary_max_downvote_increase")]
+    /// Increase in the rate of downvoted answers that rolls back `prompt_template`
+    pub prompt_canary_max_downvote_increase: f64,
+
+    #[clap(long)]
+    /// Directory of prompt templates, like `explain.jinja`, that replace the built-in prompts.
+    /// Changes are picked up without a restart
+    pub prompt_templates_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Directory of WASM modules that filter retrieved code before it is added to the context of
+    /// answers, run in the order of their file names
+    pub snippet_filters_dir: Option<PathBuf>,
+
     //
     // Cloud deployment values
     //
@@ -226,6 +665,12 @@ impl Configuration {
         Some((id, secret))
     }
 
+    pub fn gitlab_client_id_and_secret(&self) -> Option<(&str, &str)> {
+        let id = self.gitlab_client_id.as_deref()?;
+        let secret = self.gitlab_client_secret.as_ref()?.expose_secret();
+        Some((id, secret))
+    }
+
     pub fn cli_overriding_config_file() -> Result<Self> {
         let cli = Self::from_cli()?;
         let Ok(file) = cli
@@ -277,6 +722,28 @@ impl Configuration {
 
             port: right_if_default!(b.port, a.port, default_port()),
 
+            answer_concurrency_limit: right_if_default!(
+                b.answer_concurrency_limit,
+                a.answer_concurrency_limit,
+                default_answer_concurrency_limit()
+            ),
+
+            file_concurrency_limit: right_if_default!(
+                b.file_concurrency_limit,
+                a.file_concurrency_limit,
+                default_file_concurrency_limit()
+            ),
+
+            bulk_concurrency_limit: right_if_default!(
+                b.bulk_concurrency_limit,
+                a.bulk_concurrency_limit,
+                default_bulk_concurrency_limit()
+            ),
+
+            answer_rate_limit: b.answer_rate_limit.or(a.answer_rate_limit),
+
+            answer_burst_limit: b.answer_burst_limit.or(a.answer_burst_limit),
+
             model_dir: right_if_default!(b.model_dir, a.model_dir, default_model_dir()),
 
             max_chunk_tokens: right_if_default!(
@@ -285,6 +752,63 @@ impl Configuration {
                 default_max_chunk_tokens()
             ),
 
+            embedding_models: if b.embedding_models.is_empty() {
+                a.embedding_models
+            } else {
+                b.embedding_models
+            },
+
+            repo_embedding_models: if b.repo_embedding_models.is_empty() {
+                a.repo_embedding_models
+            } else {
+                b.repo_embedding_models
+            },
+
+            embedding_accelerator: right_if_default!(
+                b.embedding_accelerator,
+                a.embedding_accelerator,
+                Accelerator::default()
+            ),
+
+            reranker_model_dir: b.reranker_model_dir.or(a.reranker_model_dir),
+
+            chunk_params: if b.chunk_params.is_empty() {
+                a.chunk_params
+            } else {
+                b.chunk_params
+            },
+
+            chunk_strategy: right_if_default!(
+                b.chunk_strategy,
+                a.chunk_strategy,
+                ChunkStrategy::default()
+            ),
+
+            chunk_overlap: right_if_default!(
+                b.chunk_overlap,
+                a.chunk_overlap,
+                OverlapStrategy::default()
+            ),
+
+            chunk_lines: right_if_default!(b.chunk_lines, a.chunk_lines, default_chunk_lines()),
+
+            chunk_stride: right_if_default!(b.chunk_stride, a.chunk_stride, default_chunk_stride()),
+
+            semantic_exclusions: right_if_default!(
+                b.semantic_exclusions,
+                a.semantic_exclusions,
+                Vec::<String>::new()
+            ),
+
+            disable_default_semantic_exclusions: b.disable_default_semantic_exclusions
+                | a.disable_default_semantic_exclusions,
+
+            semantic_max_avg_line_length: right_if_default!(
+                b.semantic_max_avg_line_length,
+                a.semantic_max_avg_line_length,
+                default_semantic_max_avg_line_length()
+            ),
+
             collection_name: right_if_default!(
                 b.collection_name,
                 a.collection_name,
@@ -297,18 +821,80 @@ impl Configuration {
                 interactive_batch_size()
             ),
 
+            reindex_batch_size: right_if_default!(
+                b.reindex_batch_size,
+                a.reindex_batch_size,
+                default_reindex_batch_size()
+            ),
+
+            embedding_concurrency: right_if_default!(
+                b.embedding_concurrency,
+                a.embedding_concurrency,
+                default_embedding_concurrency()
+            ),
+
+            embedding_dims: b.embedding_dims.or(a.embedding_dims),
+
+            embedding_reduction: right_if_default!(
+                b.embedding_reduction,
+                a.embedding_reduction,
+                Method::default()
+            ),
+
+            collection_layout: right_if_default!(
+                b.collection_layout,
+                a.collection_layout,
+                CollectionLayout::default()
+            ),
+
+            max_snippets_per_file: right_if_default!(
+                b.max_snippets_per_file,
+                a.max_snippets_per_file,
+                default_max_snippets_per_file()
+            ),
+
+            max_snippets: b.max_snippets.or(a.max_snippets),
+
             embedding_server_url: b.embedding_server_url.or(a.embedding_server_url),
 
             frontend_dist: b.frontend_dist.or(a.frontend_dist),
 
             qdrant_url: b.qdrant_url.or(a.qdrant_url),
 
+            qdrant_hnsw_ef: b.qdrant_hnsw_ef.or(a.qdrant_hnsw_ef),
+
             answer_api_url: right_if_default!(
                 b.answer_api_url,
                 a.answer_api_url,
                 default_answer_api_url()
             ),
 
+            llm_provider: right_if_default!(b.llm_provider, a.llm_provider, Provider::default()),
+
+            llm_backend: right_if_default!(b.llm_backend, a.llm_backend, Backend::default()),
+
+            llm_api_url: b.llm_api_url.or(a.llm_api_url),
+
+            llm_api_key: b.llm_api_key.or(a.llm_api_key),
+
+            llm_stop_sequences: right_if_default!(
+                b.llm_stop_sequences,
+                a.llm_stop_sequences,
+                Vec::<String>::new()
+            ),
+
+            llm_context_sizes: right_if_default!(
+                b.llm_context_sizes,
+                a.llm_context_sizes,
+                Vec::<String>::new()
+            ),
+
+            llm_prices: right_if_default!(b.llm_prices, a.llm_prices, Vec::<String>::new()),
+
+            vision_model: b.vision_model.or(a.vision_model),
+
+            ocr_command: b.ocr_command.or(a.ocr_command),
+
             cognito_userpool_id: b.cognito_userpool_id.or(a.cognito_userpool_id),
 
             cognito_client_id: b.cognito_client_id.or(a.cognito_client_id),
@@ -327,10 +913,179 @@ impl Configuration {
 
             github_app_private_key: b.github_app_private_key.or(a.github_app_private_key),
 
+            gitlab_url: right_if_default!(b.gitlab_url, a.gitlab_url, default_gitlab_url()),
+
+            gitlab_token: b.gitlab_token.or(a.gitlab_token),
+
+            gitlab_client_id: b.gitlab_client_id.or(a.gitlab_client_id),
+
+            gitlab_client_secret: b.gitlab_client_secret.or(a.gitlab_client_secret),
+
+            credential_expiry_warning_days: right_if_default!(
+                b.credential_expiry_warning_days,
+                a.credential_expiry_warning_days,
+                default_credential_expiry_warning_days()
+            ),
+
             instance_domain: b.instance_domain.or(a.instance_domain),
 
             bot_secret: b.bot_secret.or(a.bot_secret),
 
+            policy_file: b.policy_file.or(a.policy_file),
+
+            block_secrets: b.block_secrets | a.block_secrets,
+
+            excluded_licenses: right_if_default!(
+                b.excluded_licenses,
+                a.excluded_licenses,
+                Vec::<String>::new()
+            ),
+
+            disable_usage_boost: b.disable_usage_boost | a.disable_usage_boost,
+
+            persona: right_if_default!(b.persona, a.persona, Persona::default()),
+
+            disable_canned_replies: b.disable_canned_replies | a.disable_canned_replies,
+
+            query_paraphrases: right_if_default!(b.query_paraphrases, a.query_paraphrases, 0),
+
+            usage_half_life_days: right_if_default!(
+                b.usage_half_life_days,
+                a.usage_half_life_days,
+                default_usage_half_life_days()
+            ),
+
+            search_stopwords: right_if_default!(
+                b.search_stopwords,
+                a.search_stopwords,
+                Vec::<String>::new()
+            ),
+
+            search_synonyms: right_if_default!(
+                b.search_synonyms,
+                a.search_synonyms,
+                Vec::<String>::new()
+            ),
+
+            search_path_boost: right_if_default!(
+                b.search_path_boost,
+                a.search_path_boost,
+                default_search_path_boost()
+            ),
+
+            search_content_boost: right_if_default!(
+                b.search_content_boost,
+                a.search_content_boost,
+                default_search_content_boost()
+            ),
+
+            search_symbol_boost: right_if_default!(
+                b.search_symbol_boost,
+                a.search_symbol_boost,
+                default_search_symbol_boost()
+            ),
+
+            deep_max_steps: right_if_default!(
+                b.deep_max_steps,
+                a.deep_max_steps,
+                default_deep_max_steps()
+            ),
+
+            deep_max_tokens: right_if_default!(
+                b.deep_max_tokens,
+                a.deep_max_tokens,
+                default_deep_max_tokens()
+            ),
+
+            answer_timeout_secs: right_if_default!(
+                b.answer_timeout_secs,
+                a.answer_timeout_secs,
+                default_answer_timeout_secs()
+            ),
+
+            answer_stage_timeout_secs: right_if_default!(
+                b.answer_stage_timeout_secs,
+                a.answer_stage_timeout_secs,
+                default_answer_stage_timeout_secs()
+            ),
+
+            answer_stall_timeout_secs: right_if_default!(
+                b.answer_stall_timeout_secs,
+                a.answer_stall_timeout_secs,
+                default_answer_stall_timeout_secs()
+            ),
+
+            answer_heartbeat_secs: right_if_default!(
+                b.answer_heartbeat_secs,
+                a.answer_heartbeat_secs,
+                default_answer_heartbeat_secs()
+            ),
+
+            answer_mode: right_if_default!(b.answer_mode, a.answer_mode, AnswerMode::default()),
+
+            map_reduce_max_tokens: right_if_default!(
+                b.map_reduce_max_tokens,
+                a.map_reduce_max_tokens,
+                default_map_reduce_max_tokens()
+            ),
+
+            user_daily_token_quota: b.user_daily_token_quota.or(a.user_daily_token_quota),
+            usage_admins: right_if_default!(b.usage_admins, a.usage_admins, Vec::<String>::new()),
+            admins: right_if_default!(b.admins, a.admins, Vec::<String>::new()),
+
+            conversation_retention_days: b
+                .conversation_retention_days
+                .or(a.conversation_retention_days),
+
+            query_event_retention_days: b
+                .query_event_retention_days
+                .or(a.query_event_retention_days),
+
+            audit_log_retention_days: b.audit_log_retention_days.or(a.audit_log_retention_days),
+
+            log_retention_days: right_if_default!(
+                b.log_retention_days,
+                a.log_retention_days,
+                default_log_retention_days()
+            ),
+
+            retention_dry_run: b.retention_dry_run | a.retention_dry_run,
+
+            answer_cache_ttl_secs: right_if_default!(
+                b.answer_cache_ttl_secs,
+                a.answer_cache_ttl_secs,
+                default_answer_cache_ttl_secs()
+            ),
+
+            prompt_template: b.prompt_template.or(a.prompt_template),
+
+            prompt_canary_percent: right_if_default!(
+                b.prompt_canary_percent,
+                a.prompt_canary_percent,
+                default_prompt_canary_percent()
+            ),
+
+            prompt_canary_min_samples: right_if_default!(
+                b.prompt_canary_min_samples,
+                a.prompt_canary_min_samples,
+                default_prompt_canary_min_samples()
+            ),
+
+            prompt_canary_max_failure_increase: right_if_default!(
+                b.prompt_canary_max_failure_increase,
+                a.prompt_canary_max_failure_increase,
+                default_prompt_canary_max_failure_increase()
+            ),
+
+            prompt_canary_max_downvote_increase: right_if_default!(
+                b.prompt_canary_max_downvote_increase,
+                a.prompt_canary_max_downvote_increase,
+                default_prompt_canary_max_downvote_increase()
+            ),
+
+            prompt_templates_dir: b.prompt_templates_dir.or(a.prompt_templates_dir),
+            snippet_filters_dir: b.snippet_filters_dir.or(a.snippet_filters_dir),
+
             analytics_key: b.analytics_key.or(a.analytics_key),
             analytics_key_fe: b.analytics_key_fe.or(a.analytics_key_fe),
 
@@ -388,6 +1143,10 @@ fn default_collection_name() -> String {
     "documents".into()
 }
 
+const fn default_max_snippets_per_file() -> usize {
+    3
+}
+
 pub fn default_parallelism() -> usize {
     std::thread::available_parallelism().unwrap().get()
 }
@@ -408,10 +1167,26 @@ const fn default_port() -> u16 {
     7878
 }
 
+fn default_answer_concurrency_limit() -> NonZeroUsize {
+    NonZeroUsize::new(32).unwrap()
+}
+
+fn default_file_concurrency_limit() -> NonZeroUsize {
+    NonZeroUsize::new(16).unwrap()
+}
+
+fn default_bulk_concurrency_limit() -> NonZeroUsize {
+    NonZeroUsize::new(2).unwrap()
+}
+
 fn default_host() -> String {
     String::from("127.0.0.1")
 }
 
+fn default_gitlab_url() -> String {
+    String::from("https://gitlab.com")
+}
+
 fn default_answer_api_url() -> String {
     String::from("http://127.0.0.1:7879")
 }
@@ -420,6 +1195,102 @@ fn default_max_chunk_tokens() -> usize {
     256
 }
 
+fn default_chunk_lines() -> usize {
+    40
+}
+
+fn default_semantic_max_avg_line_length() -> usize {
+    200
+}
+
+fn default_chunk_stride() -> usize {
+    30
+}
+
+fn parse_overlap(overlap: &str) -> Result<OverlapStrategy, &'static str> {
+    OverlapStrategy::try_from(overlap)
+}
+
+fn default_usage_half_life_days() -> f32 {
+    14.0
+}
+
+fn default_search_path_boost() -> f32 {
+    10.0
+}
+
+fn default_search_content_boost() -> f32 {
+    1.0
+}
+
+fn default_search_symbol_boost() -> f32 {
+    1.0
+}
+
+const fn default_deep_max_steps() -> usize {
+    20
+}
+
+const fn default_deep_max_tokens() -> usize {
+    200_000
+}
+
+const fn default_answer_timeout_secs() -> u64 {
+    5 * 60
+}
+
+const fn default_answer_stage_timeout_secs() -> u64 {
+    60
+}
+
+const fn default_answer_stall_timeout_secs() -> u64 {
+    25
+}
+
+const fn default_answer_heartbeat_secs() -> u64 {
+    10
+}
+
+const fn default_map_reduce_max_tokens() -> usize {
+    8000
+}
+
+const fn default_answer_cache_ttl_secs() -> u64 {
+    60 * 60
+}
+
+const fn default_log_retention_days() -> u64 {
+    7
+}
+
+const fn default_credential_expiry_warning_days() -> u64 {
+    7
+}
+
+const fn default_prompt_canary_percent() -> u8 {
+    10
+}
+
+const fn default_prompt_canary_min_samples() -> u64 {
+    50
+}
+
+fn default_prompt_canary_max_failure_increase() -> f64 {
+    0.05
+}
+
+fn default_prompt_canary_max_downvote_increase() -> f64 {
+    0.2
+}
+
+fn default_reindex_batch_size() -> NonZeroUsize {
+    NonZeroUsize::new(2).unwrap()
+}
+
 fn interactive_batch_size() -> NonZeroUsize {
     NonZeroUsize::new(1).unwrap()
 }
+
+fn default_embedding_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(4).unwrap()
+}
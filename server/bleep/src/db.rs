@@ -6,8 +6,36 @@ use tracing::{debug, warn};
 
 use crate::Configuration;
 
+mod answer_feedback;
+mod bookmarks;
+mod bulk_reindex;
+mod embedding_reductions;
+mod last_seen;
+mod policy_audit;
+mod precise_symbols;
+mod prompt_rollbacks;
 mod query_log;
+mod repo_renames;
+mod retention;
+mod semantic_pins;
+mod snippet_usage;
+mod token_usage;
+mod user_profiles;
+pub use answer_feedback::{AnswerFeedback, NewFeedback};
+pub use bookmarks::{Bookmark, Bookmarks, NewBookmark};
+pub use bulk_reindex::{BulkReindex, ReindexEntry};
+pub use embedding_reductions::EmbeddingReductions;
+pub use last_seen::LastSeen;
+pub use policy_audit::{AuditEntry, PolicyAudit};
+pub use precise_symbols::{PreciseOccurrence, PreciseSymbols};
+pub use prompt_rollbacks::PromptRollbacks;
 pub use query_log::QueryLog;
+pub use repo_renames::RepoRenames;
+pub use retention::Retention;
+pub use semantic_pins::{SemanticPin, SemanticPins};
+pub use snippet_usage::{Signal, SnippetUsage, UsageEvent};
+pub use token_usage::{TokenUsage, UsageRecord};
+pub use user_profiles::{ProfileEntry, ProfileKind, UserProfiles};
 
 pub type SqlDb = Arc<SqlitePool>;
 
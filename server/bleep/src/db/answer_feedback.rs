@@ -0,0 +1,60 @@
+/// Ratings and comments that users left on answers, to evaluate prompt changes against.
+pub struct AnswerFeedback<'a> {
+    db: &'a super::SqlitePool,
+}
+
+pub struct NewFeedback<'a> {
+    pub thread_id: uuid::Uuid,
+    pub query_id: uuid::Uuid,
+    pub repo_ref: Option<&'a str>,
+    pub prompt_version: &'a str,
+    pub positive: bool,
+    pub comment: Option<&'a str>,
+}
+
+impl<'a> AnswerFeedback<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Record feedback on an answer, replacing any earlier feedback of the user on it.
+    pub async fn insert(
+        &self,
+        user_id: Option<&str>,
+        feedback: &NewFeedback<'_>,
+    ) -> anyhow::Result<()> {
+        let created_at = chrono::Utc::now().timestamp();
+        let thread_id = feedback.thread_id.to_string();
+        let query_id = feedback.query_id.to_string();
+
+        let mut transaction = self.db.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM answer_feedback WHERE query_id = ? AND user_id IS ?",
+            query_id,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO answer_feedback \
+             (created_at, user_id, thread_id, query_id, repo_ref, prompt_version, positive, comment) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            created_at,
+            user_id,
+            thread_id,
+            query_id,
+            feedback.repo_ref,
+            feedback.prompt_version,
+            feedback.positive,
+            feedback.comment,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+/// Files and line ranges that users saved for later.
+pub struct Bookmarks<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Bookmark {
+    pub id: i64,
+    /// Seconds since the unix epoch.
+    pub created_at: i64,
+    pub repo_ref: String,
+    pub relative_path: String,
+    /// 1-indexed lines, inclusive. `None` for bookmarks of whole files.
+    pub lines: Option<(usize, usize)>,
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+}
+
+pub struct NewBookmark<'a> {
+    pub repo_ref: &'a str,
+    pub relative_path: &'a str,
+    pub lines: Option<(usize, usize)>,
+    pub note: Option<&'a str>,
+    pub tags: &'a [String],
+}
+
+impl<'a> Bookmarks<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Create a bookmark, returning its id.
+    pub async fn insert(&self, user_id: &str, bookmark: &NewBookmark<'_>) -> anyhow::Result<i64> {
+        let created_at = chrono::Utc::now().timestamp();
+        let start_line = bookmark.lines.map(|(start, _)| start as i64);
+        let end_line = bookmark.lines.map(|(_, end)| end as i64);
+        let tags = serde_json::to_string(bookmark.tags)?;
+
+        let id = sqlx::query!(
+            "INSERT INTO bookmarks \
+             (created_at, user_id, repo_ref, relative_path, start_line, end_line, note, tags) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            created_at,
+            user_id,
+            bookmark.repo_ref,
+            bookmark.relative_path,
+            start_line,
+            end_line,
+            bookmark.note,
+            tags,
+        )
+        .execute(self.db)
+        .await?
+        .last_insert_rowid();
+
+        Ok(id)
+    }
+
+    /// All bookmarks of a user, newest first.
+    pub async fn list(&self, user_id: &str) -> anyhow::Result<Vec<Bookmark>> {
+        let recs = sqlx::query!(
+            "SELECT id, created_at, repo_ref, relative_path, start_line, end_line, note, tags \
+             FROM bookmarks WHERE user_id = ? ORDER BY created_at DESC, id DESC",
+            user_id,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|r| Bookmark {
+                id: r.id,
+                created_at: r.created_at,
+                repo_ref: r.repo_ref,
+                relative_path: r.relative_path,
+                lines: r
+                    .start_line
+                    .zip(r.end_line)
+                    .map(|(start, end)| (start as usize, end as usize)),
+                note: r.note,
+                tags: serde_json::from_str(&r.tags).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// Delete a bookmark of a user, returning whether it existed.
+    pub async fn delete(&self, user_id: &str, id: i64) -> anyhow::Result<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM bookmarks WHERE id = ? AND user_id = ?",
+            id,
+            user_id,
+        )
+        .execute(self.db)
+        .await?
+        .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    /// The bookmarked paths of a user in a repository.
+    pub async fn paths(&self, user_id: &str, repo_ref: &str) -> anyhow::Result<Vec<String>> {
+        let recs = sqlx::query!(
+            "SELECT DISTINCT relative_path FROM bookmarks WHERE user_id = ? AND repo_ref = ?",
+            user_id,
+            repo_ref,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs.into_iter().map(|r| r.relative_path).collect())
+    }
+}
@@ -0,0 +1,91 @@
+/// The progress of a bulk re-index of every repository, checkpointed per repository.
+pub struct BulkReindex<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ReindexEntry {
+    pub repo_ref: String,
+    /// One of `pending`, `running`, `done`, `failed` or `skipped`.
+    pub status: String,
+    pub message: Option<String>,
+    /// Seconds since the unix epoch.
+    pub updated_at: i64,
+}
+
+impl<'a> BulkReindex<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Replace the previous re-index with a new one of `repo_refs`, in order.
+    pub async fn start(&self, repo_refs: &[String]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        let now = chrono::Utc::now().timestamp();
+
+        sqlx::query!("DELETE FROM bulk_reindex")
+            .execute(&mut tx)
+            .await?;
+
+        for (position, repo_ref) in repo_refs.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO bulk_reindex (repo_ref, position, status, updated_at) \
+                 VALUES (?, ?, 'pending', ?)",
+                repo_ref,
+                position,
+                now,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Every repository of the last re-index, in order.
+    pub async fn list(&self) -> anyhow::Result<Vec<ReindexEntry>> {
+        let entries = sqlx::query_as!(
+            ReindexEntry,
+            "SELECT repo_ref, status, message, updated_at FROM bulk_reindex ORDER BY position",
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// The repositories that are yet to be re-indexed, in order, including those that were being
+    /// re-indexed when the server stopped.
+    pub async fn unfinished(&self) -> anyhow::Result<Vec<String>> {
+        let repo_refs = sqlx::query_scalar!(
+            "SELECT repo_ref FROM bulk_reindex \
+             WHERE status IN ('pending', 'running') ORDER BY position",
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(repo_refs)
+    }
+
+    pub async fn set_status(
+        &self,
+        repo_ref: &str,
+        status: &str,
+        message: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        sqlx::query!(
+            "UPDATE bulk_reindex SET status = ?, message = ?, updated_at = ? WHERE repo_ref = ?",
+            status,
+            message,
+            now,
+            repo_ref,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
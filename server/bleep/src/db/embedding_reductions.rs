@@ -0,0 +1,65 @@
+/// The dimensionality reduction of each semantic collection, stored as JSON, and the version of
+/// the default model that its points which don't record their model were embedded with.
+pub struct EmbeddingReductions<'a> {
+    db: &'a super::SqlitePool,
+}
+
+impl<'a> EmbeddingReductions<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn get(&self, collection_name: &str) -> anyhow::Result<Option<String>> {
+        let rec = sqlx::query!(
+            "SELECT reduction FROM embedding_reductions WHERE collection_name = ?",
+            collection_name,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(rec.map(|r| r.reduction))
+    }
+
+    pub async fn set(&self, collection_name: &str, reduction: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO embedding_reductions (collection_name, reduction) VALUES (?, ?) \
+             ON CONFLICT (collection_name) DO UPDATE SET reduction = excluded.reduction",
+            collection_name,
+            reduction,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn model_hash(&self, collection_name: &str) -> anyhow::Result<Option<String>> {
+        let rec = sqlx::query!(
+            "SELECT model_hash FROM embedding_reductions WHERE collection_name = ?",
+            collection_name,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(rec.and_then(|r| r.model_hash))
+    }
+
+    /// Record the model of a collection. Collections without a stored reduction are full-size.
+    pub async fn set_model_hash(
+        &self,
+        collection_name: &str,
+        model_hash: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO embedding_reductions (collection_name, reduction, model_hash) \
+             VALUES (?, 'null', ?) \
+             ON CONFLICT (collection_name) DO UPDATE SET model_hash = excluded.model_hash",
+            collection_name,
+            model_hash,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+/// The latest commit of each repository that a user has caught up with.
+pub struct LastSeen<'a> {
+    db: &'a super::SqlitePool,
+}
+
+impl<'a> LastSeen<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn get(&self, user_id: &str, repo_ref: &str) -> anyhow::Result<Option<String>> {
+        let rec = sqlx::query!(
+            "SELECT commit_id FROM last_seen WHERE user_id = ? AND repo_ref = ?",
+            user_id,
+            repo_ref,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(rec.map(|r| r.commit_id))
+    }
+
+    pub async fn set(&self, user_id: &str, repo_ref: &str, commit_id: &str) -> anyhow::Result<()> {
+        let seen_at = chrono::Utc::now().timestamp();
+
+        sqlx::query!(
+            "INSERT INTO last_seen (user_id, repo_ref, commit_id, seen_at) VALUES (?, ?, ?, ?) \
+             ON CONFLICT (user_id, repo_ref) DO UPDATE SET commit_id = excluded.commit_id, seen_at = excluded.seen_at",
+            user_id,
+            repo_ref,
+            commit_id,
+            seen_at,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+/// An audit trail of the policy rules that blocked or rewrote questions and answers.
+pub struct PolicyAudit<'a> {
+    db: &'a super::SqlitePool,
+}
+
+pub struct AuditEntry<'a> {
+    pub user_id: Option<&'a str>,
+    pub thread_id: uuid::Uuid,
+    pub query_id: uuid::Uuid,
+    /// Whether the rule applied to a `question` or an `answer`.
+    pub scope: &'a str,
+    pub rule: &'a str,
+    /// Either `block` or `rewrite`.
+    pub action: &'a str,
+    /// The offending text, before it was rewritten.
+    pub excerpt: &'a str,
+}
+
+impl<'a> PolicyAudit<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn insert(&self, entry: &AuditEntry<'_>) -> anyhow::Result<()> {
+        let thread_id = entry.thread_id.to_string();
+        let query_id = entry.query_id.to_string();
+
+        sqlx::query!(
+            "INSERT INTO policy_audit (user_id, thread_id, query_id, scope, rule, action, excerpt) \
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            entry.user_id,
+            thread_id,
+            query_id,
+            entry.scope,
+            entry.rule,
+            entry.action,
+            entry.excerpt,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::text_range::{Point, TextRange};
+
+/// Symbol occurrences imported from SCIP and LSIF dumps, and from ctags files.
+pub struct PreciseSymbols<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreciseOccurrence {
+    pub relative_path: String,
+    pub symbol: String,
+    /// The text of the occurrence when it was imported.
+    pub name: String,
+    pub is_definition: bool,
+    pub range: TextRange,
+}
+
+impl<'a> PreciseSymbols<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// The source that the symbols of a file were imported from, if any.
+    pub async fn source(
+        &self,
+        repo_ref: &str,
+        relative_path: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let rec = sqlx::query!(
+            "SELECT source FROM precise_symbols WHERE repo_ref = ? AND relative_path = ? LIMIT 1",
+            repo_ref,
+            relative_path,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(rec.map(|r| r.source))
+    }
+
+    /// Replace the symbols of a file with `occurrences`, which were imported from `source`.
+    pub async fn replace_file(
+        &self,
+        repo_ref: &str,
+        relative_path: &str,
+        source: &str,
+        occurrences: &[PreciseOccurrence],
+    ) -> anyhow::Result<()> {
+        let mut transaction = self.db.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM precise_symbols WHERE repo_ref = ? AND relative_path = ?",
+            repo_ref,
+            relative_path,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        for occurrence in occurrences {
+            let TextRange { start, end } = occurrence.range;
+            let (start_byte, end_byte) = (start.byte as i64, end.byte as i64);
+            let (start_line, end_line) = (start.line as i64, end.line as i64);
+            let (start_column, end_column) = (start.column as i64, end.column as i64);
+
+            sqlx::query!(
+                "INSERT INTO precise_symbols \
+                 (repo_ref, relative_path, source, symbol, name, is_definition, \
+                 start_byte, end_byte, start_line, end_line, start_column, end_column) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                repo_ref,
+                relative_path,
+                source,
+                occurrence.symbol,
+                occurrence.name,
+                occurrence.is_definition,
+                start_byte,
+                end_byte,
+                start_line,
+                end_line,
+                start_column,
+                end_column,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// The occurrences of a file that contain the byte range `start..end`, innermost first, with
+    /// the source they were imported from.
+    pub async fn at(
+        &self,
+        repo_ref: &str,
+        relative_path: &str,
+        start: usize,
+        end: usize,
+    ) -> anyhow::Result<Vec<(String, PreciseOccurrence)>> {
+        let (start, end) = (start as i64, end as i64);
+        let recs = sqlx::query!(
+            "SELECT source, symbol, name, is_definition, \
+             start_byte, end_byte, start_line, end_line, start_column, end_column \
+             FROM precise_symbols \
+             WHERE repo_ref = ? AND relative_path = ? AND start_byte <= ? AND end_byte >= ? \
+             ORDER BY end_byte - start_byte",
+            repo_ref,
+            relative_path,
+            start,
+            end,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|r| {
+                let occurrence = PreciseOccurrence {
+                    relative_path: relative_path.to_owned(),
+                    symbol: r.symbol,
+                    name: r.name,
+                    is_definition: r.is_definition,
+                    range: range(
+                        (r.start_byte, r.start_line, r.start_column),
+                        (r.end_byte, r.end_line, r.end_column),
+                    ),
+                };
+
+                (r.source, occurrence)
+            })
+            .collect())
+    }
+
+    /// Every occurrence of a symbol in a repository, ordered by file and position.
+    pub async fn occurrences(
+        &self,
+        repo_ref: &str,
+        source: &str,
+        symbol: &str,
+    ) -> anyhow::Result<Vec<PreciseOccurrence>> {
+        let recs = sqlx::query!(
+            "SELECT relative_path, name, is_definition, \
+             start_byte, end_byte, start_line, end_line, start_column, end_column \
+             FROM precise_symbols \
+             WHERE repo_ref = ? AND source = ? AND symbol = ? \
+             ORDER BY relative_path, start_byte",
+            repo_ref,
+            source,
+            symbol,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .map(|r| PreciseOccurrence {
+                relative_path: r.relative_path,
+                symbol: symbol.to_owned(),
+                name: r.name,
+                is_definition: r.is_definition,
+                range: range(
+                    (r.start_byte, r.start_line, r.start_column),
+                    (r.end_byte, r.end_line, r.end_column),
+                ),
+            })
+            .collect())
+    }
+
+    /// Forget the symbols of a repository.
+    pub async fn delete_repo(&self, repo_ref: &str) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM precise_symbols WHERE repo_ref = ?", repo_ref)
+            .execute(self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn range(start: (i64, i64, i64), end: (i64, i64, i64)) -> TextRange {
+    let point = |(byte, line, column): (i64, i64, i64)| {
+        Point::new(byte as usize, line as usize, column as usize)
+    };
+
+    TextRange {
+        start: point(start),
+        end: point(end),
+    }
+}
@@ -0,0 +1,37 @@
+/// Versions of the agent's prompt template that were rolled back after a canary regressed.
+pub struct PromptRollbacks<'a> {
+    db: &'a super::SqlitePool,
+}
+
+impl<'a> PromptRollbacks<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn contains(&self, version: &str) -> anyhow::Result<bool> {
+        let rec = sqlx::query!(
+            "SELECT version FROM prompt_rollbacks WHERE version = ?",
+            version,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(rec.is_some())
+    }
+
+    pub async fn insert(&self, version: &str, reason: &str) -> anyhow::Result<()> {
+        let rolled_back_at = chrono::Utc::now().timestamp();
+
+        sqlx::query!(
+            "INSERT INTO prompt_rollbacks (version, reason, rolled_back_at) VALUES (?, ?, ?) \
+             ON CONFLICT (version) DO NOTHING",
+            version,
+            reason,
+            rolled_back_at,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+/// Repositories that were renamed or transferred on GitHub, by their previous names.
+pub struct RepoRenames<'a> {
+    db: &'a super::SqlitePool,
+}
+
+impl<'a> RepoRenames<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Record that `from` was renamed to `to`, moving the conversations, caches and profiles of
+    /// the repository to its new name.
+    pub async fn record(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let mut transaction = self.db.begin().await?;
+
+        sqlx::query!(
+            "UPDATE repo_renames SET to_ref = ? WHERE to_ref = ?",
+            to,
+            from,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO repo_renames (from_ref, to_ref, renamed_at) VALUES (?, ?, ?) \
+             ON CONFLICT (from_ref) \
+             DO UPDATE SET to_ref = excluded.to_ref, renamed_at = excluded.renamed_at",
+            from,
+            to,
+            now,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        // A repository that got its previous name back is not renamed anymore.
+        sqlx::query!("DELETE FROM repo_renames WHERE from_ref = to_ref")
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query!(
+            "UPDATE conversations SET repo_ref = ? WHERE repo_ref = ?",
+            to,
+            from,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE file_cache SET repo_ref = ? WHERE repo_ref = ?",
+            to,
+            from,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE chunk_cache SET repo_ref = ? WHERE repo_ref = ?",
+            to,
+            from,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        // Profiles that already have entries for the new name keep those.
+        sqlx::query!(
+            "UPDATE OR IGNORE user_profiles SET repo_ref = ? WHERE repo_ref = ?",
+            to,
+            from,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!("DELETE FROM user_profiles WHERE repo_ref = ?", from)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// The current names of renamed repositories, by their previous names.
+    pub async fn all(&self) -> anyhow::Result<HashMap<String, String>> {
+        let recs = sqlx::query!("SELECT from_ref, to_ref FROM repo_renames")
+            .fetch_all(self.db)
+            .await?;
+
+        Ok(recs.into_iter().map(|r| (r.from_ref, r.to_ref)).collect())
+    }
+}
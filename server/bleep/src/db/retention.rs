@@ -0,0 +1,124 @@
+/// Deletion of the records that are older than the retention windows of a deployment.
+///
+/// Every method takes the cutoff in seconds since the unix epoch, and only counts the records it
+/// would delete when `dry_run` is set.
+pub struct Retention<'a> {
+    db: &'a super::SqlitePool,
+}
+
+impl<'a> Retention<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Conversations that weren't continued since `before`, along with their summaries.
+    pub async fn conversations(&self, before: i64, dry_run: bool) -> anyhow::Result<u64> {
+        if dry_run {
+            let count = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM conversations WHERE created_at < ?",
+                before,
+            )
+            .fetch_one(self.db)
+            .await?;
+
+            return Ok(count as u64);
+        }
+
+        let deleted = sqlx::query!("DELETE FROM conversations WHERE created_at < ?", before)
+            .execute(self.db)
+            .await?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// Raw queries, as logged for the rotation of branch filters.
+    pub async fn query_log(&self, before: i64, dry_run: bool) -> anyhow::Result<u64> {
+        if dry_run {
+            let count = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM query_log WHERE created_at < datetime(?, 'unixepoch')",
+                before,
+            )
+            .fetch_one(self.db)
+            .await?;
+
+            return Ok(count as u64);
+        }
+
+        let deleted = sqlx::query!(
+            "DELETE FROM query_log WHERE created_at < datetime(?, 'unixepoch')",
+            before,
+        )
+        .execute(self.db)
+        .await?
+        .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// The files that answers used, or that were upvoted.
+    pub async fn snippet_usage(&self, before: i64, dry_run: bool) -> anyhow::Result<u64> {
+        if dry_run {
+            let count = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM snippet_usage WHERE created_at < ?",
+                before,
+            )
+            .fetch_one(self.db)
+            .await?;
+
+            return Ok(count as u64);
+        }
+
+        let deleted = sqlx::query!("DELETE FROM snippet_usage WHERE created_at < ?", before)
+            .execute(self.db)
+            .await?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// The LLM tokens that each query spent.
+    pub async fn token_usage(&self, before: i64, dry_run: bool) -> anyhow::Result<u64> {
+        if dry_run {
+            let count = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM token_usage WHERE created_at < ?",
+                before,
+            )
+            .fetch_one(self.db)
+            .await?;
+
+            return Ok(count as u64);
+        }
+
+        let deleted = sqlx::query!("DELETE FROM token_usage WHERE created_at < ?", before)
+            .execute(self.db)
+            .await?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+
+    /// The policy rules that blocked or rewrote questions and answers.
+    pub async fn policy_audit(&self, before: i64, dry_run: bool) -> anyhow::Result<u64> {
+        if dry_run {
+            let count = sqlx::query_scalar!(
+                "SELECT COUNT(*) FROM policy_audit WHERE created_at < datetime(?, 'unixepoch')",
+                before,
+            )
+            .fetch_one(self.db)
+            .await?;
+
+            return Ok(count as u64);
+        }
+
+        let deleted = sqlx::query!(
+            "DELETE FROM policy_audit WHERE created_at < datetime(?, 'unixepoch')",
+            before,
+        )
+        .execute(self.db)
+        .await?
+        .rows_affected();
+
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,84 @@
+/// Copies of the semantic index of repositories, pinned by name for evaluation runs.
+pub struct SemanticPins<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SemanticPin {
+    pub name: String,
+    pub repo_ref: String,
+    pub collection_name: String,
+    /// The model that embedded the points, as `name@version`.
+    pub embedding_model: String,
+    pub schema_version: String,
+    /// When the repository was last indexed before it was pinned, in seconds since the epoch.
+    pub indexed_at: i64,
+    pub points: i64,
+    /// Seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+impl<'a> SemanticPins<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn insert(&self, pin: &SemanticPin) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO semantic_pins \
+             (name, repo_ref, collection_name, embedding_model, schema_version, indexed_at, points, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            pin.name,
+            pin.repo_ref,
+            pin.collection_name,
+            pin.embedding_model,
+            pin.schema_version,
+            pin.indexed_at,
+            pin.points,
+            pin.created_at,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, name: &str, repo_ref: &str) -> anyhow::Result<Option<SemanticPin>> {
+        let pin = sqlx::query_as!(
+            SemanticPin,
+            "SELECT name, repo_ref, collection_name, embedding_model, schema_version, indexed_at, points, created_at \
+             FROM semantic_pins WHERE name = ? AND repo_ref = ?",
+            name,
+            repo_ref,
+        )
+        .fetch_optional(self.db)
+        .await?;
+
+        Ok(pin)
+    }
+
+    /// All pins, newest first.
+    pub async fn list(&self) -> anyhow::Result<Vec<SemanticPin>> {
+        let pins = sqlx::query_as!(
+            SemanticPin,
+            "SELECT name, repo_ref, collection_name, embedding_model, schema_version, indexed_at, points, created_at \
+             FROM semantic_pins ORDER BY created_at DESC",
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(pins)
+    }
+
+    pub async fn delete(&self, name: &str, repo_ref: &str) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM semantic_pins WHERE name = ? AND repo_ref = ?",
+            name,
+            repo_ref,
+        )
+        .execute(self.db)
+        .await?;
+
+        Ok(())
+    }
+}
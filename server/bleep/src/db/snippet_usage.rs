@@ -0,0 +1,142 @@
+/// Which snippets were used in answers, and which answers were rated positively.
+///
+/// Every file that contributes a snippet to an answer is recorded as `selected`. When the answer
+/// is upvoted, its selected files are recorded again as `upvoted`.
+pub struct SnippetUsage<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Selected,
+    Upvoted,
+}
+
+pub struct UsageEvent {
+    pub relative_path: String,
+    pub signal: Signal,
+    /// Seconds since the unix epoch.
+    pub created_at: i64,
+}
+
+impl Signal {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Selected => "selected",
+            Self::Upvoted => "upvoted",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "selected" => Some(Self::Selected),
+            "upvoted" => Some(Self::Upvoted),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SnippetUsage<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn insert_selected(
+        &self,
+        repo_ref: &str,
+        thread_id: uuid::Uuid,
+        query_id: uuid::Uuid,
+        paths: &[&str],
+    ) -> anyhow::Result<()> {
+        let created_at = chrono::Utc::now().timestamp();
+        let thread_id = thread_id.to_string();
+        let query_id = query_id.to_string();
+        let signal = Signal::Selected.as_str();
+
+        let mut transaction = self.db.begin().await?;
+        for path in paths {
+            sqlx::query!(
+                "INSERT INTO snippet_usage (created_at, repo_ref, relative_path, thread_id, query_id, signal) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                created_at,
+                repo_ref,
+                path,
+                thread_id,
+                query_id,
+                signal,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Record the vote on an answer, replacing any earlier vote on it.
+    ///
+    /// Only positive votes are kept, as the files an answer used are not necessarily the reason
+    /// it was unhelpful.
+    pub async fn vote(
+        &self,
+        thread_id: uuid::Uuid,
+        query_id: uuid::Uuid,
+        positive: bool,
+    ) -> anyhow::Result<()> {
+        let created_at = chrono::Utc::now().timestamp();
+        let thread_id = thread_id.to_string();
+        let query_id = query_id.to_string();
+
+        let mut transaction = self.db.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM snippet_usage \
+             WHERE thread_id = ? AND query_id = ? AND signal = 'upvoted'",
+            thread_id,
+            query_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        if positive {
+            sqlx::query!(
+                "INSERT INTO snippet_usage (created_at, repo_ref, relative_path, thread_id, query_id, signal) \
+                 SELECT DISTINCT ?, repo_ref, relative_path, thread_id, query_id, 'upvoted' \
+                 FROM snippet_usage \
+                 WHERE thread_id = ? AND query_id = ? AND signal = 'selected'",
+                created_at,
+                thread_id,
+                query_id,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// All events in a repository, more recent than `cutoff` seconds since the unix epoch.
+    pub async fn since(&self, repo_ref: &str, cutoff: i64) -> anyhow::Result<Vec<UsageEvent>> {
+        let recs = sqlx::query!(
+            "SELECT relative_path, signal, created_at FROM snippet_usage \
+             WHERE repo_ref = ? AND created_at > ?",
+            repo_ref,
+            cutoff,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .filter_map(|r| {
+                Some(UsageEvent {
+                    relative_path: r.relative_path,
+                    signal: Signal::parse(&r.signal)?,
+                    created_at: r.created_at,
+                })
+            })
+            .collect())
+    }
+}
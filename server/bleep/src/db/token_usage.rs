@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use crate::llm_gateway::{models::Prices, usage::Usage};
+
+/// The LLM tokens spent answering each query, by model.
+pub struct TokenUsage<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    /// Empty for requests without a user.
+    pub user_id: String,
+    pub thread_id: String,
+    pub repo_ref: String,
+    pub model: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub calls: i64,
+    /// The estimated cost in USD, at the prices when the usage was recorded.
+    pub cost: f64,
+}
+
+impl<'a> TokenUsage<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    pub async fn insert(
+        &self,
+        user_id: Option<&str>,
+        thread_id: uuid::Uuid,
+        query_id: uuid::Uuid,
+        repo_ref: &str,
+        usage: &BTreeMap<String, Usage>,
+        prices: &Prices,
+    ) -> anyhow::Result<()> {
+        let created_at = chrono::Utc::now().timestamp();
+        let user_id = user_id.unwrap_or_default();
+        let thread_id = thread_id.to_string();
+        let query_id = query_id.to_string();
+
+        let mut transaction = self.db.begin().await?;
+        for (model, usage) in usage {
+            let prompt_tokens = usage.prompt_tokens as i64;
+            let completion_tokens = usage.completion_tokens as i64;
+            let calls = usage.calls as i64;
+            let cost = prices.cost(model, usage);
+
+            sqlx::query!(
+                "INSERT INTO token_usage \
+                 (created_at, user_id, thread_id, query_id, repo_ref, model, prompt_tokens, completion_tokens, calls, cost) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                created_at,
+                user_id,
+                thread_id,
+                query_id,
+                repo_ref,
+                model,
+                prompt_tokens,
+                completion_tokens,
+                calls,
+                cost,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// All usage since `cutoff`, in seconds since the unix epoch.
+    pub async fn since(&self, cutoff: i64) -> anyhow::Result<Vec<UsageRecord>> {
+        let recs = sqlx::query_as!(
+            UsageRecord,
+            "SELECT user_id, thread_id, repo_ref, model, prompt_tokens, completion_tokens, calls, cost \
+             FROM token_usage WHERE created_at >= ?",
+            cutoff,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs)
+    }
+
+    /// The usage of a single user since `cutoff`, in seconds since the unix epoch.
+    pub async fn user_since(&self, user_id: &str, cutoff: i64) -> anyhow::Result<Vec<UsageRecord>> {
+        let recs = sqlx::query_as!(
+            UsageRecord,
+            "SELECT user_id, thread_id, repo_ref, model, prompt_tokens, completion_tokens, calls, cost \
+             FROM token_usage WHERE user_id = ? AND created_at >= ?",
+            user_id,
+            cutoff,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs)
+    }
+}
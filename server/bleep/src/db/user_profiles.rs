@@ -0,0 +1,119 @@
+/// How often users asked about repositories, and the languages and directories of the files that
+/// their answers were based on.
+pub struct UserProfiles<'a> {
+    db: &'a super::SqlitePool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    Repo,
+    Lang,
+    Dir,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ProfileEntry {
+    pub repo_ref: String,
+    pub kind: ProfileKind,
+    /// A lowercase language name, a directory, or empty for repositories.
+    pub name: String,
+    pub hits: i64,
+    /// Seconds since the unix epoch.
+    pub last_hit_at: i64,
+}
+
+impl ProfileKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Repo => "repo",
+            Self::Lang => "lang",
+            Self::Dir => "dir",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "repo" => Some(Self::Repo),
+            "lang" => Some(Self::Lang),
+            "dir" => Some(Self::Dir),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> UserProfiles<'a> {
+    pub fn new(db: &'a super::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Count a question about `repo_ref`, whose answer was based on files in `langs` and `dirs`.
+    pub async fn record(
+        &self,
+        user_id: &str,
+        repo_ref: &str,
+        langs: &[&str],
+        dirs: &[&str],
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp();
+
+        let entries = std::iter::once((ProfileKind::Repo, ""))
+            .chain(langs.iter().map(|lang| (ProfileKind::Lang, *lang)))
+            .chain(dirs.iter().map(|dir| (ProfileKind::Dir, *dir)));
+
+        let mut transaction = self.db.begin().await?;
+        for (kind, name) in entries {
+            let kind = kind.as_str();
+            sqlx::query!(
+                "INSERT INTO user_profiles (user_id, repo_ref, kind, name, hits, last_hit_at) \
+                 VALUES (?, ?, ?, ?, 1, ?) \
+                 ON CONFLICT (user_id, repo_ref, kind, name) \
+                 DO UPDATE SET hits = hits + 1, last_hit_at = excluded.last_hit_at",
+                user_id,
+                repo_ref,
+                kind,
+                name,
+                now,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// All entries of a user's profile, most hit first.
+    pub async fn list(&self, user_id: &str) -> anyhow::Result<Vec<ProfileEntry>> {
+        let recs = sqlx::query!(
+            "SELECT repo_ref, kind, name, hits, last_hit_at FROM user_profiles \
+             WHERE user_id = ? ORDER BY hits DESC, last_hit_at DESC",
+            user_id,
+        )
+        .fetch_all(self.db)
+        .await?;
+
+        Ok(recs
+            .into_iter()
+            .filter_map(|r| {
+                Some(ProfileEntry {
+                    kind: ProfileKind::parse(&r.kind)?,
+                    repo_ref: r.repo_ref,
+                    name: r.name,
+                    hits: r.hits,
+                    last_hit_at: r.last_hit_at,
+                })
+            })
+            .collect())
+    }
+
+    /// Delete the profile of a user, returning whether there was one.
+    pub async fn reset(&self, user_id: &str) -> anyhow::Result<bool> {
+        let deleted = sqlx::query!("DELETE FROM user_profiles WHERE user_id = ?", user_id)
+            .execute(self.db)
+            .await?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+}
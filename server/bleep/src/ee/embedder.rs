@@ -13,12 +13,16 @@ pub struct RemoteEmbedder {
 }
 
 impl RemoteEmbedder {
-    pub fn new(url: reqwest::Url, model_dir: &Path) -> anyhow::Result<Self> {
+    pub fn new(
+        url: reqwest::Url,
+        model_dir: &Path,
+        accelerator: Accelerator,
+    ) -> anyhow::Result<Self> {
         let url = url.join("encode")?;
         Ok(Self {
             url,
             session: reqwest::Client::builder().gzip(true).build()?,
-            embedder: LocalEmbedder::new(model_dir)?,
+            embedder: LocalEmbedder::new(model_dir, accelerator)?,
         })
     }
 
@@ -19,6 +19,10 @@ pub(crate) enum Feature {
     /// Use GitHub App permission system scoped to a single
     /// installation. Cloud instances use this.
     GithubOrgInstallation = 1 << 4,
+
+    /// The only user of the instance administers it, without
+    /// being listed in `app.config.admins`
+    LocalAdmin = 1 << 5,
 }
 
 #[rustfmt::skip]
@@ -59,7 +63,8 @@ enum EnvironmentInner {
     /// Enables scanning arbitrary user-specified locations through a Web-endpoint.
     InsecureLocal =
 	AnyPathScan as u64
-	| CognitoUserAuth as u64,
+	| CognitoUserAuth as u64
+	| LocalAdmin as u64,
 }
 
 #[derive(Debug, Clone)]
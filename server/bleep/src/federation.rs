@@ -0,0 +1,251 @@
+//! Federation with other bloop instances.
+//!
+//! Organisations often run one deployment per team. Registering the other deployments as
+//! federated peers lets search and answer requests fan out to them: each peer runs the query
+//! against its own index, and the results are merged by score, attributed to the peer they came
+//! from.
+//!
+//! Peers authenticate our requests with their `bot_secret`, which is stored as the peer token.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use tracing::{debug, warn};
+
+use crate::{indexes::reader::ContentDocument, semantic};
+
+/// How long we wait for a single peer, before leaving its results out.
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Separates the components of a path that refers to a file on a peer.
+const REMOTE_PATH_SEPARATOR: &str = "::";
+
+/// Marks the requests we make to peers, which refuse the code of their retrieval-only repositories
+/// to them, as it would be sent to our LLM.
+pub const PEER_HEADER: &str = "x-bloop-peer";
+
+/// Registered peers, by name.
+pub type Peers = scc::HashMap<String, Peer>;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Peer {
+    /// The base URL of the peer, without the `/api` suffix.
+    pub url: String,
+
+    /// The `bot_secret` of the peer, if it requires authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// A semantic search hit, attributed to the instance it came from.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct FederatedHit {
+    /// The name of the peer this hit came from, or `None` for this instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    pub score: f32,
+    pub payload: semantic::Payload,
+}
+
+#[derive(serde::Deserialize)]
+struct RemoteFile {
+    contents: String,
+    lang: Option<String>,
+}
+
+/// Whether `name` can be used as a peer name.
+///
+/// Names show up in remote paths, so they are restricted to a conservative set of characters.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A snapshot of the registered peers.
+pub fn snapshot(peers: &Peers) -> Vec<(String, Peer)> {
+    let mut list = Vec::new();
+    peers.scan(|name, peer| list.push((name.clone(), peer.clone())));
+    list.sort_by(|(a, _), (b, _)| a.cmp(b));
+    list
+}
+
+/// Run a semantic search on every peer, and merge the results by score.
+///
+/// Peers that fail or time out are logged and left out, so that one unavailable deployment does
+/// not break search for everyone else.
+pub async fn search(peers: &[(String, Peer)], query: &str, limit: u64) -> Vec<FederatedHit> {
+    let client = reqwest::Client::new();
+    let limit_str = limit.to_string();
+
+    let responses = join_all(peers.iter().map(|(name, peer)| {
+        let request = request(&client, peer, "federation/search")
+            .query(&[("q", query), ("limit", limit_str.as_str())]);
+
+        async move {
+            let hits = request
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .context("request failed")?
+                .json::<Vec<FederatedHit>>()
+                .await
+                .context("invalid response")?;
+
+            debug!(peer = name, hits = hits.len(), "received federated hits");
+            anyhow::Ok(
+                hits.into_iter()
+                    .map(|hit| FederatedHit {
+                        origin: Some(name.clone()),
+                        ..hit
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+    }))
+    .await;
+
+    let hits = peers
+        .iter()
+        .zip(responses)
+        .filter_map(|((name, _), response)| {
+            response
+                .map_err(|err| warn!(?err, peer = name, "federated search failed"))
+                .ok()
+        })
+        .flatten()
+        .collect();
+
+    merge(hits, limit as usize)
+}
+
+/// Sort hits from all instances by descending score, keeping the best `limit`.
+pub fn merge(mut hits: Vec<FederatedHit>, limit: usize) -> Vec<FederatedHit> {
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    hits
+}
+
+/// Read a file from a peer's index.
+pub async fn fetch_file(
+    peer: &Peer,
+    repo_ref: &str,
+    relative_path: &str,
+) -> Result<Option<ContentDocument>> {
+    let response = request(&reqwest::Client::new(), peer, "file")
+        .query(&[("repo_ref", repo_ref), ("path", relative_path)])
+        .send()
+        .await?;
+
+    // Files of retrieval-only repositories are refused, and are as good as missing.
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::FORBIDDEN
+    ) {
+        return Ok(None);
+    }
+
+    let file = response.error_for_status()?.json::<RemoteFile>().await?;
+
+    Ok(Some(ContentDocument {
+        content: file.contents,
+        lang: file.lang,
+        relative_path: relative_path.to_owned(),
+        repo_ref: repo_ref.to_owned(),
+        ..Default::default()
+    }))
+}
+
+fn request(client: &reqwest::Client, peer: &Peer, endpoint: &str) -> reqwest::RequestBuilder {
+    let url = format!("{}/api/{endpoint}", peer.url.trim_end_matches('/'));
+    let request = client
+        .get(url)
+        .timeout(PEER_TIMEOUT)
+        .header(PEER_HEADER, "1");
+
+    match &peer.token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Build a path that refers to a file in a peer's index.
+///
+/// Results from peers are mixed with local ones, and this is how they stay attributed to their
+/// origin, and how their contents can be read back.
+pub fn remote_path(peer: &str, repo_ref: &str, relative_path: &str) -> String {
+    [peer, repo_ref, relative_path].join(REMOTE_PATH_SEPARATOR)
+}
+
+/// Split a path created by `remote_path` into the peer, repo ref and relative path.
+pub fn parse_remote_path(path: &str) -> Option<(&str, &str, &str)> {
+    let (peer, rest) = path.split_once(REMOTE_PATH_SEPARATOR)?;
+    let (repo_ref, relative_path) = rest.split_once(REMOTE_PATH_SEPARATOR)?;
+
+    is_valid_name(peer).then_some((peer, repo_ref, relative_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(origin: Option<&str>, score: f32) -> FederatedHit {
+        FederatedHit {
+            origin: origin.map(str::to_owned),
+            score,
+            payload: semantic::Payload::default(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = merge(
+            vec![
+                hit(None, 0.5),
+                hit(Some("team-a"), 0.9),
+                hit(Some("team-b"), 0.7),
+                hit(Some("team-a"), 0.1),
+            ],
+            3,
+        );
+
+        assert_eq!(
+            merged
+                .iter()
+                .map(|h| (h.origin.as_deref(), h.score))
+                .collect::<Vec<_>>(),
+            [(Some("team-a"), 0.9), (Some("team-b"), 0.7), (None, 0.5)]
+        );
+    }
+
+    #[test]
+    fn test_remote_path() {
+        let path = remote_path(
+            "team-a",
+            "github.com/bloopai/bloop",
+            "server/bleep/src/lib.rs",
+        );
+
+        assert_eq!(
+            parse_remote_path(&path),
+            Some((
+                "team-a",
+                "github.com/bloopai/bloop",
+                "server/bleep/src/lib.rs"
+            ))
+        );
+        assert_eq!(parse_remote_path("server/bleep/src/lib.rs"), None);
+        assert_eq!(parse_remote_path("not a peer::repo::path"), None);
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("team-a"));
+        assert!(is_valid_name("backend_2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("team a"));
+        assert!(!is_valid_name("team::a"));
+    }
+}
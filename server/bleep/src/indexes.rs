@@ -1,35 +1,47 @@
-use std::{fs, ops::Deref, path::Path, sync::Arc};
+use std::{
+    collections::HashSet,
+    fs,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use smallvec::SmallVec;
 use tantivy::{
     collector::{Collector, MultiFruit},
-    schema::Schema,
+    query::QueryParser,
+    schema::{Field, Schema},
     tokenizer::NgramTokenizer,
     DocAddress, Document, IndexReader, IndexWriter, Score,
 };
 use tokio::sync::RwLock;
 
 pub mod file;
+mod migration;
 pub mod reader;
 pub mod repo;
 mod schema;
 
 pub use file::File;
 pub use repo::Repo;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
     background::{SyncHandle, SyncPipes},
     cache::FileCache,
     db::SqlDb,
-    query::parser::Query,
-    repo::{RepoError, RepoMetadata, RepoRef, Repository},
+    query::{parser::Query, tuning::LexicalTuning},
+    repo::{RepoError, RepoMetadata, RepoRef, Repository, SyncStatus},
     semantic::Semantic,
-    state::RepositoryPool,
+    state::{RepositoryPool, SCHEMA_VERSION},
     Configuration,
 };
+use migration::Migration;
 
 pub type GlobalWriteHandleRef<'a> = [IndexWriteHandle<'a>];
 
@@ -85,6 +97,8 @@ pub struct Indexes {
     pub repo: Indexer<Repo>,
     pub file: Indexer<File>,
     write_mutex: tokio::sync::Mutex<()>,
+    migration: Option<Migration>,
+    config: Arc<Configuration>,
 }
 
 impl Indexes {
@@ -94,11 +108,16 @@ impl Indexes {
         sql: SqlDb,
         semantic: Option<Semantic>,
     ) -> Result<Self> {
-        if config.source.index_version_mismatch() {
-            // we don't support old schemas, and tantivy will hard
-            // error if we try to open a db with a different schema.
-            std::fs::remove_dir_all(config.index_path("repo"))?;
-            std::fs::remove_dir_all(config.index_path("content"))?;
+        let mut version = config.source.index_version();
+
+        let reindex_semantic = semantic.as_ref().map_or(false, Semantic::reindex_required);
+        if reindex_semantic {
+            // the semantic index can't be migrated, so we start over.
+            for name in ["repo", "content"] {
+                migration::remove_dir(config.index_path(name).as_ref())?;
+                migration::remove_dir(&migration::current_dir(&config, name, &version))?;
+                migration::remove_dir(&migration::generation_dir(&config, name, SCHEMA_VERSION))?;
+            }
 
             let mut refs = vec![];
             // knocking out our current file caches will force re-indexing qdrant
@@ -114,26 +133,102 @@ impl Indexes {
             }
 
             if let Some(ref semantic) = semantic {
-                semantic.delete_collection().await?;
+                semantic.reset_collection().await?;
+            }
+
+            version.clear();
+        }
+
+        if version.is_empty() || version == SCHEMA_VERSION {
+            config.source.save_index_version()?;
+
+            return Ok(Self {
+                repo: Indexer::create(
+                    Repo::new(),
+                    &migration::current_dir(&config, "repo", SCHEMA_VERSION),
+                    config.repo_buffer_size,
+                    config.max_threads,
+                )?,
+                file: Indexer::create(
+                    File::new(sql, semantic),
+                    &migration::current_dir(&config, "content", SCHEMA_VERSION),
+                    config.buffer_size,
+                    config.max_threads,
+                )?
+                .with_tuning(LexicalTuning::new(&config)?),
+                write_mutex: Default::default(),
+                migration: None,
+                config,
+            });
+        }
+
+        // The schema changed, so we build a new generation of the indexes from scratch, while
+        // the previous one keeps serving queries.
+        let mut pending = HashSet::new();
+        repo_pool.for_each(|reporef, repo| {
+            if !matches!(
+                repo.sync_status,
+                SyncStatus::Uninitialized | SyncStatus::Removed | SyncStatus::RemoteRemoved
+            ) {
+                pending.insert(reporef.to_owned());
+                repo.last_index_unix_secs = 0;
             }
+        });
+
+        let previous =
+            ["repo", "content"].map(|name| migration::current_dir(&config, name, &version));
+        for name in ["repo", "content"] {
+            // A migration that was interrupted starts over.
+            migration::remove_dir(&migration::generation_dir(&config, name, SCHEMA_VERSION))?;
         }
-        config.source.save_index_version()?;
 
-        Ok(Self {
+        let migration = Migration::new(version.clone(), pending, previous.to_vec());
+        let indexes = Self {
             repo: Indexer::create(
                 Repo::new(),
-                config.index_path("repo").as_ref(),
+                &migration::generation_dir(&config, "repo", SCHEMA_VERSION),
                 config.repo_buffer_size,
                 config.max_threads,
-            )?,
+            )?
+            .serve_previous(&previous[0]),
             file: Indexer::create(
                 File::new(sql, semantic),
-                config.index_path("content").as_ref(),
+                &migration::generation_dir(&config, "content", SCHEMA_VERSION),
                 config.buffer_size,
                 config.max_threads,
-            )?,
+            )?
+            .with_tuning(LexicalTuning::new(&config)?)
+            .serve_previous(&previous[1]),
             write_mutex: Default::default(),
-        })
+            migration: Some(migration),
+            config,
+        };
+
+        if indexes.migration.as_ref().map_or(false, Migration::is_done) {
+            indexes.switch_generation().await?;
+        }
+
+        Ok(indexes)
+    }
+
+    /// Record that a repository was written to the indexes, which finishes a schema migration
+    /// once every repository has been migrated.
+    pub async fn migrated(&self, reporef: &RepoRef) -> Result<()> {
+        match self.migration {
+            Some(ref migration) if migration.migrated(reporef) => self.switch_generation().await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn switch_generation(&self) -> Result<()> {
+        self.repo.switch_generation().await?;
+        self.file.switch_generation().await?;
+
+        if let Some(ref migration) = self.migration {
+            migration.finish(&self.config)?;
+        }
+
+        Ok(())
     }
 
     pub async fn writers(&self) -> Result<GlobalWriteHandle<'_>> {
@@ -175,12 +270,13 @@ pub trait DocumentRead: Send + Sync {
     /// Return whether this reader can process this query.
     fn query_matches(&self, query: &Query<'_>) -> bool;
 
-    /// Compile a set of parsed queries into a single `tantivy` query.
+    /// Compile a set of parsed queries into a single `tantivy` query, weighing fields as tuned.
     fn compile<'a, I>(
         &self,
         schema: &Self::Schema,
         queries: I,
         index: &tantivy::Index,
+        tuning: &LexicalTuning,
     ) -> Result<Box<dyn tantivy::query::Query>>
     where
         I: Iterator<Item = &'a Query<'a>>;
@@ -193,12 +289,20 @@ pub struct IndexWriteHandle<'a> {
     source: &'a dyn Indexable,
     index: &'a tantivy::Index,
     reader: &'a RwLock<IndexReader>,
+    serves_previous: &'a AtomicBool,
     writer: IndexWriter,
 }
 
 impl<'a> IndexWriteHandle<'a> {
     pub async fn refresh_reader(&self) -> Result<()> {
-        *self.reader.write().await = self.index.reader()?;
+        let mut reader = self.reader.write().await;
+
+        // During migrations, readers stay on the previous generation until every repository
+        // has been written to the new one.
+        if !self.serves_previous.load(Ordering::Acquire) {
+            *reader = self.index.reader()?;
+        }
+
         Ok(())
     }
 
@@ -236,10 +340,14 @@ impl<'a> IndexWriteHandle<'a> {
 /// This contains the schema, and also additional fields used to enable re-indexing.
 pub struct Indexer<T> {
     pub source: T,
+    /// The index that is written to.
     pub index: tantivy::Index,
+    /// The reader that is queried, of the previous generation of the index during migrations.
     pub reader: RwLock<IndexReader>,
     pub reindex_buffer_size: usize,
     pub reindex_threads: usize,
+    pub tuning: LexicalTuning,
+    serves_previous: AtomicBool,
 }
 
 impl<T: Indexable> Indexer<T> {
@@ -248,6 +356,7 @@ impl<T: Indexable> Indexer<T> {
             source: &self.source,
             index: &self.index,
             reader: &self.reader,
+            serves_previous: &self.serves_previous,
             writer: self
                 .index
                 .writer_with_num_threads(self.reindex_threads, self.reindex_buffer_size)?,
@@ -257,9 +366,13 @@ impl<T: Indexable> Indexer<T> {
     fn init_index(schema: Schema, path: &Path, threads: usize) -> Result<tantivy::Index> {
         fs::create_dir_all(path).context("failed to create index dir")?;
 
-        let mut index =
+        let index =
             tantivy::Index::open_or_create(tantivy::directory::MmapDirectory::open(path)?, schema)?;
 
+        Self::configure(index, threads)
+    }
+
+    fn configure(mut index: tantivy::Index, threads: usize) -> Result<tantivy::Index> {
         index.set_default_multithread_executor()?;
         index.set_multithread_executor(threads)?;
         index
@@ -279,11 +392,67 @@ impl<T: Indexable> Indexer<T> {
             source,
             reindex_threads: threads,
             reindex_buffer_size: buffer_size,
+            tuning: LexicalTuning::default(),
+            serves_previous: AtomicBool::new(false),
         };
 
         Ok(instance)
     }
 
+    /// Serve queries from the previous generation of the index at `path` until the migration
+    /// finishes, if its schema is compatible with the current one.
+    fn serve_previous(self, path: &Path) -> Self {
+        let previous = tantivy::Index::open_in_dir(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|index| Self::configure(index, self.reindex_threads));
+
+        let reader = match previous {
+            Ok(index) if migration::compatible(&index.schema(), &self.source.schema()) => {
+                index.reader()
+            }
+            Ok(_) => {
+                info!(?path, "previous index is incompatible, serving the new one");
+                return self;
+            }
+            Err(err) => {
+                warn!(?err, ?path, "failed to open previous index");
+                return self;
+            }
+        };
+
+        match reader {
+            Ok(reader) => Self {
+                reader: reader.into(),
+                serves_previous: AtomicBool::new(true),
+                ..self
+            },
+            Err(err) => {
+                warn!(?err, ?path, "failed to read previous index");
+                self
+            }
+        }
+    }
+
+    /// Switch readers to the index that is written to.
+    async fn switch_generation(&self) -> Result<()> {
+        let mut reader = self.reader.write().await;
+        *reader = self.index.reader()?;
+        self.serves_previous.store(false, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Whether queries are served by the previous generation of the index, during a migration.
+    pub fn serves_previous(&self) -> bool {
+        self.serves_previous.load(Ordering::Acquire)
+    }
+
+    /// Tune the lexical search queries run against this index.
+    pub fn with_tuning(mut self, tuning: LexicalTuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
     pub async fn query<'a, R, I, C>(
         &'a self,
         queries: I,
@@ -299,9 +468,50 @@ impl<T: Indexable> Indexer<T> {
         let queries = queries
             .filter(|q| doc_reader.query_matches(q))
             .collect::<SmallVec<[_; 2]>>();
+        let compiled_query = doc_reader.compile(
+            &self.source,
+            queries.iter().copied(),
+            searcher.index(),
+            &self.tuning,
+        )?;
+
+        self.search(searcher, compiled_query, doc_reader, collector)
+    }
+
+    /// Run a raw tantivy query, validated against the schema of the index.
+    ///
+    /// Terms without a field are matched against `default_fields`. Queries that don't parse, or
+    /// that name fields the schema doesn't have, fail with a
+    /// [`QueryParserError`](tantivy::query::QueryParserError).
+    pub async fn query_raw<'a, R, C>(
+        &'a self,
+        raw: &str,
+        default_fields: Vec<Field>,
+        doc_reader: &'a R,
+        collector: C,
+    ) -> Result<SearchResults<'_, R::Document>>
+    where
+        C: Collector<Fruit = (Vec<(Score, DocAddress)>, MultiFruit)>,
+        R: DocumentRead<Schema = T>,
+    {
+        let searcher = self.reader.read().await.searcher();
         let compiled_query =
-            doc_reader.compile(&self.source, queries.iter().copied(), &self.index)?;
+            QueryParser::for_index(searcher.index(), default_fields).parse_query(raw)?;
+
+        self.search(searcher, compiled_query, doc_reader, collector)
+    }
 
+    fn search<'a, R, C>(
+        &'a self,
+        searcher: tantivy::Searcher,
+        compiled_query: Box<dyn tantivy::query::Query>,
+        doc_reader: &'a R,
+        collector: C,
+    ) -> Result<SearchResults<'_, R::Document>>
+    where
+        C: Collector<Fruit = (Vec<(Score, DocAddress)>, MultiFruit)>,
+        R: DocumentRead<Schema = T>,
+    {
         let (top_k, metadata) = searcher
             .search(&compiled_query, &collector)
             .context("failed to execute search query")?;
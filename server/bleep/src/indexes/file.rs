@@ -12,9 +12,9 @@ use async_trait::async_trait;
 use rayon::prelude::*;
 use scc::hash_map::Entry;
 use tantivy::{
-    collector::TopDocs,
+    collector::{DocSetCollector, TopDocs},
     doc,
-    query::{BooleanQuery, Query, QueryParser, TermQuery},
+    query::{BooleanQuery, Occur, Query, QueryParser, TermQuery},
     schema::{IndexRecordOption, Schema, Term},
     IndexWriter,
 };
@@ -35,8 +35,11 @@ use crate::{
     background::SyncPipes,
     cache::{FileCache, FileCacheSnapshot},
     intelligence::TreeSitterFile,
-    query::compiler::{case_permutations, trigrams},
-    repo::{iterator::*, RepoMetadata, RepoRef, Repository},
+    query::{
+        compiler::{case_permutations, trigrams},
+        parser,
+    },
+    repo::{iterator::*, license, RepoMetadata, RepoRef, Repository},
     symbol::SymbolLocations,
 };
 
@@ -77,6 +80,14 @@ impl Indexable for File {
                 pipes.index_percent(((completed as f32 / count as f32) * 100f32) as u8);
 
                 let entry_disk_path = dir_entry.path().unwrap_or_default().to_owned();
+                if let Ok(relative_path) = Path::new(&entry_disk_path).strip_prefix(&repo.disk_path)
+                {
+                    if repo.is_excluded(relative_path) {
+                        trace!(entry_disk_path, "excluded; skipping");
+                        return;
+                    }
+                }
+
                 let workload = Workload {
                     repo_disk_path: &repo.disk_path,
                     repo_ref: reporef.to_string(),
@@ -111,6 +122,7 @@ impl Indexable for File {
                 reporef,
                 &repo.disk_path,
                 repo.branch_filter.as_ref().map(Into::into),
+                &repo.revisions,
             )?;
             let count = walker.len();
             walker.for_each(pipes, file_worker(count));
@@ -138,14 +150,18 @@ impl Indexable for File {
             v.fresh
         });
 
+        // embed what's left before stale points are deleted, as unchanged chunks reuse their
+        // embeddings
+        file_cache.batched_process_embed_queue(true).await?;
+
         // batch-delete points from qdrant index
         if !qdrant_remove_list.is_empty() {
             if let Some(semantic) = &self.semantic {
                 let semantic = semantic.clone();
-                let reporef = reporef.to_string();
+                let reporef = reporef.clone();
                 tokio::spawn(async move {
                     semantic
-                        .delete_points_for_hash(reporef.as_str(), qdrant_remove_list.into_iter())
+                        .delete_points_for_hash(&reporef, qdrant_remove_list.into_iter())
                         .await;
                 });
             }
@@ -153,7 +169,6 @@ impl Indexable for File {
 
         pipes.index_percent(100);
         file_cache.persist(cache_snapshot).await?;
-        file_cache.batched_process_embed_queue(true).await?;
         Ok(())
     }
 
@@ -218,7 +233,11 @@ impl Indexer<File> {
                     query.push(Box::new(b.clone()));
                 };
 
-                BooleanQuery::intersection(query)
+                exclude_revisions(
+                    file_source,
+                    branch,
+                    Box::new(BooleanQuery::intersection(query)),
+                )
             })
             .flat_map(|query| {
                 searcher
@@ -314,7 +333,8 @@ impl Indexer<File> {
             .parse_query(&query_string)
             .expect("failed to parse tantivy query");
 
-        self.top_hit(query, searcher).await
+        self.top_hit(exclude_revisions(&self.source, branch, query), searcher)
+            .await
     }
 
     async fn top_hit(
@@ -403,7 +423,11 @@ impl Indexer<File> {
             Box::new(BooleanQuery::union(queries))
         });
 
-        let query = BooleanQuery::intersection(query);
+        let query = exclude_revisions(
+            &self.source,
+            branch,
+            Box::new(BooleanQuery::intersection(query)),
+        );
         let collector = TopDocs::with_limit(500);
         searcher
             .search(&query, &collector)
@@ -417,6 +441,122 @@ impl Indexer<File> {
             })
             .collect()
     }
+
+    /// The relative paths of every file of a repository at its indexed branches, without
+    /// directories and historical revisions.
+    pub async fn paths_of_repo(&self, repo_ref: &RepoRef) -> HashSet<String> {
+        let reader = self.reader.read().await;
+        let searcher = reader.searcher();
+
+        let query = TermQuery::new(
+            Term::from_field_text(self.source.repo_ref, &repo_ref.to_string()),
+            IndexRecordOption::Basic,
+        );
+
+        let query = exclude_revisions(&self.source, None, Box::new(query));
+        searcher
+            .search(&query, &DocSetCollector)
+            .expect("failed to search index")
+            .into_iter()
+            .filter_map(|doc_addr| {
+                searcher
+                    .doc(doc_addr)
+                    .expect("failed to get document by address")
+                    .get_first(self.source.relative_path)
+                    .and_then(|path| path.as_text())
+                    .map(ToOwned::to_owned)
+            })
+            // The paths of directories end with a `/`
+            .filter(|path| !path.ends_with('/'))
+            .collect()
+    }
+
+    /// Collect the identifiers and path fragments of files that share trigrams with `query_str`,
+    /// either in their symbols or in their path.
+    ///
+    /// This is the vocabulary that query identifiers are spell-corrected against, so it is
+    /// deliberately broad: callers are expected to filter it by edit distance.
+    pub async fn identifier_vocabulary(
+        &self,
+        repo_ref: &RepoRef,
+        query_str: &str,
+        branch: Option<&str>,
+        limit: usize,
+    ) -> HashSet<String> {
+        let reader = self.reader.read().await;
+        let searcher = reader.searcher();
+
+        let mut query = vec![Box::new(TermQuery::new(
+            Term::from_field_text(self.source.repo_ref, &repo_ref.to_string()),
+            IndexRecordOption::Basic,
+        )) as Box<dyn Query>];
+
+        if let Some(b) = branch {
+            query.push(Box::new(BooleanQuery::intersection(
+                trigrams(b)
+                    .map(|token| Term::from_field_text(self.source.branches, token.as_str()))
+                    .map(|term| TermQuery::new(term, IndexRecordOption::Basic))
+                    .map(Box::new)
+                    .map(|q| q as Box<dyn Query>)
+                    .collect(),
+            )));
+        }
+
+        let terms = trigrams(query_str)
+            .flat_map(|s| case_permutations(s.as_str()))
+            .flat_map(|token| {
+                [self.source.symbols, self.source.relative_path]
+                    .map(|field| Term::from_field_text(field, token.as_str()))
+            })
+            .map(|term| Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>)
+            .collect::<Vec<_>>();
+
+        if terms.is_empty() {
+            return HashSet::new();
+        }
+
+        query.push(Box::new(BooleanQuery::union(terms)));
+
+        let query = exclude_revisions(
+            &self.source,
+            branch,
+            Box::new(BooleanQuery::intersection(query)),
+        );
+        let collector = TopDocs::with_limit(limit);
+
+        searcher
+            .search(&query, &collector)
+            .expect("failed to search index")
+            .into_iter()
+            .flat_map(|(_, doc_addr)| {
+                let retrieved_doc = searcher
+                    .doc(doc_addr)
+                    .expect("failed to get document by address");
+                let doc = ContentReader.read_document(&self.source, retrieved_doc);
+
+                let symbols = doc
+                    .symbol_locations
+                    .list()
+                    .into_iter()
+                    .filter_map(|s| doc.content.get(s.range.start.byte..s.range.end.byte))
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+
+                let path_fragments = doc
+                    .relative_path
+                    .split('/')
+                    .flat_map(|component| {
+                        let stem = component.split_once('.').map(|(stem, _)| stem);
+                        std::iter::once(component).chain(stem)
+                    })
+                    .filter(|fragment| !fragment.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+
+                symbols.into_iter().chain(path_fragments)
+            })
+            .collect()
+    }
 }
 
 impl File {
@@ -453,6 +593,13 @@ impl File {
             hash.update(relative_path.to_string_lossy().as_ref().as_ref());
             hash.update(repo_ref.as_bytes());
             hash.update(dir_entry.buffer().unwrap_or_default().as_bytes());
+            if let Some(model) = self
+                .semantic
+                .as_ref()
+                .and_then(|s| s.model_cache_key(repo_name))
+            {
+                hash.update(model.as_bytes());
+            }
             hash.finalize().to_hex().to_string()
         };
 
@@ -552,6 +699,7 @@ impl RepoDir {
         #[cfg(windows)]
         let relative_path_str = relative_path_str.replace('\\', "/");
 
+        let is_revision = is_revision_only(&self.branches);
         let branches = self.branches.join("\n");
 
         doc!(
@@ -564,6 +712,7 @@ impl RepoDir {
                 schema.last_commit_unix_seconds => last_commit,
                 schema.branches => branches,
                 schema.is_directory => true,
+                schema.is_revision => is_revision,
                 schema.unique_hash => tantivy_cache_key,
 
                 // nulls
@@ -598,6 +747,7 @@ impl RepoFile {
         #[cfg(windows)]
         let relative_path_str = relative_path_str.replace('\\', "/");
 
+        let is_revision = is_revision_only(&self.branches);
         let branches = self.branches.join("\n");
         let lang_str = repo_metadata
             .langs
@@ -607,10 +757,20 @@ impl RepoFile {
                 ""
             });
 
+        // build a syntax aware representation of the file
+        let ts_file = TreeSitterFile::try_build(self.buffer.as_bytes(), lang_str);
+
+        // the distinct shapes of its syntax nodes, for structural search
+        let fingerprints = ts_file
+            .as_ref()
+            .map(TreeSitterFile::fingerprints)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|fingerprint| fingerprint.hash)
+            .collect::<HashSet<_>>();
+
         let symbol_locations = {
-            // build a syntax aware representation of the file
-            let scope_graph = TreeSitterFile::try_build(self.buffer.as_bytes(), lang_str)
-                .and_then(TreeSitterFile::scope_graph);
+            let scope_graph = ts_file.and_then(TreeSitterFile::scope_graph);
 
             match scope_graph {
                 // we have a graph, use that
@@ -648,8 +808,11 @@ impl RepoFile {
         }
 
         let lines_avg = self.buffer.len() as f64 / self.buffer.lines().count() as f64;
+        let license = license::detect_file(&self.buffer).or_else(|| repo_metadata.license.clone());
 
-        if let Some(semantic) = &schema.semantic {
+        // Historical revisions are only indexed for exact search, to keep the embedding cost of
+        // opting into them in check.
+        if let Some(semantic) = schema.semantic.as_ref().filter(|_| !is_revision) {
             tokio::task::block_in_place(|| {
                 Handle::current().block_on(async {
                     semantic
@@ -660,14 +823,21 @@ impl RepoFile {
                             &self.buffer,
                             lang_str,
                             &self.branches,
-                            file_cache.chunks_for_file(&semantic_cache_key).await,
+                            license.as_deref(),
+                            file_cache
+                                .chunks_for_file(
+                                    &semantic_cache_key,
+                                    &relative_path_str,
+                                    &self.branches,
+                                )
+                                .await,
                         )
                         .await
                 })
             });
         }
 
-        Some(doc!(
+        let mut doc = doc!(
             schema.raw_content => self.buffer.as_bytes(),
             schema.raw_repo_name => repo_name.as_bytes(),
             schema.raw_relative_path => relative_path_str.as_bytes(),
@@ -685,8 +855,45 @@ impl RepoFile {
             schema.symbols => symbols,
             schema.branches => branches,
             schema.is_directory => false,
-        ))
+            schema.is_revision => is_revision,
+        );
+
+        if let Some(license) = license {
+            doc.add_text(schema.license, license);
+        }
+
+        for fingerprint in fingerprints {
+            doc.add_text(schema.fingerprints, fingerprint);
+        }
+
+        Some(doc)
+    }
+}
+
+/// Whether an entry only exists in historical revisions.
+fn is_revision_only(branches: &[String]) -> bool {
+    !branches.is_empty() && branches.iter().all(|b| parser::is_revision(b))
+}
+
+/// Leave documents from historical revisions out of `query`, unless `branch` refers to one.
+pub(super) fn exclude_revisions(
+    schema: &File,
+    branch: Option<&str>,
+    query: Box<dyn Query>,
+) -> Box<dyn Query> {
+    if branch.map_or(false, parser::is_revision) {
+        return query;
     }
+
+    let revisions = TermQuery::new(
+        Term::from_field_bool(schema.is_revision, true),
+        IndexRecordOption::Basic,
+    );
+
+    Box::new(BooleanQuery::new(vec![
+        (Occur::Must, query),
+        (Occur::MustNot, Box::new(revisions)),
+    ]))
 }
 
 #[tracing::instrument(skip(cache))]
@@ -0,0 +1,159 @@
+//! Schema migrations of the tantivy indexes, without downtime.
+//!
+//! Each schema version is indexed in its own directory, a generation. When the schema version
+//! changes, the new generation is built alongside the previous one, as repositories are
+//! re-indexed in the background. Until every repository has been migrated, queries are served by
+//! the previous generation, as long as its schema is compatible with the current one. Readers
+//! then switch to the new generation at once, and the previous one is deleted.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use tantivy::schema::Schema;
+use tracing::info;
+
+use crate::{repo::RepoRef, Configuration};
+
+/// The directory of an index generation.
+pub(super) fn generation_dir(config: &Configuration, name: &str, version: &str) -> PathBuf {
+    config
+        .index_path(format!("{name}-{version}"))
+        .as_ref()
+        .to_owned()
+}
+
+/// The directory of the generation that is in use.
+///
+/// Indexes built before generations were introduced are in an unversioned directory.
+pub(super) fn current_dir(config: &Configuration, name: &str, version: &str) -> PathBuf {
+    let dir = generation_dir(config, name, version);
+    let unversioned = config.index_path(name).as_ref().to_owned();
+
+    if !dir.exists() && unversioned.exists() {
+        unversioned
+    } else {
+        dir
+    }
+}
+
+/// Whether an index with the `previous` schema can be queried with the `current` one.
+///
+/// Every current field must have the same id and options in the previous schema. The previous
+/// schema may have more fields, which are ignored.
+pub(super) fn compatible(previous: &Schema, current: &Schema) -> bool {
+    let previous = previous.fields().collect::<Vec<_>>();
+
+    current.fields().all(|(field, entry)| {
+        previous
+            .get(field.field_id() as usize)
+            .map_or(false, |(_, previous)| *previous == entry)
+    })
+}
+
+pub(super) struct Migration {
+    from: String,
+    /// Repositories that are not in the new generation yet.
+    pending: Mutex<HashSet<RepoRef>>,
+    /// The directories of the previous generation.
+    previous: Vec<PathBuf>,
+}
+
+impl Migration {
+    pub(super) fn new(from: String, pending: HashSet<RepoRef>, previous: Vec<PathBuf>) -> Self {
+        info!(from, pending = pending.len(), "migrating indexes");
+
+        Self {
+            from,
+            pending: pending.into(),
+            previous,
+        }
+    }
+
+    /// Mark a repository as migrated, returning whether it was the last one.
+    pub(super) fn migrated(&self, reporef: &RepoRef) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending.remove(reporef) && pending.is_empty()
+    }
+
+    pub(super) fn is_done(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+
+    /// Commit to the new generation, and delete the previous one.
+    pub(super) fn finish(&self, config: &Configuration) -> Result<()> {
+        config.source.save_index_version()?;
+
+        for dir in &self.previous {
+            remove_dir(dir)?;
+        }
+
+        info!(from = self.from, "finished migrating indexes");
+        Ok(())
+    }
+}
+
+pub(super) fn remove_dir(dir: &Path) -> Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::schema::{SchemaBuilder, FAST, STORED, STRING};
+
+    use super::*;
+
+    #[test]
+    fn checks_compatibility() {
+        let schema = |fields: &[(&str, bool)]| {
+            let mut builder = SchemaBuilder::new();
+            for &(name, fast) in fields {
+                if fast {
+                    builder.add_u64_field(name, FAST);
+                } else {
+                    builder.add_text_field(name, STRING | STORED);
+                }
+            }
+            builder.build()
+        };
+
+        let current = schema(&[("path", false), ("lines", true)]);
+
+        assert!(compatible(&current, &current));
+        assert!(compatible(
+            &schema(&[("path", false), ("lines", true), ("removed", false)]),
+            &current
+        ));
+
+        // Added fields, changed options and reordered fields can't be queried.
+        assert!(!compatible(&schema(&[("path", false)]), &current));
+        assert!(!compatible(
+            &schema(&[("path", false), ("lines", false)]),
+            &current
+        ));
+        assert!(!compatible(
+            &schema(&[("lines", true), ("path", false)]),
+            &current
+        ));
+    }
+
+    #[test]
+    fn finishes_with_last_repo() {
+        let repos =
+            ["github.com/bloopai/a", "github.com/bloopai/b"].map(|r| r.parse::<RepoRef>().unwrap());
+        let migration = Migration::new("old".into(), repos.iter().cloned().collect(), vec![]);
+
+        assert!(!migration.migrated(&repos[0]));
+        assert!(!migration.migrated(&repos[0]));
+        assert!(!migration.is_done());
+        assert!(migration.migrated(&repos[1]));
+        assert!(migration.is_done());
+        assert!(!migration.migrated(&repos[1]));
+    }
+}
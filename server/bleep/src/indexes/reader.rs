@@ -7,10 +7,11 @@ use tantivy::{
 
 use super::{file::File, repo::Repo, DocumentRead};
 use crate::{
-    intelligence::TreeSitterFile,
+    intelligence::{fingerprint, TreeSitterFile},
     query::{
         compiler::Compiler,
         parser::{self, Query, Target},
+        tuning::LexicalTuning,
     },
     symbol::SymbolLocations,
     text_range::TextRange,
@@ -26,6 +27,7 @@ pub struct ContentDocument {
     pub line_end_indices: Vec<u32>,
     pub symbol_locations: SymbolLocations,
     pub branches: Option<String>,
+    pub license: Option<String>,
 }
 
 impl ContentDocument {
@@ -43,6 +45,7 @@ pub struct FileDocument {
     pub repo_ref: String,
     pub lang: Option<String>,
     pub branches: String,
+    pub license: Option<String>,
 }
 
 pub struct RepoDocument {
@@ -74,15 +77,19 @@ impl DocumentRead for ContentReader {
         schema: &File,
         queries: I,
         tantivy_index: &Index,
+        tuning: &LexicalTuning,
     ) -> Result<Box<dyn tantivy::query::Query>>
     where
         I: Iterator<Item = &'a Query<'a>>,
     {
         Compiler::new()
-            .priority(&[schema.relative_path])
+            .boost(schema.relative_path, tuning.path_boost)
+            .boost(schema.content, tuning.content_boost)
+            .boost(schema.symbols, tuning.symbol_boost)
             .literal(schema.relative_path, |q| q.path.clone())
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
+            .exclude_unless(schema.is_revision, is_revision_query)
             .byte_string(schema.lang, |q| q.lang.as_ref())
             .literal(schema.symbols, |q| {
                 q.target.as_ref().and_then(Target::symbol).cloned()
@@ -100,6 +107,7 @@ impl DocumentRead for ContentReader {
         let content = read_text_field(&doc, schema.content);
         let lang = read_lang_field(&doc, schema.lang);
         let branches = read_lang_field(&doc, schema.branches);
+        let license = read_optional_text_field(&doc, schema.license);
 
         let line_end_indices = doc
             .get_first(schema.line_end_indices)
@@ -127,10 +135,60 @@ impl DocumentRead for ContentReader {
             line_end_indices,
             lang,
             branches,
+            license,
         }
     }
 }
 
+/// Reads the documents of files with code of the same shape as a `structural:` pattern.
+pub struct StructuralReader;
+
+#[async_trait]
+impl DocumentRead for StructuralReader {
+    type Schema = File;
+    type Document = ContentDocument;
+
+    fn query_matches(&self, query: &Query<'_>) -> bool {
+        matches!(
+            query,
+            Query {
+                open: Some(false) | None,
+                target: Some(Target::Structural(..)),
+                ..
+            }
+        )
+    }
+
+    fn compile<'a, I>(
+        &self,
+        schema: &File,
+        queries: I,
+        tantivy_index: &Index,
+        _tuning: &LexicalTuning,
+    ) -> Result<Box<dyn tantivy::query::Query>>
+    where
+        I: Iterator<Item = &'a Query<'a>>,
+    {
+        let queries = queries.collect::<Vec<_>>();
+        for query in &queries {
+            structural_fingerprints(query)?;
+        }
+
+        Compiler::new()
+            .literal(schema.relative_path, |q| q.path.clone())
+            .literal(schema.repo_name, |q| q.repo.clone())
+            .literal(schema.branches, |q| q.branch.clone())
+            .exclude_unless(schema.is_revision, is_revision_query)
+            .byte_string(schema.lang, |q| q.lang.as_ref())
+            .terms(schema.fingerprints, |q| structural_fingerprints(q).ok())
+            .compile(queries.into_iter(), tantivy_index)
+    }
+
+    fn read_document(&self, schema: &File, doc: tantivy::Document) -> Self::Document {
+        ContentReader.read_document(schema, doc)
+    }
+}
+
 pub struct FileReader;
 
 #[async_trait]
@@ -164,6 +222,7 @@ impl DocumentRead for FileReader {
         schema: &Self::Schema,
         queries: I,
         tantivy_index: &Index,
+        _tuning: &LexicalTuning,
     ) -> Result<Box<dyn tantivy::query::Query>>
     where
         I: Iterator<Item = &'a Query<'a>>,
@@ -172,6 +231,7 @@ impl DocumentRead for FileReader {
             .literal(schema.relative_path, |q| q.path.clone())
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
+            .exclude_unless(schema.is_revision, is_revision_query)
             .byte_string(schema.lang, |q| q.lang.as_ref())
             .compile(queries, tantivy_index)
     }
@@ -182,6 +242,7 @@ impl DocumentRead for FileReader {
         let repo_name = read_text_field(&doc, schema.repo_name);
         let lang = read_lang_field(&doc, schema.lang);
         let branches = read_text_field(&doc, schema.branches);
+        let license = read_optional_text_field(&doc, schema.license);
 
         FileDocument {
             relative_path,
@@ -189,6 +250,7 @@ impl DocumentRead for FileReader {
             repo_ref,
             lang,
             branches,
+            license,
         }
     }
 }
@@ -218,6 +280,7 @@ impl DocumentRead for RepoReader {
         schema: &Repo,
         queries: I,
         tantivy_index: &Index,
+        _tuning: &LexicalTuning,
     ) -> Result<Box<dyn tantivy::query::Query>>
     where
         I: Iterator<Item = &'a Query<'a>>,
@@ -281,6 +344,7 @@ impl DocumentRead for OpenReader {
         schema: &File,
         queries: I,
         tantivy_index: &Index,
+        _tuning: &LexicalTuning,
     ) -> Result<Box<dyn tantivy::query::Query>>
     where
         I: Iterator<Item = &'a Query<'a>>,
@@ -288,6 +352,7 @@ impl DocumentRead for OpenReader {
         Compiler::new()
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
+            .exclude_unless(schema.is_revision, is_revision_query)
             .literal(schema.relative_path, |q| match &q.path {
                 // We coerce path searches to always return sibling files. These are sorted later
                 // by users of this reader.
@@ -340,10 +405,43 @@ pub fn base_name(path: &str) -> &str {
     path.rfind('/').map(|i| &path[..i + 1]).unwrap_or("")
 }
 
+/// The fingerprints that a file must all have to match the pattern of a structural query, which
+/// is parsed in the language that the query filters on.
+pub fn structural_fingerprints(
+    query: &Query<'_>,
+) -> Result<Vec<String>, fingerprint::PatternError> {
+    let pattern = match query.target.as_ref().and_then(Target::structural) {
+        Some(parser::Literal::Plain(pattern)) => pattern,
+        Some(parser::Literal::Regex(_)) => return Err(fingerprint::PatternError::Regex),
+        None => return Ok(vec![]),
+    };
+
+    let lang = query
+        .lang
+        .as_deref()
+        .ok_or(fingerprint::PatternError::MissingLanguage)?;
+
+    fingerprint::pattern(pattern, lang)
+}
+
+/// Whether a query asks for documents from historical revisions, which are excluded otherwise.
+fn is_revision_query(query: &Query<'_>) -> bool {
+    query
+        .branch
+        .as_ref()
+        .map_or(false, parser::Literal::is_revision)
+}
+
 fn read_text_field(doc: &tantivy::Document, field: Field) -> String {
     doc.get_first(field).unwrap().as_text().unwrap().to_owned()
 }
 
+fn read_optional_text_field(doc: &tantivy::Document, field: Field) -> Option<String> {
+    doc.get_first(field)
+        .and_then(Value::as_text)
+        .map(ToOwned::to_owned)
+}
+
 fn read_lang_field(doc: &tantivy::Document, lang: Field) -> Option<String> {
     let lang_str = crate::query::languages::proper_case(
         doc.get_first(lang)
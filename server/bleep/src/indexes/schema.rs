@@ -3,7 +3,7 @@
 //!
 use tantivy::schema::{
     BytesOptions, Field, IndexRecordOption, Schema, SchemaBuilder, TextFieldIndexing, TextOptions,
-    FAST, STORED, STRING,
+    FAST, INDEXED, STORED, STRING,
 };
 
 use crate::{db::SqlDb, semantic::Semantic};
@@ -51,6 +51,9 @@ pub struct File {
     pub symbols: Field,
     pub symbol_locations: Field,
 
+    /// fingerprints of the shapes of syntax nodes, for structural search
+    pub fingerprints: Field,
+
     /// fast fields for scoring
     pub lang: Field,
     pub avg_line_length: Field,
@@ -64,8 +67,14 @@ pub struct File {
     /// list of branches in which this file can be found
     pub branches: Field,
 
+    /// SPDX expression of the file license, falling back to the repository license
+    pub license: Field,
+
     /// Whether this entry is a file or a directory
     pub is_directory: Field,
+
+    /// Whether this entry only exists in historical revisions, rather than in branches
+    pub is_revision: Field,
 }
 
 impl File {
@@ -91,8 +100,10 @@ impl File {
         let symbols = builder.add_text_field("symbols", trigram.clone());
         let symbol_locations =
             builder.add_bytes_field("symbol_locations", BytesOptions::default().set_stored());
+        let fingerprints = builder.add_text_field("fingerprints", STRING);
 
         let branches = builder.add_text_field("branches", trigram);
+        let license = builder.add_text_field("license", STRING | STORED);
 
         let lang = builder.add_bytes_field(
             "lang",
@@ -106,6 +117,7 @@ impl File {
         let raw_relative_path = builder.add_bytes_field("raw_relative_path", FAST);
 
         let is_directory = builder.add_bool_field("is_directory", FAST);
+        let is_revision = builder.add_bool_field("is_revision", INDEXED);
 
         Self {
             repo_disk_path,
@@ -117,6 +129,7 @@ impl File {
             line_end_indices,
             symbols,
             symbol_locations,
+            fingerprints,
             lang,
             avg_line_length,
             last_commit_unix_seconds,
@@ -126,7 +139,9 @@ impl File {
             raw_repo_name,
             raw_relative_path,
             branches,
+            license,
             is_directory,
+            is_revision,
             sql,
 
             #[cfg(feature = "debug")]
@@ -1,6 +1,8 @@
 pub mod code_navigation;
+pub mod fingerprint;
 mod language;
 mod namespace;
+pub mod precise;
 mod scope_resolution;
 
 pub use {
@@ -9,6 +11,8 @@ pub use {
     scope_resolution::{NodeKind, ScopeGraph},
 };
 
+use std::ops::Range;
+
 use scope_resolution::ResolutionMethod;
 use tree_sitter::{Parser, Tree};
 
@@ -82,6 +86,27 @@ impl<'a> TreeSitterFile<'a> {
             .collect::<Vec<_>>())
     }
 
+    /// The byte ranges of the outermost syntax nodes of this file for which `fits` holds, in
+    /// order. Nodes that don't fit are replaced by their children, and leaves are kept whether
+    /// they fit or not.
+    pub fn outermost_ranges(&self, fits: impl Fn(Range<usize>) -> bool) -> Vec<Range<usize>> {
+        let root = self.tree.root_node();
+        let mut stack = root.children(&mut root.walk()).collect::<Vec<_>>();
+        stack.reverse();
+
+        let mut ranges = vec![];
+        while let Some(node) = stack.pop() {
+            if node.child_count() == 0 || fits(node.byte_range()) {
+                ranges.push(node.byte_range());
+            } else {
+                let children = node.children(&mut node.walk()).collect::<Vec<_>>();
+                stack.extend(children.into_iter().rev());
+            }
+        }
+
+        ranges
+    }
+
     /// Produce a lexical scope-graph for this TreeSitterFile.
     pub fn scope_graph(self) -> Result<ScopeGraph, TreeSitterFileError> {
         let query = self
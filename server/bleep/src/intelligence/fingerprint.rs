@@ -0,0 +1,266 @@
+//! Structural fingerprints of syntax trees, to search for code by its shape.
+//!
+//! The fingerprint of a syntax node hashes the kinds of the nodes in its subtree, along with the
+//! keywords and punctuation between them. The text of identifiers and literals is left out, as are
+//! comments and separators, so code of the same structure has the same fingerprint however it
+//! names things and whatever its formatting.
+//!
+//! The fingerprints of every file are indexed, and `structural:` queries look up those of their
+//! pattern.
+
+use std::ops::Range;
+
+use tree_sitter::Node;
+
+use super::{TreeSitterFile, TreeSitterFileError};
+
+/// Nodes with fewer nodes than this in their subtree, like a field access or a call with a single
+/// argument, are too common to search for, and are not fingerprinted.
+pub const MIN_NODES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub hash: String,
+    /// The byte range of the fingerprinted node.
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum PatternError {
+    #[error("structural queries need a `lang:` filter")]
+    MissingLanguage,
+    #[error("structural patterns are code, not regexes")]
+    Regex,
+    #[error("structural search does not support `{0}`")]
+    UnsupportedLanguage(String),
+    #[error("the structural pattern is not valid `{0}`")]
+    Syntax(String),
+    #[error("the structural pattern is too small to search for")]
+    TooSmall,
+}
+
+impl<'a> TreeSitterFile<'a> {
+    /// The fingerprints of the named nodes of this file that are large enough to search for.
+    pub fn fingerprints(&self) -> Vec<Fingerprint> {
+        let mut fingerprints = vec![];
+        walk(self.tree.root_node(), |node, shape| {
+            if node.is_named() && shape.nodes >= MIN_NODES {
+                fingerprints.push(Fingerprint {
+                    hash: shape.fingerprint(),
+                    range: node.byte_range(),
+                });
+            }
+        });
+
+        fingerprints
+    }
+}
+
+/// The fingerprints of a `structural:` pattern, parsed as `lang`: one for each of its top-level
+/// statements or expressions, which code must all contain to match.
+pub fn pattern(pattern: &str, lang: &str) -> Result<Vec<String>, PatternError> {
+    let file = TreeSitterFile::try_build(pattern.as_bytes(), lang).map_err(|err| match err {
+        TreeSitterFileError::UnsupportedLanguage => {
+            PatternError::UnsupportedLanguage(lang.to_owned())
+        }
+        _ => PatternError::Syntax(lang.to_owned()),
+    })?;
+
+    let root = file.tree.root_node();
+    if root.has_error() {
+        return Err(PatternError::Syntax(lang.to_owned()));
+    }
+
+    let shapes = root
+        .named_children(&mut root.walk())
+        .filter(|&node| !is_skipped(node))
+        .map(|node| walk(unwrapped(node), |_, _| {}))
+        .collect::<Vec<_>>();
+
+    if shapes.is_empty() || shapes.iter().any(|shape| shape.nodes < MIN_NODES) {
+        return Err(PatternError::TooSmall);
+    }
+
+    Ok(shapes.iter().map(Shape::fingerprint).collect())
+}
+
+/// Leave out wrappers of a single node, like the statement around a lone expression, as the code
+/// that a pattern is looked for in may not have them.
+fn unwrapped(mut node: Node<'_>) -> Node<'_> {
+    loop {
+        let mut cursor = node.walk();
+        let mut children = node
+            .children(&mut cursor)
+            .filter(|&child| !is_skipped(child));
+
+        match (children.next(), children.next()) {
+            (Some(child), None) if child.is_named() => node = child,
+            _ => return node,
+        }
+    }
+}
+
+/// Whether `node` is left out of shapes: comments and other extras, and the separators that
+/// formatting adds or removes, like trailing commas.
+fn is_skipped(node: Node<'_>) -> bool {
+    node.is_extra() || (!node.is_named() && matches!(node.kind(), "," | ";"))
+}
+
+/// The shape of a subtree.
+struct Shape {
+    hash: blake3::Hash,
+    nodes: usize,
+}
+
+impl Shape {
+    fn fingerprint(&self) -> String {
+        self.hash.to_hex()[..16].to_owned()
+    }
+}
+
+/// A node whose shape is computed from the shapes of its children, as they are walked.
+struct Frame<'a> {
+    node: Node<'a>,
+    hasher: blake3::Hasher,
+    nodes: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn new(node: Node<'a>) -> Self {
+        // Named leaves are identifiers and literals, which are abstracted to their kind, while
+        // anonymous ones are keywords and punctuation, whose kind is their text.
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[node.is_named() as u8]);
+        hasher.update(&(node.kind().len() as u32).to_le_bytes());
+        hasher.update(node.kind().as_bytes());
+
+        Self {
+            node,
+            hasher,
+            nodes: 1,
+        }
+    }
+
+    fn add(&mut self, child: Shape) {
+        self.hasher.update(child.hash.as_bytes());
+        self.nodes += child.nodes;
+    }
+
+    fn finish(self) -> Shape {
+        Shape {
+            hash: self.hasher.finalize(),
+            nodes: self.nodes,
+        }
+    }
+}
+
+/// Compute the shape of the subtree of `root`, and call `visit` with each node in it and its
+/// shape, children first. Skipped nodes aren't visited, and aren't part of the shape.
+///
+/// The tree is walked with a cursor rather than recursively, as deeply nested code would
+/// otherwise overflow the stack.
+fn walk<'a>(root: Node<'a>, mut visit: impl FnMut(Node<'a>, &Shape)) -> Shape {
+    let mut cursor = root.walk();
+    let mut frames = vec![Frame::new(root)];
+
+    loop {
+        if cursor.goto_first_child() {
+            frames.push(Frame::new(cursor.node()));
+            continue;
+        }
+
+        loop {
+            let frame = frames.pop().unwrap();
+            let node = frame.node;
+            let shape = frame.finish();
+
+            let Some(parent) = frames.last_mut() else {
+                visit(node, &shape);
+                return shape;
+            };
+
+            if !is_skipped(node) {
+                visit(node, &shape);
+                parent.add(shape);
+            }
+
+            if cursor.goto_next_sibling() {
+                frames.push(Frame::new(cursor.node()));
+                break;
+            }
+
+            cursor.goto_parent();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprints(src: &str, lang: &str) -> Vec<Fingerprint> {
+        TreeSitterFile::try_build(src.as_bytes(), lang)
+            .unwrap()
+            .fingerprints()
+    }
+
+    #[test]
+    fn same_shape_regardless_of_naming() {
+        let src = r#"
+fn load(path: &Path) -> Result<Config> {
+    let text = match read_config(path) {
+        Ok(text) => text,
+        Err(err) => return Err(err.into()),
+    };
+    parse(&text)
+}
+
+fn fetch(url: &str) -> Result<Page> {
+    // comments don't change the shape
+    let body = match fetch_page(url) {
+        Ok(response) => response,
+        Err(e) => return Err(e.into())
+    };
+    render(&body)
+}
+"#;
+
+        let [returns] = pattern(
+            "match read(x) { Ok(v) => v, Err(e) => return Err(e.into()) }",
+            "rust",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        let matches = fingerprints(src, "rust")
+            .into_iter()
+            .filter(|f| f.hash == returns)
+            .map(|f| &src[f.range])
+            .collect::<Vec<_>>();
+
+        assert_eq!(matches.len(), 2);
+        assert!(matches[0].starts_with("match read_config(path)"));
+        assert!(matches[1].starts_with("match fetch_page(url)"));
+
+        // Panicking instead of returning errors is a different shape.
+        let [panics] = pattern("match read(x) { Ok(v) => v, Err(e) => panic!() }", "rust")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_ne!(panics, returns);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(pattern("x.y;", "rust"), Err(PatternError::TooSmall));
+        assert_eq!(
+            pattern("match x {", "rust"),
+            Err(PatternError::Syntax("rust".into()))
+        );
+        assert_eq!(
+            pattern("match x { _ => {} }", "cobol"),
+            Err(PatternError::UnsupportedLanguage("cobol".into()))
+        );
+    }
+}
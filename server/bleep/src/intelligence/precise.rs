@@ -0,0 +1,479 @@
+//! Precise symbols, imported from SCIP and LSIF dumps and from ctags files.
+//!
+//! Compiler-backed indexers know which definition each reference resolves to, which scope graphs
+//! only approximate, and they cover languages that have no scope queries at all. Dumps are
+//! uploaded to `POST /repos/symbols`, and a ctags file at the root of a repository is imported
+//! whenever the repository is indexed.
+//!
+//! Each file keeps the symbols of the richest source that covers it: SCIP, then LSIF, then ctags,
+//! which only lists definitions. Code navigation prefers SCIP and LSIF symbols over scope graphs,
+//! and falls back to ctags definitions when scope graphs find nothing.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::code_navigation::{to_occurrence, FileSymbols, Occurrence, OccurrenceKind};
+use crate::{
+    db::{PreciseOccurrence, PreciseSymbols},
+    indexes::{reader::ContentDocument, Indexes},
+    repo::RepoRef,
+    text_range::{Point, TextRange},
+};
+
+mod ctags;
+mod lsif;
+mod scip;
+
+/// The ctags files that are imported when a repository is indexed.
+pub const CTAGS_FILES: &[&str] = &["tags", ".tags"];
+
+/// Where symbols come from, from the least to the most precise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Ctags,
+    Lsif,
+    Scip,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ctags => "ctags",
+            Self::Lsif => "lsif",
+            Self::Scip => "scip",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        [Self::Ctags, Self::Lsif, Self::Scip]
+            .into_iter()
+            .find(|source| source.as_str() == s)
+    }
+
+    /// Whether code navigation prefers these symbols over scope graphs.
+    fn is_precise(self) -> bool {
+        self != Self::Ctags
+    }
+}
+
+/// How the columns of a dump count characters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    /// As in LSP, and in SCIP documents that don't specify their encoding.
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+/// A position as written in a dump, with a 0-indexed line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Span {
+    Range(Position, Position),
+    /// A ctags definition on a 0-indexed line, where the symbol is looked up by name.
+    Line(usize),
+    /// A ctags definition on the line that matches a search pattern.
+    Pattern(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    symbol: String,
+    definition: bool,
+    span: Span,
+}
+
+#[derive(Debug, Default)]
+struct File {
+    encoding: Encoding,
+    entries: Vec<Entry>,
+}
+
+/// The symbols of a dump, by file.
+#[derive(Debug)]
+pub struct Dump {
+    source: Source,
+    files: BTreeMap<String, File>,
+}
+
+impl Dump {
+    pub fn parse(source: Source, data: &[u8]) -> Result<Self> {
+        let files = match source {
+            Source::Scip => scip::parse(data)?,
+            Source::Lsif => lsif::parse(std::str::from_utf8(data).context("invalid UTF-8")?)?,
+            Source::Ctags => ctags::parse(&String::from_utf8_lossy(data)),
+        };
+
+        Ok(Self { source, files })
+    }
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ImportStats {
+    /// The number of files whose symbols were imported.
+    pub files: usize,
+    pub occurrences: usize,
+    /// Files that are not indexed, or whose symbols come from a richer source.
+    pub skipped: Vec<String>,
+}
+
+/// Replace the symbols of the files in `dump`, unless they come from a richer source.
+///
+/// Dumps are matched against the default branch of the index. Occurrences that don't fit the
+/// indexed content are left out, as the file changed since the dump was made.
+pub async fn import(
+    indexes: &Indexes,
+    db: &SqlitePool,
+    repo_ref: &RepoRef,
+    dump: &Dump,
+) -> Result<ImportStats> {
+    let store = PreciseSymbols::new(db);
+    let repo = repo_ref.to_string();
+    let mut stats = ImportStats::default();
+
+    for (path, file) in &dump.files {
+        let existing = store.source(&repo, path).await?;
+        if existing
+            .as_deref()
+            .and_then(Source::from_str)
+            .map_or(false, |existing| existing > dump.source)
+        {
+            stats.skipped.push(path.clone());
+            continue;
+        }
+
+        let Some(doc) = indexes.file.by_path(repo_ref, path, None).await? else {
+            stats.skipped.push(path.clone());
+            continue;
+        };
+
+        let occurrences = resolve(file, &doc);
+        store
+            .replace_file(&repo, path, dump.source.as_str(), &occurrences)
+            .await?;
+
+        stats.files += 1;
+        stats.occurrences += occurrences.len();
+    }
+
+    Ok(stats)
+}
+
+/// Navigate from the token at `range` of `doc` with imported SCIP or LSIF symbols.
+///
+/// As with scope graphs, a definition leads to its references, and anything else to the
+/// definitions and references of its symbol. Returns `None` if no such symbol covers the token.
+pub async fn navigate(
+    indexes: &Indexes,
+    db: &SqlitePool,
+    repo_ref: &RepoRef,
+    doc: &ContentDocument,
+    range: Range<usize>,
+) -> Result<Option<Vec<FileSymbols>>> {
+    let store = PreciseSymbols::new(db);
+    let repo = repo_ref.to_string();
+
+    let hovered = store
+        .at(&repo, &doc.relative_path, range.start, range.end)
+        .await?
+        .into_iter()
+        .filter(|(source, _)| Source::from_str(source).map_or(false, Source::is_precise))
+        .find(|(_, occurrence)| is_current(occurrence, doc));
+
+    let Some((source, hovered)) = hovered else {
+        return Ok(None);
+    };
+
+    let occurrences = store
+        .occurrences(&repo, &source, &hovered.symbol)
+        .await?
+        .into_iter()
+        .filter(|o| o.relative_path != hovered.relative_path || o.range != hovered.range)
+        .filter(|o| !(hovered.is_definition && o.is_definition));
+
+    file_symbols(indexes, repo_ref, doc, occurrences)
+        .await
+        .map(Some)
+}
+
+/// The ctags definitions of `name`, for tokens that scope graphs could not resolve.
+pub async fn ctags_definitions(
+    indexes: &Indexes,
+    db: &SqlitePool,
+    repo_ref: &RepoRef,
+    doc: &ContentDocument,
+    name: &str,
+) -> Result<Vec<FileSymbols>> {
+    let occurrences = PreciseSymbols::new(db)
+        .occurrences(&repo_ref.to_string(), Source::Ctags.as_str(), name)
+        .await?;
+
+    file_symbols(indexes, repo_ref, doc, occurrences.into_iter()).await
+}
+
+/// Group occurrences by file, with the files that contain definitions first.
+///
+/// Occurrences in files that changed since they were imported are left out.
+async fn file_symbols(
+    indexes: &Indexes,
+    repo_ref: &RepoRef,
+    source_doc: &ContentDocument,
+    occurrences: impl Iterator<Item = PreciseOccurrence>,
+) -> Result<Vec<FileSymbols>> {
+    let mut by_path = BTreeMap::<String, Vec<PreciseOccurrence>>::new();
+    for occurrence in occurrences {
+        by_path
+            .entry(occurrence.relative_path.clone())
+            .or_default()
+            .push(occurrence);
+    }
+
+    let mut files = Vec::new();
+    for (path, occurrences) in by_path {
+        let fetched;
+        let doc = if path == source_doc.relative_path {
+            source_doc
+        } else {
+            fetched = indexes.file.by_path(repo_ref, &path, None).await?;
+            match &fetched {
+                Some(doc) => doc,
+                None => continue,
+            }
+        };
+
+        let data = occurrences
+            .into_iter()
+            .filter(|o| is_current(o, doc))
+            .map(|o| Occurrence {
+                kind: if o.is_definition {
+                    OccurrenceKind::Definition
+                } else {
+                    OccurrenceKind::Reference
+                },
+                range: o.range,
+                snippet: to_occurrence(doc, o.range),
+            })
+            .collect::<Vec<_>>();
+
+        if !data.is_empty() {
+            files.push(FileSymbols { file: path, data });
+        }
+    }
+
+    files.sort_by_key(|f| !f.data.iter().any(Occurrence::is_definition));
+    Ok(files)
+}
+
+/// Whether the file still has the text that an occurrence was imported with.
+fn is_current(occurrence: &PreciseOccurrence, doc: &ContentDocument) -> bool {
+    let TextRange { start, end } = occurrence.range;
+    doc.content.get(start.byte..end.byte) == Some(occurrence.name.as_str())
+}
+
+/// Turn the entries of a file into ranges of its content, leaving out those that don't fit.
+fn resolve(file: &File, doc: &ContentDocument) -> Vec<PreciseOccurrence> {
+    let lines = Lines::new(&doc.content);
+
+    file.entries
+        .iter()
+        .filter_map(|entry| {
+            let (start, end) = match &entry.span {
+                Span::Range(start, end) => (
+                    lines.point(*start, file.encoding)?,
+                    lines.point(*end, file.encoding)?,
+                ),
+                Span::Line(line) => lines.find(*line, &entry.symbol)?,
+                Span::Pattern(pattern) => lines.find(lines.matching(pattern)?, &entry.symbol)?,
+            };
+
+            if start.byte >= end.byte {
+                return None;
+            }
+
+            Some(PreciseOccurrence {
+                relative_path: doc.relative_path.clone(),
+                symbol: entry.symbol.clone(),
+                name: doc.content[start.byte..end.byte].to_owned(),
+                is_definition: entry.definition,
+                range: TextRange { start, end },
+            })
+        })
+        .collect()
+}
+
+/// The lines of a file, to turn the positions of a dump into byte offsets.
+struct Lines<'a> {
+    content: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(content: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self { content, starts }
+    }
+
+    /// The text of a line, without its line break.
+    fn get(&self, line: usize) -> Option<&'a str> {
+        let start = *self.starts.get(line)?;
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.content.len(), |next| next - 1);
+
+        Some(self.content[start..end].trim_end_matches('\r'))
+    }
+
+    fn point(&self, position: Position, encoding: Encoding) -> Option<Point> {
+        let text = self.get(position.line)?;
+        let column = column(text, position.character, encoding)?;
+        Some(Point::new(
+            self.starts[position.line] + column,
+            position.line,
+            column,
+        ))
+    }
+
+    /// The first occurrence of `name` as a whole word on a line.
+    fn find(&self, line: usize, name: &str) -> Option<(Point, Point)> {
+        if name.is_empty() {
+            return None;
+        }
+
+        let text = self.get(line)?;
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let (column, _) = text.match_indices(name).find(|(i, _)| {
+            !text[..*i].chars().next_back().map_or(false, is_word)
+                && !text[i + name.len()..].chars().next().map_or(false, is_word)
+        })?;
+
+        let start = self.starts[line] + column;
+        Some((
+            Point::new(start, line, column),
+            Point::new(start + name.len(), line, column + name.len()),
+        ))
+    }
+
+    /// The first line that matches a ctags search pattern, which is anchored with `^` and `$`.
+    fn matching(&self, pattern: &str) -> Option<usize> {
+        let (text, at_start) = match pattern.strip_prefix('^') {
+            Some(text) => (text, true),
+            None => (pattern, false),
+        };
+        let (text, at_end) = match text.strip_suffix('$') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+
+        (0..self.starts.len()).find(|&i| {
+            let line = self.get(i).unwrap_or_default();
+            match (at_start, at_end) {
+                (true, true) => line == text,
+                (true, false) => line.starts_with(text),
+                (false, true) => line.ends_with(text),
+                (false, false) => line.contains(text),
+            }
+        })
+    }
+}
+
+/// The byte offset of a column in a line.
+fn column(text: &str, character: usize, encoding: Encoding) -> Option<usize> {
+    if encoding == Encoding::Utf8 {
+        return text.is_char_boundary(character).then_some(character);
+    }
+
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units == character {
+            return Some(i);
+        }
+
+        units += match encoding {
+            Encoding::Utf16 => c.len_utf16(),
+            _ => 1,
+        };
+    }
+
+    (units == character).then_some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(content: &str) -> ContentDocument {
+        ContentDocument {
+            content: content.to_owned(),
+            relative_path: "src/main.c".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resolves_ctags_definitions() {
+        let tags = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+                    main\t./src/main.c\t/^int main(void)$/;\"\tf\n\
+                    helper\tsrc/main.c\t/^static int helper(int x)$/;\"\tkind:function\tline:99\n\
+                    count\tsrc/main.c\t3;\"\tv\n";
+        let dump = Dump::parse(Source::Ctags, tags.as_bytes()).unwrap();
+        let content = "static int helper(int x) { return x; }\nint main(void)\nint count = 0;\n";
+
+        let occurrences = resolve(&dump.files["src/main.c"], &doc(content));
+        let found = occurrences
+            .iter()
+            .map(|o| (o.name.as_str(), o.range.start.line, o.range.start.column))
+            .collect::<Vec<_>>();
+
+        // the line of `helper` is out of date, so it is left out
+        assert_eq!(found, [("main", 1, 4), ("count", 2, 4)]);
+        assert!(occurrences.iter().all(|o| o.is_definition));
+        assert_eq!(occurrences[0].symbol, "main");
+    }
+
+    #[test]
+    fn converts_columns() {
+        let line = "let é = \"😀\"; x";
+
+        assert_eq!(column(line, 4, Encoding::Utf8), Some(4));
+        assert_eq!(column(line, 5, Encoding::Utf8), None);
+        assert_eq!(column(line, 14, Encoding::Utf16), Some(line.len() - 1));
+        assert_eq!(column(line, 13, Encoding::Utf32), Some(line.len() - 1));
+        assert_eq!(column(line, 15, Encoding::Utf16), Some(line.len()));
+        assert_eq!(column(line, 16, Encoding::Utf16), None);
+
+        // in the middle of a surrogate pair
+        assert_eq!(column(line, 10, Encoding::Utf16), None);
+    }
+
+    #[test]
+    fn finds_whole_words() {
+        let lines = Lines::new("a\nfoobar(foo);\r\n");
+
+        let (start, end) = lines.find(1, "foo").unwrap();
+        assert_eq!((start.byte, end.byte), (9, 12));
+        assert_eq!((start.line, start.column), (1, 7));
+        assert_eq!(lines.get(1), Some("foobar(foo);"));
+        assert!(lines.find(0, "foo").is_none());
+    }
+
+    #[test]
+    fn ranks_sources() {
+        assert!(Source::Scip > Source::Lsif && Source::Lsif > Source::Ctags);
+        assert_eq!(Source::from_str("lsif"), Some(Source::Lsif));
+        assert!(!Source::Ctags.is_precise());
+    }
+}
@@ -0,0 +1,66 @@
+//! A reader for ctags files, as written by Universal Ctags and Exuberant Ctags.
+//!
+//! Each line is `name<TAB>file<TAB>address`, optionally followed by `;"` and extension fields.
+//! The address is a line number or a search pattern. ctags only list definitions, so the name of
+//! a definition is its symbol.
+
+use std::collections::BTreeMap;
+
+use super::{Entry, File, Span};
+
+pub(super) fn parse(data: &str) -> BTreeMap<String, File> {
+    let mut files = BTreeMap::<String, File>::new();
+
+    for line in data.lines() {
+        // pseudo-tags describe the file itself
+        if line.starts_with("!_") {
+            continue;
+        }
+
+        let mut columns = line.splitn(3, '\t');
+        let (Some(name), Some(path), Some(rest)) = (columns.next(), columns.next(), columns.next())
+        else {
+            continue;
+        };
+
+        let (address, fields) = rest.split_once(";\"").unwrap_or((rest, ""));
+        let line_field = fields
+            .split('\t')
+            .find_map(|field| field.strip_prefix("line:"))
+            .and_then(|n| n.parse::<usize>().ok());
+
+        let span = match line_field.or_else(|| address.trim().parse().ok()) {
+            // ctags lines are 1-indexed
+            Some(0) => continue,
+            Some(line) => Span::Line(line - 1),
+            None => match pattern(address) {
+                Some(pattern) => Span::Pattern(pattern),
+                None => continue,
+            },
+        };
+
+        let path = path.strip_prefix("./").unwrap_or(path);
+        files
+            .entry(path.to_owned())
+            .or_default()
+            .entries
+            .push(Entry {
+                symbol: name.to_owned(),
+                definition: true,
+                span,
+            });
+    }
+
+    files
+}
+
+/// The text of a search pattern address, such as `/^int main(void)$/`.
+fn pattern(address: &str) -> Option<String> {
+    let address = address.trim();
+    let pattern = address
+        .strip_prefix('/')
+        .and_then(|a| a.strip_suffix('/'))
+        .or_else(|| address.strip_prefix('?').and_then(|a| a.strip_suffix('?')))?;
+
+    Some(pattern.replace("\\/", "/").replace("\\\\", "\\"))
+}
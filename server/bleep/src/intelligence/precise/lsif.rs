@@ -0,0 +1,217 @@
+//! A reader for LSIF dumps, which are graphs of JSON vertices and edges.
+//!
+//! Ranges that lead to the same result set through `next` edges are occurrences of the same
+//! symbol, and the ranges listed by definition results are its definitions. Result sets are only
+//! unique within a dump, so symbols are namespaced with an identifier of the import.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{Encoding, Entry, File, Position, Span};
+
+/// Chains of result sets are short, this only guards against cycles.
+const MAX_NEXT_EDGES: usize = 64;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Element {
+    id: Value,
+    #[serde(rename = "type")]
+    kind: String,
+    label: String,
+
+    // vertices
+    uri: Option<String>,
+    project_root: Option<String>,
+    start: Option<Position>,
+    end: Option<Position>,
+
+    // edges
+    out_v: Option<Value>,
+    in_v: Option<Value>,
+    #[serde(default)]
+    in_vs: Vec<Value>,
+    property: Option<String>,
+}
+
+/// Dumps are usually written one element per line, but some tools write a JSON array.
+pub(super) fn parse(data: &str) -> Result<BTreeMap<String, File>> {
+    let elements = if data.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<Element>>(data).context("invalid LSIF dump")?
+    } else {
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str::<Element>(line)
+                    .with_context(|| format!("invalid LSIF element on line {}", i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let import = uuid::Uuid::new_v4().simple().to_string();
+    let mut project_root = None;
+    let mut documents = HashMap::new();
+    let mut ranges = HashMap::new();
+    let mut next = HashMap::new();
+    let mut contained_in = HashMap::new();
+    let mut definition_results = HashSet::new();
+    let mut items = Vec::new();
+
+    for element in elements {
+        let out_v = element.out_v.as_ref().map(id);
+
+        match (element.kind.as_str(), element.label.as_str()) {
+            ("vertex", "metaData") => project_root = element.project_root,
+            ("vertex", "document") => {
+                if let Some(uri) = element.uri {
+                    documents.insert(id(&element.id), uri);
+                }
+            }
+            ("vertex", "range") => {
+                if let (Some(start), Some(end)) = (element.start, element.end) {
+                    ranges.insert(id(&element.id), (start, end));
+                }
+            }
+            ("vertex", "definitionResult") => {
+                definition_results.insert(id(&element.id));
+            }
+            ("edge", "next") => {
+                if let (Some(out_v), Some(in_v)) = (out_v, element.in_v.as_ref()) {
+                    next.insert(out_v, id(in_v));
+                }
+            }
+            ("edge", "contains") => {
+                if let Some(out_v) = out_v {
+                    for in_v in &element.in_vs {
+                        contained_in.insert(id(in_v), out_v.clone());
+                    }
+                }
+            }
+            ("edge", "item") => {
+                if let Some(out_v) = out_v {
+                    items.push((out_v, element.in_vs, element.property));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let definitions = items
+        .into_iter()
+        .filter(|(out_v, _, property)| {
+            definition_results.contains(out_v) || property.as_deref() == Some("definitions")
+        })
+        .flat_map(|(_, in_vs, _)| in_vs)
+        .map(|in_v| id(&in_v))
+        .collect::<HashSet<_>>();
+
+    let mut files = BTreeMap::<String, File>::new();
+    for (range, (start, end)) in ranges {
+        let Some(uri) = contained_in.get(&range).and_then(|doc| documents.get(doc)) else {
+            continue;
+        };
+
+        let mut result_set = &range;
+        for _ in 0..MAX_NEXT_EDGES {
+            match next.get(result_set) {
+                Some(id) => result_set = id,
+                None => break,
+            }
+        }
+
+        // ranges without results are not symbols
+        if *result_set == range {
+            continue;
+        }
+
+        let file = files
+            .entry(relative_path(uri, project_root.as_deref()))
+            .or_insert_with(|| File {
+                encoding: Encoding::Utf16,
+                entries: Vec::new(),
+            });
+
+        file.entries.push(Entry {
+            symbol: format!("{import}:{result_set}"),
+            definition: definitions.contains(&range),
+            span: Span::Range(start, end),
+        });
+    }
+
+    for file in files.values_mut() {
+        file.entries.sort_by(|a, b| a.span.cmp(&b.span));
+    }
+
+    Ok(files)
+}
+
+/// Ids are numbers or strings, depending on the tool.
+fn id(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn relative_path(uri: &str, project_root: Option<&str>) -> String {
+    let path = project_root
+        .and_then(|root| uri.strip_prefix(root))
+        .unwrap_or(uri);
+    let path = path.strip_prefix("file://").unwrap_or(path);
+
+    path.trim_start_matches('/').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_symbols() {
+        let dump = r#"
+            {"id":1,"type":"vertex","label":"metaData","projectRoot":"file:///repo"}
+            {"id":2,"type":"vertex","label":"document","uri":"file:///repo/src/a.ts"}
+            {"id":3,"type":"vertex","label":"range","start":{"line":0,"character":9},"end":{"line":0,"character":12}}
+            {"id":4,"type":"vertex","label":"range","start":{"line":2,"character":0},"end":{"line":2,"character":3}}
+            {"id":5,"type":"vertex","label":"range","start":{"line":3,"character":0},"end":{"line":3,"character":5}}
+            {"id":6,"type":"vertex","label":"resultSet"}
+            {"id":7,"type":"vertex","label":"definitionResult"}
+            {"id":8,"type":"edge","label":"contains","outV":2,"inVs":[3,4,5]}
+            {"id":9,"type":"edge","label":"next","outV":3,"inV":6}
+            {"id":10,"type":"edge","label":"next","outV":4,"inV":6}
+            {"id":11,"type":"edge","label":"textDocument/definition","outV":6,"inV":7}
+            {"id":12,"type":"edge","label":"item","outV":7,"inVs":[3],"document":2}
+        "#;
+
+        let files = parse(dump).unwrap();
+        let entries = &files["src/a.ts"].entries;
+
+        // the range without results is left out
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].symbol, entries[1].symbol);
+        assert!(entries[0].definition);
+        assert!(!entries[1].definition);
+        assert_eq!(
+            entries[1].span,
+            Span::Range(
+                Position {
+                    line: 2,
+                    character: 0
+                },
+                Position {
+                    line: 2,
+                    character: 3
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_elements() {
+        assert!(parse("{\"id\":1}\nnot json").is_err());
+    }
+}
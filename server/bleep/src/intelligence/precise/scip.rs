@@ -0,0 +1,283 @@
+//! A reader for SCIP indexes.
+//!
+//! SCIP indexes are protobuf messages. Only the few fields needed for navigation are read, so
+//! instead of generating the whole schema this walks the wire format directly:
+//!
+//! ```text
+//! Index      { 2: repeated Document documents }
+//! Document   { 1: string relative_path, 2: repeated Occurrence occurrences,
+//!              6: PositionEncoding position_encoding }
+//! Occurrence { 1: repeated int32 range, 2: string symbol, 3: int32 symbol_roles }
+//! ```
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+
+use super::{Encoding, Entry, File, Position, Span};
+
+/// The `Definition` bit of `symbol_roles`.
+const DEFINITION_ROLE: u64 = 0x1;
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The fields of a message, in order.
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn field(&mut self) -> Result<Option<(u64, Value<'a>)>> {
+        loop {
+            if self.buf.is_empty() {
+                return Ok(None);
+            }
+
+            let key = self.varint()?;
+            let value = match key & 0x7 {
+                0 => Value::Varint(self.varint()?),
+                1 => {
+                    self.take(8)?;
+                    continue;
+                }
+                2 => {
+                    let len = self.varint()? as usize;
+                    Value::Bytes(self.take(len)?)
+                }
+                5 => {
+                    self.take(4)?;
+                    continue;
+                }
+                wire_type => bail!("unsupported wire type {wire_type}"),
+            };
+
+            return Ok(Some((key >> 3, value)));
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for (i, byte) in self.buf.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(value);
+            }
+        }
+
+        bail!("truncated varint")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.buf.len() {
+            bail!("truncated field");
+        }
+
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<BTreeMap<String, File>> {
+    let mut files = BTreeMap::<String, File>::new();
+    let mut index = Fields::new(data);
+
+    while let Some((number, value)) = index.field().context("invalid SCIP index")? {
+        if let (2, Value::Bytes(document)) = (number, value) {
+            let (path, file) = parse_document(document).context("invalid SCIP document")?;
+
+            // documents may be split across several messages
+            let entry = files.entry(path).or_default();
+            entry.encoding = file.encoding;
+            entry.entries.extend(file.entries);
+        }
+    }
+
+    Ok(files)
+}
+
+fn parse_document(data: &[u8]) -> Result<(String, File)> {
+    let mut path = String::new();
+    let mut occurrences = Vec::new();
+    let mut encoding = Encoding::default();
+    let mut fields = Fields::new(data);
+
+    while let Some((number, value)) = fields.field()? {
+        match (number, value) {
+            (1, Value::Bytes(bytes)) => path = String::from_utf8(bytes.to_vec())?,
+            (2, Value::Bytes(bytes)) => occurrences.push(bytes),
+            (6, Value::Varint(1)) => encoding = Encoding::Utf8,
+            (6, Value::Varint(3)) => encoding = Encoding::Utf32,
+            _ => {}
+        }
+    }
+
+    let mut entries = Vec::new();
+    for occurrence in occurrences {
+        if let Some(entry) = parse_occurrence(occurrence, &path)? {
+            entries.push(entry);
+        }
+    }
+
+    Ok((path, File { encoding, entries }))
+}
+
+fn parse_occurrence(data: &[u8], path: &str) -> Result<Option<Entry>> {
+    let mut range = Vec::new();
+    let mut symbol = String::new();
+    let mut roles = 0;
+    let mut fields = Fields::new(data);
+
+    while let Some((number, value)) = fields.field()? {
+        match (number, value) {
+            (1, Value::Varint(n)) => range.push(n as usize),
+            (1, Value::Bytes(packed)) => {
+                let mut packed = Fields::new(packed);
+                while !packed.buf.is_empty() {
+                    range.push(packed.varint()? as usize);
+                }
+            }
+            (2, Value::Bytes(bytes)) => symbol = String::from_utf8(bytes.to_vec())?,
+            (3, Value::Varint(n)) => roles = n,
+            _ => {}
+        }
+    }
+
+    // `[line, start, end]` when the range is on a single line
+    let (start, end) = match range[..] {
+        [line, start, end] => ((line, start), (line, end)),
+        [start_line, start, end_line, end] => ((start_line, start), (end_line, end)),
+        _ => return Ok(None),
+    };
+
+    if symbol.is_empty() {
+        return Ok(None);
+    }
+
+    // local symbols are only unique within a document
+    if symbol.starts_with("local ") {
+        symbol = format!("{path}#{symbol}");
+    }
+
+    Ok(Some(Entry {
+        symbol,
+        definition: roles & DEFINITION_ROLE != 0,
+        span: Span::Range(
+            Position {
+                line: start.0,
+                character: start.1,
+            },
+            Position {
+                line: end.0,
+                character: end.1,
+            },
+        ),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut n: u64, out: &mut Vec<u8>) {
+        while n >= 0x80 {
+            out.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    fn bytes(number: u64, data: &[u8], out: &mut Vec<u8>) {
+        varint(number << 3 | 2, out);
+        varint(data.len() as u64, out);
+        out.extend_from_slice(data);
+    }
+
+    fn int(number: u64, n: u64, out: &mut Vec<u8>) {
+        varint(number << 3, out);
+        varint(n, out);
+    }
+
+    fn occurrence(range: &[u64], symbol: &str, roles: u64) -> Vec<u8> {
+        let mut packed = vec![];
+        for &n in range {
+            varint(n, &mut packed);
+        }
+
+        let mut out = vec![];
+        bytes(1, &packed, &mut out);
+        bytes(2, symbol.as_bytes(), &mut out);
+        int(3, roles, &mut out);
+        out
+    }
+
+    #[test]
+    fn reads_documents() {
+        let mut document = vec![];
+        bytes(1, b"src/lib.rs", &mut document);
+        bytes(
+            2,
+            &occurrence(&[0, 3, 6], "rust . . foo().", 1),
+            &mut document,
+        );
+        bytes(2, &occurrence(&[4, 2, 5, 1], "local 0", 0), &mut document);
+        bytes(2, &occurrence(&[1, 2], "bad range", 0), &mut document);
+        int(6, 1, &mut document);
+
+        let mut index = vec![];
+        // metadata is skipped
+        bytes(1, b"\x0a\x00", &mut index);
+        bytes(2, &document, &mut index);
+
+        let files = parse(&index).unwrap();
+        let file = &files["src/lib.rs"];
+
+        assert_eq!(file.encoding, Encoding::Utf8);
+        assert_eq!(
+            file.entries,
+            vec![
+                Entry {
+                    symbol: "rust . . foo().".into(),
+                    definition: true,
+                    span: Span::Range(
+                        Position {
+                            line: 0,
+                            character: 3
+                        },
+                        Position {
+                            line: 0,
+                            character: 6
+                        }
+                    ),
+                },
+                Entry {
+                    symbol: "src/lib.rs#local 0".into(),
+                    definition: false,
+                    span: Span::Range(
+                        Position {
+                            line: 4,
+                            character: 2
+                        },
+                        Position {
+                            line: 5,
+                            character: 1
+                        }
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        assert!(parse(&[0x12, 0x05, 0x0a]).is_err());
+    }
+}
@@ -302,6 +302,17 @@ impl ScopeGraph {
         Box::new(iterator)
     }
 
+    /// Produce the ranges of all scopes, except for the root scope that spans the whole file
+    pub fn scope_ranges(&self) -> impl Iterator<Item = TextRange> + '_ {
+        self.graph
+            .node_indices()
+            .filter(|&idx| idx != self.root_idx)
+            .filter_map(|idx| match &self.graph[idx] {
+                NodeKind::Scope(s) => Some(s.range),
+                _ => None,
+            })
+    }
+
     /// Produce possible definitions for a reference
     pub fn definitions(
         &self,
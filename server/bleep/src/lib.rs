@@ -20,7 +20,7 @@ use git_version as _;
 #[cfg(all(feature = "debug", not(tokio_unstable)))]
 use console_subscriber as _;
 
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
 use state::PersistedState;
 use std::fs::canonicalize;
 use user::UserProfile;
@@ -35,7 +35,7 @@ use axum::extract::FromRef;
 use once_cell::sync::OnceCell;
 
 use sentry_tracing::{EventFilter, SentryLayer};
-use std::{path::Path, sync::Arc};
+use std::{path::Path, sync::Arc, time::Duration};
 use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::{
     filter::{LevelFilter, Targets},
@@ -51,9 +51,12 @@ mod collector;
 mod config;
 mod db;
 mod env;
+mod federation;
 mod llm_gateway;
+mod policy;
 mod remotes;
 mod repo;
+mod secrets;
 mod webserver;
 
 #[cfg(feature = "ee")]
@@ -70,9 +73,11 @@ pub mod state;
 pub mod symbol;
 pub mod text_range;
 pub mod user;
+pub mod workspace;
 
 pub use config::{default_parallelism, minimum_parallelism, Configuration};
 pub use env::Environment;
+pub use repo::RepoRef;
 
 const LOG_ENV_VAR: &str = "BLOOP_LOG";
 static LOGGER_INSTALLED: OnceCell<bool> = OnceCell::new();
@@ -110,6 +115,39 @@ pub struct Application {
     /// Store for user profiles
     user_profiles: PersistedState<scc::HashMap<String, UserProfile>>,
 
+    /// Other bloop instances that search and answer requests can fan out to
+    federated_peers: PersistedState<federation::Peers>,
+
+    /// Guardrails for questions and answers
+    policy: Arc<policy::Policy>,
+
+    /// Context window sizes of LLMs, adjusted as the provider reports them
+    context_windows: Arc<llm_gateway::models::ContextWindows>,
+
+    /// What a compatible LLM gateway serves and supports, once it was discovered
+    llm_discovery: Arc<OnceCell<Arc<llm_gateway::discovery::Discovery>>>,
+
+    /// Prices of LLMs, to estimate the cost of answers
+    prices: Arc<llm_gateway::models::Prices>,
+
+    /// Canary rollout of the agent's prompt template
+    prompt_rollout: Arc<agent::rollout::PromptRollout>,
+
+    /// Prompt templates that replace the built-in prompts
+    prompt_templates: Arc<agent::templates::PromptTemplates>,
+
+    /// WASM modules that filter retrieved code
+    snippet_filters: Arc<agent::snippet_filters::SnippetFilters>,
+
+    /// Recent answers to the questions that start conversations
+    answer_cache: Arc<agent::answer_cache::AnswerCache>,
+
+    /// How the questions that start conversations were classified
+    classifications: Arc<agent::classification::ClassificationCache>,
+
+    /// Pages of GitHub repository lists, for conditional requests
+    github_lists: Arc<remotes::github::ListCache>,
+
     /// SQL database for persistent storage
     pub sql: SqlDb,
 
@@ -140,7 +178,9 @@ impl Application {
         // Initialise Semantic index if `qdrant_url` set in config
         let semantic = match config.qdrant_url {
             Some(ref url) => {
-                match Semantic::initialize(&config.model_dir, url, Arc::clone(&config)).await {
+                match Semantic::initialize(&config.model_dir, url, Arc::clone(&config), &sqlite)
+                    .await
+                {
                     Ok(semantic) => Some(semantic),
                     Err(e) => {
                         bail!("Qdrant initialization failed: {}", e);
@@ -148,8 +188,22 @@ impl Application {
                 }
             }
             None => {
-                warn!("Semantic search disabled because `qdrant_url` is not provided. Starting without.");
-                None
+                match Semantic::initialize_embedded(&config.model_dir, Arc::clone(&config), &sqlite)
+                    .await
+                {
+                    Ok(semantic) => {
+                        info!("`qdrant_url` is not provided, using the embedded vector store");
+                        Some(semantic)
+                    }
+                    Err(err) => {
+                        warn!(
+                            ?err,
+                            "Semantic search disabled because `qdrant_url` is not provided, \
+                             and the embedded vector store failed to start. Starting without."
+                        );
+                        None
+                    }
+                }
             }
         };
 
@@ -169,6 +223,7 @@ impl Application {
         };
 
         let repo_pool = config.source.initialize_pool()?;
+        let prompt_rollout = agent::rollout::PromptRollout::load(&config, sqlite.clone()).await?;
 
         Ok(Self {
             indexes: Indexes::new(
@@ -185,6 +240,21 @@ impl Application {
                 .source
                 .load_state_or("credentials", remotes::Backends::default())?,
             user_profiles: config.source.load_or_default("user_profiles")?,
+            federated_peers: config.source.load_or_default("federated_peers")?,
+            policy: policy::Policy::load(config.policy_file.as_deref())?.into(),
+            context_windows: llm_gateway::models::ContextWindows::new(&config.llm_context_sizes)?
+                .into(),
+            llm_discovery: Default::default(),
+            prices: llm_gateway::models::Prices::new(&config.llm_prices)?.into(),
+            prompt_rollout: prompt_rollout.into(),
+            prompt_templates: agent::templates::PromptTemplates::load(&config)?.into(),
+            snippet_filters: agent::snippet_filters::SnippetFilters::load(&config)?.into(),
+            answer_cache: agent::answer_cache::AnswerCache::new(Duration::from_secs(
+                config.answer_cache_ttl_secs,
+            ))
+            .into(),
+            classifications: Default::default(),
+            github_lists: Default::default(),
             sql: sqlite,
             repo_pool,
             analytics,
@@ -259,10 +329,17 @@ impl Application {
         if self.config.index_only {
             joins.spawn(self.write_index().startup_scan());
         } else {
+            if self.config.llm_backend == llm_gateway::api::Backend::Compatible {
+                tokio::spawn(periodic::discover_llm_gateway(self.clone()));
+            }
+
             if !self.config.disable_background {
                 tokio::spawn(periodic::sync_github_status(self.clone()));
+                tokio::spawn(periodic::sync_gitlab_status(self.clone()));
                 tokio::spawn(periodic::check_repo_updates(self.clone()));
                 tokio::spawn(periodic::log_and_branch_rotate(self.clone()));
+                tokio::spawn(periodic::purge_expired(self.clone()));
+                tokio::spawn(background::reindex::resume(self.clone()));
 
                 if !self.env.is_cloud_instance() {
                     tokio::spawn(periodic::clear_disk_logs(self.clone()));
@@ -344,6 +421,77 @@ impl Application {
             None
         })
     }
+
+    /// A client for the answer-api, set up for the configured LLM provider.
+    ///
+    /// Every client has its own meter, which counts the tokens of its requests and those of its
+    /// clones, to be recorded against the quota of the user they were made for.
+    fn llm_gateway_client(&self) -> llm_gateway::Client {
+        let backend = self.config.llm_backend;
+        let base_url = match backend {
+            llm_gateway::api::Backend::Gateway => Some(self.config.answer_api_url.as_str()),
+            _ => self.config.llm_api_url.as_deref().or(backend.default_url()),
+        };
+
+        llm_gateway::Client::new(base_url.unwrap_or_default())
+            .provider(self.config.llm_provider)
+            .backend(
+                backend,
+                self.config
+                    .llm_api_key
+                    .as_ref()
+                    .map(|key| key.expose_secret().clone()),
+            )
+            .stop_sequences(self.config.llm_stop_sequences.clone())
+            .discovery(self.llm_discovery.get().cloned())
+            .block_secrets(self.config.block_secrets)
+            .meter(Default::default())
+    }
+
+    /// Whether the code of `repo_ref` must never be sent to an LLM. Unknown repositories are not.
+    async fn is_retrieval_only(&self, repo_ref: &RepoRef) -> bool {
+        self.repo_pool
+            .read_async(repo_ref, |_, repo| repo.retrieval_only)
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Whether code under `license` is kept from the LLM, as configured with `excluded_licenses`.
+    fn is_license_excluded(&self, license: Option<&str>) -> bool {
+        license.is_some_and(|l| repo::license::is_any_of(l, &self.config.excluded_licenses))
+    }
+
+    /// Record a rule of the policy that blocked or rewrote text in the audit log.
+    ///
+    /// Failing to write the audit log does not fail the request, as the decision has already been
+    /// enforced.
+    async fn audit_policy(&self, entry: &db::AuditEntry<'_>) {
+        if let Err(err) = db::PolicyAudit::new(&self.sql).insert(entry).await {
+            warn!(?err, "failed to write policy audit entry");
+        }
+    }
+}
+
+#[cfg(test)]
+impl Application {
+    /// An application that keeps its state in `dir`, with nothing indexed.
+    async fn for_tests(dir: &Path) -> Self {
+        use clap::Parser;
+        use std::ffi::OsStr;
+
+        let config = Configuration::try_parse_from([
+            OsStr::new("bleep"),
+            OsStr::new("--index-dir"),
+            dir.as_os_str(),
+            OsStr::new("--model-dir"),
+            dir.as_os_str(),
+        ])
+        .unwrap();
+
+        Self::initialize(Environment::insecure_local(), config, None, None)
+            .await
+            .unwrap()
+    }
 }
 
 impl FromRef<Application> for axum_extra::extract::cookie::Key {
@@ -0,0 +1,250 @@
+//! Discovery of what a compatible gateway serves and supports.
+//!
+//! Gateways with an OpenAI-compatible API, like vLLM, LiteLLM or Ollama, serve models of their
+//! own under names of their own, and differ in which parts of the API they implement. At startup,
+//! the models are listed from `/v1/models`, and a one-token completion is requested with
+//! streaming, and another with logprobs, to find out whether the gateway supports them.
+//!
+//! Requests for a model that the gateway doesn't serve are sent to the first model it lists, so
+//! that the built-in model names work with any gateway.
+
+use anyhow::{bail, Result};
+use reqwest::header::CONTENT_TYPE;
+use tracing::warn;
+
+use super::{api, Client, DEFAULT_MODEL};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Model {
+    pub id: String,
+    /// The context window of the model, where the gateway reports it.
+    pub context_size: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    /// The models served by the gateway, in the order it lists them. Empty if the gateway can't
+    /// list them.
+    pub models: Vec<Model>,
+    pub streaming: bool,
+    pub logprobs: bool,
+}
+
+impl Discovery {
+    /// The model that requests for `model` are sent to: `model` itself if the gateway serves it,
+    /// or else the first model that it lists.
+    pub fn resolve<'a>(&'a self, model: &'a str) -> &'a str {
+        match self.models.first() {
+            Some(first) if !self.models.iter().any(|m| m.id == model) => &first.id,
+            _ => model,
+        }
+    }
+
+    /// The context window of the model that requests for `model` are sent to, if it is reported.
+    pub fn context_size(&self, model: &str) -> Option<usize> {
+        let model = self.resolve(model);
+        self.models
+            .iter()
+            .find(|m| m.id == model)
+            .and_then(|m| m.context_size)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+/// An entry of `/v1/models`. Only the `id` is standard. The context window is reported as
+/// `context_length` by OpenRouter and LiteLLM, as `max_model_len` by vLLM, and in the `meta` of
+/// the llama.cpp server.
+#[derive(serde::Deserialize)]
+struct ModelEntry {
+    id: String,
+    #[serde(alias = "max_model_len", alias = "context_window")]
+    context_length: Option<usize>,
+    meta: Option<ModelMeta>,
+}
+
+#[derive(serde::Deserialize)]
+struct ModelMeta {
+    n_ctx_train: Option<usize>,
+}
+
+impl From<ModelEntry> for Model {
+    fn from(entry: ModelEntry) -> Self {
+        Self {
+            context_size: entry
+                .context_length
+                .or_else(|| entry.meta.and_then(|meta| meta.n_ctx_train))
+                .filter(|&size| size > 0),
+            id: entry.id,
+        }
+    }
+}
+
+/// Discover the models and capabilities of the compatible gateway that `client` sends requests
+/// to. Fails if the gateway can't be reached, so that discovery can be retried.
+pub async fn discover(client: &Client) -> Result<Discovery> {
+    if client.base_url.is_empty() {
+        bail!("`llm_api_url` must be set for the openai-compatible LLM backend");
+    }
+
+    let mut discovery = Discovery {
+        models: list_models(client).await?,
+        streaming: false,
+        logprobs: false,
+    };
+
+    let model = discovery
+        .resolve(client.model.as_deref().unwrap_or(DEFAULT_MODEL))
+        .to_owned();
+
+    discovery.streaming = probe(client, &model, true, None).await?;
+    discovery.logprobs = probe(client, &model, false, Some(1)).await?;
+
+    Ok(discovery)
+}
+
+async fn list_models(client: &Client) -> Result<Vec<Model>> {
+    let response = authorized(client, client.http.get(url(client, "models")))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        warn!(status = %response.status(), "LLM gateway can't list its models");
+        return Ok(vec![]);
+    }
+
+    Ok(parse_models(&response.text().await?)?)
+}
+
+fn parse_models(body: &str) -> serde_json::Result<Vec<Model>> {
+    Ok(serde_json::from_str::<ModelList>(body)?
+        .data
+        .into_iter()
+        .map(Model::from)
+        .collect())
+}
+
+/// Request a one-token completion of `model`, and return whether it came back streamed, or with
+/// logprobs when `top_logprobs` is set.
+async fn probe(
+    client: &Client,
+    model: &str,
+    stream: bool,
+    top_logprobs: Option<u8>,
+) -> Result<bool> {
+    let messages = [api::Message::user("Hi")];
+    let body = api::ChatCompletionRequest {
+        model: Some(model),
+        messages: &messages,
+        functions: None,
+        max_tokens: Some(1),
+        temperature: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        stop: &[],
+        stream,
+        logprobs: top_logprobs.is_some(),
+        top_logprobs,
+    };
+
+    let request = client
+        .http
+        .post(url(client, "chat/completions"))
+        .json(&body);
+    let response = authorized(client, request).send().await?;
+
+    // Gateways that don't implement a parameter either ignore it or reject the request.
+    if !response.status().is_success() {
+        return Ok(false);
+    }
+
+    if stream {
+        return Ok(response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with("text/event-stream")));
+    }
+
+    Ok(response
+        .json::<api::ChatCompletion>()
+        .await
+        .ok()
+        .and_then(|completion| completion.choices.into_iter().next())
+        .and_then(|choice| choice.logprobs)
+        .map_or(false, |logprobs| !logprobs.content.is_empty()))
+}
+
+fn url(client: &Client, path: &str) -> String {
+    format!("{}/v1/{path}", client.base_url.trim_end_matches('/'))
+}
+
+fn authorized(client: &Client, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match &client.api_key {
+        Some(key) => builder.bearer_auth(key),
+        None => builder,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_model_lists() {
+        let vllm = r#"{"object":"list","data":[
+            {"id":"meta-llama/Llama-2-13b-chat-hf","object":"model","max_model_len":4096}
+        ]}"#;
+        let ollama = r#"{"object":"list","data":[
+            {"id":"codellama","object":"model","owned_by":"library"},
+            {"id":"mistral","object":"model","owned_by":"library"}
+        ]}"#;
+        let llama_cpp = r#"{"object":"list","data":[
+            {"id":"models/7B/ggml-model.gguf","object":"model","meta":{"n_ctx_train":16384}}
+        ]}"#;
+
+        let model = |id: &str, context_size| Model {
+            id: id.to_owned(),
+            context_size,
+        };
+
+        assert_eq!(
+            parse_models(vllm).unwrap(),
+            [model("meta-llama/Llama-2-13b-chat-hf", Some(4096))]
+        );
+        assert_eq!(
+            parse_models(ollama).unwrap(),
+            [model("codellama", None), model("mistral", None)]
+        );
+        assert_eq!(
+            parse_models(llama_cpp).unwrap(),
+            [model("models/7B/ggml-model.gguf", Some(16384))]
+        );
+    }
+
+    #[test]
+    fn resolves_unserved_models_to_the_first() {
+        let discovery = Discovery {
+            models: parse_models(
+                r#"{"data":[{"id":"codellama","context_length":16384},{"id":"mistral"}]}"#,
+            )
+            .unwrap(),
+            streaming: true,
+            logprobs: false,
+        };
+
+        assert_eq!(discovery.resolve("mistral"), "mistral");
+        assert_eq!(discovery.resolve("gpt-4-0613"), "codellama");
+        assert_eq!(discovery.context_size("gpt-4-0613"), Some(16384));
+        assert_eq!(discovery.context_size("mistral"), None);
+
+        let unlisted = Discovery {
+            models: vec![],
+            ..discovery
+        };
+        assert_eq!(unlisted.resolve("gpt-4-0613"), "gpt-4-0613");
+    }
+}
@@ -0,0 +1,267 @@
+//! Context window sizes and prices of the models we prompt.
+//!
+//! The size of a model is, in order of precedence: the size reported by the provider in a context
+//! length error, the size configured in `llm_context_sizes`, the size listed by a compatible
+//! gateway for the model it serves in its place, or the size known to `tiktoken`.
+//!
+//! The price of a model is the one configured in `llm_prices`, or the built-in list price.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use lazy_regex::regex;
+use tracing::warn;
+
+use once_cell::sync::OnceCell;
+
+use super::{api, discovery::Discovery, usage::Usage};
+
+/// List prices in USD per 1000 prompt and completion tokens.
+const BUILTIN_PRICES: &[(&str, Price)] = &[
+    ("gpt-4", Price::new(0.03, 0.06)),
+    ("gpt-4-0613", Price::new(0.03, 0.06)),
+    ("gpt-4-32k-0613", Price::new(0.06, 0.12)),
+    ("gpt-3.5-turbo", Price::new(0.0015, 0.002)),
+    ("gpt-3.5-turbo-0613", Price::new(0.0015, 0.002)),
+    ("gpt-3.5-turbo-16k-0613", Price::new(0.003, 0.004)),
+];
+
+pub struct ContextWindows {
+    configured: HashMap<String, usize>,
+    learned: scc::HashMap<String, usize>,
+    discovered: OnceCell<Arc<Discovery>>,
+}
+
+impl ContextWindows {
+    /// Create a registry with the configured sizes, each formatted as `model=tokens`.
+    pub fn new(configured: &[String]) -> Result<Self> {
+        let configured = configured
+            .iter()
+            .map(|entry| {
+                let (model, tokens) = entry.split_once('=').with_context(|| {
+                    format!("invalid context size `{entry}`, expected `model=tokens`")
+                })?;
+                let tokens = tokens
+                    .trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("invalid context size `{entry}`"))?;
+
+                if tokens == 0 {
+                    bail!("invalid context size `{entry}`");
+                }
+
+                Ok((model.trim().to_owned(), tokens))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            configured,
+            learned: scc::HashMap::default(),
+            discovered: OnceCell::new(),
+        })
+    }
+
+    /// The number of tokens `model` accepts, for the prompt and completion combined.
+    pub fn size(&self, model: &str) -> usize {
+        self.learned
+            .read(model, |_, size| *size)
+            .or_else(|| self.configured.get(model).copied())
+            .or_else(|| self.discovered.get()?.context_size(model))
+            .unwrap_or_else(|| tiktoken_rs::model::get_context_size(model))
+    }
+
+    /// Use the sizes that a compatible gateway lists for its models. Only the first discovery is
+    /// kept.
+    pub fn discovered(&self, discovery: Arc<Discovery>) {
+        _ = self.discovered.set(discovery);
+    }
+
+    /// Learn the size of `model` from an error returned by the provider.
+    ///
+    /// Returns `true` if this was a context length error that reported a different size than we
+    /// assumed, in which case the request can be retried with a prompt that fits.
+    pub fn learn(&self, model: &str, error: &anyhow::Error) -> bool {
+        let Some(size) = reported_size(error) else {
+            return false;
+        };
+
+        let assumed = self.size(model);
+        if size == assumed {
+            return false;
+        }
+
+        warn!(model, assumed, size, "adjusting context window size");
+        self.learned.upsert(model.to_owned(), size);
+        true
+    }
+}
+
+/// The price of a model, in USD per 1000 tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    prompt: f64,
+    completion: f64,
+}
+
+impl Price {
+    const fn new(prompt: f64, completion: f64) -> Self {
+        Self { prompt, completion }
+    }
+
+    fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1000.0
+    }
+}
+
+pub struct Prices {
+    configured: HashMap<String, Price>,
+}
+
+impl Prices {
+    /// Create a registry with the configured prices, each formatted as `model=prompt/completion`.
+    pub fn new(configured: &[String]) -> Result<Self> {
+        let configured = configured
+            .iter()
+            .map(|entry| {
+                let parsed = entry.split_once('=').and_then(|(model, price)| {
+                    let (prompt, completion) = price.split_once('/')?;
+                    let price =
+                        Price::new(prompt.trim().parse().ok()?, completion.trim().parse().ok()?);
+
+                    Some((model.trim().to_owned(), price))
+                });
+
+                match parsed {
+                    Some((_, price)) if price.prompt < 0.0 || price.completion < 0.0 => {
+                        bail!("invalid price `{entry}`")
+                    }
+                    Some(parsed) => Ok(parsed),
+                    None => bail!("invalid price `{entry}`, expected `model=prompt/completion`"),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { configured })
+    }
+
+    pub fn get(&self, model: &str) -> Option<Price> {
+        self.configured.get(model).copied().or_else(|| {
+            BUILTIN_PRICES
+                .iter()
+                .find(|(name, _)| *name == model)
+                .map(|(_, price)| *price)
+        })
+    }
+
+    /// The estimated cost of the usage of `model`, in USD. Models without a price are free.
+    pub fn cost(&self, model: &str, usage: &Usage) -> f64 {
+        self.get(model).map_or(0.0, |price| price.cost(usage))
+    }
+
+    /// The estimated cost of the usage of each model, in USD.
+    pub fn total_cost(&self, usage: &BTreeMap<String, Usage>) -> f64 {
+        usage
+            .iter()
+            .map(|(model, usage)| self.cost(model, usage))
+            .sum()
+    }
+}
+
+/// The context size reported in a context length error, if this is one.
+fn reported_size(error: &anyhow::Error) -> Option<usize> {
+    if let Some(api::Error::ContextLengthExceeded { max_tokens }) = error.downcast_ref() {
+        return Some(*max_tokens);
+    }
+
+    // Errors that the gateway passes through from the provider only have a message, like
+    // "This model's maximum context length is 8192 tokens. However, your messages resulted in
+    // 9012 tokens."
+    regex!(r"maximum context length is (\d+) tokens")
+        .captures(&format!("{error:#}"))?
+        .get(1)?
+        .as_str()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn configured_sizes() {
+        let windows = ContextWindows::new(&["gpt-4-0613 = 32768".to_owned()]).unwrap();
+
+        assert_eq!(windows.size("gpt-4-0613"), 32768);
+        assert_eq!(windows.size("gpt-3.5-turbo-16k-0613"), 16384);
+
+        assert!(ContextWindows::new(&["gpt-4-0613".to_owned()]).is_err());
+        assert!(ContextWindows::new(&["gpt-4-0613=lots".to_owned()]).is_err());
+        assert!(ContextWindows::new(&["gpt-4-0613=0".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn discovered_sizes() {
+        let windows = ContextWindows::new(&["gpt-4-0613=32768".to_owned()]).unwrap();
+        windows.discovered(Arc::new(Discovery {
+            models: vec![crate::llm_gateway::discovery::Model {
+                id: "codellama".to_owned(),
+                context_size: Some(16384),
+            }],
+            streaming: true,
+            logprobs: false,
+        }));
+
+        assert_eq!(windows.size("gpt-4-0613"), 32768);
+        assert_eq!(windows.size("gpt-3.5-turbo-0613"), 16384);
+    }
+
+    #[test]
+    fn learns_from_errors() {
+        let windows = ContextWindows::new(&["gpt-4-0613=32768".to_owned()]).unwrap();
+
+        assert!(!windows.learn("gpt-4-0613", &anyhow!("event source failed to open")));
+        assert_eq!(windows.size("gpt-4-0613"), 32768);
+
+        let error = anyhow!(
+            "This model's maximum context length is 8192 tokens. However, your messages \
+             resulted in 9012 tokens."
+        );
+        assert!(windows.learn("gpt-4-0613", &error));
+        assert_eq!(windows.size("gpt-4-0613"), 8192);
+        assert!(!windows.learn("gpt-4-0613", &error));
+
+        let error = anyhow::Error::new(api::Error::ContextLengthExceeded { max_tokens: 4096 })
+            .context("failed to fold LLM function call output");
+        assert!(windows.learn("gpt-4-0613", &error));
+        assert_eq!(windows.size("gpt-4-0613"), 4096);
+    }
+
+    #[test]
+    fn estimates_costs() {
+        let prices = Prices::new(&["gpt-4-0613 = 0.01/0.02".to_owned()]).unwrap();
+        let usage = |prompt_tokens, completion_tokens| Usage {
+            prompt_tokens,
+            completion_tokens,
+            calls: 1,
+        };
+
+        let usage = BTreeMap::from([
+            ("gpt-4-0613".to_owned(), usage(1000, 500)),
+            ("gpt-3.5-turbo-0613".to_owned(), usage(2000, 1000)),
+            ("some-unknown-model".to_owned(), usage(1000, 1000)),
+        ]);
+        assert!((prices.total_cost(&usage) - (0.02 + 0.005)).abs() < 1e-9);
+
+        assert!(Prices::new(&["gpt-4-0613=0.01".to_owned()]).is_err());
+        assert!(Prices::new(&["gpt-4-0613=free/0.02".to_owned()]).is_err());
+        assert!(Prices::new(&["gpt-4-0613=-0.01/0.02".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Token usage of LLM requests.
+//!
+//! The gateway streams responses without reporting usage, so tokens are counted locally: the
+//! prompt when a request is sent, and the completion as it streams in. Completions that end early,
+//! because the caller stopped reading, count the tokens that were received.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use super::api;
+
+/// The tokenizer used for models that `tiktoken` does not know.
+const FALLBACK_MODEL: &str = "gpt-4";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// The number of requests the tokens were spent on.
+    #[serde(default)]
+    pub calls: usize,
+}
+
+impl Usage {
+    pub fn total(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.calls += other.calls;
+    }
+}
+
+/// The usage of every model for a single query, and its estimated cost.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Spend {
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// In USD, for the models with a known price.
+    pub cost: f64,
+}
+
+/// Accumulates the usage of every request made through the clients that share it, by model.
+#[derive(Debug, Default)]
+pub struct Meter {
+    models: Mutex<HashMap<String, Usage>>,
+}
+
+impl Meter {
+    pub fn record(&self, model: &str, usage: Usage) {
+        *self
+            .models
+            .lock()
+            .unwrap()
+            .entry(model.to_owned())
+            .or_default() += usage;
+    }
+
+    /// The usage of each model.
+    pub fn totals(&self) -> BTreeMap<String, Usage> {
+        self.models
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(model, usage)| (model.clone(), *usage))
+            .collect()
+    }
+}
+
+/// Records the usage of a single request in a `Meter` when it is dropped, which happens once its
+/// response stream is finished or abandoned.
+pub(super) struct Recorder {
+    meter: Arc<Meter>,
+    model: String,
+    prompt_tokens: usize,
+    completion: String,
+}
+
+impl Recorder {
+    pub(super) fn new(meter: Arc<Meter>, model: &str, messages: &[api::Message]) -> Self {
+        Self {
+            meter,
+            model: model.to_owned(),
+            prompt_tokens: prompt_tokens(model, messages),
+            completion: String::new(),
+        }
+    }
+
+    pub(super) fn push(&mut self, fragment: &str) {
+        self.completion += fragment;
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.meter.record(
+            &self.model,
+            Usage {
+                prompt_tokens: self.prompt_tokens,
+                completion_tokens: completion_tokens(&self.model, &self.completion),
+                calls: 1,
+            },
+        );
+    }
+}
+
+fn prompt_tokens(model: &str, messages: &[api::Message]) -> usize {
+    let messages = messages.iter().map(Into::into).collect::<Vec<_>>();
+    tiktoken_rs::num_tokens_from_messages(model, &messages)
+        .or_else(|_| tiktoken_rs::num_tokens_from_messages(FALLBACK_MODEL, &messages))
+        .unwrap_or_default()
+}
+
+fn completion_tokens(model: &str, completion: &str) -> usize {
+    if completion.is_empty() {
+        return 0;
+    }
+
+    tiktoken_rs::get_bpe_from_model(model)
+        .or_else(|_| tiktoken_rs::get_bpe_from_model(FALLBACK_MODEL))
+        .map(|bpe| bpe.encode_with_special_tokens(completion).len())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_on_drop() {
+        let meter = Arc::new(Meter::default());
+
+        let mut recorder = Recorder::new(
+            meter.clone(),
+            "gpt-4-0613",
+            &[api::Message::user("hello world")],
+        );
+        recorder.push("hello ");
+        recorder.push("world");
+        assert!(meter.totals().is_empty());
+
+        drop(recorder);
+        drop(Recorder::new(
+            meter.clone(),
+            "some-unknown-model",
+            &[api::Message::user("hello world")],
+        ));
+
+        let totals = meter.totals();
+        let known = totals["gpt-4-0613"];
+        let unknown = totals["some-unknown-model"];
+        assert_eq!(known.completion_tokens, 2);
+        assert!(known.prompt_tokens > 2);
+        assert_eq!(unknown.prompt_tokens, known.prompt_tokens);
+        assert_eq!(unknown.completion_tokens, 0);
+        assert_eq!((known.calls, unknown.calls), (1, 1));
+    }
+}
@@ -1,5 +1,9 @@
+mod llm;
 mod logrotate;
 mod remotes;
+mod retention;
 
+pub(crate) use llm::*;
 pub(crate) use logrotate::*;
 pub(crate) use remotes::*;
+pub(crate) use retention::*;
@@ -0,0 +1,36 @@
+//! Discovery of a compatible LLM gateway, which may come up after bloop does.
+
+use std::{sync::Arc, time::Duration};
+
+use tracing::{info, warn};
+
+use crate::{llm_gateway::discovery, Application};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Discover the models and capabilities of the compatible gateway, retrying until it is reached.
+///
+/// Until then, requests are sent with streaming and without logprobs, to the models they name.
+pub(crate) async fn discover_llm_gateway(app: Application) {
+    let client = app.llm_gateway_client();
+
+    let discovery = loop {
+        match discovery::discover(&client).await {
+            Ok(discovery) => break Arc::new(discovery),
+            Err(err) => {
+                warn!(?err, "failed to discover LLM gateway, retrying");
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    };
+
+    info!(
+        models = ?discovery.models,
+        streaming = discovery.streaming,
+        logprobs = discovery.logprobs,
+        "discovered LLM gateway"
+    );
+
+    app.context_windows.discovered(Arc::clone(&discovery));
+    _ = app.llm_discovery.set(discovery);
+}
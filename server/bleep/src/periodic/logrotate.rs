@@ -34,7 +34,8 @@ pub(crate) async fn log_and_branch_rotate(app: crate::Application) {
     }
 }
 
-/// Remove log files older than 7 days.
+/// Remove log files older than `log_retention_days`, or only report them with
+/// `retention_dry_run`.
 ///
 /// Runs on startup and every hour thereafter
 pub(crate) async fn clear_disk_logs(app: crate::Application) {
@@ -45,7 +46,7 @@ pub(crate) async fn clear_disk_logs(app: crate::Application) {
         info!("removing old logs");
 
         let today = Utc::now().date_naive();
-        let allowed_files = (0..7)
+        let allowed_files = (0..app.config.log_retention_days as i64)
             .map(|offset| today - Duration::days(offset))
             .map(|d| format!("bloop.log.{}", d.format("%Y-%m-%d")))
             .collect::<HashSet<_>>();
@@ -58,7 +59,9 @@ pub(crate) async fn clear_disk_logs(app: crate::Application) {
                     .map(|f| allowed_files.contains(f))
                     .unwrap_or_default()
                 {
-                    if tokio::fs::remove_file(entry.path()).await.is_ok() {
+                    if app.config.retention_dry_run {
+                        info!("would remove old log file {:?}", entry.file_name());
+                    } else if tokio::fs::remove_file(entry.path()).await.is_ok() {
                         info!("removed old log file {:?}", entry.file_name())
                     } else {
                         info!(
@@ -129,6 +132,11 @@ fn record_branch<'a>(
     r: Cow<'a, str>,
     b: Cow<'a, str>,
 ) {
+    // Revisions are opted into separately, and are not branches that can be filtered for.
+    if parser::is_revision(&b) {
+        return;
+    }
+
     map.entry(r.to_string())
         .or_insert_with(HashSet::default)
         .get_mut()
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     ops::Not,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
@@ -21,7 +22,7 @@ use crate::{
     remotes::{
         self,
         github::{self, Auth},
-        CognitoGithubTokenBundle,
+        gitlab, CognitoGithubTokenBundle, CredentialStatus,
     },
     repo::{Backend, RepoRef, SyncStatus},
     Application,
@@ -39,6 +40,10 @@ pub(crate) async fn sync_github_status(app: Application) {
     const POLL_PERIOD: Duration = POLL_INTERVAL_MINUTE[1];
     const LIVENESS: Duration = Duration::from_secs(1);
 
+    // Number of consecutive authentication failures after which we
+    // consider the credentials revoked.
+    const MAX_AUTH_FAILURES: usize = 3;
+
     let timeout = || async {
         sleep(LIVENESS).await;
     };
@@ -71,9 +76,11 @@ pub(crate) async fn sync_github_status(app: Application) {
 
     // In case this is a GitHub App installation, we get the
     // credentials from CLI/config
-    update_credentials(&app).await;
+    let mut refresh_failures = 0;
+    update_credentials(&app, &mut refresh_failures).await;
 
     let mut last_poll = UNIX_EPOCH;
+    let mut auth_failures = 0;
     loop {
         let Some(github) = app.credentials.github() else {
             timeout().await;
@@ -81,12 +88,66 @@ pub(crate) async fn sync_github_status(app: Application) {
 	};
         debug!("credentials exist");
 
-        let Ok(repos) = github.current_repo_list().await else {
-            timeout().await;
-            continue;
-	};
+        let repos = match github.current_repo_list(&app.github_lists).await {
+            Ok(repos) => {
+                auth_failures = 0;
+                app.credentials.resolve(|status| {
+                    matches!(
+                        status,
+                        CredentialStatus::ReauthenticationRequired { .. }
+                            | CredentialStatus::InstallationSuspended { .. }
+                    )
+                });
+
+                let (hits, misses) = app.github_lists.stats();
+                debug!(hits, misses, "listed github repositories");
+                repos
+            }
+            Err(err) if err.is_auth_failure() => {
+                auth_failures += 1;
+                warn!(?err, auth_failures, "github rejected credentials");
+
+                if err.is_suspended() {
+                    app.credentials
+                        .notify(CredentialStatus::InstallationSuspended {
+                            backend: Backend::Github,
+                        });
+                }
+
+                if auth_failures >= MAX_AUTH_FAILURES {
+                    error!("github credentials are invalid; re-authentication required");
+                    auth_failures = 0;
+
+                    if app.credentials.invalidate(Backend::Github).is_some() {
+                        if let Err(err) = app.credentials.store() {
+                            error!(?err, "failed to save credentials");
+                        }
+                    }
+
+                    // GitHub App installations can recover by
+                    // requesting a fresh installation token
+                    update_credentials(&app, &mut refresh_failures).await;
+                }
+
+                timeout().await;
+                continue;
+            }
+            Err(err) => {
+                debug!(?err, "failed to list github repositories");
+                timeout().await;
+                continue;
+            }
+        };
         debug!("repo list updated");
 
+        update_repo_metadata(&app, &repos);
+
+        for (from, to) in renamed_repos(&app, &repos) {
+            if let Err(err) = app.write_index().rename(from.clone(), to.clone()).await {
+                error!(?err, %from, %to, "failed to rename repository");
+            }
+        }
+
         let updated = app.credentials.github_updated().unwrap();
         let new = github.update_repositories(repos);
 
@@ -94,7 +155,7 @@ pub(crate) async fn sync_github_status(app: Application) {
         app.credentials.set_github(new);
 
         // then retrieve username & other maintenance
-        update_credentials(&app).await;
+        update_credentials(&app, &mut refresh_failures).await;
 
         // swallow the event that's generated from this update
         _ = updated.recv_async().await;
@@ -102,26 +163,141 @@ pub(crate) async fn sync_github_status(app: Application) {
     }
 }
 
+/// Refresh the descriptions and topics of the GitHub repositories in the pool.
+fn update_repo_metadata(app: &Application, repos: &[octocrab::models::Repository]) {
+    let mut updated = false;
+
+    for origin in repos {
+        let Some(reporef) = origin
+            .full_name
+            .as_deref()
+            .and_then(|name| RepoRef::new(Backend::Github, name).ok())
+        else {
+            continue;
+        };
+
+        let description = origin.description.clone().filter(|d| !d.trim().is_empty());
+        let topics = github::topics(origin);
+        let github_id = Some(origin.id.0);
+
+        app.repo_pool.update(&reporef, |_, repo| {
+            if repo.description != description
+                || repo.topics != topics
+                || repo.github_id != github_id
+            {
+                repo.description = description;
+                repo.topics = topics;
+                repo.github_id = github_id;
+                updated = true;
+            }
+        });
+    }
+
+    if updated {
+        if let Err(err) = app.config.source.save_pool(app.repo_pool.clone()) {
+            error!(?err, "failed to save repository metadata");
+        }
+    }
+}
+
+/// GitHub repositories in the pool that were renamed or transferred, with their new names.
+///
+/// GitHub keeps the id of a repository when it is renamed, so a repository whose id is listed
+/// under another name was renamed.
+fn renamed_repos(
+    app: &Application,
+    repos: &[octocrab::models::Repository],
+) -> Vec<(RepoRef, RepoRef)> {
+    let names = repos
+        .iter()
+        .filter_map(|origin| {
+            let reporef = RepoRef::new(Backend::Github, origin.full_name.as_deref()?).ok()?;
+            Some((origin.id.0, reporef))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut renamed = vec![];
+    app.repo_pool.for_each(|reporef, repo| {
+        let Some(name) = repo.github_id.and_then(|id| names.get(&id)) else {
+            return;
+        };
+
+        if name != reporef {
+            renamed.push((reporef.clone(), name.clone()));
+        }
+    });
+
+    renamed
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct RefreshedAccessToken {
     access_token: String,
 }
 
-async fn update_credentials(app: &Application) {
+/// Number of consecutive failures to renew the credentials after which
+/// users are notified.
+const MAX_REFRESH_FAILURES: usize = 3;
+
+/// Notify users once renewing the credentials keeps failing, or if the
+/// credentials are about to expire.
+fn refresh_failed(app: &Application, refresh_failures: &mut usize) {
+    *refresh_failures += 1;
+
+    if *refresh_failures >= MAX_REFRESH_FAILURES {
+        app.credentials.notify(CredentialStatus::RefreshFailing {
+            backend: Backend::Github,
+            failures: *refresh_failures,
+        });
+    }
+
+    let warning = chrono::Duration::days(app.config.credential_expiry_warning_days as i64);
+    if let Some(expiry) = app.credentials.github().and_then(|c| c.expiry()) {
+        if expiry < Utc::now() + warning {
+            app.credentials.notify(CredentialStatus::ExpiresSoon {
+                backend: Backend::Github,
+                expiry,
+            });
+        }
+    }
+}
+
+fn refresh_succeeded(app: &Application, refresh_failures: &mut usize) {
+    *refresh_failures = 0;
+    app.credentials.resolve(|status| {
+        matches!(
+            status,
+            CredentialStatus::RefreshFailing { .. }
+                | CredentialStatus::ExpiresSoon { .. }
+                | CredentialStatus::InstallationSuspended { .. }
+        )
+    });
+}
+
+async fn update_credentials(app: &Application, refresh_failures: &mut usize) {
     if app.env.allow(Feature::GithubOrgInstallation) {
         match app.credentials.github().and_then(|c| c.expiry()) {
             // If we have a valid token, do nothing.
             Some(expiry) if expiry > Utc::now() + chrono::Duration::minutes(10) => {}
 
-            _ => {
-                if let Err(e) = remotes::github::refresh_github_installation_token(app).await {
-                    error!(?e, "failed to get GitHub token");
+            _ => match remotes::github::refresh_github_installation_token(app).await {
+                Ok(()) => {
+                    refresh_succeeded(app, refresh_failures);
+                    info!("Github installation token refreshed!")
                 }
-                if app.credentials.github().is_none() {
-                    error!("Error in the matrix");
+                Err(e) => {
+                    error!(?e, "failed to get GitHub token");
+
+                    if e.is_suspended() {
+                        app.credentials
+                            .notify(CredentialStatus::InstallationSuspended {
+                                backend: Backend::Github,
+                            });
+                    }
+
+                    refresh_failed(app, refresh_failures);
                 }
-                info!("Github installation token refreshed!")
-            }
+            },
         }
     }
 
@@ -177,6 +353,7 @@ async fn update_credentials(app: &Application) {
                 Ok(res) => res.text().await,
                 Err(err) => {
                     warn!(?err, "refreshing bloop token failed");
+                    refresh_failed(app, refresh_failures);
                     return;
                 }
             }
@@ -210,7 +387,7 @@ async fn update_credentials(app: &Application) {
                     //
                     error!(?err, "failed to refresh access token. forcing re-login");
 
-                    if app.credentials.remove(&Backend::Github).is_some() {
+                    if app.credentials.invalidate(Backend::Github).is_some() {
                         app.credentials.store().unwrap();
                     }
 
@@ -228,6 +405,7 @@ async fn update_credentials(app: &Application) {
                 )));
 
             app.credentials.store().unwrap();
+            refresh_succeeded(app, refresh_failures);
             info!("new bloop access keys saved");
         }
 
@@ -246,15 +424,146 @@ async fn update_credentials(app: &Application) {
             true
         };
 
-        if github_expired && app.credentials.remove(&Backend::Github).is_some() {
+        if github_expired && app.credentials.invalidate(Backend::Github).is_some() {
             app.credentials.store().unwrap();
             debug!("github oauth is invalid; credentials removed");
         }
     }
 }
 
+/// Keep the GitLab credentials fresh, and the list of GitLab projects up to date.
+///
+/// An access token in `gitlab_token` connects GitLab when no user has.
+pub(crate) async fn sync_gitlab_status(app: Application) {
+    const POLL_PERIOD: Duration = POLL_INTERVAL_MINUTE[1];
+    const MAX_AUTH_FAILURES: usize = 3;
+
+    let mut auth_failures = 0;
+    let mut refresh_failures = 0;
+    loop {
+        if let (None, Some(token)) = (app.credentials.gitlab(), &app.config.gitlab_token) {
+            let auth = gitlab::Auth::Token {
+                token: token.clone(),
+            };
+            app.credentials
+                .set_gitlab(gitlab::State::new(&app.config.gitlab_url, auth));
+        }
+
+        let Some(mut gitlab) = app.credentials.gitlab() else {
+            sleep(Duration::from_secs(1)).await;
+            continue;
+        };
+
+        if gitlab.needs_refresh() {
+            let refreshed = match app.config.gitlab_client_id_and_secret() {
+                Some((id, secret)) => gitlab.refresh(id, secret).await,
+                None => Err(remotes::RemoteError::Configuration("gitlab_client_id")),
+            };
+
+            match refreshed {
+                Ok(refreshed) => {
+                    refresh_failures = 0;
+                    app.credentials.resolve(|status| {
+                        matches!(
+                            status,
+                            CredentialStatus::RefreshFailing {
+                                backend: Backend::GitLab,
+                                ..
+                            }
+                        )
+                    });
+
+                    app.credentials.set_gitlab(refreshed.clone());
+                    if let Err(err) = app.credentials.store() {
+                        error!(?err, "failed to save credentials");
+                    }
+
+                    gitlab = refreshed;
+                }
+                Err(err) => {
+                    refresh_failures += 1;
+                    warn!(?err, refresh_failures, "failed to refresh gitlab token");
+
+                    if refresh_failures >= MAX_REFRESH_FAILURES {
+                        app.credentials.notify(CredentialStatus::RefreshFailing {
+                            backend: Backend::GitLab,
+                            failures: refresh_failures,
+                        });
+                    }
+                }
+            }
+        }
+
+        match gitlab.current_repo_list().await {
+            Ok(projects) => {
+                auth_failures = 0;
+                app.credentials.resolve(|status| {
+                    status
+                        == &CredentialStatus::ReauthenticationRequired {
+                            backend: Backend::GitLab,
+                        }
+                });
+
+                update_gitlab_metadata(&app, &gitlab, &projects);
+                app.credentials
+                    .set_gitlab(gitlab.update_repositories(projects));
+            }
+            Err(err) if err.is_auth_failure() => {
+                auth_failures += 1;
+                warn!(?err, auth_failures, "gitlab rejected credentials");
+
+                if auth_failures >= MAX_AUTH_FAILURES {
+                    error!("gitlab credentials are invalid; re-authentication required");
+                    auth_failures = 0;
+
+                    if app.credentials.invalidate(Backend::GitLab).is_some() {
+                        if let Err(err) = app.credentials.store() {
+                            error!(?err, "failed to save credentials");
+                        }
+                    }
+                }
+            }
+            Err(err) => debug!(?err, "failed to list gitlab projects"),
+        }
+
+        sleep(POLL_PERIOD).await;
+    }
+}
+
+/// Refresh the descriptions and topics of the GitLab repositories in the pool.
+fn update_gitlab_metadata(app: &Application, gitlab: &gitlab::State, projects: &[gitlab::Project]) {
+    let mut updated = false;
+
+    for project in projects {
+        let Ok(reporef) = gitlab.reporef(project) else {
+            continue;
+        };
+
+        let description = project.description.clone().filter(|d| !d.trim().is_empty());
+        let topics = project
+            .topics
+            .iter()
+            .map(|t| t.to_lowercase())
+            .collect::<Vec<_>>();
+
+        app.repo_pool.update(&reporef, |_, repo| {
+            if repo.description != description || repo.topics != topics {
+                repo.description = description;
+                repo.topics = topics;
+                updated = true;
+            }
+        });
+    }
+
+    if updated {
+        if let Err(err) = app.config.source.save_pool(app.repo_pool.clone()) {
+            error!(?err, "failed to save repository metadata");
+        }
+    }
+}
+
 pub(crate) async fn check_repo_updates(app: Application) {
-    while app.credentials.github().is_none() {
+    while app.credentials.github().is_none() && app.credentials.gitlab().is_none() {
         sleep(Duration::from_millis(100)).await
     }
 
@@ -268,7 +577,7 @@ pub(crate) async fn check_repo_updates(app: Application) {
                     }
                 }
                 scc::hash_map::Entry::Vacant(vacant) => {
-                    if repo.sync_status.indexable() {
+                    if repo.sync_status.indexable() && !repo.pinned {
                         vacant.insert_entry(tokio::spawn(periodic_repo_poll(
                             app.clone(),
                             reporef.to_owned(),
@@ -298,6 +607,11 @@ async fn periodic_repo_poll(app: Application, reporef: RepoRef) -> Option<()> {
             return None;
         }
 
+        if app.repo_pool.read(&reporef, |_, repo| repo.pinned)? {
+            debug!(?reporef, "repo is pinned, terminating monitoring");
+            return None;
+        }
+
         debug!("starting sync");
         if let Err(err) = app.write_index().block_until_synced(reporef.clone()).await {
             error!(?err, ?reporef, "failed to sync & index repo");
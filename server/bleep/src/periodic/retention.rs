@@ -0,0 +1,79 @@
+//! Deletion of data older than the retention windows of the deployment.
+//!
+//! Conversations, query events and the policy audit log are kept for `conversation_retention_days`,
+//! `query_event_retention_days` and `audit_log_retention_days`, or forever when those aren't set.
+//! Query events are the query log, the usage of snippets, and the token usage, so usage boosts and
+//! usage reports only reach as far back as they are kept.
+
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{db::Retention, Application};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Purge {
+    /// Whether the records were only counted.
+    pub dry_run: bool,
+    /// The records that were deleted from each table, or would be on a dry run. Tables without a
+    /// retention window are left out.
+    pub deleted: BTreeMap<&'static str, u64>,
+}
+
+/// Delete expired data, or only report it with `retention_dry_run`.
+///
+/// Runs on startup and every hour thereafter
+pub(crate) async fn purge_expired(app: Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+
+        match purge(&app, app.config.retention_dry_run).await {
+            Ok(purge) if purge.deleted.values().any(|&n| n > 0) => {
+                info!(?purge, "purged expired data")
+            }
+            Ok(_) => {}
+            Err(err) => error!(?err, "failed to purge expired data"),
+        }
+    }
+}
+
+pub(crate) async fn purge(app: &Application, dry_run: bool) -> anyhow::Result<Purge> {
+    let config = &app.config;
+    let retention = Retention::new(&app.sql);
+
+    let now = Utc::now().timestamp();
+    let cutoff = |days: Option<u64>| days.map(|days| now - days as i64 * DAY_SECS);
+
+    let mut purge = Purge {
+        dry_run,
+        ..Default::default()
+    };
+
+    if let Some(before) = cutoff(config.conversation_retention_days) {
+        let deleted = retention.conversations(before, dry_run).await?;
+        purge.deleted.insert("conversations", deleted);
+    }
+
+    if let Some(before) = cutoff(config.query_event_retention_days) {
+        let deleted = retention.query_log(before, dry_run).await?;
+        purge.deleted.insert("query_log", deleted);
+
+        let deleted = retention.snippet_usage(before, dry_run).await?;
+        purge.deleted.insert("snippet_usage", deleted);
+
+        let deleted = retention.token_usage(before, dry_run).await?;
+        purge.deleted.insert("token_usage", deleted);
+    }
+
+    if let Some(before) = cutoff(config.audit_log_retention_days) {
+        let deleted = retention.policy_audit(before, dry_run).await?;
+        purge.deleted.insert("policy_audit", deleted);
+    }
+
+    Ok(purge)
+}
@@ -0,0 +1,397 @@
+//! Guardrails for questions and answers.
+//!
+//! A policy is a list of rules, read from the JSON file at `policy_file`. Each rule checks
+//! questions, answers, or both, and either blocks the text or rewrites it. Questions are checked
+//! before any retrieval happens, and answers are checked as they are generated, so a blocked
+//! answer is never streamed in full.
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     {
+//!       "name": "secrets",
+//!       "applies_to": "question",
+//!       "check": "regex",
+//!       "pattern": "(api|secret)[ _-]?keys?",
+//!       "message": "Questions about credentials are not allowed."
+//!     },
+//!     {
+//!       "name": "verbatim",
+//!       "applies_to": "answer",
+//!       "check": "verbatim",
+//!       "max_lines": 40,
+//!       "action": { "rewrite": { "replacement": "[... truncated by policy]" } }
+//!     }
+//!   ]
+//! }
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+const CODE_FENCE: &str = "```";
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Policy {
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rule {
+    name: String,
+    #[serde(default)]
+    applies_to: Scope,
+    #[serde(flatten)]
+    check: Check,
+    #[serde(default)]
+    action: Action,
+    /// The message returned in place of blocked text.
+    message: Option<String>,
+}
+
+/// The kind of text a rule applies to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Question,
+    Answer,
+    #[default]
+    Both,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "check")]
+enum Check {
+    /// Matches text containing the pattern, case-insensitively.
+    ///
+    /// Rewriting replaces every match with the replacement.
+    Regex { pattern: Pattern },
+
+    /// A linear classifier over words. Text matches when the weights of the distinct words it
+    /// contains add up to at least `threshold`.
+    ///
+    /// Rewriting replaces the whole text with the replacement.
+    Classifier {
+        weights: HashMap<String, f32>,
+        threshold: f32,
+    },
+
+    /// Matches text containing a code block longer than `max_lines`, as in large verbatim dumps
+    /// of a file.
+    ///
+    /// Rewriting truncates every such block to `max_lines`, followed by the replacement.
+    Verbatim { max_lines: usize },
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    #[default]
+    Block,
+    Rewrite {
+        replacement: String,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "String")]
+struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map(Self)
+    }
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Question => "question",
+            Self::Answer => "answer",
+            Self::Both => "both",
+        }
+    }
+}
+
+/// The result of checking text against a policy.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// The text was rewritten by the listed rules, in order.
+    Rewrite {
+        text: String,
+        rules: Vec<String>,
+    },
+    Block {
+        rule: String,
+        message: String,
+    },
+}
+
+impl Policy {
+    /// Read the policy at `path`. Without a path, the policy allows everything.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open policy file {}", path.display()))?;
+        serde_json::from_reader(file).context("invalid policy file")
+    }
+
+    /// Check `text` against every rule that applies to `scope`, in order.
+    ///
+    /// Rewrites are cumulative, and later rules see the rewritten text. The first rule that
+    /// blocks the text ends the check.
+    pub fn check(&self, scope: Scope, text: &str) -> Verdict {
+        let mut text = text.to_owned();
+        let mut rewritten_by = Vec::new();
+
+        for rule in self
+            .rules
+            .iter()
+            .filter(|r| r.applies_to == scope || r.applies_to == Scope::Both)
+        {
+            if !rule.check.matches(&text) {
+                continue;
+            }
+
+            match &rule.action {
+                Action::Block => {
+                    return Verdict::Block {
+                        rule: rule.name.clone(),
+                        message: rule.message.clone().unwrap_or_else(|| {
+                            format!("This was blocked by the `{}` policy.", rule.name)
+                        }),
+                    }
+                }
+                Action::Rewrite { replacement } => {
+                    text = rule.check.rewrite(&text, replacement);
+                    rewritten_by.push(rule.name.clone());
+                }
+            }
+        }
+
+        if rewritten_by.is_empty() {
+            Verdict::Allow
+        } else {
+            Verdict::Rewrite {
+                text,
+                rules: rewritten_by,
+            }
+        }
+    }
+}
+
+impl Check {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Regex { pattern } => pattern.0.is_match(text),
+            Self::Classifier { weights, threshold } => {
+                let words = text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|w| !w.is_empty())
+                    .map(str::to_lowercase)
+                    .collect::<HashSet<_>>();
+
+                let score = words.iter().filter_map(|w| weights.get(w)).sum::<f32>();
+                score >= *threshold
+            }
+            Self::Verbatim { max_lines } => code_blocks(text).any(|lines| lines > *max_lines),
+        }
+    }
+
+    fn rewrite(&self, text: &str, replacement: &str) -> String {
+        match self {
+            Self::Regex { pattern } => pattern
+                .0
+                .replace_all(text, regex::NoExpand(replacement))
+                .into_owned(),
+            Self::Classifier { .. } => replacement.to_owned(),
+            Self::Verbatim { max_lines } => truncate_code_blocks(text, *max_lines, replacement),
+        }
+    }
+}
+
+/// The number of lines in each fenced code block of a markdown document.
+///
+/// A block that is still open at the end of the text is counted too, as answers are checked while
+/// they are streamed.
+fn code_blocks(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut lines = text.lines();
+    std::iter::from_fn(move || {
+        lines.find(|l| l.trim_start().starts_with(CODE_FENCE))?;
+        Some(
+            lines
+                .by_ref()
+                .take_while(|l| !l.trim_start().starts_with(CODE_FENCE))
+                .count(),
+        )
+    })
+}
+
+fn truncate_code_blocks(text: &str, max_lines: usize, replacement: &str) -> String {
+    let mut out = Vec::new();
+    let mut block_lines = None;
+
+    for line in text.lines() {
+        let is_fence = line.trim_start().starts_with(CODE_FENCE);
+
+        block_lines = match (block_lines, is_fence) {
+            (None, true) => Some(0),
+            (Some(_), true) => None,
+            (Some(n), false) => Some(n + 1),
+            (None, false) => None,
+        };
+
+        match block_lines {
+            Some(n) if n == max_lines + 1 => out.push(replacement),
+            Some(n) if n > max_lines + 1 => {}
+            _ => out.push(line),
+        }
+    }
+
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(rules: serde_json::Value) -> Policy {
+        serde_json::from_value(serde_json::json!({ "rules": rules })).unwrap()
+    }
+
+    #[test]
+    fn regex_blocks_questions() {
+        let policy = policy(serde_json::json!([{
+            "name": "secrets",
+            "applies_to": "question",
+            "check": "regex",
+            "pattern": "exfiltrate|api[ _-]?keys",
+        }]));
+
+        assert_eq!(
+            policy.check(Scope::Question, "Exfiltrate all API keys"),
+            Verdict::Block {
+                rule: "secrets".into(),
+                message: "This was blocked by the `secrets` policy.".into(),
+            }
+        );
+        assert_eq!(
+            policy.check(Scope::Question, "where is the router?"),
+            Verdict::Allow
+        );
+        assert_eq!(
+            policy.check(Scope::Answer, "here are the api keys"),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn regex_rewrites() {
+        let policy = policy(serde_json::json!([{
+            "name": "internal",
+            "check": "regex",
+            "pattern": r"project \w+",
+            "action": { "rewrite": { "replacement": "project $redacted" } },
+        }]));
+
+        assert_eq!(
+            policy.check(Scope::Answer, "See Project Falcon for details"),
+            Verdict::Rewrite {
+                text: "See project $redacted for details".into(),
+                rules: vec!["internal".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn classifier_threshold() {
+        let policy = policy(serde_json::json!([{
+            "name": "exfiltration",
+            "check": "classifier",
+            "weights": { "dump": 0.5, "all": 0.2, "secrets": 0.6, "tokens": 0.6 },
+            "threshold": 1.0,
+            "message": "Not allowed.",
+        }]));
+
+        assert_eq!(
+            policy.check(Scope::Question, "dump all the secrets, all of them"),
+            Verdict::Block {
+                rule: "exfiltration".into(),
+                message: "Not allowed.".into(),
+            }
+        );
+        assert_eq!(
+            policy.check(Scope::Question, "how are secrets stored?"),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn verbatim_truncates_code_blocks() {
+        let policy = policy(serde_json::json!([{
+            "name": "verbatim",
+            "applies_to": "answer",
+            "check": "verbatim",
+            "max_lines": 2,
+            "action": { "rewrite": { "replacement": "// ..." } },
+        }]));
+
+        let answer = "Short:\n```rust\na\nb\n```\nLong:\n```rust\na\nb\nc\nd\n```\nDone";
+        assert_eq!(
+            policy.check(Scope::Answer, answer),
+            Verdict::Rewrite {
+                text: "Short:\n```rust\na\nb\n```\nLong:\n```rust\na\nb\n// ...\n```\nDone".into(),
+                rules: vec!["verbatim".into()],
+            }
+        );
+
+        // A block that is still being streamed is checked too.
+        assert_eq!(code_blocks("```\na\nb\nc").collect::<Vec<_>>(), [3]);
+        assert_eq!(
+            policy.check(Scope::Question, "```\na\nb\nc\n```"),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn blocks_take_precedence_over_rewrites() {
+        let policy = policy(serde_json::json!([
+            {
+                "name": "rename",
+                "check": "regex",
+                "pattern": "foo",
+                "action": { "rewrite": { "replacement": "bar" } },
+            },
+            { "name": "no-bar", "check": "regex", "pattern": "bar" },
+        ]));
+
+        assert!(matches!(
+            policy.check(Scope::Question, "foo"),
+            Verdict::Block { rule, .. } if rule == "no-bar"
+        ));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let rules = serde_json::json!({
+            "rules": [{ "name": "bad", "check": "regex", "pattern": "(" }],
+        });
+        assert!(serde_json::from_value::<Policy>(rules).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 pub mod compiler;
+pub mod correction;
 pub mod execute;
-pub mod languages;
-pub mod parser;
+pub mod export;
 pub mod planner;
 pub mod ranking;
+pub mod tuning;
+
+pub use bleep_query::{languages, parser};
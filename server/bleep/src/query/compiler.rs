@@ -1,15 +1,11 @@
-use std::{
-    borrow::Cow,
-    collections::{HashMap, HashSet},
-    mem,
-};
+use std::{borrow::Cow, collections::HashMap, mem};
 
 use anyhow::{Context, Result};
 use compact_str::CompactString;
 use either::Either;
 use smallvec::SmallVec;
 use tantivy::{
-    query::{AllQuery, BooleanQuery, BoostQuery, TermQuery},
+    query::{AllQuery, BooleanQuery, BoostQuery, Occur, TermQuery},
     schema::{Field, IndexRecordOption},
     Index, Term,
 };
@@ -27,15 +23,22 @@ enum Extraction<'a> {
 
     /// Match a string against a tantivy `bytes` field.
     ByteString(&'a Cow<'a, str>),
+
+    /// Match every one of a list of exact terms against a tantivy `text` field.
+    Terms(Vec<String>),
 }
 
 /// A closure that tries to pull out an `Extraction` variant, given a `Query` reference.
 type Extractor = dyn for<'a> FnMut(&'a Query<'a>) -> Option<Extraction<'a>>;
 
+/// A closure that decides whether a `Query` asks for documents that are excluded by default.
+type Inclusion = dyn for<'a> Fn(&'a Query<'a>) -> bool;
+
 #[derive(Default)]
 pub struct Compiler {
-    priority: HashSet<Field>,
+    boosts: HashMap<Field, f32>,
     extractors: HashMap<Field, Box<Extractor>>,
+    exclusions: Vec<(Field, Box<Inclusion>)>,
 }
 
 impl Compiler {
@@ -44,9 +47,9 @@ impl Compiler {
         Self::default()
     }
 
-    /// Mark a list of fields as being high priority in compiled search queries.
-    pub fn priority(mut self, fields: &[Field]) -> Self {
-        self.priority = fields.iter().copied().collect();
+    /// Weigh matches on a field by `boost` in compiled search queries.
+    pub fn boost(mut self, field: Field, boost: f32) -> Self {
+        self.boosts.insert(field, boost);
         self
     }
 
@@ -81,6 +84,31 @@ impl Compiler {
         self
     }
 
+    /// Add a field of exact terms to the compiler.
+    ///
+    /// Matches documents that have every one of the `Vec<String>` terms in a tantivy `text` field,
+    /// as they are, without tokenizing them.
+    pub fn terms<F>(mut self, tantivy_field: Field, mut extractor: F) -> Self
+    where
+        F: for<'b> FnMut(&'b Query<'b>) -> Option<Vec<String>> + 'static,
+    {
+        self.extractors.insert(
+            tantivy_field,
+            Box::new(move |q| extractor(q).map(Extraction::Terms)),
+        );
+        self
+    }
+
+    /// Exclude documents whose boolean `tantivy_field` is set, unless `include` returns `true`
+    /// for the query being compiled.
+    pub fn exclude_unless<F>(mut self, tantivy_field: Field, include: F) -> Self
+    where
+        F: for<'b> Fn(&'b Query<'b>) -> bool + 'static,
+    {
+        self.exclusions.push((tantivy_field, Box::new(include)));
+        self
+    }
+
     /// Compile a list of queries into a single Tantivy query that matches any
     /// of them.
     pub fn compile<'a, I>(mut self, queries: I, index: &Index) -> Result<DynQuery>
@@ -122,13 +150,7 @@ impl Compiler {
                                 .collect()
                         };
 
-                        let mut field_query: DynQuery = Box::new(BooleanQuery::intersection(terms));
-
-                        if self.priority.contains(field) {
-                            field_query = Box::new(BoostQuery::new(field_query, 10.0));
-                        }
-
-                        field_query
+                        Box::new(BooleanQuery::intersection(terms))
                     }
                     Extraction::Literal(Literal::Regex(regex)) => {
                         let plan = planner::plan(&regex)?;
@@ -140,12 +162,49 @@ impl Compiler {
                         let q = TermQuery::new(term, IndexRecordOption::Basic);
                         Box::new(q) as DynQuery
                     }
+
+                    Extraction::Terms(terms) => {
+                        let terms = terms
+                            .iter()
+                            .map(|t| {
+                                let term = Term::from_field_text(*field, t);
+                                Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as DynQuery
+                            })
+                            .collect();
+
+                        Box::new(BooleanQuery::intersection(terms))
+                    }
+                };
+
+                let field_query: DynQuery = match self.boosts.get(field) {
+                    Some(&boost) if boost != 1.0 => Box::new(BoostQuery::new(field_query, boost)),
+                    _ => field_query,
                 };
 
                 intersection.push(field_query);
             }
 
-            sub_queries.push(Box::new(BooleanQuery::intersection(intersection)));
+            let excluded = self
+                .exclusions
+                .iter()
+                .filter(|(_, include)| !include(query))
+                .map(|(field, _)| {
+                    let term = Term::from_field_bool(*field, true);
+                    let q = TermQuery::new(term, IndexRecordOption::Basic);
+                    (Occur::MustNot, Box::new(q) as DynQuery)
+                })
+                .collect::<Vec<_>>();
+
+            let sub_query: DynQuery = Box::new(BooleanQuery::intersection(intersection));
+            sub_queries.push(if excluded.is_empty() {
+                sub_query
+            } else {
+                Box::new(BooleanQuery::new(
+                    std::iter::once((Occur::Must, sub_query))
+                        .chain(excluded)
+                        .collect(),
+                ))
+            });
         }
 
         Ok(if sub_queries.len() == 1 {
@@ -379,4 +438,31 @@ mod tests {
             assert_eq!(term.term().as_str().unwrap(), expected);
         }
     }
+
+    #[test]
+    fn test_exclude_unless() {
+        fn compile<'a>(query: &'a Query<'a>) -> DynQuery {
+            let index = Index::create_in_ram(tantivy::schema::Schema::builder().build());
+            Compiler::new()
+                .exclude_unless(Field::from_field_id(7), |q| q.branch.is_some())
+                .compile(std::iter::once(query), &index)
+                .unwrap()
+        }
+
+        let query = compile(&Query::default());
+        let query = query.downcast_ref::<BooleanQuery>().unwrap();
+        assert_eq!(query.clauses().len(), 2);
+        assert_eq!(query.clauses()[0].0, Occur::Must);
+        assert_eq!(query.clauses()[1].0, Occur::MustNot);
+
+        let query = compile(&Query {
+            branch: Some(Literal::Plain("rev:v1.4".into())),
+            ..Default::default()
+        });
+        let query = query.downcast_ref::<BooleanQuery>().unwrap();
+        assert!(query
+            .clauses()
+            .iter()
+            .all(|(occur, _)| *occur != Occur::MustNot));
+    }
 }
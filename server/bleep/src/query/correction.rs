@@ -0,0 +1,215 @@
+//! Spell-correction of identifiers in natural language queries.
+//!
+//! Queries often mention code by name, and a single typo in an identifier ("RepoReff") is enough to
+//! derail retrieval. Before searching, we find the identifier-like words of a query and replace
+//! those that are a small edit away from a known symbol or path fragment.
+
+use std::ops::Range;
+
+use lazy_regex::regex;
+use tracing::debug;
+
+use crate::{
+    indexes::{File, Indexer},
+    repo::RepoRef,
+};
+
+/// The maximum number of words we try to correct in a single query.
+const MAX_CORRECTED_WORDS: usize = 5;
+
+/// The number of documents we collect vocabulary from, for each word.
+const VOCABULARY_DOCUMENTS: usize = 20;
+
+/// Words shorter than this are never corrected, as almost anything is a small edit away from them.
+const MIN_WORD_LEN: usize = 4;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    /// The word as written in the query.
+    pub original: String,
+    /// The identifier or path fragment that was searched for instead.
+    pub corrected: String,
+}
+
+/// Correct identifiers in `query` against the symbols and paths of a repository.
+///
+/// Returns the corrected query, and the corrections that were applied to it.
+pub async fn correct(
+    file_index: &Indexer<File>,
+    repo_ref: &RepoRef,
+    branch: Option<&str>,
+    query: &str,
+) -> (String, Vec<Correction>) {
+    let mut replacements = Vec::new();
+
+    for range in identifiers(query).into_iter().take(MAX_CORRECTED_WORDS) {
+        let word = &query[range.clone()];
+        let vocabulary = file_index
+            .identifier_vocabulary(repo_ref, word, branch, VOCABULARY_DOCUMENTS)
+            .await;
+
+        if let Some(corrected) = best_match(word, vocabulary.iter().map(String::as_str)) {
+            replacements.push((range, corrected.to_owned()));
+        }
+    }
+
+    debug!(?replacements, "corrected query identifiers");
+    apply(query, replacements)
+}
+
+/// Replace the given ranges of `query`, which must be in order and non-overlapping.
+fn apply(query: &str, replacements: Vec<(Range<usize>, String)>) -> (String, Vec<Correction>) {
+    let mut corrected = String::with_capacity(query.len());
+    let mut corrections = Vec::with_capacity(replacements.len());
+    let mut cursor = 0;
+
+    for (range, replacement) in replacements {
+        corrected += &query[cursor..range.start];
+        corrected += &replacement;
+        cursor = range.end;
+
+        corrections.push(Correction {
+            original: query[range].to_owned(),
+            corrected: replacement,
+        });
+    }
+
+    corrected += &query[cursor..];
+    (corrected, corrections)
+}
+
+/// Find the byte ranges of words in `query` that look like identifiers or path fragments, rather
+/// than prose.
+fn identifiers(query: &str) -> Vec<Range<usize>> {
+    regex!(r"[A-Za-z_][A-Za-z0-9_]*")
+        .find_iter(query)
+        .map(|m| m.range())
+        .filter(|range| {
+            let word = &query[range.clone()];
+            let (before, after) = (&query[..range.start], &query[range.end..]);
+
+            let is_code_case = word.contains('_')
+                || (word.chars().skip(1).any(char::is_uppercase)
+                    && word.chars().any(char::is_lowercase));
+
+            let is_delimited = before.ends_with(['`', '/', '.', ':'])
+                || after.starts_with(['`', '/', ':'])
+                || regex!(r"^\.\w").is_match(after);
+
+            word.chars().count() >= MIN_WORD_LEN && (is_code_case || is_delimited)
+        })
+        .collect()
+}
+
+/// Pick the closest entry in `vocabulary` to `word`, if `word` is not itself in the vocabulary and
+/// the closest entry is near enough to be a typo.
+fn best_match<'a>(word: &str, vocabulary: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = if word.chars().count() < 8 { 1 } else { 2 };
+    let lowercase = word.to_lowercase();
+
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in vocabulary {
+        let distance = edit_distance(&lowercase, &candidate.to_lowercase());
+
+        // Words that match a known identifier, regardless of case, are left alone.
+        if distance == 0 {
+            return None;
+        }
+
+        if distance > max_distance {
+            continue;
+        }
+
+        // Break ties alphabetically, as the vocabulary is unordered.
+        let is_better = match best {
+            Some(b) => (distance, candidate) < b,
+            None => true,
+        };
+
+        if is_better {
+            best = Some((distance, candidate));
+        }
+    }
+
+    best.map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        let query =
+            "where is RepoReff parsed in `indexes` or src/webserver/query.rs? How does it work";
+        let words = identifiers(query)
+            .into_iter()
+            .map(|r| &query[r])
+            .collect::<Vec<_>>();
+
+        assert_eq!(words, ["RepoReff", "indexes", "webserver", "query"]);
+
+        assert!(identifiers("How do I run the tests?").is_empty());
+        assert_eq!(identifiers("what calls fuzzy_path_match"), [11..27]);
+    }
+
+    #[test]
+    fn test_best_match() {
+        let vocabulary = ["RepoRef", "RepoRefs", "Repository", "repo"];
+
+        assert_eq!(
+            best_match("RepoReff", vocabulary.into_iter()),
+            Some("RepoRef")
+        );
+        assert_eq!(best_match("RepoRef", vocabulary.into_iter()), None);
+        assert_eq!(best_match("reporef", vocabulary.into_iter()), None);
+        assert_eq!(
+            best_match("Repositry", vocabulary.into_iter()),
+            Some("Repository")
+        );
+        assert_eq!(best_match("Indexer", vocabulary.into_iter()), None);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("reporeff", "reporef"), 1);
+        assert_eq!(edit_distance("héllo", "hello"), 1);
+    }
+
+    #[test]
+    fn test_apply() {
+        let query = "where is RepoReff defined?";
+        let (corrected, corrections) = apply(query, vec![(9..17, "RepoRef".to_owned())]);
+
+        assert_eq!(corrected, "where is RepoRef defined?");
+        assert_eq!(
+            corrections,
+            [Correction {
+                original: "RepoReff".to_owned(),
+                corrected: "RepoRef".to_owned(),
+            }]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
     sync::Arc,
 };
@@ -7,10 +8,15 @@ use super::{parser, ranking::DocumentTweaker};
 use crate::{
     collector::{BytesFilterCollector, FrequencyCollector},
     indexes::{
-        reader::{base_name, ContentReader, FileReader, OpenReader, RepoReader},
+        reader::{
+            base_name, structural_fingerprints, ContentReader, FileReader, OpenReader, RepoReader,
+            StructuralReader,
+        },
         DocumentRead, File, Indexable, Indexer, Indexes, Repo,
     },
+    intelligence::TreeSitterFile,
     snippet::{HighlightedString, SnippedFile, Snipper},
+    state::RepositoryPool,
 };
 
 use anyhow::{bail, Result};
@@ -20,6 +26,9 @@ use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use tantivy::collector::{MultiCollector, TopDocs};
 
+/// The prefix of raw tantivy queries, which are run as they are against the file index.
+pub const ADVANCED_PREFIX: &str = "advanced:";
+
 const fn default_page_size() -> usize {
     100
 }
@@ -196,6 +205,18 @@ pub trait ExecuteQuery {
 }
 
 impl ApiQuery {
+    /// A query with the same paging and context defaults as the HTTP API.
+    pub fn new(q: impl Into<String>) -> Self {
+        Self {
+            q: q.into(),
+            page: 0,
+            page_size: default_page_size(),
+            calculate_totals: true,
+            context_before: default_context(),
+            context_after: default_context(),
+        }
+    }
+
     pub async fn query(self: Arc<Self>, indexes: Arc<Indexes>) -> Result<QueryResponse> {
         let query = self.q.clone();
         let compiled = parser::parse(&query)?;
@@ -236,6 +257,11 @@ impl ApiQuery {
             if ContentReader.query_matches(q) {
                 tracing::trace!("executing with ContentReader");
                 return ContentReader.execute(&indexes.file, &queries, &self).await;
+            } else if StructuralReader.query_matches(q) {
+                tracing::trace!("executing with StructuralReader");
+                return StructuralReader
+                    .execute(&indexes.file, &queries, &self)
+                    .await;
             } else if RepoReader.query_matches(q) {
                 tracing::trace!("executing with RepoReader");
                 return RepoReader.execute(&indexes.repo, &queries, &self).await;
@@ -251,6 +277,69 @@ impl ApiQuery {
         bail!("mangled query")
     }
 
+    /// The raw tantivy query, if this is an `advanced:` query.
+    pub fn advanced(&self) -> Option<&str> {
+        self.q
+            .trim_start()
+            .strip_prefix(ADVANCED_PREFIX)
+            .map(str::trim)
+    }
+
+    /// Run a raw tantivy query against the file index, bypassing the query language.
+    ///
+    /// Fields are named as in the file schema, and terms without a field match file content.
+    /// Results are files, ranked by tantivy's score alone.
+    pub async fn query_advanced(
+        self: Arc<Self>,
+        indexes: Arc<Indexes>,
+        raw: &str,
+    ) -> Result<QueryResponse> {
+        let indexer = &indexes.file;
+        let top_k = TopDocs::with_limit(self.limit()).and_offset(self.offset());
+
+        let mut metadata_collector = MultiCollector::new();
+        let total_count_handle = metadata_collector.add_collector(tantivy::collector::Count);
+        let lang_stats_handle =
+            metadata_collector.add_collector(FrequencyCollector(indexer.source.lang));
+        let repo_stats_handle =
+            metadata_collector.add_collector(FrequencyCollector(indexer.source.raw_repo_name));
+
+        let mut results = indexer
+            .query_raw(
+                raw,
+                vec![indexer.source.content],
+                &FileReader,
+                (top_k, metadata_collector),
+            )
+            .await?;
+
+        let data = results
+            .docs
+            .map(|f| {
+                QueryResult::FileResult(FileResultData {
+                    relative_path: HighlightedString::new(f.relative_path),
+                    repo_name: f.repo_name,
+                    repo_ref: f.repo_ref,
+                    lang: f.lang,
+                    branches: f.branches,
+                })
+            })
+            .collect::<Vec<QueryResult>>();
+
+        let total_count = total_count_handle.extract(&mut results.metadata);
+
+        let stats = ResultStats::default()
+            .with_lang_freqs(lang_stats_handle.extract(&mut results.metadata))
+            .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
+
+        Ok(QueryResponse {
+            count: data.len(),
+            data,
+            metadata: PagingMetadata::new(self.page, self.page_size, Some(total_count)),
+            stats,
+        })
+    }
+
     fn limit(&self) -> usize {
         // do not permit a page-size of 0
         self.page_size.max(1)
@@ -309,6 +398,9 @@ impl ExecuteQuery for ContentReader {
         queries: &[parser::Query<'_>],
         q: &ApiQuery,
     ) -> Result<QueryResponse> {
+        // apply the workspace's stopwords and synonyms before anything is matched
+        let queries = &indexer.tuning.rewrite(queries);
+
         // queries that produce content results
         let relevant_queries = queries.iter().filter(|q| self.query_matches(q));
 
@@ -369,6 +461,8 @@ impl ExecuteQuery for ContentReader {
                     let (is_symbol, lit) = match target {
                         parser::Target::Symbol(lit) => (true, lit),
                         parser::Target::Content(lit) => (false, lit),
+                        // answered by the `StructuralReader`
+                        parser::Target::Structural(_) => continue,
                     };
 
                     if let Some(snippets) = snipper
@@ -408,6 +502,84 @@ impl ExecuteQuery for ContentReader {
     }
 }
 
+#[async_trait]
+impl ExecuteQuery for StructuralReader {
+    type Index = File;
+
+    async fn execute(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        queries: &[parser::Query<'_>],
+        q: &ApiQuery,
+    ) -> Result<QueryResponse> {
+        // the fingerprints of each pattern, which a file must all have to match it
+        let patterns = queries
+            .iter()
+            .filter(|q| self.query_matches(q))
+            .map(structural_fingerprints)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let top_k = TopDocs::with_limit(q.limit())
+            .and_offset(q.offset())
+            .tweak_score(DocumentTweaker(indexer.source.clone()));
+
+        let total_count_collector = tantivy::collector::Count;
+        let lang_stats_collector = FrequencyCollector(indexer.source.lang);
+        let repo_stats_collector = FrequencyCollector(indexer.source.raw_repo_name);
+
+        let mut metadata_collector = MultiCollector::new();
+        let total_count_handle = metadata_collector.add_collector(total_count_collector);
+        let lang_stats_handle = metadata_collector.add_collector(lang_stats_collector);
+        let repo_stats_handle = metadata_collector.add_collector(repo_stats_collector);
+
+        let mut results = indexer
+            .query(queries.iter(), self, (top_k, metadata_collector))
+            .await?;
+
+        let data = results
+            .docs
+            .filter_map(|doc| {
+                // fingerprints aren't stored, so the nodes that match are found by parsing again
+                let file = TreeSitterFile::try_build(doc.content.as_bytes(), doc.lang.as_ref()?);
+                let mut highlights = file
+                    .ok()?
+                    .fingerprints()
+                    .into_iter()
+                    .filter(|f| patterns.iter().any(|p| p.contains(&f.hash)))
+                    .map(|f| f.range)
+                    .collect::<Vec<_>>();
+
+                // matches of one pattern may be nested in those of another
+                highlights.sort_by_key(|range| (range.start, Reverse(range.end)));
+                let mut end = 0;
+                highlights.retain(|range| {
+                    let outermost = range.start >= end;
+                    end = end.max(range.end);
+                    outermost
+                });
+
+                Snipper::default()
+                    .context(q.context_before, q.context_after)
+                    .all_for_ranges(highlights, &doc)
+                    .map(QueryResult::Snippets)
+            })
+            .collect::<Vec<QueryResult>>();
+
+        let total_count = total_count_handle.extract(&mut results.metadata);
+
+        let stats = ResultStats::default()
+            .with_lang_freqs(lang_stats_handle.extract(&mut results.metadata))
+            .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
+
+        Ok(QueryResponse {
+            count: data.len(),
+            data,
+            metadata: PagingMetadata::new(q.page, q.page_size, Some(total_count)),
+            stats,
+        })
+    }
+}
+
 #[async_trait]
 impl ExecuteQuery for FileReader {
     type Index = File;
@@ -751,10 +923,78 @@ impl ExecuteQuery for OpenReader {
     }
 }
 
+/// Replace `topic:` filters with filters on the repositories that have a matching topic.
+///
+/// Queries that also filter by `repo:` keep that filter, and are dropped unless one of the
+/// repositories with the topic matches it.
+pub fn resolve_topics<'a>(
+    repo_pool: &RepositoryPool,
+    queries: Vec<parser::Query<'a>>,
+) -> Result<Vec<parser::Query<'a>>> {
+    let mut resolved = Vec::new();
+
+    for query in queries {
+        let Some(topic) = &query.topic else {
+            resolved.push(query);
+            continue;
+        };
+
+        let topic = match topic {
+            parser::Literal::Plain(text) => format!("^{}$", regex::escape(text)),
+            parser::Literal::Regex(regex) => regex.to_string(),
+        };
+        let topic = RegexBuilder::new(&topic).case_insensitive(true).build()?;
+        let repo = query
+            .repo
+            .as_ref()
+            .map(|repo| {
+                RegexBuilder::new(&repo.regex_str())
+                    .case_insensitive(!query.is_case_sensitive())
+                    .build()
+            })
+            .transpose()?;
+
+        let mut names = Vec::new();
+        repo_pool.scan(|reporef, r| {
+            let name = reporef.indexed_name();
+            if r.topics.iter().any(|t| topic.is_match(t))
+                && repo.as_ref().map_or(true, |repo| repo.is_match(&name))
+            {
+                names.push(name);
+            }
+        });
+        names.sort();
+
+        if query.repo.is_some() {
+            if !names.is_empty() {
+                resolved.push(parser::Query {
+                    topic: None,
+                    ..query
+                });
+            }
+        } else {
+            resolved.extend(names.into_iter().map(|name| parser::Query {
+                topic: None,
+                repo: Some(parser::Literal::Plain(name.into())),
+                ..query.clone()
+            }));
+        }
+    }
+
+    if resolved.is_empty() {
+        bail!("no repository has a matching topic");
+    }
+
+    Ok(resolved)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::snippet::*;
+    use crate::{
+        repo::{Backend, RepoRef, Repository},
+        snippet::*,
+    };
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -810,6 +1050,7 @@ mod tests {
                 repo_name: "local//bleep".into(),
                 repo_ref: "/User/bloop/bleep".into(),
                 lang: Some("Rust".into()),
+                license: None,
                 snippets: vec![Snippet {
                     data: r#"        mut writer: IndexWriter,\n        _threads: usize,\n    ) -> Result<()> {"#.to_owned(),
                     line_range: 49..51,
@@ -829,4 +1070,57 @@ mod tests {
 
         assert_eq!(expected, observed);
     }
+
+    #[test]
+    fn resolves_topics() {
+        let repo_pool = RepositoryPool::default();
+        for (name, topics) in [
+            ("bloopai/bloop", vec!["search", "rust"]),
+            ("bloopai/bloop-docs", vec!["docs"]),
+            ("quickwit-oss/tantivy", vec!["search"]),
+        ] {
+            let reporef = RepoRef::new(Backend::Github, name).unwrap();
+            let mut repo = Repository::remote_from(&reporef, "/unused".into());
+            repo.topics = topics.into_iter().map(str::to_owned).collect();
+            repo_pool.insert(reporef, repo).unwrap();
+        }
+
+        let repos = |q: &str| {
+            resolve_topics(&repo_pool, parser::parse(q).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|q| {
+                    assert_eq!(q.topic, None);
+                    q.repo.unwrap().unwrap().into_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            repos("topic:Search parse"),
+            [
+                "github.com/bloopai/bloop",
+                "github.com/quickwit-oss/tantivy"
+            ]
+        );
+        assert_eq!(repos("topic:search repo:tantivy parse"), ["tantivy"]);
+        assert_eq!(repos("repo:bloop parse"), ["bloop"]);
+
+        assert!(resolve_topics(&repo_pool, parser::parse("topic:sea parse").unwrap()).is_err());
+        assert!(resolve_topics(
+            &repo_pool,
+            parser::parse("topic:docs repo:tantivy").unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn advanced_queries() {
+        assert_eq!(
+            ApiQuery::new(" advanced: lang:rust AND content:parse").advanced(),
+            Some("lang:rust AND content:parse")
+        );
+        assert_eq!(ApiQuery::new("lang:rust parse").advanced(), None);
+        assert_eq!(ApiQuery::new("parse advanced:foo").advanced(), None);
+    }
 }
@@ -0,0 +1,221 @@
+//! Export of search results for tooling pipelines.
+//!
+//! Every highlighted match in a snippet becomes one row or one result, located by its region in
+//! the file. Results without a matched line, such as path and repository matches, are left out.
+
+use serde::Deserialize;
+use serde_json::json;
+
+use super::execute::{QueryResponse, QueryResult};
+use crate::snippet::SnippedFile;
+
+/// The id of the single rule that every SARIF result is reported under.
+const SARIF_RULE: &str = "bloop/search";
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Json,
+    Sarif,
+    Csv,
+}
+
+/// A single match, with one-based lines and columns. Columns count unicode code points, and the
+/// end column is exclusive.
+#[derive(Debug, PartialEq, Eq)]
+struct Match<'a> {
+    repo_ref: &'a str,
+    relative_path: &'a str,
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+    /// The line the match starts on.
+    line: &'a str,
+}
+
+/// Render a query response as a SARIF 2.1.0 log, with one run and one result per match.
+pub fn sarif(q: &str, response: &QueryResponse) -> serde_json::Value {
+    let results = matches(response)
+        .map(|m| {
+            json!({
+                "ruleId": SARIF_RULE,
+                "level": "note",
+                "message": { "text": m.line.trim() },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": m.relative_path },
+                        "region": {
+                            "startLine": m.start_line,
+                            "startColumn": m.start_column,
+                            "endLine": m.end_line,
+                            "endColumn": m.end_column,
+                            "snippet": { "text": m.line },
+                        },
+                    },
+                }],
+                "properties": { "repoRef": m.repo_ref },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "bloop",
+                    "informationUri": "https://bloop.ai",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [{
+                        "id": SARIF_RULE,
+                        "shortDescription": { "text": format!("Matches of the query `{q}`") },
+                    }],
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+/// Render a query response as CSV, with a header row and one row per match.
+pub fn csv(response: &QueryResponse) -> String {
+    let mut out =
+        "repo_ref,relative_path,start_line,start_column,end_line,end_column,match\n".to_owned();
+
+    for m in matches(response) {
+        out += &format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(m.repo_ref),
+            csv_field(m.relative_path),
+            m.start_line,
+            m.start_column,
+            m.end_line,
+            m.end_column,
+            csv_field(m.line.trim()),
+        );
+    }
+
+    out
+}
+
+fn matches(response: &QueryResponse) -> impl Iterator<Item = Match<'_>> {
+    response.data.iter().flat_map(|result| match result {
+        QueryResult::Snippets(file) => file_matches(file),
+        _ => vec![],
+    })
+}
+
+fn file_matches(file: &SnippedFile) -> Vec<Match<'_>> {
+    file.snippets
+        .iter()
+        .flat_map(|snippet| {
+            snippet.highlights.iter().filter_map(|range| {
+                let (start_line, start_column) = position(&snippet.data, range.start)?;
+                let (end_line, end_column) = position(&snippet.data, range.end)?;
+                let line = snippet.data.lines().nth(start_line)?;
+
+                Some(Match {
+                    repo_ref: &file.repo_ref,
+                    relative_path: &file.relative_path,
+                    start_line: snippet.line_range.start + start_line + 1,
+                    start_column: start_column + 1,
+                    end_line: snippet.line_range.start + end_line + 1,
+                    end_column: end_column + 1,
+                    line,
+                })
+            })
+        })
+        .collect()
+}
+
+/// The zero-based line and column of the byte `offset` in `text`.
+fn position(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = text.get(..offset)?;
+    let line = before.matches('\n').count();
+    let column = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+        .chars()
+        .count();
+
+    Some((line, column))
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snippet::Snippet;
+
+    fn response() -> QueryResponse {
+        QueryResponse {
+            count: 1,
+            metadata: Default::default(),
+            data: vec![QueryResult::Snippets(SnippedFile {
+                relative_path: "src/main.rs".into(),
+                repo_name: "bloop".into(),
+                repo_ref: "github.com/bloopai/bloop".into(),
+                lang: Some("Rust".into()),
+                license: None,
+                snippets: vec![Snippet {
+                    data: "fn main() {\n    let key = \"ünsafe\", 1;\n}\n".into(),
+                    highlights: vec![16..19, 26..35],
+                    symbols: vec![],
+                    line_range: 9..12,
+                }],
+            })],
+            stats: Default::default(),
+        }
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(position("ab\ncd", 0), Some((0, 0)));
+        assert_eq!(position("ab\ncd", 4), Some((1, 1)));
+        assert_eq!(position("ü\nü", 5), Some((1, 1)));
+        assert_eq!(position("ü\nü", 4), None);
+        assert_eq!(position("ab", 3), None);
+    }
+
+    #[test]
+    fn exports_csv() {
+        assert_eq!(
+            csv(&response()),
+            "repo_ref,relative_path,start_line,start_column,end_line,end_column,match\n\
+             github.com/bloopai/bloop,src/main.rs,11,5,11,8,\"let key = \"\"ünsafe\"\", 1;\"\n\
+             github.com/bloopai/bloop,src/main.rs,11,15,11,23,\"let key = \"\"ünsafe\"\", 1;\"\n"
+        );
+    }
+
+    #[test]
+    fn exports_sarif() {
+        let log = sarif("key", &response());
+        let results = log["runs"][0]["results"].as_array().unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0]["locations"][0]["physicalLocation"]["region"],
+            json!({
+                "startLine": 11,
+                "startColumn": 5,
+                "endLine": 11,
+                "endColumn": 8,
+                "snippet": { "text": "    let key = \"ünsafe\", 1;" },
+            })
+        );
+        assert_eq!(results[0]["message"]["text"], "let key = \"ünsafe\", 1;");
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "src/main.rs"
+        );
+    }
+}
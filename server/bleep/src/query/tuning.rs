@@ -0,0 +1,234 @@
+//! Workspace-level tuning of lexical search.
+//!
+//! Admins can drop noise words from queries (`search_stopwords`), match terms by any of their
+//! synonyms (`search_synonyms`), and weigh matches on paths, contents and symbols against each
+//! other, as the default ranking fits some codebases poorly.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    query::parser::{Literal, Query, Target},
+    Configuration,
+};
+
+pub struct LexicalTuning {
+    stopwords: HashSet<String>,
+    /// Each term of a synonym group, mapped to all the terms of its group.
+    synonyms: HashMap<String, Vec<String>>,
+    pub path_boost: f32,
+    pub content_boost: f32,
+    pub symbol_boost: f32,
+}
+
+impl Default for LexicalTuning {
+    fn default() -> Self {
+        Self {
+            stopwords: HashSet::new(),
+            synonyms: HashMap::new(),
+            path_boost: 10.0,
+            content_boost: 1.0,
+            symbol_boost: 1.0,
+        }
+    }
+}
+
+impl LexicalTuning {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        Self::default()
+            .stopwords(&config.search_stopwords)
+            .synonyms(&config.search_synonyms)?
+            .boosts(
+                config.search_path_boost,
+                config.search_content_boost,
+                config.search_symbol_boost,
+            )
+    }
+
+    /// Words that are dropped from queries, regardless of case.
+    pub fn stopwords(mut self, words: &[String]) -> Self {
+        self.stopwords = words
+            .iter()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        self
+    }
+
+    /// Groups of interchangeable words, each formatted as `k8s,kubernetes`.
+    pub fn synonyms(mut self, groups: &[String]) -> Result<Self> {
+        self.synonyms.clear();
+        for group in groups {
+            let terms = group
+                .split(',')
+                .map(|term| term.trim().to_lowercase())
+                .filter(|term| !term.is_empty())
+                .collect::<Vec<_>>();
+
+            if terms.len() < 2 || terms.iter().any(|term| term.contains(char::is_whitespace)) {
+                bail!("invalid synonyms `{group}`, expected single words like `k8s,kubernetes`");
+            }
+
+            for term in &terms {
+                self.synonyms.insert(term.clone(), terms.clone());
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Weights of matches on paths, contents and symbols.
+    pub fn boosts(mut self, path: f32, content: f32, symbol: f32) -> Result<Self> {
+        for boost in [path, content, symbol] {
+            if !(boost.is_finite() && boost > 0.0) {
+                bail!("invalid search boost `{boost}`, expected a positive number");
+            }
+        }
+
+        self.path_boost = path;
+        self.content_boost = content;
+        self.symbol_boost = symbol;
+        Ok(self)
+    }
+
+    /// Apply stopwords and synonyms to the content targets of `queries`.
+    pub fn rewrite<'a>(&self, queries: &[Query<'a>]) -> Vec<Query<'a>> {
+        queries
+            .iter()
+            .cloned()
+            .map(|mut query| {
+                if let Some(Target::Content(lit)) = &query.target {
+                    if let Some(rewritten) = self.rewrite_literal(lit) {
+                        query.target = Some(Target::Content(rewritten));
+                    }
+                }
+
+                query
+            })
+            .collect()
+    }
+
+    /// Rewrite plain text, or return `None` if no stopwords or synonyms apply.
+    ///
+    /// Stopwords are dropped from either end of the text and become optional between other words,
+    /// while words with synonyms match any term of their group. Text made up only of stopwords is
+    /// left alone, as there would be nothing left to search for.
+    fn rewrite_literal<'a>(&self, lit: &Literal<'a>) -> Option<Literal<'a>> {
+        let Literal::Plain(text) = lit else {
+            return None;
+        };
+
+        let is_stopword = |word: &str| self.stopwords.contains(&word.to_lowercase());
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let start = words.iter().position(|word| !is_stopword(word))?;
+        let end = words.iter().rposition(|word| !is_stopword(word))? + 1;
+        let words = &words[start..end];
+
+        let has_synonyms = words
+            .iter()
+            .any(|word| self.synonyms.contains_key(&word.to_lowercase()));
+        let has_stopwords = words.iter().any(|word| is_stopword(word));
+
+        if !has_synonyms && !has_stopwords {
+            let trimmed = words.join(" ");
+            return (trimmed != text.trim()).then_some(Literal::Plain(Cow::Owned(trimmed)));
+        }
+
+        let mut regex = String::new();
+        for (i, word) in words.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "\\s+" };
+            let term = match self.synonyms.get(&word.to_lowercase()) {
+                Some(group) => {
+                    let terms = group.iter().map(|t| regex::escape(t)).collect::<Vec<_>>();
+                    format!("(?:{})", terms.join("|"))
+                }
+                None => regex::escape(word),
+            };
+
+            if is_stopword(word) {
+                regex += &format!("(?:{separator}{term})?");
+            } else {
+                regex += &format!("{separator}{term}");
+            }
+        }
+
+        Some(Literal::Regex(Cow::Owned(regex)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuning() -> LexicalTuning {
+        LexicalTuning::default()
+            .stopwords(&["the".into(), "A".into()])
+            .synonyms(&["k8s, kubernetes".into()])
+            .unwrap()
+    }
+
+    fn rewrite(text: &str) -> Option<Literal<'static>> {
+        tuning().rewrite_literal(&Literal::Plain(text.to_owned().into()))
+    }
+
+    #[test]
+    fn rewrites_stopwords() {
+        assert_eq!(rewrite("parse_query"), None);
+        assert_eq!(rewrite("the parser"), Some(Literal::Plain("parser".into())));
+        assert_eq!(
+            rewrite("open the door A"),
+            Some(Literal::Regex(r"open(?:\s+the)?\s+door".into()))
+        );
+        assert_eq!(rewrite("The a"), None);
+    }
+
+    #[test]
+    fn rewrites_synonyms() {
+        assert_eq!(
+            rewrite("K8s"),
+            Some(Literal::Regex("(?:k8s|kubernetes)".into()))
+        );
+        assert_eq!(
+            rewrite("deploy to kubernetes"),
+            Some(Literal::Regex(r"deploy\s+to\s+(?:k8s|kubernetes)".into()))
+        );
+        assert_eq!(
+            rewrite("the kubernetes.yml"),
+            Some(Literal::Plain("kubernetes.yml".into()))
+        );
+    }
+
+    #[test]
+    fn rewrites_content_targets() {
+        let queries = [
+            Query {
+                target: Some(Target::Content(Literal::Plain("k8s".into()))),
+                ..Query::default()
+            },
+            Query {
+                target: Some(Target::Symbol(Literal::Plain("k8s".into()))),
+                ..Query::default()
+            },
+        ];
+
+        let rewritten = tuning().rewrite(&queries);
+        assert_eq!(
+            rewritten[0].target,
+            Some(Target::Content(Literal::Regex("(?:k8s|kubernetes)".into())))
+        );
+        assert_eq!(rewritten[1], queries[1]);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let tuning = LexicalTuning::default;
+        assert!(tuning().synonyms(&["k8s".into()]).is_err());
+        assert!(tuning().synonyms(&["k8s,kube rnetes".into()]).is_err());
+        assert!(tuning().boosts(0.0, 1.0, 1.0).is_err());
+        assert!(tuning().boosts(10.0, f32::NAN, 1.0).is_err());
+    }
+}
@@ -4,12 +4,20 @@ use std::{
     borrow::Borrow,
     collections::HashMap,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use gix::sec::identity::Account;
 use ignore::WalkBuilder;
+use reqwest::{
+    header::{self, HeaderMap},
+    StatusCode,
+};
 use serde::{Deserialize, Serialize};
 use tracing::{error, warn};
 
@@ -20,6 +28,7 @@ use crate::{
 };
 
 pub mod github;
+pub mod gitlab;
 
 type GitCreds = Account;
 
@@ -47,6 +56,9 @@ pub(crate) enum RemoteError {
     #[error("permission denied")]
     PermissionDenied,
 
+    #[error("installation suspended")]
+    InstallationSuspended,
+
     #[error("invalid configuration; missing: {0}")]
     Configuration(&'static str),
 
@@ -93,6 +105,53 @@ pub(crate) enum RemoteError {
     GitCloneFetch(#[from] gix::clone::fetch::Error),
 }
 
+impl RemoteError {
+    /// Whether the remote rejected our credentials, as opposed to a
+    /// transient (network, rate limit, etc) failure.
+    ///
+    /// Responses of remotes are turned into `PermissionDenied` by their status, with
+    /// [`is_auth_failure_status`].
+    pub(crate) fn is_auth_failure(&self) -> bool {
+        matches!(
+            self,
+            RemoteError::PermissionDenied | RemoteError::InstallationSuspended
+        )
+    }
+
+    /// Whether the GitHub App installation was suspended, which can only
+    /// be undone by an administrator of the organization.
+    pub(crate) fn is_suspended(&self) -> bool {
+        match self {
+            RemoteError::InstallationSuspended => true,
+            RemoteError::GitHub(octocrab::Error::GitHub { source, .. }) => {
+                source.message == SUSPENDED_MESSAGE
+            }
+            _ => false,
+        }
+    }
+}
+
+const SUSPENDED_MESSAGE: &str = "This installation has been suspended";
+
+/// Whether an HTTP response means that the remote rejected our credentials.
+///
+/// Remotes also answer `403 Forbidden` when a rate limit is exceeded, which only their rate limit
+/// headers tell apart.
+pub(crate) fn is_auth_failure_status(status: StatusCode, headers: &HeaderMap) -> bool {
+    let rate_limited = || {
+        headers.contains_key(header::RETRY_AFTER)
+            || ["x-ratelimit-remaining", "ratelimit-remaining"]
+                .iter()
+                .any(|name| headers.get(*name).is_some_and(|v| v == "0"))
+    };
+
+    match status {
+        StatusCode::UNAUTHORIZED => true,
+        StatusCode::FORBIDDEN => !rate_limited(),
+        _ => false,
+    }
+}
+
 impl From<&RemoteError> for SyncStatus {
     fn from(value: &RemoteError) -> Self {
         SyncStatus::Error {
@@ -266,12 +325,150 @@ impl From<BackendCredential> for BackendEntry {
     }
 }
 
+/// Health of the credentials for a backend, as reported to clients.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub(crate) enum CredentialStatus {
+    /// The remote repeatedly rejected the stored credentials, which
+    /// have been removed. The user needs to log in again.
+    ReauthenticationRequired { backend: Backend },
+    /// The credentials expire soon, and could not be renewed.
+    ExpiresSoon {
+        backend: Backend,
+        expiry: DateTime<Utc>,
+    },
+    /// Renewing the credentials failed several times in a row.
+    RefreshFailing { backend: Backend, failures: usize },
+    /// The GitHub App installation was suspended by an administrator
+    /// of the organization.
+    InstallationSuspended { backend: Backend },
+}
+
+impl CredentialStatus {
+    fn backend(&self) -> &Backend {
+        match self {
+            Self::ReauthenticationRequired { backend }
+            | Self::ExpiresSoon { backend, .. }
+            | Self::RefreshFailing { backend, .. }
+            | Self::InstallationSuspended { backend } => backend,
+        }
+    }
+
+    /// Whether both statuses report the same problem, possibly with different details.
+    fn same_problem(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+            && self.backend() == other.backend()
+    }
+}
+
+/// A credential problem that the user is notified about until they acknowledge it.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct CredentialNotification {
+    pub(crate) id: u64,
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub(crate) status: CredentialStatus,
+    #[serde(skip)]
+    acknowledged: bool,
+}
+
+#[derive(Clone)]
+struct CredentialStatusStream {
+    sender: tokio::sync::broadcast::Sender<CredentialNotification>,
+
+    /// Problems that are still current, including acknowledged ones, so
+    /// that they are not reported again until they are resolved.
+    notifications: Arc<std::sync::Mutex<Vec<CredentialNotification>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Default for CredentialStatusStream {
+    fn default() -> Self {
+        Self {
+            sender: tokio::sync::broadcast::channel(16).0,
+            notifications: Arc::default(),
+            next_id: Arc::default(),
+        }
+    }
+}
+
+impl CredentialStatusStream {
+    fn notify(&self, status: CredentialStatus) {
+        let mut notifications = self.notifications.lock().unwrap();
+
+        let notification = match notifications
+            .iter_mut()
+            .find(|n| n.status.same_problem(&status))
+        {
+            // the details of a problem that was already reported are
+            // kept up to date, but only reported again if they change
+            // before the user acknowledges them
+            Some(existing) => {
+                if existing.status == status {
+                    return;
+                }
+
+                existing.status = status;
+                if existing.acknowledged {
+                    return;
+                }
+
+                existing.clone()
+            }
+            None => {
+                let notification = CredentialNotification {
+                    id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                    created_at: Utc::now(),
+                    status,
+                    acknowledged: false,
+                };
+
+                notifications.push(notification.clone());
+                notification
+            }
+        };
+
+        // no subscribers is not an error
+        _ = self.sender.send(notification);
+    }
+
+    fn resolve(&self, resolved: impl Fn(&CredentialStatus) -> bool) {
+        self.notifications
+            .lock()
+            .unwrap()
+            .retain(|n| !resolved(&n.status));
+    }
+
+    fn pending(&self) -> Vec<CredentialNotification> {
+        self.notifications
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|n| !n.acknowledged)
+            .cloned()
+            .collect()
+    }
+
+    fn acknowledge(&self, id: u64) -> bool {
+        self.notifications
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|n| n.id == id)
+            .map(|n| n.acknowledged = true)
+            .is_some()
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Backends {
     /// If the environment is a Tauri app, or auth is instance-wide,
     /// This will refresh the correct user.
     authenticated_user: Arc<std::sync::RwLock<Option<String>>>,
     backends: Arc<scc::HashMap<Backend, BackendEntry>>,
+
+    #[serde(skip)]
+    status: CredentialStatusStream,
 }
 
 impl From<HashMap<Backend, BackendCredential>> for Backends {
@@ -284,6 +481,7 @@ impl From<HashMap<Backend, BackendCredential>> for Backends {
         Self {
             backends,
             authenticated_user: Arc::default(),
+            status: Default::default(),
         }
     }
 }
@@ -297,11 +495,56 @@ impl Backends {
         self.backends.remove(backend.borrow()).map(|(_, v)| v.inner)
     }
 
+    /// Remove the credentials for `backend`, and notify clients that
+    /// they need to authenticate again.
+    pub(crate) fn invalidate(&self, backend: Backend) -> Option<BackendCredential> {
+        let removed = self.remove(&backend);
+
+        if removed.is_some() {
+            self.notify(CredentialStatus::ReauthenticationRequired { backend });
+        }
+
+        removed
+    }
+
+    pub(crate) fn subscribe_status(
+        &self,
+    ) -> tokio::sync::broadcast::Receiver<CredentialNotification> {
+        self.status.sender.subscribe()
+    }
+
+    /// Notify clients of a problem with the credentials.
+    ///
+    /// A problem is only reported once until it is resolved, even if it
+    /// persists.
+    pub(crate) fn notify(&self, status: CredentialStatus) {
+        self.status.notify(status)
+    }
+
+    /// Forget the problems for which `resolved` is true, so that they are
+    /// reported again if they reoccur.
+    pub(crate) fn resolve(&self, resolved: impl Fn(&CredentialStatus) -> bool) {
+        self.status.resolve(resolved)
+    }
+
+    /// Notifications that the user has not acknowledged yet, oldest first.
+    pub(crate) fn notifications(&self) -> Vec<CredentialNotification> {
+        self.status.pending()
+    }
+
+    /// Stop notifying the user about a problem. Returns `false` if there
+    /// is no such notification.
+    pub(crate) fn acknowledge(&self, id: u64) -> bool {
+        self.status.acknowledge(id)
+    }
+
     pub(crate) fn github(&self) -> Option<github::State> {
-        self.backends.read(&Backend::Github, |_, v| {
-            let BackendCredential::Github(ref github) = v.inner;
-            github.clone()
-        })
+        self.backends
+            .read(&Backend::Github, |_, v| match v.inner {
+                BackendCredential::Github(ref github) => Some(github.clone()),
+                _ => None,
+            })
+            .flatten()
     }
 
     pub(crate) fn set_github(&self, gh: impl Into<github::State>) {
@@ -320,6 +563,25 @@ impl Backends {
             .read(&Backend::Github, |_, v| v.updated.clone())
     }
 
+    pub(crate) fn gitlab(&self) -> Option<gitlab::State> {
+        self.backends
+            .read(&Backend::GitLab, |_, v| match v.inner {
+                BackendCredential::GitLab(ref gitlab) => Some(gitlab.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn set_gitlab(&self, gitlab: gitlab::State) {
+        self.backends
+            .entry(Backend::GitLab)
+            .and_modify(|existing| {
+                existing.inner = BackendCredential::GitLab(gitlab.clone());
+                _ = existing.updated_tx.send(());
+            })
+            .or_insert_with(|| BackendCredential::GitLab(gitlab).into());
+    }
+
     pub(crate) async fn remove_user(&self) {
         *self.authenticated_user.write().unwrap() = None;
     }
@@ -336,29 +598,27 @@ impl Backends {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum BackendCredential {
     Github(github::State),
+    GitLab(gitlab::State),
 }
 
 impl BackendCredential {
     #[tracing::instrument(fields(repo=%reporef), skip_all)]
     pub(crate) async fn git_sync(&self, reporef: &RepoRef, repo: Repository) -> Result<SyncStatus> {
-        use BackendCredential::*;
-        let Github(gh) = self;
-
         let synced = if repo.last_index_unix_secs == 0 && repo.disk_path.exists() {
             // it is possible syncing was killed, but the repo is
             // intact. pull if the dir exists, then quietly revert
             // to cloning if that fails
-            if let Ok(success) = gh.auth.pull_repo(&repo).await {
+            if let Ok(success) = self.pull_repo(&repo).await {
                 Ok(success)
             } else {
-                gh.auth.clone_repo(&repo).await
+                self.clone_repo(&repo).await
             }
         } else if repo.last_index_unix_secs == 0 {
-            gh.auth.clone_repo(&repo).await
+            self.clone_repo(&repo).await
         } else {
-            let pulled = gh.auth.pull_repo(&repo).await;
+            let pulled = self.pull_repo(&repo).await;
             if pulled.is_err() {
-                gh.auth.clone_repo(&repo).await
+                self.clone_repo(&repo).await
             } else {
                 pulled
             }
@@ -366,4 +626,71 @@ impl BackendCredential {
 
         synced.map(|_| SyncStatus::Queued)
     }
+
+    async fn clone_repo(&self, repo: &Repository) -> Result<()> {
+        match self {
+            BackendCredential::Github(gh) => gh.auth.clone_repo(repo).await,
+            BackendCredential::GitLab(gl) => gl.clone_repo(repo).await,
+        }
+    }
+
+    async fn pull_repo(&self, repo: &Repository) -> Result<()> {
+        match self {
+            BackendCredential::Github(gh) => gh.auth.pull_repo(repo).await,
+            BackendCredential::GitLab(gl) => gl.pull_repo(repo).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifies_each_problem_until_resolved() {
+        let stream = CredentialStatusStream::default();
+        let mut receiver = stream.sender.subscribe();
+        let failing = |failures| CredentialStatus::RefreshFailing {
+            backend: Backend::Github,
+            failures,
+        };
+
+        stream.notify(failing(3));
+        stream.notify(failing(3));
+        stream.notify(failing(4));
+        assert_eq!(receiver.try_recv().unwrap().status, failing(3));
+        assert_eq!(receiver.try_recv().unwrap().status, failing(4));
+        assert!(receiver.try_recv().is_err());
+
+        let id = stream.pending()[0].id;
+        assert!(stream.acknowledge(id));
+        assert!(!stream.acknowledge(id + 1));
+        assert!(stream.pending().is_empty());
+
+        // acknowledged problems are not reported again while they persist
+        stream.notify(failing(5));
+        assert!(receiver.try_recv().is_err());
+
+        stream.resolve(|status| matches!(status, CredentialStatus::RefreshFailing { .. }));
+        stream.notify(failing(3));
+        assert_eq!(receiver.try_recv().unwrap().status, failing(3));
+        assert_eq!(stream.pending().len(), 1);
+    }
+
+    #[test]
+    fn auth_failures_by_status() {
+        let rate_limited = HeaderMap::from_iter([(
+            header::HeaderName::from_static("x-ratelimit-remaining"),
+            header::HeaderValue::from_static("0"),
+        )]);
+        let none = HeaderMap::new();
+        let fails =
+            |status: StatusCode, headers: &HeaderMap| is_auth_failure_status(status, headers);
+
+        assert!(fails(StatusCode::UNAUTHORIZED, &none));
+        assert!(fails(StatusCode::FORBIDDEN, &none));
+        assert!(!fails(StatusCode::FORBIDDEN, &rate_limited));
+        assert!(!fails(StatusCode::TOO_MANY_REQUESTS, &none));
+        assert!(!fails(StatusCode::INTERNAL_SERVER_ERROR, &none));
+    }
 }
@@ -1,9 +1,15 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
 use chrono::{DateTime, Utc};
 use jsonwebtoken::EncodingKey;
 use octocrab::{
     models::{Installation, InstallationToken},
     Octocrab,
 };
+use reqwest::{header, StatusCode};
 
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
@@ -59,8 +65,13 @@ impl State {
     }
 
     /// Get a representative list of repositories currently accessible
-    pub async fn current_repo_list(&self) -> Result<Vec<octocrab::models::Repository>> {
-        self.auth.list_repos().await
+    ///
+    /// Pages that haven't changed since they were last listed are served from `cache`.
+    pub async fn current_repo_list(
+        &self,
+        cache: &ListCache,
+    ) -> Result<Vec<octocrab::models::Repository>> {
+        self.auth.list_repos(cache).await
     }
 
     /// Create a new object with the updated repositories list
@@ -75,6 +86,15 @@ impl State {
     }
 }
 
+/// The topics of a GitHub repository, in lowercase.
+pub(crate) fn topics(repo: &octocrab::models::Repository) -> Vec<String> {
+    repo.topics
+        .iter()
+        .flatten()
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum Auth {
     OAuth(CognitoGithubTokenBundle),
@@ -155,19 +175,20 @@ impl Auth {
     }
 
     fn git_cred(&self) -> GitCreds {
+        GitCreds {
+            username: "x-access-token".into(),
+            password: self.access_token().into(),
+        }
+    }
+
+    fn access_token(&self) -> &str {
         use Auth::*;
         match self {
             OAuth(CognitoGithubTokenBundle {
                 github_access_token,
                 ..
-            }) => GitCreds {
-                username: "x-access-token".into(),
-                password: github_access_token.into(),
-            },
-            App { token, .. } => GitCreds {
-                username: "x-access-token".into(),
-                password: token.expose_secret().into(),
-            },
+            }) => github_access_token,
+            App { token, .. } => token.expose_secret(),
         }
     }
 
@@ -192,42 +213,159 @@ impl Auth {
         }
     }
 
-    async fn list_repos(&self) -> Result<Vec<octocrab::models::Repository>> {
-        let gh_client = self.client().expect("failed to build github client");
+    async fn list_repos(&self, cache: &ListCache) -> Result<Vec<octocrab::models::Repository>> {
+        let endpoint = match self {
+            Auth::OAuth { .. } => format!("{GITHUB_API_URL}/user/repos"),
+            Auth::App { org, .. } => format!("{GITHUB_API_URL}/orgs/{org}/repos"),
+        };
+
         let mut results = vec![];
         for page in 1.. {
-            let mut resp = match self {
-                remotes::github::Auth::OAuth { .. } => {
-                    gh_client
-                        .current()
-                        .list_repos_for_authenticated_user()
-                        .per_page(100)
-                        .page(page)
-                        .send()
-                        .await
-                }
-                remotes::github::Auth::App { ref org, .. } => {
-                    gh_client
-                        .orgs(org)
-                        .list_repos()
-                        .per_page(100)
-                        .page(page)
-                        .send()
-                        .await
-                }
-            }?;
-
-            if resp.items.is_empty() {
+            let url = format!("{endpoint}?per_page=100&page={page}");
+            let repos = cache.get(&url, self.access_token()).await?;
+
+            if repos.is_empty() {
                 break;
             }
 
-            results.extend(resp.take_items())
+            results.extend(repos)
         }
 
         Ok(results)
     }
 }
 
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Pages of GitHub repository lists, with the ETags they were returned with.
+///
+/// Pages are requested with the ETag of their last response, and GitHub doesn't count requests
+/// that are answered with `304 Not Modified` against the rate limit. So polling lists that didn't
+/// change is almost free.
+///
+/// Pages are keyed by URL only. An ETag only matches a response with the same contents, so the
+/// cached page of another user is never served.
+pub(crate) struct ListCache {
+    http: reqwest::Client,
+    pages: Mutex<HashMap<String, Page>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Page {
+    etag: String,
+    repos: Vec<octocrab::models::Repository>,
+}
+
+impl Default for ListCache {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .user_agent(concat!("bloop/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .expect("failed to build http client"),
+            pages: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+}
+
+impl ListCache {
+    /// The number of pages served from the cache, and the number fetched from GitHub.
+    pub(crate) fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    async fn get(&self, url: &str, token: &str) -> Result<Vec<octocrab::models::Repository>> {
+        let etag = self.etag(url);
+
+        let mut request = self
+            .http
+            .get(url)
+            .bearer_auth(token)
+            .header(header::ACCEPT, "application/vnd.github+json");
+        if let Some(etag) = &etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+
+        let response = request.send().await.map_err(anyhow::Error::from)?;
+        let status = response.status();
+
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(repos) = self.cached(url, etag.as_deref()) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(repos);
+            }
+        }
+
+        if !status.is_success() {
+            #[derive(Deserialize, Default)]
+            struct ErrorBody {
+                message: String,
+            }
+
+            let auth_failure = is_auth_failure_status(status, response.headers());
+            let message = response
+                .json::<ErrorBody>()
+                .await
+                .unwrap_or_default()
+                .message;
+            return Err(if message == SUSPENDED_MESSAGE {
+                RemoteError::InstallationSuspended
+            } else if auth_failure {
+                RemoteError::PermissionDenied
+            } else {
+                anyhow::anyhow!("failed to list repositories ({status}): {message}").into()
+            });
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned);
+        let repos = response
+            .json::<Vec<octocrab::models::Repository>>()
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        let mut pages = self.pages.lock().unwrap();
+        match etag {
+            Some(etag) => {
+                pages.insert(
+                    url.to_owned(),
+                    Page {
+                        etag,
+                        repos: repos.clone(),
+                    },
+                );
+            }
+            None => {
+                pages.remove(url);
+            }
+        }
+
+        Ok(repos)
+    }
+
+    fn etag(&self, url: &str) -> Option<String> {
+        let pages = self.pages.lock().unwrap();
+        pages.get(url).map(|page| page.etag.clone())
+    }
+
+    /// The cached page at `url`, if it still has `etag`.
+    fn cached(&self, url: &str, etag: Option<&str>) -> Option<Vec<octocrab::models::Repository>> {
+        let pages = self.pages.lock().unwrap();
+        let page = pages.get(url)?;
+        (Some(page.etag.as_str()) == etag).then(|| page.repos.clone())
+    }
+}
+
 pub(crate) async fn refresh_github_installation_token(app: &Application) -> Result<()> {
     let privkey = std::fs::read(
         app.config
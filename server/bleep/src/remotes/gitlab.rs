@@ -0,0 +1,340 @@
+//! GitLab, on gitlab.com or self-hosted, through its REST API.
+//!
+//! Users connect with an access token, or through OAuth with an application registered on the
+//! instance. OAuth access tokens expire after two hours, and are refreshed as GitLab is polled.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::repo::{GitRemote, RepoRemote, Repository};
+
+use super::*;
+
+static HTTP: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent(concat!("bloop/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("failed to build http client")
+});
+
+/// The number of projects listed per request, which is the most GitLab allows.
+const PAGE_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    /// Base URL of the GitLab instance, like `https://gitlab.com`.
+    pub url: String,
+    pub auth: Auth,
+    #[serde(skip)]
+    pub repositories: Arc<Vec<Project>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum Auth {
+    /// A personal, group or project access token.
+    Token {
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        token: SecretString,
+    },
+    OAuth {
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        access_token: SecretString,
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        refresh_token: SecretString,
+        expiry: DateTime<Utc>,
+    },
+}
+
+/// A project, as listed by the GitLab API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Project {
+    pub id: u64,
+    pub path_with_namespace: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    pub http_url_to_repo: String,
+    pub ssh_url_to_repo: String,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+/// A new or refreshed token, as returned by the OAuth token endpoint.
+#[derive(Deserialize)]
+struct OAuthToken {
+    access_token: String,
+    refresh_token: String,
+    expires_in: i64,
+}
+
+impl From<OAuthToken> for Auth {
+    fn from(token: OAuthToken) -> Self {
+        Auth::OAuth {
+            access_token: token.access_token.into(),
+            refresh_token: token.refresh_token.into(),
+            expiry: Utc::now() + chrono::Duration::seconds(token.expires_in),
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn new(url: &str, auth: Auth) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            auth,
+            repositories: Arc::default(),
+        }
+    }
+
+    /// The host of the instance, which the names of its repositories start with.
+    pub(crate) fn host(&self) -> &str {
+        host(&self.url)
+    }
+
+    pub(crate) fn reporef(&self, project: &Project) -> std::result::Result<RepoRef, RepoError> {
+        RepoRef::new(
+            Backend::GitLab,
+            &format!("{}/{}", self.host(), project.path_with_namespace),
+        )
+    }
+
+    pub(crate) fn expiry(&self) -> Option<DateTime<Utc>> {
+        match self.auth {
+            Auth::OAuth { expiry, .. } => Some(expiry),
+            Auth::Token { .. } => None,
+        }
+    }
+
+    /// The username that the credentials belong to.
+    pub(crate) async fn validate(&self) -> Result<String> {
+        #[derive(Deserialize)]
+        struct User {
+            username: String,
+        }
+
+        let response = self.get("user").send().await.map_err(anyhow::Error::from)?;
+        let user = checked(response)
+            .await?
+            .json::<User>()
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        Ok(user.username)
+    }
+
+    /// Every project that the user is a member of, except archived ones.
+    pub(crate) async fn current_repo_list(&self) -> Result<Vec<Project>> {
+        let per_page = PAGE_SIZE.to_string();
+
+        let mut results = vec![];
+        for page in 1.. {
+            let page = page.to_string();
+            let response = self
+                .get("projects")
+                .query(&[
+                    ("membership", "true"),
+                    ("archived", "false"),
+                    ("order_by", "id"),
+                    ("per_page", per_page.as_str()),
+                    ("page", page.as_str()),
+                ])
+                .send()
+                .await
+                .map_err(anyhow::Error::from)?;
+
+            let projects = checked(response)
+                .await?
+                .json::<Vec<Project>>()
+                .await
+                .map_err(anyhow::Error::from)?;
+
+            let last = projects.len() < PAGE_SIZE;
+            results.extend(projects);
+
+            if last {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Create a new object with the updated repositories list
+    pub(crate) fn update_repositories(self, repos: Vec<Project>) -> Self {
+        Self {
+            repositories: repos.into(),
+            ..self
+        }
+    }
+
+    /// Whether the access token expires in the next 10 minutes, and should be refreshed.
+    pub(crate) fn needs_refresh(&self) -> bool {
+        self.expiry().map_or(false, |expiry| {
+            expiry < Utc::now() + chrono::Duration::minutes(10)
+        })
+    }
+
+    /// Exchange the refresh token of OAuth credentials for a new access token.
+    pub(crate) async fn refresh(&self, client_id: &str, client_secret: &str) -> Result<Self> {
+        let Auth::OAuth { refresh_token, .. } = &self.auth else {
+            return Err(RemoteError::NotSupported("refreshing a gitlab token"));
+        };
+
+        let auth = request_token(
+            &self.url,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.expose_secret()),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+            ],
+        )
+        .await?;
+
+        Ok(Self {
+            auth,
+            ..self.clone()
+        })
+    }
+
+    pub(crate) async fn clone_repo(&self, repo: &Repository) -> Result<()> {
+        self.check_repo(repo).await?;
+        git_clone(self.git_cred(), &repo.remote.to_string(), &repo.disk_path).await
+    }
+
+    pub(crate) async fn pull_repo(&self, repo: &Repository) -> Result<()> {
+        self.check_repo(repo).await?;
+        git_pull(self.git_cred(), repo).await
+    }
+
+    async fn check_repo(&self, repo: &Repository) -> Result<()> {
+        let RepoRemote::Git(GitRemote { ref address, .. }) = repo.remote else {
+            return Err(RemoteError::NotSupported("gitlab without git backend"));
+        };
+
+        let response = self
+            .get(&format!("projects/{}", address.replace('/', "%2F")))
+            .send()
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        checked(response).await.map(|_| ())
+    }
+
+    fn git_cred(&self) -> GitCreds {
+        GitCreds {
+            username: "oauth2".into(),
+            password: self.access_token().into(),
+        }
+    }
+
+    fn access_token(&self) -> &str {
+        match &self.auth {
+            Auth::Token { token } => token.expose_secret(),
+            Auth::OAuth { access_token, .. } => access_token.expose_secret(),
+        }
+    }
+
+    fn get(&self, endpoint: &str) -> reqwest::RequestBuilder {
+        HTTP.get(format!("{}/api/v4/{endpoint}", self.url))
+            .bearer_auth(self.access_token())
+    }
+}
+
+/// Exchange the code of a completed OAuth authorization for credentials.
+pub(crate) async fn exchange_code(
+    url: &str,
+    client_id: &str,
+    client_secret: &str,
+    code: &str,
+    redirect_uri: &str,
+) -> Result<Auth> {
+    request_token(
+        url,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("redirect_uri", redirect_uri),
+        ],
+    )
+    .await
+}
+
+async fn request_token(url: &str, form: &[(&str, &str)]) -> Result<Auth> {
+    let response = HTTP
+        .post(format!("{}/oauth/token", url.trim_end_matches('/')))
+        .form(form)
+        .send()
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    let token = checked(response)
+        .await?
+        .json::<OAuthToken>()
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    Ok(token.into())
+}
+
+/// The host of a GitLab instance URL, with its port if it has one.
+pub(crate) fn host(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    url.split('/').next().unwrap_or(url)
+}
+
+/// Turn error responses of the GitLab API into errors.
+async fn checked(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let auth_failure = is_auth_failure_status(status, response.headers());
+    let body = response.text().await.unwrap_or_default();
+    Err(match status {
+        _ if auth_failure => RemoteError::PermissionDenied,
+        StatusCode::NOT_FOUND => RemoteError::RemoteNotFound,
+        // An invalid or revoked refresh token
+        StatusCode::BAD_REQUEST if body.contains("invalid_grant") => RemoteError::PermissionDenied,
+        _ => anyhow::anyhow!("gitlab request failed ({status}): {body}").into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reporefs_start_with_the_host() {
+        let state = State::new(
+            "https://gitlab.example.com:8443/",
+            Auth::Token {
+                token: "glpat-token".to_owned().into(),
+            },
+        );
+        let project = serde_json::from_str::<Project>(
+            r#"{
+                "id": 42,
+                "path_with_namespace": "platform/billing/api",
+                "description": null,
+                "http_url_to_repo": "https://gitlab.example.com:8443/platform/billing/api.git",
+                "ssh_url_to_repo": "git@gitlab.example.com:platform/billing/api.git",
+                "last_activity_at": "2023-09-14T08:10:24.123Z"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(state.host(), "gitlab.example.com:8443");
+        assert_eq!(
+            state.reporef(&project).unwrap().to_string(),
+            "gitlab/gitlab.example.com:8443/platform/billing/api"
+        );
+        assert_eq!(host("https://gitlab.com"), "gitlab.com");
+        assert_eq!(state.expiry(), None);
+    }
+}
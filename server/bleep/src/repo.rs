@@ -14,6 +14,7 @@ use tracing::debug;
 use crate::state::get_relative_path;
 
 pub(crate) mod iterator;
+pub(crate) mod license;
 use iterator::language;
 
 // Types of repo
@@ -22,6 +23,9 @@ use iterator::language;
 pub enum Backend {
     Local,
     Github,
+    /// gitlab.com or a self-hosted GitLab instance. Names start with the host of the instance.
+    #[serde(rename = "gitlab")]
+    GitLab,
 }
 
 // Repository identifier
@@ -40,6 +44,13 @@ impl RepoRef {
                 backend,
                 name: name.as_ref().to_owned(),
             }),
+            GitLab => match name.as_ref().split_once('/') {
+                Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok(RepoRef {
+                    backend,
+                    name: name.as_ref().to_owned(),
+                }),
+                _ => Err(RepoError::InvalidPath),
+            },
             Local => {
                 let path = Path::new(name.as_ref());
 
@@ -67,6 +78,7 @@ impl RepoRef {
         let refstr = components.join("/");
         let pathstr = match refstr.trim_start_matches('/').split_once('/') {
             Some(("github.com", name)) => return RepoRef::new(Backend::Github, name),
+            Some(("gitlab", name)) => return RepoRef::new(Backend::GitLab, name),
             Some(("local", name)) => name,
             _ => &refstr,
         };
@@ -94,6 +106,7 @@ impl RepoRef {
     pub fn indexed_name(&self) -> String {
         // Local repos indexed as: dirname
         // Github repos indexed as: github.com/org/repo
+        // GitLab repos indexed as: gitlab.example.com/group/project
         match self.backend {
             Backend::Local => Path::new(&self.name)
                 .file_name()
@@ -101,6 +114,7 @@ impl RepoRef {
                 .to_string_lossy()
                 .into(),
             Backend::Github => format!("{}", self),
+            Backend::GitLab => self.name.to_owned(),
         }
     }
 
@@ -108,6 +122,12 @@ impl RepoRef {
         match self.backend {
             // org_name/repo_name
             Backend::Github => self.name.to_owned(),
+            // group/project, without the host
+            Backend::GitLab => self
+                .name
+                .split_once('/')
+                .map_or(&*self.name, |(_, path)| path)
+                .to_owned(),
             // repo_name
             Backend::Local => self.indexed_name(),
         }
@@ -150,6 +170,8 @@ impl FromStr for RepoRef {
         match refstr.trim_start_matches('/').split_once('/') {
             // github.com/...
             Some(("github.com", name)) => RepoRef::new(Backend::Github, name),
+            // gitlab/...
+            Some(("gitlab", name)) => RepoRef::new(Backend::GitLab, name),
             // local/...
             Some(("local", name)) => RepoRef::new(Backend::Local, name),
             _ => Err(RepoError::InvalidBackend),
@@ -161,6 +183,7 @@ impl Display for RepoRef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.backend() {
             Backend::Github => write!(f, "github.com/{}", self.name()),
+            Backend::GitLab => write!(f, "gitlab/{}", self.name()),
             Backend::Local => write!(f, "local/{}", self.name()),
         }
     }
@@ -236,6 +259,37 @@ pub struct Repository {
     pub last_index_unix_secs: u64,
     pub most_common_lang: Option<String>,
     pub branch_filter: Option<BranchFilter>,
+
+    /// Tags of historical revisions that are indexed alongside the branches, under the
+    /// `rev:<tag>` branch names. Empty unless configured for this repository.
+    #[serde(default)]
+    pub revisions: Vec<String>,
+
+    /// Pinned repositories are not polled for updates, and only sync when explicitly asked to.
+    #[serde(default)]
+    pub pinned: bool,
+
+    /// Paths relative to the repository root that are not indexed, along with everything
+    /// below them.
+    #[serde(default)]
+    pub excluded_paths: Vec<String>,
+
+    /// The description of the repository on GitHub, refreshed whenever GitHub is polled.
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// The topics of the repository on GitHub, in lowercase.
+    #[serde(default)]
+    pub topics: Vec<String>,
+
+    /// The id of the repository on GitHub, which is kept when it is renamed or transferred.
+    #[serde(default)]
+    pub github_id: Option<u64>,
+
+    /// Retrieval-only repositories can be searched, but their code is never sent to an LLM, so
+    /// questions about them aren't answered.
+    #[serde(default)]
+    pub retrieval_only: bool,
 }
 
 impl Repository {
@@ -274,6 +328,13 @@ impl Repository {
             remote,
             most_common_lang: None,
             branch_filter: None,
+            revisions: Vec::new(),
+            pinned: false,
+            excluded_paths: Vec::new(),
+            description: None,
+            topics: Vec::new(),
+            github_id: None,
+            retrieval_only: false,
         }
     }
 
@@ -286,14 +347,59 @@ impl Repository {
             .ok();
 
         let langs = Default::default();
+        let license = license::detect_repo(&self.disk_path);
 
         RepoMetadata {
             last_commit_unix_secs,
             langs,
+            license,
         }
         .into()
     }
 
+    /// Only use this with remote refs
+    pub(crate) fn remote_from(reporef: &RepoRef, disk_path: PathBuf) -> Self {
+        Self {
+            disk_path,
+            remote: reporef.into(),
+            sync_status: SyncStatus::Queued,
+            last_index_unix_secs: 0,
+            last_commit_unix_secs: 0,
+            most_common_lang: None,
+            branch_filter: None,
+            revisions: Vec::new(),
+            pinned: false,
+            excluded_paths: Vec::new(),
+            description: None,
+            topics: Vec::new(),
+            github_id: None,
+            retrieval_only: false,
+        }
+    }
+
+    /// A single line with the description and topics of the repository, if it has either.
+    pub(crate) fn about(&self) -> Option<String> {
+        let description = self
+            .description
+            .as_deref()
+            .map(|d| d.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|d| !d.is_empty());
+        let topics =
+            (!self.topics.is_empty()).then(|| format!("topics: {}", self.topics.join(", ")));
+
+        match (description, topics) {
+            (Some(description), Some(topics)) => Some(format!("{description} ({topics})")),
+            (description, topics) => description.or(topics),
+        }
+    }
+
+    /// Whether `relative_path` is excluded from indexing by `excluded_paths`.
+    pub(crate) fn is_excluded(&self, relative_path: &Path) -> bool {
+        self.excluded_paths
+            .iter()
+            .any(|excluded| relative_path.starts_with(excluded.trim_end_matches('/')))
+    }
+
     /// Marks the repository for removal on the next sync
     /// Does not initiate a new sync.
     pub(crate) fn mark_removed(&mut self) {
@@ -337,6 +443,8 @@ fn get_unix_time(time: SystemTime) -> u64 {
 pub struct RepoMetadata {
     pub last_commit_unix_secs: Option<u64>,
     pub langs: language::LanguageInfo,
+    /// The SPDX expression of the repository license, if one was found.
+    pub license: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Hash)]
@@ -416,6 +524,19 @@ impl<T: AsRef<RepoRef>> From<T> for RepoRemote {
                 host: "github.com".to_owned(),
                 address: name.to_owned(),
             }),
+            RepoRef {
+                backend: Backend::GitLab,
+                name,
+            } => {
+                let (host, address) = name
+                    .split_once('/')
+                    .expect("gitlab names start with a host");
+                RepoRemote::Git(GitRemote {
+                    protocol: GitProtocol::Https,
+                    host: host.to_owned(),
+                    address: address.to_owned(),
+                })
+            }
             RepoRef {
                 backend: Backend::Local,
                 name: _name,
@@ -521,6 +642,67 @@ mod test {
         }
     }
 
+    #[test]
+    fn gitlab_reporef() {
+        let reporef = "gitlab/gitlab.example.com/platform/billing/api"
+            .parse::<RepoRef>()
+            .unwrap();
+
+        assert_eq!(
+            reporef,
+            RepoRef::new(Backend::GitLab, "gitlab.example.com/platform/billing/api").unwrap()
+        );
+        assert_eq!(
+            reporef.indexed_name(),
+            "gitlab.example.com/platform/billing/api"
+        );
+        assert_eq!(reporef.display_name(), "platform/billing/api");
+        assert_eq!(
+            RepoRemote::from(&reporef).to_string(),
+            "https://gitlab.example.com/platform/billing/api.git"
+        );
+        assert_eq!(
+            serde_json::to_string(&reporef).unwrap(),
+            r#""gitlab/gitlab.example.com/platform/billing/api""#
+        );
+
+        assert!("gitlab/gitlab.example.com".parse::<RepoRef>().is_err());
+    }
+
+    #[test]
+    fn excluded_paths() {
+        let mut repo = Repository::local_from(&RepoRef::new(Backend::Local, "/tmp/repo").unwrap());
+        repo.excluded_paths = vec!["vendor/".into(), "docs/generated".into()];
+
+        assert!(repo.is_excluded(Path::new("vendor")));
+        assert!(repo.is_excluded(Path::new("vendor/lib/mod.rs")));
+        assert!(repo.is_excluded(Path::new("docs/generated/api.md")));
+        assert!(!repo.is_excluded(Path::new("docs/guide.md")));
+        assert!(!repo.is_excluded(Path::new("vendored/mod.rs")));
+        assert!(!repo.is_excluded(Path::new("src/vendor/mod.rs")));
+    }
+
+    #[test]
+    fn about() {
+        let mut repo = Repository::remote_from(
+            &RepoRef::new(Backend::Github, "bloopai/bloop").unwrap(),
+            "/tmp/bloop".into(),
+        );
+        assert_eq!(repo.about(), None);
+
+        repo.topics = vec!["rust".into(), "search".into()];
+        assert_eq!(repo.about().unwrap(), "topics: rust, search");
+
+        repo.description = Some("Conversational code search\n engine".into());
+        assert_eq!(
+            repo.about().unwrap(),
+            "Conversational code search engine (topics: rust, search)"
+        );
+
+        repo.topics.clear();
+        assert_eq!(repo.about().unwrap(), "Conversational code search engine");
+    }
+
     #[test]
     fn serialize_reporef() {
         assert_eq!(
@@ -1,4 +1,4 @@
-use crate::repo::RepoRef;
+use crate::{query::parser::revision_branch, repo::RepoRef};
 
 use super::*;
 
@@ -41,7 +41,9 @@ fn human_readable_branch_name(r: &gix::Reference<'_>) -> String {
 
 pub struct GitWalker {
     git: ThreadSafeRepository,
-    entries: HashMap<(String, FileType, gix::ObjectId), BTreeSet<String>>,
+    /// Entries are keyed by whether they come from a historical revision, along with their path
+    /// and object, so that revisions never share documents with branches.
+    entries: HashMap<(String, FileType, gix::ObjectId, bool), BTreeSet<String>>,
 }
 
 impl GitWalker {
@@ -49,6 +51,7 @@ impl GitWalker {
         reporef: &RepoRef,
         dir: impl AsRef<Path>,
         filter: impl Into<Option<BranchFilter>>,
+        revisions: &[String],
     ) -> Result<Self> {
         let root_dir = dir.as_ref();
         let branches = filter.into().unwrap_or_default();
@@ -128,9 +131,40 @@ impl GitWalker {
                 .collect()
         };
 
+        // Historical revisions are opted into by tag name, regardless of the branch filter.
+        let revision_trees = if revisions.is_empty() {
+            vec![]
+        } else {
+            refs.tags()?
+                .filter_map(Result::ok)
+                .filter_map(|r| {
+                    let name = human_readable_branch_name(&r);
+                    if !revisions.contains(&name) {
+                        return None;
+                    }
+
+                    Some((
+                        revision_branch(&name),
+                        r.into_fully_peeled_id()
+                            .ok()?
+                            .object()
+                            .ok()?
+                            .peel_to_tree()
+                            .ok()?,
+                    ))
+                })
+                .collect::<Vec<_>>()
+        };
+
         let entries = trees
             .into_iter()
-            .flat_map(|(is_head, branch, tree)| {
+            .map(|(is_head, branch, tree)| (is_head, false, branch, tree))
+            .chain(
+                revision_trees
+                    .into_iter()
+                    .map(|(branch, tree)| (false, true, branch, tree)),
+            )
+            .flat_map(|(is_head, is_revision, branch, tree)| {
                 let files = tree.traverse().breadthfirst.files().unwrap().into_iter();
 
                 files
@@ -140,17 +174,18 @@ impl GitWalker {
                         trace!(?strpath, ?full_path, "got path from gix");
                         (
                             is_head,
+                            is_revision,
                             branch.clone(),
                             full_path.to_string_lossy().to_string(),
                             entry.mode,
                             entry.oid,
                         )
                     })
-                    .filter(|(_, _, path, _, _)| should_index(path))
+                    .filter(|(_, _, _, path, _, _)| should_index(path))
             })
             .fold(
                 HashMap::new(),
-                |mut acc, (is_head, branch, file, mode, oid)| {
+                |mut acc, (is_head, is_revision, branch, file, mode, oid)| {
                     let kind = if mode.is_tree() {
                         FileType::Dir
                     } else if mode.is_blob() {
@@ -159,7 +194,9 @@ impl GitWalker {
                         FileType::Other
                     };
 
-                    let branches = acc.entry((file, kind, oid)).or_insert_with(BTreeSet::new);
+                    let branches = acc
+                        .entry((file, kind, oid, is_revision))
+                        .or_insert_with(BTreeSet::new);
                     if is_head {
                         branches.insert("HEAD".to_string());
                     }
@@ -182,7 +219,7 @@ impl FileSource for GitWalker {
         use rayon::prelude::*;
         self.entries
             .into_par_iter()
-            .filter_map(|((path, kind, oid), branches)| {
+            .filter_map(|((path, kind, oid, _), branches)| {
                 trace!(?path, "walking over path");
                 let git = self.git.to_thread_local();
                 let Ok(Some(object)) = git.try_find_object(oid) else {
@@ -0,0 +1,259 @@
+//! License detection for repositories and files.
+//!
+//! Licenses are identified by their SPDX expression, e.g. `MIT` or `GPL-2.0-only OR MIT`. A
+//! file's own `SPDX-License-Identifier` header takes precedence over the license of the
+//! repository it is in.
+
+use std::path::Path;
+
+use lazy_regex::regex;
+
+/// The number of lines at the top of a file that are searched for an SPDX header.
+const HEADER_LINES: usize = 30;
+
+/// The number of characters at the top of a license file that are searched for its signature.
+const SIGNATURE_CHARS: usize = 2000;
+
+/// Prefixes of the names of license files at the root of a repository, matched
+/// case-insensitively against the file stem.
+const LICENSE_FILES: &[&str] = &["license", "licence", "copying", "unlicense"];
+
+/// Phrases that identify a license text, in order of precedence. Every phrase in a list must be
+/// present near the top of the text, as license texts often mention related licenses further
+/// down.
+const SIGNATURES: &[(&str, &[&str])] = &[
+    (
+        "AGPL-3.0",
+        &["gnu affero general public license", "version 3"],
+    ),
+    (
+        "LGPL-3.0",
+        &["gnu lesser general public license", "version 3"],
+    ),
+    (
+        "LGPL-2.1",
+        &["gnu lesser general public license", "version 2.1"],
+    ),
+    ("GPL-3.0", &["gnu general public license", "version 3"]),
+    ("GPL-2.0", &["gnu general public license", "version 2"]),
+    ("MPL-2.0", &["mozilla public license", "2.0"]),
+    ("EPL-2.0", &["eclipse public license", "2.0"]),
+    ("Apache-2.0", &["apache license", "version 2.0"]),
+    (
+        "Unlicense",
+        &["free and unencumbered software released into the public domain"],
+    ),
+    (
+        "BSD-3-Clause",
+        &[
+            "redistribution and use in source and binary forms",
+            "neither the name",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["redistribution and use in source and binary forms"],
+    ),
+    (
+        "ISC",
+        &["permission to use, copy, modify, and/or distribute this software"],
+    ),
+    ("MIT", &["permission is hereby granted, free of charge"]),
+];
+
+/// License families that require derived works to be distributed under the same terms.
+const COPYLEFT: &[&str] = &[
+    "AGPL-", "GPL-", "LGPL-", "MPL-", "EPL-", "EUPL-", "CDDL-", "OSL-",
+];
+
+/// Detect the license of the repository at `disk_path`, from the license files at its root.
+///
+/// Repositories with several license files, such as `LICENSE-MIT` and `LICENSE-APACHE`, are
+/// taken to be dual licensed.
+pub fn detect_repo(disk_path: &Path) -> Option<String> {
+    let mut licenses = std::fs::read_dir(disk_path)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .path()
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map_or(false, |stem| {
+                    let stem = stem.to_ascii_lowercase();
+                    LICENSE_FILES.iter().any(|name| stem.starts_with(name))
+                })
+        })
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .filter_map(|text| identify(&text))
+        .collect::<Vec<_>>();
+
+    licenses.sort_unstable();
+    licenses.dedup();
+
+    (!licenses.is_empty()).then(|| licenses.join(" OR "))
+}
+
+/// Detect the license declared by a file, from an SPDX header near its top.
+pub fn detect_file(content: &str) -> Option<String> {
+    let header = regex!(r"SPDX-License-Identifier:\s*([^\n*]+?)\s*(?:\*/|-->)?\s*$"m);
+
+    content
+        .lines()
+        .take(HEADER_LINES)
+        .find_map(|line| header.captures(line))
+        .map(|c| c[1].trim().to_owned())
+        .filter(|expr| !expr.is_empty())
+}
+
+/// Identify a license from its full text.
+fn identify(text: &str) -> Option<&'static str> {
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .take(SIGNATURE_CHARS)
+        .collect::<String>();
+
+    SIGNATURES
+        .iter()
+        .find(|(_, phrases)| phrases.iter().all(|p| text.contains(p)))
+        .map(|(id, _)| *id)
+}
+
+/// Whether every way of complying with the license expression `expr` is copyleft.
+///
+/// For instance, `GPL-2.0-only OR MIT` can be complied with under MIT, so it is not copyleft,
+/// while `MIT AND GPL-3.0-or-later` is.
+pub fn is_copyleft(expr: &str) -> bool {
+    requires(expr, |id| {
+        COPYLEFT
+            .iter()
+            .any(|family| id.to_ascii_uppercase().starts_with(family))
+    })
+}
+
+/// Whether every way of complying with the license expression `expr` involves one of `ids`.
+///
+/// Identifiers are compared case-insensitively, and `GPL-3.0` also matches `GPL-3.0-only` and
+/// `GPL-3.0-or-later`.
+pub fn is_any_of(expr: &str, ids: &[String]) -> bool {
+    requires(expr, |id| {
+        ids.iter().any(|excluded| {
+            id.eq_ignore_ascii_case(excluded)
+                || id
+                    .to_ascii_lowercase()
+                    .strip_prefix(&excluded.to_ascii_lowercase())
+                    .map_or(false, |suffix| suffix.starts_with('-'))
+        })
+    })
+}
+
+fn requires(expr: &str, matches: impl Fn(&str) -> bool) -> bool {
+    let expr = expr.replace(['(', ')'], " ");
+
+    regex!(r"\s+OR\s+"i).split(&expr).all(|alternative| {
+        regex!(r"\s+(?:AND|WITH)\s+"i)
+            .split(alternative)
+            .map(str::trim)
+            .any(&matches)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_license_texts() {
+        // The GPL mentions the LGPL and the AGPL towards its end.
+        let gpl_full = format!(
+            "GNU GENERAL PUBLIC LICENSE Version 3{}GNU Lesser General Public License",
+            " ".repeat(SIGNATURE_CHARS)
+        );
+        assert_eq!(identify(&gpl_full), Some("GPL-3.0"));
+
+        let gpl = "GNU GENERAL PUBLIC LICENSE\n  Version 3, 29 June 2007\n\n Copyright (C) 2007";
+        let lgpl = "GNU LESSER GENERAL PUBLIC LICENSE\n Version 2.1, February 1999";
+        let mit = "MIT License\n\nPermission is hereby granted,\nfree of charge, to any person";
+
+        assert_eq!(identify(gpl), Some("GPL-3.0"));
+        assert_eq!(identify(lgpl), Some("LGPL-2.1"));
+        assert_eq!(identify(mit), Some("MIT"));
+        assert_eq!(identify("All rights reserved."), None);
+    }
+
+    #[test]
+    fn detects_spdx_headers() {
+        assert_eq!(
+            detect_file("// SPDX-License-Identifier: GPL-2.0-only\nint main() {}").as_deref(),
+            Some("GPL-2.0-only")
+        );
+        assert_eq!(
+            detect_file("/* SPDX-License-Identifier: MIT OR Apache-2.0 */\n").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(
+            detect_file("# SPDX-License-Identifier: (LGPL-2.1 AND MIT)").as_deref(),
+            Some("(LGPL-2.1 AND MIT)")
+        );
+        assert_eq!(detect_file("fn main() {}\n"), None);
+
+        let late = format!(
+            "{}// SPDX-License-Identifier: MIT",
+            "\n".repeat(HEADER_LINES)
+        );
+        assert_eq!(detect_file(&late), None);
+    }
+
+    #[test]
+    fn copyleft_expressions() {
+        assert!(is_copyleft("GPL-3.0"));
+        assert!(is_copyleft("agpl-3.0-or-later"));
+        assert!(is_copyleft("MIT AND GPL-2.0-only"));
+        assert!(is_copyleft("(LGPL-2.1 AND MIT)"));
+        assert!(!is_copyleft("GPL-2.0-only OR MIT"));
+        assert!(!is_copyleft("Apache-2.0"));
+    }
+
+    #[test]
+    fn excluded_licenses() {
+        let excluded = vec!["GPL-3.0".to_owned(), "agpl-3.0".to_owned()];
+
+        assert!(is_any_of("GPL-3.0-or-later", &excluded));
+        assert!(is_any_of("AGPL-3.0", &excluded));
+        assert!(!is_any_of("LGPL-3.0", &excluded));
+        assert!(!is_any_of("GPL-3.0 OR MIT", &excluded));
+        assert!(!is_any_of("GPL-3.00", &excluded));
+    }
+
+    #[test]
+    fn detects_repo_license_files() {
+        let dir = tempdir::TempDir::new("license").unwrap();
+        std::fs::write(
+            dir.path().join("README.md"),
+            "Permission is hereby granted, free of charge",
+        )
+        .unwrap();
+        assert_eq!(detect_repo(dir.path()), None);
+
+        std::fs::write(
+            dir.path().join("LICENSE.txt"),
+            "Apache License\nVersion 2.0, January 2004",
+        )
+        .unwrap();
+        assert_eq!(detect_repo(dir.path()).as_deref(), Some("Apache-2.0"));
+
+        std::fs::write(
+            dir.path().join("LICENSE-MIT"),
+            "Permission is hereby granted, free of charge",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_repo(dir.path()).as_deref(),
+            Some("Apache-2.0 OR MIT")
+        );
+    }
+}
@@ -1,13 +1,26 @@
-use std::{borrow::Cow, collections::HashMap, env, path::Path, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::{query::parser::SemanticQuery, Configuration};
+use crate::{
+    db::{EmbeddingReductions, SqlDb},
+    query::parser::SemanticQuery,
+    repo::RepoRef,
+    Configuration,
+};
 
 use qdrant_client::{
     prelude::{QdrantClient, QdrantClientConfig},
     qdrant::{
         point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions,
-        with_payload_selector, with_vectors_selector, CollectionOperationResponse, FieldCondition,
-        FieldType, Filter, Match, PointId, RetrievedPoint, ScoredPoint, SearchPoints, Value,
+        with_payload_selector, with_vectors_selector, CollectionOperationResponse, Condition,
+        CountPoints, FieldCondition, FieldType, Filter, Match, PayloadIncludeSelector, PointId,
+        PointStruct, RetrievedPoint, ScoredPoint, ScrollPoints, SearchParams, SearchPoints, Value,
         Vectors, WithPayloadSelector, WithVectorsSelector,
     },
 };
@@ -19,13 +32,24 @@ use tracing::{debug, info, warn};
 
 pub mod chunk;
 pub mod embedder;
+pub mod exclusion;
 pub mod execute;
+pub mod filter;
+pub mod reduction;
+pub mod reranker;
 mod schema;
+pub mod store;
 
+use chunk::{ChunkParams, ChunkStrategy, OverlapStrategy};
 pub use embedder::Embedder;
-use embedder::LocalEmbedder;
+use embedder::{EmbeddingModel, LocalEmbedder, DEFAULT_MODEL};
+use exclusion::Exclusions;
+pub use filter::SearchFilters;
+use reduction::{Method, ReducedEmbedder, Reduction};
+use reranker::Reranker;
 use schema::{create_collection, EMBEDDING_DIM};
 pub use schema::{Embedding, Payload};
+use store::{EmbeddedStore, VectorStore};
 
 #[derive(Error, Debug)]
 pub enum SemanticError {
@@ -48,11 +72,101 @@ pub enum SemanticError {
 
 #[derive(Clone)]
 pub struct Semantic {
-    qdrant: Arc<QdrantClient>,
-    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
+    /// Embedding models by name, including the default model.
+    models: Arc<HashMap<String, EmbeddingModel>>,
+    /// The model that points which don't record their model were embedded with.
+    legacy_model: String,
+    reranker: Option<Arc<Reranker>>,
+    /// Permits for the embedding batches that may be computed at once.
+    embedding_permits: Arc<tokio::sync::Semaphore>,
     pub(crate) config: Arc<Configuration>,
+    /// The dimensionality of the stored embeddings.
+    dims: usize,
+    /// Whether the collection was re-created with a different reduction or layout, and has to be
+    /// re-indexed.
+    reindex: bool,
+    /// Per-repository collections that are known to exist.
+    repo_collections: Arc<scc::HashSet<String>>,
+    /// The pinned snapshot that searches are restricted to, if any.
+    pinned: Option<Arc<Pinned>>,
+    /// Vendored and generated code, which is neither embedded nor returned.
+    exclusions: Arc<Exclusions>,
+}
+
+/// A copy of the points of a repository, and the model that embedded them.
+struct Pinned {
+    collection_name: String,
+    model: EmbeddingModel,
+}
+
+/// How points are split into qdrant collections.
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollectionLayout {
+    /// All repositories share `collection_name`.
+    #[default]
+    Shared,
+    /// Each repository gets its own collection. Queries search the collections of the
+    /// repositories in their scope in parallel, and a collection is dropped with its repository.
+    PerRepo,
+}
+
+/// A query embedded by the model of each collection it searches.
+struct QueryVectors {
+    by_collection: HashMap<String, Embedding>,
+    /// The embedding for the first collection, or by the default model without collections.
+    primary: Embedding,
+}
+
+/// What happened to the candidates of a search, for retrieval diagnostics.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchStats {
+    /// The points returned by qdrant.
+    pub candidates: usize,
+    /// The candidates dropped by deduplication, which also keeps at most `limit` of them.
+    pub deduplicated: usize,
+    /// Whether a cross-encoder reranked the candidates.
+    pub reranked: bool,
+    /// The payload filter sent to qdrant, see [`filter::describe`].
+    pub filter: String,
+    /// The points requested from each collection.
+    pub limit: u64,
+    /// The `hnsw_ef` search parameter, when `qdrant_hnsw_ef` sets it.
+    pub hnsw_ef: Option<u64>,
+    pub timings: SearchTimings,
 }
 
+/// Where the time of a search went.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchTimings {
+    /// Embedding the query.
+    pub embed: Duration,
+    /// Scoring points in qdrant, as it reports it. With several collections, which are searched in
+    /// parallel, the slowest of them.
+    pub scoring: Duration,
+    /// The rest of the round trip to qdrant: the network, and encoding the request and response.
+    pub network: Duration,
+    /// Filtering, reranking and deduplicating the candidates.
+    pub postprocessing: Duration,
+}
+
+/// The number of embeddings sampled from a full-size collection to fit a PCA reduction on.
+const PCA_SAMPLES: u32 = 2000;
+
+/// The number of points copied at once when a repository is pinned.
+const PIN_PAGE_SIZE: u32 = 256;
+
 macro_rules! val_str(($hash:ident, $val:expr) => { serde_json::from_value($hash.remove($val).unwrap()).unwrap() });
 macro_rules! val_parse_str(($hash:ident, $val:expr) => {
     serde_json::from_value::<Cow<'_, str>>($hash.remove($val).unwrap())
@@ -86,7 +200,7 @@ impl Payload {
     }
 
     pub(crate) fn into_qdrant(self) -> HashMap<String, Value> {
-        HashMap::from([
+        let mut payload = HashMap::from([
             ("lang".into(), self.lang.to_ascii_lowercase().into()),
             ("repo_name".into(), self.repo_name.into()),
             ("repo_ref".into(), self.repo_ref.into()),
@@ -98,7 +212,34 @@ impl Payload {
             ("start_byte".into(), self.start_byte.to_string().into()),
             ("end_byte".into(), self.end_byte.to_string().into()),
             ("branches".into(), self.branches.into()),
-        ])
+        ]);
+
+        if let Some(license) = self.license {
+            payload.insert("license".into(), license.into());
+        }
+
+        if let Some(model) = self.embedding_model {
+            payload.insert("embedding_model".into(), model.into());
+        }
+
+        if let Some(params) = self.chunk_params {
+            payload.insert(
+                "chunk_max_tokens".into(),
+                params.max_tokens.to_string().into(),
+            );
+            payload.insert("chunk_overlap".into(), params.overlap.to_string().into());
+
+            if let Some(strategy) = params.strategy {
+                payload.insert("chunk_strategy".into(), strategy.to_string().into());
+            }
+
+            if let (Some(lines), Some(stride)) = (params.lines, params.stride) {
+                payload.insert("chunk_lines".into(), lines.to_string().into());
+                payload.insert("chunk_stride".into(), stride.to_string().into());
+            }
+        }
+
+        payload
     }
 }
 
@@ -143,6 +284,13 @@ fn parse_payload(
         end_line: val_parse_str!(converted, "end_line"),
         start_byte: val_parse_str!(converted, "start_byte"),
         end_byte: val_parse_str!(converted, "end_byte"),
+        license: converted
+            .remove("license")
+            .and_then(|v| serde_json::from_value(v).ok()),
+        chunk_params: parse_chunk_params(&mut converted),
+        embedding_model: converted
+            .remove("embedding_model")
+            .and_then(|v| serde_json::from_value(v).ok()),
 
         id: Some(id),
         score: Some(score),
@@ -150,6 +298,24 @@ fn parse_payload(
     }
 }
 
+/// Read the chunk parameters recorded in a payload, if the point has them.
+fn parse_chunk_params(converted: &mut HashMap<String, serde_json::Value>) -> Option<ChunkParams> {
+    let max_tokens = converted.remove("chunk_max_tokens")?;
+    let overlap = converted.remove("chunk_overlap")?;
+    let strategy = converted
+        .remove("chunk_strategy")
+        .and_then(|v| serde_json::from_value(v).ok());
+    let mut lines = |key: &str| converted.remove(key).and_then(|v| v.as_str()?.parse().ok());
+
+    Some(ChunkParams {
+        max_tokens: max_tokens.as_str()?.parse().ok()?,
+        overlap: OverlapStrategy::try_from(overlap.as_str()?).ok()?,
+        strategy,
+        lines: lines("chunk_lines"),
+        stride: lines("chunk_stride"),
+    })
+}
+
 fn kind_to_value(kind: Option<qdrant_client::qdrant::value::Kind>) -> serde_json::Value {
     use qdrant_client::qdrant::value::Kind;
     match kind {
@@ -171,11 +337,25 @@ fn kind_to_value(kind: Option<qdrant_client::qdrant::value::Kind>) -> serde_json
     }
 }
 
-async fn create_indexes(collection_name: &str, qdrant: &QdrantClient) -> anyhow::Result<()> {
-    let text_fields = &["repo_ref", "content_hash", "branches", "relative_path"];
+async fn create_indexes(collection_name: &str, store: &dyn VectorStore) -> anyhow::Result<()> {
+    let keyword_fields = &["lang", "repo_name"];
+    for field in keyword_fields {
+        store
+            .create_field_index(collection_name, field, FieldType::Keyword)
+            .await?;
+    }
+
+    let text_fields = &[
+        "repo_ref",
+        "content_hash",
+        "branches",
+        "relative_path",
+        "data_hash",
+        "embedding_model",
+    ];
     for field in text_fields {
-        qdrant
-            .create_field_index(collection_name, field, FieldType::Text, None, None)
+        store
+            .create_field_index(collection_name, field, FieldType::Text)
             .await?;
     }
 
@@ -187,30 +367,115 @@ impl Semantic {
         model_dir: &Path,
         qdrant_url: &str,
         config: Arc<Configuration>,
+        sql: &SqlDb,
     ) -> Result<Self, SemanticError> {
         let qdrant = QdrantClient::new(Some(QdrantClientConfig::from_url(qdrant_url))).unwrap();
+        Self::with_store(model_dir, Arc::new(qdrant), config, sql).await
+    }
 
-        match qdrant.has_collection(&config.collection_name).await {
-            Ok(false) => {
-                let CollectionOperationResponse { result, time } =
-                    create_collection(&config.collection_name, &qdrant)
-                        .await
-                        .unwrap();
-
-                debug!(
-                    time,
-                    created = result,
-                    name = config.collection_name,
-                    "created qdrant collection"
-                );
-
-                assert!(result);
-            }
-            Ok(true) => {}
+    /// Initialize semantic search with points stored in the index directory, for when Qdrant
+    /// isn't configured.
+    pub async fn initialize_embedded(
+        model_dir: &Path,
+        config: Arc<Configuration>,
+        sql: &SqlDb,
+    ) -> Result<Self, SemanticError> {
+        let store = EmbeddedStore::open(config.index_path("vectors").as_ref())?;
+        Self::with_store(model_dir, Arc::new(store), config, sql).await
+    }
+
+    async fn with_store(
+        model_dir: &Path,
+        store: Arc<dyn VectorStore>,
+        config: Arc<Configuration>,
+        sql: &SqlDb,
+    ) -> Result<Self, SemanticError> {
+        let qdrant = store.as_ref();
+        let reductions = EmbeddingReductions::new(sql);
+
+        let current = match qdrant.has_collection(&config.collection_name).await {
+            // Collections that were created before reductions were stored are full-size.
+            Ok(true) => Some(match reductions.get(&config.collection_name).await? {
+                Some(json) => {
+                    serde_json::from_str::<Option<Reduction>>(&json).map_err(anyhow::Error::from)?
+                }
+                None => None,
+            }),
+            Ok(false) => None,
             Err(_) => return Err(SemanticError::QdrantInitializationError),
+        };
+
+        let existing_repo_collections = list_repo_collections(&config, qdrant).await?;
+
+        // In the per-repository layout, PCA is fit on one of the repositories.
+        let sample_from = existing_repo_collections
+            .first()
+            .unwrap_or(&config.collection_name);
+        let reduction = plan_reduction(&config, qdrant, sample_from, current.as_ref()).await?;
+        let dims = reduction.as_ref().map_or(EMBEDDING_DIM, Reduction::dims);
+
+        let reduction_changed = current.as_ref().map_or(false, |c| *c != reduction);
+        let layout_changed = match config.collection_layout {
+            CollectionLayout::Shared => !existing_repo_collections.is_empty(),
+            CollectionLayout::PerRepo => {
+                existing_repo_collections.is_empty()
+                    && current.is_some()
+                    && count_points(qdrant, &config.collection_name).await? > 0
+            }
+        };
+
+        let reindex = reduction_changed || layout_changed;
+        if reindex {
+            info!(
+                name = config.collection_name,
+                dims, reduction_changed, layout_changed, "re-creating qdrant collections"
+            );
+
+            qdrant.delete_collection(&config.collection_name).await?;
+            for name in &existing_repo_collections {
+                qdrant.delete_collection(name).await?;
+            }
+        }
+
+        if current.is_none() || reindex {
+            let CollectionOperationResponse { result, time } =
+                create_collection(&config.collection_name, dims, qdrant)
+                    .await
+                    .unwrap();
+
+            debug!(
+                time,
+                created = result,
+                name = config.collection_name,
+                dims,
+                "created qdrant collection"
+            );
+
+            assert!(result);
+
+            reductions
+                .set(
+                    &config.collection_name,
+                    &serde_json::to_string(&reduction).map_err(anyhow::Error::from)?,
+                )
+                .await?;
         }
 
-        create_indexes(&config.collection_name, &qdrant).await?;
+        // Points that were embedded before models were recorded in their payloads were embedded
+        // with the default model of the time, so its version is kept until the collection is
+        // re-created.
+        let version = embedder::model_version(model_dir)?;
+        let legacy_version = match reductions.model_hash(&config.collection_name).await? {
+            Some(legacy) if current.is_some() && !reindex => legacy,
+            _ => {
+                reductions
+                    .set_model_hash(&config.collection_name, &version)
+                    .await?;
+                version.clone()
+            }
+        };
+
+        create_indexes(&config.collection_name, qdrant).await?;
 
         if let Some(dylib_dir) = config.dylib_dir.as_ref() {
             init_ort_dylib(dylib_dir);
@@ -218,83 +483,701 @@ impl Semantic {
 
         #[cfg(feature = "ee")]
         let embedder: Arc<dyn Embedder> = if let Some(ref url) = config.embedding_server_url {
-            Arc::new(embedder::RemoteEmbedder::new(url.clone(), model_dir)?)
+            Arc::new(embedder::RemoteEmbedder::new(
+                url.clone(),
+                model_dir,
+                config.embedding_accelerator,
+            )?)
         } else {
-            Arc::new(LocalEmbedder::new(model_dir)?)
+            Arc::new(LocalEmbedder::new(model_dir, config.embedding_accelerator)?)
         };
 
         #[cfg(not(feature = "ee"))]
-        let embedder: Arc<dyn Embedder> = Arc::new(LocalEmbedder::new(model_dir)?);
+        let embedder: Arc<dyn Embedder> =
+            Arc::new(LocalEmbedder::new(model_dir, config.embedding_accelerator)?);
+
+        let mut models = HashMap::from([(
+            DEFAULT_MODEL.to_owned(),
+            EmbeddingModel {
+                name: DEFAULT_MODEL.to_owned(),
+                version,
+                embedder,
+            },
+        )]);
+
+        for (name, model_dir) in &config.embedding_models {
+            if name == DEFAULT_MODEL {
+                return Err(anyhow::anyhow!(
+                    "the `{DEFAULT_MODEL}` model is read from `model_dir`"
+                )
+                .into());
+            }
+
+            let model = EmbeddingModel::load(name, model_dir, config.embedding_accelerator)?;
+            models.insert(name.clone(), model);
+        }
+
+        for (repo, model) in &config.repo_embedding_models {
+            if !models.contains_key(model) {
+                return Err(anyhow::anyhow!(
+                    "unknown embedding model `{model}` for repository {repo}"
+                )
+                .into());
+            }
+        }
+
+        // Every model is reduced the same way, so that the collections keep one schema.
+        if let Some(reduction) = reduction {
+            for model in models.values_mut() {
+                model.embedder = Arc::new(ReducedEmbedder::new(
+                    model.embedder.clone(),
+                    reduction.clone(),
+                ));
+            }
+        }
+
+        let reranker = config
+            .reranker_model_dir
+            .as_deref()
+            .map(Reranker::new)
+            .transpose()?
+            .map(Arc::new);
+
+        let exclusions = Exclusions::load(&config)?;
+
+        let repo_collections = scc::HashSet::new();
+        if !reindex {
+            for name in existing_repo_collections {
+                _ = repo_collections.insert(name);
+            }
+        }
 
         Ok(Self {
-            qdrant: qdrant.into(),
-            embedder,
+            store,
+            models: models.into(),
+            legacy_model: format!("{DEFAULT_MODEL}@{legacy_version}"),
+            reranker,
+            embedding_permits: tokio::sync::Semaphore::new(config.embedding_concurrency.get())
+                .into(),
             config,
+            dims,
+            reindex,
+            repo_collections: repo_collections.into(),
+            pinned: None,
+            exclusions: exclusions.into(),
         })
     }
 
+    /// Whether the collection has to be re-indexed, because its embeddings are reduced
+    /// differently than before.
+    pub fn reindex_required(&self) -> bool {
+        self.reindex
+    }
+
     pub fn collection_name(&self) -> &str {
         &self.config.collection_name
     }
 
-    pub fn qdrant_client(&self) -> &QdrantClient {
-        &self.qdrant
+    /// The collection that the points of a repository are stored in.
+    pub fn collection_for(&self, repo_ref: &RepoRef) -> String {
+        match self.config.collection_layout {
+            CollectionLayout::Shared => self.config.collection_name.clone(),
+            CollectionLayout::PerRepo => repo_collection(&self.config, &repo_ref.indexed_name()),
+        }
+    }
+
+    /// Upsert points of a repository, creating its collection if needed.
+    pub async fn upsert_points(
+        &self,
+        repo_ref: &RepoRef,
+        points: Vec<PointStruct>,
+    ) -> anyhow::Result<()> {
+        let collection = self.collection_for(repo_ref);
+        if self.config.collection_layout == CollectionLayout::PerRepo {
+            self.ensure_repo_collection(&collection).await?;
+        }
+
+        self.store.upsert_points(&collection, points).await?;
+        Ok(())
+    }
+
+    /// Every collection that points are stored in.
+    pub async fn collections(&self) -> anyhow::Result<Vec<String>> {
+        match self.config.collection_layout {
+            CollectionLayout::Shared => Ok(vec![self.config.collection_name.clone()]),
+            CollectionLayout::PerRepo => list_repo_collections(&self.config, &self.store).await,
+        }
+    }
+
+    /// A page of the points in a collection, without their embeddings, and the offset of the
+    /// next page if there is one.
+    pub async fn scroll_page(
+        &self,
+        collection_name: &str,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> anyhow::Result<(Vec<Payload>, Option<PointId>)> {
+        let response = self
+            .store
+            .scroll(&ScrollPoints {
+                collection_name: collection_name.to_owned(),
+                offset,
+                limit: Some(limit),
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok((
+            response
+                .result
+                .into_iter()
+                .map(Payload::from_scroll)
+                .collect(),
+            response.next_page_offset,
+        ))
+    }
+
+    async fn ensure_repo_collection(&self, name: &str) -> anyhow::Result<()> {
+        if self.repo_collections.contains(name) {
+            return Ok(());
+        }
+
+        if !self.store.has_collection(name).await? {
+            // Another indexing task may have created it in the meantime.
+            if let Err(err) = create_collection(name, self.dims, &self.store).await {
+                if !self.store.has_collection(name).await? {
+                    return Err(err);
+                }
+            }
+
+            create_indexes(name, &self.store).await?;
+            debug!(name, dims = self.dims, "created qdrant collection");
+        }
+
+        _ = self.repo_collections.insert(name.to_owned());
+        Ok(())
+    }
+
+    /// Embeddings already stored for chunks of a repository, keyed by the hash of their data, so
+    /// that chunks which survive an edit of their file aren't embedded again.
+    ///
+    /// Only embeddings of the repository's current model are reused, so the chunks of a
+    /// repository are re-embedded as its files are indexed after the model changed.
+    pub async fn embeddings_for_data(
+        &self,
+        repo_ref: &RepoRef,
+        data_hashes: &[&str],
+    ) -> anyhow::Result<HashMap<String, Embedding>> {
+        let collection_name = self.collection_for(repo_ref);
+        let is_new_repo = self.config.collection_layout == CollectionLayout::PerRepo
+            && !self.repo_collections.contains(&collection_name);
+
+        if data_hashes.is_empty() || is_new_repo {
+            return Ok(HashMap::new());
+        }
+
+        let model = self.model_for(&repo_ref.indexed_name()).id();
+        let filter = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", &repo_ref.to_string()).into()],
+            should: data_hashes
+                .iter()
+                .map(|h| make_kv_keyword_filter("data_hash", h).into())
+                .collect(),
+            ..Default::default()
+        };
+
+        // Chunks with the same data have the same hash, so there can be more points than hashes.
+        let mut embeddings = HashMap::new();
+        let mut offset = None;
+        loop {
+            let page = self
+                .store
+                .scroll(&ScrollPoints {
+                    collection_name: collection_name.clone(),
+                    filter: Some(filter.clone()),
+                    offset,
+                    limit: Some(data_hashes.len() as u32),
+                    with_payload: Some(WithPayloadSelector {
+                        selector_options: Some(with_payload_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    with_vectors: Some(WithVectorsSelector {
+                        selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    ..Default::default()
+                })
+                .await?;
+
+            embeddings.extend(page.result.into_iter().filter_map(|mut p| {
+                let hash = match p.payload.remove("data_hash")?.kind? {
+                    qdrant_client::qdrant::value::Kind::StringValue(hash) => hash,
+                    _ => return None,
+                };
+
+                let embedded_with = match p.payload.remove("embedding_model").and_then(|v| v.kind) {
+                    Some(qdrant_client::qdrant::value::Kind::StringValue(id)) => id,
+                    _ => self.legacy_model.clone(),
+                };
+
+                if embedded_with != model {
+                    return None;
+                }
+
+                match p.vectors?.vectors_options? {
+                    VectorsOptions::Vector(v) => Some((hash, v.data)),
+                    _ => None,
+                }
+            }));
+
+            match page.next_page_offset {
+                Some(next) if embeddings.len() < data_hashes.len() => offset = Some(next),
+                _ => break,
+            }
+        }
+
+        Ok(embeddings)
+    }
+
+    /// Delete the points of a repository, and its collection once it is empty.
+    ///
+    /// Unlike the deletion of the points of single files, this fails if the points couldn't be
+    /// deleted, so that the repository isn't forgotten while its snippets can still be retrieved.
+    pub async fn delete_repo(&self, repo_ref: &RepoRef) -> anyhow::Result<()> {
+        let collection = self.collection_for(repo_ref);
+        if self.config.collection_layout == CollectionLayout::PerRepo
+            && !self.store.has_collection(&collection).await?
+        {
+            return Ok(());
+        }
+
+        let selector = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", &repo_ref.to_string()).into()],
+            ..Default::default()
+        }
+        .into();
+        self.store.delete_points(&collection, &selector).await?;
+
+        if self.config.collection_layout != CollectionLayout::PerRepo {
+            return Ok(());
+        }
+
+        // Local repositories are named after their directory, so they can share a collection.
+        match count_points(&self.store, &collection).await {
+            Ok(0) => {
+                if let Err(err) = self.store.delete_collection(&collection).await {
+                    warn!(?err, collection, "failed to drop repository collection");
+                }
+                self.repo_collections.remove(&collection);
+            }
+            Ok(_) => {}
+            Err(err) => warn!(?err, collection, "failed to count repository points"),
+        }
+
+        Ok(())
+    }
+
+    /// Point the payloads of a renamed repository to its new name, so that its embeddings are
+    /// reused when it is indexed under that name.
+    ///
+    /// Points can't move between the collections of the per-repository layout, so they are
+    /// deleted, and the repository is embedded again.
+    pub async fn rename_repo(&self, from: &RepoRef, to: &RepoRef) -> anyhow::Result<()> {
+        let collection = self.collection_for(from);
+        if collection != self.collection_for(to) {
+            return self.delete_repo(from).await;
+        }
+
+        let selector = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", &from.to_string()).into()],
+            ..Default::default()
+        }
+        .into();
+
+        let payload = qdrant_client::client::Payload::new_from_hashmap(
+            [
+                ("repo_ref".to_string(), to.to_string().into()),
+                ("repo_name".to_string(), to.indexed_name().into()),
+            ]
+            .into(),
+        );
+
+        self.store
+            .set_payload(&collection, &selector, payload)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The collection that a repository is pinned to under `name`.
+    pub fn pin_collection(&self, name: &str, repo_ref: &RepoRef) -> String {
+        let hash = blake3::hash(format!("{name}\0{repo_ref}").as_bytes()).to_hex();
+        format!("{}-pin-{}", self.config.collection_name, &hash[..16])
+    }
+
+    /// Copy the points of a repository into `collection_name`, which syncs don't change, and
+    /// return the number of points copied.
+    ///
+    /// Searches of the copy return the same results however the repository changes since, so
+    /// that evaluation runs compare retrieval on the same code.
+    pub async fn pin_repo(&self, repo_ref: &RepoRef, collection_name: &str) -> anyhow::Result<u64> {
+        if self.store.has_collection(collection_name).await? {
+            self.store.delete_collection(collection_name).await?;
+        }
+        create_collection(collection_name, self.dims, &self.store).await?;
+        create_indexes(collection_name, &self.store).await?;
+
+        let filter = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", &repo_ref.to_string()).into()],
+            ..Default::default()
+        };
+
+        let mut offset = None;
+        let mut copied = 0;
+        loop {
+            let page = self
+                .store
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection_for(repo_ref),
+                    filter: Some(filter.clone()),
+                    offset,
+                    limit: Some(PIN_PAGE_SIZE),
+                    with_payload: Some(WithPayloadSelector {
+                        selector_options: Some(with_payload_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    with_vectors: Some(WithVectorsSelector {
+                        selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    ..Default::default()
+                })
+                .await?;
+
+            let points = page
+                .result
+                .into_iter()
+                .map(|p| PointStruct {
+                    id: p.id,
+                    payload: p.payload,
+                    vectors: p.vectors,
+                })
+                .collect::<Vec<_>>();
+
+            copied += points.len() as u64;
+            if !points.is_empty() {
+                self.store.upsert_points(collection_name, points).await?;
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(copied)
+    }
+
+    /// The number of points of a repository, and the paths of the files they were embedded from.
+    pub async fn repo_points(&self, repo_ref: &RepoRef) -> anyhow::Result<(u64, HashSet<String>)> {
+        let filter = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", &repo_ref.to_string()).into()],
+            ..Default::default()
+        };
+
+        let mut offset = None;
+        let mut points = 0;
+        let mut paths = HashSet::new();
+        loop {
+            let page = self
+                .store
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection_for(repo_ref),
+                    filter: Some(filter.clone()),
+                    offset,
+                    limit: Some(PIN_PAGE_SIZE),
+                    with_payload: Some(WithPayloadSelector {
+                        selector_options: Some(with_payload_selector::SelectorOptions::Include(
+                            PayloadIncludeSelector {
+                                fields: vec!["relative_path".to_owned()],
+                            },
+                        )),
+                    }),
+                    ..Default::default()
+                })
+                .await?;
+
+            points += page.result.len() as u64;
+            paths.extend(page.result.into_iter().filter_map(|mut p| {
+                match p.payload.remove("relative_path")?.kind? {
+                    qdrant_client::qdrant::value::Kind::StringValue(path) => Some(path),
+                    _ => None,
+                }
+            }));
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok((points, paths))
+    }
+
+    /// Drop the copy of a pinned repository.
+    pub async fn unpin(&self, collection_name: &str) -> anyhow::Result<()> {
+        self.store.delete_collection(collection_name).await
+    }
+
+    /// Semantic search of a pinned repository, with queries embedded by the model that embedded
+    /// its points, as `name@version`.
+    pub fn pinned(&self, collection_name: &str, embedding_model: &str) -> anyhow::Result<Self> {
+        let Some(model) = self.models.values().find(|m| m.id() == embedding_model) else {
+            anyhow::bail!("the model `{embedding_model}` of the pinned snapshot isn't loaded");
+        };
+
+        Ok(Self {
+            pinned: Some(Arc::new(Pinned {
+                collection_name: collection_name.to_owned(),
+                model: model.clone(),
+            })),
+            ..self.clone()
+        })
+    }
+
+    pub fn store(&self) -> &dyn VectorStore {
+        self.store.as_ref()
+    }
+
+    /// Whether a snippet is vendored or generated code, which is left out of search results.
+    pub fn excludes_snippet(&self, payload: &Payload) -> bool {
+        self.exclusions.excludes_snippet(payload)
     }
 
+    /// The embedder of the default model.
     pub fn embedder(&self) -> &dyn Embedder {
-        self.embedder.as_ref()
+        self.models[DEFAULT_MODEL].embedder.as_ref()
+    }
+
+    /// Identifies the model of a repository in the cache keys of its files, so that they are
+    /// re-embedded when the model changes. Files embedded with the legacy model keep their keys.
+    pub fn model_cache_key(&self, repo_name: &str) -> Option<String> {
+        let id = self.model_for(repo_name).id();
+        (id != self.legacy_model).then_some(id)
+    }
+
+    /// The model that a repository is embedded with, by its indexed name.
+    ///
+    /// Repositories can only pick their own model in the per-repository layout, as a collection
+    /// holds the embeddings of a single model.
+    pub fn model_for(&self, repo_name: &str) -> &EmbeddingModel {
+        let name = match self.config.collection_layout {
+            CollectionLayout::Shared => None,
+            CollectionLayout::PerRepo => self.config.repo_embedding_models.get(repo_name),
+        };
+
+        &self.models[name.map_or(DEFAULT_MODEL, String::as_str)]
+    }
+
+    /// Embed a batch of a repository's chunks, waiting while `embedding_concurrency` batches are
+    /// being embedded already.
+    pub async fn batch_embed(
+        &self,
+        repo_name: &str,
+        batch: Vec<&str>,
+    ) -> anyhow::Result<Vec<Embedding>> {
+        let _permit = self.embedding_permits.acquire().await?;
+        self.model_for(repo_name).embedder.batch_embed(batch).await
+    }
+
+    /// The model that the points of a collection are embedded with.
+    fn model_of_collection(&self, collection_name: &str) -> &EmbeddingModel {
+        if let Some(pinned) = self
+            .pinned
+            .as_ref()
+            .filter(|p| p.collection_name == collection_name)
+        {
+            return &pinned.model;
+        }
+
+        let name = match self.config.collection_layout {
+            CollectionLayout::Shared => None,
+            CollectionLayout::PerRepo => self
+                .config
+                .repo_embedding_models
+                .iter()
+                .find(|(repo, _)| repo_collection(&self.config, repo) == collection_name)
+                .map(|(_, model)| model.as_str()),
+        };
+
+        &self.models[name.unwrap_or(DEFAULT_MODEL)]
+    }
+
+    /// Embed a query with the model of each collection, computing each embedding once.
+    fn embed_query(&self, collections: &[String], query: &str) -> anyhow::Result<QueryVectors> {
+        let mut by_model = HashMap::<&str, Embedding>::new();
+        let mut by_collection = HashMap::new();
+
+        for collection in collections {
+            let model = self.model_of_collection(collection);
+            let vector = match by_model.get(model.name.as_str()) {
+                Some(vector) => vector.clone(),
+                None => {
+                    let vector = model.embedder.embed(query)?;
+                    by_model.insert(&model.name, vector.clone());
+                    vector
+                }
+            };
+
+            by_collection.insert(collection.clone(), vector);
+        }
+
+        let primary = match collections.first() {
+            Some(first) => by_collection[first].clone(),
+            None => self.embedder().embed(query)?,
+        };
+
+        Ok(QueryVectors {
+            by_collection,
+            primary,
+        })
     }
     pub async fn delete_collection(&self) -> anyhow::Result<()> {
         _ = self
-            .qdrant
+            .store
             .delete_collection(&self.config.collection_name)
             .await?;
 
         Ok(())
     }
 
+    /// Delete all points, by re-creating the collection with the same dimensionality, and
+    /// dropping the collections of repositories.
+    pub async fn reset_collection(&self) -> anyhow::Result<()> {
+        for name in list_repo_collections(&self.config, &self.store).await? {
+            self.store.delete_collection(&name).await?;
+        }
+        self.repo_collections.clear();
+
+        self.delete_collection().await?;
+        create_collection(&self.config.collection_name, self.dims, &self.store).await?;
+        create_indexes(&self.config.collection_name, &self.store).await
+    }
+
     pub async fn health_check(&self) -> anyhow::Result<()> {
-        self.qdrant.health_check().await?;
+        self.store.health_check().await?;
         Ok(())
     }
 
-    pub async fn search_with<'a>(
+    /// The collections to search for a query: the shared collection, or the collections of the
+    /// repositories in scope, which are all repositories if the query isn't scoped.
+    async fn collections_in_scope(&self, query: &SemanticQuery<'_>) -> anyhow::Result<Vec<String>> {
+        if let Some(pinned) = &self.pinned {
+            return Ok(vec![pinned.collection_name.clone()]);
+        }
+
+        if self.config.collection_layout == CollectionLayout::Shared {
+            return Ok(vec![self.config.collection_name.clone()]);
+        }
+
+        let existing = list_repo_collections(&self.config, &self.store).await?;
+        let names = repo_names(query);
+        if names.is_empty() {
+            return Ok(existing);
+        }
+
+        Ok(names
+            .iter()
+            .map(|name| repo_collection(&self.config, name))
+            .filter(|c| existing.contains(c))
+            .collect())
+    }
+
+    /// Search collections in parallel, each with the query embedded by its model, and merge the
+    /// results by score.
+    ///
+    /// Also returns the time that qdrant reports having spent on the slowest collection.
+    async fn scatter_search(
         &self,
-        parsed_query: &SemanticQuery<'a>,
-        vector: Embedding,
+        collections: &[String],
+        filters: &[Condition],
+        vectors: &QueryVectors,
         limit: u64,
         offset: u64,
         threshold: f32,
-    ) -> anyhow::Result<Vec<ScoredPoint>> {
-        let response = self
-            .qdrant
-            .search_points(&SearchPoints {
-                limit,
-                vector,
-                collection_name: self.config.collection_name.to_string(),
-                offset: Some(offset),
-                score_threshold: Some(threshold),
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
-                }),
-                filter: Some(Filter {
-                    must: build_conditions(parsed_query),
+    ) -> anyhow::Result<(Vec<ScoredPoint>, Duration)> {
+        // With several collections, the page can only be cut out of the merged results.
+        let (limit, offset, skip) = match collections {
+            [_] => (limit, offset, 0),
+            _ => (limit + offset, 0, offset as usize),
+        };
+
+        let responses = stream::iter(collections)
+            .map(|collection_name| {
+                let points = SearchPoints {
+                    limit,
+                    vector: vectors.by_collection[collection_name].clone(),
+                    collection_name: collection_name.clone(),
+                    offset: Some(offset),
+                    score_threshold: Some(threshold),
+                    with_payload: Some(WithPayloadSelector {
+                        selector_options: Some(with_payload_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    filter: Some(Filter {
+                        must: filters.to_vec(),
+                        ..Default::default()
+                    }),
+                    with_vectors: Some(WithVectorsSelector {
+                        selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
+                            true,
+                        )),
+                    }),
+                    params: self.config.qdrant_hnsw_ef.map(|hnsw_ef| SearchParams {
+                        hnsw_ef: Some(hnsw_ef),
+                        ..Default::default()
+                    }),
                     ..Default::default()
-                }),
-                with_vectors: Some(WithVectorsSelector {
-                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(true)),
-                }),
-                ..Default::default()
+                };
+
+                async move { self.store.search_points(&points).await }
             })
+            .buffer_unordered(10)
+            .try_collect::<Vec<_>>()
             .await?;
 
-        Ok(response.result)
+        let scoring = responses
+            .iter()
+            .map(|r| Duration::from_secs_f64(r.time.max(0.0)))
+            .max()
+            .unwrap_or_default();
+
+        let mut points = responses
+            .into_iter()
+            .flat_map(|r| r.result)
+            .collect::<Vec<_>>();
+
+        if collections.len() > 1 {
+            points.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+
+        let points = points.into_iter().skip(skip).take(limit as usize).collect();
+        Ok((points, scoring))
     }
 
-    pub async fn batch_search_with<'a>(
+    async fn batch_search_with<'a>(
         &self,
         parsed_queries: &[&SemanticQuery<'a>],
-        vectors: Vec<Embedding>,
+        collections: &[String],
+        vectors: &[QueryVectors],
         limit: u64,
         offset: u64,
         threshold: f32,
@@ -312,40 +1195,22 @@ impl Semantic {
 
         // Queries should contain the same filters, so we get the first one
         let parsed_query = parsed_queries.first().unwrap();
-        let filters = &build_conditions(parsed_query);
-
-        let responses = stream::iter(vectors.into_iter())
-            .map(|vector| async move {
-                let points = SearchPoints {
-                    limit,
-                    vector,
-                    collection_name: self.config.collection_name.to_string(),
-                    offset: Some(offset),
-                    score_threshold: Some(threshold),
-                    with_payload: Some(WithPayloadSelector {
-                        selector_options: Some(with_payload_selector::SelectorOptions::Enable(
-                            true,
-                        )),
-                    }),
-                    filter: Some(Filter {
-                        must: filters.clone(),
-                        ..Default::default()
-                    }),
-                    with_vectors: Some(WithVectorsSelector {
-                        selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
-                            true,
-                        )),
-                    }),
-                    ..Default::default()
-                };
+        let mut filters = build_conditions(parsed_query);
+        filters.extend(SearchFilters::from_query(parsed_query).conditions());
+        let filters = &filters;
 
-                self.qdrant.search_points(&points).await
+        let responses = stream::iter(vectors)
+            .map(|vectors| {
+                self.scatter_search(collections, filters, vectors, limit, offset, threshold)
             })
             .buffered(10)
             .try_collect::<Vec<_>>()
             .await?;
 
-        Ok(responses.into_iter().flat_map(|r| r.result).collect())
+        Ok(responses
+            .into_iter()
+            .flat_map(|(points, _)| points)
+            .collect())
     }
 
     pub async fn search<'a>(
@@ -356,29 +1221,112 @@ impl Semantic {
         threshold: f32,
         retrieve_more: bool,
     ) -> anyhow::Result<Vec<Payload>> {
+        let filters = SearchFilters::from_query(parsed_query);
+        self.search_with_stats(
+            parsed_query,
+            &filters,
+            limit,
+            offset,
+            threshold,
+            retrieve_more,
+        )
+        .await
+        .map(|(results, _)| results)
+    }
+
+    /// Like [`Semantic::search`], restricted by `filters` rather than by the qualifiers of the
+    /// query that they cover, and also returning what happened to the candidates on the way.
+    pub async fn search_with_stats<'a>(
+        &self,
+        parsed_query: &SemanticQuery<'a>,
+        filters: &SearchFilters,
+        limit: u64,
+        offset: u64,
+        threshold: f32,
+        retrieve_more: bool,
+    ) -> anyhow::Result<(Vec<Payload>, SearchStats)> {
         let Some(query) = parsed_query.target() else {
             anyhow::bail!("no search target for query");
         };
-        let vector = self.embedder.embed(&query)?;
+        let collections = self.collections_in_scope(parsed_query).await?;
+
+        let start = Instant::now();
+        let vectors = self.embed_query(&collections, &query)?;
+        let embed = start.elapsed();
+
+        let mut conditions = build_conditions(parsed_query);
+        conditions.extend(filters.conditions());
 
         // TODO: Remove the need for `retrieve_more`. It's here because:
         // In /q `limit` is the maximum number of results returned (the actual number will often be lower due to deduplication)
         // In /answer we want to retrieve `limit` results exactly
-        let results = self
-            .search_with(
-                parsed_query,
-                vector.clone(),
-                if retrieve_more { limit * 2 } else { limit }, // Retrieve double `limit` and deduplicate
+        let search_limit = if retrieve_more { limit * 2 } else { limit }; // Retrieve double `limit` and deduplicate
+
+        let start = Instant::now();
+        let (raw, scoring) = self
+            .scatter_search(
+                &collections,
+                &conditions,
+                &vectors,
+                search_limit,
                 offset,
                 threshold,
             )
-            .await
-            .map(|raw| {
-                raw.into_iter()
-                    .map(Payload::from_qdrant)
-                    .collect::<Vec<_>>()
-            })?;
-        Ok(deduplicate_snippets(results, vector, limit))
+            .await?;
+        let round_trip = start.elapsed();
+
+        let start = Instant::now();
+        let mut results = raw
+            .into_iter()
+            .map(Payload::from_qdrant)
+            .filter(|payload| {
+                filters.matches(payload) && !self.exclusions.excludes_snippet(payload)
+            })
+            .collect::<Vec<_>>();
+
+        let candidates = results.len();
+
+        // Reranked scores replace the similarity scores, which deduplication orders by.
+        if let Some(reranker) = &self.reranker {
+            tokio::task::block_in_place(|| reranker.rerank(&query, &mut results))?;
+        }
+
+        // Results of collections embedded with other models are compared to the query as embedded
+        // for the first collection.
+        let results = self.deduplicate_snippets(results, vectors.primary, limit);
+
+        let stats = SearchStats {
+            candidates,
+            deduplicated: candidates.saturating_sub(results.len()),
+            reranked: self.reranker.is_some(),
+            filter: filter::describe(&conditions),
+            limit: search_limit,
+            hnsw_ef: self.config.qdrant_hnsw_ef,
+            timings: SearchTimings {
+                embed,
+                scoring,
+                network: round_trip.saturating_sub(scoring),
+                postprocessing: start.elapsed(),
+            },
+        };
+
+        debug!(
+            filter = %stats.filter,
+            limit = stats.limit,
+            offset,
+            threshold,
+            hnsw_ef = stats.hnsw_ef,
+            collections = collections.len(),
+            candidates,
+            returned = results.len(),
+            embed_ms = embed.as_secs_f64() * 1000.0,
+            scoring_ms = scoring.as_secs_f64() * 1000.0,
+            network_ms = stats.timings.network.as_secs_f64() * 1000.0,
+            postprocessing_ms = stats.timings.postprocessing.as_secs_f64() * 1000.0,
+            "semantic search"
+        );
+
+        Ok((results, stats))
     }
 
     pub async fn batch_search<'a>(
@@ -393,9 +1341,13 @@ impl Semantic {
             anyhow::bail!("no search target for query");
         };
 
+        // Queries should contain the same filters, so the first one sets the scope
+        let collections = self
+            .collections_in_scope(parsed_queries.first().unwrap())
+            .await?;
         let vectors = parsed_queries
             .iter()
-            .map(|q| self.embedder.embed(&q.target().unwrap()))
+            .map(|q| self.embed_query(&collections, &q.target().unwrap()))
             .collect::<anyhow::Result<Vec<_>>>()?;
 
         tracing::trace!(?parsed_queries, "performing qdrant batch search");
@@ -403,7 +1355,8 @@ impl Semantic {
         let result = self
             .batch_search_with(
                 parsed_queries,
-                vectors.clone(),
+                &collections,
+                &vectors,
                 if retrieve_more { limit * 2 } else { limit }, // Retrieve double `limit` and deduplicate
                 offset,
                 threshold,
@@ -412,15 +1365,38 @@ impl Semantic {
 
         tracing::trace!(?result, "qdrant batch search returned");
 
+        let filters = SearchFilters::from_query(parsed_queries.first().unwrap());
         let results = result?
             .into_iter()
             .map(Payload::from_qdrant)
+            .filter(|payload| {
+                filters.matches(payload) && !self.exclusions.excludes_snippet(payload)
+            })
             .collect::<Vec<_>>();
 
         // deduplicate with mmr with respect to the mean of query vectors
         // TODO: implement a more robust multi-vector deduplication strategy
-        let target_vector = mean_pool(vectors);
-        Ok(deduplicate_snippets(results, target_vector, limit))
+        let target_vector = mean_pool(vectors.into_iter().map(|v| v.primary).collect());
+        Ok(self.deduplicate_snippets(results, target_vector, limit))
+    }
+
+    fn deduplicate_snippets(
+        &self,
+        snippets: Vec<Payload>,
+        query_embedding: Embedding,
+        limit: u64,
+    ) -> Vec<Payload> {
+        let limit = match self.config.max_snippets {
+            Some(max) => limit.min(max as u64),
+            None => limit,
+        };
+
+        deduplicate_snippets(
+            snippets,
+            query_embedding,
+            limit,
+            self.config.max_snippets_per_file,
+        )
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -433,48 +1409,111 @@ impl Semantic {
         buffer: &str,
         lang_str: &str,
         branches: &[String],
+        license: Option<&str>,
         chunk_cache: crate::cache::ChunkCache<'_>,
     ) {
         const MIN_CHUNK_TOKENS: usize = 50;
 
-        let chunks = chunk::by_tokens(
-            repo_name,
-            relative_path,
-            buffer,
-            self.embedder.tokenizer(),
-            MIN_CHUNK_TOKENS..self.config.max_chunk_tokens,
-            chunk::OverlapStrategy::default(),
+        if self.exclusions.excludes_file(relative_path, buffer) {
+            debug!(relative_path, "not embedding vendored or generated code");
+            return;
+        }
+
+        let params = ChunkParams::for_language(
+            lang_str,
+            &self.config.chunk_params,
+            ChunkParams {
+                max_tokens: self.config.max_chunk_tokens,
+                overlap: self.config.chunk_overlap,
+                strategy: Some(self.config.chunk_strategy),
+                lines: Some(self.config.chunk_lines),
+                stride: Some(self.config.chunk_stride),
+            },
         );
-        debug!(chunk_count = chunks.len(), "found chunks");
-
-        chunks.par_iter().for_each(|chunk| {
-            let data = format!("{repo_name}\t{relative_path}\n{}", chunk.data,);
-            let payload = Payload {
-                repo_name: repo_name.to_owned(),
-                repo_ref: repo_ref.to_owned(),
-                relative_path: relative_path.to_owned(),
-                content_hash: chunk_cache.file_hash(),
-                text: chunk.data.to_owned(),
-                lang: lang_str.to_ascii_lowercase(),
-                branches: branches.to_owned(),
-                start_line: chunk.range.start.line as u64,
-                end_line: chunk.range.end.line as u64,
-                start_byte: chunk.range.start.byte as u64,
-                end_byte: chunk.range.end.byte as u64,
-                ..Default::default()
-            };
 
-            let cached = chunk_cache.update_or_embed(&data, payload);
-            if let Err(err) = cached {
-                warn!(?err, %repo_name, %relative_path, "embedding failed");
-            }
-        });
+        let model = self.model_for(repo_name);
+        let chunks = match params.strategy.unwrap_or_default() {
+            ChunkStrategy::Tokens => chunk::by_tokens(
+                repo_name,
+                relative_path,
+                buffer,
+                model.embedder.tokenizer(),
+                MIN_CHUNK_TOKENS..params.max_tokens,
+                params.overlap,
+            ),
+            ChunkStrategy::ContentDefined => chunk::by_content(
+                repo_name,
+                relative_path,
+                buffer,
+                model.embedder.tokenizer(),
+                MIN_CHUNK_TOKENS..params.max_tokens,
+            ),
+            ChunkStrategy::Lines => chunk::by_line_windows(
+                repo_name,
+                relative_path,
+                buffer,
+                model.embedder.tokenizer(),
+                MIN_CHUNK_TOKENS..params.max_tokens,
+                params.lines.unwrap_or(self.config.chunk_lines),
+                params.stride.unwrap_or(self.config.chunk_stride),
+            ),
+            ChunkStrategy::Scopes => chunk::by_scopes(
+                repo_name,
+                relative_path,
+                buffer,
+                lang_str,
+                model.embedder.tokenizer(),
+                MIN_CHUNK_TOKENS..params.max_tokens,
+                params.overlap,
+            ),
+        };
+        debug!(chunk_count = chunks.len(), ?params, "found chunks");
+
+        // Chunks with the same data are told apart by their order in the file.
+        let mut seen = HashMap::<&str, usize>::new();
+        let occurrences = chunks
+            .iter()
+            .map(|chunk| {
+                let count = seen.entry(chunk.data).or_default();
+                *count += 1;
+                *count - 1
+            })
+            .collect::<Vec<_>>();
+
+        chunks
+            .par_iter()
+            .zip(occurrences)
+            .for_each(|(chunk, occurrence)| {
+                let data = format!("{repo_name}\t{relative_path}\n{}", chunk.data,);
+                let payload = Payload {
+                    repo_name: repo_name.to_owned(),
+                    repo_ref: repo_ref.to_owned(),
+                    relative_path: relative_path.to_owned(),
+                    content_hash: chunk_cache.file_hash(),
+                    text: chunk.data.to_owned(),
+                    lang: lang_str.to_ascii_lowercase(),
+                    branches: branches.to_owned(),
+                    start_line: chunk.range.start.line as u64,
+                    end_line: chunk.range.end.line as u64,
+                    start_byte: chunk.range.start.byte as u64,
+                    end_byte: chunk.range.end.byte as u64,
+                    license: license.map(str::to_owned),
+                    chunk_params: Some(params),
+                    embedding_model: Some(model.id()),
+                    ..Default::default()
+                };
+
+                let cached = chunk_cache.update_or_embed(&data, occurrence, payload);
+                if let Err(err) = cached {
+                    warn!(?err, %repo_name, %relative_path, "embedding failed");
+                }
+            });
 
         match chunk_cache.commit().await {
-            Ok((new, updated, deleted)) => {
+            Ok((new, kept, updated, deleted)) => {
                 info!(
                     repo_name,
-                    relative_path, new, updated, deleted, "Successful commit"
+                    relative_path, new, kept, updated, deleted, "Successful commit"
                 )
             }
             Err(err) => {
@@ -485,10 +1524,10 @@ impl Semantic {
 
     pub async fn delete_points_for_hash(
         &self,
-        repo_ref: &str,
+        repo_ref: &RepoRef,
         paths: impl Iterator<Item = String>,
     ) {
-        let repo_filter = make_kv_keyword_filter("repo_ref", repo_ref).into();
+        let repo_filter = make_kv_keyword_filter("repo_ref", &repo_ref.to_string()).into();
         let file_filter = paths
             .map(|p| make_kv_keyword_filter("content_hash", &p).into())
             .collect::<Vec<_>>();
@@ -501,12 +1540,83 @@ impl Semantic {
         .into();
 
         let _ = self
-            .qdrant
-            .delete_points(&self.config.collection_name, &selector, None)
+            .store
+            .delete_points(&self.collection_for(repo_ref), &selector)
             .await;
     }
 }
 
+/// Decide how the embeddings of the collection are reduced, given how they are `current`ly
+/// reduced, if the collection exists. PCA samples are taken from the `sample_from` collection.
+///
+/// A PCA reduction is fit on a sample of the collection while it is still full-size, and kept
+/// as long as the configured dimensionality doesn't change.
+async fn plan_reduction(
+    config: &Configuration,
+    qdrant: &dyn VectorStore,
+    sample_from: &str,
+    current: Option<&Option<Reduction>>,
+) -> anyhow::Result<Option<Reduction>> {
+    let Some(dims) = config.embedding_dims else {
+        return Ok(None);
+    };
+
+    if dims == 0 || dims >= EMBEDDING_DIM {
+        anyhow::bail!(
+            "`embedding_dims` must be between 1 and {}",
+            EMBEDDING_DIM - 1
+        );
+    }
+
+    match config.embedding_reduction {
+        Method::Truncate => Ok(Some(Reduction::Truncate { dims })),
+        Method::Pca => match current {
+            Some(Some(reduction))
+                if reduction.method() == Method::Pca && reduction.dims() == dims =>
+            {
+                Ok(Some(reduction.clone()))
+            }
+            Some(None) => {
+                let samples = qdrant
+                    .scroll(&ScrollPoints {
+                        collection_name: sample_from.to_owned(),
+                        limit: Some(PCA_SAMPLES),
+                        with_vectors: Some(WithVectorsSelector {
+                            selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
+                                true,
+                            )),
+                        }),
+                        ..Default::default()
+                    })
+                    .await?
+                    .result
+                    .into_iter()
+                    .filter_map(|p| match p.vectors?.vectors_options? {
+                        VectorsOptions::Vector(v) => Some(v.data),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                match tokio::task::block_in_place(|| Reduction::fit_pca(&samples, dims)) {
+                    Ok(reduction) => Ok(Some(reduction)),
+                    Err(err) => {
+                        warn!(
+                            ?err,
+                            "not enough embeddings to fit PCA on yet, keeping full-size"
+                        );
+                        Ok(None)
+                    }
+                }
+            }
+            // There is nothing to fit on, so the collection starts full-size.
+            _ => {
+                info!("PCA is fit once the collection is indexed, starting full-size");
+                Ok(None)
+            }
+        },
+    }
+}
+
 /// Initialize the `ORT_DYLIB_PATH` variable, consumed by the `ort` crate.
 ///
 /// This doesn't do anything on Windows, as tauri on Windows will automatically bundle any `.dll`
@@ -554,17 +1664,58 @@ fn make_kv_text_filter(key: &str, value: &str) -> FieldCondition {
     }
 }
 
+/// The `repo_name`s of the repositories a query is scoped to.
+fn repo_names(query: &SemanticQuery<'_>) -> Vec<String> {
+    query
+        .repos()
+        .map(|r| {
+            if r.contains('/') && !r.starts_with("github.com/") {
+                format!("github.com/{r}")
+            } else {
+                r.to_string()
+            }
+        })
+        .collect()
+}
+
+/// The name of the collection of a repository in the per-repository layout.
+fn repo_collection(config: &Configuration, repo_name: &str) -> String {
+    let hash = blake3::hash(repo_name.as_bytes()).to_hex();
+    format!("{}-repo-{}", config.collection_name, &hash[..16])
+}
+
+/// The per-repository collections that exist in qdrant.
+async fn list_repo_collections(
+    config: &Configuration,
+    qdrant: &dyn VectorStore,
+) -> anyhow::Result<Vec<String>> {
+    let prefix = format!("{}-repo-", config.collection_name);
+    Ok(qdrant
+        .list_collections()
+        .await?
+        .into_iter()
+        .filter(|name| name.starts_with(&prefix))
+        .collect())
+}
+
+async fn count_points(qdrant: &dyn VectorStore, collection_name: &str) -> anyhow::Result<u64> {
+    let count = qdrant
+        .count(&CountPoints {
+            collection_name: collection_name.to_owned(),
+            exact: Some(true),
+            ..Default::default()
+        })
+        .await?
+        .result
+        .map_or(0, |r| r.count);
+
+    Ok(count)
+}
+
 fn build_conditions(query: &SemanticQuery<'_>) -> Vec<qdrant_client::qdrant::Condition> {
     let repo_filter = {
-        let conditions = query
-            .repos()
-            .map(|r| {
-                if r.contains('/') && !r.starts_with("github.com/") {
-                    format!("github.com/{r}")
-                } else {
-                    r.to_string()
-                }
-            })
+        let conditions = repo_names(query)
+            .into_iter()
             .map(|r| make_kv_keyword_filter("repo_name", r.as_ref()).into())
             .collect::<Vec<_>>();
         // one of the above repos should match
@@ -578,9 +1729,11 @@ fn build_conditions(query: &SemanticQuery<'_>) -> Vec<qdrant_client::qdrant::Con
         }
     };
 
+    // Globs are covered by `SearchFilters`.
     let path_filter = {
         let conditions = query
             .paths()
+            .filter(|p| !filter::PathGlob::is_glob(p))
             .map(|r| make_kv_text_filter("relative_path", r.as_ref()).into())
             .collect::<Vec<_>>();
         if conditions.is_empty() {
@@ -593,22 +1746,6 @@ fn build_conditions(query: &SemanticQuery<'_>) -> Vec<qdrant_client::qdrant::Con
         }
     };
 
-    let lang_filter = {
-        let conditions = query
-            .langs()
-            .map(|l| make_kv_keyword_filter("lang", l.as_ref()).into())
-            .collect::<Vec<_>>();
-        // one of the above langs should match
-        if conditions.is_empty() {
-            None
-        } else {
-            Some(Filter {
-                should: conditions,
-                ..Default::default()
-            })
-        }
-    };
-
     let branch_filter = {
         let conditions = query
             .branch()
@@ -625,7 +1762,7 @@ fn build_conditions(query: &SemanticQuery<'_>) -> Vec<qdrant_client::qdrant::Con
         }
     };
 
-    let filters: Vec<_> = [repo_filter, path_filter, lang_filter, branch_filter]
+    let filters: Vec<_> = [repo_filter, path_filter, branch_filter]
         .into_iter()
         .flatten()
         .map(Into::into)
@@ -649,7 +1786,7 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 // Calculate the element-wise mean of the embeddings
 fn mean_pool(embeddings: Vec<Vec<f32>>) -> Vec<f32> {
     let len = embeddings.len() as f32;
-    let mut result = vec![0.0; EMBEDDING_DIM];
+    let mut result = vec![0.0; embeddings.first().map_or(0, Vec::len)];
     for embedding in embeddings {
         for (i, v) in embedding.iter().enumerate() {
             result[i] += v;
@@ -671,6 +1808,7 @@ fn mean_pool(embeddings: Vec<Vec<f32>>) -> Vec<f32> {
 //    - we add a language diversity factor to the score to encourage a range of langauges in the results
 //    - we also add a path diversity factor to the score to encourage a range of paths in the results
 //  k: the number of embeddings to select
+/// Select `k` embeddings by maximal marginal relevance, with at most `max_per_path` of each path.
 pub fn deduplicate_with_mmr(
     query_embedding: &[f32],
     embeddings: &[&[f32]],
@@ -678,21 +1816,18 @@ pub fn deduplicate_with_mmr(
     paths: &[&str],
     lambda: f32,
     k: usize,
+    max_per_path: usize,
 ) -> Vec<usize> {
     let mut idxs = vec![];
     let mut lang_counts = HashMap::new();
     let mut path_counts = HashMap::new();
 
-    if embeddings.len() < k {
-        return (0..embeddings.len()).collect();
-    }
-
     while idxs.len() < k {
         let mut best_score = f32::NEG_INFINITY;
         let mut idx_to_add = None;
 
         for (i, emb) in embeddings.iter().enumerate() {
-            if idxs.contains(&i) {
+            if idxs.contains(&i) || path_counts.get(paths[i]).unwrap_or(&0) >= &max_per_path {
                 continue;
             }
             let first_part = cosine_similarity(query_embedding, emb);
@@ -718,50 +1853,91 @@ pub fn deduplicate_with_mmr(
                 idx_to_add = Some(i);
             }
         }
-        if let Some(i) = idx_to_add {
-            idxs.push(i);
-            *lang_counts.entry(languages[i]).or_insert(0) += 1;
-            *path_counts.entry(paths[i]).or_insert(0) += 1;
+        match idx_to_add {
+            Some(i) => {
+                idxs.push(i);
+                *lang_counts.entry(languages[i]).or_insert(0) += 1;
+                *path_counts.entry(paths[i]).or_insert(0) += 1;
+            }
+            None => break,
         }
     }
     idxs
 }
 
-fn filter_overlapping_snippets(mut snippets: Vec<Payload>) -> Vec<Payload> {
+/// Merge overlapping and adjacent snippets of each file into one snippet, which keeps the
+/// embedding of the best scoring snippet it contains.
+fn merge_snippets(mut snippets: Vec<Payload>) -> Vec<Payload> {
     snippets.sort_by(|a, b| {
-        a.relative_path
-            .cmp(&b.relative_path)
-            .then(a.start_line.cmp(&b.start_line))
+        a.repo_ref
+            .cmp(&b.repo_ref)
+            .then_with(|| a.relative_path.cmp(&b.relative_path))
+            .then(a.start_byte.cmp(&b.start_byte))
     });
 
     snippets = snippets
         .into_iter()
-        .fold(Vec::<Payload>::new(), |mut deduped_snippets, snippet| {
-            if let Some(prev) = deduped_snippets.last_mut() {
-                if prev.relative_path == snippet.relative_path
-                    && prev.end_line >= snippet.start_line
-                {
-                    debug!(
-                        "Filtering overlapping snippets. End: {:?} - Start: {:?} from {:?}",
-                        prev.end_line, snippet.start_line, prev.relative_path
-                    );
-                    return deduped_snippets;
-                }
+        .fold(Vec::<Payload>::new(), |mut merged, snippet| {
+            match merged.last_mut() {
+                Some(prev) => merged.extend(merge_snippet(prev, snippet)),
+                None => merged.push(snippet),
             }
-            deduped_snippets.push(snippet);
-            deduped_snippets
+            merged
         });
 
     snippets.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
     snippets
 }
 
+/// Merge `next` into `prev` if they are of the same file, and overlap or are on adjacent lines.
+///
+/// This assumes that `prev` does not start after `next`, and returns `next` if it was not merged.
+fn merge_snippet(prev: &mut Payload, next: Payload) -> Option<Payload> {
+    if prev.repo_ref != next.repo_ref || prev.relative_path != next.relative_path {
+        return Some(next);
+    }
+
+    let added = if next.end_byte <= prev.end_byte {
+        ""
+    } else if next.start_byte <= prev.end_byte {
+        match next.text.get((prev.end_byte - next.start_byte) as usize..) {
+            Some(added) => added,
+            None => return Some(next),
+        }
+    } else if next.start_byte == prev.end_byte + 1 && next.start_line == prev.end_line + 1 {
+        // The only byte between the snippets is the newline at the end of `prev`.
+        prev.text.push('\n');
+        &next.text
+    } else {
+        return Some(next);
+    };
+
+    debug!(
+        path = %prev.relative_path,
+        prev = ?(prev.start_line, prev.end_line),
+        next = ?(next.start_line, next.end_line),
+        "merging snippets"
+    );
+
+    prev.text.push_str(added);
+    prev.end_byte = prev.end_byte.max(next.end_byte);
+    prev.end_line = prev.end_line.max(next.end_line);
+
+    if next.score > prev.score {
+        prev.score = next.score;
+        prev.embedding = next.embedding;
+    }
+
+    None
+}
+
 pub fn deduplicate_snippets(
     mut all_snippets: Vec<Payload>,
     query_embedding: Embedding,
     output_count: u64,
+    max_per_file: usize,
 ) -> Vec<Payload> {
-    all_snippets = filter_overlapping_snippets(all_snippets);
+    all_snippets = merge_snippets(all_snippets);
 
     let idxs = {
         let lambda = 0.5;
@@ -774,10 +1950,11 @@ pub fn deduplicate_snippets(
             .iter()
             .map(|s| s.lang.as_ref())
             .collect::<Vec<_>>();
-        let paths = all_snippets
+        let files = all_snippets
             .iter()
-            .map(|s| s.relative_path.as_ref())
+            .map(|s| format!("{}/{}", s.repo_ref, s.relative_path))
             .collect::<Vec<_>>();
+        let paths = files.iter().map(String::as_str).collect::<Vec<_>>();
         deduplicate_with_mmr(
             &query_embedding,
             &embeddings,
@@ -785,6 +1962,7 @@ pub fn deduplicate_snippets(
             &paths,
             lambda,
             k as usize,
+            max_per_file,
         )
     };
 
@@ -802,3 +1980,73 @@ pub fn deduplicate_snippets(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(path: &str, lines: (u64, u64), bytes: (u64, u64), score: f32) -> Payload {
+        let src = "l0\nl1\nl2\nl3\nl4\nl5\nl6\nl7";
+
+        Payload {
+            repo_ref: "github.com/bloopai/bloop".into(),
+            relative_path: path.into(),
+            text: src[bytes.0 as usize..bytes.1 as usize].into(),
+            start_line: lines.0,
+            end_line: lines.1,
+            start_byte: bytes.0,
+            end_byte: bytes.1,
+            embedding: Some(vec![score]),
+            score: Some(score),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_snippets() {
+        let merged = merge_snippets(vec![
+            payload("a.rs", (3, 4), (9, 14), 0.2),
+            payload("a.rs", (0, 1), (0, 5), 0.5),
+            payload("b.rs", (1, 2), (3, 8), 0.7),
+            payload("a.rs", (1, 2), (3, 8), 0.9),
+            payload("a.rs", (6, 7), (18, 23), 0.3),
+            payload("a.rs", (1, 1), (3, 5), 0.1),
+        ]);
+
+        assert_eq!(
+            merged
+                .iter()
+                .map(|p| (
+                    p.relative_path.as_str(),
+                    p.text.as_str(),
+                    p.start_line,
+                    p.end_line,
+                    p.score
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("a.rs", "l0\nl1\nl2\nl3\nl4", 0, 4, Some(0.9)),
+                ("b.rs", "l1\nl2", 1, 2, Some(0.7)),
+                ("a.rs", "l6\nl7", 6, 7, Some(0.3)),
+            ]
+        );
+        assert_eq!(merged[0].embedding, Some(vec![0.9]));
+    }
+
+    #[test]
+    fn limits_snippets_per_file() {
+        let embeddings: [&[f32]; 4] = [&[1.0, 0.0], &[0.9, 0.1], &[0.8, 0.2], &[0.0, 1.0]];
+        let idxs = deduplicate_with_mmr(
+            &[1.0, 0.0],
+            &embeddings,
+            &["rust"; 4],
+            &["a.rs", "a.rs", "a.rs", "b.rs"],
+            0.5,
+            4,
+            2,
+        );
+
+        assert_eq!(idxs.len(), 3);
+        assert!(idxs.contains(&3));
+    }
+}
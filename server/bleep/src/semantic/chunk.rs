@@ -1,9 +1,13 @@
 use std::{
+    collections::HashMap,
     fmt::{Display, Write},
     ops::Range,
 };
 
-use crate::text_range::{Point, TextRange};
+use crate::{
+    intelligence::TreeSitterFile,
+    text_range::{Point, TextRange},
+};
 
 use clap::{builder::PossibleValue, ValueEnum};
 use serde::{Deserialize, Serialize};
@@ -73,7 +77,7 @@ pub fn point(src: &str, byte: usize, last_line: usize, last_byte: usize) -> Poin
 
 /// The strategy for overlapping chunks
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
-#[serde(try_from = "&str", into = "String")]
+#[serde(try_from = "String", into = "String")]
 pub enum OverlapStrategy {
     /// go back _ lines from the end
     ByLines(usize),
@@ -86,7 +90,7 @@ impl Display for OverlapStrategy {
         match self {
             Self::ByLines(n) => n.fmt(f),
             Self::Partial(p) => {
-                (*p / 100.0).fmt(f)?;
+                (*p * 100.0).fmt(f)?;
                 f.write_char('%')
             }
         }
@@ -138,6 +142,14 @@ impl TryFrom<&'_ str> for OverlapStrategy {
     }
 }
 
+impl TryFrom<String> for OverlapStrategy {
+    type Error = &'static str;
+
+    fn try_from(input: String) -> Result<Self, &'static str> {
+        Self::try_from(input.as_str())
+    }
+}
+
 impl OverlapStrategy {
     // returns the next startpoint for overlong lines
     fn next_subdivision(&self, max_tokens: usize) -> usize {
@@ -155,6 +167,113 @@ impl Default for OverlapStrategy {
     }
 }
 
+/// Where chunk boundaries are placed.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChunkStrategy {
+    /// Windows of up to the maximum number of tokens, overlapping. See [`by_tokens`].
+    #[default]
+    Tokens,
+    /// Boundaries picked by a rolling hash of the content, which survive edits elsewhere in the
+    /// file. See [`by_content`].
+    ContentDefined,
+    /// Windows of `lines` lines, starting every `stride` lines. See [`by_line_windows`].
+    Lines,
+    /// Boundaries between syntax nodes, like functions and classes, for languages that
+    /// tree-sitter parses. See [`by_scopes`].
+    Scopes,
+}
+
+impl Display for ChunkStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Tokens => "tokens",
+            Self::ContentDefined => "content-defined",
+            Self::Lines => "lines",
+            Self::Scopes => "scopes",
+        })
+    }
+}
+
+/// How the files of a language are split into chunks.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ChunkParams {
+    /// The maximum number of tokens in a chunk, including the repo and path header.
+    pub max_tokens: usize,
+    /// The overlap of `tokens` chunks. Content-defined chunks don't overlap.
+    #[serde(default)]
+    pub overlap: OverlapStrategy,
+    /// Falls back to the configured `chunk_strategy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<ChunkStrategy>,
+    /// The number of lines in a chunk of the `lines` strategy. Falls back to `chunk_lines`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    /// The number of lines between the starts of chunks of the `lines` strategy. Falls back to
+    /// `chunk_stride`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stride: Option<usize>,
+}
+
+/// Built-in chunk parameters, with the maximum size as a share of the model's input size.
+///
+/// Dense languages pack more meaning into each line, so smaller chunks keep each embedding about
+/// one thing. Verbose and data languages keep the full input size, and overlap by lines since
+/// their lines rarely depend on much context.
+const LANGUAGE_DEFAULTS: &[(&str, f64, OverlapStrategy)] = &[
+    ("python", 0.75, OverlapStrategy::Partial(0.5)),
+    ("ruby", 0.75, OverlapStrategy::Partial(0.5)),
+    ("elixir", 0.75, OverlapStrategy::Partial(0.5)),
+    ("sql", 0.75, OverlapStrategy::Partial(0.5)),
+    ("haskell", 0.625, OverlapStrategy::Partial(0.5)),
+    ("ocaml", 0.625, OverlapStrategy::Partial(0.5)),
+    ("shell", 0.625, OverlapStrategy::Partial(0.5)),
+    ("json", 1.0, OverlapStrategy::ByLines(1)),
+    ("yaml", 1.0, OverlapStrategy::ByLines(1)),
+    ("toml", 1.0, OverlapStrategy::ByLines(1)),
+    ("xml", 1.0, OverlapStrategy::ByLines(1)),
+    ("html", 1.0, OverlapStrategy::ByLines(1)),
+    ("markdown", 1.0, OverlapStrategy::ByLines(2)),
+];
+
+impl ChunkParams {
+    /// The parameters for a language: a configured override, or the built-in default for the
+    /// language, or `defaults`.
+    ///
+    /// The size never exceeds the size of `defaults`, which is the model's input size, and the
+    /// strategy is always set, to the one of `defaults` unless the override picks one. `lines` and
+    /// `stride` are only set for the `lines` strategy.
+    pub fn for_language(
+        lang: &str,
+        overrides: &HashMap<String, ChunkParams>,
+        defaults: Self,
+    ) -> Self {
+        let lang = lang.to_ascii_lowercase();
+
+        let params = overrides.get(&lang).copied().unwrap_or_else(|| {
+            LANGUAGE_DEFAULTS.iter().find(|(l, ..)| *l == lang).map_or(
+                defaults,
+                |&(_, share, overlap)| Self {
+                    max_tokens: (defaults.max_tokens as f64 * share) as usize,
+                    overlap,
+                    ..defaults
+                },
+            )
+        });
+
+        let strategy = params.strategy.or(defaults.strategy);
+        let by_lines = strategy == Some(ChunkStrategy::Lines);
+
+        Self {
+            max_tokens: params.max_tokens.min(defaults.max_tokens),
+            overlap: params.overlap,
+            strategy,
+            lines: params.lines.or(defaults.lines).filter(|_| by_lines),
+            stride: params.stride.or(defaults.stride).filter(|_| by_lines),
+        }
+    }
+}
+
 /// This should take care of [CLS], [SEP] etc. which could be introduced during per-chunk tokenization
 pub const DEDUCT_SPECIAL_TOKENS: usize = 2;
 
@@ -219,21 +338,9 @@ pub fn by_tokens<'s>(
         return Vec::new();
     }
 
-    let repo_plus_file = repo.to_owned() + "\t" + file + "\n";
-    let repo_tokens = match tokenizer.encode(repo_plus_file, true) {
-        Ok(encoding) => encoding.get_ids().len(),
-        Err(e) => {
-            error!("failure during encoding repo + file {:?}", e);
-            return Vec::new();
-        }
-    };
-
-    if token_bounds.end <= DEDUCT_SPECIAL_TOKENS + repo_tokens {
-        error!("too few tokens");
+    let Some(max_tokens) = max_content_tokens(repo, file, tokenizer, token_bounds.end) else {
         return Vec::new();
-    }
-
-    let max_tokens = token_bounds.end - DEDUCT_SPECIAL_TOKENS - repo_tokens;
+    };
     let max_newline_tokens = max_tokens * 3 / 4; //TODO: make this configurable
     let max_boundary_tokens = max_tokens * 7 / 8; //TODO: make this configurable
     debug!("max tokens reduced to {max_tokens}");
@@ -307,6 +414,305 @@ pub fn by_tokens<'s>(
     }
 }
 
+/// The number of tokens of a chunk's content, once the repo and path header and special tokens
+/// are taken out of the model's input size.
+fn max_content_tokens(repo: &str, file: &str, tokenizer: &Tokenizer, max: usize) -> Option<usize> {
+    let repo_plus_file = repo.to_owned() + "\t" + file + "\n";
+    let repo_tokens = match tokenizer.encode(repo_plus_file, true) {
+        Ok(encoding) => encoding.get_ids().len(),
+        Err(e) => {
+            error!("failure during encoding repo + file {:?}", e);
+            return None;
+        }
+    };
+
+    if max <= DEDUCT_SPECIAL_TOKENS + repo_tokens {
+        error!("too few tokens");
+        return None;
+    }
+
+    Some(max - DEDUCT_SPECIAL_TOKENS - repo_tokens)
+}
+
+/// Random values for the gear hash of [`by_content`], one per byte value, from splitmix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// A line end is a chunk boundary with a probability of 1 in 2^`BOUNDARY_BITS`, once a chunk has
+/// the minimum number of tokens.
+const BOUNDARY_BITS: u32 = 3;
+
+/// Split the code at boundaries that depend on the content around them, so that an edit only
+/// changes the chunks near it, and the embeddings of the others can be reused.
+///
+/// Chunks end at line ends where a gear hash of the preceding bytes has its top bits clear. The
+/// hash only depends on the last 64 bytes, so boundaries away from an edit stay where they were,
+/// while fixed windows would all shift. A chunk that would exceed `max_tokens` is cut at its last
+/// line end instead, and lines that don't fit on their own are split by tokens.
+pub fn by_content<'s>(
+    repo: &str,
+    file: &str,
+    src: &'s str,
+    tokenizer: &Tokenizer,
+    token_bounds: Range<usize>,
+) -> Vec<Chunk<'s>> {
+    let min_tokens = token_bounds.start;
+    let (tokens, max_tokens) = match tokenize(repo, file, src, tokenizer, token_bounds) {
+        Ok(tokenized) => tokenized,
+        Err(chunks) => return chunks,
+    };
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    let mut last_line_end = None;
+    let mut hash = 0u64;
+
+    for (i, byte) in src.bytes().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if byte != b'\n' {
+            continue;
+        }
+
+        let end = i + 1;
+        if tokens.count(start..end) > max_tokens {
+            if let Some(cut) = last_line_end.take() {
+                spans.push(start..cut);
+                start = cut;
+            }
+
+            if tokens.count(start..end) > max_tokens {
+                tokens.push_split(&mut spans, start..end, max_tokens);
+                start = end;
+                continue;
+            }
+        }
+
+        if tokens.count(start..end) >= min_tokens && hash >> (u64::BITS - BOUNDARY_BITS) == 0 {
+            spans.push(start..end);
+            start = end;
+            last_line_end = None;
+        } else {
+            last_line_end = Some(end);
+        }
+    }
+
+    if start < src.len() {
+        if tokens.count(start..src.len()) > max_tokens {
+            if let Some(cut) = last_line_end {
+                spans.push(start..cut);
+                start = cut;
+            }
+        }
+
+        tokens.push_split(&mut spans, start..src.len(), max_tokens);
+    }
+
+    into_chunks(src, spans)
+}
+
+/// Windows of `lines` lines, starting every `stride` lines, so that consecutive windows overlap
+/// by `lines - stride` lines.
+///
+/// A window that would exceed `max_tokens` ends at its last line that fits, and the next window
+/// starts no later than where it ended. Lines that don't fit on their own are split by tokens.
+pub fn by_line_windows<'s>(
+    repo: &str,
+    file: &str,
+    src: &'s str,
+    tokenizer: &Tokenizer,
+    token_bounds: Range<usize>,
+    lines: usize,
+    stride: usize,
+) -> Vec<Chunk<'s>> {
+    let (tokens, max_tokens) = match tokenize(repo, file, src, tokenizer, token_bounds) {
+        Ok(tokenized) => tokenized,
+        Err(chunks) => return chunks,
+    };
+
+    let lines = lines.max(1);
+    let stride = stride.clamp(1, lines);
+
+    // Where each line starts, followed by the end of the file.
+    let line_starts = std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|&start| start < src.len())
+        .chain([src.len()])
+        .collect::<Vec<_>>();
+    let line_count = line_starts.len() - 1;
+
+    let mut spans = Vec::new();
+    let mut first = 0;
+    while first < line_count {
+        let window = |last: usize| line_starts[first]..line_starts[last];
+
+        let mut last = (first + lines).min(line_count);
+        while last > first + 1 && tokens.count(window(last)) > max_tokens {
+            last -= 1;
+        }
+
+        tokens.push_split(&mut spans, window(last), max_tokens);
+        if last == line_count {
+            break;
+        }
+
+        first = (first + stride).min(last);
+    }
+
+    into_chunks(src, spans)
+}
+
+/// Split the code between its syntax nodes, so that chunks rarely end in the middle of a
+/// function or a class.
+///
+/// The outermost nodes that fit in `max_tokens` are packed into chunks in order, and nodes that
+/// don't fit are split along their children. Chunks start at the start of the line of their first
+/// node. Files of languages that tree-sitter doesn't parse are chunked [`by_tokens`].
+pub fn by_scopes<'s>(
+    repo: &str,
+    file: &str,
+    src: &'s str,
+    lang: &str,
+    tokenizer: &Tokenizer,
+    token_bounds: Range<usize>,
+    overlap: OverlapStrategy,
+) -> Vec<Chunk<'s>> {
+    let Ok(tree) = TreeSitterFile::try_build(src.as_bytes(), lang) else {
+        return by_tokens(repo, file, src, tokenizer, token_bounds, overlap);
+    };
+
+    let (tokens, max_tokens) = match tokenize(repo, file, src, tokenizer, token_bounds) {
+        Ok(tokenized) => tokenized,
+        Err(chunks) => return chunks,
+    };
+
+    let line_starts = std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+
+    let mut boundaries = tree
+        .outermost_ranges(|range| tokens.count(range) <= max_tokens)
+        .into_iter()
+        .map(|range| line_starts[line_starts.partition_point(|&s| s <= range.start) - 1])
+        .chain([0, src.len()])
+        .collect::<Vec<_>>();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for node in boundaries.windows(2).map(|w| w[0]..w[1]) {
+        if tokens.count(start..node.end) <= max_tokens {
+            continue;
+        }
+
+        if start < node.start {
+            spans.push(start..node.start);
+        }
+
+        if tokens.count(node.clone()) > max_tokens {
+            tokens.push_split(&mut spans, node.clone(), max_tokens);
+            start = node.end;
+        } else {
+            start = node.start;
+        }
+    }
+
+    if start < src.len() {
+        spans.push(start..src.len());
+    }
+
+    into_chunks(src, spans)
+}
+
+/// The byte offsets where the tokens of a file start, to count the tokens of parts of it.
+struct TokenStarts(Vec<usize>);
+
+impl TokenStarts {
+    fn count(&self, span: Range<usize>) -> usize {
+        self.0.partition_point(|&s| s < span.end) - self.0.partition_point(|&s| s < span.start)
+    }
+
+    /// Push a span, split into pieces of `max_tokens` if it's too long.
+    fn push_split(&self, spans: &mut Vec<Range<usize>>, span: Range<usize>, max_tokens: usize) {
+        let first = self.0.partition_point(|&s| s < span.start);
+        let last = self.0.partition_point(|&s| s < span.end);
+
+        let mut start = span.start;
+        for &cut in self.0[first..last].iter().step_by(max_tokens).skip(1) {
+            spans.push(start..cut);
+            start = cut;
+        }
+        spans.push(start..span.end);
+    }
+}
+
+/// The tokens of a file, and the number of them that fit in a chunk.
+///
+/// Files with less than the minimum number of tokens have no chunks, and files that can't be
+/// encoded are chunked by lines, which is returned as the error.
+fn tokenize<'s>(
+    repo: &str,
+    file: &str,
+    src: &'s str,
+    tokenizer: &Tokenizer,
+    token_bounds: Range<usize>,
+) -> Result<(TokenStarts, usize), Vec<Chunk<'s>>> {
+    let min_tokens = token_bounds.start;
+    if src.len() < min_tokens {
+        return Err(Vec::new());
+    }
+
+    let Ok(encoding) = tokenizer.encode(src, true) else {
+        warn!("Could not encode \"{}\"", src);
+        return Err(by_lines(src, 15));
+    };
+
+    if encoding.get_offsets().len() < min_tokens {
+        return Err(Vec::new());
+    }
+
+    let Some(max_tokens) = max_content_tokens(repo, file, tokenizer, token_bounds.end) else {
+        return Err(Vec::new());
+    };
+
+    // Special tokens have empty offsets, and are left out.
+    let starts = encoding
+        .get_offsets()
+        .iter()
+        .filter(|(start, end)| end > start)
+        .map(|&(start, _)| start)
+        .collect();
+
+    Ok((TokenStarts(starts), max_tokens))
+}
+
+/// The chunks of the spans of a file, in order, leaving out spans of whitespace.
+fn into_chunks(src: &str, spans: Vec<Range<usize>>) -> Vec<Chunk<'_>> {
+    let (mut last_line, mut last_byte) = (0, 0);
+    spans
+        .into_iter()
+        .filter(|span| !src[span.clone()].trim().is_empty())
+        .map(|span| {
+            let start = point(src, span.start, last_line, last_byte);
+            let end = point(src, span.end, start.line, start.byte);
+            (last_line, last_byte) = (start.line, start.byte);
+            Chunk::new(&src[span], start, end)
+        })
+        .collect()
+}
+
 pub fn by_lines(src: &str, size: usize) -> Vec<Chunk<'_>> {
     let ends = std::iter::once(0)
         .chain(src.match_indices('\n').map(|(i, _)| i))
@@ -358,6 +764,203 @@ mod tests {
         tokenizer
     }
 
+    #[test]
+    fn chunk_params_per_language() {
+        let defaults = |max_tokens, strategy| ChunkParams {
+            max_tokens,
+            overlap: OverlapStrategy::default(),
+            strategy: Some(strategy),
+            lines: Some(40),
+            stride: Some(30),
+        };
+
+        let overrides = HashMap::from([
+            (
+                "rust".to_owned(),
+                ChunkParams {
+                    max_tokens: 128,
+                    overlap: OverlapStrategy::ByLines(3),
+                    strategy: None,
+                    lines: None,
+                    stride: None,
+                },
+            ),
+            (
+                "markdown".to_owned(),
+                ChunkParams {
+                    max_tokens: 256,
+                    overlap: OverlapStrategy::default(),
+                    strategy: Some(ChunkStrategy::Lines),
+                    lines: Some(20),
+                    stride: None,
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            ChunkParams::for_language("Rust", &overrides, defaults(256, ChunkStrategy::Tokens)),
+            ChunkParams {
+                max_tokens: 128,
+                overlap: OverlapStrategy::ByLines(3),
+                strategy: Some(ChunkStrategy::Tokens),
+                lines: None,
+                stride: None,
+            }
+        );
+        assert_eq!(
+            ChunkParams::for_language(
+                "Python",
+                &overrides,
+                defaults(256, ChunkStrategy::ContentDefined)
+            ),
+            ChunkParams {
+                max_tokens: 192,
+                overlap: OverlapStrategy::Partial(0.5),
+                strategy: Some(ChunkStrategy::ContentDefined),
+                lines: None,
+                stride: None,
+            }
+        );
+        assert_eq!(
+            ChunkParams::for_language("Go", &overrides, defaults(256, ChunkStrategy::Tokens)),
+            ChunkParams {
+                max_tokens: 256,
+                overlap: OverlapStrategy::default(),
+                strategy: Some(ChunkStrategy::Tokens),
+                lines: None,
+                stride: None,
+            }
+        );
+
+        // Line windows take the configured size and stride unless the language sets them.
+        assert_eq!(
+            ChunkParams::for_language("Markdown", &overrides, defaults(256, ChunkStrategy::Tokens)),
+            ChunkParams {
+                max_tokens: 256,
+                overlap: OverlapStrategy::default(),
+                strategy: Some(ChunkStrategy::Lines),
+                lines: Some(20),
+                stride: Some(30),
+            }
+        );
+
+        // Overrides can't exceed the model's input size.
+        assert_eq!(
+            ChunkParams::for_language("Rust", &overrides, defaults(100, ChunkStrategy::Tokens))
+                .max_tokens,
+            100
+        );
+
+        let parsed: ChunkParams = serde_json::from_str(
+            r#"{"max_tokens": 200, "overlap": "2", "strategy": "content-defined"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.overlap, OverlapStrategy::ByLines(2));
+        assert_eq!(parsed.strategy, Some(ChunkStrategy::ContentDefined));
+
+        // Overlaps are recorded in payloads as strings, and read back.
+        let overlap = OverlapStrategy::Partial(0.5).to_string();
+        assert_eq!(overlap, "50%");
+        assert_eq!(
+            OverlapStrategy::try_from(overlap.as_str()),
+            Ok(OverlapStrategy::Partial(0.5))
+        );
+    }
+
+    #[test]
+    fn content_defined_boundaries_survive_edits() {
+        let tokenizer = minilm();
+        let token_bounds = 50..256;
+
+        let src = (0..400)
+            .map(|i| {
+                format!(
+                    "    let value_{i} = compute(input_{i}, {}) + offset;\n",
+                    i * 7
+                )
+            })
+            .collect::<String>();
+
+        let edited = src.replacen(
+            "let value_200 = ",
+            "// a new comment\n    let value_200 = ",
+            1,
+        );
+
+        let texts = |src| {
+            by_content("bloop", "edited.rs", src, &tokenizer, token_bounds.clone())
+                .into_iter()
+                .map(|c| c.data)
+                .collect::<Vec<_>>()
+        };
+
+        let before = texts(&src);
+        let after = texts(&edited);
+
+        // Chunks cover the whole file, in order, within the token bounds.
+        assert_eq!(before.concat(), src);
+        assert!(before.len() > 5);
+        for chunk in &before {
+            let tokens = tokenizer.encode(*chunk, false).unwrap().get_ids().len();
+            assert!(tokens < token_bounds.end, "{tokens} tokens");
+        }
+
+        // Only the chunks around the edit change.
+        let changed = after.iter().filter(|c| !before.contains(c)).count();
+        assert!(changed <= 2, "{changed} of {} chunks changed", after.len());
+    }
+
+    #[test]
+    fn content_defined_splits_long_lines() {
+        let tokenizer = minilm();
+        let src = "word ".repeat(1000) + "\n";
+
+        let chunks = by_content("bloop", "long.txt", &src, &tokenizer, 50..256);
+        assert!(chunks.len() >= 4);
+        assert_eq!(chunks.iter().map(|c| c.data).collect::<String>(), src);
+    }
+
+    #[test]
+    fn line_windows_overlap_by_stride() {
+        let tokenizer = minilm();
+        let src = (0..100)
+            .map(|i| format!("let value_{i} = {i};\n"))
+            .collect::<String>();
+
+        let chunks = by_line_windows("bloop", "lines.rs", &src, &tokenizer, 50..256, 10, 5);
+        assert_eq!(chunks.len(), 19);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.range.start.line, i * 5);
+            assert_eq!(chunk.data.lines().count(), 10);
+        }
+    }
+
+    #[test]
+    fn scopes_keep_functions_whole() {
+        let tokenizer = minilm();
+        let src = (0..20)
+            .map(|i| {
+                format!("fn add_{i}(value: u32) -> u32 {{\n    let offset = {i};\n    value + offset\n}}\n\n")
+            })
+            .collect::<String>();
+
+        let chunks = by_scopes(
+            "bloop",
+            "scopes.rs",
+            &src,
+            "Rust",
+            &tokenizer,
+            50..128,
+            OverlapStrategy::default(),
+        );
+
+        assert!(chunks.len() > 2);
+        assert_eq!(chunks.iter().map(|c| c.data).collect::<String>(), src);
+        for chunk in &chunks {
+            assert!(chunk.data.starts_with("fn add_"), "{}", chunk.data);
+        }
+    }
+
     #[test]
     pub fn empty() {
         let tokenizer = minilm();
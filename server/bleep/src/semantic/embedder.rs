@@ -7,16 +7,17 @@ use std::{
     },
 };
 
+use anyhow::Context;
 use async_trait::async_trait;
-use ndarray::Axis;
+use ndarray::{s, Array2, Axis, Ix3};
 use ort::{
     tensor::{FromArray, InputTensor, OrtOwnedTensor},
     Environment, ExecutionProvider, GraphOptimizationLevel, LoggingLevel, SessionBuilder,
 };
-use tokenizers::Tokenizer;
-use tracing::trace;
+use tokenizers::{Encoding, Tokenizer};
+use tracing::{info, trace, warn};
 
-use super::Embedding;
+use super::{schema::EMBEDDING_DIM, Embedding};
 
 #[cfg(feature = "ee")]
 pub use crate::ee::embedder::*;
@@ -60,6 +61,8 @@ impl EmbedQueue {
 pub struct EmbedChunk {
     pub id: String,
     pub data: String,
+    /// Identifies the data across file versions, to reuse embeddings of unchanged chunks.
+    pub data_hash: String,
     pub payload: HashMap<String, qdrant_client::qdrant::Value>,
 }
 
@@ -70,18 +73,119 @@ pub trait Embedder: Send + Sync {
     async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>>;
 }
 
+/// The name of the model in `model_dir`.
+pub const DEFAULT_MODEL: &str = "default";
+
+/// An embedding model, by its configured name and a version derived from its weights.
+#[derive(Clone)]
+pub struct EmbeddingModel {
+    pub name: String,
+    pub version: String,
+    pub embedder: Arc<dyn Embedder>,
+}
+
+impl EmbeddingModel {
+    /// Load the model in `model_dir`.
+    pub fn load(name: &str, model_dir: &Path, accelerator: Accelerator) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: name.to_owned(),
+            version: model_version(model_dir)?,
+            embedder: Arc::new(LocalEmbedder::new(model_dir, accelerator)?),
+        })
+    }
+
+    /// The model as it is recorded in the payloads of the points it embedded.
+    pub fn id(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+/// The version of the model in `model_dir`, which changes whenever the model is replaced, for
+/// instance with one fine-tuned on an exported corpus.
+pub fn model_version(model_dir: &Path) -> anyhow::Result<String> {
+    let path = model_dir.join("model.onnx");
+    let model = std::fs::read(&path)
+        .with_context(|| format!("failed to read embedding model {}", path.display()))?;
+
+    Ok(blake3::hash(&model).to_hex()[..16].to_owned())
+}
+
+/// The hardware that local embedding models run on.
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Accelerator {
+    #[default]
+    Cpu,
+    /// The first of the accelerators below that the ONNX runtime supports on this machine.
+    Auto,
+    /// NVIDIA GPUs, with an ONNX runtime built with CUDA.
+    Cuda,
+    /// The GPU and neural engine of Apple devices.
+    #[value(name = "coreml")]
+    #[serde(rename = "coreml")]
+    CoreMl,
+    /// GPUs on Windows, through DirectX 12.
+    #[value(name = "directml")]
+    #[serde(rename = "directml")]
+    DirectMl,
+}
+
+impl Accelerator {
+    const ALL: [Self; 3] = [Self::Cuda, Self::CoreMl, Self::DirectMl];
+
+    fn provider(self) -> ExecutionProvider {
+        match self {
+            Self::Cpu | Self::Auto => ExecutionProvider::cpu(),
+            Self::Cuda => ExecutionProvider::cuda(),
+            Self::CoreMl => ExecutionProvider::coreml(),
+            Self::DirectMl => ExecutionProvider::directml(),
+        }
+    }
+
+    /// The accelerator to run on, which is the CPU when the requested one isn't available.
+    fn detect(self) -> Self {
+        let available = |a: &Self| a.provider().is_available();
+
+        match self {
+            Self::Cpu => Self::Cpu,
+            Self::Auto => Self::ALL.into_iter().find(available).unwrap_or(Self::Cpu),
+            requested if available(&requested) => requested,
+            requested => {
+                warn!(
+                    ?requested,
+                    "embedding accelerator is not available, falling back to the CPU"
+                );
+                Self::Cpu
+            }
+        }
+    }
+}
+
 pub struct LocalEmbedder {
     session: ort::Session,
     tokenizer: Tokenizer,
 }
 
 impl LocalEmbedder {
-    pub fn new(model_dir: &Path) -> anyhow::Result<Self> {
+    pub fn new(model_dir: &Path, accelerator: Accelerator) -> anyhow::Result<Self> {
+        let accelerator = accelerator.detect();
+
+        // The runtime moves on to the CPU provider if the accelerator fails to initialize.
         let environment = Arc::new(
             Environment::builder()
                 .with_name("Encode")
                 .with_log_level(LoggingLevel::Warning)
-                .with_execution_providers([ExecutionProvider::cpu()])
+                .with_execution_providers([accelerator.provider(), ExecutionProvider::cpu()])
                 .with_telemetry(false)
                 .build()?,
         );
@@ -98,8 +202,78 @@ impl LocalEmbedder {
             .with_model_from_file(model_dir.join("model.onnx"))?;
 
         let tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json")).unwrap();
+        let embedder = Self { session, tokenizer };
+
+        // Replacement models, such as ones fine-tuned on an exported corpus, must fit the
+        // collection schema.
+        let dims = embedder.embed("fn main() {}")?.len();
+        if dims != EMBEDDING_DIM {
+            anyhow::bail!(
+                "the model in {} produces {dims}-dimensional embeddings, expected {EMBEDDING_DIM}",
+                model_dir.display()
+            );
+        }
+
+        info!(
+            ?accelerator,
+            model_dir = %model_dir.display(),
+            "loaded embedding model"
+        );
+
+        Ok(embedder)
+    }
+}
+
+impl LocalEmbedder {
+    /// Embed sequences in a single run of the model.
+    ///
+    /// Shorter sequences are padded to the longest one, and the padding is left out of the mean
+    /// that pools their token embeddings, so that each embedding matches [`Embedder::embed`].
+    fn embed_all(&self, sequences: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        if sequences.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let encodings = self
+            .tokenizer
+            .encode_batch(sequences, true)
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        let batch = encodings.len();
+        let length = encodings.iter().map(|e| e.len()).max().unwrap_or_default();
+        trace!(batch, length, "embedding batch");
+
+        let padded = |field: fn(&Encoding) -> &[u32]| {
+            let mut array = Array2::<i64>::zeros((batch, length));
+            for (i, encoding) in encodings.iter().enumerate() {
+                for (j, &x) in field(encoding).iter().enumerate() {
+                    array[[i, j]] = x as i64;
+                }
+            }
+            array
+        };
+
+        let outputs = self.session.run([
+            InputTensor::from_array(padded(Encoding::get_ids).into_dyn()),
+            InputTensor::from_array(padded(Encoding::get_attention_mask).into_dyn()),
+            InputTensor::from_array(padded(Encoding::get_type_ids).into_dyn()),
+        ])?;
+
+        let output_tensor: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let hidden = output_tensor.view().into_dimensionality::<Ix3>()?;
 
-        Ok(Self { session, tokenizer })
+        Ok(encodings
+            .iter()
+            .enumerate()
+            .map(|(i, encoding)| {
+                let tokens = encoding.len().max(1);
+                hidden
+                    .slice(s![i, ..tokens, ..])
+                    .mean_axis(Axis(0))
+                    .unwrap()
+                    .to_vec()
+            })
+            .collect())
     }
 }
 
@@ -146,8 +320,6 @@ impl Embedder for LocalEmbedder {
     }
 
     async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
-        log.into_iter()
-            .map(|entry| tokio::task::block_in_place(|| self.embed(entry)))
-            .collect::<anyhow::Result<Vec<Embedding>>>()
+        tokio::task::block_in_place(|| self.embed_all(log))
     }
 }
@@ -0,0 +1,149 @@
+//! Vendored and generated code, which is left out of semantic search.
+//!
+//! Dependencies checked into a repository, lockfiles, and minified or generated sources crowd out
+//! the code that people wrote, so they are not embedded. Points embedded before a file was
+//! excluded are also dropped from search results.
+//!
+//! Files are excluded by gitignore-style globs over their relative paths, by the length of their
+//! lines, and by the markers that code generators leave at their top.
+
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use super::Payload;
+use crate::Configuration;
+
+/// Globs that are excluded unless `disable_default_semantic_exclusions` is set.
+const DEFAULT_GLOBS: &[&str] = &[
+    "vendor/",
+    "node_modules/",
+    "bower_components/",
+    "third_party/",
+    "*.min.js",
+    "*.min.css",
+    "*.map",
+    "*.lock",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "go.sum",
+    "*.pb.go",
+    "*_pb2.py",
+    "*.generated.*",
+];
+
+/// Markers of generated code, matched in the first `MARKER_LINES` lines of a file.
+const GENERATED_MARKERS: &[&str] = &[
+    "@generated",
+    "DO NOT EDIT",
+    "Code generated by",
+    "auto-generated",
+    "autogenerated",
+];
+
+const MARKER_LINES: usize = 5;
+
+pub struct Exclusions {
+    globs: Gitignore,
+    /// Files with longer lines on average are considered minified. Disabled when 0.
+    max_avg_line_length: usize,
+    detect_generated: bool,
+}
+
+impl Exclusions {
+    pub fn load(config: &Configuration) -> anyhow::Result<Self> {
+        let defaults = if config.disable_default_semantic_exclusions {
+            &[][..]
+        } else {
+            DEFAULT_GLOBS
+        };
+
+        Self::new(
+            defaults
+                .iter()
+                .copied()
+                .chain(config.semantic_exclusions.iter().map(String::as_str)),
+            config.semantic_max_avg_line_length,
+            !config.disable_default_semantic_exclusions,
+        )
+    }
+
+    fn new<'a>(
+        globs: impl Iterator<Item = &'a str>,
+        max_avg_line_length: usize,
+        detect_generated: bool,
+    ) -> anyhow::Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for glob in globs {
+            builder
+                .add_line(None, glob)
+                .with_context(|| format!("invalid semantic exclusion `{glob}`"))?;
+        }
+
+        Ok(Self {
+            globs: builder.build()?,
+            max_avg_line_length,
+            detect_generated,
+        })
+    }
+
+    /// Whether a file is left out of the semantic index.
+    pub fn excludes_file(&self, relative_path: &str, content: &str) -> bool {
+        self.excludes_path(relative_path) || self.is_minified(content) || self.is_generated(content)
+    }
+
+    /// Whether a snippet is left out of search results.
+    ///
+    /// Only snippets from the top of a file have the markers of generated code.
+    pub fn excludes_snippet(&self, payload: &Payload) -> bool {
+        self.excludes_path(&payload.relative_path)
+            || self.is_minified(&payload.text)
+            || (payload.start_line == 0 && self.is_generated(&payload.text))
+    }
+
+    fn excludes_path(&self, relative_path: &str) -> bool {
+        self.globs
+            .matched_path_or_any_parents(relative_path, false)
+            .is_ignore()
+    }
+
+    fn is_minified(&self, content: &str) -> bool {
+        if self.max_avg_line_length == 0 {
+            return false;
+        }
+
+        let lines = content.lines().count().max(1);
+        content.len() / lines > self.max_avg_line_length
+    }
+
+    fn is_generated(&self, content: &str) -> bool {
+        self.detect_generated
+            && content
+                .lines()
+                .take(MARKER_LINES)
+                .any(|line| GENERATED_MARKERS.iter().any(|m| line.contains(m)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_vendored_and_generated_files() {
+        let globs = DEFAULT_GLOBS.iter().copied().chain(["docs/api/*.ts"]);
+        let exclusions = Exclusions::new(globs, 200, true).unwrap();
+        let code = "fn main() {\n    println!(\"hello\");\n}\n";
+
+        assert!(exclusions.excludes_file("vendor/github.com/pkg/errors/errors.go", code));
+        assert!(exclusions.excludes_file("client/node_modules/react/index.js", code));
+        assert!(exclusions.excludes_file("Cargo.lock", code));
+        assert!(exclusions.excludes_file("public/app.min.js", code));
+        assert!(exclusions.excludes_file("docs/api/client.ts", code));
+        assert!(exclusions.excludes_file("src/bundle.js", &"x".repeat(5000)));
+        assert!(exclusions.excludes_file("src/api.rs", &format!("// @generated\n{code}")));
+
+        assert!(!exclusions.excludes_file("src/main.rs", code));
+        assert!(!exclusions.excludes_file("src/vendors.rs", code));
+        assert!(!exclusions.excludes_file("docs/api/v2/client.ts", code));
+    }
+}
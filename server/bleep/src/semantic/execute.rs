@@ -1,14 +1,15 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::RangeInclusive};
 
 use crate::{
     query::{
         execute::{ApiQuery, PagingMetadata, QueryResponse, QueryResult, ResultStats},
-        parser::SemanticQuery,
+        parser::{Literal, SemanticQuery},
     },
-    snippet::Snippet,
+    repo::RepoRef,
+    snippet::{SnippedFile, Snippet},
 };
 
-use super::Semantic;
+use super::{Payload, SearchFilters, Semantic};
 
 use anyhow::Result;
 
@@ -27,36 +28,7 @@ pub async fn execute(
         )
         .await?;
 
-    let data = results
-        .into_iter()
-        .fold(HashMap::new(), |mut acc, payload| {
-            acc.entry((
-                payload.relative_path.to_string(),
-                payload.repo_name.to_string(),
-                payload.repo_ref.to_string(),
-                Some(payload.lang.to_string()),
-            ))
-            .or_insert_with(Vec::new)
-            .push(Snippet {
-                data: payload.text.to_string(),
-                line_range: payload.start_line as usize..payload.end_line as usize,
-                highlights: vec![],
-                symbols: vec![],
-            });
-
-            acc
-        })
-        .into_iter()
-        .map(|((relative_path, repo_name, repo_ref, lang), snippets)| {
-            QueryResult::Snippets(crate::snippet::SnippedFile {
-                relative_path,
-                repo_name,
-                repo_ref,
-                snippets,
-                lang,
-            })
-        })
-        .collect::<Vec<_>>();
+    let data = snippets(results);
     Ok(QueryResponse {
         count: data.len(),
         metadata: PagingMetadata::new(params.page, params.page_size, None),
@@ -64,3 +36,140 @@ pub async fn execute(
         data,
     })
 }
+
+/// A selection of code in an indexed file.
+pub struct Selection {
+    pub repo_ref: RepoRef,
+    pub relative_path: String,
+    /// 0-indexed lines of the selection, inclusive.
+    pub lines: RangeInclusive<usize>,
+}
+
+impl Selection {
+    fn overlaps(&self, payload: &Payload) -> bool {
+        payload.repo_ref == self.repo_ref.to_string()
+            && payload.relative_path == self.relative_path
+            && payload.start_line as usize <= *self.lines.end()
+            && payload.end_line as usize >= *self.lines.start()
+    }
+}
+
+/// Find the `limit` snippets that are nearest to a piece of code, in the repositories of `scope`,
+/// or all of them when it's empty.
+///
+/// The snippets that overlap with `selection`, which the code was taken from, are left out.
+pub async fn similar(
+    semantic: Semantic,
+    code: &str,
+    scope: &[RepoRef],
+    selection: Option<&Selection>,
+    limit: u64,
+) -> Result<QueryResponse> {
+    let query = SemanticQuery {
+        repos: scope
+            .iter()
+            .map(|r| Literal::Plain(r.indexed_name().into()))
+            .collect(),
+        target: Some(Literal::Plain(code.into())),
+        ..Default::default()
+    };
+    let filters = SearchFilters {
+        repo_refs: scope.iter().map(RepoRef::to_string).collect(),
+        ..Default::default()
+    };
+
+    // The selection itself is usually the nearest snippet of all.
+    let (mut results, _) = semantic
+        .search_with_stats(&query, &filters, limit + 1, 0, 0.0, true)
+        .await?;
+    if let Some(selection) = selection {
+        results.retain(|payload| !selection.overlaps(payload));
+    }
+    results.truncate(limit as usize);
+
+    let data = snippets(results);
+    Ok(QueryResponse {
+        count: data.len(),
+        metadata: PagingMetadata::new(0, limit as usize, None),
+        stats: ResultStats::default(),
+        data,
+    })
+}
+
+/// Group search results by file, in the order of the first result of each file.
+pub fn snippets(results: Vec<Payload>) -> Vec<QueryResult> {
+    let mut files = Vec::<SnippedFile>::new();
+    let mut positions = HashMap::new();
+
+    for payload in results {
+        let key = (payload.repo_ref.clone(), payload.relative_path.clone());
+        let position = *positions.entry(key).or_insert_with(|| {
+            files.push(SnippedFile {
+                relative_path: payload.relative_path.clone(),
+                repo_name: payload.repo_name.clone(),
+                repo_ref: payload.repo_ref.clone(),
+                snippets: vec![],
+                lang: Some(payload.lang.clone()),
+                license: payload.license.clone(),
+            });
+            files.len() - 1
+        });
+
+        files[position].snippets.push(Snippet {
+            data: payload.text,
+            line_range: payload.start_line as usize..payload.end_line as usize,
+            highlights: vec![],
+            symbols: vec![],
+        });
+    }
+
+    files.into_iter().map(QueryResult::Snippets).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(relative_path: &str, start_line: u64, end_line: u64) -> Payload {
+        Payload {
+            repo_ref: "github.com/BloopAI/bloop".into(),
+            relative_path: relative_path.into(),
+            start_line,
+            end_line,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn leaves_out_the_selection() {
+        let selection = Selection {
+            repo_ref: "github.com/BloopAI/bloop".parse().unwrap(),
+            relative_path: "src/main.rs".into(),
+            lines: 10..=20,
+        };
+
+        assert!(selection.overlaps(&payload("src/main.rs", 5, 10)));
+        assert!(selection.overlaps(&payload("src/main.rs", 12, 14)));
+        assert!(selection.overlaps(&payload("src/main.rs", 20, 30)));
+        assert!(!selection.overlaps(&payload("src/main.rs", 21, 30)));
+        assert!(!selection.overlaps(&payload("src/lib.rs", 12, 14)));
+    }
+
+    #[test]
+    fn groups_snippets_in_order() {
+        let results = snippets(vec![
+            payload("src/b.rs", 0, 5),
+            payload("src/a.rs", 0, 5),
+            payload("src/b.rs", 10, 15),
+        ]);
+
+        let files = results
+            .iter()
+            .map(|r| match r {
+                QueryResult::Snippets(file) => (file.relative_path.as_str(), file.snippets.len()),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(files, [("src/b.rs", 2), ("src/a.rs", 1)]);
+    }
+}
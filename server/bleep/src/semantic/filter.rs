@@ -0,0 +1,267 @@
+//! Structured filters of semantic search, translated into Qdrant payload filters.
+//!
+//! Qdrant can only match whole words of paths, so path globs are narrowed down by the words they
+//! require, and then matched exactly against the returned payloads.
+
+use qdrant_client::qdrant::{
+    condition::ConditionOneOf, r#match::MatchValue, Condition, FieldCondition, Filter,
+};
+use regex::Regex;
+
+use super::{make_kv_keyword_filter, make_kv_text_filter, Payload};
+use crate::query::parser::SemanticQuery;
+
+#[derive(Default, Debug, Clone)]
+pub struct SearchFilters {
+    /// The repositories to search, by `repo_ref`. All of them when empty.
+    pub repo_refs: Vec<String>,
+    /// The languages to search, as lowercase names. All of them when empty.
+    pub langs: Vec<String>,
+    /// Globs over the relative paths to search, of which a path must match one.
+    pub paths: Vec<PathGlob>,
+}
+
+impl SearchFilters {
+    /// The filters of the `lang:` qualifiers of a query, and of its `path:` qualifiers that are
+    /// globs.
+    ///
+    /// Other `path:` qualifiers keep matching paths that contain them.
+    pub fn from_query(query: &SemanticQuery<'_>) -> Self {
+        Self {
+            repo_refs: vec![],
+            langs: query.langs().map(|l| l.to_ascii_lowercase()).collect(),
+            paths: query
+                .paths()
+                .filter(|p| PathGlob::is_glob(p))
+                .map(|p| PathGlob::new(&p))
+                .collect(),
+        }
+    }
+
+    /// The Qdrant conditions that a point must all satisfy.
+    pub fn conditions(&self) -> Vec<Condition> {
+        let repo_filter = any_of(
+            self.repo_refs
+                .iter()
+                .map(|r| make_kv_keyword_filter("repo_ref", r).into())
+                .collect(),
+        );
+
+        let lang_filter = any_of(
+            self.langs
+                .iter()
+                .map(|l| make_kv_keyword_filter("lang", l).into())
+                .collect(),
+        );
+
+        // A glob that requires no words matches paths that Qdrant can't tell apart.
+        let words = self
+            .paths
+            .iter()
+            .map(|glob| glob.words.join(" "))
+            .collect::<Vec<_>>();
+        let path_filter = if words.iter().any(String::is_empty) {
+            None
+        } else {
+            any_of(
+                words
+                    .iter()
+                    .map(|w| make_kv_text_filter("relative_path", w).into())
+                    .collect(),
+            )
+        };
+
+        [repo_filter, lang_filter, path_filter]
+            .into_iter()
+            .flatten()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Whether a payload returned by Qdrant matches the path globs exactly.
+    pub fn matches(&self, payload: &Payload) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|glob| glob.regex.is_match(&payload.relative_path))
+    }
+}
+
+fn any_of(conditions: Vec<Condition>) -> Option<Filter> {
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(Filter {
+            should: conditions,
+            ..Default::default()
+        })
+    }
+}
+
+/// A compact rendering of the conditions that a point must all satisfy, for tracing and
+/// diagnostics, such as `repo_ref = "github.com/BloopAI/bloop" AND (lang = "rust" OR lang = "go")`.
+pub fn describe(conditions: &[Condition]) -> String {
+    conditions
+        .iter()
+        .map(describe_condition)
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+fn describe_condition(condition: &Condition) -> String {
+    match &condition.condition_one_of {
+        Some(ConditionOneOf::Field(field)) => describe_field(field),
+        Some(ConditionOneOf::Filter(filter)) => {
+            let mut clauses = vec![];
+            if !filter.must.is_empty() {
+                clauses.push(describe(&filter.must));
+            }
+            if !filter.should.is_empty() {
+                let any = filter
+                    .should
+                    .iter()
+                    .map(describe_condition)
+                    .collect::<Vec<_>>();
+                clauses.push(any.join(" OR "));
+            }
+            if !filter.must_not.is_empty() {
+                clauses.push(format!("NOT ({})", describe(&filter.must_not)));
+            }
+            format!("({})", clauses.join(" AND "))
+        }
+        other => format!("{other:?}"),
+    }
+}
+
+fn describe_field(field: &FieldCondition) -> String {
+    match field.r#match.as_ref().and_then(|m| m.match_value.as_ref()) {
+        Some(MatchValue::Keyword(k)) => format!("{} = {k:?}", field.key),
+        Some(MatchValue::Text(t)) => format!("{} ~ {t:?}", field.key),
+        Some(MatchValue::Integer(i)) => format!("{} = {i}", field.key),
+        Some(MatchValue::Boolean(b)) => format!("{} = {b}", field.key),
+        _ => format!("{field:?}"),
+    }
+}
+
+/// A glob over relative paths, where `*` and `?` match within a path segment, and `**` matches
+/// across them.
+#[derive(Debug, Clone)]
+pub struct PathGlob {
+    regex: Regex,
+    /// The words that any matching path contains, as Qdrant's full-text index splits them.
+    words: Vec<String>,
+}
+
+impl PathGlob {
+    pub fn is_glob(pattern: &str) -> bool {
+        pattern.contains(['*', '?'])
+    }
+
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            regex: glob_regex(pattern),
+            words: required_words(pattern),
+        }
+    }
+}
+
+fn glob_regex(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    let mut rest = pattern;
+
+    while let Some(c) = rest.chars().next() {
+        let (fragment, len) = if rest.starts_with("**/") {
+            ("(?:.*/)?".to_owned(), 3)
+        } else if rest.starts_with("**") {
+            (".*".to_owned(), 2)
+        } else if c == '*' {
+            ("[^/]*".to_owned(), 1)
+        } else if c == '?' {
+            ("[^/]".to_owned(), 1)
+        } else {
+            (regex::escape(&c.to_string()), c.len_utf8())
+        };
+
+        regex.push_str(&fragment);
+        rest = &rest[len..];
+    }
+
+    regex.push('$');
+    Regex::new(&regex).expect("escaped glob is a valid regex")
+}
+
+/// The words of the literal parts of a glob that can't be extended by a wildcard next to them.
+fn required_words(pattern: &str) -> Vec<String> {
+    let literals = pattern.split(['*', '?']).collect::<Vec<_>>();
+    let last = literals.len() - 1;
+
+    literals
+        .iter()
+        .enumerate()
+        .flat_map(|(i, literal)| {
+            let mut words = literal
+                .split(|c: char| !c.is_alphanumeric())
+                .collect::<Vec<_>>();
+
+            // Words that touch a wildcard may be the middle of a longer word of the path.
+            if i > 0 {
+                words[0] = "";
+            }
+            if i < last {
+                *words.last_mut().unwrap() = "";
+            }
+
+            words
+        })
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        PathGlob::new(pattern).regex.is_match(path)
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/semantic/filter.rs"));
+        assert!(matches("src/**/*.rs", "src/main.rs"));
+        assert!(matches("src/**/*.rs", "src/semantic/filter.rs"));
+        assert!(matches("**/test?.py", "a/b/test1.py"));
+        assert!(!matches("**/test?.py", "a/b/test12.py"));
+        assert!(!matches("*.c", "main.cc"));
+    }
+
+    #[test]
+    fn describes_conditions() {
+        let filters = SearchFilters {
+            repo_refs: vec!["github.com/BloopAI/bloop".into()],
+            langs: vec!["rust".into(), "go".into()],
+            paths: vec![PathGlob::new("server/**/*.rs")],
+        };
+
+        assert_eq!(
+            describe(&filters.conditions()),
+            "(repo_ref = \"github.com/BloopAI/bloop\") AND (lang = \"rust\" OR lang = \"go\") \
+             AND (relative_path ~ \"server rs\")"
+        );
+        assert_eq!(describe(&[]), "");
+    }
+
+    #[test]
+    fn glob_words() {
+        assert_eq!(
+            required_words("server/bleep/**/*.rs"),
+            ["server", "bleep", "rs"]
+        );
+        assert_eq!(required_words("src/sem*/mod.rs"), ["src", "mod", "rs"]);
+        assert_eq!(required_words("*s.rs"), ["rs"]);
+        assert!(required_words("**/*").is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+//! Dimensionality reduction of embeddings, to save memory on large installs.
+//!
+//! The reduction of a collection is stored with it, and both the embeddings that are upserted and
+//! the query embeddings are reduced with the stored transform. See `Configuration::embedding_dims`.
+
+use async_trait::async_trait;
+use tokenizers::Tokenizer;
+
+use super::{Embedder, Embedding};
+
+/// The number of power iterations run for each principal component.
+const PCA_ITERATIONS: usize = 50;
+
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// Keep the leading dimensions, for models trained with Matryoshka representation learning.
+    #[default]
+    Truncate,
+    /// Project onto the principal components of the embeddings already in the index.
+    Pca,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum Reduction {
+    Truncate {
+        dims: usize,
+    },
+    Pca {
+        mean: Vec<f32>,
+        /// Unit-length principal components, largest variance first.
+        components: Vec<Vec<f32>>,
+    },
+}
+
+impl Reduction {
+    pub fn dims(&self) -> usize {
+        match self {
+            Self::Truncate { dims } => *dims,
+            Self::Pca { components, .. } => components.len(),
+        }
+    }
+
+    pub fn method(&self) -> Method {
+        match self {
+            Self::Truncate { .. } => Method::Truncate,
+            Self::Pca { .. } => Method::Pca,
+        }
+    }
+
+    pub fn apply(&self, embedding: &[f32]) -> Embedding {
+        let reduced = match self {
+            Self::Truncate { dims } => embedding[..(*dims).min(embedding.len())].to_vec(),
+            Self::Pca { mean, components } => {
+                let centered = embedding
+                    .iter()
+                    .zip(mean)
+                    .map(|(x, m)| x - m)
+                    .collect::<Vec<_>>();
+
+                components.iter().map(|c| dot(c, &centered)).collect()
+            }
+        };
+
+        normalize(reduced)
+    }
+
+    /// Fit a PCA reduction to `dims` dimensions on sample embeddings.
+    ///
+    /// Components are found one at a time by power iteration on the covariance matrix, keeping
+    /// each orthogonal to the ones found before it.
+    pub fn fit_pca(samples: &[Embedding], dims: usize) -> anyhow::Result<Self> {
+        let Some(n) = samples.first().map(Vec::len) else {
+            anyhow::bail!("no samples to fit PCA on");
+        };
+
+        if samples.len() <= dims {
+            anyhow::bail!(
+                "{} samples are too few to fit {dims} components",
+                samples.len()
+            );
+        }
+
+        let count = samples.len() as f32;
+        let mut mean = vec![0.0; n];
+        for sample in samples {
+            for (m, x) in mean.iter_mut().zip(sample) {
+                *m += x / count;
+            }
+        }
+
+        let mut covariance = vec![vec![0.0; n]; n];
+        for sample in samples {
+            let centered = sample
+                .iter()
+                .zip(&mean)
+                .map(|(x, m)| x - m)
+                .collect::<Vec<_>>();
+
+            for (i, row) in covariance.iter_mut().enumerate() {
+                for (j, c) in row.iter_mut().enumerate() {
+                    *c += centered[i] * centered[j] / count;
+                }
+            }
+        }
+
+        let mut components: Vec<Vec<f32>> = Vec::with_capacity(dims);
+        for k in 0..dims {
+            // A fixed, non-degenerate starting vector, so that fits are reproducible.
+            let mut v = normalize(
+                (0..n)
+                    .map(|i| ((i * 7919 + k * 104_729) % 1000) as f32 / 1000.0 - 0.5)
+                    .collect(),
+            );
+
+            for _ in 0..PCA_ITERATIONS {
+                let mut w = covariance
+                    .iter()
+                    .map(|row| dot(row, &v))
+                    .collect::<Vec<_>>();
+
+                for c in &components {
+                    let projection = dot(&w, c);
+                    w.iter_mut().zip(c).for_each(|(x, c)| *x -= projection * c);
+                }
+
+                v = normalize(w);
+            }
+
+            components.push(v);
+        }
+
+        Ok(Self::Pca { mean, components })
+    }
+}
+
+/// An embedder that reduces the embeddings of another.
+pub struct ReducedEmbedder<E: ?Sized> {
+    inner: std::sync::Arc<E>,
+    reduction: Reduction,
+}
+
+impl<E: Embedder + ?Sized> ReducedEmbedder<E> {
+    pub fn new(inner: std::sync::Arc<E>, reduction: Reduction) -> Self {
+        Self { inner, reduction }
+    }
+}
+
+#[async_trait]
+impl<E: Embedder + ?Sized> Embedder for ReducedEmbedder<E> {
+    fn embed(&self, data: &str) -> anyhow::Result<Embedding> {
+        Ok(self.reduction.apply(&self.inner.embed(data)?))
+    }
+
+    fn tokenizer(&self) -> &Tokenizer {
+        self.inner.tokenizer()
+    }
+
+    async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        Ok(self
+            .inner
+            .batch_embed(log)
+            .await?
+            .iter()
+            .map(|e| self.reduction.apply(e))
+            .collect())
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = dot(&v, &v).sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates() {
+        let reduction = Reduction::Truncate { dims: 2 };
+        assert_eq!(reduction.apply(&[3.0, 4.0, 12.0]), [0.6, 0.8]);
+        assert_eq!(reduction.dims(), 2);
+    }
+
+    #[test]
+    fn fits_pca() {
+        // Points along (1, 1, 0) with a little noise along (1, -1, 0), centered on (1, 1, 1).
+        let samples = (-10..=10)
+            .flat_map(|i| {
+                let t = i as f32;
+                [
+                    vec![1.0 + t + 0.1, 1.0 + t - 0.1, 1.0],
+                    vec![1.0 + t - 0.1, 1.0 + t + 0.1, 1.0],
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let reduction = Reduction::fit_pca(&samples, 2).unwrap();
+        let Reduction::Pca { mean, components } = &reduction else {
+            panic!("expected a PCA reduction");
+        };
+
+        assert!(mean.iter().all(|m| (m - 1.0).abs() < 1e-4));
+
+        let first = [
+            components[0][0].abs(),
+            components[0][1].abs(),
+            components[0][2],
+        ];
+        assert!((first[0] - 0.5f32.sqrt()).abs() < 1e-3, "{first:?}");
+        assert!((first[1] - 0.5f32.sqrt()).abs() < 1e-3, "{first:?}");
+        assert!(first[2].abs() < 1e-3, "{first:?}");
+        assert!(dot(&components[0], &components[1]).abs() < 1e-3);
+
+        // Points on either side of the mean stay on either side once reduced.
+        let a = reduction.apply(&[5.0, 5.0, 1.0]);
+        let b = reduction.apply(&[-3.0, -3.0, 1.0]);
+        assert_eq!(a.len(), 2);
+        assert!(dot(&a, &b) < -0.9);
+
+        assert!(Reduction::fit_pca(&samples[..2], 2).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Reranking of semantic search results with a cross-encoder.
+//!
+//! Embeddings of the question and of the code are computed separately, while a cross-encoder reads
+//! them together. This orders results by relevance much more accurately, at the cost of running
+//! the model once per result, so only the candidates returned by Qdrant are reranked.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::anyhow;
+use ort::{
+    tensor::{FromArray, InputTensor, OrtOwnedTensor},
+    Environment, ExecutionProvider, GraphOptimizationLevel, LoggingLevel, SessionBuilder,
+};
+use tokenizers::Tokenizer;
+use tracing::trace;
+
+use super::Payload;
+
+/// The most tokens of a question and snippet pair that are scored, which is the input size of
+/// common cross-encoders.
+const MAX_TOKENS: usize = 512;
+
+pub struct Reranker {
+    session: ort::Session,
+    tokenizer: Tokenizer,
+}
+
+impl Reranker {
+    /// Load a cross-encoder from the `model.onnx` and `tokenizer.json` files in `model_dir`.
+    pub fn new(model_dir: &Path) -> anyhow::Result<Self> {
+        let environment = Arc::new(
+            Environment::builder()
+                .with_name("Rerank")
+                .with_log_level(LoggingLevel::Warning)
+                .with_execution_providers([ExecutionProvider::cpu()])
+                .with_telemetry(false)
+                .build()?,
+        );
+
+        let session = SessionBuilder::new(&environment)?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_model_from_file(model_dir.join("model.onnx"))?;
+
+        let tokenizer =
+            Tokenizer::from_file(model_dir.join("tokenizer.json")).map_err(|e| anyhow!(e))?;
+
+        Ok(Self { session, tokenizer })
+    }
+
+    /// The relevance of `text` to `query`, between 0 and 1.
+    pub fn score(&self, query: &str, text: &str) -> anyhow::Result<f32> {
+        let encoding = self
+            .tokenizer
+            .encode((query, text), true)
+            .map_err(|e| anyhow!(e))?;
+
+        let length = encoding.get_ids().len().min(MAX_TOKENS);
+        let tensor = |values: &[u32]| {
+            ndarray::Array::from_shape_vec(
+                (1, length),
+                values[..length].iter().map(|&x| x as i64).collect(),
+            )
+            .map(|array| InputTensor::from_array(array.into_dyn()))
+        };
+
+        let outputs = self.session.run([
+            tensor(encoding.get_ids())?,
+            tensor(encoding.get_attention_mask())?,
+            tensor(encoding.get_type_ids())?,
+        ])?;
+
+        let logits: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let logit = *logits
+            .view()
+            .iter()
+            .next()
+            .ok_or_else(|| anyhow!("no logits"))?;
+        trace!(length, logit, "scored snippet");
+
+        Ok(sigmoid(logit))
+    }
+
+    /// Replace the scores of `payloads` with their relevance to `query`, and order them by it.
+    pub fn rerank(&self, query: &str, payloads: &mut [Payload]) -> anyhow::Result<()> {
+        let scores = payloads
+            .iter()
+            .map(|payload| self.score(query, &payload.text))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        apply_scores(payloads, scores);
+        Ok(())
+    }
+}
+
+fn apply_scores(payloads: &mut [Payload], scores: Vec<f32>) {
+    for (payload, score) in payloads.iter_mut().zip(scores) {
+        payload.score = Some(score);
+    }
+
+    payloads.sort_by(|a, b| {
+        b.score
+            .unwrap_or_default()
+            .total_cmp(&a.score.unwrap_or_default())
+    });
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_by_score() {
+        let mut payloads = ["a.rs", "b.rs", "c.rs"].map(|path| Payload {
+            relative_path: path.to_owned(),
+            score: Some(0.9),
+            ..Default::default()
+        });
+
+        apply_scores(&mut payloads, vec![0.2, 0.7, 0.5]);
+
+        let ranked = payloads
+            .iter()
+            .map(|p| (p.relative_path.as_str(), p.score.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, [("b.rs", 0.7), ("c.rs", 0.5), ("a.rs", 0.2)]);
+    }
+
+    #[test]
+    fn squashes_logits() {
+        assert_eq!(sigmoid(0.0), 0.5);
+        assert!(sigmoid(8.0) > 0.99);
+        assert!(sigmoid(-8.0) < 0.01);
+    }
+}
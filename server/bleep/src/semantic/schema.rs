@@ -1,14 +1,13 @@
 //! Every change in this file will trigger a reset of the databases.
 //! Use with care.
 //!
-use qdrant_client::{
-    prelude::QdrantClient,
-    qdrant::{
-        vectors_config, CollectionOperationResponse, CreateCollection, Distance, VectorParams,
-        VectorsConfig,
-    },
+use qdrant_client::qdrant::{
+    vectors_config, CollectionOperationResponse, CreateCollection, Distance, VectorParams,
+    VectorsConfig,
 };
 
+use super::store::VectorStore;
+
 pub(super) const EMBEDDING_DIM: usize = 384;
 pub type Embedding = Vec<f32>;
 
@@ -25,6 +24,16 @@ pub struct Payload {
     pub start_byte: u64,
     pub end_byte: u64,
     pub branches: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// How the file was chunked. Points indexed before chunk parameters were recorded don't
+    /// have them, and were chunked with the defaults of their time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_params: Option<super::chunk::ChunkParams>,
+    /// The model that embedded the point, as `name@version`. Points indexed before models were
+    /// recorded don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding_model: Option<String>,
 
     #[serde(skip)]
     pub id: Option<String>,
@@ -47,6 +56,9 @@ impl PartialEq for Payload {
             && self.start_byte == other.start_byte
             && self.end_byte == other.end_byte
             && self.branches == other.branches
+            && self.license == other.license
+            && self.chunk_params == other.chunk_params
+            && self.embedding_model == other.embedding_model
 
         // ignoring deserialized fields that will not exist on a newly
         // created payload
@@ -55,14 +67,15 @@ impl PartialEq for Payload {
 
 pub(super) async fn create_collection(
     name: &str,
-    qdrant: &QdrantClient,
+    dims: usize,
+    qdrant: &dyn VectorStore,
 ) -> anyhow::Result<CollectionOperationResponse> {
     qdrant
         .create_collection(&CreateCollection {
             collection_name: name.to_string(),
             vectors_config: Some(VectorsConfig {
                 config: Some(vectors_config::Config::Params(VectorParams {
-                    size: EMBEDDING_DIM as u64,
+                    size: dims as u64,
                     distance: Distance::Cosine.into(),
                     on_disk: Some(true),
                     ..Default::default()
@@ -0,0 +1,152 @@
+//! Where the points of semantic search are stored.
+//!
+//! Points are stored in Qdrant when `qdrant_url` is configured, and otherwise in an embedded
+//! store in the index directory, so that semantic search works without any external service.
+
+use async_trait::async_trait;
+use qdrant_client::{
+    client::Payload,
+    prelude::QdrantClient,
+    qdrant::{
+        CollectionOperationResponse, CountPoints, CountResponse, CreateCollection, FieldType,
+        PointStruct, PointsSelector, ScrollPoints, ScrollResponse, SearchPoints, SearchResponse,
+    },
+};
+
+mod embedded;
+
+pub use embedded::EmbeddedStore;
+
+/// The operations of Qdrant that semantic search relies on.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn health_check(&self) -> anyhow::Result<()>;
+
+    async fn list_collections(&self) -> anyhow::Result<Vec<String>>;
+
+    async fn has_collection(&self, collection_name: &str) -> anyhow::Result<bool>;
+
+    async fn create_collection(
+        &self,
+        details: &CreateCollection,
+    ) -> anyhow::Result<CollectionOperationResponse>;
+
+    async fn delete_collection(&self, collection_name: &str) -> anyhow::Result<()>;
+
+    async fn create_field_index(
+        &self,
+        collection_name: &str,
+        field_name: &str,
+        field_type: FieldType,
+    ) -> anyhow::Result<()>;
+
+    async fn upsert_points(
+        &self,
+        collection_name: &str,
+        points: Vec<PointStruct>,
+    ) -> anyhow::Result<()>;
+
+    /// Merge `payload` into the payloads of the selected points.
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+        payload: Payload,
+    ) -> anyhow::Result<()>;
+
+    async fn delete_points(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+    ) -> anyhow::Result<()>;
+
+    async fn scroll(&self, request: &ScrollPoints) -> anyhow::Result<ScrollResponse>;
+
+    async fn search_points(&self, request: &SearchPoints) -> anyhow::Result<SearchResponse>;
+
+    async fn count(&self, request: &CountPoints) -> anyhow::Result<CountResponse>;
+}
+
+#[async_trait]
+impl VectorStore for QdrantClient {
+    async fn health_check(&self) -> anyhow::Result<()> {
+        QdrantClient::health_check(self).await?;
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> anyhow::Result<Vec<String>> {
+        Ok(QdrantClient::list_collections(self)
+            .await?
+            .collections
+            .into_iter()
+            .map(|c| c.name)
+            .collect())
+    }
+
+    async fn has_collection(&self, collection_name: &str) -> anyhow::Result<bool> {
+        QdrantClient::has_collection(self, collection_name).await
+    }
+
+    async fn create_collection(
+        &self,
+        details: &CreateCollection,
+    ) -> anyhow::Result<CollectionOperationResponse> {
+        QdrantClient::create_collection(self, details).await
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> anyhow::Result<()> {
+        QdrantClient::delete_collection(self, collection_name).await?;
+        Ok(())
+    }
+
+    async fn create_field_index(
+        &self,
+        collection_name: &str,
+        field_name: &str,
+        field_type: FieldType,
+    ) -> anyhow::Result<()> {
+        QdrantClient::create_field_index(self, collection_name, field_name, field_type, None, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn upsert_points(
+        &self,
+        collection_name: &str,
+        points: Vec<PointStruct>,
+    ) -> anyhow::Result<()> {
+        QdrantClient::upsert_points(self, collection_name, points, None).await?;
+        Ok(())
+    }
+
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+        payload: Payload,
+    ) -> anyhow::Result<()> {
+        QdrantClient::set_payload(self, collection_name, points, payload, None).await?;
+        Ok(())
+    }
+
+    async fn delete_points(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+    ) -> anyhow::Result<()> {
+        QdrantClient::delete_points(self, collection_name, points, None).await?;
+        Ok(())
+    }
+
+    async fn scroll(&self, request: &ScrollPoints) -> anyhow::Result<ScrollResponse> {
+        QdrantClient::scroll(self, request).await
+    }
+
+    async fn search_points(&self, request: &SearchPoints) -> anyhow::Result<SearchResponse> {
+        QdrantClient::search_points(self, request).await
+    }
+
+    async fn count(&self, request: &CountPoints) -> anyhow::Result<CountResponse> {
+        QdrantClient::count(self, request).await
+    }
+}
@@ -0,0 +1,773 @@
+//! An in-process vector store, persisted to disk.
+//!
+//! Each collection is kept in memory, and stored as a snapshot and a log of the changes made
+//! since the snapshot was written. The log is folded into the snapshot every [`COMPACT_AFTER`]
+//! changes, and when the store is opened.
+//!
+//! Searches compare the query to every point that passes the filter. This is fast enough for the
+//! repositories of a desktop user, and returns exact results rather than the approximate ones of
+//! an HNSW graph.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use qdrant_client::{
+    client::Payload,
+    qdrant::{
+        condition::ConditionOneOf, point_id::PointIdOptions, points_selector::PointsSelectorOneOf,
+        r#match::MatchValue, value::Kind, vectors::VectorsOptions, vectors_config,
+        with_payload_selector, with_vectors_selector, CollectionOperationResponse, Condition,
+        CountPoints, CountResponse, CountResult, CreateCollection, FieldCondition, FieldType,
+        Filter, ListValue, PointId, PointStruct, PointsSelector, RetrievedPoint, ScoredPoint,
+        ScrollPoints, ScrollResponse, SearchPoints, SearchResponse, Struct, Value,
+        WithPayloadSelector, WithVectorsSelector,
+    },
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::VectorStore;
+
+/// The number of logged changes after which a collection's snapshot is rewritten.
+const COMPACT_AFTER: usize = 1000;
+
+pub struct EmbeddedStore {
+    dir: PathBuf,
+    collections: RwLock<HashMap<String, Collection>>,
+}
+
+impl EmbeddedStore {
+    /// Open the store in `dir`, loading the collections stored there.
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create vector store in {}", dir.display()))?;
+
+        let mut collections = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension() != Some("snapshot".as_ref()) {
+                continue;
+            }
+
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let collection = Collection::load(dir, name)
+                .with_context(|| format!("failed to load vector collection {name}"))?;
+            debug!(
+                name,
+                points = collection.snapshot.points.len(),
+                "loaded vector collection"
+            );
+
+            collections.insert(name.to_owned(), collection);
+        }
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            collections: RwLock::new(collections),
+        })
+    }
+
+    /// Change a collection, if it exists.
+    fn update(
+        &self,
+        collection_name: &str,
+        change: impl FnOnce(&Snapshot) -> anyhow::Result<Change>,
+    ) -> anyhow::Result<()> {
+        let mut collections = self.collections.write().unwrap();
+        let Some(collection) = collections.get_mut(collection_name) else {
+            bail!("collection `{collection_name}` doesn't exist");
+        };
+
+        let change = change(&collection.snapshot)?;
+        collection.commit(change)
+    }
+
+    fn read<T>(
+        &self,
+        collection_name: &str,
+        read: impl FnOnce(&Snapshot) -> T,
+    ) -> anyhow::Result<T> {
+        let collections = self.collections.read().unwrap();
+        match collections.get(collection_name) {
+            Some(collection) => Ok(read(&collection.snapshot)),
+            None => bail!("collection `{collection_name}` doesn't exist"),
+        }
+    }
+}
+
+#[async_trait]
+impl VectorStore for EmbeddedStore {
+    async fn health_check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.collections.read().unwrap().keys().cloned().collect())
+    }
+
+    async fn has_collection(&self, collection_name: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .collections
+            .read()
+            .unwrap()
+            .contains_key(collection_name))
+    }
+
+    async fn create_collection(
+        &self,
+        details: &CreateCollection,
+    ) -> anyhow::Result<CollectionOperationResponse> {
+        let dims = match details
+            .vectors_config
+            .as_ref()
+            .and_then(|c| c.config.as_ref())
+        {
+            Some(vectors_config::Config::Params(params)) => params.size as usize,
+            _ => bail!("only collections of unnamed vectors are supported"),
+        };
+
+        let name = &details.collection_name;
+        let mut collections = self.collections.write().unwrap();
+        if collections.contains_key(name) {
+            bail!("collection `{name}` already exists");
+        }
+
+        collections.insert(name.clone(), Collection::create(&self.dir, name, dims)?);
+        Ok(CollectionOperationResponse {
+            result: true,
+            time: 0.0,
+        })
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> anyhow::Result<()> {
+        if let Some(collection) = self.collections.write().unwrap().remove(collection_name) {
+            collection.remove()?;
+        }
+        Ok(())
+    }
+
+    async fn create_field_index(
+        &self,
+        collection_name: &str,
+        field_name: &str,
+        field_type: FieldType,
+    ) -> anyhow::Result<()> {
+        // Only full-text indexes change how points are matched.
+        if field_type != FieldType::Text {
+            return Ok(());
+        }
+
+        self.update(collection_name, |_| {
+            Ok(Change::TextIndex(field_name.to_owned()))
+        })
+    }
+
+    async fn upsert_points(
+        &self,
+        collection_name: &str,
+        points: Vec<PointStruct>,
+    ) -> anyhow::Result<()> {
+        self.update(collection_name, |snapshot| {
+            let points = points
+                .into_iter()
+                .map(|p| {
+                    let id = p.id.as_ref().and_then(key).context("point without an id")?;
+                    let vector = match p.vectors.and_then(|v| v.vectors_options) {
+                        Some(VectorsOptions::Vector(v)) => v.data,
+                        _ => bail!("only unnamed vectors are supported"),
+                    };
+
+                    if vector.len() != snapshot.dims {
+                        bail!(
+                            "expected a {}-dimensional vector, got {}",
+                            snapshot.dims,
+                            vector.len()
+                        );
+                    }
+
+                    let payload = p.payload.into_iter().map(|(k, v)| (k, v.into())).collect();
+                    Ok((id, Point { vector, payload }))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            Ok(Change::Upsert(points))
+        })
+    }
+
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+        payload: Payload,
+    ) -> anyhow::Result<()> {
+        self.update(collection_name, |snapshot| {
+            let payload = HashMap::<String, Value>::from(payload)
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect();
+
+            Ok(Change::SetPayload(snapshot.select(points), payload))
+        })
+    }
+
+    async fn delete_points(
+        &self,
+        collection_name: &str,
+        points: &PointsSelector,
+    ) -> anyhow::Result<()> {
+        self.update(collection_name, |snapshot| {
+            Ok(Change::Delete(snapshot.select(points)))
+        })
+    }
+
+    async fn scroll(&self, request: &ScrollPoints) -> anyhow::Result<ScrollResponse> {
+        self.read(&request.collection_name, |snapshot| {
+            let start = request.offset.as_ref().and_then(key).unwrap_or_default();
+            let limit = request.limit.unwrap_or(10) as usize;
+            let with_payload = payload_enabled(request.with_payload.as_ref());
+            let with_vectors = vectors_enabled(request.with_vectors.as_ref());
+
+            let mut matching = snapshot
+                .points
+                .range(start..)
+                .filter(|(id, point)| snapshot.filtered(request.filter.as_ref(), id, point));
+
+            let result = matching
+                .by_ref()
+                .take(limit)
+                .map(|(id, point)| RetrievedPoint {
+                    id: Some(PointId::from(id.clone())),
+                    payload: point.payload(with_payload),
+                    vectors: point.vectors(with_vectors),
+                    ..Default::default()
+                })
+                .collect();
+
+            ScrollResponse {
+                next_page_offset: matching.next().map(|(id, _)| PointId::from(id.clone())),
+                result,
+                time: 0.0,
+            }
+        })
+    }
+
+    async fn search_points(&self, request: &SearchPoints) -> anyhow::Result<SearchResponse> {
+        self.read(&request.collection_name, |snapshot| {
+            let threshold = request.score_threshold.unwrap_or(f32::MIN);
+            let mut scored = snapshot
+                .points
+                .par_iter()
+                .filter(|(id, point)| snapshot.filtered(request.filter.as_ref(), id, point))
+                .map(|(id, point)| (cosine_similarity(&request.vector, &point.vector), id))
+                .filter(|(score, _)| *score >= threshold)
+                .collect::<Vec<_>>();
+
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            let with_payload = payload_enabled(request.with_payload.as_ref());
+            let with_vectors = vectors_enabled(request.with_vectors.as_ref());
+
+            let result = scored
+                .into_iter()
+                .skip(request.offset.unwrap_or_default() as usize)
+                .take(request.limit as usize)
+                .map(|(score, id)| {
+                    let point = &snapshot.points[id];
+                    ScoredPoint {
+                        id: Some(PointId::from(id.clone())),
+                        payload: point.payload(with_payload),
+                        score,
+                        vectors: point.vectors(with_vectors),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+
+            SearchResponse { result, time: 0.0 }
+        })
+    }
+
+    async fn count(&self, request: &CountPoints) -> anyhow::Result<CountResponse> {
+        self.read(&request.collection_name, |snapshot| {
+            let count = snapshot
+                .points
+                .iter()
+                .filter(|(id, point)| snapshot.filtered(request.filter.as_ref(), id, point))
+                .count();
+
+            CountResponse {
+                result: Some(CountResult {
+                    count: count as u64,
+                }),
+                time: 0.0,
+            }
+        })
+    }
+}
+
+struct Collection {
+    snapshot: Snapshot,
+    snapshot_path: PathBuf,
+    log_path: PathBuf,
+    log: File,
+    /// The changes logged since the snapshot was written.
+    logged: usize,
+}
+
+impl Collection {
+    fn create(dir: &Path, name: &str, dims: usize) -> anyhow::Result<Self> {
+        let snapshot = Snapshot {
+            dims,
+            ..Default::default()
+        };
+
+        let (snapshot_path, log_path) = paths(dir, name);
+        let mut collection = Self {
+            snapshot,
+            log: File::create(&log_path)?,
+            snapshot_path,
+            log_path,
+            logged: 0,
+        };
+
+        collection.compact()?;
+        Ok(collection)
+    }
+
+    fn load(dir: &Path, name: &str) -> anyhow::Result<Self> {
+        let (snapshot_path, log_path) = paths(dir, name);
+        let mut snapshot: Snapshot =
+            bincode::deserialize_from(BufReader::new(File::open(&snapshot_path)?))?;
+
+        if let Ok(log) = File::open(&log_path) {
+            let mut log = BufReader::new(log);
+            loop {
+                match bincode::deserialize_from::<_, Change>(&mut log) {
+                    Ok(change) => snapshot.apply(change),
+                    // A crash while a change is written cuts the log short.
+                    Err(err) if is_eof(&err) => break,
+                    Err(err) => {
+                        warn!(?err, name, "discarding the rest of a corrupt vector log");
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut collection = Self {
+            snapshot,
+            log: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log_path)?,
+            snapshot_path,
+            log_path,
+            logged: 0,
+        };
+
+        collection.compact()?;
+        Ok(collection)
+    }
+
+    fn commit(&mut self, change: Change) -> anyhow::Result<()> {
+        self.log.write_all(&bincode::serialize(&change)?)?;
+        self.snapshot.apply(change);
+
+        self.logged += 1;
+        if self.logged >= COMPACT_AFTER {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the snapshot, and start a new log.
+    ///
+    /// Changes are idempotent, so if the log can't be truncated after the snapshot is written,
+    /// replaying it on the new snapshot leaves the collection as it is.
+    fn compact(&mut self) -> anyhow::Result<()> {
+        let tmp = self.snapshot_path.with_extension("snapshot.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        bincode::serialize_into(&mut writer, &self.snapshot)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &self.snapshot_path)?;
+
+        self.log = File::create(&self.log_path)?;
+        self.logged = 0;
+        Ok(())
+    }
+
+    fn remove(self) -> anyhow::Result<()> {
+        let Self {
+            snapshot_path,
+            log_path,
+            log,
+            ..
+        } = self;
+
+        // Open files can't be removed on Windows.
+        drop(log);
+
+        for path in [snapshot_path, log_path] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_eof(err: &bincode::Error) -> bool {
+    matches!(&**err, bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+fn paths(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("{name}.snapshot")),
+        dir.join(format!("{name}.log")),
+    )
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Snapshot {
+    dims: usize,
+    /// Fields with a full-text index, which are matched by their words rather than by substring.
+    text_fields: HashSet<String>,
+    points: BTreeMap<String, Point>,
+}
+
+impl Snapshot {
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Upsert(points) => self.points.extend(points),
+            Change::SetPayload(ids, payload) => {
+                for id in ids {
+                    if let Some(point) = self.points.get_mut(&id) {
+                        point.payload.extend(payload.clone());
+                    }
+                }
+            }
+            Change::Delete(ids) => {
+                for id in ids {
+                    self.points.remove(&id);
+                }
+            }
+            Change::TextIndex(field) => {
+                self.text_fields.insert(field);
+            }
+        }
+    }
+
+    /// The ids of the points that a selector picks.
+    fn select(&self, selector: &PointsSelector) -> Vec<String> {
+        match &selector.points_selector_one_of {
+            Some(PointsSelectorOneOf::Points(list)) => list.ids.iter().filter_map(key).collect(),
+            Some(PointsSelectorOneOf::Filter(filter)) => self
+                .points
+                .iter()
+                .filter(|(id, point)| self.matches(filter, id, point))
+                .map(|(id, _)| id.clone())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn filtered(&self, filter: Option<&Filter>, id: &str, point: &Point) -> bool {
+        filter.map_or(true, |f| self.matches(f, id, point))
+    }
+
+    fn matches(&self, filter: &Filter, id: &str, point: &Point) -> bool {
+        filter.must.iter().all(|c| self.condition(c, id, point))
+            && (filter.should.is_empty()
+                || filter.should.iter().any(|c| self.condition(c, id, point)))
+            && !filter.must_not.iter().any(|c| self.condition(c, id, point))
+    }
+
+    fn condition(&self, condition: &Condition, id: &str, point: &Point) -> bool {
+        match &condition.condition_one_of {
+            Some(ConditionOneOf::Field(field)) => self.field(field, point),
+            Some(ConditionOneOf::Filter(filter)) => self.matches(filter, id, point),
+            Some(ConditionOneOf::HasId(has)) => {
+                has.has_id.iter().any(|i| key(i).as_deref() == Some(id))
+            }
+            _ => false,
+        }
+    }
+
+    fn field(&self, condition: &FieldCondition, point: &Point) -> bool {
+        let Some(expected) = condition
+            .r#match
+            .as_ref()
+            .and_then(|m| m.match_value.as_ref())
+        else {
+            return false;
+        };
+
+        let values = match point.payload.get(&condition.key) {
+            Some(StoredValue::List(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => vec![],
+        };
+
+        values.into_iter().any(|value| match (expected, value) {
+            (MatchValue::Keyword(k), StoredValue::String(s)) => k == s,
+            (MatchValue::Integer(i), StoredValue::Integer(j)) => i == j,
+            (MatchValue::Boolean(b), StoredValue::Bool(c)) => b == c,
+            (MatchValue::Text(t), StoredValue::String(s)) => {
+                if self.text_fields.contains(&condition.key) {
+                    let words = words(s).collect::<HashSet<_>>();
+                    words(t).all(|w| words.contains(&w))
+                } else {
+                    s.contains(t.as_str())
+                }
+            }
+            _ => false,
+        })
+    }
+}
+
+/// The lowercase words of a text, as Qdrant's full-text index splits them.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// A change of a collection, as it is logged.
+#[derive(Serialize, Deserialize)]
+enum Change {
+    Upsert(Vec<(String, Point)>),
+    SetPayload(Vec<String>, HashMap<String, StoredValue>),
+    Delete(Vec<String>),
+    TextIndex(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Point {
+    vector: Vec<f32>,
+    payload: HashMap<String, StoredValue>,
+}
+
+impl Point {
+    fn payload(&self, enabled: bool) -> HashMap<String, Value> {
+        if !enabled {
+            return HashMap::new();
+        }
+
+        self.payload
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone().into()))
+            .collect()
+    }
+
+    fn vectors(&self, enabled: bool) -> Option<qdrant_client::qdrant::Vectors> {
+        enabled.then(|| self.vector.clone().into())
+    }
+}
+
+/// A payload value, in a form that can be serialized.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum StoredValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Double(f64),
+    String(String),
+    List(Vec<StoredValue>),
+    Struct(HashMap<String, StoredValue>),
+}
+
+impl From<Value> for StoredValue {
+    fn from(value: Value) -> Self {
+        match value.kind {
+            None | Some(Kind::NullValue(_)) => Self::Null,
+            Some(Kind::BoolValue(b)) => Self::Bool(b),
+            Some(Kind::IntegerValue(i)) => Self::Integer(i),
+            Some(Kind::DoubleValue(d)) => Self::Double(d),
+            Some(Kind::StringValue(s)) => Self::String(s),
+            Some(Kind::ListValue(l)) => Self::List(l.values.into_iter().map(Into::into).collect()),
+            Some(Kind::StructValue(s)) => {
+                Self::Struct(s.fields.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+        }
+    }
+}
+
+impl From<StoredValue> for Value {
+    fn from(value: StoredValue) -> Self {
+        let kind = match value {
+            StoredValue::Null => Kind::NullValue(0),
+            StoredValue::Bool(b) => Kind::BoolValue(b),
+            StoredValue::Integer(i) => Kind::IntegerValue(i),
+            StoredValue::Double(d) => Kind::DoubleValue(d),
+            StoredValue::String(s) => Kind::StringValue(s),
+            StoredValue::List(l) => Kind::ListValue(ListValue {
+                values: l.into_iter().map(Into::into).collect(),
+            }),
+            StoredValue::Struct(s) => Kind::StructValue(Struct {
+                fields: s.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            }),
+        };
+
+        Value { kind: Some(kind) }
+    }
+}
+
+/// Points are keyed by the string form of their id.
+fn key(id: &PointId) -> Option<String> {
+    match id.point_id_options.as_ref()? {
+        PointIdOptions::Uuid(uuid) => Some(uuid.clone()),
+        PointIdOptions::Num(num) => Some(num.to_string()),
+    }
+}
+
+fn payload_enabled(selector: Option<&WithPayloadSelector>) -> bool {
+    !matches!(
+        selector.and_then(|s| s.selector_options.as_ref()),
+        None | Some(with_payload_selector::SelectorOptions::Enable(false))
+    )
+}
+
+fn vectors_enabled(selector: Option<&WithVectorsSelector>) -> bool {
+    !matches!(
+        selector.and_then(|s| s.selector_options.as_ref()),
+        None | Some(with_vectors_selector::SelectorOptions::Enable(false))
+    )
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm(a) * norm(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::make_kv_keyword_filter;
+    use qdrant_client::qdrant::{VectorParams, VectorsConfig};
+
+    fn point(id: &str, vector: Vec<f32>, lang: &str) -> PointStruct {
+        PointStruct::new(
+            id.to_owned(),
+            vector,
+            Payload::new_from_hashmap([("lang".to_owned(), lang.to_owned().into())].into()),
+        )
+    }
+
+    async fn store(dir: &Path) -> EmbeddedStore {
+        let store = EmbeddedStore::open(dir).unwrap();
+        store
+            .create_collection(&CreateCollection {
+                collection_name: "test".to_owned(),
+                vectors_config: Some(VectorsConfig {
+                    config: Some(vectors_config::Config::Params(VectorParams {
+                        size: 2,
+                        ..Default::default()
+                    })),
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        store
+    }
+
+    fn search(vector: Vec<f32>, filter: Option<Filter>) -> SearchPoints {
+        SearchPoints {
+            collection_name: "test".to_owned(),
+            vector,
+            filter,
+            limit: 10,
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_by_similarity_and_filter() {
+        let dir = tempdir::TempDir::new("vector-store").unwrap();
+        let store = store(dir.path()).await;
+
+        store
+            .upsert_points(
+                "test",
+                vec![
+                    point("a", vec![1.0, 0.0], "rust"),
+                    point("b", vec![0.7, 0.7], "rust"),
+                    point("c", vec![1.0, 0.1], "python"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let ids = |response: SearchResponse| {
+            response
+                .result
+                .into_iter()
+                .map(|p| key(&p.id.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let all = store.search_points(&search(vec![1.0, 0.0], None)).await;
+        assert_eq!(ids(all.unwrap()), ["a", "c", "b"]);
+
+        let rust = Filter {
+            must: vec![make_kv_keyword_filter("lang", "rust").into()],
+            ..Default::default()
+        };
+        let filtered = store
+            .search_points(&search(vec![1.0, 0.0], Some(rust)))
+            .await;
+        assert_eq!(ids(filtered.unwrap()), ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn persists_changes() {
+        let dir = tempdir::TempDir::new("vector-store").unwrap();
+        let store = store(dir.path()).await;
+
+        store
+            .upsert_points(
+                "test",
+                vec![
+                    point("a", vec![1.0, 0.0], "rust"),
+                    point("b", vec![0.0, 1.0], "rust"),
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .delete_points("test", &vec![PointId::from("a".to_owned())].into())
+            .await
+            .unwrap();
+        drop(store);
+
+        let reopened = EmbeddedStore::open(dir.path()).unwrap();
+        let response = reopened
+            .search_points(&search(vec![1.0, 0.0], None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.result.len(), 1);
+        assert_eq!(key(response.result[0].id.as_ref().unwrap()).unwrap(), "b");
+        assert_eq!(
+            StoredValue::from(response.result[0].payload["lang"].clone()),
+            StoredValue::String("rust".to_owned())
+        );
+    }
+}
@@ -12,6 +12,8 @@ pub struct SnippedFile {
     pub repo_name: String,
     pub repo_ref: String,
     pub lang: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
     pub snippets: Vec<Snippet>,
 }
 
@@ -210,17 +212,22 @@ impl Snipper {
                 .collect::<Vec<_>>()
         };
 
-        Ok(if snippets.is_empty() {
-            None
-        } else {
-            Some(SnippedFile {
-                relative_path: doc.relative_path.clone(),
-                repo_name: doc.repo_name.clone(),
-                repo_ref: doc.repo_ref.clone(),
-                lang: doc.lang.clone(),
-                snippets,
-            })
-        })
+        Ok(snipped_file(doc, snippets))
+    }
+
+    /// Snip a document around highlighted byte ranges, which are sorted by where they start and
+    /// don't overlap.
+    pub fn all_for_ranges(
+        &self,
+        highlights: Vec<Range<usize>>,
+        doc: &indexes::reader::ContentDocument,
+    ) -> Option<SnippedFile> {
+        let snippets = self
+            .expand_many(highlights.into_iter(), &doc.content, &doc.line_end_indices)
+            .map(|loc| loc.reify(&doc.content, &[]))
+            .collect::<Vec<_>>();
+
+        snipped_file(doc, snippets)
     }
 
     fn expand_many<'a>(
@@ -293,6 +300,77 @@ impl Snipper {
     }
 }
 
+fn snipped_file(
+    doc: &indexes::reader::ContentDocument,
+    snippets: Vec<Snippet>,
+) -> Option<SnippedFile> {
+    if snippets.is_empty() {
+        return None;
+    }
+
+    Some(SnippedFile {
+        relative_path: doc.relative_path.clone(),
+        repo_name: doc.repo_name.clone(),
+        repo_ref: doc.repo_ref.clone(),
+        lang: doc.lang.clone(),
+        license: doc.license.clone(),
+        snippets,
+    })
+}
+
+/// Grow a 0-indexed, end-exclusive range of lines by `step` lines in each direction, without
+/// going past the start or the end of a file with `file_lines` lines.
+pub fn grow(span: Range<usize>, step: usize, file_lines: usize) -> Range<usize> {
+    span.start.min(file_lines).saturating_sub(step)..(span.end + step).min(file_lines)
+}
+
+/// Find the smallest scope, such as a function or a class, that encloses a 0-indexed,
+/// end-exclusive range of lines and is larger than it.
+///
+/// `scopes` are line ranges in the same form, usually from a file's scope graph.
+pub fn enclosing_scope(span: Range<usize>, scopes: &[Range<usize>]) -> Option<Range<usize>> {
+    scopes
+        .iter()
+        .filter(|s| s.start <= span.start && span.end <= s.end && s.len() > span.len())
+        .min_by_key(|s| s.len())
+        .cloned()
+}
+
+/// Grow a 0-indexed, end-exclusive range of lines by about `step` lines in each direction, without
+/// cutting a scope in half.
+///
+/// Inside a scope too large to grow to, the span stays within the scope. Elsewhere, the span
+/// grows to the whole of the scopes that the extra lines reach into. Without `scopes`, this is
+/// [`grow`].
+pub fn grow_within_scopes(
+    span: Range<usize>,
+    step: usize,
+    scopes: &[Range<usize>],
+    file_lines: usize,
+) -> Range<usize> {
+    let bounds = enclosing_scope(span.clone(), scopes).unwrap_or(0..file_lines);
+    let bounds = bounds.start..bounds.end.min(file_lines);
+
+    let grown = grow(span, step, file_lines);
+    let mut grown = grown.start.max(bounds.start)..grown.end.min(bounds.end);
+
+    loop {
+        let snapped = scopes
+            .iter()
+            .filter(|s| bounds.start <= s.start && s.end <= bounds.end && s.len() < bounds.len())
+            .filter(|s| s.start < grown.end && grown.start < s.end)
+            .fold(grown.clone(), |acc, s| {
+                acc.start.min(s.start)..acc.end.max(s.end)
+            });
+
+        if snapped == grown {
+            return grown;
+        }
+
+        grown = snapped;
+    }
+}
+
 #[derive(Serialize)]
 pub struct HighlightedString {
     pub text: String,
@@ -611,6 +689,47 @@ mod tests {
             .is_some());
     }
 
+    #[test]
+    fn grows_spans() {
+        assert_eq!(grow(10..20, 5, 100), 5..25);
+        assert_eq!(grow(2..20, 5, 22), 0..22);
+        assert_eq!(grow(0..100, 5, 100), 0..100);
+        assert_eq!(grow(120..130, 5, 100), 95..100);
+    }
+
+    #[test]
+    fn finds_enclosing_scopes() {
+        // An `impl` block, with a function that has a loop in it.
+        let scopes = [0..40, 10..20, 12..15];
+
+        assert_eq!(enclosing_scope(12..14, &scopes), Some(12..15));
+        assert_eq!(enclosing_scope(12..15, &scopes), Some(10..20));
+        assert_eq!(enclosing_scope(9..11, &scopes), Some(0..40));
+        assert_eq!(enclosing_scope(0..40, &scopes), None);
+        assert_eq!(enclosing_scope(35..45, &scopes), None);
+        assert_eq!(enclosing_scope(12..14, &[]), None);
+    }
+
+    #[test]
+    fn grows_without_cutting_scopes() {
+        // Two functions in an `impl` block, and a free function after it.
+        let scopes = [0..100, 0..40, 5..15, 20..35, 50..60];
+
+        // Within a function that is too large to grow to, growth stops at its edges.
+        assert_eq!(grow_within_scopes(22..25, 5, &scopes, 100), 20..30);
+        assert_eq!(grow_within_scopes(20..30, 5, &scopes, 100), 20..35);
+
+        // Between the functions of the `impl` block, growth takes in whole functions.
+        assert_eq!(grow_within_scopes(16..18, 3, &scopes, 100), 5..35);
+
+        // At the top level, it takes in whole items.
+        assert_eq!(grow_within_scopes(42..46, 5, &scopes, 100), 0..60);
+
+        // Without scopes, it grows by lines.
+        assert_eq!(grow_within_scopes(22..25, 5, &[], 100), 17..30);
+        assert_eq!(grow_within_scopes(2..25, 5, &[], 27), 0..27);
+    }
+
     #[test]
     fn test_highlighted_string() {
         let mut s = HighlightedString::new("foo bar quux");
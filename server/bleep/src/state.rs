@@ -1,6 +1,6 @@
 use crate::{
     remotes::gather_repo_roots,
-    repo::{RepoError, RepoRef, Repository},
+    repo::{RepoError, RepoRef, Repository, SyncStatus},
 };
 use anyhow::Result;
 use clap::Args;
@@ -195,8 +195,9 @@ impl StateSource {
                         }
                     }
 
-                    // in case the app terminated during indexing, make sure to re-queue it
-                    if !repo.sync_status.indexable() {
+                    // in case the app terminated during indexing, make sure to re-queue it,
+                    // while repositories that couldn't be deleted are deleted again
+                    if !repo.sync_status.indexable() && repo.sync_status != SyncStatus::Removed {
                         repo.mark_queued();
                     }
                 });
@@ -234,10 +235,9 @@ impl StateSource {
         }
     }
 
-    pub fn index_version_mismatch(&self) -> bool {
-        let current: String = read_file_or_default(self.version_file.as_ref().unwrap()).unwrap();
-
-        !current.is_empty() && current != SCHEMA_VERSION
+    /// The schema version of the indexes in use, which is empty for new installations.
+    pub fn index_version(&self) -> String {
+        read_file_or_default(self.version_file.as_ref().unwrap()).unwrap()
     }
 
     pub fn save_index_version(&self) -> Result<(), RepoError> {
@@ -1,9 +1,12 @@
-use crate::{env::Feature, Application};
+use crate::{env::Feature, repo::RepoRef, Application};
+use middleware::User;
 
 use axum::{
-    http::StatusCode,
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Extension, Json,
 };
 use std::{borrow::Cow, net::SocketAddr};
@@ -15,16 +18,30 @@ use tracing::info;
 mod aaa;
 pub mod answer;
 mod autocomplete;
+mod bookmarks;
 mod config;
+mod corpus;
+mod digest;
+mod federation;
 mod file;
+mod generate;
 mod github;
+mod gitlab;
 mod hoverable;
 mod index;
 mod intelligence;
+mod log_source;
 pub mod middleware;
+mod notifications;
+mod pins;
+mod profile;
 mod query;
+mod reindex;
 pub mod repos;
+mod retention;
 mod semantic;
+mod snippets;
+mod usage;
 
 pub type Router<S = Application> = axum::Router<S>;
 
@@ -40,6 +57,12 @@ pub(crate) mod prelude {
 pub async fn start(app: Application) -> anyhow::Result<()> {
     let bind = SocketAddr::new(app.config.host.parse()?, app.config.port);
 
+    let limits = middleware::ConcurrencyLimits::new(&app.config);
+    let answer_rate_limit = axum::middleware::from_fn_with_state(
+        middleware::AnswerRateLimit::new(&app.config),
+        middleware::answer_rate_limit,
+    );
+
     let mut api = Router::new()
         .route("/config", get(config::get).put(config::put))
         // querying
@@ -49,15 +72,53 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         // indexing
         .route("/index", get(index::handle))
         // repo management
-        .nest("/repos", repos::router())
+        .nest("/repos", repos::router(&limits))
         // intelligence
-        .route("/hoverable", get(hoverable::handle))
-        .route("/token-info", get(intelligence::handle))
+        .route("/hoverable", get(hoverable::handle).layer(limits.files()))
+        .route(
+            "/token-info",
+            get(intelligence::handle).layer(limits.files()),
+        )
         // misc
         .route("/search", get(semantic::complex_search))
-        .route("/file", get(file::handle))
-        .route("/answer", get(answer::answer))
-        .route("/answer/explain", get(answer::explain))
+        .route("/search/similar", post(semantic::similar))
+        .route("/file", get(file::handle).layer(limits.files()))
+        .route(
+            "/snippets/expand",
+            get(snippets::expand).layer(limits.files()),
+        )
+        .route("/log-source", get(log_source::handle))
+        .route(
+            "/answer",
+            get(answer::answer)
+                .post(answer::answer_post)
+                .layer(limits.answer())
+                .layer(answer_rate_limit.clone()),
+        )
+        .route(
+            "/answer/explain",
+            get(answer::explain)
+                .layer(limits.answer())
+                .layer(answer_rate_limit.clone()),
+        )
+        .route(
+            "/answer/test",
+            get(answer::testgen::handle)
+                .layer(limits.answer())
+                .layer(answer_rate_limit.clone()),
+        )
+        .route(
+            "/answer/compare",
+            get(answer::compare::handle)
+                .layer(limits.answer())
+                .layer(answer_rate_limit.clone()),
+        )
+        .route(
+            "/answer/bundle",
+            get(answer::bundle::handle)
+                .layer(limits.files())
+                .layer(limits.bulk()),
+        )
         .route(
             "/answer/conversations",
             get(answer::conversations::list).delete(answer::conversations::delete),
@@ -66,12 +127,60 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
             "/answer/conversations/:thread_id",
             get(answer::conversations::thread),
         )
-        .route("/answer/vote", post(answer::vote));
+        .route(
+            "/answer/conversations/:thread_id/branches",
+            get(answer::conversations::branches),
+        )
+        .route("/answer/vote", post(answer::vote))
+        .route("/answer/feedback", post(answer::feedback))
+        .route("/bookmarks", get(bookmarks::list).post(bookmarks::create))
+        .route("/bookmarks/saved", get(bookmarks::saved))
+        .route("/bookmarks/:id", delete(bookmarks::delete))
+        .route(
+            "/digest",
+            get(digest::handle).layer(answer_rate_limit.clone()),
+        )
+        .route("/digest/seen", put(digest::mark_seen))
+        .route(
+            "/generate/commit-message",
+            post(generate::commit_message).layer(answer_rate_limit),
+        )
+        .route("/notifications", get(notifications::list))
+        .route("/notifications/:id/ack", post(notifications::ack))
+        .route("/profile", get(profile::get).delete(profile::reset))
+        // token usage
+        .route("/usage", get(usage::get))
+        .route("/usage/all", get(usage::all))
+        .route("/usage/metrics", get(usage::metrics))
+        // data retention
+        .route("/retention", get(retention::report))
+        .route(
+            "/admin/reindex-all",
+            get(reindex::status).post(reindex::start),
+        )
+        // fine-tuning
+        .route("/export/corpus", get(corpus::export))
+        // evaluation
+        .route("/pins", get(pins::list).post(pins::create))
+        .route("/pins/:name", delete(pins::delete))
+        // federation
+        .route("/federation/peers", get(federation::list_peers))
+        .route(
+            "/federation/peers/:name",
+            put(federation::put_peer).delete(federation::delete_peer),
+        )
+        .route("/federation/search", get(federation::search));
 
     if app.env.allow(Feature::AnyPathScan) {
         api = api.route("/repos/scan", get(repos::scan_local));
     }
 
+    api = api
+        .route("/remotes/gitlab/login", get(gitlab::login))
+        .route("/remotes/gitlab/complete", get(gitlab::complete))
+        .route("/remotes/gitlab/token", put(gitlab::set_token))
+        .route("/remotes/gitlab/logout", get(gitlab::logout));
+
     if app.env.allow(Feature::CognitoUserAuth) {
         api = api
             .route("/remotes/github/login", get(github::login))
@@ -149,11 +258,13 @@ impl Error {
             | ErrorKind::Custom => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorKind::User => StatusCode::BAD_REQUEST,
             ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
         };
 
         let body = EndpointError {
             kind,
             message: message.into(),
+            retry_after_secs: None,
         };
 
         Error { status, body }
@@ -170,6 +281,7 @@ impl Error {
             body: EndpointError {
                 kind: ErrorKind::Internal,
                 message: message.to_string().into(),
+                retry_after_secs: None,
             },
         }
     }
@@ -180,10 +292,19 @@ impl Error {
             body: EndpointError {
                 kind: ErrorKind::User,
                 message: message.to_string().into(),
+                retry_after_secs: None,
             },
         }
     }
 
+    /// A `429 Too Many Requests`, which can be retried once `retry_after` has passed.
+    fn rate_limited<S: std::fmt::Display>(message: S, retry_after: std::time::Duration) -> Self {
+        let mut error = Error::new(ErrorKind::RateLimited, message.to_string());
+        // round up, so that a retry at the hinted time succeeds
+        error.body.retry_after_secs = Some((retry_after.as_secs_f64().ceil() as u64).max(1));
+        error
+    }
+
     fn message(&self) -> &str {
         self.body.message.as_ref()
     }
@@ -197,7 +318,16 @@ impl From<anyhow::Error> for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        (self.status, Json(Response::from(self.body))).into_response()
+        let retry_after = self.body.retry_after_secs;
+        let mut response = (self.status, Json(Response::from(self.body))).into_response();
+
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, secs.into());
+        }
+
+        response
     }
 }
 
@@ -209,6 +339,10 @@ pub struct EndpointError<'a> {
 
     /// A context aware message describing the error
     message: Cow<'a, str>,
+
+    /// How long to wait before retrying, for rate limited requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
 }
 
 /// The kind of an error
@@ -223,6 +357,7 @@ pub enum ErrorKind {
     Configuration,
     UpstreamService,
     Internal,
+    RateLimited,
 
     // TODO: allow construction of detailed custom kinds
     #[doc(hidden)]
@@ -260,3 +395,114 @@ async fn health(Extension(app): Extension<Application>) {
         semantic.health_check().await.unwrap()
     }
 }
+
+/// Refuse requests that would send the code of a retrieval-only repository to an LLM.
+async fn check_llm_access(app: &Application, repo_ref: &RepoRef) -> Result<()> {
+    if app.is_retrieval_only(repo_ref).await {
+        return Err(Error::user(
+            "this repository is retrieval-only, so its code is never sent to an LLM",
+        )
+        .with_status(StatusCode::FORBIDDEN));
+    }
+
+    Ok(())
+}
+
+/// Whether `user` administers this instance, and can change what applies to every user.
+///
+/// Instances without authorization have no admins other than those in `admins`, unless they are a
+/// local install with a single user.
+fn is_admin(app: &Application, user: &User) -> bool {
+    app.env.allow(Feature::LocalAdmin)
+        || user
+            .login()
+            .is_some_and(|login| app.config.admins.iter().any(|admin| admin == login))
+}
+
+/// Refuses requests of users who aren't admins.
+///
+/// Endpoints that change the instance for every user take this, rather than checking themselves.
+struct Admin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self> {
+        let (Some(app), Some(user)) = (
+            parts.extensions.get::<Application>(),
+            parts.extensions.get::<User>(),
+        ) else {
+            return Err(Error::internal(
+                "the admin check needs the application and the user",
+            ));
+        };
+
+        if !is_admin(app, user) {
+            return Err(Error::user("only admins can do this").with_status(StatusCode::FORBIDDEN));
+        }
+
+        Ok(Admin)
+    }
+}
+
+/// An application with a single, retrieval-only, repository in it.
+#[cfg(test)]
+async fn retrieval_only_app(dir: &std::path::Path) -> (Application, RepoRef) {
+    let repo_ref = RepoRef::from(&dir);
+    let mut repo = crate::repo::Repository::local_from(&repo_ref);
+    repo.retrieval_only = true;
+
+    let app = Application::for_tests(dir).await;
+    app.repo_pool.insert(repo_ref.clone(), repo).unwrap();
+
+    (app, repo_ref)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn user(login: &str) -> User {
+        User::Authenticated {
+            login: login.to_owned(),
+            crab: Arc::new(|| -> anyhow::Result<octocrab::Octocrab> { anyhow::bail!("no github") }),
+        }
+    }
+
+    async fn admin(app: &Application, user: User) -> StatusCode {
+        let (mut parts, ()) = axum::http::Request::new(()).into_parts();
+        parts.extensions.insert(app.clone());
+        parts.extensions.insert(user);
+
+        match Admin::from_request_parts(&mut parts, &()).await {
+            Ok(Admin) => StatusCode::OK,
+            Err(err) => err.into_response().status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_lets_admins_through() {
+        let dir = tempdir::TempDir::new("webserver").unwrap();
+        let mut app = Application::for_tests(dir.path()).await;
+        assert_eq!(admin(&app, User::Unknown).await, StatusCode::OK);
+
+        app.config = Arc::new(crate::Configuration {
+            admins: vec!["alice".into()],
+            usage_admins: vec!["bob".into()],
+            ..(*app.config).clone()
+        });
+
+        for env in [
+            crate::env::Environment::server(),
+            crate::env::Environment::private_server(),
+        ] {
+            app.env = env;
+            assert_eq!(admin(&app, user("alice")).await, StatusCode::OK);
+            assert_eq!(admin(&app, user("bob")).await, StatusCode::FORBIDDEN);
+            assert_eq!(admin(&app, User::Unknown).await, StatusCode::FORBIDDEN);
+        }
+    }
+}
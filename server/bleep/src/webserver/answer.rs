@@ -1,5 +1,5 @@
 use secrecy::ExposeSecret;
-use std::{panic::AssertUnwindSafe, time::Duration};
+use std::{panic::AssertUnwindSafe, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
 use axum::{
@@ -13,28 +13,39 @@ use axum::{
 use futures::{future::Either, stream, StreamExt};
 use reqwest::StatusCode;
 use serde_json::json;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, warn};
 
 use self::conversations::ConversationId;
 
-use super::middleware::User;
+use super::{middleware::User, pins};
 use crate::{
     agent::{
-        self,
-        exchange::{CodeChunk, Exchange, FocusedChunk},
+        self, answer_cache, attachment,
+        budget::Budget,
+        exchange::{CodeChunk, Exchange, FocusedChunk, Outcome, Update},
+        profile::Profile,
+        stages::{FederatedRetriever, HybridRetriever, SemanticRetriever, Stages},
+        summary::{self, Summary},
+        templates::PromptTemplates,
         Action, Agent,
     },
     analytics::{EventData, QueryEvent},
-    db::QueryLog,
+    db::{AnswerFeedback, NewFeedback, QueryLog, SnippetUsage, SqlDb, UserProfiles},
     llm_gateway,
+    policy::{Scope, Verdict},
     query::parser::{self, Literal},
     repo::RepoRef,
-    Application,
+    Application, Configuration,
 };
 
+pub mod bundle;
+pub mod compare;
 pub mod conversations;
+pub mod testgen;
 
-const TIMEOUT_SECS: u64 = 60;
+/// The maximum number of characters in a comment on an answer.
+const MAX_FEEDBACK_COMMENT_CHARS: usize = 4000;
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct Vote {
@@ -51,6 +62,23 @@ pub enum VoteFeedback {
     Negative { feedback: String },
 }
 
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Feedback {
+    pub thread_id: uuid::Uuid,
+    pub query_id: uuid::Uuid,
+    pub repo_ref: Option<RepoRef>,
+    pub rating: Rating,
+    /// What the user liked, or what was wrong with the answer.
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
 pub(super) async fn vote(
     Extension(app): Extension<Application>,
     Extension(user): Extension<User>,
@@ -61,34 +89,207 @@ pub(super) async fn vote(
         &QueryEvent {
             query_id: params.query_id,
             thread_id: params.thread_id,
-            repo_ref: params.repo_ref,
-            data: EventData::output_stage("vote").with_payload("feedback", params.feedback),
+            repo_ref: params.repo_ref.clone(),
+            data: EventData::output_stage("vote").with_payload("feedback", &params.feedback),
         },
     );
+
+    let (positive, comment) = match &params.feedback {
+        VoteFeedback::Positive => (true, None),
+        VoteFeedback::Negative { feedback } => (false, Some(feedback.as_str())),
+    };
+
+    let repo_ref = params.repo_ref.map(|r| r.to_string());
+    let feedback = NewFeedback {
+        thread_id: params.thread_id,
+        query_id: params.query_id,
+        repo_ref: repo_ref.as_deref(),
+        prompt_version: &app.prompt_rollout.template(params.query_id).version,
+        positive,
+        comment: comment.map(str::trim).filter(|c| !c.is_empty()),
+    };
+
+    if let Err(err) = record_feedback(&app, &user, &feedback).await {
+        warn!(?err, "failed to store vote");
+    }
+}
+
+/// Rate an answer with a thumbs up or down, and optionally comment on it.
+///
+/// Feedback is stored with the version of the prompt template the answer was generated with,
+/// replacing any earlier feedback of the same user on the answer.
+pub(super) async fn feedback(
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+    Json(params): Json<Feedback>,
+) -> super::Result<impl IntoResponse> {
+    let comment = params
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty());
+
+    if comment.map_or(false, |c| c.chars().count() > MAX_FEEDBACK_COMMENT_CHARS) {
+        return Err(super::Error::user(format!(
+            "comment is longer than {MAX_FEEDBACK_COMMENT_CHARS} characters"
+        )));
+    }
+
+    app.track_query(
+        &user,
+        &QueryEvent {
+            query_id: params.query_id,
+            thread_id: params.thread_id,
+            repo_ref: params.repo_ref.clone(),
+            data: EventData::output_stage("feedback")
+                .with_payload("rating", params.rating)
+                .with_payload("comment", comment),
+        },
+    );
+
+    let repo_ref = params.repo_ref.map(|r| r.to_string());
+    let feedback = NewFeedback {
+        thread_id: params.thread_id,
+        query_id: params.query_id,
+        repo_ref: repo_ref.as_deref(),
+        prompt_version: &app.prompt_rollout.template(params.query_id).version,
+        positive: matches!(params.rating, Rating::Up),
+        comment,
+    };
+
+    record_feedback(&app, &user, &feedback).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Store feedback on an answer, and count it towards the prompt rollout and snippet usage.
+async fn record_feedback(app: &Application, user: &User, feedback: &NewFeedback<'_>) -> Result<()> {
+    app.prompt_rollout
+        .record_vote(feedback.query_id, feedback.positive)
+        .await;
+
+    if let Err(err) = SnippetUsage::new(&app.sql)
+        .vote(feedback.thread_id, feedback.query_id, feedback.positive)
+        .await
+    {
+        warn!(?err, "failed to record vote on snippet usage");
+    }
+
+    AnswerFeedback::new(&app.sql)
+        .insert(user.login(), feedback)
+        .await
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct Answer {
     pub q: String,
-    pub repo_ref: RepoRef,
+    /// The repository to answer the query against.
+    ///
+    /// This is optional for existing conversations, which default to the repository they were
+    /// last scoped to. When set, it overrides that scope for this and all subsequent turns.
+    pub repo_ref: Option<RepoRef>,
     #[serde(default = "default_thread_id")]
     pub thread_id: uuid::Uuid,
-    /// Optional id of the parent of the exchange to overwrite
-    /// If this UUID is nil, then overwrite the first exchange in the thread
+    /// The exchange to follow, which branches the conversation off at that exchange to edit the
+    /// next question or regenerate its answer. If this UUID is nil, the new branch starts with
+    /// this question.
+    ///
+    /// This defaults to the last exchange of the active branch. Other branches are kept, and can
+    /// be retrieved from `/answer/conversations/:thread_id`.
     pub parent_exchange_id: Option<uuid::Uuid>,
+    /// Also retrieve code from federated peers.
+    #[serde(default)]
+    pub federated: bool,
+    /// Also search the lexical index for identifiers in the question, and merge its results with
+    /// those of semantic search. This finds code by exact names that semantic search misses.
+    #[serde(default)]
+    pub hybrid: bool,
+    /// Research the question in depth, with tools to open files, list symbols and grep, within
+    /// the step and token limits set by `deep_max_steps` and `deep_max_tokens`.
+    #[serde(default)]
+    pub deep: bool,
+    /// An image to read code or text from, such as a screenshot of a stack trace, as a
+    /// `data:image/...;base64,` URL. Images are too large for a query string, so this is usually
+    /// sent in the body of a `POST`.
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Answer from scratch, even if the same question was answered recently. The new answer
+    /// replaces the cached one.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// The index of the interpretation picked from a `clarification_needed` outcome of the last
+    /// exchange. The question of that exchange is then answered from the code of this
+    /// interpretation, and `q` is only recorded.
+    #[serde(default)]
+    pub clarification: Option<usize>,
+    /// Record how code was retrieved in the `diagnostics` of each exchange: the candidates of
+    /// each search, what was dropped, and the state of the index that was searched.
+    #[serde(default)]
+    pub diagnostics: bool,
+    /// Retrieve code from the copy of the repository's semantic index pinned under this name,
+    /// rather than from the live index, so that evaluation runs compare answers over the same
+    /// code. Pinned answers are never cached.
+    #[serde(default)]
+    pub pin: Option<String>,
+    /// The number of answers to generate, from 1 to `MAX_ANSWERS`. The answers after the first are
+    /// generated from the same code at higher temperatures, and are returned as `alternatives`
+    /// of the exchange, for the user to pick the one that matches what they meant. Answers with
+    /// alternatives are never cached.
+    #[serde(default = "default_n")]
+    pub n: usize,
+    /// A directory of the repository, such as `services/billing`, that the code of the answer is
+    /// restricted to. Searches, and the files that the agent reads, leave out other paths.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// The branch to answer from, rather than the default branch. Scoped answers are never
+    /// cached.
+    #[serde(default)]
+    pub branch: Option<String>,
 }
 
+/// The most answers that a single question can ask for.
+const MAX_ANSWERS: usize = 3;
+
 fn default_thread_id() -> uuid::Uuid {
     uuid::Uuid::new_v4()
 }
 
+fn default_n() -> usize {
+    1
+}
+
 pub(super) async fn answer(
     Query(params): Query<Answer>,
     Extension(app): Extension<Application>,
     Extension(user): Extension<User>,
 ) -> super::Result<impl IntoResponse> {
+    handle(params, app, user).await
+}
+
+/// Like `answer`, but with the parameters in a JSON body, so that an image can be attached.
+pub(super) async fn answer_post(
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+    Json(params): Json<Answer>,
+) -> super::Result<impl IntoResponse> {
+    handle(params, app, user).await
+}
+
+async fn handle(
+    params: Answer,
+    app: Application,
+    user: User,
+) -> super::Result<
+    Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
+> {
     let query_id = uuid::Uuid::new_v4();
 
+    if !(1..=MAX_ANSWERS).contains(&params.n) {
+        return Err(super::Error::user(format!(
+            "`n` must be between 1 and {MAX_ANSWERS}"
+        )));
+    }
+
     let conversation_id = ConversationId {
         user_id: user
             .login()
@@ -97,9 +298,23 @@ pub(super) async fn answer(
         thread_id: params.thread_id,
     };
 
-    let (_, mut exchanges) = conversations::load(&app.sql, &conversation_id)
-        .await?
-        .unwrap_or_else(|| (params.repo_ref.clone(), Vec::new()));
+    let (repo_ref, tree) = match conversations::load(&app.sql, &conversation_id).await? {
+        Some((scope, exchanges)) => (params.repo_ref.clone().unwrap_or(scope), exchanges),
+        None => {
+            let repo_ref = match params.repo_ref.clone() {
+                Some(repo_ref) => Some(repo_ref),
+                None => default_repo(&app, &conversation_id.user_id).await,
+            }
+            .ok_or_else(|| super::Error::user("missing repo_ref for a new conversation"))?;
+
+            (repo_ref, Vec::new())
+        }
+    };
+
+    let params = Answer {
+        repo_ref: Some(repo_ref),
+        ..params
+    };
 
     let Answer {
         parent_exchange_id,
@@ -107,18 +322,33 @@ pub(super) async fn answer(
         ..
     } = &params;
 
-    if let Some(parent_exchange_id) = parent_exchange_id {
-        let truncate_from_index = if parent_exchange_id.is_nil() {
-            0
-        } else {
-            exchanges
-                .iter()
-                .position(|e| e.id == *parent_exchange_id)
-                .ok_or_else(|| super::Error::user("parent query id not found in exchanges"))?
-                + 1
-        };
+    let active = conversations::active_leaf(&tree);
+    let parent = parent_exchange_id.or(active).unwrap_or_default();
+    let mut exchanges = conversations::branch(&tree, Some(parent))
+        .ok_or_else(|| super::Error::user("parent query id not found in exchanges"))?;
+
+    // Summaries are written for one branch, and only apply to branches that share the exchanges
+    // they cover.
+    let summary = conversations::load_summary(&app.sql, &conversation_id)
+        .await?
+        .filter(|s| s.covers(&exchanges));
+
+    if let Some(choice) = params.clarification {
+        let (exchange, action) = clarified(&exchanges, query_id, choice)?;
+        exchanges.push(exchange);
 
-        exchanges.truncate(truncate_from_index);
+        return execute_agent(
+            params.clone(),
+            app.clone(),
+            user.clone(),
+            query_id,
+            conversation_id,
+            exchanges,
+            summary,
+            action,
+            None,
+        )
+        .await;
     }
 
     let query = parser::parse_nl(q)
@@ -135,8 +365,23 @@ pub(super) async fn answer(
         .clone()
         .into_owned();
 
+    let cache_key = answer_cache_key(&app, &user, &params, &exchanges).await;
+    if let Some(mut exchange) = cache_key
+        .as_ref()
+        .filter(|_| !params.no_cache)
+        .and_then(|key| app.answer_cache.get(key))
+    {
+        exchange.id = query_id;
+        exchange.parent = Some(parent);
+        // Nothing was spent on this request.
+        exchange.usage = None;
+        return cached_answer(params, app, user, conversation_id, exchange).await;
+    }
+
     let action = Action::Query(query_target);
-    exchanges.push(Exchange::new(query_id, query));
+    let mut exchange = Exchange::new(query_id, query);
+    exchange.parent = Some(parent);
+    exchanges.push(exchange);
 
     execute_agent(
         params.clone(),
@@ -145,12 +390,135 @@ pub(super) async fn answer(
         query_id,
         conversation_id,
         exchanges,
+        summary,
         action,
+        cache_key,
     )
     .await
 }
 
+/// Start an exchange that answers the question of the last exchange from the code of the
+/// interpretation that the user picked.
+fn clarified(
+    exchanges: &[Exchange],
+    query_id: uuid::Uuid,
+    choice: usize,
+) -> super::Result<(Exchange, Action)> {
+    let Some(Outcome::ClarificationNeeded { candidates, .. }) =
+        exchanges.last().and_then(|e| e.outcome.as_ref())
+    else {
+        return Err(super::Error::user(
+            "the last exchange did not ask for clarification",
+        ));
+    };
+
+    let interpretation = candidates
+        .get(choice)
+        .ok_or_else(|| super::Error::user("unknown clarification"))?;
+
+    let last = exchanges.last().unwrap();
+    let offset = exchanges.iter().map(|e| e.paths.len()).sum::<usize>();
+    let mut exchange = interpretation.exchange(query_id, last.query.clone(), offset);
+    exchange.parent = Some(last.id);
+    let paths = (offset..offset + exchange.paths.len()).collect();
+
+    Ok((exchange, Action::Answer { paths }))
+}
+
+/// The key that the answer to a question is cached under, if the question starts a conversation.
+///
+/// Questions that the policy blocks or rewrites are always answered by the agent, which enforces
+/// and audits the policy.
+async fn answer_cache_key(
+    app: &Application,
+    user: &User,
+    params: &Answer,
+    exchanges: &[Exchange],
+) -> Option<answer_cache::Key> {
+    if !app.answer_cache.is_enabled()
+        || !exchanges.is_empty()
+        || params.image.is_some()
+        || params.pin.is_some()
+        || params.n > 1
+        || params.path.is_some()
+        || params.branch.is_some()
+        || params.diagnostics
+        || !matches!(app.policy.check(Scope::Question, &params.q), Verdict::Allow)
+    {
+        return None;
+    }
+
+    let repo_ref = params.repo_ref.clone()?;
+    let indexed_at = app
+        .repo_pool
+        .read_async(&repo_ref, |_, repo| repo.last_index_unix_secs)
+        .await?;
+
+    Some(answer_cache::Key::new(
+        &params.q,
+        user.login(),
+        repo_ref,
+        indexed_at,
+        params.deep,
+        params.federated,
+        params.hybrid,
+    ))
+}
+
+/// Respond with a cached answer, and start a conversation with it as if it was just answered.
+async fn cached_answer(
+    params: Answer,
+    app: Application,
+    user: User,
+    conversation_id: ConversationId,
+    exchange: Exchange,
+) -> super::Result<
+    Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
+> {
+    let repo_ref = params
+        .repo_ref
+        .clone()
+        .ok_or_else(|| super::Error::user("missing repo_ref"))?;
+
+    // Access may have been revoked, or the quota used up, since the answer was cached.
+    super::check_llm_access(&app, &repo_ref).await?;
+    super::usage::check_quota(&app, &user).await?;
+
+    QueryLog::new(&app.sql).insert(&params.q).await?;
+    conversations::store_branch(
+        &app.sql,
+        conversation_id,
+        (repo_ref, vec![exchange.clone()]),
+        None,
+    )
+    .await?;
+
+    app.track_query(
+        &user,
+        &QueryEvent {
+            query_id: exchange.id,
+            thread_id: params.thread_id,
+            repo_ref: params.repo_ref,
+            data: EventData::output_stage("cached_answer").with_payload("q", &params.q),
+        },
+    );
+
+    let init = sse::Event::default()
+        .json_data(json!({
+            "thread_id": params.thread_id.to_string(),
+            "query_id": exchange.id
+        }))
+        .map_err(anyhow::Error::new);
+    let answer = sse::Event::default()
+        .json_data(Ok::<_, String>(exchange.compressed()))
+        .map_err(anyhow::Error::new);
+    let done = Ok(sse::Event::default().data("[DONE]"));
+
+    Ok(Sse::new(Box::pin(stream::iter([init, answer, done]))))
+}
+
 /// Like `try_execute_agent`, but additionally logs errors in our analytics.
+#[allow(clippy::too_many_arguments)]
 async fn execute_agent(
     params: Answer,
     app: Application,
@@ -158,7 +526,9 @@ async fn execute_agent(
     query_id: uuid::Uuid,
     conversation_id: ConversationId,
     exchanges: Vec<Exchange>,
+    summary: Option<Summary>,
     action: Action,
+    cache_key: Option<answer_cache::Key>,
 ) -> super::Result<
     Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
 > {
@@ -169,7 +539,9 @@ async fn execute_agent(
         query_id,
         conversation_id,
         exchanges,
+        summary,
         action,
+        cache_key,
     )
     .await;
 
@@ -181,7 +553,7 @@ async fn execute_agent(
             &QueryEvent {
                 query_id,
                 thread_id: params.thread_id,
-                repo_ref: Some(params.repo_ref),
+                repo_ref: params.repo_ref,
                 data: EventData::output_stage("error")
                     .with_payload("status", err.status.as_u16())
                     .with_payload("message", err.message()),
@@ -192,17 +564,21 @@ async fn execute_agent(
     response
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn try_execute_agent(
     params: Answer,
     app: Application,
     user: User,
     query_id: uuid::Uuid,
     conversation_id: ConversationId,
-    exchanges: Vec<Exchange>,
+    mut exchanges: Vec<Exchange>,
+    summary: Option<Summary>,
     mut action: Action,
+    cache_key: Option<answer_cache::Key>,
 ) -> super::Result<
     Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
 > {
+    super::usage::check_quota(&app, &user).await?;
     QueryLog::new(&app.sql).insert(&params.q).await?;
 
     let answer_api_token = app
@@ -210,47 +586,121 @@ async fn try_execute_agent(
         .map_err(|e| super::Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
         .map(|s| s.expose_secret().clone());
 
-    let llm_gateway = llm_gateway::Client::new(&app.config.answer_api_url)
+    let llm_gateway = app
+        .llm_gateway_client()
         .temperature(0.0)
         .bearer(answer_api_token)
         .session_reference_id(conversation_id.to_string());
 
-    // confirm client compatibility with answer-api
-    match llm_gateway
-        .is_compatible(env!("CARGO_PKG_VERSION").parse().unwrap())
-        .await
-    {
-        Ok(res) if res.status() == StatusCode::OK => (),
-        Ok(res) if res.status() == StatusCode::NOT_ACCEPTABLE => {
-            let out_of_date = futures::stream::once(async {
-                Ok(sse::Event::default()
-                    .json_data(serde_json::json!({"Err": "incompatible client"}))
-                    .unwrap())
-            });
-            return Ok(Sse::new(Box::pin(out_of_date)));
+    // Cancelled when the response stream is dropped before the answer is stored, which happens
+    // when the client disconnects. This aborts the LLM requests that are still in flight.
+    let cancellation = CancellationToken::new();
+    let llm_gateway = llm_gateway.cancellation(cancellation.clone());
+
+    // confirm client compatibility with answer-api, which direct backends don't go through
+    if llm_gateway.backend == llm_gateway::api::Backend::Gateway {
+        match llm_gateway
+            .is_compatible(env!("CARGO_PKG_VERSION").parse().unwrap())
+            .await
+        {
+            Ok(res) if res.status() == StatusCode::OK => (),
+            Ok(res) if res.status() == StatusCode::NOT_ACCEPTABLE => {
+                let out_of_date = futures::stream::once(async {
+                    Ok(sse::Event::default()
+                        .json_data(serde_json::json!({"Err": "incompatible client"}))
+                        .unwrap())
+                });
+                return Ok(Sse::new(Box::pin(out_of_date)));
+            }
+            Ok(_) => unreachable!(),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "failed to check compatibility ... defaulting to `incompatible`"
+                );
+                let failed_to_check = futures::stream::once(async {
+                    Ok(sse::Event::default()
+                        .json_data(serde_json::json!({"Err": "failed to check compatibility"}))
+                        .unwrap())
+                });
+                return Ok(Sse::new(Box::pin(failed_to_check)));
+            }
         }
-        Ok(_) => unreachable!(),
-        Err(err) => {
-            warn!(
-                ?err,
-                "failed to check compatibility ... defaulting to `incompatible`"
-            );
-            let failed_to_check = futures::stream::once(async {
-                Ok(sse::Event::default()
-                    .json_data(serde_json::json!({"Err": "failed to check compatibility"}))
-                    .unwrap())
-            });
-            return Ok(Sse::new(Box::pin(failed_to_check)));
+    }
+
+    if let Some(url) = &params.image {
+        if !attachment::is_enabled(&app.config) {
+            return Err(super::Error::user(
+                "attached images need `vision_model` or `ocr_command` to be configured",
+            ));
         }
-    };
+
+        let image = attachment::Image::from_data_url(url)
+            .map_err(|e| super::Error::user(format!("invalid image: {e}")))?;
+        let text = attachment::extract_text(&app.config, &llm_gateway, &image)
+            .await
+            .map_err(super::Error::internal)?;
+
+        if let Some(exchange) = exchanges.last_mut() {
+            exchange.attachment = Some(text).filter(|t| !t.is_empty());
+        }
+    }
 
     let Answer {
         thread_id,
         repo_ref,
+        federated,
+        hybrid,
+        deep,
+        diagnostics,
+        n,
+        branch,
         ..
     } = params.clone();
+    let repo_ref = repo_ref.ok_or_else(|| super::Error::user("missing repo_ref"))?;
+    super::check_llm_access(&app, &repo_ref).await?;
+    let pinned = match &params.pin {
+        // Peers don't have the pinned copy.
+        Some(_) if federated => {
+            return Err(super::Error::user(
+                "pinned answers can't also search federated peers",
+            ))
+        }
+        Some(name) => Some(pins::resolve(&app, name, &repo_ref).await?),
+        None => None,
+    };
+    let path_scope = match params.path.as_deref().map(|p| p.trim_matches('/')) {
+        // Peers answer from their own repositories.
+        Some(_) if federated => {
+            return Err(super::Error::user(
+                "scoped answers can't also search federated peers",
+            ))
+        }
+        Some("") | None => None,
+        Some(path) => Some(path.to_owned()),
+    };
+    if let (Some(branch), Some(exchange)) = (branch, exchanges.last_mut()) {
+        exchange.query.branch.insert(Literal::Plain(branch.into()));
+    }
+    let overall_timeout = Duration::from_secs(app.config.answer_timeout_secs);
+    let heartbeat = heartbeat(&app.config);
+    let stage_timeout = Duration::from_secs(app.config.answer_stage_timeout_secs);
+
     let stream = async_stream::try_stream! {
+        let cancel_on_drop = cancellation.drop_guard();
+        let deadline = tokio::time::Instant::now() + overall_timeout;
+
         let (exchange_tx, exchange_rx) = tokio::sync::mpsc::channel(10);
+        let mut stages = Stages::new(&app, &llm_gateway);
+        if pinned.is_some() {
+            stages.retriever = Arc::new(SemanticRetriever::new(pinned));
+        }
+        if federated {
+            stages.retriever = Arc::new(FederatedRetriever::new(&app));
+        }
+        if hybrid {
+            stages.retriever = Arc::new(HybridRetriever::new(&app, stages.retriever));
+        }
 
         let mut agent = Agent {
             app,
@@ -258,9 +708,17 @@ async fn try_execute_agent(
             exchanges,
             exchange_tx,
             llm_gateway,
+            stages,
+            summary,
             user,
             thread_id,
             query_id,
+            budget: deep.then(|| {
+                Budget::new(app.config.deep_max_steps, app.config.deep_max_tokens)
+            }),
+            diagnostics,
+            alternatives: n - 1,
+            path_scope,
             complete: false,
         };
 
@@ -280,20 +738,20 @@ async fn try_execute_agent(
                 .into_stream()
                 .map(Either::Right);
 
-            let timeout = Duration::from_secs(TIMEOUT_SECS);
+            // This ends the stream without a next action once the overall deadline is reached,
+            // while the timeout fails a stage that stops sending updates.
+            let steps = stream::select(left_stream, right_stream)
+                .take_until(tokio::time::sleep_until(deadline));
 
             let mut next = None;
-            for await item in tokio_stream::StreamExt::timeout(
-                stream::select(left_stream, right_stream),
-                timeout,
-            ) {
+            for await item in tokio_stream::StreamExt::timeout(steps, stage_timeout) {
                 match item {
                     Ok(Either::Left(exchange)) => yield exchange.compressed(),
                     Ok(Either::Right(next_action)) => match next_action {
-                        Ok(n) => break next = n,
-                        Err(e) => break 'outer Err(agent::Error::Processing(e)),
+                        Ok(n) => break next = Some(n),
+                        Err(e) => break 'outer Err(e.into()),
                     },
-                    Err(_) => break 'outer Err(agent::Error::Timeout(timeout)),
+                    Err(_) => break 'outer Err(agent::Error::Timeout(stage_timeout)),
                 }
             }
 
@@ -307,11 +765,19 @@ async fn try_execute_agent(
             }
 
             match next {
-                Some(a) => action = a,
-                None => break Ok(()),
+                Some(Some(a)) => action = a,
+                Some(None) => break Ok(()),
+                None => break Err(agent::Error::Timeout(overall_timeout)),
             }
         };
 
+        // Failed queries spend tokens too.
+        agent.record_usage().await;
+        if let Some(exchange) = agent.exchanges.last().filter(|e| e.usage.is_some()) {
+            yield exchange.compressed();
+        }
+
+        let timed_out = matches!(result, Err(agent::Error::Timeout(_)));
         match result {
             Ok(_) => {}
             Err(agent::Error::Timeout(duration)) => {
@@ -320,7 +786,19 @@ async fn try_execute_agent(
                     EventData::output_stage("error")
                         .with_payload("timeout", duration.as_secs()),
                 );
-                Err(anyhow!("reached timeout of {duration:?}"))?;
+
+                // Rather than failing, we send what the completed stages found so far.
+                let exchange = agent.exchanges.last_mut().context("agent lost its exchange")?;
+                let completed = exchange.completed_stages();
+                exchange.apply_update(Update::Outcome(Outcome::Partial {
+                    message: format!(
+                        "This answer was cut short after {} seconds. Here is what I found so far.",
+                        duration.as_secs()
+                    ),
+                    timeout: true,
+                    completed,
+                }));
+                yield exchange.compressed();
             }
             Err(agent::Error::Processing(e)) => {
                 agent.track_query(
@@ -332,7 +810,32 @@ async fn try_execute_agent(
         }
 
         // Storing the conversation here allows us to make subsequent requests.
-        conversations::store(&agent.app.sql, conversation_id, (agent.repo_ref.clone(), agent.exchanges.clone())).await?;
+        conversations::store_branch(
+            &agent.app.sql,
+            conversation_id.clone(),
+            (agent.repo_ref.clone(), agent.exchanges.clone()),
+            agent.summary.as_ref(),
+        ).await?;
+
+        agent.record_profile().await;
+
+        // Partial answers are not cached, so that asking again gets a complete one.
+        let cache_key = cache_key.filter(|_| !timed_out);
+        if let (Some(key), Some(exchange)) = (cache_key, agent.exchanges.last()) {
+            agent.app.answer_cache.insert(key, exchange);
+        }
+
+        // The answer is complete, so the summary may outlive the request.
+        cancel_on_drop.disarm();
+        tokio::spawn(update_summary(
+            agent.app.sql.clone(),
+            conversation_id,
+            agent.llm_gateway.clone(),
+            Arc::clone(&agent.app.prompt_templates),
+            agent.summary.clone(),
+            agent.exchanges.clone(),
+        ));
+
         agent.complete();
     };
 
@@ -360,7 +863,53 @@ async fn try_execute_agent(
 
     let stream = init_stream.chain(answer_stream).chain(done_stream);
 
-    Ok(Sse::new(Box::pin(stream)))
+    Ok(Sse::new(Box::pin(stream)).keep_alive(heartbeat))
+}
+
+/// Heartbeat events for answer streams, which go quiet while code is retrieved and the answer
+/// model prepares its response.
+fn heartbeat(config: &Configuration) -> sse::KeepAlive {
+    sse::KeepAlive::new()
+        .interval(Duration::from_secs(config.answer_heartbeat_secs))
+        .event(sse::Event::default().event("heartbeat"))
+}
+
+/// The indexed repository that the user asks about most, according to their context profile.
+async fn default_repo(app: &Application, user_id: &str) -> Option<RepoRef> {
+    let entries = match UserProfiles::new(&app.sql).list(user_id).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!(?err, "failed to load user profile");
+            return None;
+        }
+    };
+
+    let repo_ref = Profile::new(entries)
+        .default_repo()?
+        .parse::<RepoRef>()
+        .ok()?;
+    app.repo_pool.read_async(&repo_ref, |_, _| ()).await?;
+    Some(repo_ref)
+}
+
+/// Fold older exchanges of a conversation into its rolling summary, once there are enough of them.
+async fn update_summary(
+    sql: SqlDb,
+    conversation_id: ConversationId,
+    llm_gateway: llm_gateway::Client,
+    templates: Arc<PromptTemplates>,
+    previous: Option<Summary>,
+    exchanges: Vec<Exchange>,
+) {
+    match summary::summarize(&llm_gateway, &templates, previous.as_ref(), &exchanges).await {
+        Ok(Some(summary)) => {
+            if let Err(err) = conversations::store_summary(&sql, &conversation_id, &summary).await {
+                warn!(?err, "failed to store conversation summary");
+            }
+        }
+        Ok(None) => {}
+        Err(err) => warn!(?err, "failed to summarize conversation"),
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -389,9 +938,20 @@ pub async fn explain(
             params.line_end + 1,
             params.relative_path
         ),
-        repo_ref: params.repo_ref,
+        repo_ref: Some(params.repo_ref.clone()),
         thread_id: params.thread_id,
         parent_exchange_id: None,
+        federated: false,
+        hybrid: false,
+        deep: false,
+        image: None,
+        no_cache: false,
+        clarification: None,
+        diagnostics: false,
+        pin: None,
+        n: 1,
+        path: None,
+        branch: None,
     };
 
     let conversation_id = ConversationId {
@@ -418,7 +978,7 @@ pub async fn explain(
     let file_content = app
         .indexes
         .file
-        .by_path(&virtual_req.repo_ref, &params.relative_path, None)
+        .by_path(&params.repo_ref, &params.relative_path, None)
         .await
         .context("file retrieval failed")?
         .context("did not find requested file")?
@@ -432,6 +992,7 @@ pub async fn explain(
         .join("\n");
 
     let mut exchange = Exchange::new(query_id, query);
+    exchange.parent = Some(uuid::Uuid::nil());
 
     exchange.focused_chunk = Some(FocusedChunk {
         file_path: params.relative_path.clone(),
@@ -446,6 +1007,7 @@ pub async fn explain(
         start_line: params.line_start,
         end_line: params.line_end,
         snippet,
+        score: None,
     });
 
     let action = Action::Answer { paths: vec![0] };
@@ -457,7 +1019,9 @@ pub async fn explain(
         query_id,
         conversation_id,
         vec![exchange],
+        None,
         action,
+        None,
     )
     .await
 }
@@ -0,0 +1,363 @@
+//! Download the files cited by an answer as a zip archive.
+//!
+//! The files are read from the index, on the branch the question was asked about. They are the
+//! versions the answer was based on unless the repository was indexed again since, which the
+//! manifest records for each repository, along with the time of its indexed commit. A
+//! `manifest.json` at the root of the archive lists the cited line ranges of each file, so the
+//! bundle can be shared with people who don't have the repository.
+
+use std::io::{Cursor, Write};
+
+use anyhow::Context;
+use axum::{
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use super::conversations::{self, ConversationId};
+use crate::{
+    agent::exchange::Citation,
+    repo::RepoRef,
+    webserver::{self, middleware::User, Error, ErrorKind},
+    Application,
+};
+
+const MANIFEST_PATH: &str = "manifest.json";
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Params {
+    thread_id: uuid::Uuid,
+    query_id: uuid::Uuid,
+}
+
+#[derive(serde::Serialize)]
+struct Manifest {
+    thread_id: uuid::Uuid,
+    query_id: uuid::Uuid,
+    question: Option<String>,
+    /// The branch the files were read from, or `None` for the default branch.
+    branch: Option<String>,
+    /// Seconds since the unix epoch.
+    created_at: i64,
+    repos: Vec<BundledRepo>,
+    files: Vec<BundledFile>,
+    /// Cited files that are not in the archive.
+    missing: Vec<MissingFile>,
+}
+
+#[derive(serde::Serialize)]
+struct BundledRepo {
+    repo: String,
+    /// The time of the last indexed commit, in seconds since the unix epoch.
+    last_commit_unix_secs: u64,
+    last_index_unix_secs: u64,
+    /// Whether the repository was indexed after the answer, so that its files may have changed
+    /// since.
+    reindexed_since_answer: bool,
+}
+
+#[derive(serde::Serialize)]
+struct BundledFile {
+    repo: String,
+    path: String,
+    /// Where the file is in the archive.
+    archive_path: String,
+    lang: Option<String>,
+    /// The cited lines, which are 1-indexed and inclusive, in order and without overlaps.
+    ranges: Vec<(usize, usize)>,
+}
+
+#[derive(serde::Serialize)]
+struct MissingFile {
+    repo: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    path: String,
+    reason: String,
+}
+
+/// A cited file, with all of its cited ranges.
+struct CitedFile<'a> {
+    repo: &'a str,
+    peer: Option<&'a str>,
+    path: &'a str,
+    ranges: Vec<(usize, usize)>,
+}
+
+pub(in crate::webserver) async fn handle(
+    Query(params): Query<Params>,
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> webserver::Result<Response> {
+    let user_id = user
+        .login()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let conversation_id = ConversationId {
+        thread_id: params.thread_id,
+        user_id,
+    };
+    let (.., exchanges) = conversations::load(&app.sql, &conversation_id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchange = exchanges
+        .iter()
+        .find(|e| e.id == params.query_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "query was not found"))?;
+
+    if exchange.citations.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "the answer has no citations",
+        ));
+    }
+
+    let branch = exchange.query.first_branch().map(|b| b.into_owned());
+    let answered_at = exchange.answered_at().map(|t| t.timestamp());
+
+    let mut manifest = Manifest {
+        thread_id: params.thread_id,
+        query_id: params.query_id,
+        question: exchange.query(),
+        branch: branch.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+        repos: vec![],
+        files: vec![],
+        missing: vec![],
+    };
+    let mut contents = vec![];
+
+    for file in cited_files(&exchange.citations) {
+        let missing = |reason: &str| MissingFile {
+            repo: file.repo.to_owned(),
+            peer: file.peer.map(str::to_owned),
+            path: file.path.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        if file.peer.is_some() {
+            manifest
+                .missing
+                .push(missing("retrieved from a federated peer"));
+            continue;
+        }
+
+        let Ok(repo_ref) = file.repo.parse::<RepoRef>() else {
+            manifest.missing.push(missing("unknown repository"));
+            continue;
+        };
+
+        let Some((last_commit_unix_secs, last_index_unix_secs)) = app
+            .repo_pool
+            .read_async(&repo_ref, |_, repo| {
+                (repo.last_commit_unix_secs, repo.last_index_unix_secs)
+            })
+            .await
+        else {
+            manifest.missing.push(missing("repository is not indexed"));
+            continue;
+        };
+
+        let Some(doc) = app
+            .indexes
+            .file
+            .by_path(&repo_ref, file.path, branch.as_deref())
+            .await
+            .context("file retrieval failed")?
+        else {
+            manifest.missing.push(missing("file is no longer indexed"));
+            continue;
+        };
+
+        if !manifest.repos.iter().any(|r| r.repo == file.repo) {
+            manifest.repos.push(BundledRepo {
+                repo: file.repo.to_owned(),
+                last_commit_unix_secs,
+                last_index_unix_secs,
+                reindexed_since_answer: answered_at
+                    .is_some_and(|t| last_index_unix_secs as i64 > t),
+            });
+        }
+
+        let path = archive_path(file.repo, file.path);
+        contents.push((path.clone(), doc.content));
+        manifest.files.push(BundledFile {
+            repo: file.repo.to_owned(),
+            path: file.path.to_owned(),
+            archive_path: path,
+            lang: doc.lang,
+            ranges: file.ranges,
+        });
+    }
+
+    let archive = archive(&manifest, &contents)?;
+    let disposition = format!("attachment; filename=\"answer-{}.zip\"", params.query_id);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Group citations by file, merging overlapping and adjacent ranges.
+///
+/// Files are in the order they were first cited.
+fn cited_files(citations: &[Citation]) -> Vec<CitedFile<'_>> {
+    let mut files = Vec::<CitedFile<'_>>::new();
+
+    for citation in citations {
+        let range = (citation.start_line, citation.end_line);
+        let peer = citation.peer.as_deref();
+
+        match files
+            .iter_mut()
+            .find(|f| f.repo == citation.repo && f.peer == peer && f.path == citation.path)
+        {
+            Some(file) => file.ranges.push(range),
+            None => files.push(CitedFile {
+                repo: &citation.repo,
+                peer,
+                path: &citation.path,
+                ranges: vec![range],
+            }),
+        }
+    }
+
+    for file in &mut files {
+        file.ranges.sort_unstable();
+        file.ranges = file
+            .ranges
+            .iter()
+            .fold(vec![], |mut merged, &(start, end)| {
+                match merged.last_mut() {
+                    Some((_, last_end)) if start <= *last_end + 1 => *last_end = end.max(*last_end),
+                    _ => merged.push((start, end)),
+                }
+                merged
+            });
+    }
+
+    files
+}
+
+/// The path of a file in the archive, under a directory for its repository.
+///
+/// Empty, `.` and `..` components are dropped, so that entries cannot escape the archive root.
+fn archive_path(repo: &str, path: &str) -> String {
+    repo.split('/')
+        .chain(path.split('/'))
+        .filter(|c| !matches!(*c, "" | "." | ".."))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn archive(manifest: &Manifest, contents: &[(String, String)]) -> anyhow::Result<Vec<u8>> {
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    zip.start_file(MANIFEST_PATH, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+
+    for (path, content) in contents {
+        zip.start_file(path, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn citation(peer: Option<&str>, path: &str, start_line: usize, end_line: usize) -> Citation {
+        Citation {
+            repo: "github.com/bloopai/bloop".to_owned(),
+            peer: peer.map(str::to_owned),
+            path: path.to_owned(),
+            start_line,
+            end_line,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn groups_and_merges_ranges() {
+        let citations = [
+            citation(None, "src/b.rs", 30, 40),
+            citation(None, "src/a.rs", 1, 5),
+            citation(None, "src/b.rs", 10, 20),
+            citation(None, "src/b.rs", 21, 25),
+            citation(None, "src/b.rs", 35, 50),
+            citation(Some("peer"), "src/a.rs", 1, 5),
+        ];
+
+        let files = cited_files(&citations);
+        assert_eq!(
+            files
+                .iter()
+                .map(|f| (f.peer, f.path, f.ranges.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (None, "src/b.rs", vec![(10, 25), (30, 50)]),
+                (None, "src/a.rs", vec![(1, 5)]),
+                (Some("peer"), "src/a.rs", vec![(1, 5)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn archive_paths_stay_in_root() {
+        assert_eq!(
+            archive_path("github.com/bloopai/bloop", "src/main.rs"),
+            "github.com/bloopai/bloop/src/main.rs"
+        );
+        assert_eq!(
+            archive_path("local//home/me/repo", "../../etc/passwd"),
+            "local/home/me/repo/etc/passwd"
+        );
+    }
+
+    #[test]
+    fn archives_manifest_and_files() {
+        let manifest = Manifest {
+            thread_id: uuid::Uuid::nil(),
+            query_id: uuid::Uuid::nil(),
+            question: Some("where is main?".into()),
+            branch: None,
+            created_at: 0,
+            repos: vec![],
+            files: vec![],
+            missing: vec![],
+        };
+        let contents = [("repo/src/main.rs".to_owned(), "fn main() {}\n".to_owned())];
+
+        let bytes = archive(&manifest, &contents).unwrap();
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut names = zip.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, [MANIFEST_PATH, "repo/src/main.rs"]);
+
+        let mut main = String::new();
+        zip.by_name("repo/src/main.rs")
+            .unwrap()
+            .read_to_string(&mut main)
+            .unwrap();
+        assert_eq!(main, "fn main() {}\n");
+
+        let manifest: serde_json::Value =
+            serde_json::from_reader(zip.by_name(MANIFEST_PATH).unwrap()).unwrap();
+        assert_eq!(manifest["question"], "where is main?");
+    }
+}
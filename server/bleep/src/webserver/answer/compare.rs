@@ -0,0 +1,290 @@
+//! Compare a file across a historical revision and its current version.
+//!
+//! Like `/answer/test`, this does not run the agent. Both versions of the file are read from the
+//! index, so the revision must have been opted into with `PUT /repos/revisions`.
+
+use anyhow::Context;
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+use futures::TryStreamExt;
+use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+
+use crate::{
+    db::AuditEntry,
+    llm_gateway,
+    policy::{Scope, Verdict},
+    query::parser::revision_branch,
+    repo::RepoRef,
+    webserver::{self, middleware::User, usage, Error, ErrorKind},
+    Application,
+};
+
+const COMPARE_MODEL: &str = "gpt-4-0613";
+
+/// The maximum number of lines we include from each version of the file.
+const MAX_LINES: usize = 400;
+
+const DEFAULT_QUESTION: &str = "How did this file change, and why does it matter?";
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Compare {
+    repo_ref: RepoRef,
+    relative_path: String,
+    /// The tag of the historical revision, e.g. `v1.4`.
+    revision: String,
+    /// The branch to compare against. Defaults to the default branch.
+    branch: Option<String>,
+    /// What to ask about the two versions, e.g. "how did `parse` look in v1.4 vs now?"
+    q: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(in crate::webserver) struct Comparison {
+    relative_path: String,
+    revision: String,
+    /// Whether the two versions are identical, in which case the LLM is not asked.
+    unchanged: bool,
+    answer: String,
+}
+
+pub(in crate::webserver) async fn handle(
+    Query(params): Query<Compare>,
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    webserver::check_llm_access(&app, &params.repo_ref).await?;
+    usage::check_quota(&app, &user).await?;
+
+    let query_id = uuid::Uuid::new_v4();
+    let question = params.q.as_deref().unwrap_or(DEFAULT_QUESTION);
+    let question = enforce_policy(&app, &user, query_id, Scope::Question, question).await?;
+
+    let revision_branch = revision_branch(&params.revision);
+
+    let old = app
+        .indexes
+        .file
+        .by_path(
+            &params.repo_ref,
+            &params.relative_path,
+            Some(&revision_branch),
+        )
+        .await
+        .context("file retrieval failed")?
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "did not find requested file in this revision; is the revision indexed?",
+            )
+        })?;
+
+    let new = app
+        .indexes
+        .file
+        .by_path(
+            &params.repo_ref,
+            &params.relative_path,
+            params.branch.as_deref(),
+        )
+        .await
+        .context("file retrieval failed")?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "did not find requested file"))?;
+
+    if app.is_license_excluded(old.license.as_deref())
+        || app.is_license_excluded(new.license.as_deref())
+    {
+        return Err(
+            Error::user("this file is under a license whose code is never sent to an LLM")
+                .with_status(StatusCode::FORBIDDEN),
+        );
+    }
+
+    if old.content == new.content {
+        return Ok(Json(Comparison {
+            relative_path: params.relative_path,
+            revision: params.revision,
+            unchanged: true,
+            answer: String::new(),
+        }));
+    }
+
+    let prompt = compare_prompt(
+        &params.relative_path,
+        &params.revision,
+        &old.content,
+        &new.content,
+        &question,
+    );
+
+    let answer_api_token = app
+        .answer_api_token()
+        .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+        .map(|s| s.expose_secret().clone());
+
+    let llm_gateway = app
+        .llm_gateway_client()
+        .temperature(0.0)
+        .bearer(answer_api_token)
+        .model(COMPARE_MODEL);
+
+    let answer = llm_gateway
+        .chat(&[llm_gateway::api::Message::system(&prompt)], None)
+        .await?
+        .try_collect::<String>()
+        .await?;
+    usage::record(&app, &user, &params.repo_ref, &llm_gateway).await;
+
+    let answer = enforce_policy(&app, &user, query_id, Scope::Answer, &answer).await?;
+
+    Ok(Json(Comparison {
+        relative_path: params.relative_path,
+        revision: params.revision,
+        unchanged: false,
+        answer,
+    }))
+}
+
+/// Check `text` against the policy, as `/answer` does, and return the text to use.
+///
+/// Blocked text fails the request with the message of the rule that blocked it.
+async fn enforce_policy(
+    app: &Application,
+    user: &User,
+    query_id: uuid::Uuid,
+    scope: Scope,
+    text: &str,
+) -> webserver::Result<String> {
+    let (result, rules, action) = match app.policy.check(scope, text) {
+        Verdict::Allow => return Ok(text.to_owned()),
+        Verdict::Rewrite {
+            text: rewritten,
+            rules,
+        } => (Ok(rewritten), rules, "rewrite"),
+        Verdict::Block { rule, message } => (
+            Err(Error::user(message).with_status(StatusCode::FORBIDDEN)),
+            vec![rule],
+            "block",
+        ),
+    };
+
+    for rule in &rules {
+        app.audit_policy(&AuditEntry {
+            user_id: user.login(),
+            thread_id: query_id,
+            query_id,
+            scope: scope.as_str(),
+            rule,
+            action,
+            excerpt: text,
+        })
+        .await;
+    }
+
+    result
+}
+
+/// Number the lines of a file, keeping at most `MAX_LINES` of them.
+fn numbered(content: &str) -> String {
+    let mut out = content
+        .lines()
+        .take(MAX_LINES)
+        .enumerate()
+        .map(|(i, line)| format!("{} {line}", i + 1))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if content.lines().count() > MAX_LINES {
+        out += "\n[... truncated]";
+    }
+
+    out
+}
+
+fn compare_prompt(path: &str, revision: &str, old: &str, new: &str, question: &str) -> String {
+    let old = numbered(old);
+    let new = numbered(new);
+
+    format!(
+        r#"Below are two versions of the file {path}: first as of the revision {revision}, then as it is now.
+
+##### {path} at {revision}
+
+{old}
+
+##### {path} now
+
+{new}
+
+#####
+
+Answer the following question about how the file changed between {revision} and now: {question}
+- Only describe differences that are visible in the two versions above, DO NOT make up changes
+- Refer to code by the line numbers of the version it is in, and say which version that is
+- When quoting code, quote it exactly, in blocks of triple backticks
+- Answer in markdown"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_retrieval_only_repos() {
+        let dir = tempdir::TempDir::new("compare").unwrap();
+        let (app, repo_ref) = webserver::retrieval_only_app(dir.path()).await;
+
+        let params = Compare {
+            repo_ref,
+            relative_path: "src/lib.rs".into(),
+            revision: "v1.0".into(),
+            branch: None,
+            q: None,
+        };
+
+        let response = handle(Query(params), Extension(User::Unknown), Extension(app))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn enforces_the_policy_on_questions() {
+        let dir = tempdir::TempDir::new("compare").unwrap();
+        let mut app = Application::for_tests(dir.path()).await;
+        app.policy = Arc::new(
+            serde_json::from_str(
+                r#"{"rules": [{"name": "keys", "applies_to": "question", "check": "regex", "pattern": "api keys?", "message": "No."}]}"#,
+            )
+            .unwrap(),
+        );
+
+        let params = Compare {
+            repo_ref: RepoRef::from(&dir.path()),
+            relative_path: "src/lib.rs".into(),
+            revision: "v1.0".into(),
+            branch: None,
+            q: Some("where did the API keys go?".into()),
+        };
+
+        let response = handle(Query(params), Extension(User::Unknown), Extension(app))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_numbered() {
+        assert_eq!(
+            numbered("fn a() {}\nfn b() {}\n"),
+            "1 fn a() {}\n2 fn b() {}"
+        );
+
+        let long = "x\n".repeat(MAX_LINES + 1);
+        let out = numbered(&long);
+        assert_eq!(out.lines().count(), MAX_LINES + 1);
+        assert!(out.ends_with("[... truncated]"));
+    }
+}
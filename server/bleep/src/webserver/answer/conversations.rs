@@ -5,12 +5,12 @@ use axum::{
     Extension, Json,
 };
 use reqwest::StatusCode;
-use std::{fmt, str::FromStr};
+use std::{collections::HashMap, fmt, str::FromStr};
 use tracing::info;
 
 use crate::{
-    agent::exchange::Exchange,
-    db::SqlDb,
+    agent::{exchange::Exchange, summary::Summary},
+    db::{RepoRenames, SqlDb},
     repo::RepoRef,
     webserver::{self, middleware::User, Error, ErrorKind},
     Application,
@@ -109,8 +109,15 @@ pub(in crate::webserver) async fn delete(
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Thread {
+    /// The last exchange of the branch to return. This defaults to the active branch.
+    leaf: Option<uuid::Uuid>,
+}
+
 pub(in crate::webserver) async fn thread(
     Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<Thread>,
     Extension(user): Extension<User>,
     State(app): State<Application>,
 ) -> webserver::Result<impl IntoResponse> {
@@ -123,7 +130,9 @@ pub(in crate::webserver) async fn thread(
         .await?
         .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
 
-    let exchanges = exchanges
+    let leaf = params.leaf.or_else(|| active_leaf(&exchanges));
+    let exchanges = branch(&exchanges, leaf)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "branch was not found"))?
         .into_iter()
         .map(|ex| ex.compressed())
         .collect::<Vec<_>>();
@@ -131,7 +140,120 @@ pub(in crate::webserver) async fn thread(
     Ok(Json(exchanges))
 }
 
-pub async fn store(db: &SqlDb, id: ConversationId, conversation: Conversation) -> Result<()> {
+#[derive(serde::Serialize)]
+pub struct BranchPreview {
+    /// The last exchange of the branch, to retrieve it with.
+    pub leaf: uuid::Uuid,
+    /// The last question of the branch.
+    pub title: String,
+    pub exchanges: usize,
+    /// Whether this is the branch that the conversation continues on.
+    pub active: bool,
+}
+
+/// List the branches of a conversation, in the order they were last continued.
+pub(in crate::webserver) async fn branches(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    let user_id = user
+        .login()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let (.., exchanges) = load(&app.sql, &ConversationId { thread_id, user_id })
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let active = active_leaf(&exchanges);
+    let branches = leaves(&exchanges)
+        .into_iter()
+        .map(|leaf| BranchPreview {
+            leaf: leaf.id,
+            title: leaf.query().unwrap_or_default(),
+            exchanges: branch(&exchanges, Some(leaf.id)).map_or(0, |b| b.len()),
+            active: Some(leaf.id) == active,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(branches))
+}
+
+/// The last exchange of the active branch, which is the most recently stored exchange.
+pub fn active_leaf(exchanges: &[Exchange]) -> Option<uuid::Uuid> {
+    exchanges.last().map(|e| e.id)
+}
+
+/// The exchanges of the branch that ends with `leaf`, in order, or `None` if `leaf` is not part
+/// of the conversation.
+///
+/// Without a leaf, the branch is empty, as it is for a question that opens a new branch.
+pub fn branch(exchanges: &[Exchange], leaf: Option<uuid::Uuid>) -> Option<Vec<Exchange>> {
+    let mut branch = Vec::new();
+    let mut next = leaf.filter(|id| !id.is_nil());
+
+    while let Some(id) = next {
+        // A cycle could only come from corrupted data, but would otherwise loop forever.
+        if branch.len() == exchanges.len() {
+            return None;
+        }
+
+        let exchange = exchanges.iter().find(|e| e.id == id)?;
+        next = exchange.parent.filter(|id| !id.is_nil());
+        branch.push(exchange.clone());
+    }
+
+    branch.reverse();
+    Some(branch)
+}
+
+/// Exchanges that no other exchange follows, in the order they were stored.
+fn leaves(exchanges: &[Exchange]) -> Vec<&Exchange> {
+    exchanges
+        .iter()
+        .filter(|e| !exchanges.iter().any(|other| other.parent == Some(e.id)))
+        .collect()
+}
+
+/// Write the exchanges of a branch into the conversation, replacing those that are already there
+/// and appending new ones, which makes this branch the active one.
+fn graft(exchanges: &mut Vec<Exchange>, branch: Vec<Exchange>) {
+    for exchange in branch {
+        match exchanges.iter_mut().find(|e| e.id == exchange.id) {
+            Some(existing) => *existing = exchange,
+            None => exchanges.push(exchange),
+        }
+    }
+}
+
+/// Give every exchange of a conversation stored before branching its preceding exchange as its
+/// parent.
+fn link_parents(exchanges: &mut [Exchange]) {
+    let mut previous = uuid::Uuid::nil();
+    for exchange in exchanges {
+        exchange.parent.get_or_insert(previous);
+        previous = exchange.id;
+    }
+}
+
+/// Point citations of renamed repositories to their current names.
+fn rename_citations(exchanges: &mut [Exchange], renames: &HashMap<String, String>) {
+    let citations = exchanges.iter_mut().flat_map(|e| e.citations.iter_mut());
+
+    for citation in citations.filter(|c| c.peer.is_none()) {
+        if let Some(name) = renames.get(&citation.repo) {
+            citation.repo = name.clone();
+        }
+    }
+}
+
+pub async fn store(
+    db: &SqlDb,
+    id: ConversationId,
+    conversation: Conversation,
+    summary: Option<&Summary>,
+) -> Result<()> {
     info!("writing conversation {}-{}", id.user_id, id.thread_id);
     let mut transaction = db.begin().await?;
 
@@ -155,16 +277,18 @@ pub async fn store(db: &SqlDb, id: ConversationId, conversation: Conversation) -
         .context("couldn't find conversation title")?;
 
     let exchanges = serde_json::to_string(&exchanges)?;
+    let summary = summary.map(serde_json::to_string).transpose()?;
     sqlx::query! {
         "INSERT INTO conversations (\
-            user_id, thread_id, repo_ref, title, exchanges, created_at\
+            user_id, thread_id, repo_ref, title, exchanges, summary, created_at\
             ) \
-            VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))",
+            VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))",
         user_id,
         thread_id,
         repo_ref,
         title,
         exchanges,
+        summary,
     }
     .execute(&mut transaction)
     .await?;
@@ -174,6 +298,20 @@ pub async fn store(db: &SqlDb, id: ConversationId, conversation: Conversation) -
     Ok(())
 }
 
+/// Store a branch of a conversation as its active branch, keeping the other branches.
+pub async fn store_branch(
+    db: &SqlDb,
+    id: ConversationId,
+    conversation: Conversation,
+    summary: Option<&Summary>,
+) -> Result<()> {
+    let (repo_ref, branch) = conversation;
+    let mut exchanges = load(db, &id).await?.map(|(_, e)| e).unwrap_or_default();
+    graft(&mut exchanges, branch);
+
+    store(db, id, (repo_ref, exchanges), summary).await
+}
+
 pub async fn load(db: &SqlDb, id: &ConversationId) -> Result<Option<Conversation>> {
     let (user_id, thread_id) = (id.user_id.clone(), id.thread_id.to_string());
 
@@ -192,7 +330,206 @@ pub async fn load(db: &SqlDb, id: &ConversationId) -> Result<Option<Conversation
     };
 
     let repo_ref = RepoRef::from_str(&row.repo_ref).context("failed to parse repo ref")?;
-    let exchanges = serde_json::from_str(&row.exchanges)?;
+    let mut exchanges = serde_json::from_str::<Vec<Exchange>>(&row.exchanges)?;
+    link_parents(&mut exchanges);
+    rename_citations(&mut exchanges, &RepoRenames::new(db).all().await?);
 
     Ok(Some((repo_ref, exchanges)))
 }
+
+/// Every path referenced in the answers a user received about a repository, with repetitions.
+pub async fn queried_paths(db: &SqlDb, user_id: &str, repo_ref: &RepoRef) -> Result<Vec<String>> {
+    let repo_ref = repo_ref.to_string();
+
+    let rows = sqlx::query! {
+        "SELECT exchanges FROM conversations \
+         WHERE user_id = ? AND repo_ref = ?",
+        user_id,
+        repo_ref,
+    }
+    .fetch_all(db.as_ref())
+    .await?;
+
+    let mut paths = Vec::new();
+    for row in rows {
+        let exchanges = serde_json::from_str::<Vec<Exchange>>(&row.exchanges)?;
+        paths.extend(exchanges.into_iter().flat_map(|e| e.paths));
+    }
+
+    Ok(paths)
+}
+
+/// The conversations of every user.
+pub async fn all(db: &SqlDb) -> Result<Vec<Conversation>> {
+    let rows = sqlx::query! { "SELECT repo_ref, exchanges FROM conversations" }
+        .fetch_all(db.as_ref())
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let repo_ref = RepoRef::from_str(&row.repo_ref).context("failed to parse repo ref")?;
+            let exchanges = serde_json::from_str::<Vec<Exchange>>(&row.exchanges)?;
+            Ok((repo_ref, exchanges))
+        })
+        .collect()
+}
+
+pub async fn load_summary(db: &SqlDb, id: &ConversationId) -> Result<Option<Summary>> {
+    let (user_id, thread_id) = (id.user_id.clone(), id.thread_id.to_string());
+
+    let row = sqlx::query! {
+        "SELECT summary FROM conversations \
+         WHERE user_id = ? AND thread_id = ?",
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(db.as_ref())
+    .await?;
+
+    match row.and_then(|r| r.summary) {
+        Some(summary) => Ok(Some(serde_json::from_str(&summary)?)),
+        None => Ok(None),
+    }
+}
+
+/// Store the summary of a conversation, unless the conversation has since moved to another branch,
+/// or has a summary that covers more of it.
+pub async fn store_summary(db: &SqlDb, id: &ConversationId, summary: &Summary) -> Result<()> {
+    let exchanges = load(db, id).await?.map(|(_, e)| e).unwrap_or_default();
+    let active = branch(&exchanges, active_leaf(&exchanges)).unwrap_or_default();
+    if !summary.covers(&active) {
+        return Ok(());
+    }
+
+    let current = load_summary(db, id).await?.filter(|s| s.covers(&active));
+    if current.is_some_and(|s| s.exchanges >= summary.exchanges) {
+        return Ok(());
+    }
+
+    let (user_id, thread_id) = (id.user_id.clone(), id.thread_id.to_string());
+    let summary = serde_json::to_string(summary)?;
+
+    sqlx::query! {
+        "UPDATE conversations SET summary = ? \
+         WHERE user_id = ? AND thread_id = ?",
+        summary,
+        user_id,
+        thread_id,
+    }
+    .execute(db.as_ref())
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(id: u128, parent: Option<u128>) -> Exchange {
+        let mut exchange = Exchange::default();
+        exchange.id = uuid::Uuid::from_u128(id);
+        exchange.parent = parent.map(uuid::Uuid::from_u128);
+        exchange
+    }
+
+    fn ids(exchanges: &[Exchange]) -> Vec<u128> {
+        exchanges.iter().map(|e| e.id.as_u128()).collect()
+    }
+
+    #[test]
+    fn links_parents_of_linear_conversations() {
+        let mut exchanges = vec![exchange(1, None), exchange(2, None), exchange(3, Some(1))];
+        link_parents(&mut exchanges);
+
+        let parents = exchanges
+            .iter()
+            .map(|e| e.parent.unwrap().as_u128())
+            .collect::<Vec<_>>();
+        assert_eq!(parents, [0, 1, 1]);
+    }
+
+    #[test]
+    fn follows_branches() {
+        // 1 -> 2 -> 3, with 2 regenerated as 4, and the first question edited as 5.
+        let exchanges = vec![
+            exchange(1, Some(0)),
+            exchange(2, Some(1)),
+            exchange(3, Some(2)),
+            exchange(4, Some(1)),
+            exchange(5, Some(0)),
+        ];
+
+        let branch = |leaf: Option<u128>| {
+            super::branch(&exchanges, leaf.map(uuid::Uuid::from_u128)).map(|b| ids(&b))
+        };
+        assert_eq!(branch(Some(3)), Some(vec![1, 2, 3]));
+        assert_eq!(branch(Some(4)), Some(vec![1, 4]));
+        assert_eq!(branch(Some(5)), Some(vec![5]));
+        assert_eq!(branch(Some(0)), Some(vec![]));
+        assert_eq!(branch(None), Some(vec![]));
+        assert_eq!(branch(Some(6)), None);
+
+        assert_eq!(active_leaf(&exchanges), Some(uuid::Uuid::from_u128(5)));
+        let leaves = leaves(&exchanges).into_iter().cloned().collect::<Vec<_>>();
+        assert_eq!(ids(&leaves), [3, 4, 5]);
+    }
+
+    #[test]
+    fn renames_citations() {
+        let citation = |repo: &str, peer: Option<&str>| crate::agent::exchange::Citation {
+            repo: repo.to_owned(),
+            peer: peer.map(str::to_owned),
+            path: "src/main.rs".to_owned(),
+            start_line: 1,
+            end_line: 2,
+            score: None,
+        };
+
+        let mut exchanges = vec![exchange(1, None)];
+        exchanges[0].citations = vec![
+            citation("github.com/old/name", None),
+            citation("github.com/old/name", Some("peer")),
+            citation("github.com/other/repo", None),
+        ];
+
+        let renames = [("github.com/old/name".into(), "github.com/new/name".into())].into();
+        rename_citations(&mut exchanges, &renames);
+
+        let repos = exchanges[0]
+            .citations
+            .iter()
+            .map(|c| c.repo.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repos,
+            [
+                "github.com/new/name",
+                "github.com/old/name",
+                "github.com/other/repo"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let exchanges = vec![exchange(1, Some(2)), exchange(2, Some(1))];
+        assert_eq!(
+            branch(&exchanges, Some(uuid::Uuid::from_u128(1))).map(|b| b.len()),
+            None
+        );
+    }
+
+    #[test]
+    fn grafts_branches() {
+        let mut exchanges = vec![exchange(1, Some(0)), exchange(2, Some(1))];
+
+        let mut continued = exchange(1, Some(0));
+        continued.answer = Some("updated".to_owned());
+        graft(&mut exchanges, vec![continued, exchange(3, Some(1))]);
+
+        assert_eq!(ids(&exchanges), [1, 2, 3]);
+        assert_eq!(exchanges[0].answer.as_deref(), Some("updated"));
+        assert_eq!(active_leaf(&exchanges), Some(uuid::Uuid::from_u128(3)));
+    }
+}
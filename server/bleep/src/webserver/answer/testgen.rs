@@ -0,0 +1,483 @@
+//! Generate a test for a snippet of code.
+//!
+//! Unlike `/answer`, this does not run the agent. We gather the snippet, and the existing tests
+//! that most likely cover it, and ask the LLM to write a new test in their style. Those are the
+//! test files that reference the symbols defined in the snippet, or if there are none, the test
+//! files named after the file it is in.
+
+use std::{collections::HashMap, ops::RangeInclusive, path::Path};
+
+use anyhow::Context;
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+use futures::TryStreamExt;
+use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+use tracing::debug;
+
+use crate::{
+    indexes::reader::ContentDocument,
+    intelligence::{
+        code_navigation::{CodeNavigationContext, Token},
+        Language, NodeKind, TSLanguage,
+    },
+    llm_gateway,
+    repo::RepoRef,
+    webserver::{self, middleware::User, usage, Error, ErrorKind},
+    Application,
+};
+
+const TEST_MODEL: &str = "gpt-4-0613";
+
+/// The maximum number of existing test files we include as examples.
+const MAX_EXISTING_TESTS: usize = 2;
+
+/// The maximum number of lines we include from each existing test file.
+const MAX_EXISTING_TEST_LINES: usize = 200;
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct GenerateTest {
+    relative_path: String,
+    line_start: usize,
+    line_end: usize,
+    branch: Option<String>,
+    repo_ref: RepoRef,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(in crate::webserver) struct GeneratedTest {
+    /// The suggested path for the new test.
+    target_path: String,
+    /// Whether `target_path` already exists, in which case `code` should be added to it.
+    target_exists: bool,
+    /// Existing tests that were used as examples.
+    existing_tests: Vec<String>,
+    language: Option<String>,
+    code: String,
+}
+
+pub(in crate::webserver) async fn handle(
+    Query(params): Query<GenerateTest>,
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    webserver::check_llm_access(&app, &params.repo_ref).await?;
+    usage::check_quota(&app, &user).await?;
+    let branch = params.branch.as_deref();
+
+    let file = app
+        .indexes
+        .file
+        .by_path(&params.repo_ref, &params.relative_path, branch)
+        .await
+        .context("file retrieval failed")?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "did not find requested file"))?;
+
+    let snippet = file
+        .content
+        .lines()
+        .skip(params.line_start)
+        .take(params.line_end.saturating_sub(params.line_start) + 1)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if snippet.trim().is_empty() {
+        return Err(Error::user("requested line range is empty"));
+    }
+
+    let lang_ids = match file.lang.as_deref().map(TSLanguage::from_id) {
+        Some(Language::Supported(config)) => config.language_ids,
+        _ => &[],
+    };
+    let all_docs = if lang_ids.is_empty() {
+        vec![]
+    } else {
+        app.indexes
+            .file
+            .by_repo(&params.repo_ref, lang_ids.iter(), branch)
+            .await
+    };
+
+    let mut existing_tests = referencing_tests(
+        &params.repo_ref,
+        &file,
+        params.line_start..=params.line_end,
+        all_docs,
+    );
+
+    if existing_tests.is_empty() {
+        debug!("no tests reference the snippet, matching test files by name");
+
+        let stem = file_stem(&params.relative_path);
+        existing_tests = app
+            .indexes
+            .file
+            .fuzzy_path_match(&params.repo_ref, stem, branch, 50)
+            .await
+            .map(|doc| doc.relative_path)
+            .filter(|path| path != &params.relative_path && is_test_path(path))
+            .collect();
+    }
+
+    existing_tests.truncate(MAX_EXISTING_TESTS);
+
+    debug!(?existing_tests, "found existing tests");
+
+    let mut examples = Vec::new();
+    for path in &existing_tests {
+        if let Some(doc) = app
+            .indexes
+            .file
+            .by_path(&params.repo_ref, path, branch)
+            .await?
+        {
+            let content = doc
+                .content
+                .lines()
+                .take(MAX_EXISTING_TEST_LINES)
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            examples.push((path.as_str(), content));
+        }
+    }
+
+    let (target_path, target_exists) = match existing_tests.first() {
+        Some(path) => (path.clone(), true),
+        None => suggest_test_path(&params.relative_path, file.lang.as_deref()),
+    };
+
+    let prompt = test_prompt(
+        &params.relative_path,
+        &snippet,
+        &examples,
+        &target_path,
+        file.lang.as_deref(),
+    );
+
+    let answer_api_token = app
+        .answer_api_token()
+        .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+        .map(|s| s.expose_secret().clone());
+
+    let llm_gateway = app
+        .llm_gateway_client()
+        .temperature(0.0)
+        .bearer(answer_api_token)
+        .model(TEST_MODEL);
+
+    let response = llm_gateway
+        .chat(&[llm_gateway::api::Message::system(&prompt)], None)
+        .await?
+        .try_collect::<String>()
+        .await?;
+    usage::record(&app, &user, &params.repo_ref, &llm_gateway).await;
+
+    let code = extract_code(&response)
+        .ok_or_else(|| Error::internal("the model did not return any code"))?
+        .to_owned();
+
+    Ok(Json(GeneratedTest {
+        target_path,
+        target_exists,
+        existing_tests,
+        language: file.lang,
+        code,
+    }))
+}
+
+/// The test files that reference the top-level symbols defined in `lines` of `file`, with the
+/// files that reference them most first.
+///
+/// `all_docs` are the documents that references are looked for in, which must include `file`.
+fn referencing_tests(
+    repo_ref: &RepoRef,
+    file: &ContentDocument,
+    lines: RangeInclusive<usize>,
+    all_docs: Vec<ContentDocument>,
+) -> Vec<String> {
+    let Some(scope_graph) = file.symbol_locations.scope_graph() else {
+        return vec![];
+    };
+    let Some(source_document_idx) = all_docs
+        .iter()
+        .position(|doc| doc.relative_path == file.relative_path)
+    else {
+        return vec![];
+    };
+
+    let definitions = scope_graph
+        .graph
+        .node_indices()
+        .filter(|&idx| scope_graph.is_top_level(idx))
+        .filter(|&idx| matches!(scope_graph.get_node(idx), Some(NodeKind::Def(_))))
+        .map(|idx| scope_graph.graph[idx].range())
+        .filter(|range| lines.contains(&range.start.line))
+        .collect::<Vec<_>>();
+
+    let mut ctx = CodeNavigationContext {
+        repo_ref: repo_ref.clone(),
+        token: Token {
+            relative_path: &file.relative_path,
+            start_byte: 0,
+            end_byte: 0,
+        },
+        all_docs,
+        source_document_idx,
+    };
+
+    let mut references = HashMap::<String, usize>::new();
+    for range in definitions {
+        ctx.token.start_byte = range.start.byte;
+        ctx.token.end_byte = range.end.byte;
+
+        for symbols in ctx.token_info() {
+            if symbols.file == file.relative_path || !is_test_path(&symbols.file) {
+                continue;
+            }
+
+            let count = symbols.data.iter().filter(|o| !o.is_definition()).count();
+            *references.entry(symbols.file).or_default() += count;
+        }
+    }
+
+    let mut tests = references
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect::<Vec<_>>();
+    tests.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    tests.into_iter().map(|(path, _)| path).collect()
+}
+
+fn file_stem(path: &str) -> &str {
+    Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(path)
+}
+
+/// Heuristically decide whether a path contains tests, by common naming conventions.
+fn is_test_path(path: &str) -> bool {
+    let path = Path::new(path);
+
+    let in_test_dir = path
+        .parent()
+        .into_iter()
+        .flat_map(|p| p.components())
+        .filter_map(|c| c.as_os_str().to_str())
+        .any(|c| matches!(c, "test" | "tests" | "__tests__" | "spec" | "specs"));
+
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let is_test_file = stem.starts_with("test_")
+        || stem.ends_with("_test")
+        || stem.ends_with(".test")
+        || stem.ends_with("_spec")
+        || stem.ends_with(".spec")
+        || stem.ends_with("Test")
+        || stem.ends_with("Tests");
+
+    in_test_dir || is_test_file
+}
+
+/// Suggest where a new test for `path` should go, when there are no existing tests for it.
+///
+/// Returns the suggested path, and whether that path already exists.
+fn suggest_test_path(path: &str, lang: Option<&str>) -> (String, bool) {
+    let p = Path::new(path);
+    let dir = p.parent().and_then(|d| d.to_str()).unwrap_or_default();
+    let stem = file_stem(path);
+    let ext = p.extension().and_then(|e| e.to_str()).unwrap_or_default();
+
+    let join = |name: String| {
+        if dir.is_empty() {
+            name
+        } else {
+            format!("{dir}/{name}")
+        }
+    };
+
+    match lang.map(str::to_ascii_lowercase).as_deref() {
+        // Rust unit tests live in the same file, in a `tests` module.
+        Some("rust") => (path.to_owned(), true),
+        Some("go") => (join(format!("{stem}_test.go")), false),
+        Some("python") => (join(format!("test_{stem}.py")), false),
+        Some("javascript" | "typescript" | "tsx" | "jsx") => {
+            (join(format!("{stem}.test.{ext}")), false)
+        }
+        Some("java" | "c#") => (join(format!("{stem}Test.{ext}")), false),
+        _ => (format!("tests/{stem}_test.{ext}"), false),
+    }
+}
+
+fn test_prompt(
+    path: &str,
+    snippet: &str,
+    examples: &[(&str, String)],
+    target_path: &str,
+    lang: Option<&str>,
+) -> String {
+    let lang = lang.unwrap_or("the same language as the code");
+
+    let examples = if examples.is_empty() {
+        "There are no existing tests for this code. Use the most common test framework for the \
+        language."
+            .to_owned()
+    } else {
+        examples
+            .iter()
+            .map(|(path, content)| format!("Existing tests in {path}:\n\n{content}"))
+            .collect::<Vec<_>>()
+            .join("\n\n#####\n\n")
+    };
+
+    format!(
+        r#"Below is some code from the file {path}.
+
+#####
+
+{snippet}
+
+#####
+
+{examples}
+
+#####
+
+Your job is to write a new test for the code above, which will be added to {target_path}.
+- Write the test in {lang}
+- Follow the style, naming and framework of the existing tests, if there are any
+- Only test behaviour that is visible in the code above, DO NOT make up functions or types
+- Include only the code that needs to be added, not the existing contents of {target_path}
+- Surround the test in a single block of triple backticks"#
+    )
+}
+
+/// Extract the contents of the first markdown code block.
+fn extract_code(response: &str) -> Option<&str> {
+    let (_, rest) = response.split_once("```")?;
+    // Skip the language tag, if any.
+    let (_, rest) = rest.split_once('\n')?;
+    let code = rest.split_once("```").map_or(rest, |(code, _)| code);
+
+    Some(code.trim_end()).filter(|c| !c.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_test_path() {
+        assert!(is_test_path("server/bleep/tests/query.rs"));
+        assert!(is_test_path("client/src/utils/__tests__/index.ts"));
+        assert!(is_test_path("client/src/utils/index.test.ts"));
+        assert!(is_test_path("pkg/parser_test.go"));
+        assert!(is_test_path("test_parser.py"));
+        assert!(is_test_path("src/main/java/ParserTest.java"));
+
+        assert!(!is_test_path("server/bleep/src/query/parser.rs"));
+        assert!(!is_test_path("src/contest.py"));
+    }
+
+    fn doc(path: &str, content: &str) -> ContentDocument {
+        use crate::{intelligence::TreeSitterFile, symbol::SymbolLocations};
+
+        let scope_graph = TreeSitterFile::try_build(content.as_bytes(), "Rust")
+            .and_then(TreeSitterFile::scope_graph)
+            .unwrap();
+
+        ContentDocument {
+            content: content.to_owned(),
+            lang: Some("Rust".to_owned()),
+            relative_path: path.to_owned(),
+            line_end_indices: content.match_indices('\n').map(|(i, _)| i as u32).collect(),
+            symbol_locations: SymbolLocations::TreeSitter(scope_graph),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finds_tests_by_references() {
+        let source = doc(
+            "src/lexer.rs",
+            "pub fn tokenize(s: &str) -> usize {\n    s.len()\n}\n\npub fn unused() {}\n",
+        );
+        let all_docs = vec![
+            source.clone(),
+            // Named after another file, but exercises `tokenize`.
+            doc(
+                "tests/parser.rs",
+                "use demo::tokenize;\n\n#[test]\nfn counts() {\n    assert_eq!(tokenize(\"ab\"), 2);\n    assert_eq!(tokenize(\"\"), 0);\n}\n",
+            ),
+            doc(
+                "tests/other.rs",
+                "use demo::tokenize;\n\n#[test]\nfn once() {\n    tokenize(\"a\");\n}\n",
+            ),
+            // Not a test.
+            doc(
+                "src/main.rs",
+                "use demo::tokenize;\n\nfn main() {\n    tokenize(\"x\");\n}\n",
+            ),
+        ];
+        let repo_ref = RepoRef::from("github.com/BloopAI/bloop");
+
+        assert_eq!(
+            referencing_tests(&repo_ref, &source, 0..=2, all_docs.clone()),
+            ["tests/parser.rs", "tests/other.rs"]
+        );
+
+        // Nothing references `unused`, so tests are matched by name instead.
+        assert!(referencing_tests(&repo_ref, &source, 4..=4, all_docs).is_empty());
+    }
+
+    #[test]
+    fn test_suggest_test_path() {
+        assert_eq!(
+            suggest_test_path("src/query/parser.rs", Some("Rust")),
+            ("src/query/parser.rs".to_owned(), true)
+        );
+        assert_eq!(
+            suggest_test_path("pkg/parser.go", Some("Go")),
+            ("pkg/parser_test.go".to_owned(), false)
+        );
+        assert_eq!(
+            suggest_test_path("client/src/utils/index.ts", Some("TypeScript")),
+            ("client/src/utils/index.test.ts".to_owned(), false)
+        );
+        assert_eq!(
+            suggest_test_path("parser.py", Some("Python")),
+            ("test_parser.py".to_owned(), false)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_retrieval_only_repos() {
+        let dir = tempdir::TempDir::new("testgen").unwrap();
+        let (app, repo_ref) = webserver::retrieval_only_app(dir.path()).await;
+
+        let params = GenerateTest {
+            relative_path: "src/lib.rs".into(),
+            line_start: 0,
+            line_end: 10,
+            branch: None,
+            repo_ref,
+        };
+
+        let response = handle(Query(params), Extension(User::Unknown), Extension(app))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_extract_code() {
+        assert_eq!(
+            extract_code("Here you go:\n```rust\n#[test]\nfn foo() {}\n```\nDone."),
+            Some("#[test]\nfn foo() {}")
+        );
+        assert_eq!(extract_code("no code"), None);
+        assert_eq!(extract_code("```\n\n```"), None);
+    }
+}
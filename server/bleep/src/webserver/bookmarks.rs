@@ -0,0 +1,264 @@
+//! Bookmarks of files and line ranges, for the "saved" panel.
+//!
+//! Bookmarked files also get a retrieval boost in the answers of the user who bookmarked them.
+
+use std::collections::BTreeMap;
+
+use axum::{extract::Path, Json};
+
+use super::{middleware::User, prelude::*};
+use crate::{
+    db::{Bookmark, Bookmarks, NewBookmark},
+    repo::RepoRef,
+    Application,
+};
+
+/// The maximum number of tags on a bookmark.
+const MAX_TAGS: usize = 20;
+
+/// The maximum number of lines in the preview of a bookmark.
+const MAX_PREVIEW_LINES: usize = 10;
+
+#[derive(Deserialize)]
+pub(super) struct Create {
+    repo_ref: RepoRef,
+    path: String,
+    /// 1-indexed first line of the bookmarked range. Leave out both lines to bookmark a file.
+    start_line: Option<usize>,
+    /// 1-indexed last line of the bookmarked range, inclusive.
+    end_line: Option<usize>,
+    note: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ListParams {
+    repo_ref: Option<RepoRef>,
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(super) struct Created {
+    id: i64,
+}
+
+#[derive(Serialize)]
+pub(super) struct SavedRepo {
+    repo_ref: String,
+    bookmarks: Vec<Saved>,
+}
+
+#[derive(Serialize)]
+pub(super) struct Saved {
+    #[serde(flatten)]
+    bookmark: Bookmark,
+    /// The first lines of the bookmarked range, or of the file.
+    preview: Option<String>,
+    /// Whether the bookmarked file, or range, is no longer in the index.
+    stale: bool,
+}
+
+pub(super) async fn create(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Json(params): Json<Create>,
+) -> Result<impl IntoResponse> {
+    let user_id = user_id(&user)?;
+    let lines = lines(params.start_line, params.end_line)?;
+    let tags = normalize_tags(params.tags)?;
+    let note = params
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+
+    let id = Bookmarks::new(&app.sql)
+        .insert(
+            user_id,
+            &NewBookmark {
+                repo_ref: &params.repo_ref.to_string(),
+                relative_path: &params.path,
+                lines,
+                note,
+                tags: &tags,
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(Created { id })))
+}
+
+/// List the bookmarks of the user, newest first.
+pub(super) async fn list(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Query(params): Query<ListParams>,
+) -> Result<impl IntoResponse> {
+    let bookmarks = Bookmarks::new(&app.sql).list(user_id(&user)?).await?;
+    let repo_ref = params.repo_ref.map(|r| r.to_string());
+
+    Ok(Json(
+        bookmarks
+            .into_iter()
+            .filter(|b| repo_ref.as_ref().map_or(true, |r| &b.repo_ref == r))
+            .filter(|b| params.tag.as_ref().map_or(true, |t| b.tags.contains(t)))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+pub(super) async fn delete(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse> {
+    if Bookmarks::new(&app.sql).delete(user_id(&user)?, id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::user("bookmark not found").with_status(StatusCode::NOT_FOUND))
+    }
+}
+
+/// The bookmarks of the user grouped by repository, with a preview of each.
+pub(super) async fn saved(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let bookmarks = Bookmarks::new(&app.sql).list(user_id(&user)?).await?;
+
+    let mut repos = BTreeMap::<String, Vec<Saved>>::new();
+    for bookmark in bookmarks {
+        let doc = match bookmark.repo_ref.parse::<RepoRef>() {
+            Ok(repo_ref) => app
+                .indexes
+                .file
+                .by_path(&repo_ref, &bookmark.relative_path, None)
+                .await
+                .map_err(Error::internal)?,
+            Err(_) => None,
+        };
+
+        let preview = doc
+            .as_ref()
+            .and_then(|doc| preview(&doc.content, bookmark.lines));
+
+        repos
+            .entry(bookmark.repo_ref.clone())
+            .or_default()
+            .push(Saved {
+                stale: preview.is_none(),
+                preview,
+                bookmark,
+            });
+    }
+
+    Ok(Json(
+        repos
+            .into_iter()
+            .map(|(repo_ref, bookmarks)| SavedRepo {
+                repo_ref,
+                bookmarks,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn user_id(user: &User) -> Result<&str> {
+    user.login()
+        .ok_or_else(|| Error::user("didn't have user ID"))
+}
+
+fn lines(start: Option<usize>, end: Option<usize>) -> Result<Option<(usize, usize)>> {
+    match (start, end) {
+        (None, None) => Ok(None),
+        (Some(start), Some(end)) if 0 < start && start <= end => Ok(Some((start, end))),
+        (Some(_), Some(_)) => Err(Error::user("invalid line range, lines are 1-indexed")),
+        _ => Err(Error::user("both start_line and end_line are needed")),
+    }
+}
+
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>> {
+    let mut tags = tags
+        .into_iter()
+        .map(|t| t.trim().to_owned())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+
+    if tags.len() > MAX_TAGS {
+        return Err(Error::user(format!("at most {MAX_TAGS} tags are allowed")));
+    }
+
+    Ok(tags)
+}
+
+/// The first lines of a bookmarked range, or `None` if the range is past the end of the file.
+fn preview(content: &str, lines: Option<(usize, usize)>) -> Option<String> {
+    let (start, end) = lines.unwrap_or((1, MAX_PREVIEW_LINES));
+    let end = end.min(start + MAX_PREVIEW_LINES - 1);
+
+    let lines = content
+        .lines()
+        .skip(start - 1)
+        .take(end - start + 1)
+        .collect::<Vec<_>>();
+
+    if lines.is_empty() && !content.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_lines() {
+        assert_eq!(lines(None, None).unwrap(), None);
+        assert_eq!(lines(Some(3), Some(5)).unwrap(), Some((3, 5)));
+        assert_eq!(lines(Some(3), Some(3)).unwrap(), Some((3, 3)));
+        assert!(lines(Some(0), Some(5)).is_err());
+        assert!(lines(Some(5), Some(3)).is_err());
+        assert!(lines(Some(5), None).is_err());
+    }
+
+    #[test]
+    fn normalizes_tags() {
+        assert_eq!(
+            normalize_tags(vec![
+                " auth ".into(),
+                "".into(),
+                "api".into(),
+                "auth".into()
+            ])
+            .unwrap(),
+            ["api", "auth"]
+        );
+        assert!(normalize_tags((0..=MAX_TAGS).map(|i| i.to_string()).collect()).is_err());
+    }
+
+    #[test]
+    fn previews_ranges() {
+        let content = (1..=30)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert_eq!(preview(&content, Some((2, 4))).unwrap(), "2\n3\n4");
+        assert_eq!(
+            preview(&content, None).unwrap(),
+            (1..=10)
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        assert_eq!(
+            preview(&content, Some((25, 40))).unwrap(),
+            "25\n26\n27\n28\n29\n30"
+        );
+        assert_eq!(preview(&content, Some((31, 40))), None);
+    }
+}
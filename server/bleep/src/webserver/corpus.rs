@@ -0,0 +1,336 @@
+//! Export of the semantic corpus, to fine-tune embedding and reranking models offline.
+//!
+//! Every chunk in the semantic index becomes one JSON line, with its metadata and the questions
+//! whose answers used it. Secrets are redacted from the code as they are before it is sent to the
+//! LLM, and with `block_secrets` chunks holding secrets that look real are left out. Questions go
+//! through the question policy, and blocked questions are left out.
+//!
+//! A model trained on the export is imported by pointing `model_dir` at its `model.onnx` and
+//! `tokenizer.json`, or at a directory named in `embedding_models` for the repositories that should
+//! use it, after which their files are re-embedded with it as they are indexed.
+
+use std::collections::HashMap;
+
+use axum::{body::StreamBody, http::header};
+
+use super::{answer::conversations, prelude::*, Admin};
+use crate::{
+    agent::exchange::Exchange,
+    federation,
+    policy::{Policy, Scope, Verdict},
+    repo::RepoRef,
+    secrets,
+    semantic::Payload,
+    Application,
+};
+
+/// The number of points read from qdrant at a time.
+const PAGE_SIZE: u32 = 256;
+
+/// A chunk of the corpus, as a line of the export.
+#[derive(Serialize, Debug)]
+struct Record {
+    id: Option<String>,
+    repo_ref: String,
+    relative_path: String,
+    lang: String,
+    /// 0-indexed and inclusive.
+    start_line: u64,
+    end_line: u64,
+    text: String,
+    /// The model that embedded the chunk, as `name@version`.
+    embedding_model: Option<String>,
+    /// The kinds of secrets that were redacted from `text`.
+    redacted: Vec<&'static str>,
+    queries: Vec<AnsweredQuery>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct AnsweredQuery {
+    query: String,
+    /// Whether the answer cited the chunk, rather than only reading it.
+    cited: bool,
+}
+
+/// A range of a file that was used to answer a question.
+struct Use {
+    query: String,
+    start_line: usize,
+    end_line: usize,
+    cited: bool,
+}
+
+/// The questions that were answered with code from each file, by repository and path.
+#[derive(Default)]
+struct Answered(HashMap<(String, String), Vec<Use>>);
+
+impl Answered {
+    fn new(conversations: Vec<(RepoRef, Vec<Exchange>)>, policy: &Policy) -> Self {
+        let mut answered = Self::default();
+
+        for (repo_ref, exchanges) in conversations {
+            let repo_ref = repo_ref.to_string();
+
+            for exchange in exchanges {
+                let Some(query) = exchange.query() else {
+                    continue;
+                };
+
+                let query = match policy.check(Scope::Question, &query) {
+                    Verdict::Allow => query,
+                    Verdict::Rewrite { text, .. } => text,
+                    Verdict::Block { .. } => continue,
+                };
+                let query = secrets::redact(&query).text.into_owned();
+
+                for chunk in &exchange.code_chunks {
+                    // Chunks of other bloop instances are not in this corpus.
+                    if federation::parse_remote_path(&chunk.path).is_some() {
+                        continue;
+                    }
+
+                    let cited = exchange.citations.iter().any(|c| {
+                        c.peer.is_none()
+                            && c.path == chunk.path
+                            && c.start_line <= chunk.end_line + 1
+                            && chunk.start_line + 1 <= c.end_line
+                    });
+
+                    answered
+                        .0
+                        .entry((repo_ref.clone(), chunk.path.clone()))
+                        .or_default()
+                        .push(Use {
+                            query: query.clone(),
+                            start_line: chunk.start_line,
+                            end_line: chunk.end_line,
+                            cited,
+                        });
+                }
+            }
+        }
+
+        answered
+    }
+
+    /// The questions answered with code that overlaps a chunk, once each.
+    fn queries(&self, payload: &Payload) -> Vec<AnsweredQuery> {
+        let key = (payload.repo_ref.clone(), payload.relative_path.clone());
+        let Some(uses) = self.0.get(&key) else {
+            return vec![];
+        };
+
+        let mut queries = Vec::<AnsweredQuery>::new();
+        for u in uses.iter().filter(|u| {
+            u.start_line as u64 <= payload.end_line && payload.start_line <= u.end_line as u64
+        }) {
+            match queries.iter_mut().find(|q| q.query == u.query) {
+                Some(q) => q.cited |= u.cited,
+                None => queries.push(AnsweredQuery {
+                    query: u.query.clone(),
+                    cited: u.cited,
+                }),
+            }
+        }
+
+        queries
+    }
+}
+
+/// Turn a point into a line of the export, unless its secrets keep it out.
+fn record(payload: Payload, answered: &Answered, block_secrets: bool) -> Option<Record> {
+    let redaction = secrets::redact(&payload.text);
+    if block_secrets && redaction.high_entropy {
+        return None;
+    }
+
+    let queries = answered.queries(&payload);
+    Some(Record {
+        id: payload.id,
+        queries,
+        text: redaction.text.into_owned(),
+        embedding_model: payload.embedding_model,
+        redacted: redaction.kinds,
+        repo_ref: payload.repo_ref,
+        relative_path: payload.relative_path,
+        lang: payload.lang,
+        start_line: payload.start_line,
+        end_line: payload.end_line,
+    })
+}
+
+/// Stream the semantic corpus as JSON lines, for admins.
+pub(super) async fn export(
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let Some(semantic) = app.semantic.clone() else {
+        return Err(Error::new(
+            ErrorKind::Configuration,
+            "semantic search is not enabled",
+        ));
+    };
+
+    let answered = Answered::new(conversations::all(&app.sql).await?, &app.policy);
+    let block_secrets = app.config.block_secrets;
+
+    let stream = async_stream::try_stream! {
+        for collection in semantic.collections().await? {
+            let mut offset = None;
+
+            loop {
+                let (points, next) = semantic.scroll_page(&collection, offset, PAGE_SIZE).await?;
+
+                for payload in points {
+                    if let Some(record) = record(payload, &answered, block_secrets) {
+                        let mut line = serde_json::to_string(&record).map_err(anyhow::Error::from)?;
+                        line.push('\n');
+                        yield line;
+                    }
+                }
+
+                match next {
+                    Some(next) => offset = Some(next),
+                    None => break,
+                }
+            }
+        }
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"corpus.jsonl\"",
+            ),
+        ],
+        StreamBody::new(stream),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::exchange::{Citation, CodeChunk},
+        query::parser::{Literal, SemanticQuery},
+    };
+
+    fn exchange(query: &str, chunks: &[(&str, usize, usize)], cited: &[usize]) -> Exchange {
+        let code_chunks = chunks
+            .iter()
+            .enumerate()
+            .map(|(alias, &(path, start_line, end_line))| CodeChunk {
+                path: path.to_owned(),
+                alias,
+                snippet: String::new(),
+                start_line,
+                end_line,
+                score: None,
+            })
+            .collect::<Vec<_>>();
+
+        Exchange {
+            query: SemanticQuery {
+                target: Some(Literal::Plain(query.to_owned().into())),
+                ..Default::default()
+            },
+            citations: cited
+                .iter()
+                .map(|&i| Citation::new("github.com/BloopAI/bloop", &code_chunks[i]))
+                .collect(),
+            code_chunks,
+            ..Default::default()
+        }
+    }
+
+    fn payload(path: &str, start_line: u64, end_line: u64) -> Payload {
+        Payload {
+            repo_ref: "github.com/BloopAI/bloop".to_owned(),
+            relative_path: path.to_owned(),
+            start_line,
+            end_line,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn links_chunks_to_queries() {
+        let repo_ref = "github.com/BloopAI/bloop".parse::<RepoRef>().unwrap();
+        let policy = serde_json::from_str::<Policy>(
+            r#"{"rules": [{"name": "deny", "check": "regex", "pattern": "password"}]}"#,
+        )
+        .unwrap();
+
+        let answered = Answered::new(
+            vec![(
+                repo_ref,
+                vec![
+                    exchange(
+                        "where are queries parsed?",
+                        &[("src/parser.rs", 10, 20), ("src/main.rs", 0, 5)],
+                        &[0],
+                    ),
+                    exchange("what calls the parser?", &[("src/parser.rs", 18, 30)], &[]),
+                    exchange("where is the password?", &[("src/parser.rs", 0, 40)], &[]),
+                    exchange(
+                        "what is acme?",
+                        &[("acme::github.com/acme/api::src/parser.rs", 0, 40)],
+                        &[],
+                    ),
+                ],
+            )],
+            &policy,
+        );
+
+        let query = |query: &str, cited| AnsweredQuery {
+            query: query.to_owned(),
+            cited,
+        };
+
+        assert_eq!(
+            answered.queries(&payload("src/parser.rs", 15, 19)),
+            [
+                query("where are queries parsed?", true),
+                query("what calls the parser?", false)
+            ]
+        );
+        assert_eq!(
+            answered.queries(&payload("src/parser.rs", 25, 35)),
+            [query("what calls the parser?", false)]
+        );
+        assert!(answered
+            .queries(&payload("src/parser.rs", 31, 40))
+            .is_empty());
+        assert!(answered.queries(&payload("src/lib.rs", 0, 5)).is_empty());
+    }
+
+    #[test]
+    fn redacts_records() {
+        let secret = "api_key = \"9f8Kq2LxZ7vB4nT1mR6wY3hJ\"";
+        let answered = Answered::default();
+
+        let redacted = record(
+            Payload {
+                text: secret.to_owned(),
+                ..payload("src/main.rs", 0, 1)
+            },
+            &answered,
+            false,
+        )
+        .unwrap();
+        assert!(!redacted.text.contains("9f8Kq2LxZ7vB4nT1mR6wY3hJ"));
+        assert_eq!(redacted.redacted, ["secret"]);
+
+        let blocked = record(
+            Payload {
+                text: secret.to_owned(),
+                ..payload("src/main.rs", 0, 1)
+            },
+            &answered,
+            true,
+        );
+        assert!(blocked.is_none());
+    }
+}
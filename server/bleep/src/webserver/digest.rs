@@ -0,0 +1,414 @@
+//! "What changed since my last visit" digests.
+//!
+//! Each user has a last-seen commit per repository. A digest lists the commits made since then,
+//! keeping those that touch the directories the user asks about most, and can optionally be
+//! summarized by the LLM. Viewing a digest does not move the last-seen commit; clients mark it as
+//! seen with `PUT /digest/seen` once it has been shown.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use axum::Json;
+use futures::TryStreamExt;
+use secrecy::ExposeSecret;
+
+use super::{middleware::User, prelude::*, usage};
+use crate::{db::LastSeen, llm_gateway, repo::RepoRef, Application};
+
+const DIGEST_MODEL: &str = "gpt-3.5-turbo-16k-0613";
+
+/// The maximum number of commits read since the last visit.
+const MAX_COMMITS: usize = 200;
+
+/// The number of most queried directories that a digest is scoped to.
+const FOCUS_DIRS: usize = 5;
+
+/// The maximum number of changed files listed per commit in the summary prompt.
+const MAX_PROMPT_FILES: usize = 10;
+
+#[derive(Deserialize)]
+pub(super) struct DigestParams {
+    repo_ref: RepoRef,
+    /// Whether to have the LLM summarize the relevant commits.
+    #[serde(default)]
+    summarize: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Digest {
+    /// The last-seen commit, or `None` on the first visit, in which case no commits are listed.
+    since: Option<String>,
+    /// The current head of the repository, which can be marked as seen.
+    head: String,
+    /// The directories the user asks about most. Empty when the user has no history, in which
+    /// case every commit is relevant.
+    focus: Vec<String>,
+    /// Commits since the last visit that touch the focus directories, newest first.
+    commits: Vec<DigestCommit>,
+    /// The number of commits since the last visit that were left out as unrelated to the focus.
+    other_commits: usize,
+    /// Whether there were more commits since the last visit than were read.
+    truncated: bool,
+    summary: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(super) struct DigestCommit {
+    id: String,
+    author: String,
+    /// Seconds since the unix epoch.
+    time: i64,
+    subject: String,
+    files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct MarkSeen {
+    repo_ref: RepoRef,
+    /// The commit to mark as seen. Defaults to the current head.
+    commit: Option<String>,
+}
+
+/// Summarize the changes to a repository since the user last caught up with it.
+pub(super) async fn handle(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Query(params): Query<DigestParams>,
+) -> Result<impl IntoResponse> {
+    // Only summaries are sent to an LLM, and listing the commits is fine.
+    if params.summarize {
+        super::check_llm_access(&app, &params.repo_ref).await?;
+        usage::check_quota(&app, &user).await?;
+    }
+
+    let user_id = user.login().ok_or_else(|| Error::user("missing user ID"))?;
+    let disk_path = disk_path(&app, &params.repo_ref).await?;
+
+    let head = git(&disk_path, &["rev-parse", "HEAD"]).await?;
+    let since = LastSeen::new(&app.sql)
+        .get(user_id, &params.repo_ref.to_string())
+        .await?;
+
+    let mut commits = match &since {
+        Some(since) if *since != head => {
+            let range = format!("{since}..HEAD");
+            let max_count = format!("--max-count={}", MAX_COMMITS + 1);
+            parse_log(
+                &git(
+                    &disk_path,
+                    &[
+                        "log",
+                        "--no-merges",
+                        "--no-renames",
+                        "--name-only",
+                        "--format=%x1e%H%x1f%an%x1f%at%x1f%s",
+                        &max_count,
+                        &range,
+                    ],
+                )
+                .await?,
+            )
+        }
+        _ => vec![],
+    };
+
+    let truncated = commits.len() > MAX_COMMITS;
+    commits.truncate(MAX_COMMITS);
+
+    let queried =
+        super::answer::conversations::queried_paths(&app.sql, user_id, &params.repo_ref).await?;
+    let focus = focus_dirs(&queried, FOCUS_DIRS);
+
+    let total = commits.len();
+    commits.retain(|c| is_relevant(c, &focus));
+    let other_commits = total - commits.len();
+
+    let summary = if params.summarize && !commits.is_empty() {
+        let answer_api_token = app
+            .answer_api_token()
+            .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+            .map(|s| s.expose_secret().clone());
+
+        let llm_gateway = app
+            .llm_gateway_client()
+            .temperature(0.0)
+            .bearer(answer_api_token)
+            .model(DIGEST_MODEL);
+
+        let summary = llm_gateway
+            .chat(
+                &[llm_gateway::api::Message::system(&digest_prompt(
+                    &params.repo_ref.display_name(),
+                    &focus,
+                    &commits,
+                ))],
+                None,
+            )
+            .await?
+            .try_collect::<String>()
+            .await?;
+        usage::record(&app, &user, &params.repo_ref, &llm_gateway).await;
+
+        Some(summary)
+    } else {
+        None
+    };
+
+    Ok(Json(Digest {
+        since,
+        head,
+        focus,
+        commits,
+        other_commits,
+        truncated,
+        summary,
+    }))
+}
+
+/// Mark a commit as the last one the user has seen in a repository.
+pub(super) async fn mark_seen(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Json(params): Json<MarkSeen>,
+) -> Result<()> {
+    let user_id = user.login().ok_or_else(|| Error::user("missing user ID"))?;
+    let disk_path = disk_path(&app, &params.repo_ref).await?;
+
+    let rev = format!("{}^{{commit}}", params.commit.as_deref().unwrap_or("HEAD"));
+    let commit = git(&disk_path, &["rev-parse", "--verify", &rev])
+        .await
+        .map_err(|_| Error::user("unknown commit"))?;
+
+    LastSeen::new(&app.sql)
+        .set(user_id, &params.repo_ref.to_string(), &commit)
+        .await?;
+
+    Ok(())
+}
+
+async fn disk_path(app: &Application, repo_ref: &RepoRef) -> Result<PathBuf> {
+    app.repo_pool
+        .read_async(repo_ref, |_, repo| repo.disk_path.clone())
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown repository"))
+}
+
+async fn git(disk_path: &Path, args: &[&str]) -> Result<String> {
+    let output = tokio::process::Command::new("git")
+        .args(args)
+        .current_dir(disk_path)
+        .output()
+        .await
+        .context("failed to run git")?;
+
+    if !output.status.success() {
+        return Err(Error::internal(format!(
+            "failed to read repository history: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Parse the output of `git log --name-only`, with records separated by `\x1e` and the fields of
+/// the header line separated by `\x1f`.
+fn parse_log(log: &str) -> Vec<DigestCommit> {
+    log.split('\x1e')
+        .filter_map(|record| {
+            let mut lines = record.lines();
+            let mut fields = lines.next()?.splitn(4, '\x1f');
+
+            Some(DigestCommit {
+                id: fields.next()?.to_owned(),
+                author: fields.next()?.to_owned(),
+                time: fields.next()?.parse().ok()?,
+                subject: fields.next().unwrap_or_default().to_owned(),
+                files: lines
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// The `limit` directories that contain the most queried paths, most queried first.
+fn focus_dirs(paths: &[String], limit: usize) -> Vec<String> {
+    let mut counts = HashMap::<&str, usize>::new();
+    for path in paths {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        *counts.entry(dir).or_default() += 1;
+    }
+
+    let mut dirs = counts.into_iter().collect::<Vec<_>>();
+    dirs.sort_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+
+    dirs.into_iter()
+        .take(limit)
+        .map(|(dir, _)| dir.to_owned())
+        .collect()
+}
+
+/// Whether a commit touches any of the `focus` directories, or any subdirectory of them.
+///
+/// Without a focus, every commit is relevant. Files at the root of the repository form their own
+/// focus, which does not include subdirectories.
+fn is_relevant(commit: &DigestCommit, focus: &[String]) -> bool {
+    if focus.is_empty() {
+        return true;
+    }
+
+    commit.files.iter().any(|file| {
+        focus.iter().any(|dir| {
+            if dir.is_empty() {
+                !file.contains('/')
+            } else {
+                file.strip_prefix(dir.as_str())
+                    .map_or(false, |rest| rest.starts_with('/'))
+            }
+        })
+    })
+}
+
+fn digest_prompt(repo_name: &str, focus: &[String], commits: &[DigestCommit]) -> String {
+    let focus = if focus.is_empty() {
+        "the whole repository".to_owned()
+    } else {
+        focus
+            .iter()
+            .map(|dir| {
+                if dir.is_empty() {
+                    "the repository root".to_owned()
+                } else {
+                    format!("`{dir}`")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let commits = commits
+        .iter()
+        .map(|c| {
+            let mut files = c
+                .files
+                .iter()
+                .take(MAX_PROMPT_FILES)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            if c.files.len() > MAX_PROMPT_FILES {
+                files += &format!(" and {} more", c.files.len() - MAX_PROMPT_FILES);
+            }
+
+            format!(
+                "- {} ({}, by {}): {files}",
+                c.subject,
+                &c.id[..c.id.len().min(8)],
+                c.author
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"Below are the commits made to the repository {repo_name} since the user last looked at it, newest first, with the files each one changed. The user mostly works with {focus}.
+
+{commits}
+
+Write a short catch-up digest for the user:
+- Group related commits, and lead with the changes that matter most to the areas the user works with
+- Explain what changed and why it matters, rather than listing every commit
+- Only describe changes that are evident from the commits above, DO NOT make up changes
+- Mention commits by their short hash in backticks
+- Answer in markdown, in at most 10 bullet points"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_summaries_of_retrieval_only_repos() {
+        let dir = tempdir::TempDir::new("digest").unwrap();
+        let (app, repo_ref) = crate::webserver::retrieval_only_app(dir.path()).await;
+
+        let params = DigestParams {
+            repo_ref,
+            summarize: true,
+        };
+
+        let response = handle(Extension(User::Unknown), Extension(app), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn commit(files: &[&str]) -> DigestCommit {
+        DigestCommit {
+            id: "0".repeat(40),
+            author: "A".to_owned(),
+            time: 0,
+            subject: "change".to_owned(),
+            files: files.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_log() {
+        let log = "\x1eabc\x1fJane Doe\x1f1692000000\x1fFix parser: handle \x1f\n\n\
+                   src/parser.rs\nsrc/lib.rs\n\
+                   \x1edef\x1fJohn\x1f1691000000\x1fEmpty commit\n";
+
+        assert_eq!(
+            parse_log(log),
+            [
+                DigestCommit {
+                    id: "abc".to_owned(),
+                    author: "Jane Doe".to_owned(),
+                    time: 1692000000,
+                    subject: "Fix parser: handle \x1f".to_owned(),
+                    files: vec!["src/parser.rs".to_owned(), "src/lib.rs".to_owned()],
+                },
+                DigestCommit {
+                    id: "def".to_owned(),
+                    author: "John".to_owned(),
+                    time: 1691000000,
+                    subject: "Empty commit".to_owned(),
+                    files: vec![],
+                },
+            ]
+        );
+        assert!(parse_log("").is_empty());
+    }
+
+    #[test]
+    fn focuses_on_most_queried_dirs() {
+        let paths =
+            ["src/a.rs", "src/b.rs", "docs/x.md", "README.md", "src/a.rs"].map(str::to_owned);
+
+        assert_eq!(focus_dirs(&paths, 2), ["src", ""]);
+        assert_eq!(focus_dirs(&paths, 5), ["src", "", "docs"]);
+        assert!(focus_dirs(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn relevance() {
+        let focus = vec!["server/src".to_owned(), "".to_owned()];
+
+        assert!(is_relevant(&commit(&["server/src/lib.rs"]), &focus));
+        assert!(is_relevant(&commit(&["server/src/db/mod.rs"]), &focus));
+        assert!(is_relevant(&commit(&["Cargo.toml"]), &focus));
+        assert!(!is_relevant(&commit(&["server/srcs/lib.rs"]), &focus));
+        assert!(!is_relevant(&commit(&["client/index.ts"]), &focus));
+        assert!(!is_relevant(&commit(&[]), &focus));
+        assert!(is_relevant(&commit(&["client/index.ts"]), &[]));
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{extract::Path, Json};
+
+use super::{prelude::*, Admin};
+use crate::{
+    federation::{self, FederatedHit, Peer},
+    query::parser::{self, ParsedQuery},
+    repo::RepoRef,
+    Application,
+};
+
+const fn default_limit() -> u64 {
+    10
+}
+
+#[derive(Serialize)]
+pub(super) struct PeerView {
+    name: String,
+    url: String,
+    /// Whether a token is stored for this peer. The token itself is never returned.
+    authenticated: bool,
+}
+
+pub(super) async fn list_peers(Extension(app): Extension<Application>) -> impl IntoResponse {
+    let peers = federation::snapshot(&app.federated_peers)
+        .into_iter()
+        .map(|(name, peer)| PeerView {
+            name,
+            url: peer.url,
+            authenticated: peer.token.is_some(),
+        })
+        .collect::<Vec<_>>();
+
+    Json(peers)
+}
+
+/// Peers are queried on behalf of every user, and their results end up in answers, so only admins
+/// may change them.
+pub(super) async fn put_peer(
+    Path(name): Path<String>,
+    Extension(app): Extension<Application>,
+    _: Admin,
+    Json(peer): Json<Peer>,
+) -> Result<impl IntoResponse> {
+    if !federation::is_valid_name(&name) {
+        return Err(Error::user(
+            "peer names may only contain letters, digits, `-` and `_`",
+        ));
+    }
+
+    reqwest::Url::parse(&peer.url).map_err(|_| Error::user("invalid peer URL"))?;
+
+    app.federated_peers
+        .entry_async(name)
+        .await
+        .or_default()
+        .insert(peer);
+    app.federated_peers.store()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn delete_peer(
+    Path(name): Path<String>,
+    Extension(app): Extension<Application>,
+    _: Admin,
+) -> Result<impl IntoResponse> {
+    app.federated_peers
+        .remove(&name)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown peer"))?;
+    app.federated_peers.store()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub(super) struct Search {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: u64,
+    /// Also search every registered peer.
+    ///
+    /// Peers are always queried without this flag, so requests never fan out more than once.
+    #[serde(default)]
+    federated: bool,
+}
+
+/// Semantic search, with scored and attributed results that can be merged across instances.
+pub(super) async fn search(
+    Query(params): Query<Search>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let semantic = app
+        .semantic
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::Configuration, "Qdrant not configured"))?;
+
+    let Ok(ParsedQuery::Semantic(query)) = parser::parse_nl(&params.q) else {
+        return Err(Error::user("federated search requires a semantic query"));
+    };
+
+    let mut hits = vec![];
+    for payload in semantic.search(&query, params.limit, 0, 0.0, true).await? {
+        // Peers send the code they find to their LLM, so retrieval-only code is left out.
+        if is_retrieval_only(&app, &payload.repo_ref).await {
+            continue;
+        }
+
+        hits.push(FederatedHit {
+            origin: None,
+            score: payload.score.unwrap_or_default(),
+            payload,
+        });
+    }
+
+    if params.federated {
+        let peers = federation::snapshot(&app.federated_peers);
+        hits.extend(federation::search(&peers, &params.q, params.limit).await);
+    }
+
+    Ok(Json(federation::merge(hits, params.limit as usize)))
+}
+
+async fn is_retrieval_only(app: &Application, repo_ref: &str) -> bool {
+    match repo_ref.parse::<RepoRef>() {
+        Ok(repo_ref) => app.is_retrieval_only(&repo_ref).await,
+        Err(_) => false,
+    }
+}
@@ -1,9 +1,9 @@
-use std::{path::PathBuf, sync::Arc};
+use std::path::PathBuf;
 
 use anyhow::Context;
-use axum::{extract::Query, Extension, Json};
+use axum::{extract::Query, http::HeaderMap, Extension, Json};
 
-use crate::repo::RepoRef;
+use crate::{federation, repo::RepoRef, Application};
 
 use super::prelude::*;
 
@@ -30,9 +30,21 @@ impl super::ApiResponse for FileResponse {}
 
 pub(super) async fn handle<'a>(
     Query(params): Query<Params>,
-    Extension(indexes): Extension<Arc<Indexes>>,
+    Extension(app): Extension<Application>,
+    headers: HeaderMap,
 ) -> Result<Json<super::Response<'a>>, Error> {
-    let doc = indexes
+    // Peers send the files they read to their LLM.
+    if headers.contains_key(federation::PEER_HEADER)
+        && app.is_retrieval_only(&params.repo_ref).await
+    {
+        return Err(Error::user(
+            "this repository is retrieval-only, so its code is never sent to an LLM",
+        )
+        .with_status(StatusCode::FORBIDDEN));
+    }
+
+    let doc = app
+        .indexes
         .file
         .by_path(
             &params.repo_ref,
@@ -74,6 +86,28 @@ fn split_by_lines<'a>(text: &'a str, indices: &[u32], params: &Params) -> Result
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn refuses_retrieval_only_repos_to_peers() {
+        let dir = tempdir::TempDir::new("file").unwrap();
+        let (app, repo_ref) = crate::webserver::retrieval_only_app(dir.path()).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(federation::PEER_HEADER, "1".parse().unwrap());
+
+        let params = Params {
+            repo_ref,
+            path: "src/lib.rs".into(),
+            line_start: None,
+            line_end: None,
+            branch: None,
+        };
+
+        let response = handle(Query(params), Extension(app), headers)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
     #[test]
     fn no_params() {
         let text = r#"aaaaaa
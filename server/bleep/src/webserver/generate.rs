@@ -0,0 +1,336 @@
+//! Text generation from repository state, for editor and CLI integrations.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use anyhow::Context;
+use axum::Json;
+use futures::TryStreamExt;
+use lazy_regex::regex;
+use secrecy::ExposeSecret;
+use tracing::debug;
+
+use super::{middleware::User, prelude::*, usage};
+use crate::{llm_gateway, repo::RepoRef, Application};
+
+const GENERATE_MODEL: &str = "gpt-4-0613";
+
+/// The maximum size of the diff we send to the LLM, in bytes.
+const MAX_DIFF_BYTES: usize = 24_000;
+
+/// The maximum number of lines of indexed context we include per changed file.
+const MAX_CONTEXT_LINES: usize = 60;
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum MessageKind {
+    #[default]
+    CommitMessage,
+    PullRequest,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CommitMessage {
+    repo_ref: RepoRef,
+    #[serde(default)]
+    kind: MessageKind,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct GeneratedMessage {
+    /// The commit subject, or pull request title.
+    title: String,
+    body: String,
+    /// Symbols touched by the staged changes, as found in the index.
+    symbols: Vec<String>,
+}
+
+/// Propose a commit message or pull request description for the staged changes of a local repo.
+pub(super) async fn commit_message(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Json(params): Json<CommitMessage>,
+) -> Result<impl IntoResponse> {
+    super::check_llm_access(&app, &params.repo_ref).await?;
+    usage::check_quota(&app, &user).await?;
+    let disk_path = params.repo_ref.local_path().ok_or_else(|| {
+        Error::user("commit messages can only be generated for local repositories")
+    })?;
+
+    // The staged changes are read from disk, so only registered repositories are, and only where
+    // indexing them is allowed.
+    app.repo_pool
+        .read_async(&params.repo_ref, |_, _| ())
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown repository"))?;
+
+    if !app.allow_path(&disk_path) {
+        return Err(
+            Error::user(format!("reading {} is not allowed", disk_path.display()))
+                .with_status(StatusCode::FORBIDDEN),
+        );
+    }
+
+    let output = tokio::process::Command::new("git")
+        .args(["diff", "--cached", "--no-color", "--no-ext-diff"])
+        .current_dir(&disk_path)
+        .output()
+        .await
+        .context("failed to run git")?;
+
+    if !output.status.success() {
+        return Err(Error::user(format!(
+            "failed to read staged changes: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let diff = String::from_utf8_lossy(&output.stdout);
+    if diff.trim().is_empty() {
+        return Err(Error::user("there are no staged changes"));
+    }
+
+    let changes = changed_ranges(&diff);
+    debug!(files = changes.len(), "read staged diff");
+
+    let mut symbols = Vec::new();
+    let mut context = String::new();
+
+    for (path, ranges) in &changes {
+        let Some(doc) = app
+            .indexes
+            .file
+            .by_path(&params.repo_ref, path, None)
+            .await?
+        else {
+            continue;
+        };
+
+        let touched = |line: usize| ranges.iter().any(|r| r.contains(&line));
+
+        symbols.extend(
+            doc.symbol_locations
+                .list()
+                .into_iter()
+                .filter(|s| touched(s.range.start.line))
+                .filter_map(|s| {
+                    let name = doc.content.get(s.range.start.byte..s.range.end.byte)?;
+                    Some(format!("{} `{name}` in {path}", s.kind))
+                }),
+        );
+
+        let lines = doc.content.lines().collect::<Vec<_>>();
+        let snippet = ranges
+            .iter()
+            .flat_map(|r| r.start.min(lines.len())..r.end.min(lines.len()))
+            .take(MAX_CONTEXT_LINES)
+            .map(|i| lines[i])
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if !snippet.is_empty() {
+            context += &format!("Code in {path} before the change:\n\n{snippet}\n\n");
+        }
+    }
+
+    symbols.sort();
+    symbols.dedup();
+
+    let prompt = message_prompt(
+        params.kind,
+        truncate_diff(&diff, MAX_DIFF_BYTES),
+        &symbols,
+        &context,
+    );
+
+    let answer_api_token = app
+        .answer_api_token()
+        .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+        .map(|s| s.expose_secret().clone());
+
+    let llm_gateway = app
+        .llm_gateway_client()
+        .temperature(0.0)
+        .bearer(answer_api_token)
+        .model(GENERATE_MODEL);
+
+    let response = llm_gateway
+        .chat(&[llm_gateway::api::Message::system(&prompt)], None)
+        .await?
+        .try_collect::<String>()
+        .await?;
+    usage::record(&app, &user, &params.repo_ref, &llm_gateway).await;
+
+    let response = response.trim();
+    let (title, body) = response.split_once('\n').unwrap_or((response, ""));
+
+    Ok(Json(GeneratedMessage {
+        title: title.trim().to_owned(),
+        body: body.trim().to_owned(),
+        symbols,
+    }))
+}
+
+/// Collect the zero-based line ranges changed in each file of a unified diff.
+///
+/// Ranges refer to the original version of each file, which is the version in the index.
+fn changed_ranges(diff: &str) -> BTreeMap<String, Vec<Range<usize>>> {
+    let mut changes = BTreeMap::<_, Vec<_>>::new();
+    let mut path = None;
+
+    for line in diff.lines() {
+        if let Some(p) = line.strip_prefix("--- ") {
+            path = p.strip_prefix("a/").map(str::to_owned);
+        } else if let Some(captures) = regex!(r"^@@ -(\d+)(?:,(\d+))? ").captures(line) {
+            let Some(path) = path.clone() else {
+                // Newly added files have no original version.
+                continue;
+            };
+
+            let start = captures[1].parse::<usize>().unwrap_or(1).saturating_sub(1);
+            let len = captures
+                .get(2)
+                .map_or(Ok(1), |m| m.as_str().parse::<usize>())
+                .unwrap_or(1);
+
+            changes.entry(path).or_default().push(start..start + len);
+        }
+    }
+
+    changes
+}
+
+fn truncate_diff(diff: &str, max_bytes: usize) -> &str {
+    if diff.len() <= max_bytes {
+        return diff;
+    }
+
+    let mut end = max_bytes;
+    while !diff.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    // Cut at the last complete line that fits, if there is one.
+    let end = diff[..end].rfind('\n').unwrap_or(end);
+    &diff[..end]
+}
+
+fn message_prompt(kind: MessageKind, diff: &str, symbols: &[String], context: &str) -> String {
+    let symbols = if symbols.is_empty() {
+        "None found.".to_owned()
+    } else {
+        symbols
+            .iter()
+            .map(|s| format!("- {s}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let task = match kind {
+        MessageKind::CommitMessage => {
+            "Your job is to write a git commit message for the staged changes above.
+- The first line is the subject: at most 72 characters, in the imperative mood, with no trailing period
+- Follow the subject with an empty line, and a body that explains what changed and why in a few sentences
+- Wrap the body at 72 characters"
+        }
+        MessageKind::PullRequest => {
+            "Your job is to write a pull request description for the staged changes above.
+- The first line is the title: at most 72 characters, with no trailing period
+- Follow the title with an empty line, and a markdown body that summarizes what changed and why, and how to verify it"
+        }
+    };
+
+    format!(
+        r#"Below is a diff of the staged changes in a repository.
+
+#####
+
+{diff}
+
+#####
+
+Symbols touched by the change:
+
+{symbols}
+
+#####
+
+{context}#####
+
+{task}
+- DO NOT describe changes that are not in the diff
+- Respond with only the message, DO NOT surround it in quotes or code blocks"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_retrieval_only_repos() {
+        let dir = tempdir::TempDir::new("generate").unwrap();
+        let (app, repo_ref) = crate::webserver::retrieval_only_app(dir.path()).await;
+
+        let params = CommitMessage {
+            repo_ref,
+            kind: MessageKind::CommitMessage,
+        };
+
+        let response = commit_message(Extension(User::Unknown), Extension(app), Json(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_repos() {
+        let dir = tempdir::TempDir::new("generate").unwrap();
+        let app = Application::for_tests(dir.path()).await;
+
+        let params = CommitMessage {
+            repo_ref: RepoRef::from(&dir.path()),
+            kind: MessageKind::CommitMessage,
+        };
+
+        let response = commit_message(Extension(User::Unknown), Extension(app), Json(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -10,3 +10,4 @@ fn main() {
+ a
++b
+ c
+@@ -40 +41 @@
+-x
++y
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,2 @@
++fn new() {}
++
+";
+
+        let changes = changed_ranges(diff);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes["src/lib.rs"], vec![9..12, 39..40]);
+    }
+
+    #[test]
+    fn test_truncate_diff() {
+        assert_eq!(truncate_diff("a\nb\nc\n", 100), "a\nb\nc\n");
+        assert_eq!(truncate_diff("a\nb\nc\n", 4), "a\nb");
+        assert_eq!(truncate_diff("ééé", 3), "é");
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::{Duration, Instant};
+
+use axum::{response::Redirect, Json};
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::SecretString;
+use tracing::error;
+
+use super::{prelude::*, Admin};
+use crate::{remotes::gitlab, repo::Backend, Application};
+
+/// OAuth authorizations that were started, but not completed, with the time they were started.
+static PENDING_LOGINS: Lazy<scc::HashMap<String, Instant>> = Lazy::new(Default::default);
+
+const MAX_LOGIN_AGE: Duration = Duration::from_secs(5 * 60);
+const STATE_LEN: usize = 32;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GitlabResponse {
+    AuthenticationNeeded { url: String },
+    Connected { username: String },
+    Status(GitlabCredentialStatus),
+}
+
+impl super::ApiResponse for GitlabResponse {}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GitlabCredentialStatus {
+    Ok,
+    Missing,
+}
+
+/// Connect to GitLab through OAuth, with the application configured in `gitlab_client_id`.
+///
+/// The GitLab connection is shared by every user of the instance, so only admins may change it.
+//
+pub(super) async fn login(
+    Extension(app): Extension<Application>,
+    _: Admin,
+) -> Result<impl IntoResponse> {
+    let Some((client_id, _)) = app.config.gitlab_client_id_and_secret() else {
+        return Err(
+            Error::user("GitLab OAuth is not configured").with_status(StatusCode::NOT_FOUND)
+        );
+    };
+
+    let now = Instant::now();
+    PENDING_LOGINS.retain(|_, started| now - *started < MAX_LOGIN_AGE);
+
+    let state = rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(STATE_LEN)
+        .map(|c| c as char)
+        .collect::<String>();
+
+    let url = reqwest::Url::parse_with_params(
+        &format!(
+            "{}/oauth/authorize",
+            app.config.gitlab_url.trim_end_matches('/')
+        ),
+        &[
+            ("client_id", client_id),
+            ("redirect_uri", &redirect_uri(&app)),
+            ("response_type", "code"),
+            ("scope", "read_api read_repository"),
+            ("state", &state),
+        ],
+    )
+    .map_err(|_| Error::internal("invalid `gitlab_url`"))?
+    .to_string();
+
+    _ = PENDING_LOGINS.insert(state, now);
+    Ok(json(GitlabResponse::AuthenticationNeeded { url }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct CompleteParams {
+    state: String,
+    code: String,
+}
+
+/// Complete the OAuth flow that `login` started, which GitLab redirects back to.
+//
+pub(super) async fn complete(
+    Extension(app): Extension<Application>,
+    _: Admin,
+    Query(params): Query<CompleteParams>,
+) -> Result<impl IntoResponse> {
+    match PENDING_LOGINS.remove(&params.state) {
+        Some((_, started)) if started.elapsed() < MAX_LOGIN_AGE => {}
+        _ => return Err(Error::user("invalid or expired login")),
+    }
+
+    let (client_id, client_secret) = app
+        .config
+        .gitlab_client_id_and_secret()
+        .ok_or_else(|| Error::internal("GitLab OAuth is not configured"))?;
+
+    let auth = gitlab::exchange_code(
+        &app.config.gitlab_url,
+        client_id,
+        client_secret,
+        &params.code,
+        &redirect_uri(&app),
+    )
+    .await
+    .map_err(|err| {
+        error!(?err, "failed to complete gitlab authorization");
+        Error::user("GitLab authorization failed").with_status(StatusCode::UNAUTHORIZED)
+    })?;
+
+    connect(&app, gitlab::State::new(&app.config.gitlab_url, auth)).await?;
+    Ok(Redirect::to("/"))
+}
+
+#[derive(Deserialize)]
+pub(super) struct TokenParams {
+    token: SecretString,
+}
+
+/// Connect to the GitLab instance at `gitlab_url` with a personal, group or project access token.
+//
+pub(super) async fn set_token(
+    Extension(app): Extension<Application>,
+    _: Admin,
+    Json(params): Json<TokenParams>,
+) -> Result<impl IntoResponse> {
+    let state = gitlab::State::new(
+        &app.config.gitlab_url,
+        gitlab::Auth::Token {
+            token: params.token,
+        },
+    );
+
+    let username = connect(&app, state).await?;
+    Ok(json(GitlabResponse::Connected { username }))
+}
+
+/// Remove the GitLab credentials
+//
+pub(super) async fn logout(
+    Extension(app): Extension<Application>,
+    _: Admin,
+) -> Result<impl IntoResponse> {
+    if app.credentials.remove(Backend::GitLab).is_none() {
+        return Ok(json(GitlabResponse::Status(
+            GitlabCredentialStatus::Missing,
+        )));
+    }
+
+    app.credentials.store().map_err(|err| {
+        error!(?err, "Failed to delete credentials from disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    Ok(json(GitlabResponse::Status(GitlabCredentialStatus::Ok)))
+}
+
+/// Store credentials once GitLab accepts them, and return the name of their user.
+async fn connect(app: &Application, state: gitlab::State) -> Result<String> {
+    let username = state.validate().await.map_err(|err| {
+        if err.is_auth_failure() {
+            Error::user("GitLab rejected the credentials").with_status(StatusCode::UNAUTHORIZED)
+        } else {
+            Error::internal(format!("failed to reach GitLab: {err}"))
+        }
+    })?;
+
+    app.credentials.set_gitlab(state);
+    app.credentials.store().map_err(|err| {
+        error!(?err, "failed to save credentials to disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    Ok(username)
+}
+
+/// Where GitLab redirects to once a user authorized bloop.
+fn redirect_uri(app: &Application) -> String {
+    match &app.config.instance_domain {
+        Some(domain) => format!("https://{domain}/api/remotes/gitlab/complete"),
+        None => format!(
+            "http://{}:{}/api/remotes/gitlab/complete",
+            app.config.host, app.config.port
+        ),
+    }
+}
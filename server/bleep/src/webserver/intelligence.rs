@@ -5,11 +5,12 @@ use crate::{
     indexes::{reader::ContentDocument, Indexes},
     intelligence::{
         code_navigation::{CodeNavigationContext, FileSymbols, Occurrence, OccurrenceKind, Token},
-        Language, NodeKind, TSLanguage,
+        precise, Language, NodeKind, TSLanguage,
     },
     repo::RepoRef,
     snippet::Snipper,
     text_range::TextRange,
+    Application,
 };
 
 use axum::{extract::Query, response::IntoResponse, Extension};
@@ -49,6 +50,7 @@ impl super::ApiResponse for TokenInfoResponse {}
 pub(super) async fn handle(
     Query(payload): Query<TokenInfoRequest>,
     Extension(indexes): Extension<Arc<Indexes>>,
+    Extension(app): Extension<Application>,
 ) -> Result<impl IntoResponse> {
     let repo_ref = payload.repo_ref.parse::<RepoRef>().map_err(Error::user)?;
 
@@ -64,6 +66,22 @@ pub(super) async fn handle(
         .await
         .map_err(Error::user)?
         .ok_or_else(|| Error::user("path not found").with_status(StatusCode::NOT_FOUND))?;
+
+    // imported symbols describe the default branch
+    if payload.branch.is_none() {
+        if let Some(data) = precise::navigate(
+            &indexes,
+            &app.sql,
+            &repo_ref,
+            &source_document,
+            payload.start..payload.end,
+        )
+        .await?
+        {
+            return Ok(json(TokenInfoResponse::new(data)));
+        }
+    }
+
     let lang = source_document.lang.as_deref();
     let all_docs = {
         let associated_langs = match lang.map(TSLanguage::from_id) {
@@ -92,7 +110,18 @@ pub(super) async fn handle(
         source_document_idx,
     };
 
-    let data = ctx.token_info();
+    let mut data = ctx.token_info();
+    if data.is_empty() && payload.branch.is_none() {
+        data = precise::ctags_definitions(
+            &indexes,
+            &app.sql,
+            &repo_ref,
+            &source_document,
+            ctx.active_token_text(),
+        )
+        .await?;
+    }
+
     if data.is_empty() {
         search_nav(
             Arc::clone(&indexes),
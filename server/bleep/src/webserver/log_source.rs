@@ -0,0 +1,243 @@
+//! Find the code that printed a log line.
+//!
+//! The variable parts of the line, like timestamps, numbers, ids and quoted values, are removed,
+//! and the constant parts that remain are searched for in the index. These are usually pieces of
+//! the format string at the log call site.
+
+use axum::Json;
+use lazy_regex::regex;
+
+use super::prelude::*;
+use crate::{
+    query::{
+        execute::{ApiQuery, QueryResult},
+        parser,
+    },
+    repo::RepoRef,
+};
+
+/// The maximum number of candidate locations returned.
+const MAX_CANDIDATES: usize = 20;
+
+/// The number of files searched for candidates.
+const MAX_FILES: usize = 50;
+
+/// Constant parts shorter than this are too common to search for.
+const MIN_FRAGMENT_CHARS: usize = 4;
+
+/// The maximum length of a log line, in bytes.
+const MAX_LINE_BYTES: usize = 4096;
+
+/// The score added to candidates that look like a log or print call.
+const LOG_CALL_BONUS: f32 = 0.1;
+
+#[derive(Deserialize)]
+pub(super) struct Params {
+    repo: RepoRef,
+    branch: Option<String>,
+    /// The raw log line, as printed.
+    line: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct LogSourceResponse {
+    /// The constant parts of the log line that were searched for.
+    fragments: Vec<String>,
+    /// Whether the longest fragment had no match, and the fragments were searched for as a regex.
+    regex_fallback: bool,
+    candidates: Vec<Candidate>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(super) struct Candidate {
+    path: String,
+    /// 1-indexed line number.
+    line: usize,
+    text: String,
+    /// The share of the constant text of the log line found on this line, with a bonus if the
+    /// line looks like a log call.
+    score: f32,
+}
+
+/// Find candidate source locations of a log line, best match first.
+pub(super) async fn handle(
+    Query(params): Query<Params>,
+    Extension(indexes): Extension<Arc<Indexes>>,
+) -> Result<impl IntoResponse> {
+    if params.line.len() > MAX_LINE_BYTES {
+        return Err(Error::user(format!(
+            "log line is longer than {MAX_LINE_BYTES} bytes"
+        )));
+    }
+
+    let fragments = constant_parts(&params.line);
+    let Some(longest) = fragments.iter().max_by_key(|f| f.len()) else {
+        return Err(Error::user("log line has no constant text to search for"));
+    };
+
+    let lexical = parser::Literal::Plain(longest.clone().into());
+    let mut candidates = search(&indexes, &params, lexical, &fragments).await?;
+
+    let regex_fallback = candidates.is_empty();
+    if regex_fallback {
+        let pattern = fragments
+            .iter()
+            .map(|f| regex::escape(f))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        let regex = parser::Literal::Regex(pattern.into());
+        candidates = search(&indexes, &params, regex, &fragments).await?;
+    }
+
+    Ok(Json(LogSourceResponse {
+        fragments,
+        regex_fallback,
+        candidates,
+    }))
+}
+
+async fn search(
+    indexes: &Arc<Indexes>,
+    params: &Params,
+    content: parser::Literal<'static>,
+    fragments: &[String],
+) -> Result<Vec<Candidate>> {
+    let query = parser::Query {
+        repo: Some(parser::Literal::Plain(params.repo.display_name().into())),
+        branch: params
+            .branch
+            .clone()
+            .map(|b| parser::Literal::Plain(b.into())),
+        target: Some(parser::Target::Content(content)),
+        ..Default::default()
+    };
+
+    let mut api_query = ApiQuery::new(&params.line);
+    api_query.page_size = MAX_FILES;
+    api_query.calculate_totals = false;
+
+    let results = Arc::new(api_query)
+        .query_with(Arc::clone(indexes), vec![query])
+        .await
+        .map_err(Error::internal)?;
+
+    let repo_ref = params.repo.to_string();
+    let mut candidates = Vec::new();
+
+    for file in results.data.into_iter().filter_map(|r| match r {
+        QueryResult::Snippets(file) if file.repo_ref == repo_ref => Some(file),
+        _ => None,
+    }) {
+        for snippet in &file.snippets {
+            for (i, text) in snippet.data.lines().enumerate() {
+                let line = snippet.line_range.start + i + 1;
+                let score = score(text, fragments);
+
+                let seen = candidates
+                    .iter()
+                    .any(|c: &Candidate| c.path == file.relative_path && c.line == line);
+
+                if score > 0.0 && !seen {
+                    candidates.push(Candidate {
+                        path: file.relative_path.clone(),
+                        line,
+                        text: text.trim().to_owned(),
+                        score,
+                    });
+                }
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+            .then(a.line.cmp(&b.line))
+    });
+    candidates.truncate(MAX_CANDIDATES);
+
+    Ok(candidates)
+}
+
+/// The constant parts of a log line, in order.
+fn constant_parts(line: &str) -> Vec<String> {
+    let variable = regex!(
+        r#"(?x)
+        \d{4}-\d{2}-\d{2}[T\ ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?  # timestamps
+        | \b(?:TRACE|DEBUG|INFO|WARN|WARNING|ERROR|FATAL|CRITICAL)\b               # log levels
+        | \[[^\]]*\]                                                              # [thread] and similar
+        | "[^"]*" | '[^']*' | `[^`]*`                                             # quoted values
+        | =\S+                                                                    # key=value pairs
+        | \b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b
+        | \b0x[0-9a-fA-F]+\b
+        | \b\d[\w.]*                                                              # numbers
+        | \b[0-9a-fA-F]*\d[0-9a-fA-F]*\b                                          # hashes
+        | \S*[/\\]\S*                                                             # paths and urls
+        "#
+    );
+
+    variable
+        .split(line)
+        .map(|part| part.trim_matches(|c: char| !c.is_alphanumeric() && c != '_'))
+        .filter(|part| part.chars().filter(|c| c.is_alphanumeric()).count() >= MIN_FRAGMENT_CHARS)
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The share of the constant text found on a line of code.
+fn score(text: &str, fragments: &[String]) -> f32 {
+    let total = fragments.iter().map(String::len).sum::<usize>();
+    let matched = fragments
+        .iter()
+        .filter(|f| text.contains(f.as_str()))
+        .map(String::len)
+        .sum::<usize>();
+
+    if matched == 0 {
+        return 0.0;
+    }
+
+    let is_log_call = regex!(
+        r"(?i)\b(?:log|logger|logging|trace|debug|info|warn|warning|error|fatal|print|println|printf|eprintln|console)\b"
+    )
+    .is_match(text);
+
+    matched as f32 / total as f32 + if is_log_call { LOG_CALL_BONUS } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_constant_parts() {
+        assert_eq!(
+            constant_parts(
+                "2023-09-20T10:15:02.123Z ERROR [worker-3] failed to sync repo \"bloop\" after 3 retries, took 1200ms"
+            ),
+            ["failed to sync repo", "after", "retries, took"]
+        );
+        assert_eq!(
+            constant_parts("INFO bleep::indexes: indexed /home/me/repo in 12s commit=4f2a9c1"),
+            ["bleep::indexes: indexed", "commit"]
+        );
+        assert!(constant_parts("2023-09-20 10:15:02 42 0xdeadbeef").is_empty());
+    }
+
+    #[test]
+    fn scores_lines() {
+        let fragments = vec!["failed to sync repo".to_owned(), "retries".to_owned()];
+
+        let call = score(
+            r#"error!("failed to sync repo {} after {} retries", name, n);"#,
+            &fragments,
+        );
+        let comment = score("// failed to sync repo", &fragments);
+        let doc = score("/// Number of retries", &fragments);
+
+        assert!(call > comment && comment > doc && doc > 0.0);
+        assert_eq!(score("let x = 1;", &fragments), 0.0);
+    }
+}
@@ -1,5 +1,5 @@
 use super::prelude::*;
-use crate::Application;
+use crate::{Application, Configuration};
 
 use anyhow::Context;
 use axum::{
@@ -9,6 +9,13 @@ use axum::{
     response::Response,
 };
 use sentry::{Hub, SentryFutureExt};
+use tokio::sync::Semaphore;
+use tower::limit::GlobalConcurrencyLimitLayer;
+
+use std::{
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
 
 #[derive(Serialize, Clone)]
 pub enum User {
@@ -62,6 +69,139 @@ async fn sentry_layer_mw<B>(
     next.run(request).bind_hub(hub).await
 }
 
+/// Concurrency limits of the endpoints that read from the file index, which compete with the
+/// indexing writers.
+///
+/// Each class of endpoints has its own pool of permits, so that raw-file and bulk traffic queues
+/// up without taking capacity from interactive answers. Bulk requests also take a permit from the
+/// file pool, so they are limited by both.
+#[derive(Clone)]
+pub struct ConcurrencyLimits {
+    answer: Arc<Semaphore>,
+    files: Arc<Semaphore>,
+    bulk: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(config: &Configuration) -> Self {
+        let semaphore = |limit: std::num::NonZeroUsize| Arc::new(Semaphore::new(limit.get()));
+
+        Self {
+            answer: semaphore(config.answer_concurrency_limit),
+            files: semaphore(config.file_concurrency_limit),
+            bulk: semaphore(config.bulk_concurrency_limit),
+        }
+    }
+
+    pub fn answer(&self) -> GlobalConcurrencyLimitLayer {
+        GlobalConcurrencyLimitLayer::with_semaphore(Arc::clone(&self.answer))
+    }
+
+    pub fn files(&self) -> GlobalConcurrencyLimitLayer {
+        GlobalConcurrencyLimitLayer::with_semaphore(Arc::clone(&self.files))
+    }
+
+    /// The limit of bulk requests only. It has to be the outer layer of `files`, so that bulk
+    /// requests don't hold on to file permits while they wait for a bulk one.
+    pub fn bulk(&self) -> GlobalConcurrencyLimitLayer {
+        GlobalConcurrencyLimitLayer::with_semaphore(Arc::clone(&self.bulk))
+    }
+}
+
+/// Per-user token buckets for answers, so that one user can't exhaust the shared LLM quota.
+///
+/// Each user starts with `answer_burst_limit` tokens, which refill at `answer_rate_limit` per
+/// minute. Every answer, or other request that calls the LLM, takes a token from the same bucket,
+/// and these requests are rejected while a user has none left.
+#[derive(Clone)]
+pub struct AnswerRateLimit {
+    limit: Option<(f64, f64)>,
+    buckets: Arc<scc::HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl AnswerRateLimit {
+    /// Buckets that have refilled are dropped once this many users are tracked.
+    const MAX_TRACKED_USERS: usize = 10_000;
+
+    pub fn new(config: &Configuration) -> Self {
+        Self::with_limit(config.answer_rate_limit, config.answer_burst_limit)
+    }
+
+    fn with_limit(per_minute: Option<NonZeroU32>, burst: Option<NonZeroU32>) -> Self {
+        let limit = per_minute.map(|rate| {
+            let burst = burst.unwrap_or(rate);
+            (f64::from(burst.get()), f64::from(rate.get()) / 60.0)
+        });
+
+        Self {
+            limit,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Take a token from the bucket of `user_id`, or return how long until one is available.
+    fn take(&self, user_id: &str, now: Instant) -> std::result::Result<(), Duration> {
+        let Some((capacity, per_sec)) = self.limit else {
+            return Ok(());
+        };
+
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            (bucket.tokens + elapsed.as_secs_f64() * per_sec).min(capacity)
+        };
+
+        if self.buckets.len() >= Self::MAX_TRACKED_USERS {
+            self.buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+
+        let mut entry = self
+            .buckets
+            .entry(user_id.to_owned())
+            .or_insert_with(|| Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+
+        let bucket = entry.get_mut();
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+/// Reject answers with `429 Too Many Requests` once a user has run out of tokens.
+///
+/// This is the outer layer of the answer concurrency limit, so that rejected requests don't wait
+/// for a permit first.
+pub async fn answer_rate_limit<B>(
+    State(limit): State<AnswerRateLimit>,
+    Extension(user): Extension<User>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(user_id) = user.login() else {
+        return next.run(request).await;
+    };
+
+    if let Err(retry_after) = limit.take(user_id, Instant::now()) {
+        return Error::rate_limited("too many requests to the LLM, try again later", retry_after)
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
 pub fn local_user(router: Router, app: Application) -> Router {
     router.layer(from_fn_with_state(app, local_user_mw))
 }
@@ -87,3 +227,36 @@ async fn local_user_mw<B>(
 
     next.run(request).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(per_minute: u32, burst: u32) -> AnswerRateLimit {
+        AnswerRateLimit::with_limit(NonZeroU32::new(per_minute), NonZeroU32::new(burst))
+    }
+
+    #[test]
+    fn refills_buckets_per_user() {
+        let limit = limit(6, 2);
+        let now = Instant::now();
+
+        assert!(limit.take("alice", now).is_ok());
+        assert!(limit.take("alice", now).is_ok());
+        assert_eq!(limit.take("alice", now), Err(Duration::from_secs(10)));
+
+        // other users have their own bucket
+        assert!(limit.take("bob", now).is_ok());
+
+        assert!(limit.take("alice", now + Duration::from_secs(10)).is_ok());
+        assert!(limit.take("alice", now + Duration::from_secs(10)).is_err());
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let limit = AnswerRateLimit::with_limit(None, None);
+        let now = Instant::now();
+
+        assert!((0..100).all(|_| limit.take("alice", now).is_ok()));
+    }
+}
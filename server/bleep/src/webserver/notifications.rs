@@ -0,0 +1,27 @@
+//! Notifications about the health of the remote credentials.
+//!
+//! Notifications are also pushed on the `credential_status` events of the `/repos/status`
+//! stream. They are listed until the user acknowledges them, and a problem that persists is not
+//! reported again once acknowledged.
+
+use axum::{extract::Path, Json};
+
+use super::prelude::*;
+use crate::Application;
+
+/// Notifications that have not been acknowledged yet, oldest first.
+pub(super) async fn list(Extension(app): Extension<Application>) -> impl IntoResponse {
+    Json(app.credentials.notifications())
+}
+
+/// Stop showing a notification.
+pub(super) async fn ack(
+    Extension(app): Extension<Application>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse> {
+    if app.credentials.acknowledge(id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::user("notification not found").with_status(StatusCode::NOT_FOUND))
+    }
+}
@@ -0,0 +1,121 @@
+//! Pinned copies of the semantic index of repositories.
+//!
+//! Evaluation runs and A/B experiments answer with `pin` set, so that they compare retrieval on
+//! the same code, while syncs keep changing the live index.
+
+use axum::{extract::Path, Json};
+
+use super::{prelude::*, Admin};
+use crate::{
+    db::{SemanticPin, SemanticPins},
+    repo::RepoRef,
+    semantic::Semantic,
+    state::SCHEMA_VERSION,
+    Application,
+};
+
+#[derive(Deserialize)]
+pub(super) struct NewPin {
+    name: String,
+    repo_ref: RepoRef,
+}
+
+#[derive(Deserialize)]
+pub(super) struct PinScope {
+    repo_ref: RepoRef,
+}
+
+/// Pin the semantic index of a repository as it is now, for admins.
+pub(super) async fn create(
+    _: Admin,
+    Extension(app): Extension<Application>,
+    Json(params): Json<NewPin>,
+) -> Result<impl IntoResponse> {
+    let semantic = enabled_semantic(&app)?;
+    let pins = SemanticPins::new(&app.sql);
+    let repo_ref = params.repo_ref.to_string();
+
+    if pins.get(&params.name, &repo_ref).await?.is_some() {
+        return Err(
+            Error::user("this repository is already pinned under this name")
+                .with_status(StatusCode::CONFLICT),
+        );
+    }
+
+    let indexed_at = app
+        .repo_pool
+        .read_async(&params.repo_ref, |_, repo| repo.last_index_unix_secs)
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown repository"))?;
+
+    let collection_name = semantic.pin_collection(&params.name, &params.repo_ref);
+    let points = semantic
+        .pin_repo(&params.repo_ref, &collection_name)
+        .await?;
+
+    let pin = SemanticPin {
+        embedding_model: semantic.model_for(&params.repo_ref.indexed_name()).id(),
+        name: params.name,
+        repo_ref,
+        collection_name,
+        schema_version: SCHEMA_VERSION.to_owned(),
+        indexed_at: indexed_at as i64,
+        points: points as i64,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    pins.insert(&pin).await?;
+
+    Ok(Json(pin))
+}
+
+/// All pins, for admins.
+pub(super) async fn list(
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    enabled_semantic(&app)?;
+    Ok(Json(SemanticPins::new(&app.sql).list().await?))
+}
+
+/// Drop a pin and its copy of the index, for admins.
+pub(super) async fn delete(
+    Path(name): Path<String>,
+    Query(scope): Query<PinScope>,
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let semantic = enabled_semantic(&app)?;
+    let pins = SemanticPins::new(&app.sql);
+    let repo_ref = scope.repo_ref.to_string();
+
+    let pin = pins
+        .get(&name, &repo_ref)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown pin"))?;
+
+    semantic.unpin(&pin.collection_name).await?;
+    pins.delete(&name, &repo_ref).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Semantic search of a repository as it was pinned under `name`.
+pub(super) async fn resolve(app: &Application, name: &str, repo_ref: &RepoRef) -> Result<Semantic> {
+    let semantic = app
+        .semantic
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::Configuration, "semantic search is not enabled"))?;
+
+    let pin = SemanticPins::new(&app.sql)
+        .get(name, &repo_ref.to_string())
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown pin"))?;
+
+    Ok(semantic.pinned(&pin.collection_name, &pin.embedding_model)?)
+}
+
+fn enabled_semantic(app: &Application) -> Result<Semantic> {
+    app.semantic
+        .clone()
+        .ok_or_else(|| Error::new(ErrorKind::Configuration, "semantic search is not enabled"))
+}
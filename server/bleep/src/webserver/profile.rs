@@ -0,0 +1,29 @@
+//! The context profile of a user, which biases retrieval towards the repositories, languages and
+//! directories they ask about most.
+
+use axum::Json;
+
+use super::{middleware::User, prelude::*};
+use crate::{agent::profile::Profile, db::UserProfiles, Application};
+
+pub(super) async fn get(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let entries = UserProfiles::new(&app.sql).list(user_id(&user)?).await?;
+    Ok(Json(Profile::new(entries)))
+}
+
+/// Forget everything the profile of the user has recorded.
+pub(super) async fn reset(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    UserProfiles::new(&app.sql).reset(user_id(&user)?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn user_id(user: &User) -> Result<&str> {
+    user.login()
+        .ok_or_else(|| Error::user("didn't have user ID"))
+}
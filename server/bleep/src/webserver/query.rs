@@ -1,18 +1,80 @@
-use axum::extract::State;
+use axum::{extract::State, http::header, response::Response, Json};
 
-use super::prelude::*;
-use crate::{db::QueryLog, query::execute::ApiQuery, Application};
+use super::{middleware::User, prelude::*};
+use crate::{
+    db::QueryLog,
+    intelligence::fingerprint::PatternError,
+    query::{
+        execute::{resolve_topics, ApiQuery},
+        export::{self, Format},
+        parser,
+    },
+    Application,
+};
+
+#[derive(Deserialize)]
+pub(super) struct ExportParams {
+    /// The format of the results, for consumption by other tools.
+    #[serde(default)]
+    format: Format,
+}
 
 pub(super) async fn handle(
     Query(api_params): Query<ApiQuery>,
+    Query(ExportParams { format }): Query<ExportParams>,
     Extension(indexes): Extension<Arc<Indexes>>,
+    Extension(user): Extension<User>,
     State(app): State<Application>,
-) -> impl IntoResponse {
+) -> Result<Response> {
     QueryLog::new(&app.sql).insert(&api_params.q).await?;
 
-    Arc::new(api_params)
-        .query(indexes)
-        .await
-        .map(json)
-        .map_err(super::Error::from)
+    let q = api_params.q.clone();
+    let response = if let Some(raw) = api_params.advanced().map(str::to_owned) {
+        if !super::is_admin(&app, &user) {
+            return Err(Error::user("only admins can run advanced queries")
+                .with_status(StatusCode::FORBIDDEN));
+        }
+
+        Arc::new(api_params)
+            .query_advanced(indexes, &raw)
+            .await
+            .map_err(advanced_error)?
+    } else {
+        let queries = parser::parse(&q).map_err(Error::user)?;
+        let queries = resolve_topics(&app.repo_pool, queries).map_err(Error::user)?;
+        Arc::new(api_params)
+            .query_with(indexes, queries)
+            .await
+            .map_err(structural_error)?
+    };
+
+    Ok(match format {
+        Format::Json => json(response).into_response(),
+        Format::Sarif => (
+            [(header::CONTENT_TYPE, "application/sarif+json")],
+            Json(export::sarif(&q, &response)),
+        )
+            .into_response(),
+        Format::Csv => (
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            export::csv(&response),
+        )
+            .into_response(),
+    })
+}
+
+/// Queries that don't fit the schema are the user's to fix.
+fn advanced_error(err: anyhow::Error) -> Error {
+    match err.downcast_ref::<tantivy::query::QueryParserError>() {
+        Some(invalid) => Error::user(format!("invalid advanced query: {invalid}")),
+        None => err.into(),
+    }
+}
+
+/// Structural patterns that can't be searched for are the user's to fix.
+fn structural_error(err: anyhow::Error) -> Error {
+    match err.downcast_ref::<PatternError>() {
+        Some(invalid) => Error::user(invalid),
+        None => err.into(),
+    }
 }
@@ -0,0 +1,44 @@
+//! Re-indexing every repository from scratch, for admins.
+
+use axum::Json;
+
+use super::{prelude::*, Admin};
+use crate::{
+    background::reindex,
+    db::{BulkReindex, ReindexEntry},
+    Application,
+};
+
+#[derive(Serialize)]
+pub(super) struct ReindexReport {
+    running: bool,
+    /// The status of each repository of the last re-index, in the order they are re-indexed.
+    repos: Vec<ReindexEntry>,
+}
+
+/// Start re-indexing every repository.
+pub(super) async fn start(
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    if !reindex::start(&app).await? {
+        return Err(Error::user("a re-index is running already").with_status(StatusCode::CONFLICT));
+    }
+
+    Ok((StatusCode::ACCEPTED, Json(report(&app).await?)))
+}
+
+/// The progress of the last re-index.
+pub(super) async fn status(
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(report(&app).await?))
+}
+
+async fn report(app: &Application) -> Result<ReindexReport> {
+    Ok(ReindexReport {
+        running: reindex::is_running(),
+        repos: BulkReindex::new(&app.sql).list().await?,
+    })
+}
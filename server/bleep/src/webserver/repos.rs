@@ -2,12 +2,13 @@ use std::{collections::HashSet, hash::Hash, time::Duration};
 
 use crate::{
     background::QueuedRepoStatus,
+    remotes::{github, gitlab},
     repo::{Backend, BranchFilter, RepoRef, Repository, SyncStatus},
     state::RepositoryPool,
     Application,
 };
 use axum::{
-    extract::{Query, State},
+    extract::{DefaultBodyLimit, Query, State},
     http::StatusCode,
     response::{sse, IntoResponse, Sse},
     Extension, Json,
@@ -15,7 +16,12 @@ use axum::{
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use super::{middleware::User, prelude::*};
+use super::{middleware::User, prelude::*, Admin};
+
+mod file_search;
+mod import;
+mod semantic_stats;
+mod symbols;
 
 #[derive(Serialize, Debug, PartialEq, Eq)]
 pub(crate) struct Branch {
@@ -36,6 +42,14 @@ pub(crate) struct Repo {
     pub(super) most_common_lang: Option<String>,
     pub(super) branch_filter: BranchFilter,
     pub(super) branches: Vec<Branch>,
+    /// Tags of the historical revisions that are indexed, searchable with `rev:<tag>`.
+    pub(super) revisions: Vec<String>,
+    pub(super) pinned: bool,
+    pub(super) excluded_paths: Vec<String>,
+    pub(super) description: Option<String>,
+    /// Topics of the repository on GitHub, searchable with `topic:<topic>`.
+    pub(super) topics: Vec<String>,
+    pub(super) retrieval_only: bool,
 }
 
 impl From<(&RepoRef, &Repository)> for Repo {
@@ -134,6 +148,12 @@ impl From<(&RepoRef, &Repository)> for Repo {
             most_common_lang: repo.most_common_lang.clone(),
             branch_filter,
             branches,
+            revisions: repo.revisions.clone(),
+            pinned: repo.pinned,
+            excluded_paths: repo.excluded_paths.clone(),
+            description: repo.description.clone(),
+            topics: repo.topics.clone(),
+            retrieval_only: repo.retrieval_only,
         }
     }
 }
@@ -155,10 +175,44 @@ impl Repo {
             most_common_lang: None,
             branch_filter: crate::repo::BranchFilter::Select(vec![]),
             branches: vec![],
+            revisions: vec![],
+            pinned: false,
+            excluded_paths: vec![],
+            description: origin.description.clone(),
+            topics: github::topics(origin),
+            retrieval_only: false,
         }
     }
 }
 
+impl Repo {
+    pub(crate) fn from_gitlab(
+        local_duplicates: Vec<RepoRef>,
+        gitlab: &gitlab::State,
+        project: &gitlab::Project,
+    ) -> Option<Self> {
+        let repo_ref = gitlab.reporef(project).ok()?;
+        Some(Repo {
+            provider: Backend::GitLab,
+            name: repo_ref.display_name(),
+            repo_ref,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            last_update: project.last_activity_at,
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: crate::repo::BranchFilter::Select(vec![]),
+            branches: vec![],
+            revisions: vec![],
+            pinned: false,
+            excluded_paths: vec![],
+            description: project.description.clone(),
+            topics: project.topics.iter().map(|t| t.to_lowercase()).collect(),
+            retrieval_only: false,
+        })
+    }
+}
+
 impl Hash for Repo {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.repo_ref.hash(state)
@@ -179,12 +233,14 @@ pub(crate) enum ReposResponse {
     SyncQueue(Vec<QueuedRepoStatus>),
     SyncQueued,
     Deleted,
+    Imported(Vec<RepoRef>),
+    ImportErrors(Vec<import::ImportError>),
 }
 
 impl super::ApiResponse for ReposResponse {}
 
 #[allow(unused_mut)]
-pub(super) fn router() -> Router {
+pub(super) fn router(limits: &super::middleware::ConcurrencyLimits) -> Router {
     use axum::routing::*;
 
     let mut indexed = get(indexed).put(set_indexed).delete(delete_by_id);
@@ -199,18 +255,42 @@ pub(super) fn router() -> Router {
         .route("/queue", get(queue))
         .route("/status", get(index_status))
         .route("/indexed", indexed)
+        .route("/revisions", put(set_revisions))
+        .route("/retrieval-only", put(set_retrieval_only))
+        .route("/import", post(import::import))
+        .route(
+            "/symbols",
+            post(symbols::import)
+                .layer(DefaultBodyLimit::max(symbols::MAX_DUMP_SIZE))
+                .layer(limits.bulk()),
+        )
         .route("/sync", get(sync).delete(delete_sync))
+        .route("/semantic-stats", get(semantic_stats::handle))
+        .route(
+            "/:ref/file-search",
+            get(file_search::handle).layer(limits.files()),
+        )
 }
 
-/// Get a stream of status notifications about the indexing of each repository
+/// Get a stream of status notifications about the indexing of each repository,
+/// and the health of remote credentials.
 /// This endpoint opens an SSE stream
 //
 pub(super) async fn index_status(Extension(app): Extension<Application>) -> impl IntoResponse {
     let mut receiver = app.sync_queue.subscribe();
+    let mut credentials = app.credentials.subscribe_status();
 
     Sse::new(async_stream::stream! {
-        while let Ok(event) = receiver.recv().await {
-            yield sse::Event::default().json_data(event).map_err(|err| {
+        loop {
+            let event = tokio::select! {
+                Ok(event) = receiver.recv() => sse::Event::default().json_data(event),
+                Ok(status) = credentials.recv() => sse::Event::default()
+                    .event("credential_status")
+                    .json_data(status),
+                else => break,
+            };
+
+            yield event.map_err(|err| {
                 <_ as Into<Box<dyn std::error::Error + Send + Sync>>>::into(err)
             });
         }
@@ -361,7 +441,34 @@ pub(super) async fn available(State(app): State<Application>) -> impl IntoRespon
         })
         .collect::<HashSet<_>>();
 
-    let repos = list_unique_repos(app.repo_pool.clone(), unknown_github).await;
+    let unknown_gitlab = app
+        .credentials
+        .gitlab()
+        .map(|gitlab| {
+            gitlab
+                .repositories
+                .iter()
+                .filter_map(|project| {
+                    let mut local_duplicates = vec![];
+                    app.repo_pool.scan(|k, v| {
+                        if [&project.ssh_url_to_repo, &project.http_url_to_repo]
+                            .iter()
+                            .any(|url| url.eq_ignore_ascii_case(&v.remote.to_string()))
+                        {
+                            local_duplicates.push(k.clone())
+                        }
+                    });
+
+                    Repo::from_gitlab(local_duplicates, &gitlab, project)
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut unknown = unknown_github;
+    unknown.extend(unknown_gitlab);
+
+    let repos = list_unique_repos(app.repo_pool.clone(), unknown).await;
     (StatusCode::OK, Json(ReposResponse::List(repos)))
 }
 
@@ -400,6 +507,67 @@ pub(super) async fn set_indexed(
     json(ReposResponse::SyncQueued)
 }
 
+#[derive(Deserialize)]
+pub(super) struct SetRevisions {
+    revisions: Vec<String>,
+}
+
+/// Set the historical revisions of a repository that are indexed alongside its branches.
+/// This will automatically trigger a sync of the repository.
+//
+pub(super) async fn set_revisions(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+    Json(SetRevisions { mut revisions }): Json<SetRevisions>,
+) -> Result<impl IntoResponse> {
+    revisions.sort();
+    revisions.dedup();
+
+    let found = app
+        .repo_pool
+        .update_async(&repo, |_, v| v.revisions = revisions)
+        .await
+        .is_some();
+
+    if !found {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    app.write_index().enqueue_sync(vec![repo]).await;
+    Ok(json(ReposResponse::SyncQueued))
+}
+
+#[derive(Deserialize)]
+pub(super) struct SetRetrievalOnly {
+    retrieval_only: bool,
+}
+
+/// Keep the code of a repository from LLMs, while it can still be searched.
+///
+/// This is a compliance control, so only admins may change it.
+pub(super) async fn set_retrieval_only(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+    _: Admin,
+    Json(SetRetrievalOnly { retrieval_only }): Json<SetRetrievalOnly>,
+) -> Result<impl IntoResponse> {
+    let updated = app
+        .repo_pool
+        .update_async(&repo, |k, v| {
+            v.retrieval_only = retrieval_only;
+            Repo::from((k, &*v))
+        })
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    app.config
+        .source
+        .save_pool(app.repo_pool.clone())
+        .map_err(Error::internal)?;
+
+    Ok(json(ReposResponse::Item(updated)))
+}
+
 #[derive(Deserialize)]
 pub(super) struct ScanRequest {
     /// The path to scan
@@ -453,7 +621,7 @@ mod test {
 
     use crate::repo::{GitProtocol, GitRemote, RepoRef, RepoRemote::Git, Repository, SyncStatus};
 
-    use super::{list_unique_repos, Repo, RepositoryPool};
+    use super::*;
 
     #[tokio::test]
     async fn unique_repos_only() {
@@ -473,6 +641,13 @@ mod test {
                     last_index_unix_secs: 123456,
                     most_common_lang: None,
                     branch_filter: Default::default(),
+                    revisions: Vec::new(),
+                    pinned: false,
+                    excluded_paths: Vec::new(),
+                    description: None,
+                    topics: Vec::new(),
+                    github_id: None,
+                    retrieval_only: false,
                 },
             )
             .unwrap();
@@ -491,6 +666,13 @@ mod test {
                     last_index_unix_secs: 123456,
                     most_common_lang: None,
                     branch_filter: Default::default(),
+                    revisions: Vec::new(),
+                    pinned: false,
+                    excluded_paths: Vec::new(),
+                    description: None,
+                    topics: Vec::new(),
+                    github_id: None,
+                    retrieval_only: false,
                 },
             )
             .unwrap();
@@ -511,6 +693,13 @@ mod test {
                     last_index_unix_secs: 0,
                     most_common_lang: None,
                     branch_filter: Default::default(),
+                    revisions: Vec::new(),
+                    pinned: false,
+                    excluded_paths: Vec::new(),
+                    description: None,
+                    topics: Vec::new(),
+                    github_id: None,
+                    retrieval_only: false,
                 },
             )
                 .into(),
@@ -530,6 +719,13 @@ mod test {
                 last_index_unix_secs: 0,
                 most_common_lang: None,
                 branch_filter: Default::default(),
+                revisions: Vec::new(),
+                pinned: false,
+                excluded_paths: Vec::new(),
+                description: None,
+                topics: Vec::new(),
+                github_id: None,
+                retrieval_only: false,
             },
         )
             .into();
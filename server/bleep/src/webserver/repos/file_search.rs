@@ -0,0 +1,199 @@
+//! Find-in-file for the code viewer.
+//!
+//! Matches are searched for on the server, in the indexed copy of the file, so that the client
+//! only receives the matching lines and their context rather than the whole file.
+
+use std::ops::Range;
+
+use axum::extract::Path;
+use regex::{Regex, RegexBuilder};
+
+use crate::{repo::RepoRef, webserver::prelude::*};
+
+/// The maximum number of matches returned for one file.
+const MAX_MATCHES: usize = 1000;
+
+/// The maximum number of context lines on either side of a match.
+const MAX_CONTEXT_LINES: usize = 20;
+
+#[derive(Deserialize)]
+pub(super) struct Params {
+    path: String,
+    q: String,
+    branch: Option<String>,
+    /// Treat `q` as a regular expression rather than literal text.
+    #[serde(default)]
+    regex: bool,
+    #[serde(default)]
+    case_sensitive: bool,
+    /// The number of lines to include before and after each match.
+    #[serde(default = "default_context_lines")]
+    context_lines: usize,
+}
+
+fn default_context_lines() -> usize {
+    2
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub(super) struct FileSearchResponse {
+    matches: Vec<FileMatch>,
+    /// Whether there were more than `MAX_MATCHES` matches.
+    truncated: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub(super) struct FileMatch {
+    /// Byte range of the match in the file.
+    byte_range: Range<usize>,
+    /// 0-indexed range of the lines that the match spans, exclusive of the end.
+    line_range: Range<usize>,
+    /// Byte range of the match relative to the start of its first line.
+    column_range: Range<usize>,
+    /// Lines before the match, up to `context_lines`.
+    context_before: Vec<String>,
+    /// The lines the match spans.
+    lines: Vec<String>,
+    /// Lines after the match, up to `context_lines`.
+    context_after: Vec<String>,
+}
+
+impl crate::webserver::ApiResponse for FileSearchResponse {}
+
+/// Search for all matches of a query in one file.
+///
+/// The repo ref is a single path segment, so its slashes must be percent-encoded.
+pub(super) async fn handle(
+    Path(repo_ref): Path<RepoRef>,
+    Query(params): Query<Params>,
+    Extension(indexes): Extension<Arc<Indexes>>,
+) -> Result<impl IntoResponse> {
+    if params.q.is_empty() {
+        return Err(Error::user("empty query"));
+    }
+
+    let pattern = if params.regex {
+        params.q.clone()
+    } else {
+        regex::escape(&params.q)
+    };
+
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(!params.case_sensitive)
+        .multi_line(true)
+        .build()
+        .map_err(|e| Error::user(format!("invalid regex: {e}")))?;
+
+    let doc = indexes
+        .file
+        .by_path(&repo_ref, &params.path, params.branch.as_deref())
+        .await
+        .map_err(Error::internal)?
+        .ok_or_else(|| Error::user("file not found").with_status(StatusCode::NOT_FOUND))?;
+
+    let context_lines = params.context_lines.min(MAX_CONTEXT_LINES);
+    Ok(json(search(&doc.content, &regex, context_lines)))
+}
+
+fn search(content: &str, regex: &Regex, context_lines: usize) -> FileSearchResponse {
+    let line_starts = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+    let lines = content.lines().collect::<Vec<_>>();
+    let line_of = |byte: usize| line_starts.partition_point(|&start| start <= byte) - 1;
+
+    let mut matches = regex
+        .find_iter(content)
+        // Empty matches, e.g. of `^`, can't be highlighted.
+        .filter(|m| !m.range().is_empty())
+        .take(MAX_MATCHES + 1)
+        .map(|m| {
+            let start_line = line_of(m.start());
+            // The end is exclusive, so a match that ends with a newline stays on its line.
+            let end_line = line_of(m.end() - 1) + 1;
+            let column = m.start() - line_starts[start_line];
+            let context_start = start_line.saturating_sub(context_lines);
+            let context_end = (end_line + context_lines).min(lines.len());
+
+            let owned = |range: Range<usize>| {
+                lines
+                    .get(range)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            FileMatch {
+                byte_range: m.range(),
+                line_range: start_line..end_line,
+                column_range: column..column + m.len(),
+                context_before: owned(context_start..start_line),
+                lines: owned(start_line..end_line.min(lines.len())),
+                context_after: owned(end_line.min(lines.len())..context_end),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let truncated = matches.len() > MAX_MATCHES;
+    matches.truncate(MAX_MATCHES);
+
+    FileSearchResponse { matches, truncated }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "fn main() {\n    let foo = 1;\n    let bar = foo;\n}\n";
+
+    #[test]
+    fn finds_matches_with_context() {
+        let regex = Regex::new("foo").unwrap();
+        let response = search(CONTENT, &regex, 1);
+
+        assert!(!response.truncated);
+        assert_eq!(
+            response.matches,
+            [
+                FileMatch {
+                    byte_range: 20..23,
+                    line_range: 1..2,
+                    column_range: 8..11,
+                    context_before: vec!["fn main() {".to_owned()],
+                    lines: vec!["    let foo = 1;".to_owned()],
+                    context_after: vec!["    let bar = foo;".to_owned()],
+                },
+                FileMatch {
+                    byte_range: 43..46,
+                    line_range: 2..3,
+                    column_range: 14..17,
+                    context_before: vec!["    let foo = 1;".to_owned()],
+                    lines: vec!["    let bar = foo;".to_owned()],
+                    context_after: vec!["}".to_owned()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_matches() {
+        let regex = Regex::new(r"1;\n\s+let").unwrap();
+        let response = search(CONTENT, &regex, 0);
+
+        assert_eq!(response.matches.len(), 1);
+        assert_eq!(response.matches[0].line_range, 1..3);
+        assert_eq!(
+            response.matches[0].lines,
+            ["    let foo = 1;", "    let bar = foo;"]
+        );
+        assert!(response.matches[0].context_before.is_empty());
+        assert!(response.matches[0].context_after.is_empty());
+    }
+
+    #[test]
+    fn skips_empty_matches() {
+        let regex = RegexBuilder::new("^").multi_line(true).build().unwrap();
+        assert!(search(CONTENT, &regex, 2).matches.is_empty());
+    }
+}
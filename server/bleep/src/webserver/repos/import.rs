@@ -0,0 +1,399 @@
+//! Bulk import of repositories from a manifest.
+//!
+//! A manifest lists repositories along with their indexing options, in YAML or JSON:
+//!
+//! ```yaml
+//! repos:
+//!   - repo: https://github.com/bloopai/bloop.git
+//!     branches: [staging]
+//!     revisions: [v0.4.0]
+//!     excluded_paths: [client/public]
+//!   - repo: /home/me/src/project
+//!     pinned: true
+//!     retrieval_only: true
+//! ```
+//!
+//! Repositories that are already registered keep the options their entry leaves out.
+//!
+//! Every entry is validated before anything is registered. If any entry is invalid, no
+//! repository is imported, and the errors of every invalid entry are reported.
+
+use std::collections::HashSet;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+
+use super::ReposResponse;
+use crate::{
+    remotes::gitlab,
+    repo::{Backend, BranchFilter, GitRemote, RepoRef, RepoRemote, Repository},
+    webserver::prelude::*,
+    Application,
+};
+
+#[derive(Deserialize)]
+struct Manifest {
+    repos: Vec<serde_yaml::Value>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    /// A GitHub URL, a repo ref such as `github.com/org/repo`, or the absolute path of a local
+    /// repository.
+    repo: String,
+    /// Branches to index in addition to the default branch.
+    #[serde(default)]
+    branches: Vec<String>,
+
+    /// Tags of historical revisions to index, as with `PUT /repos/revisions`.
+    #[serde(default)]
+    revisions: Option<Vec<String>>,
+    #[serde(default)]
+    pinned: Option<bool>,
+    #[serde(default)]
+    excluded_paths: Option<Vec<String>>,
+    /// Keep the code of the repository from LLMs, as with `PUT /repos/retrieval-only`.
+    #[serde(default)]
+    retrieval_only: Option<bool>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct ImportError {
+    /// The position of the entry in the manifest.
+    index: usize,
+    message: String,
+}
+
+/// Register and queue every repository in a manifest, or none of them.
+//
+pub(super) async fn import(
+    State(app): State<Application>,
+    body: String,
+) -> Result<impl IntoResponse> {
+    let manifest = serde_yaml::from_str::<Manifest>(&body)
+        .map_err(|e| Error::user(format!("invalid manifest: {e}")))?;
+
+    let mut seen = HashSet::new();
+    let mut imports = vec![];
+    let mut errors = vec![];
+
+    for (index, value) in manifest.repos.into_iter().enumerate() {
+        let validated = serde_yaml::from_value::<Entry>(value)
+            .map_err(|e| e.to_string())
+            .and_then(|entry| {
+                let reporef = validate(&app, &entry)?;
+                if !seen.insert(reporef.clone()) {
+                    return Err(format!("{reporef} is listed more than once"));
+                }
+
+                Ok((reporef, entry))
+            });
+
+        match validated {
+            Ok(import) => imports.push(import),
+            Err(message) => errors.push(ImportError { index, message }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            json(ReposResponse::ImportErrors(errors)),
+        ));
+    }
+
+    let mut reporefs = vec![];
+    for (reporef, entry) in imports {
+        match app.repo_pool.entry_async(reporef.clone()).await {
+            scc::hash_map::Entry::Occupied(mut existing) => {
+                apply(&reporef, existing.get_mut(), &entry);
+            }
+            scc::hash_map::Entry::Vacant(vacant) => {
+                let mut repo = new_repository(&app, &reporef);
+                apply(&reporef, &mut repo, &entry);
+                vacant.insert_entry(repo);
+            }
+        }
+
+        reporefs.push(reporef);
+    }
+
+    app.write_index().enqueue_sync(reporefs.clone()).await;
+    Ok((StatusCode::OK, json(ReposResponse::Imported(reporefs))))
+}
+
+fn validate(app: &Application, entry: &Entry) -> std::result::Result<RepoRef, String> {
+    let reporef = parse_repo(&entry.repo, &app.config.gitlab_url)?;
+
+    match reporef.backend() {
+        Backend::Local => {
+            let path = reporef.local_path().expect("local repo ref");
+            if !app.allow_path(&path) {
+                return Err(format!("indexing {} is not allowed", path.display()));
+            }
+
+            if !path.is_dir() {
+                return Err(format!("{} is not a directory", path.display()));
+            }
+        }
+        Backend::Github => {
+            if app.credentials.github().is_none() {
+                return Err("GitHub is not connected".to_owned());
+            }
+        }
+        Backend::GitLab => {
+            if app.credentials.gitlab().is_none() {
+                return Err("GitLab is not connected".to_owned());
+            }
+        }
+    }
+
+    for branch in &entry.branches {
+        regex::Regex::new(branch).map_err(|e| format!("invalid branch `{branch}`: {e}"))?;
+    }
+
+    if entry
+        .revisions
+        .iter()
+        .flatten()
+        .any(|r| r.trim().is_empty())
+    {
+        return Err("revisions can't be empty".to_owned());
+    }
+
+    for path in entry.excluded_paths.iter().flatten() {
+        let path = std::path::Path::new(path);
+        if path.is_absolute()
+            || path
+                .components()
+                .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(format!(
+                "excluded path `{}` must be relative to the repository root",
+                path.display()
+            ));
+        }
+    }
+
+    Ok(reporef)
+}
+
+/// Parse a repository, given as a GitHub URL, a URL of a project on the GitLab instance at
+/// `gitlab_url`, a local path, or a repository reference.
+fn parse_repo(repo: &str, gitlab_url: &str) -> std::result::Result<RepoRef, String> {
+    if let Ok(RepoRemote::Git(GitRemote { address, .. })) = repo.parse::<RepoRemote>() {
+        return match address.split('/').collect::<Vec<_>>()[..] {
+            [org, name] if !org.is_empty() && !name.is_empty() => {
+                RepoRef::new(Backend::Github, &address).map_err(|e| e.to_string())
+            }
+            _ => Err(format!("`{repo}` is not a GitHub repository URL")),
+        };
+    }
+
+    let gitlab_host = gitlab::host(gitlab_url);
+    let gitlab_path = repo
+        .strip_prefix(&format!("https://{gitlab_host}/"))
+        .or_else(|| repo.strip_prefix(&format!("git@{gitlab_host}:")));
+
+    if let Some(path) = gitlab_path {
+        let path = path.trim_matches('/').trim_end_matches(".git");
+        if !path.contains('/') {
+            return Err(format!("`{repo}` is not a GitLab project URL"));
+        }
+
+        return RepoRef::new(Backend::GitLab, &format!("{gitlab_host}/{path}"))
+            .map_err(|e| e.to_string());
+    }
+
+    if repo.starts_with('/') {
+        return RepoRef::new(Backend::Local, repo).map_err(|e| e.to_string());
+    }
+
+    repo.parse::<RepoRef>()
+        .map_err(|e| format!("`{repo}` is not a repository: {e}"))
+}
+
+fn new_repository(app: &Application, reporef: &RepoRef) -> Repository {
+    if reporef.is_local() {
+        return Repository::local_from(reporef);
+    }
+
+    let name = reporef.to_string();
+    let disk_path = app
+        .config
+        .source
+        .repo_path_for_name(&name.replace('/', "_"));
+
+    Repository::remote_from(reporef, disk_path)
+}
+
+fn apply(reporef: &RepoRef, repo: &mut Repository, entry: &Entry) {
+    if !entry.branches.is_empty() {
+        let branches = entry
+            .branches
+            .iter()
+            .map(|branch| {
+                if reporef.is_remote() && !branch.starts_with("origin/") {
+                    format!("origin/{branch}")
+                } else {
+                    branch.clone()
+                }
+            })
+            .collect();
+
+        repo.branch_filter = Some(BranchFilter::Select(branches));
+    }
+
+    if let Some(revisions) = &entry.revisions {
+        let mut revisions = revisions.clone();
+        revisions.sort();
+        revisions.dedup();
+
+        repo.revisions = revisions;
+    }
+
+    if let Some(pinned) = entry.pinned {
+        repo.pinned = pinned;
+    }
+
+    if let Some(excluded_paths) = &entry.excluded_paths {
+        repo.excluded_paths = excluded_paths.clone();
+    }
+
+    if let Some(retrieval_only) = entry.retrieval_only {
+        repo.retrieval_only = retrieval_only;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GITLAB_URL: &str = "https://gitlab.example.com";
+
+    #[test]
+    fn parses_repos() {
+        let github = RepoRef::new(Backend::Github, "bloopai/bloop").unwrap();
+
+        assert_eq!(
+            parse_repo("https://github.com/bloopai/bloop.git", GITLAB_URL),
+            Ok(github.clone())
+        );
+        assert_eq!(
+            parse_repo("git@github.com:bloopai/bloop.git", GITLAB_URL),
+            Ok(github.clone())
+        );
+        assert_eq!(
+            parse_repo("github.com/bloopai/bloop", GITLAB_URL),
+            Ok(github)
+        );
+        assert_eq!(
+            parse_repo("/home/me/src/project", GITLAB_URL),
+            Ok(RepoRef::new(Backend::Local, "/home/me/src/project").unwrap())
+        );
+
+        let gitlab = RepoRef::new(Backend::GitLab, "gitlab.example.com/platform/api").unwrap();
+        assert_eq!(
+            parse_repo("https://gitlab.example.com/platform/api.git", GITLAB_URL),
+            Ok(gitlab.clone())
+        );
+        assert_eq!(
+            parse_repo("git@gitlab.example.com:platform/api.git", GITLAB_URL),
+            Ok(gitlab.clone())
+        );
+        assert_eq!(
+            parse_repo("gitlab/gitlab.example.com/platform/api", GITLAB_URL),
+            Ok(gitlab)
+        );
+
+        assert!(parse_repo("https://github.com/bloopai", GITLAB_URL).is_err());
+        assert!(parse_repo("https://gitlab.example.com/platform", GITLAB_URL).is_err());
+        assert!(parse_repo("src/project", GITLAB_URL).is_err());
+        assert!(parse_repo("/home/me/../project", GITLAB_URL).is_err());
+    }
+
+    #[test]
+    fn reads_manifests() {
+        let yaml = "repos:\n  - repo: github.com/bloopai/bloop\n    branches: [staging]\n    pinned: true\n  - repo: /src/project\n    colour: blue\n";
+        let manifest = serde_yaml::from_str::<Manifest>(yaml).unwrap();
+        let entries = manifest
+            .repos
+            .into_iter()
+            .map(serde_yaml::from_value::<Entry>)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries[0].as_ref().unwrap(),
+            &Entry {
+                repo: "github.com/bloopai/bloop".into(),
+                branches: vec!["staging".into()],
+                revisions: None,
+                pinned: Some(true),
+                excluded_paths: None,
+                retrieval_only: None,
+            }
+        );
+        assert!(entries[1].is_err());
+
+        // JSON manifests are read as YAML.
+        let json = r#"{"repos": [{"repo": "/src/project", "excluded_paths": ["vendor"]}]}"#;
+        assert_eq!(
+            serde_yaml::from_str::<Manifest>(json).unwrap().repos.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn applies_options() {
+        let reporef = RepoRef::new(Backend::Github, "bloopai/bloop").unwrap();
+        let mut repo = Repository::remote_from(&reporef, "/unused".into());
+        let entry = Entry {
+            repo: reporef.to_string(),
+            branches: vec!["staging".into(), "origin/main".into()],
+            revisions: Some(vec!["v2".into(), "v1".into(), "v2".into()]),
+            pinned: Some(true),
+            excluded_paths: Some(vec!["vendor".into()]),
+            retrieval_only: Some(true),
+        };
+
+        apply(&reporef, &mut repo, &entry);
+
+        assert_eq!(
+            repo.branch_filter,
+            Some(BranchFilter::Select(vec![
+                "origin/staging".into(),
+                "origin/main".into()
+            ]))
+        );
+        assert_eq!(repo.revisions, ["v1", "v2"]);
+        assert!(repo.pinned);
+        assert!(repo.retrieval_only);
+        assert_eq!(repo.excluded_paths, ["vendor"]);
+    }
+
+    #[test]
+    fn keeps_options_that_are_not_set() {
+        let reporef = RepoRef::new(Backend::Github, "bloopai/bloop").unwrap();
+        let mut repo = Repository::remote_from(&reporef, "/unused".into());
+        repo.revisions = vec!["v1".into()];
+        repo.pinned = true;
+        repo.excluded_paths = vec!["vendor".into()];
+        repo.retrieval_only = true;
+
+        let entry = Entry {
+            repo: reporef.to_string(),
+            branches: vec![],
+            revisions: None,
+            pinned: Some(false),
+            excluded_paths: None,
+            retrieval_only: None,
+        };
+
+        apply(&reporef, &mut repo, &entry);
+
+        assert_eq!(repo.revisions, ["v1"]);
+        assert!(!repo.pinned);
+        assert_eq!(repo.excluded_paths, ["vendor"]);
+        assert!(repo.retrieval_only);
+    }
+}
@@ -0,0 +1,120 @@
+//! Statistics of the semantic index of a repository.
+//!
+//! Operators compare the files of a repository on disk with the files that it has points for,
+//! to tell when its semantic index is stale or was only partially written.
+
+use axum::extract::State;
+use chrono::{DateTime, TimeZone, Utc};
+
+use super::RepoParams;
+use crate::{cache::ChunkCache, repo::RepoRef, webserver::prelude::*, Application};
+
+/// The maximum number of paths listed for each kind of drift.
+const MAX_DRIFT_PATHS: usize = 100;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub(super) struct SemanticStats {
+    repo_ref: RepoRef,
+    /// The number of points of the repository in its collection.
+    points: u64,
+    /// The model that embeds the repository, as `name@version`.
+    embedding_model: String,
+    last_index: Option<DateTime<Utc>>,
+    /// The number of files of the repository on disk.
+    files: usize,
+    /// The number of files that the repository has points for.
+    embedded_files: usize,
+    /// The number of files on disk that were never embedded, usually because they are too short
+    /// to make a chunk.
+    unembedded_files: usize,
+    /// Files on disk that chunks were embedded for, but which have no points.
+    missing: Drift,
+    /// Files with points that are no longer on disk.
+    orphaned: Drift,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub(super) struct Drift {
+    count: usize,
+    /// The first paths in order, up to `MAX_DRIFT_PATHS`.
+    paths: Vec<String>,
+}
+
+impl Drift {
+    fn new<'a>(paths: impl Iterator<Item = &'a String>) -> Self {
+        let mut paths = paths.cloned().collect::<Vec<_>>();
+        paths.sort();
+
+        let count = paths.len();
+        paths.truncate(MAX_DRIFT_PATHS);
+
+        Self { count, paths }
+    }
+}
+
+impl crate::webserver::ApiResponse for SemanticStats {}
+
+/// Report the state of the semantic index of a repository.
+///
+/// This reads every point of the repository, so it is meant for operators rather than clients.
+pub(super) async fn handle(
+    Query(RepoParams { repo }): Query<RepoParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let semantic = app
+        .semantic
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::Configuration, "semantic search is not enabled"))?;
+
+    let last_index_unix_secs = app
+        .repo_pool
+        .read_async(&repo, |_, repo| repo.last_index_unix_secs)
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    let (points, embedded) = semantic.repo_points(&repo).await?;
+    let on_disk = app.indexes.file.paths_of_repo(&repo).await;
+    let chunked = ChunkCache::embedded_paths(&app.sql, &repo).await?;
+
+    let missing = on_disk
+        .iter()
+        .filter(|path| chunked.contains(*path) && !embedded.contains(*path));
+    let orphaned = embedded.difference(&on_disk);
+    let unembedded = on_disk
+        .iter()
+        .filter(|path| !chunked.contains(*path) && !embedded.contains(*path))
+        .count();
+
+    Ok(json(SemanticStats {
+        points,
+        embedding_model: semantic.model_for(&repo.indexed_name()).id(),
+        last_index: match last_index_unix_secs {
+            0 => None,
+            secs => Utc.timestamp_opt(secs as i64, 0).single(),
+        },
+        files: on_disk.len(),
+        embedded_files: embedded.len(),
+        unembedded_files: unembedded,
+        missing: Drift::new(missing),
+        orphaned: Drift::new(orphaned),
+        repo_ref: repo,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_lists_first_paths() {
+        let paths = (0..MAX_DRIFT_PATHS + 5)
+            .rev()
+            .map(|i| format!("src/{i:03}.rs"))
+            .collect::<Vec<_>>();
+
+        let drift = Drift::new(paths.iter());
+        assert_eq!(drift.count, MAX_DRIFT_PATHS + 5);
+        assert_eq!(drift.paths.len(), MAX_DRIFT_PATHS);
+        assert_eq!(drift.paths[0], "src/000.rs");
+    }
+}
@@ -0,0 +1,49 @@
+//! Upload of SCIP and LSIF dumps, or of ctags files, as a source of symbols for code navigation.
+//!
+//! ```text
+//! POST /repos/symbols?repo=github.com/bloopai/bloop&format=scip
+//! ```
+//!
+//! The dump is the body of the request. Paths in the dump are relative to the root of the
+//! repository, and are matched against its default branch.
+
+use axum::{body::Bytes, extract::State, Json};
+
+use crate::{
+    intelligence::precise::{self, Dump, ImportStats, Source},
+    repo::RepoRef,
+    webserver::prelude::*,
+    Application,
+};
+
+/// Dumps of large repositories are much bigger than the default body limit.
+pub(super) const MAX_DUMP_SIZE: usize = 512 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub(super) struct ImportParams {
+    repo: RepoRef,
+    format: Source,
+}
+
+/// Import the symbols of a dump, replacing those of the files it covers.
+//
+pub(super) async fn import(
+    State(app): State<Application>,
+    Query(params): Query<ImportParams>,
+    body: Bytes,
+) -> Result<Json<ImportStats>> {
+    app.repo_pool
+        .read_async(&params.repo, |_, _| ())
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown repository"))?;
+
+    let format = params.format;
+    let dump = tokio::task::spawn_blocking(move || Dump::parse(format, &body))
+        .await
+        .map_err(Error::internal)?
+        .map_err(|e| Error::user(format!("invalid {} dump: {e:#}", format.as_str())))?;
+
+    let stats = precise::import(&app.indexes, &app.sql, &params.repo, &dump).await?;
+
+    Ok(Json(stats))
+}
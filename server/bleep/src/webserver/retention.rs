@@ -0,0 +1,15 @@
+//! A dry run of the purge of expired data, so that admins can check what a retention window
+//! would delete before they set it, or while `retention_dry_run` is set.
+
+use axum::Json;
+
+use super::{prelude::*, Admin};
+use crate::{periodic, Application};
+
+/// The records that are past their retention window now, for admins.
+pub(super) async fn report(
+    _: Admin,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(periodic::purge(&app, true).await?))
+}
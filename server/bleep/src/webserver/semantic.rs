@@ -4,8 +4,10 @@ use crate::{
         execute::ApiQuery,
         parser::{self, ParsedQuery},
     },
-    semantic::{self, Semantic},
+    repo::RepoRef,
+    semantic::{self, execute::Selection, Semantic},
 };
+use axum::Json;
 use tracing::error;
 
 pub(super) async fn complex_search(
@@ -36,3 +38,106 @@ pub(super) async fn complex_search(
         }
     }
 }
+
+#[derive(Deserialize)]
+pub(super) struct SimilarParams {
+    /// Pasted code to find similar code to. Without it, the code is read from `path`.
+    text: Option<String>,
+    repo_ref: Option<RepoRef>,
+    path: Option<String>,
+    branch: Option<String>,
+    /// 1-indexed lines of the selection in `path`, inclusive. The whole file without them.
+    line_start: Option<usize>,
+    line_end: Option<usize>,
+    /// The repositories to search, all of them when empty.
+    #[serde(default)]
+    repos: Vec<RepoRef>,
+    #[serde(default = "default_similar_limit")]
+    limit: u64,
+}
+
+fn default_similar_limit() -> u64 {
+    10
+}
+
+/// Find the code that is most similar to a selection of a file, or to pasted code.
+pub(super) async fn similar(
+    Extension(indexes): Extension<Arc<Indexes>>,
+    Extension(semantic): Extension<Option<Semantic>>,
+    Json(params): Json<SimilarParams>,
+) -> impl IntoResponse {
+    let Some(semantic) = semantic else {
+        return Err(Error::new(
+            ErrorKind::Configuration,
+            "Qdrant not configured",
+        ));
+    };
+
+    let (code, selection) = match (params.text, params.repo_ref, params.path) {
+        (Some(text), _, _) => (text, None),
+        (None, Some(repo_ref), Some(path)) => {
+            let doc = indexes
+                .file
+                .by_path(&repo_ref, &path, params.branch.as_deref())
+                .await
+                .map_err(Error::internal)?
+                .ok_or_else(|| Error::user("file not found").with_status(StatusCode::NOT_FOUND))?;
+
+            let start = params.line_start.unwrap_or(1).max(1) - 1;
+            let end = params.line_end.map_or(usize::MAX, |end| end.max(1) - 1);
+            let selection = Selection {
+                repo_ref,
+                relative_path: path,
+                lines: start..=end,
+            };
+
+            (
+                select_lines(&doc.content, &selection.lines),
+                Some(selection),
+            )
+        }
+        _ => {
+            return Err(Error::user(
+                "either `text`, or `repo_ref` and `path` must be provided",
+            ))
+        }
+    };
+
+    if code.trim().is_empty() {
+        return Err(Error::user("the selection is empty"));
+    }
+
+    semantic::execute::similar(
+        semantic,
+        &code,
+        &params.repos,
+        selection.as_ref(),
+        params.limit.clamp(1, 100),
+    )
+    .await
+    .map(json)
+    .map_err(super::Error::from)
+}
+
+fn select_lines(content: &str, lines: &std::ops::RangeInclusive<usize>) -> String {
+    content
+        .lines()
+        .skip(*lines.start())
+        .take(lines.end().saturating_sub(*lines.start()).saturating_add(1))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_lines() {
+        let content = "a\nb\nc\nd\n";
+
+        assert_eq!(select_lines(content, &(1..=2)), "b\nc");
+        assert_eq!(select_lines(content, &(2..=usize::MAX)), "c\nd");
+        assert_eq!(select_lines(content, &(5..=6)), "");
+    }
+}
@@ -0,0 +1,104 @@
+//! Context expansion for snippets shown in search results and answers.
+
+use axum::Json;
+
+use super::prelude::*;
+use crate::{repo::RepoRef, snippet};
+
+/// The maximum number of lines a snippet can be expanded by at once.
+const MAX_LINES: usize = 500;
+
+#[derive(Deserialize)]
+pub(super) struct ExpandParams {
+    repo: RepoRef,
+    path: String,
+    branch: Option<String>,
+    /// 1-indexed first line of the snippet.
+    start_line: usize,
+    /// 1-indexed last line of the snippet, inclusive.
+    end_line: usize,
+    /// The number of lines to add, split evenly before and after the snippet.
+    #[serde(default = "default_lines")]
+    lines: usize,
+}
+
+fn default_lines() -> usize {
+    40
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub(super) struct Expanded {
+    text: String,
+    /// 1-indexed first line of `text`.
+    start_line: usize,
+    /// 1-indexed last line of `text`, inclusive.
+    end_line: usize,
+}
+
+/// Expand a snippet with the lines around it.
+pub(super) async fn expand(
+    Query(params): Query<ExpandParams>,
+    Extension(indexes): Extension<Arc<Indexes>>,
+) -> Result<impl IntoResponse> {
+    if params.start_line == 0 || params.start_line > params.end_line {
+        return Err(Error::user("invalid line range, lines are 1-indexed"));
+    }
+
+    let doc = indexes
+        .file
+        .by_path(&params.repo, &params.path, params.branch.as_deref())
+        .await
+        .map_err(Error::internal)?
+        .ok_or_else(|| Error::user("file not found").with_status(StatusCode::NOT_FOUND))?;
+
+    let lines = doc.content.lines().collect::<Vec<_>>();
+    if params.start_line > lines.len() {
+        return Err(Error::user("start line is past the end of the file"));
+    }
+
+    Ok(Json(expand_lines(
+        &lines,
+        params.start_line - 1..params.end_line,
+        params.lines.min(MAX_LINES),
+    )))
+}
+
+fn expand_lines(lines: &[&str], span: std::ops::Range<usize>, by: usize) -> Expanded {
+    let span = snippet::grow(span, by / 2, lines.len());
+
+    Expanded {
+        text: lines[span.clone()].join("\n"),
+        start_line: span.start + 1,
+        end_line: span.end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_within_file() {
+        let lines = (1..=10).map(|n| n.to_string()).collect::<Vec<_>>();
+        let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+
+        assert_eq!(
+            expand_lines(&lines, 4..6, 4),
+            Expanded {
+                text: "3\n4\n5\n6\n7\n8".to_owned(),
+                start_line: 3,
+                end_line: 8,
+            }
+        );
+
+        // The end of the snippet is corrected to the end of the file.
+        assert_eq!(
+            expand_lines(&lines, 7..20, 4),
+            Expanded {
+                text: "6\n7\n8\n9\n10".to_owned(),
+                start_line: 6,
+                end_line: 10,
+            }
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! LLM token usage, and the daily per-user quota.
+//!
+//! Usage is recorded per query by the agent, and per request by endpoints that call the LLM
+//! outside of a conversation. Users can see their own usage over the last day, and
+//! usage admins can see everyone's, grouped by user, thread, repository or model. Admins can also
+//! scrape the total usage and cost of each model, in the Prometheus text format, along with the
+//! cache hits of the GitHub repository lists that are polled in the background.
+
+use std::{collections::HashMap, fmt::Write};
+
+use axum::{http::header, Json};
+
+use tracing::warn;
+
+use super::{middleware::User, prelude::*};
+use crate::{
+    db::{TokenUsage, UsageRecord},
+    llm_gateway,
+    repo::RepoRef,
+    Application,
+};
+
+/// The window of the daily quota, in seconds.
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GroupBy {
+    #[default]
+    User,
+    Thread,
+    Repo,
+    Model,
+}
+
+#[derive(Deserialize)]
+pub(super) struct AllParams {
+    #[serde(default)]
+    by: GroupBy,
+    /// Seconds since the unix epoch. Defaults to a day ago.
+    since: Option<i64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(super) struct Group {
+    key: String,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    total_tokens: i64,
+    calls: i64,
+    /// The estimated cost in USD.
+    cost: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct UserUsage {
+    /// Tokens spent over the last day.
+    used: i64,
+    /// The estimated cost of the tokens spent over the last day, in USD.
+    cost: f64,
+    quota: Option<usize>,
+    remaining: Option<usize>,
+    threads: Vec<Group>,
+    repos: Vec<Group>,
+}
+
+/// The token usage of the current user over the last day.
+pub(super) async fn get(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    let user_id = user
+        .login()
+        .ok_or_else(|| Error::user("didn't have user ID"))?;
+
+    let records = TokenUsage::new(&app.sql)
+        .user_since(user_id, day_ago())
+        .await?;
+
+    let used = records
+        .iter()
+        .map(|r| r.prompt_tokens + r.completion_tokens)
+        .sum::<i64>();
+    let quota = app.config.user_daily_token_quota;
+
+    Ok(Json(UserUsage {
+        used,
+        cost: records.iter().map(|r| r.cost).sum(),
+        quota,
+        remaining: quota.map(|q| q.saturating_sub(used as usize)),
+        threads: group(&records, GroupBy::Thread),
+        repos: group(&records, GroupBy::Repo),
+    }))
+}
+
+/// The token usage of all users, for usage admins.
+pub(super) async fn all(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+    Query(params): Query<AllParams>,
+) -> Result<impl IntoResponse> {
+    if !is_usage_admin(&app, &user) {
+        return Err(
+            Error::user("only usage admins can see the usage of all users")
+                .with_status(StatusCode::FORBIDDEN),
+        );
+    }
+
+    let records = TokenUsage::new(&app.sql)
+        .since(params.since.unwrap_or_else(day_ago))
+        .await?;
+
+    Ok(Json(group(&records, params.by)))
+}
+
+/// The total usage and cost of each model, for usage admins to monitor spend.
+pub(super) async fn metrics(
+    Extension(user): Extension<User>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    if !is_usage_admin(&app, &user) {
+        return Err(Error::user("only usage admins can see usage metrics")
+            .with_status(StatusCode::FORBIDDEN));
+    }
+
+    let records = TokenUsage::new(&app.sql).since(0).await?;
+    let mut text = metrics_text(&group(&records, GroupBy::Model));
+    text += &github_metrics_text(app.github_lists.stats());
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+}
+
+/// Reject a query if the user has spent their daily token quota.
+pub(super) async fn check_quota(app: &Application, user: &User) -> Result<()> {
+    let (Some(quota), Some(user_id)) = (app.config.user_daily_token_quota, user.login()) else {
+        return Ok(());
+    };
+
+    let used = TokenUsage::new(&app.sql)
+        .user_since(user_id, day_ago())
+        .await?
+        .iter()
+        .map(|r| r.prompt_tokens + r.completion_tokens)
+        .sum::<i64>();
+
+    if used as usize >= quota {
+        return Err(Error::user(format!(
+            "daily token quota of {quota} exceeded, try again later"
+        ))
+        .with_status(StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    Ok(())
+}
+
+/// Record the tokens spent with `llm_gateway` by a request that is not part of a conversation, as
+/// a thread of its own.
+pub(super) async fn record(
+    app: &Application,
+    user: &User,
+    repo_ref: &RepoRef,
+    llm_gateway: &llm_gateway::Client,
+) {
+    let Some(meter) = &llm_gateway.meter else {
+        return;
+    };
+
+    let usage = meter.totals();
+    if usage.is_empty() {
+        return;
+    }
+
+    let id = uuid::Uuid::new_v4();
+    if let Err(err) = TokenUsage::new(&app.sql)
+        .insert(
+            user.login(),
+            id,
+            id,
+            &repo_ref.to_string(),
+            &usage,
+            &app.prices,
+        )
+        .await
+    {
+        warn!(?err, "failed to record token usage");
+    }
+}
+
+/// Usage admins see the token usage of every user, but can't change anything else.
+fn is_usage_admin(app: &Application, user: &User) -> bool {
+    super::is_admin(app, user)
+        || user
+            .login()
+            .is_some_and(|login| app.config.usage_admins.iter().any(|a| a == login))
+}
+
+fn day_ago() -> i64 {
+    chrono::Utc::now().timestamp() - DAY_SECS
+}
+
+/// Sum usage by `by`, with the largest totals first.
+fn group(records: &[UsageRecord], by: GroupBy) -> Vec<Group> {
+    let mut groups = HashMap::<&str, (i64, i64, i64, f64)>::new();
+
+    for r in records {
+        let key = match by {
+            GroupBy::User => &r.user_id,
+            GroupBy::Thread => &r.thread_id,
+            GroupBy::Repo => &r.repo_ref,
+            GroupBy::Model => &r.model,
+        };
+
+        let (prompt, completion, calls, cost) = groups.entry(key).or_default();
+        *prompt += r.prompt_tokens;
+        *completion += r.completion_tokens;
+        *calls += r.calls;
+        *cost += r.cost;
+    }
+
+    let mut groups = groups
+        .into_iter()
+        .map(
+            |(key, (prompt_tokens, completion_tokens, calls, cost))| Group {
+                key: key.to_owned(),
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+                calls,
+                cost,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    groups.sort_by(|a, b| b.total_tokens.cmp(&a.total_tokens).then(a.key.cmp(&b.key)));
+    groups
+}
+
+/// Counters of the GitHub repository list pages that were served from the cache, and those that
+/// were fetched, in the Prometheus text format.
+fn github_metrics_text((hits, misses): (u64, u64)) -> String {
+    let name = "bloop_github_list_pages_total";
+    format!(
+        "# HELP {name} Pages of GitHub repository lists, by whether they were unchanged\n\
+         # TYPE {name} counter\n\
+         {name}{{cache=\"hit\"}} {hits}\n\
+         {name}{{cache=\"miss\"}} {misses}\n"
+    )
+}
+
+/// Counters of the usage of each model, in the Prometheus text format.
+fn metrics_text(models: &[Group]) -> String {
+    let metrics: [(&str, &str, fn(&Group) -> String); 4] = [
+        ("prompt_tokens", "Prompt tokens sent to the model", |g| {
+            g.prompt_tokens.to_string()
+        }),
+        (
+            "completion_tokens",
+            "Completion tokens received from the model",
+            |g| g.completion_tokens.to_string(),
+        ),
+        ("calls", "Requests made to the model", |g| {
+            g.calls.to_string()
+        }),
+        ("cost_usd", "Estimated cost of the requests, in USD", |g| {
+            g.cost.to_string()
+        }),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in metrics {
+        let name = format!("bloop_llm_{name}_total");
+        writeln!(text, "# HELP {name} {help}").unwrap();
+        writeln!(text, "# TYPE {name} counter").unwrap();
+
+        for group in models {
+            let model = group.key.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(text, "{name}{{model=\"{model}\"}} {}", value(group)).unwrap();
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(user_id: &str, repo_ref: &str, prompt_tokens: i64) -> UsageRecord {
+        UsageRecord {
+            user_id: user_id.to_owned(),
+            thread_id: "thread".to_owned(),
+            repo_ref: repo_ref.to_owned(),
+            model: "gpt-4-0613".to_owned(),
+            prompt_tokens,
+            completion_tokens: 1,
+            calls: 1,
+            cost: 0.5,
+        }
+    }
+
+    #[test]
+    fn groups_usage() {
+        let records = [
+            record("alice", "github.com/org/a", 10),
+            record("bob", "github.com/org/a", 30),
+            record("alice", "github.com/org/b", 5),
+        ];
+
+        // Each record has a single completion token.
+        let group_of = |key: &str, prompt_tokens, completion_tokens| Group {
+            key: key.to_owned(),
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            calls: completion_tokens,
+            cost: completion_tokens as f64 * 0.5,
+        };
+
+        assert_eq!(
+            group(&records, GroupBy::User),
+            [group_of("bob", 30, 1), group_of("alice", 15, 2)]
+        );
+        assert_eq!(
+            group(&records, GroupBy::Repo),
+            [
+                group_of("github.com/org/a", 40, 2),
+                group_of("github.com/org/b", 5, 1)
+            ]
+        );
+        assert_eq!(
+            group(&records, GroupBy::Model),
+            [group_of("gpt-4-0613", 45, 3)]
+        );
+        assert!(group(&[], GroupBy::Thread).is_empty());
+    }
+
+    #[test]
+    fn formats_metrics() {
+        let records = [
+            record("alice", "github.com/org/a", 10),
+            record("bob", "github.com/org/a", 30),
+        ];
+
+        let text = metrics_text(&group(&records, GroupBy::Model));
+        let lines = text.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 12);
+        assert_eq!(
+            lines[..3],
+            [
+                "# HELP bloop_llm_prompt_tokens_total Prompt tokens sent to the model",
+                "# TYPE bloop_llm_prompt_tokens_total counter",
+                r#"bloop_llm_prompt_tokens_total{model="gpt-4-0613"} 40"#,
+            ]
+        );
+        assert!(lines.contains(&r#"bloop_llm_calls_total{model="gpt-4-0613"} 2"#));
+        assert!(lines.contains(&r#"bloop_llm_cost_usd_total{model="gpt-4-0613"} 1"#));
+
+        assert_eq!(
+            github_metrics_text((3, 1))
+                .lines()
+                .skip(2)
+                .collect::<Vec<_>>(),
+            [
+                r#"bloop_github_list_pages_total{cache="hit"} 3"#,
+                r#"bloop_github_list_pages_total{cache="miss"} 1"#,
+            ]
+        );
+    }
+}
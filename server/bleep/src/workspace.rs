@@ -0,0 +1,179 @@
+//! An embeddable API for the bloop engine.
+//!
+//! A [`Workspace`] gives other Rust tools access to indexing, search and the answer pipeline,
+//! without running the web server:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use bleep::{workspace::Workspace, Configuration};
+//!
+//! let workspace = Workspace::open(Configuration::read("bloop.json")?).await?;
+//! let repo_ref = workspace.index("/home/me/src/project").await?;
+//!
+//! let results = workspace.search("symbol:Workspace").await?;
+//! let answer = workspace.ask(&repo_ref, "how are repositories indexed?").await?;
+//! println!("{}", answer.text);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use secrecy::ExposeSecret;
+use tracing::debug;
+
+use crate::{
+    agent::{exchange::Exchange, stages::Stages, Action, Agent},
+    query::{
+        execute::{resolve_topics, ApiQuery, QueryResponse},
+        parser,
+    },
+    repo::{RepoRef, SyncStatus},
+    webserver::middleware::User,
+    Application, Configuration, Environment,
+};
+
+/// The number of agent updates that can be buffered before the agent waits for them to be read.
+const UPDATE_BUFFER: usize = 10;
+
+/// A set of indexed repositories, along with the search and answer engines that run on them.
+#[derive(Clone)]
+pub struct Workspace {
+    app: Application,
+}
+
+/// The answer to a question, as returned by [`Workspace::ask`].
+#[derive(Debug, Clone)]
+pub struct Answer {
+    /// The identifier of the conversation this answer belongs to.
+    pub thread_id: uuid::Uuid,
+
+    /// The full answer, in markdown.
+    pub text: String,
+
+    /// The paths of files that were considered while answering.
+    pub paths: Vec<String>,
+}
+
+impl Workspace {
+    /// Open a workspace, keeping its indexes and state under `config.index_dir`.
+    ///
+    /// Any repositories indexed by a previous session with the same configuration are available
+    /// immediately.
+    pub async fn open(config: Configuration) -> Result<Self> {
+        let app =
+            Application::initialize(Environment::insecure_local(), config, None, None).await?;
+        Ok(Self { app })
+    }
+
+    /// Index the local repository at `path`, and wait for indexing to finish.
+    ///
+    /// Indexing a repository that is already known re-indexes any files that changed since.
+    pub async fn index(&self, path: impl AsRef<Path>) -> Result<RepoRef> {
+        let path = std::fs::canonicalize(path.as_ref())
+            .with_context(|| format!("invalid repository path: {}", path.as_ref().display()))?;
+
+        let repo_ref = RepoRef::from(&path);
+        debug!(%repo_ref, "indexing repository");
+
+        match self
+            .app
+            .write_index()
+            .block_until_synced(repo_ref.clone())
+            .await?
+        {
+            SyncStatus::Done => Ok(repo_ref),
+            SyncStatus::Error { message } => bail!("failed to index {repo_ref}: {message}"),
+            status => bail!("indexing {repo_ref} did not complete: {status:?}"),
+        }
+    }
+
+    /// Run a query written in the bloop query language, across all indexed repositories.
+    pub async fn search(&self, query: &str) -> Result<QueryResponse> {
+        let queries = resolve_topics(&self.app.repo_pool, parser::parse(query)?)?;
+        Arc::new(ApiQuery::new(query))
+            .query_with(self.app.indexes.clone(), queries)
+            .await
+    }
+
+    /// Answer a natural language question about a repository.
+    ///
+    /// This runs the same pipeline as the `/answer` endpoint, and requires access to the answer
+    /// API configured with `answer_api_url`.
+    pub async fn ask(&self, repo_ref: &RepoRef, question: &str) -> Result<Answer> {
+        let query = parser::parse_nl(question)
+            .context("parse error")?
+            .into_semantic()
+            .context("got a 'Grep' query")?
+            .into_owned();
+        let query_target = query.target().context("query was empty")?.into_owned();
+
+        if self.app.is_retrieval_only(repo_ref).await {
+            bail!("{repo_ref} is retrieval-only, so its code is never sent to an LLM");
+        }
+
+        let thread_id = uuid::Uuid::new_v4();
+        let query_id = uuid::Uuid::new_v4();
+
+        let answer_api_token = self
+            .app
+            .answer_api_token()?
+            .map(|s| s.expose_secret().clone());
+
+        let llm_gateway = self
+            .app
+            .llm_gateway_client()
+            .temperature(0.0)
+            .bearer(answer_api_token)
+            .session_reference_id(thread_id.to_string());
+
+        // Intermediate updates are only useful for streaming, so we discard them.
+        let (exchange_tx, mut exchange_rx) = tokio::sync::mpsc::channel(UPDATE_BUFFER);
+        tokio::spawn(async move { while exchange_rx.recv().await.is_some() {} });
+
+        let mut agent = Agent {
+            app: self.app.clone(),
+            repo_ref: repo_ref.clone(),
+            exchanges: vec![Exchange::new(query_id, query)],
+            exchange_tx,
+            stages: Stages::new(&self.app, &llm_gateway),
+            llm_gateway,
+            summary: None,
+            user: User::Unknown,
+            thread_id,
+            query_id,
+            budget: None,
+            diagnostics: false,
+            alternatives: 0,
+            path_scope: None,
+            complete: false,
+        };
+
+        let mut action = Action::Query(query_target);
+        let result = loop {
+            match agent.step(action).await {
+                Ok(Some(next)) => action = next,
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+
+        // Failed queries spend tokens too.
+        agent.record_usage().await;
+        result?;
+
+        let exchange = agent.exchanges.pop().context("agent lost its exchange")?;
+        agent.complete();
+
+        let (text, _) = exchange
+            .answer()
+            .context("agent did not produce an answer")?;
+
+        Ok(Answer {
+            thread_id,
+            text: text.to_owned(),
+            paths: exchange.paths.clone(),
+        })
+    }
+}